@@ -0,0 +1,128 @@
+//! Relevance checks of a block with 200,000 account updates by a consumer tracking 1% of them.
+//!
+//! Compares probing the tracked accounts with a hash set lookup per update against an
+//! `AccountFilter`, which rules out most updates with a single bloom filter probe, both on their
+//! own and as the filter of a filtered decode. The benchmark fails if the bloom filter probes
+//! aren't faster than the hash set lookups, if either filtered decode keeps a different set of
+//! accounts than the tracked ones, or if the measured false positive rate exceeds twice the rate
+//! the filter was sized for.
+//!
+//! Run with `cargo bench --bench bloom_filter`.
+mod common;
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
+};
+
+use alloy_primitives::{Address, U256};
+use tycho_simulation::evm::{
+    bloom::{AccountFilter, DEFAULT_FALSE_POSITIVE_RATE},
+    tycho_models::{AccountUpdate, Block, BlockAccountChanges, Chain, ChangeType},
+};
+
+const ACCOUNTS: u64 = 200_000;
+const TRACKED: u64 = 2_000;
+const ROUNDS: u32 = 10;
+
+fn address(i: u64) -> Address {
+    Address::left_padding_from(&i.to_be_bytes())
+}
+
+fn main() {
+    if !common::is_bench_run() {
+        return;
+    }
+    let addresses: Vec<Address> = (0..ACCOUNTS).map(address).collect();
+    let tracked: HashSet<Address> = (0..TRACKED)
+        .map(|i| address(i * (ACCOUNTS / TRACKED)))
+        .collect();
+    let mut filter = AccountFilter::default();
+    for account in &tracked {
+        filter.insert(*account);
+    }
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let hits = addresses
+            .iter()
+            .filter(|account| tracked.contains(*account))
+            .count();
+        assert_eq!(hits as u64, TRACKED);
+    }
+    let hash_set = start.elapsed();
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let hits = addresses
+            .iter()
+            .filter(|account| filter.contains(account))
+            .count();
+        assert_eq!(hits as u64, TRACKED);
+    }
+    let bloom = start.elapsed();
+
+    let account_updates = addresses
+        .iter()
+        .map(|&account| {
+            let slots = HashMap::from([(U256::from(1), U256::from(2))]);
+            let update =
+                AccountUpdate::new(account, Chain::Ethereum, slots, None, None, ChangeType::Update);
+            (account, update)
+        })
+        .collect();
+    let changes = BlockAccountChanges::new(
+        "vm:ambient".to_string(),
+        Chain::Ethereum,
+        Block::default(),
+        account_updates,
+        HashMap::new(),
+    );
+    let json = serde_json::to_string(&changes).unwrap();
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let changes = BlockAccountChanges::from_json_filtered(&json, &tracked).unwrap();
+        assert_eq!(decoded_accounts(&changes), tracked);
+        std::hint::black_box(changes);
+    }
+    let hash_set_decode = start.elapsed();
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let changes = BlockAccountChanges::from_json_filtered(&json, &filter).unwrap();
+        assert_eq!(decoded_accounts(&changes), tracked);
+        std::hint::black_box(changes);
+    }
+    let bloom_decode = start.elapsed();
+
+    let stats = filter.stats();
+    println!("accounts: {ACCOUNTS}, tracked: {TRACKED}");
+    println!("hash set lookups: {:?} per block", hash_set / ROUNDS);
+    println!("bloom filter probes: {:?} per block", bloom / ROUNDS);
+    println!("filtered decode with hash set: {:?} per block", hash_set_decode / ROUNDS);
+    println!("filtered decode with bloom filter: {:?} per block", bloom_decode / ROUNDS);
+    println!(
+        "bloom filter: {} probes, {:.4} false positive rate",
+        stats.probes,
+        stats.false_positive_rate()
+    );
+
+    assert!(
+        bloom < hash_set,
+        "bloom filter probes ({bloom:?}) aren't faster than hash set lookups ({hash_set:?})"
+    );
+    assert!(
+        stats.false_positive_rate() <= 2.0 * DEFAULT_FALSE_POSITIVE_RATE,
+        "false positive rate {} exceeds twice the configured {DEFAULT_FALSE_POSITIVE_RATE}",
+        stats.false_positive_rate()
+    );
+}
+
+fn decoded_accounts(changes: &BlockAccountChanges) -> HashSet<Address> {
+    changes
+        .account_updates
+        .keys()
+        .copied()
+        .collect()
+}
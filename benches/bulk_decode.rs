@@ -0,0 +1,97 @@
+//! Decoding the account updates of 500 Uniswap V2 and V3 pools in a block.
+//!
+//! Single-address dispatch looks up the update of every registered pool in the block and decodes
+//! it on its own, the way each pool's code path used to. Bulk dispatch walks the block's updates
+//! once and looks each of them up in the registry. The output compares the time per block of
+//! both.
+//!
+//! Run with `cargo bench --bench bulk_decode`.
+mod common;
+
+use std::{collections::HashMap, time::Instant};
+
+use alloy_primitives::{Address, U256};
+use tycho_simulation::evm::{
+    inferrer::StorageLayout,
+    protocol::decoder::{BulkDecoder, PoolRegistry},
+    storage_layout::{
+        UniswapV2Reserves, UniswapV3Slot0, V2_RESERVES_SLOT, V3_LIQUIDITY_SLOT, V3_SLOT0_SLOT,
+    },
+    tycho_models::{AccountUpdate, Block, BlockAccountChanges, Chain, ChangeType},
+};
+
+const POOLS: u64 = 500;
+const BLOCKS: u32 = 1_000;
+
+fn address(i: u64) -> Address {
+    Address::left_padding_from(&i.to_be_bytes())
+}
+
+fn main() {
+    if !common::is_bench_run() {
+        return;
+    }
+    let mut registry = PoolRegistry::new();
+    let mut account_updates = HashMap::new();
+    for i in 0..POOLS {
+        let (layout, slots) = if i % 2 == 0 {
+            let reserves = UniswapV2Reserves {
+                reserve0: U256::from(i),
+                reserve1: U256::from(2 * i),
+                block_timestamp_last: 1,
+            };
+            (StorageLayout::UniswapV2, HashMap::from([(V2_RESERVES_SLOT, reserves.pack())]))
+        } else {
+            let slot0 = UniswapV3Slot0 {
+                sqrt_price: U256::from(i) << 96,
+                tick: i as i32,
+                observation_index: 0,
+                observation_cardinality: 1,
+                observation_cardinality_next: 1,
+                fee_protocol: 0,
+                unlocked: true,
+            };
+            (
+                StorageLayout::UniswapV3,
+                HashMap::from([(V3_SLOT0_SLOT, slot0.pack()), (V3_LIQUIDITY_SLOT, U256::from(i))]),
+            )
+        };
+        registry.register(address(i), layout);
+        account_updates.insert(
+            address(i),
+            AccountUpdate::new(address(i), Chain::Ethereum, slots, None, None, ChangeType::Update),
+        );
+    }
+    let changes = BlockAccountChanges::new(
+        "vm:test".to_string(),
+        Chain::Ethereum,
+        Block::default(),
+        account_updates,
+        HashMap::new(),
+    );
+
+    let start = Instant::now();
+    for _ in 0..BLOCKS {
+        let decoded: Vec<_> = (0..POOLS)
+            .filter_map(|i| {
+                let pool = registry.get(&address(i))?;
+                let update = changes
+                    .account_updates
+                    .get(&pool.address)?;
+                BulkDecoder::decode(pool, update).ok()
+            })
+            .collect();
+        std::hint::black_box(decoded);
+    }
+    let single = start.elapsed();
+
+    let start = Instant::now();
+    for _ in 0..BLOCKS {
+        std::hint::black_box(BulkDecoder::decode_all(&changes, &registry));
+    }
+    let bulk = start.elapsed();
+
+    println!("pool updates: {POOLS}");
+    println!("single-address dispatch: {:?} per block", single / BLOCKS);
+    println!("bulk dispatch: {:?} per block", bulk / BLOCKS);
+}
@@ -0,0 +1,7 @@
+/// Whether the benchmark was started by `cargo bench`, which passes `--bench`.
+///
+/// Test runners also list and run bench targets, e.g. `cargo nextest run --all-targets`. They get
+/// an empty list of tests and the benchmark doesn't run.
+pub fn is_bench_run() -> bool {
+    std::env::args().any(|arg| arg == "--bench")
+}
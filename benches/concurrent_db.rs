@@ -0,0 +1,109 @@
+//! Applying the account updates of a block with 100 accounts to a `ConcurrentSimulationDB`.
+//!
+//! Sequential application updates one account after the other, parallel application spreads the
+//! accounts over all available cores. Both run while reader threads keep querying storage. The
+//! output compares the time per block of both.
+//!
+//! Run with `cargo bench --bench concurrent_db`.
+mod common;
+
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use alloy_primitives::{Address, B256, U256};
+use revm::{db::DatabaseRef, primitives::AccountInfo};
+use tycho_simulation::evm::{
+    account_storage::StateUpdate,
+    engine_db::{
+        concurrent_db::ConcurrentSimulationDB, engine_db_interface::EngineDatabaseInterface,
+        simulation_db::BlockHeader,
+    },
+};
+
+const ACCOUNTS: u64 = 100;
+const SLOTS: u64 = 50;
+const BLOCKS: u64 = 1_000;
+const READERS: usize = 4;
+
+fn address(i: u64) -> Address {
+    Address::left_padding_from(&i.to_be_bytes())
+}
+
+fn header(number: u64) -> BlockHeader {
+    BlockHeader { number, hash: B256::default(), timestamp: 0 }
+}
+
+fn updates(block: u64) -> HashMap<Address, StateUpdate> {
+    (0..ACCOUNTS)
+        .map(|i| {
+            let storage = (0..SLOTS)
+                .map(|slot| (U256::from(slot), U256::from(block * slot + i)))
+                .collect();
+            (
+                address(i),
+                StateUpdate {
+                    storage: Some(storage),
+                    balance: Some(U256::from(block)),
+                    ..Default::default()
+                },
+            )
+        })
+        .collect()
+}
+
+/// Time per block to apply `blocks` with `apply`, while `READERS` threads read storage.
+fn run(
+    db: &ConcurrentSimulationDB,
+    blocks: &[HashMap<Address, StateUpdate>],
+    apply: impl Fn(&HashMap<Address, StateUpdate>, BlockHeader),
+) -> Duration {
+    let done = AtomicBool::new(false);
+    thread::scope(|scope| {
+        for _ in 0..READERS {
+            scope.spawn(|| {
+                let mut i = 0;
+                while !done.load(Ordering::Relaxed) {
+                    let slot = db
+                        .storage_ref(address(i % ACCOUNTS), U256::from(i % SLOTS))
+                        .unwrap();
+                    std::hint::black_box(slot);
+                    i += 1;
+                }
+            });
+        }
+        let start = Instant::now();
+        for (number, changes) in blocks.iter().enumerate() {
+            apply(changes, header(number as u64));
+        }
+        let elapsed = start.elapsed();
+        done.store(true, Ordering::Relaxed);
+        elapsed / blocks.len() as u32
+    })
+}
+
+fn main() {
+    if !common::is_bench_run() {
+        return;
+    }
+    let db = ConcurrentSimulationDB::new();
+    for i in 0..ACCOUNTS {
+        db.init_account(address(i), AccountInfo::default(), None, false);
+    }
+    let blocks: Vec<_> = (0..BLOCKS).map(updates).collect();
+
+    let sequential = run(&db, &blocks, |changes, block| {
+        std::hint::black_box(db.update_state(changes, block));
+    });
+    let parallel = run(&db, &blocks, |changes, block| {
+        std::hint::black_box(db.apply_updates_parallel(changes, block));
+    });
+
+    println!("accounts per block: {ACCOUNTS}, slots per account: {SLOTS}, readers: {READERS}");
+    println!("sequential per block: {sequential:?}");
+    println!("parallel per block: {parallel:?}");
+    println!("speedup: {:.2}x", sequential.as_secs_f64() / parallel.as_secs_f64());
+}
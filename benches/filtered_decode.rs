@@ -0,0 +1,79 @@
+//! Decoding of a block with 5,000 account updates by a consumer tracking 50 of them.
+//!
+//! Filtering while decoding skips the updates of untracked accounts without deserializing them,
+//! whereas filtering after a full decode deserializes and then drops all of them. The output
+//! compares the time per message of both.
+//!
+//! Run with `cargo bench --bench filtered_decode`.
+mod common;
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
+};
+
+use alloy_primitives::{Address, U256};
+use tycho_simulation::evm::tycho_models::{
+    AccountUpdate, Block, BlockAccountChanges, Chain, ChangeType,
+};
+
+const ACCOUNTS: u64 = 5_000;
+const FILTERED: u64 = 50;
+const MESSAGES: u32 = 100;
+
+fn address(i: u64) -> Address {
+    Address::left_padding_from(&i.to_be_bytes())
+}
+
+fn main() {
+    if !common::is_bench_run() {
+        return;
+    }
+    let account_updates = (0..ACCOUNTS)
+        .map(|i| {
+            let slots = (0..20u64)
+                .map(|slot| (U256::from(slot), U256::from(i * slot)))
+                .collect();
+            let update = AccountUpdate::new(
+                address(i),
+                Chain::Ethereum,
+                slots,
+                Some(U256::from(i)),
+                None,
+                ChangeType::Update,
+            );
+            (address(i), update)
+        })
+        .collect();
+    let changes = BlockAccountChanges::new(
+        "vm:ambient".to_string(),
+        Chain::Ethereum,
+        Block::default(),
+        account_updates,
+        HashMap::new(),
+    );
+    let json = serde_json::to_string(&changes).unwrap();
+    let filter: HashSet<Address> = (0..FILTERED)
+        .map(|i| address(i * (ACCOUNTS / FILTERED)))
+        .collect();
+
+    let start = Instant::now();
+    for _ in 0..MESSAGES {
+        let mut changes: BlockAccountChanges = serde_json::from_str(&json).unwrap();
+        changes.retain_accounts(&filter);
+        std::hint::black_box(changes);
+    }
+    let full = start.elapsed();
+
+    let start = Instant::now();
+    for _ in 0..MESSAGES {
+        let changes = BlockAccountChanges::from_json_filtered(&json, &filter).unwrap();
+        std::hint::black_box(changes);
+    }
+    let filtered = start.elapsed();
+
+    println!("accounts: {ACCOUNTS}, filter: {FILTERED}");
+    println!("message size: {} bytes", json.len());
+    println!("full decode, then filter: {:?} per message", full / MESSAGES);
+    println!("filtered decode: {:?} per message", filtered / MESSAGES);
+}
@@ -0,0 +1,95 @@
+//! Heap allocations of a single quote on a Uniswap V3 pool with 5,000 initialized ticks.
+//!
+//! The state returned by a quote shares the pool's ticks instead of copying them, so a quote
+//! allocates a few hundred bytes regardless of the number of ticks. The output compares this to
+//! the size of the tick list, which every quote used to copy.
+//!
+//! Run with `cargo bench --bench quote_allocations`.
+mod common;
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use alloy_primitives::U256;
+use num_bigint::BigUint;
+use tycho_simulation::{
+    evm::protocol::{
+        uniswap_v3::{enums::FeeAmount, state::UniswapV3State},
+        utils::uniswap::tick_list::TickInfo,
+    },
+    models::Token,
+    protocol::state::ProtocolSim,
+};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const TICKS: i32 = 5_000;
+const QUOTES: usize = 1_000;
+
+fn main() {
+    if !common::is_bench_run() {
+        return;
+    }
+    let ticks: Vec<TickInfo> = (1..=TICKS / 2)
+        .flat_map(|k| {
+            [
+                TickInfo::new(-60 * k, 1_000_000_000_000_000_000),
+                TickInfo::new(60 * k, -1_000_000_000_000_000_000),
+            ]
+        })
+        .collect();
+    let tick_bytes = ticks.len() * std::mem::size_of::<TickInfo>();
+    let pool = UniswapV3State::new(
+        100_000_000_000_000_000_000,
+        U256::from_str("79228162514264337593543950336").unwrap(),
+        FeeAmount::Medium,
+        0,
+        ticks,
+    );
+    let token_in =
+        Token::new("0x6b175474e89094c44da98b954eedeac495271d0f", 18, "X", BigUint::from(10_000u64));
+    let token_out =
+        Token::new("0xf1ca9cb74685755965c7458528a36934df52a3ef", 18, "Y", BigUint::from(10_000u64));
+    let amount_in = BigUint::from_str("1000000000000000000").unwrap();
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let allocated_bytes = ALLOCATED_BYTES.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..QUOTES {
+        let res = pool
+            .get_amount_out(amount_in.clone(), &token_in, &token_out)
+            .unwrap();
+        std::hint::black_box(res);
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    let allocated_bytes = ALLOCATED_BYTES.load(Ordering::Relaxed) - allocated_bytes;
+
+    println!("ticks: {TICKS}");
+    println!("tick list size: {tick_bytes} bytes");
+    println!("allocations per quote: {}", allocations / QUOTES);
+    println!("bytes allocated per quote: {}", allocated_bytes / QUOTES);
+    println!("time per quote: {:?}", elapsed / QUOTES as u32);
+}
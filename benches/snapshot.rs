@@ -0,0 +1,96 @@
+//! Writing and reading a snapshot of 50k cached accounts, compared to serializing them as JSON.
+//!
+//! The output shows the size and the write time of JSON, uncompressed and zstd-compressed
+//! snapshots, and the time to read each snapshot back.
+//!
+//! Run with `cargo bench --bench snapshot`.
+mod common;
+
+use std::time::{Duration, Instant};
+
+use alloy_primitives::{keccak256, Address, Bytes, B256, U256};
+use revm::primitives::{AccountInfo, Bytecode};
+use tycho_simulation::evm::{
+    account_storage::AccountStorage,
+    engine_db::{
+        simulation_db::BlockHeader,
+        snapshot::{read_snapshot, write_snapshot_with, Compression, DEFAULT_ZSTD_LEVEL},
+    },
+};
+
+const ACCOUNTS: u64 = 50_000;
+const SLOTS: u64 = 4;
+
+fn storage() -> AccountStorage {
+    let code = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x00, 0x60, 0x00, 0xf3]));
+    let code_hash = keccak256(code.original_bytes());
+    let mut storage = AccountStorage::new();
+    for i in 0..ACCOUNTS {
+        let slots = (0..SLOTS)
+            .map(|s| (U256::from(s), U256::from(i * s)))
+            .collect();
+        let info = if i % 2 == 0 {
+            AccountInfo { balance: U256::from(i), nonce: i, code_hash, code: Some(code.clone()) }
+        } else {
+            AccountInfo { balance: U256::from(i), nonce: 1, ..Default::default() }
+        };
+        storage.init_account(
+            Address::left_padding_from(&i.to_be_bytes()),
+            info,
+            Some(slots),
+            false,
+        );
+    }
+    storage
+}
+
+/// The snapshot written with `compression` and the time it took.
+fn write(storage: &AccountStorage, compression: Compression) -> (Vec<u8>, Duration) {
+    let block = BlockHeader { number: 21_000_000, hash: B256::repeat_byte(0xab), timestamp: 0 };
+    let start = Instant::now();
+    let mut buf = Vec::new();
+    write_snapshot_with(storage, Some(&block), &mut buf, compression).unwrap();
+    (buf, start.elapsed())
+}
+
+/// Time to read `snapshot` back, checking that all accounts are restored.
+fn read(snapshot: &[u8]) -> Duration {
+    let start = Instant::now();
+    let (restored, _) = read_snapshot(snapshot).unwrap();
+    let elapsed = start.elapsed();
+    assert_eq!(restored.accounts().count() as u64, ACCOUNTS);
+    elapsed
+}
+
+fn main() {
+    if !common::is_bench_run() {
+        return;
+    }
+
+    let storage = storage();
+
+    let start = Instant::now();
+    let as_json: Vec<_> = storage
+        .accounts()
+        .map(|(address, account)| (address, &account.info, &account.permanent_storage))
+        .collect();
+    let json = serde_json::to_vec(&as_json).unwrap();
+    let json_time = start.elapsed();
+
+    let (binary, binary_time) = write(&storage, Compression::None);
+    let (zstd, zstd_time) = write(&storage, Compression::Zstd(DEFAULT_ZSTD_LEVEL));
+    let binary_read = read(&binary);
+    let zstd_read = read(&zstd);
+
+    println!("{ACCOUNTS} accounts with {SLOTS} slots each");
+    println!("json:   {:>10} bytes, written in {json_time:?}", json.len());
+    println!(
+        "binary: {:>10} bytes, written in {binary_time:?}, read in {binary_read:?}",
+        binary.len()
+    );
+    println!("zstd:   {:>10} bytes, written in {zstd_time:?}, read in {zstd_read:?}", zstd.len());
+
+    assert!(binary.len() * 2 < json.len(), "binary snapshot should be at most half the JSON");
+    assert!(binary_time < json_time, "binary snapshot should be written faster than JSON");
+    assert!(zstd.len() < binary.len(), "zstd should shrink the snapshot");
+}
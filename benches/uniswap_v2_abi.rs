@@ -0,0 +1,83 @@
+//! Reading the reserves of a Uniswap V2 pair through the ABI codec versus simulating the call.
+//!
+//! The codec only encodes the calldata and decodes the return data of `getReserves()`, while a
+//! simulation executes the pair's code in revm. The output compares the time per call of both.
+//!
+//! Run with `cargo bench --bench uniswap_v2_abi`.
+mod common;
+
+use std::{collections::HashMap, time::Instant};
+
+use alloy_primitives::{hex, keccak256, Address, U256};
+use revm::primitives::{AccountInfo, Bytecode};
+use tycho_simulation::evm::{
+    engine_db::{
+        create_engine, engine_db_interface::EngineDatabaseInterface, tycho_db::PreCachedDB,
+    },
+    protocol::uniswap_v2::abi::UniswapV2Abi,
+    simulation::SimulationParameters,
+};
+
+/// Runtime code answering any call like `UniswapV2Pair.getReserves()` from the reserves packed
+/// into slot 8.
+const GET_RESERVES_CODE: &[u8] = &hex!(
+    "600854"
+    "80 6dffffffffffffffffffffffffffff 16 600052"
+    "80 60701c 6dffffffffffffffffffffffffffff 16 602052"
+    "60e01c 604052"
+    "60606000f3"
+);
+
+const CALLS: u32 = 1_000;
+
+fn main() {
+    if !common::is_bench_run() {
+        return;
+    }
+    let pair = Address::repeat_byte(0x22);
+    let engine = create_engine(PreCachedDB::new().unwrap(), false).unwrap();
+    let code = Bytecode::new_raw(GET_RESERVES_CODE.into());
+    let packed = U256::from(1_234_567_890_123_456_789u128) |
+        (U256::from(987_654_321u128) << 112) |
+        (U256::from(1_700_000_000u32) << 224);
+    engine.state.init_account(
+        pair,
+        AccountInfo {
+            balance: U256::ZERO,
+            nonce: 0,
+            code_hash: keccak256(code.bytes()),
+            code: Some(code),
+        },
+        Some(HashMap::from([(U256::from(8), packed)])),
+        true,
+    );
+    let params = SimulationParameters {
+        caller: Address::ZERO,
+        to: pair,
+        data: UniswapV2Abi::encode_get_reserves(),
+        value: U256::ZERO,
+        overrides: None,
+        balance_overrides: None,
+        nonce: None,
+        gas_limit: None,
+        block_number: 0,
+        timestamp: 0,
+    };
+    let data = engine.simulate(&params).unwrap().result;
+
+    let start = Instant::now();
+    for _ in 0..CALLS {
+        std::hint::black_box(engine.simulate(&params).unwrap());
+    }
+    let revm_time = start.elapsed();
+    let start = Instant::now();
+    for _ in 0..CALLS {
+        std::hint::black_box(UniswapV2Abi::encode_get_reserves());
+        std::hint::black_box(UniswapV2Abi::decode_get_reserves(&data).unwrap());
+    }
+    let codec_time = start.elapsed();
+
+    println!("calls: {CALLS}");
+    println!("revm per call: {:?}", revm_time / CALLS);
+    println!("abi codec per call: {:?}", codec_time / CALLS);
+}
@@ -388,7 +388,8 @@ fn get_best_swap(
     for (id, state) in message.states.iter() {
         if let Some(component) = pairs.get(id) {
             let tokens = &component.tokens;
-            if HashSet::from([&sell_token, &buy_token]) == HashSet::from([&tokens[0], &tokens[1]]) {
+            if HashSet::from([&sell_token, &buy_token]) == HashSet::from([&*tokens[0], &*tokens[1]])
+            {
                 let amount_out = state
                     .get_amount_out(amount_in.clone(), &sell_token, &buy_token)
                     .map_err(|e| {
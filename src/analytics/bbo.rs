@@ -0,0 +1,339 @@
+//! Best bid and offer of a token pair across pools.
+//!
+//! The bid is the best rate at which `token_in` can be sold for `token_out` on a single pool, the
+//! ask the best rate at which `token_in` can be bought back with `token_out`, both in `token_out`
+//! per unit of `token_in` and net of pool fees. [`BboAggregator`] keeps the rate of every pool per
+//! direction, so a block only requotes the pools it updated.
+use std::{collections::HashMap, sync::Arc};
+
+use thiserror::Error;
+use tycho_common::Bytes;
+
+use crate::{
+    models::Token,
+    protocol::{models::BlockUpdate, state::ProtocolSim},
+    routing::pool_graph::PoolGraph,
+};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum BboError {
+    #[error("No pool quotes {token_in} -> {token_out}")]
+    NoLiquidity { token_in: Bytes, token_out: Bytes },
+    #[error("The bid {bid} of pool {bid_pool} exceeds the ask {ask} of pool {ask_pool}")]
+    Crossed { bid: f64, ask: f64, bid_pool: String, ask_pool: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BboResult {
+    /// Highest amount of `token_out` received for one `token_in`.
+    pub best_bid: f64,
+    /// Lowest amount of `token_out` paid for one `token_in`.
+    pub best_ask: f64,
+    pub bid_pool: String,
+    pub ask_pool: String,
+    /// `best_ask - best_bid` in basis points of the bid, rounded.
+    pub spread_bps: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BboAggregator {
+    /// The rate of each pool for a directed token pair.
+    rates: HashMap<(Bytes, Bytes), HashMap<String, f64>>,
+    /// The directed pairs each pool quotes.
+    pairs: HashMap<String, Vec<(Bytes, Bytes)>>,
+    /// The tokens of each pool, to requote it when its state changes.
+    tokens: HashMap<String, Vec<Arc<Token>>>,
+}
+
+impl BboAggregator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an aggregator with the rates of all edges of `graph`.
+    ///
+    /// The graph doesn't hold the tokens of its pools, so these pools are only requoted by
+    /// [`Self::apply`] once added again with [`Self::add_pool`].
+    pub fn from_graph(graph: &PoolGraph) -> Self {
+        let mut aggregator = Self::new();
+        for (pool_id, token_in, token_out, rate) in graph.edges() {
+            aggregator.set_rate(pool_id, token_in, token_out, rate);
+        }
+        aggregator
+    }
+
+    /// Quotes every token pair of a pool with its spot price net of the pool fee, like
+    /// [`PoolGraph::add_pool`], replacing the pool's previous rates.
+    ///
+    /// Pairs without a valid spot price are not quoted by the pool.
+    pub fn add_pool(&mut self, pool_id: &str, state: &dyn ProtocolSim, tokens: &[Token]) {
+        let tokens = tokens
+            .iter()
+            .cloned()
+            .map(Arc::new)
+            .collect();
+        self.quote_pool(pool_id, state, tokens);
+    }
+
+    fn quote_pool(&mut self, pool_id: &str, state: &dyn ProtocolSim, tokens: Vec<Arc<Token>>) {
+        self.remove_rates(pool_id);
+        let fee_factor = 1.0 - state.fee();
+        for token_in in &tokens {
+            for token_out in &tokens {
+                if token_in == token_out {
+                    continue;
+                }
+                let rate = state
+                    .spot_price(token_in, token_out)
+                    .unwrap_or(f64::NAN) *
+                    fee_factor;
+                if rate.is_finite() && rate > 0.0 {
+                    self.set_rate(pool_id, &token_in.address, &token_out.address, rate);
+                }
+            }
+        }
+        self.tokens
+            .insert(pool_id.to_string(), tokens);
+    }
+
+    pub fn remove_pool(&mut self, pool_id: &str) {
+        self.remove_rates(pool_id);
+        self.tokens.remove(pool_id);
+    }
+
+    /// Requotes the pools updated by a block and drops the removed ones.
+    ///
+    /// New pools are quoted with the tokens of their component. Updated states of pools whose
+    /// tokens are unknown are ignored.
+    pub fn apply(&mut self, update: &BlockUpdate) {
+        for pool_id in update.removed_pairs.keys() {
+            self.remove_pool(pool_id);
+        }
+        for (pool_id, component) in &update.new_pairs {
+            self.tokens
+                .insert(pool_id.clone(), component.tokens.clone());
+        }
+        for (pool_id, state) in &update.states {
+            if let Some(tokens) = self.tokens.get(pool_id).cloned() {
+                self.quote_pool(pool_id, state.as_ref(), tokens);
+            }
+        }
+    }
+
+    /// Returns the best bid and ask of `token_in` in `token_out` across all pools quoting both
+    /// directions of the pair.
+    ///
+    /// # Errors
+    ///
+    /// * `BboError::NoLiquidity` - if no pool quotes one of the directions.
+    /// * `BboError::Crossed` - if the best bid exceeds the best ask, i.e. the pools can be
+    ///   arbitraged against each other.
+    pub fn bbo(&self, token_in: &Bytes, token_out: &Bytes) -> Result<BboResult, BboError> {
+        let no_liquidity =
+            || BboError::NoLiquidity { token_in: token_in.clone(), token_out: token_out.clone() };
+        let (bid_pool, best_bid) = self
+            .best_rate(token_in, token_out)
+            .ok_or_else(no_liquidity)?;
+        let (ask_pool, buy_rate) = self
+            .best_rate(token_out, token_in)
+            .ok_or_else(no_liquidity)?;
+        let best_ask = 1.0 / buy_rate;
+        if best_bid > best_ask {
+            return Err(BboError::Crossed {
+                bid: best_bid,
+                ask: best_ask,
+                bid_pool: bid_pool.to_string(),
+                ask_pool: ask_pool.to_string(),
+            });
+        }
+        Ok(BboResult {
+            best_bid,
+            best_ask,
+            bid_pool: bid_pool.to_string(),
+            ask_pool: ask_pool.to_string(),
+            // saturates on overflow
+            spread_bps: ((best_ask - best_bid) / best_bid * 10_000.0).round() as u32,
+        })
+    }
+
+    /// The pool with the highest rate for a direction, the lowest pool id among equal rates.
+    fn best_rate(&self, token_in: &Bytes, token_out: &Bytes) -> Option<(&str, f64)> {
+        self.rates
+            .get(&(token_in.clone(), token_out.clone()))?
+            .iter()
+            .map(|(pool_id, rate)| (pool_id.as_str(), *rate))
+            .max_by(|(a_id, a), (b_id, b)| {
+                a.total_cmp(b)
+                    .then_with(|| b_id.cmp(a_id))
+            })
+    }
+
+    fn set_rate(&mut self, pool_id: &str, token_in: &Bytes, token_out: &Bytes, rate: f64) {
+        let pair = (token_in.clone(), token_out.clone());
+        self.rates
+            .entry(pair.clone())
+            .or_default()
+            .insert(pool_id.to_string(), rate);
+        self.pairs
+            .entry(pool_id.to_string())
+            .or_default()
+            .push(pair);
+    }
+
+    fn remove_rates(&mut self, pool_id: &str) {
+        for pair in self
+            .pairs
+            .remove(pool_id)
+            .unwrap_or_default()
+        {
+            if let Some(rates) = self.rates.get_mut(&pair) {
+                rates.remove(pool_id);
+                if rates.is_empty() {
+                    self.rates.remove(&pair);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::U256;
+    use num_bigint::BigUint;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::evm::protocol::uniswap_v2::state::UniswapV2State;
+
+    fn address(i: u8) -> Bytes {
+        Bytes::from(vec![i])
+    }
+
+    fn token(i: u8) -> Token {
+        Token::new(&format!("0x{:040x}", i), 18, "T", BigUint::from(10_000u64))
+    }
+
+    fn pool(reserve0: u64, reserve1: u64) -> UniswapV2State {
+        UniswapV2State::new(U256::from(reserve0), U256::from(reserve1))
+    }
+
+    /// `n` pools of a pair at reference price 2, each off by less than its fee, so no two pools
+    /// can be arbitraged against each other.
+    fn consistent_pools(n: u32) -> PoolGraph {
+        let mut graph = PoolGraph::new();
+        for i in 0..n {
+            let fee = 0.0005 + 0.001 * f64::from(i % 7);
+            let deviation = fee * 0.9 * (f64::from(i % 5) / 2.0 - 1.0);
+            let price = 2.0 * (1.0 + deviation);
+            let id = format!("pool_{i}");
+            graph
+                .add_edge(&id, &address(1), &address(2), price * (1.0 - fee))
+                .unwrap();
+            graph
+                .add_edge(&id, &address(2), &address(1), 1.0 / price * (1.0 - fee))
+                .unwrap();
+        }
+        graph
+    }
+
+    #[rstest]
+    #[case::single(1)]
+    #[case::few(5)]
+    #[case::many(100)]
+    fn test_spread_is_non_negative(#[case] n: u32) {
+        let aggregator = BboAggregator::from_graph(&consistent_pools(n));
+
+        for (token_in, token_out) in [(address(1), address(2)), (address(2), address(1))] {
+            let bbo = aggregator
+                .bbo(&token_in, &token_out)
+                .unwrap();
+
+            assert!(bbo.best_ask >= bbo.best_bid, "{bbo:?}");
+            let spread = (bbo.best_ask - bbo.best_bid) / bbo.best_bid * 10_000.0;
+            assert_eq!(bbo.spread_bps, spread.round() as u32);
+        }
+    }
+
+    #[test]
+    fn test_bbo_picks_best_pool_per_side() {
+        let mut graph = PoolGraph::new();
+        // pool_a has the better bid, pool_b the better ask
+        graph
+            .add_edge("pool_a", &address(1), &address(2), 1.99)
+            .unwrap();
+        graph
+            .add_edge("pool_a", &address(2), &address(1), 0.49)
+            .unwrap();
+        graph
+            .add_edge("pool_b", &address(1), &address(2), 1.98)
+            .unwrap();
+        graph
+            .add_edge("pool_b", &address(2), &address(1), 0.498)
+            .unwrap();
+
+        let bbo = BboAggregator::from_graph(&graph)
+            .bbo(&address(1), &address(2))
+            .unwrap();
+
+        assert_eq!(bbo.best_bid, 1.99);
+        assert_eq!(bbo.best_ask, 1.0 / 0.498);
+        assert_eq!((bbo.bid_pool.as_str(), bbo.ask_pool.as_str()), ("pool_a", "pool_b"));
+        assert_eq!(bbo.spread_bps, 91);
+    }
+
+    #[test]
+    fn test_bbo_errors() {
+        let mut graph = PoolGraph::new();
+        graph
+            .add_edge("one_way", &address(1), &address(2), 2.0)
+            .unwrap();
+        graph
+            .add_edge("cheap", &address(3), &address(4), 2.0)
+            .unwrap();
+        graph
+            .add_edge("expensive", &address(4), &address(3), 1.0)
+            .unwrap();
+        let aggregator = BboAggregator::from_graph(&graph);
+
+        assert_eq!(
+            aggregator.bbo(&address(1), &address(2)),
+            Err(BboError::NoLiquidity { token_in: address(1), token_out: address(2) })
+        );
+        assert_eq!(
+            aggregator.bbo(&address(3), &address(4)),
+            Err(BboError::Crossed {
+                bid: 2.0,
+                ask: 1.0,
+                bid_pool: "cheap".to_string(),
+                ask_pool: "expensive".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_apply_requotes_updated_pools() {
+        let tokens = [token(1), token(2)];
+        let (t0, t1) = (&tokens[0].address, &tokens[1].address);
+        let mut aggregator = BboAggregator::new();
+        aggregator.add_pool("a", &pool(1_000_000, 2_000_000), &tokens);
+        aggregator.add_pool("b", &pool(1_000_000, 2_010_000), &tokens);
+        assert_eq!(aggregator.bbo(t0, t1).unwrap().bid_pool, "b");
+
+        aggregator.apply(&BlockUpdate::new(
+            1,
+            HashMap::from([(
+                "a".to_string(),
+                Box::new(pool(1_000_000, 2_020_000)) as Box<dyn ProtocolSim>,
+            )]),
+            HashMap::new(),
+        ));
+        let bbo = aggregator.bbo(t0, t1).unwrap();
+        assert_eq!(bbo.bid_pool, "a");
+        assert!(bbo.best_ask >= bbo.best_bid);
+
+        aggregator.remove_pool("a");
+        assert_eq!(aggregator.bbo(t0, t1).unwrap().bid_pool, "b");
+        aggregator.remove_pool("b");
+        assert!(matches!(aggregator.bbo(t0, t1), Err(BboError::NoLiquidity { .. })));
+    }
+}
@@ -0,0 +1,2 @@
+//! Market data derived from the tracked pools.
+pub mod bbo;
@@ -0,0 +1,175 @@
+//! Decoding of Solidity custom errors.
+//!
+//! A contract reverting with a custom error returns the 4 byte selector of the error's signature
+//! followed by its ABI encoded parameters. Without knowing the signature this is just hex; the
+//! [`SolidityErrorDecoder`] keeps a registry of known errors to turn it into e.g.
+//! `SwapLimit(100, 120)`.
+//!
+//! `Error(string)` and `Panic(uint256)` aren't handled here, they are told apart from custom errors
+//! by the [`RevertDecoder`](super::revert::RevertDecoder). Note that many protocols (e.g. Balancer
+//! V2 with its `BAL#xxx` codes or Curve) revert with plain strings, which therefore don't need to
+//! be registered.
+use std::{collections::HashMap, fmt};
+
+use lazy_static::lazy_static;
+
+use super::value::{
+    canonical_signature, decode_values, parse_param_types, AbiValue, AbiValueError, ParamType,
+};
+use crate::evm::protocol::abi::selector;
+
+/// Custom errors of common DeFi contracts, registered by default.
+const DEFAULT_ERRORS: &[&str] = &[
+    // Uniswap V3 TickMath (0.8 ports)
+    "T()",
+    "R()",
+    // Uniswap V4 PoolManager
+    "PoolNotInitialized()",
+    "CurrencyNotSettled()",
+    "PriceLimitAlreadyExceeded(uint160,uint160)",
+    "PriceLimitOutOfBounds(uint160)",
+    "NoLiquidityToReceiveFees()",
+    // Balancer V3 Vault
+    "SwapLimit(uint256,uint256)",
+    "BalanceNotSettled()",
+    "PoolNotRegistered(address)",
+    "AmountGivenZero()",
+    // OpenZeppelin 5 ERC20
+    "ERC20InsufficientBalance(address,uint256,uint256)",
+    "ERC20InsufficientAllowance(address,uint256,uint256)",
+];
+
+lazy_static! {
+    static ref DEFAULT_DECODER: SolidityErrorDecoder = SolidityErrorDecoder::with_defaults();
+}
+
+/// A known custom error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorDefinition {
+    pub selector: [u8; 4],
+    pub name: String,
+    pub param_types: Vec<ParamType>,
+}
+
+/// A custom error decoded from revert data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedError {
+    pub name: String,
+    pub params: Vec<AbiValue>,
+}
+
+impl fmt::Display for DecodedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let params: Vec<String> = self
+            .params
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        write!(f, "{}({})", self.name, params.join(", "))
+    }
+}
+
+/// Registry of custom errors by selector.
+#[derive(Debug, Clone, Default)]
+pub struct SolidityErrorDecoder {
+    errors: HashMap<[u8; 4], ErrorDefinition>,
+}
+
+impl SolidityErrorDecoder {
+    /// Creates a decoder knowing the errors of common DeFi contracts.
+    pub fn with_defaults() -> Self {
+        let mut decoder = Self::default();
+        for signature in DEFAULT_ERRORS {
+            decoder
+                .register_signature(signature)
+                .expect("Default error signatures are valid");
+        }
+        decoder
+    }
+
+    /// Registers an error under the given selector, replacing any error registered before.
+    pub fn register(&mut self, selector: [u8; 4], name: String, param_types: Vec<ParamType>) {
+        self.errors
+            .insert(selector, ErrorDefinition { selector, name, param_types });
+    }
+
+    /// Registers an error by its signature, e.g. `SwapLimit(uint256,uint256)`. The selector is
+    /// derived from the signature.
+    pub fn register_signature(&mut self, signature: &str) -> Result<(), AbiValueError> {
+        let (name, params) = signature
+            .split_once('(')
+            .ok_or_else(|| AbiValueError::UnsupportedType(signature.to_string()))?;
+        let name = name.trim();
+        let param_types = parse_param_types(&format!("({params}"))?;
+        let canonical = canonical_signature(name, &param_types);
+        self.register(selector(&canonical), name.to_string(), param_types);
+        Ok(())
+    }
+
+    /// Decodes revert data of a known custom error. Returns `None` if the selector is unknown or
+    /// the parameters don't match the registered types.
+    pub fn decode(&self, data: &[u8]) -> Option<DecodedError> {
+        let selector: [u8; 4] = data.get(..4)?.try_into().ok()?;
+        let definition = self.errors.get(&selector)?;
+        let params = decode_values(&definition.param_types, &data[4..]).ok()?;
+        Some(DecodedError { name: definition.name.clone(), params })
+    }
+}
+
+/// Decodes revert data using the default registry, see [`SolidityErrorDecoder::with_defaults`].
+pub fn decode_revert_data(data: &[u8]) -> Option<DecodedError> {
+    DEFAULT_DECODER.decode(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::U256;
+    use alloy_sol_types::SolValue;
+
+    use super::*;
+
+    #[test]
+    fn test_decode_error_without_params() {
+        // keccak256("T()")[..4]
+        let data = hex::decode("2bc80f3a").unwrap();
+        assert_eq!(selector("T()"), data[..4]);
+
+        let res = decode_revert_data(&data).unwrap();
+
+        assert_eq!(res.to_string(), "T()");
+    }
+
+    #[test]
+    fn test_decode_error_with_params() {
+        let mut data = selector("SwapLimit(uint256,uint256)").to_vec();
+        data.extend((U256::from(100u64), U256::from(120u64)).abi_encode_params());
+
+        let res = decode_revert_data(&data).unwrap();
+
+        assert_eq!(res.name, "SwapLimit");
+        assert_eq!(res.to_string(), "SwapLimit(100, 120)");
+    }
+
+    #[test]
+    fn test_decode_unknown_selector() {
+        assert_eq!(decode_revert_data(&[0xde, 0xad, 0xbe, 0xef]), None);
+        assert_eq!(decode_revert_data(&[0xde, 0xad]), None);
+    }
+
+    #[test]
+    fn test_register_signature_normalizes_types() {
+        let mut decoder = SolidityErrorDecoder::default();
+        decoder
+            .register_signature("Slippage(uint, address)")
+            .unwrap();
+        let mut data = selector("Slippage(uint256,address)").to_vec();
+        data.extend((U256::from(1u64), alloy_primitives::Address::ZERO).abi_encode_params());
+
+        let res = decoder.decode(&data).unwrap();
+
+        assert_eq!(res.params.len(), 2);
+        assert!(res
+            .to_string()
+            .starts_with("Slippage(1, 0x"));
+    }
+}
@@ -0,0 +1,5 @@
+//! Dynamic ABI encoding and decoding, for data whose types are only known at runtime.
+pub mod errors;
+pub mod revert;
+pub mod value;
+pub mod view_call;
@@ -0,0 +1,194 @@
+//! Classification of revert data.
+//!
+//! A reverted call returns nothing for a bare `revert`, an ABI encoded `Error(string)` for
+//! `require(cond, msg)` and `revert(msg)`, an ABI encoded `Panic(uint256)` for failed assertions,
+//! arithmetic overflows and the like, or the selector and parameters of a custom error.
+//! [`RevertDecoder::decode`] tells these apart; custom errors can then be decoded further with the
+//! [`SolidityErrorDecoder`](super::errors::SolidityErrorDecoder).
+use std::fmt;
+
+use alloy_primitives::U256;
+use alloy_sol_types::SolValue;
+
+/// Selector of `Error(string)`.
+pub const ERROR_STRING_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+/// Selector of `Panic(uint256)`.
+pub const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+/// The kind of a revert, with its decoded payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RevertKind {
+    /// A bare `revert()`, or `require(cond)` without a message.
+    Empty,
+    /// `Error(string)`, from `require(cond, msg)` or `revert(msg)`.
+    ErrorString(String),
+    /// `Panic(uint256)` with its panic code, e.g. `0x11` for an arithmetic overflow.
+    Panic(U256),
+    /// Any other selector with the data following it, usually a custom error.
+    CustomSelector([u8; 4], Vec<u8>),
+    /// Data too short to hold a selector.
+    Malformed(Vec<u8>),
+}
+
+impl RevertKind {
+    /// Name of a panic code as listed in the Solidity docs, `None` for unknown codes.
+    pub fn panic_name(code: U256) -> Option<&'static str> {
+        let name = match u64::try_from(code).ok()? {
+            0x00 => "GenericCompilerPanic",
+            0x01 => "AssertionError",
+            0x11 => "ArithmeticOver/Underflow",
+            0x12 => "ZeroDivisionError",
+            0x21 => "UnknownEnumMember",
+            0x22 => "BadStorageByteArrayEncoding",
+            0x31 => "EmptyArray",
+            0x32 => "OutOfBounds",
+            0x41 => "OutOfMemory",
+            0x51 => "BadFunctionPointer",
+            _ => return None,
+        };
+        Some(name)
+    }
+}
+
+impl fmt::Display for RevertKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RevertKind::Empty => write!(f, "Empty revert"),
+            RevertKind::ErrorString(message) => write!(f, "{message}"),
+            RevertKind::Panic(code) => match Self::panic_name(*code) {
+                Some(name) => write!(f, "{name}"),
+                None => write!(f, "Panic({code})"),
+            },
+            RevertKind::CustomSelector(selector, data) => {
+                write!(f, "0x{}{}", hex::encode(selector), hex::encode(data))
+            }
+            RevertKind::Malformed(data) => write!(f, "0x{}", hex::encode(data)),
+        }
+    }
+}
+
+pub struct RevertDecoder;
+
+impl RevertDecoder {
+    /// Classifies the data returned by a reverted call.
+    ///
+    /// `Error(string)` and `Panic(uint256)` data whose payload doesn't decode falls through to
+    /// [`RevertKind::CustomSelector`], like any unknown selector.
+    ///
+    /// A bare `revert` returns no data:
+    ///
+    /// ```
+    /// use tycho_simulation::evm::abi::revert::{RevertDecoder, RevertKind};
+    ///
+    /// assert_eq!(RevertDecoder::decode(&[]), RevertKind::Empty);
+    /// ```
+    ///
+    /// `require(amount > 0, "Amount too low")` returns the message as `Error(string)`:
+    ///
+    /// ```
+    /// use tycho_simulation::evm::abi::revert::{RevertDecoder, RevertKind};
+    ///
+    /// let data = hex::decode(concat!(
+    ///     "08c379a0",
+    ///     "0000000000000000000000000000000000000000000000000000000000000020",
+    ///     "000000000000000000000000000000000000000000000000000000000000000e",
+    ///     "416d6f756e7420746f6f206c6f77000000000000000000000000000000000000",
+    /// ))
+    /// .unwrap();
+    ///
+    /// assert_eq!(
+    ///     RevertDecoder::decode(&data),
+    ///     RevertKind::ErrorString("Amount too low".to_string())
+    /// );
+    /// ```
+    ///
+    /// An overflow in checked arithmetic panics with code `0x11`:
+    ///
+    /// ```
+    /// use alloy_primitives::U256;
+    /// use tycho_simulation::evm::abi::revert::{RevertDecoder, RevertKind};
+    ///
+    /// let data = hex::decode(concat!(
+    ///     "4e487b71",
+    ///     "0000000000000000000000000000000000000000000000000000000000000011",
+    /// ))
+    /// .unwrap();
+    ///
+    /// let kind = RevertDecoder::decode(&data);
+    ///
+    /// assert_eq!(kind, RevertKind::Panic(U256::from(0x11)));
+    /// assert_eq!(kind.to_string(), "ArithmeticOver/Underflow");
+    /// ```
+    ///
+    /// A custom error, here `T()` of the Uniswap V3 tick math ports, returns its own selector:
+    ///
+    /// ```
+    /// use tycho_simulation::evm::abi::revert::{RevertDecoder, RevertKind};
+    ///
+    /// let data = hex::decode("2bc80f3a").unwrap();
+    ///
+    /// assert_eq!(
+    ///     RevertDecoder::decode(&data),
+    ///     RevertKind::CustomSelector([0x2b, 0xc8, 0x0f, 0x3a], vec![])
+    /// );
+    /// ```
+    pub fn decode(data: &[u8]) -> RevertKind {
+        if data.is_empty() {
+            return RevertKind::Empty;
+        }
+        let Some((selector, payload)) = data
+            .split_first_chunk::<4>()
+            .map(|(selector, payload)| (*selector, payload))
+        else {
+            return RevertKind::Malformed(data.to_vec());
+        };
+        match selector {
+            ERROR_STRING_SELECTOR => {
+                if let Ok(message) = String::abi_decode(payload, true) {
+                    return RevertKind::ErrorString(message);
+                }
+            }
+            PANIC_SELECTOR => {
+                if let Ok(code) = U256::abi_decode(payload, true) {
+                    return RevertKind::Panic(code);
+                }
+            }
+            _ => {}
+        }
+        RevertKind::CustomSelector(selector, payload.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    use crate::evm::protocol::abi::selector;
+
+    #[test]
+    fn test_selectors() {
+        assert_eq!(selector("Error(string)"), ERROR_STRING_SELECTOR);
+        assert_eq!(selector("Panic(uint256)"), PANIC_SELECTOR);
+    }
+
+    #[rstest]
+    #[case::short(&[0x08, 0xc3, 0x79], RevertKind::Malformed(vec![0x08, 0xc3, 0x79]))]
+    #[case::selector_only(
+        &ERROR_STRING_SELECTOR,
+        RevertKind::CustomSelector(ERROR_STRING_SELECTOR, vec![])
+    )]
+    #[case::truncated_panic(
+        &[0x4e, 0x48, 0x7b, 0x71, 0x11],
+        RevertKind::CustomSelector(PANIC_SELECTOR, vec![0x11])
+    )]
+    fn test_decode_malformed(#[case] data: &[u8], #[case] expected: RevertKind) {
+        assert_eq!(RevertDecoder::decode(data), expected);
+    }
+
+    #[test]
+    fn test_display_unknown_panic() {
+        assert_eq!(RevertKind::Panic(U256::from(0x99)).to_string(), "Panic(153)");
+    }
+}
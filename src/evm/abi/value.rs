@@ -0,0 +1,411 @@
+//! Dynamically typed ABI values.
+//!
+//! Supports the elementary Solidity types (no arrays or tuples), which covers error parameters
+//! and the return values of the common view functions.
+use std::{fmt, str::FromStr};
+
+use alloy_primitives::{Address, I256, U256};
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AbiValueError {
+    #[error("Unsupported ABI type: {0}")]
+    UnsupportedType(String),
+    #[error("Expected {expected} values, got {actual}")]
+    CountMismatch { expected: usize, actual: usize },
+    #[error("ABI data too short: needed {needed} bytes, got {actual}")]
+    TooShort { needed: usize, actual: usize },
+    #[error("Invalid value for parameter {index} of type {ty}: {reason}")]
+    InvalidValue { index: usize, ty: ParamType, reason: String },
+}
+
+/// An elementary Solidity type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ParamType {
+    Address,
+    Bool,
+    /// An unsigned integer of the given number of bits.
+    Uint(usize),
+    /// A signed integer of the given number of bits.
+    Int(usize),
+    /// A fixed size byte array of the given length.
+    FixedBytes(usize),
+    Bytes,
+    String,
+}
+
+impl ParamType {
+    /// Whether values of this type are stored in the tail of the encoding.
+    pub fn is_dynamic(&self) -> bool {
+        matches!(self, ParamType::Bytes | ParamType::String)
+    }
+}
+
+impl fmt::Display for ParamType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamType::Address => write!(f, "address"),
+            ParamType::Bool => write!(f, "bool"),
+            ParamType::Uint(bits) => write!(f, "uint{bits}"),
+            ParamType::Int(bits) => write!(f, "int{bits}"),
+            ParamType::FixedBytes(len) => write!(f, "bytes{len}"),
+            ParamType::Bytes => write!(f, "bytes"),
+            ParamType::String => write!(f, "string"),
+        }
+    }
+}
+
+impl FromStr for ParamType {
+    type Err = AbiValueError;
+
+    /// Parses a canonical or shorthand type name, e.g. `uint160`, `int` or `bytes32`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unsupported = || AbiValueError::UnsupportedType(s.to_string());
+        let bits = |suffix: &str| -> Result<usize, AbiValueError> {
+            if suffix.is_empty() {
+                return Ok(256);
+            }
+            match suffix.parse::<usize>() {
+                Ok(bits) if bits > 0 && bits <= 256 && bits % 8 == 0 => Ok(bits),
+                _ => Err(unsupported()),
+            }
+        };
+        match s.trim() {
+            "address" => Ok(ParamType::Address),
+            "bool" => Ok(ParamType::Bool),
+            "string" => Ok(ParamType::String),
+            "bytes" => Ok(ParamType::Bytes),
+            t if t.starts_with("uint") => Ok(ParamType::Uint(bits(&t[4..])?)),
+            t if t.starts_with("int") => Ok(ParamType::Int(bits(&t[3..])?)),
+            t if t.starts_with("bytes") => match t[5..].parse::<usize>() {
+                Ok(len) if len > 0 && len <= 32 => Ok(ParamType::FixedBytes(len)),
+                _ => Err(unsupported()),
+            },
+            _ => Err(unsupported()),
+        }
+    }
+}
+
+/// A decoded ABI value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AbiValue {
+    Address(Address),
+    Bool(bool),
+    Uint(U256),
+    Int(I256),
+    FixedBytes(Vec<u8>),
+    Bytes(Vec<u8>),
+    String(String),
+}
+
+impl fmt::Display for AbiValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AbiValue::Address(address) => write!(f, "{address}"),
+            AbiValue::Bool(value) => write!(f, "{value}"),
+            AbiValue::Uint(value) => write!(f, "{value}"),
+            AbiValue::Int(value) => write!(f, "{value}"),
+            AbiValue::FixedBytes(bytes) | AbiValue::Bytes(bytes) => {
+                write!(f, "0x{}", hex::encode(bytes))
+            }
+            AbiValue::String(value) => write!(f, "{value:?}"),
+        }
+    }
+}
+
+/// Parses a comma separated list of types in parentheses, e.g. `(address,uint24)`.
+pub fn parse_param_types(list: &str) -> Result<Vec<ParamType>, AbiValueError> {
+    list.trim()
+        .strip_prefix('(')
+        .and_then(|l| l.strip_suffix(')'))
+        .ok_or_else(|| AbiValueError::UnsupportedType(list.to_string()))?
+        .split(',')
+        .filter(|p| !p.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// Returns the canonical signature of a function or error, e.g. `getPool(address,address,uint24)`.
+pub fn canonical_signature(name: &str, types: &[ParamType]) -> String {
+    let types: Vec<String> = types
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    format!("{}({})", name, types.join(","))
+}
+
+/// Encodes `values` as a tuple of `types`, checking every value against its type.
+pub fn encode_values(types: &[ParamType], values: &[AbiValue]) -> Result<Vec<u8>, AbiValueError> {
+    if types.len() != values.len() {
+        return Err(AbiValueError::CountMismatch { expected: types.len(), actual: values.len() });
+    }
+    let mut head = Vec::with_capacity(types.len() * 32);
+    let mut tail = Vec::new();
+    for (index, (ty, value)) in types.iter().zip(values).enumerate() {
+        let mismatch = || invalid(index, ty, &format!("got {value}"));
+        let word = match (ty, value) {
+            (ParamType::Address, AbiValue::Address(address)) => {
+                U256::from_be_bytes(address.into_word().0)
+            }
+            (ParamType::Bool, AbiValue::Bool(value)) => U256::from(*value as u8),
+            (ParamType::Uint(bits), AbiValue::Uint(value)) => {
+                if !fits_uint(*value, *bits) {
+                    return Err(invalid(index, ty, "value out of range"));
+                }
+                *value
+            }
+            (ParamType::Int(bits), AbiValue::Int(value)) => {
+                if !fits_int(value.into_raw(), *bits) {
+                    return Err(invalid(index, ty, "value out of range"));
+                }
+                value.into_raw()
+            }
+            (ParamType::FixedBytes(len), AbiValue::FixedBytes(bytes)) if bytes.len() == *len => {
+                let mut word = [0u8; 32];
+                word[..*len].copy_from_slice(bytes);
+                U256::from_be_bytes(word)
+            }
+            (ParamType::Bytes, AbiValue::Bytes(bytes)) => {
+                encode_dynamic(types.len() * 32 + tail.len(), bytes, &mut tail)
+            }
+            (ParamType::String, AbiValue::String(value)) => {
+                encode_dynamic(types.len() * 32 + tail.len(), value.as_bytes(), &mut tail)
+            }
+            _ => return Err(mismatch()),
+        };
+        head.extend_from_slice(&word.to_be_bytes::<32>());
+    }
+    head.extend(tail);
+    Ok(head)
+}
+
+/// Appends the length prefixed and padded `bytes` to `tail`. Returns the offset to put in the
+/// head.
+fn encode_dynamic(offset: usize, bytes: &[u8], tail: &mut Vec<u8>) -> U256 {
+    tail.extend_from_slice(&U256::from(bytes.len()).to_be_bytes::<32>());
+    tail.extend_from_slice(bytes);
+    tail.resize(tail.len() + (32 - bytes.len() % 32) % 32, 0);
+    U256::from(offset)
+}
+
+fn fits_uint(word: U256, bits: usize) -> bool {
+    bits == 256 || word >> bits == U256::ZERO
+}
+
+/// Whether all bits above the sign bit equal the sign bit.
+fn fits_int(word: U256, bits: usize) -> bool {
+    let upper = word >> (bits - 1);
+    bits == 256 || upper == U256::ZERO || upper == U256::MAX >> (bits - 1)
+}
+
+/// Decodes `data` as the ABI encoding of a tuple of `types`.
+///
+/// Trailing bytes are ignored, like Solidity's `abi.decode` does.
+pub fn decode_values(types: &[ParamType], data: &[u8]) -> Result<Vec<AbiValue>, AbiValueError> {
+    let head_len = types.len() * 32;
+    if data.len() < head_len {
+        return Err(AbiValueError::TooShort { needed: head_len, actual: data.len() });
+    }
+    types
+        .iter()
+        .enumerate()
+        .map(|(index, ty)| {
+            let head = word_at(data, index * 32)?;
+            if ty.is_dynamic() {
+                let bytes = dynamic_at(data, head)?;
+                return match ty {
+                    ParamType::String => String::from_utf8(bytes.to_vec())
+                        .map(AbiValue::String)
+                        .map_err(|_| invalid(index, ty, "not valid UTF-8")),
+                    _ => Ok(AbiValue::Bytes(bytes.to_vec())),
+                };
+            }
+            decode_word(index, ty, head)
+        })
+        .collect()
+}
+
+fn decode_word(index: usize, ty: &ParamType, word: U256) -> Result<AbiValue, AbiValueError> {
+    match *ty {
+        ParamType::Address => {
+            if word >> 160 != U256::ZERO {
+                return Err(invalid(index, ty, "dirty upper bytes"));
+            }
+            Ok(AbiValue::Address(Address::from_word(word.to_be_bytes::<32>().into())))
+        }
+        ParamType::Bool => match word {
+            w if w == U256::ZERO => Ok(AbiValue::Bool(false)),
+            w if w == U256::from(1u64) => Ok(AbiValue::Bool(true)),
+            _ => Err(invalid(index, ty, "neither 0 nor 1")),
+        },
+        ParamType::Uint(bits) => {
+            if !fits_uint(word, bits) {
+                return Err(invalid(index, ty, "value out of range"));
+            }
+            Ok(AbiValue::Uint(word))
+        }
+        ParamType::Int(bits) => {
+            if !fits_int(word, bits) {
+                return Err(invalid(index, ty, "value out of range"));
+            }
+            Ok(AbiValue::Int(I256::from_raw(word)))
+        }
+        ParamType::FixedBytes(len) => {
+            let bytes = word.to_be_bytes::<32>();
+            if bytes[len..].iter().any(|b| *b != 0) {
+                return Err(invalid(index, ty, "dirty lower bytes"));
+            }
+            Ok(AbiValue::FixedBytes(bytes[..len].to_vec()))
+        }
+        ParamType::Bytes | ParamType::String => unreachable!("dynamic types have no single word"),
+    }
+}
+
+fn word_at(data: &[u8], offset: usize) -> Result<U256, AbiValueError> {
+    let end = offset
+        .checked_add(32)
+        .ok_or(AbiValueError::TooShort { needed: usize::MAX, actual: data.len() })?;
+    data.get(offset..end)
+        .map(U256::from_be_slice)
+        .ok_or(AbiValueError::TooShort { needed: end, actual: data.len() })
+}
+
+/// Reads the length prefixed byte string at the given offset.
+fn dynamic_at(data: &[u8], offset: U256) -> Result<&[u8], AbiValueError> {
+    let too_short = || AbiValueError::TooShort { needed: usize::MAX, actual: data.len() };
+    let offset = usize::try_from(offset).map_err(|_| too_short())?;
+    let len = usize::try_from(word_at(data, offset)?).map_err(|_| too_short())?;
+    let start = offset + 32;
+    let end = start
+        .checked_add(len)
+        .ok_or_else(too_short)?;
+    data.get(start..end)
+        .ok_or(AbiValueError::TooShort { needed: end, actual: data.len() })
+}
+
+fn invalid(index: usize, ty: &ParamType, reason: &str) -> AbiValueError {
+    AbiValueError::InvalidValue { index, ty: *ty, reason: reason.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_sol_types::SolValue;
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("uint", ParamType::Uint(256))]
+    #[case("uint160", ParamType::Uint(160))]
+    #[case("int24", ParamType::Int(24))]
+    #[case("bytes32", ParamType::FixedBytes(32))]
+    #[case("bytes", ParamType::Bytes)]
+    #[case("address", ParamType::Address)]
+    fn test_parse_param_type(#[case] name: &str, #[case] expected: ParamType) {
+        assert_eq!(name.parse::<ParamType>().unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("uint7")]
+    #[case("bytes33")]
+    #[case("uint256[]")]
+    #[case("tuple")]
+    fn test_parse_unsupported_param_type(#[case] name: &str) {
+        assert!(matches!(name.parse::<ParamType>(), Err(AbiValueError::UnsupportedType(_))));
+    }
+
+    #[test]
+    fn test_decode_values() {
+        let address = Address::repeat_byte(0x11);
+        let data = (address, U256::from(7u64), "hello".to_string(), true).abi_encode_params();
+        let types = [ParamType::Address, ParamType::Uint(256), ParamType::String, ParamType::Bool];
+
+        let res = decode_values(&types, &data).unwrap();
+
+        assert_eq!(
+            res,
+            vec![
+                AbiValue::Address(address),
+                AbiValue::Uint(U256::from(7u64)),
+                AbiValue::String("hello".to_string()),
+                AbiValue::Bool(true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_encode_values_round_trip() {
+        let types = [ParamType::Int(24), ParamType::Bytes, ParamType::FixedBytes(2)];
+        let values = vec![
+            AbiValue::Int(I256::try_from(-60).unwrap()),
+            AbiValue::Bytes(vec![0xab; 33]),
+            AbiValue::FixedBytes(vec![0x12, 0x34]),
+        ];
+
+        let data = encode_values(&types, &values).unwrap();
+
+        assert_eq!(data.len(), 3 * 32 + 32 + 64);
+        assert_eq!(decode_values(&types, &data).unwrap(), values);
+    }
+
+    #[test]
+    fn test_encode_values_matches_alloy() {
+        let address = Address::repeat_byte(0x11);
+        let types = [ParamType::Address, ParamType::String, ParamType::Uint(24)];
+        let values = vec![
+            AbiValue::Address(address),
+            AbiValue::String("hello".to_string()),
+            AbiValue::Uint(U256::from(3000u64)),
+        ];
+
+        let data = encode_values(&types, &values).unwrap();
+
+        assert_eq!(data, (address, "hello".to_string(), U256::from(3000u64)).abi_encode_params());
+    }
+
+    #[rstest]
+    #[case(ParamType::Uint(8), AbiValue::Uint(U256::from(256u64)))]
+    #[case(ParamType::Int(8), AbiValue::Int(I256::try_from(128).unwrap()))]
+    #[case(ParamType::Address, AbiValue::Bool(true))]
+    #[case(ParamType::FixedBytes(4), AbiValue::FixedBytes(vec![0; 3]))]
+    fn test_encode_invalid_value(#[case] ty: ParamType, #[case] value: AbiValue) {
+        let res = encode_values(&[ty], &[value]);
+
+        assert!(matches!(res, Err(AbiValueError::InvalidValue { index: 0, .. })));
+    }
+
+    #[test]
+    fn test_parse_param_types() {
+        assert_eq!(
+            parse_param_types("(uint160, int24,bool)").unwrap(),
+            vec![ParamType::Uint(160), ParamType::Int(24), ParamType::Bool]
+        );
+        assert_eq!(parse_param_types("()").unwrap(), vec![]);
+        assert!(parse_param_types("uint256").is_err());
+    }
+
+    #[test]
+    fn test_decode_negative_int() {
+        let data = I256::try_from(-5).unwrap().abi_encode();
+
+        let res = decode_values(&[ParamType::Int(24)], &data).unwrap();
+
+        assert_eq!(res, vec![AbiValue::Int(I256::try_from(-5).unwrap())]);
+    }
+
+    #[test]
+    fn test_decode_out_of_range() {
+        let data = U256::from(256u64).abi_encode();
+
+        let res = decode_values(&[ParamType::Uint(8)], &data);
+
+        assert!(matches!(res, Err(AbiValueError::InvalidValue { index: 0, .. })));
+    }
+
+    #[test]
+    fn test_decode_too_short() {
+        let res = decode_values(&[ParamType::Uint(256), ParamType::Bool], &[0u8; 40]);
+
+        assert_eq!(res, Err(AbiValueError::TooShort { needed: 64, actual: 40 }));
+    }
+}
@@ -0,0 +1,322 @@
+//! Generic view calls against the simulation engine.
+//!
+//! [`ViewCall`] covers the many small reads - a pool's `slot0`, an oracle's `latestAnswer`, a
+//! factory's `getPool` - that otherwise each need hand-rolled calldata and decoding. It is built
+//! from a signature with return types, e.g. `slot0()(uint160,int24,uint16,uint16,uint16,uint8,
+//! bool)`, encodes arguments given as [`AbiValue`]s and decodes the return data accordingly.
+use std::fmt::Debug;
+
+use alloy_primitives::{Address, U256};
+use revm::DatabaseRef;
+use thiserror::Error;
+
+use super::value::{
+    canonical_signature, decode_values, encode_values, parse_param_types, AbiValue, AbiValueError,
+    ParamType,
+};
+use crate::evm::{
+    engine_db::engine_db_interface::{EngineDatabaseError, EngineDatabaseInterface},
+    protocol::abi::selector,
+    simulation::{SimulationEngine, SimulationEngineError, SimulationParameters},
+};
+
+/// Gas limit of a single view call.
+const VIEW_CALL_GAS_LIMIT: u64 = 5_000_000;
+
+#[derive(Error, Debug)]
+pub enum ViewCallError {
+    #[error("Invalid signature {0}: expected e.g. `getPool(address,address,uint24)(address)`")]
+    InvalidSignature(String, #[source] Option<AbiValueError>),
+    #[error("Invalid arguments for {function}")]
+    Arguments {
+        function: String,
+        #[source]
+        source: AbiValueError,
+    },
+    #[error("Call to {function} failed")]
+    Simulation {
+        function: String,
+        #[source]
+        source: SimulationEngineError,
+    },
+    #[error("Failed to decode return value of {function} as ({types}) from {len} bytes")]
+    Decode {
+        function: String,
+        types: String,
+        len: usize,
+        #[source]
+        source: AbiValueError,
+    },
+}
+
+/// A read-only function call with known argument and return types.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ViewCall {
+    name: String,
+    inputs: Vec<ParamType>,
+    outputs: Vec<ParamType>,
+}
+
+impl ViewCall {
+    pub fn new(name: &str, inputs: Vec<ParamType>, outputs: Vec<ParamType>) -> Self {
+        Self { name: name.to_string(), inputs, outputs }
+    }
+
+    /// Parses a signature followed by the return types, e.g. `balanceOf(address)(uint256)`.
+    /// Functions without return values may omit the second list.
+    pub fn parse(signature: &str) -> Result<Self, ViewCallError> {
+        let invalid = |source| ViewCallError::InvalidSignature(signature.to_string(), source);
+        let signature_trimmed = signature.trim();
+        let (name, rest) = signature_trimmed
+            .split_once('(')
+            .ok_or_else(|| invalid(None))?;
+        let (inputs, outputs) = rest
+            .split_once(')')
+            .ok_or_else(|| invalid(None))?;
+        if name.trim().is_empty() {
+            return Err(invalid(None));
+        }
+        let inputs = parse_param_types(&format!("({inputs})")).map_err(|e| invalid(Some(e)))?;
+        let outputs = if outputs.trim().is_empty() {
+            Vec::new()
+        } else {
+            parse_param_types(outputs).map_err(|e| invalid(Some(e)))?
+        };
+        Ok(Self::new(name.trim(), inputs, outputs))
+    }
+
+    /// The canonical function signature, without return types.
+    pub fn signature(&self) -> String {
+        canonical_signature(&self.name, &self.inputs)
+    }
+
+    pub fn selector(&self) -> [u8; 4] {
+        selector(&self.signature())
+    }
+
+    pub fn outputs(&self) -> &[ParamType] {
+        &self.outputs
+    }
+
+    /// Calldata calling the function with `args`.
+    pub fn encode(&self, args: &[AbiValue]) -> Result<Vec<u8>, ViewCallError> {
+        let encoded = encode_values(&self.inputs, args)
+            .map_err(|source| ViewCallError::Arguments { function: self.signature(), source })?;
+        let mut calldata = self.selector().to_vec();
+        calldata.extend(encoded);
+        Ok(calldata)
+    }
+
+    /// Decodes the return data of the function.
+    pub fn decode(&self, data: &[u8]) -> Result<Vec<AbiValue>, ViewCallError> {
+        decode_values(&self.outputs, data).map_err(|source| ViewCallError::Decode {
+            function: self.signature(),
+            types: self
+                .outputs
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+            len: data.len(),
+            source,
+        })
+    }
+
+    /// Calls the function on `to` and decodes the result.
+    ///
+    /// The call is executed as a regular simulation; any state changes it makes are discarded.
+    pub fn call<D>(
+        &self,
+        engine: &SimulationEngine<D>,
+        to: Address,
+        args: &[AbiValue],
+        block_number: u64,
+        timestamp: u64,
+    ) -> Result<Vec<AbiValue>, ViewCallError>
+    where
+        D: EngineDatabaseInterface + Clone + Debug,
+        <D as DatabaseRef>::Error: EngineDatabaseError,
+        <D as EngineDatabaseInterface>::Error: Debug,
+    {
+        let params = SimulationParameters {
+            caller: Address::ZERO,
+            to,
+            data: self.encode(args)?,
+            value: U256::ZERO,
+            overrides: None,
+            balance_overrides: None,
+            nonce: None,
+            gas_limit: Some(VIEW_CALL_GAS_LIMIT),
+            block_number,
+            timestamp,
+        };
+        let result = engine
+            .simulate(&params)
+            .map_err(|source| ViewCallError::Simulation { function: self.signature(), source })?;
+        self.decode(&result.result)
+    }
+}
+
+/// Executes many view calls against the same engine state. A failing call doesn't affect the
+/// others; results are returned in call order.
+pub fn call_batch<D>(
+    engine: &SimulationEngine<D>,
+    calls: &[(&ViewCall, Address, Vec<AbiValue>)],
+    block_number: u64,
+    timestamp: u64,
+) -> Vec<Result<Vec<AbiValue>, ViewCallError>>
+where
+    D: EngineDatabaseInterface + Clone + Debug,
+    <D as DatabaseRef>::Error: EngineDatabaseError,
+    <D as EngineDatabaseInterface>::Error: Debug,
+{
+    calls
+        .iter()
+        .map(|(call, to, args)| call.call(engine, *to, args, block_number, timestamp))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, error::Error};
+
+    use alloy_primitives::{hex, keccak256, I256};
+    use revm::primitives::{AccountInfo, Bytecode};
+
+    use super::*;
+    use crate::evm::{
+        engine_db::{create_engine, tycho_db::PreCachedDB},
+        protocol::uniswap_v3::abi::fixtures::{deploy_slot0_pool, slot0},
+    };
+
+    const SLOT0: &str = "slot0()(uint160,int24,uint16,uint16,uint16,uint8,bool)";
+
+    /// Runtime code returning its call arguments, i.e. calldata without the selector.
+    const ECHO_CODE: &[u8] = &hex!("600436 03 6004 6000 37 600436 03 6000 f3");
+
+    fn engine() -> SimulationEngine<PreCachedDB> {
+        create_engine(PreCachedDB::new().unwrap(), false).unwrap()
+    }
+
+    fn deploy_echo(engine: &SimulationEngine<PreCachedDB>, address: Address) {
+        let code = Bytecode::new_raw(ECHO_CODE.into());
+        engine.state.init_account(
+            address,
+            AccountInfo {
+                balance: U256::ZERO,
+                nonce: 0,
+                code_hash: keccak256(code.bytes()),
+                code: Some(code),
+            },
+            Some(HashMap::new()),
+            true,
+        );
+    }
+
+    #[test]
+    fn test_parse_signature() {
+        let call = ViewCall::parse("getPool(address, address, uint24)(address)").unwrap();
+
+        assert_eq!(call.signature(), "getPool(address,address,uint24)");
+        assert_eq!(call.selector(), hex!("1698ee82"));
+        assert_eq!(call.outputs(), &[ParamType::Address]);
+        assert!(ViewCall::parse("getPool").is_err());
+        assert!(ViewCall::parse("getPool(address)(uint7)").is_err());
+    }
+
+    #[test]
+    fn test_read_slot0() {
+        let engine = engine();
+        let pool = Address::repeat_byte(0x33);
+        deploy_slot0_pool(&engine.state, pool);
+        let expected = slot0();
+
+        let res = ViewCall::parse(SLOT0)
+            .unwrap()
+            .call(&engine, pool, &[], 0, 0)
+            .unwrap();
+
+        assert_eq!(
+            res,
+            vec![
+                AbiValue::Uint(expected.sqrt_price_x96),
+                AbiValue::Int(I256::try_from(expected.tick).unwrap()),
+                AbiValue::Uint(U256::from(expected.observation_index)),
+                AbiValue::Uint(U256::from(expected.observation_cardinality)),
+                AbiValue::Uint(U256::from(expected.observation_cardinality_next)),
+                AbiValue::Uint(U256::from(expected.fee_protocol)),
+                AbiValue::Bool(expected.unlocked),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multiple_return_values() {
+        let engine = engine();
+        let echo = Address::repeat_byte(0x44);
+        deploy_echo(&engine, echo);
+        let call = ViewCall::parse("echo(uint24,address,string)(uint24,address,string)").unwrap();
+        let args = vec![
+            AbiValue::Uint(U256::from(3000u64)),
+            AbiValue::Address(Address::repeat_byte(0x11)),
+            AbiValue::String("tycho".to_string()),
+        ];
+
+        let res = call
+            .call(&engine, echo, &args, 0, 0)
+            .unwrap();
+
+        assert_eq!(res, args);
+    }
+
+    #[test]
+    fn test_decoding_failure_is_explained() {
+        let engine = engine();
+        let echo = Address::repeat_byte(0x44);
+        deploy_echo(&engine, echo);
+        // Returns a single word, but three are expected.
+        let call = ViewCall::new(
+            "echo",
+            vec![ParamType::Uint(256)],
+            vec![ParamType::Uint(256), ParamType::Address, ParamType::Bool],
+        );
+
+        let err = call
+            .call(&engine, echo, &[AbiValue::Uint(U256::from(1u64))], 0, 0)
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Failed to decode return value of echo(uint256) as (uint256,address,bool) from 32 bytes"
+        );
+        assert_eq!(
+            err.source().unwrap().to_string(),
+            "ABI data too short: needed 96 bytes, got 32"
+        );
+    }
+
+    #[test]
+    fn test_call_batch() {
+        let engine = engine();
+        let pool = Address::repeat_byte(0x33);
+        deploy_slot0_pool(&engine.state, pool);
+        let slot0_call = ViewCall::parse(SLOT0).unwrap();
+        let bad_args = ViewCall::parse("getPool(address)(address)").unwrap();
+
+        let res = call_batch(
+            &engine,
+            &[
+                (&slot0_call, pool, vec![]),
+                (&bad_args, pool, vec![AbiValue::Bool(true)]),
+                (&slot0_call, pool, vec![]),
+            ],
+            0,
+            0,
+        );
+
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0].as_ref().unwrap().len(), 7);
+        assert!(matches!(res[1], Err(ViewCallError::Arguments { .. })));
+        assert_eq!(res[0].as_ref().unwrap(), res[2].as_ref().unwrap());
+    }
+}
@@ -1,7 +1,7 @@
 use std::collections::{hash_map::Entry::Vacant, HashMap};
 
-use alloy_primitives::{Address, U256};
-use revm::primitives::AccountInfo;
+use alloy_primitives::{Address, B256, U256};
+use revm::primitives::{AccountInfo, Bytecode};
 use tracing::{debug, warn};
 
 /// Represents an account in the account storage.
@@ -20,10 +20,61 @@ pub struct Account {
     pub mocked: bool,
 }
 
+impl Account {
+    /// Applies `update` and returns the update reverting it.
+    ///
+    /// Deleted slots are removed before the written slots are set, so an update deleting and
+    /// writing the same slot leaves the written value. The revert restores the previous value of
+    /// every changed slot and deletes the written slots that weren't set before. Replaced code is
+    /// restored with its hash as it was, including code that wasn't loaded.
+    pub fn apply_update(&mut self, update: &StateUpdate) -> StateUpdate {
+        let mut revert = StateUpdate { balance: Some(self.info.balance), ..Default::default() };
+        if let Some(new_balance) = update.balance {
+            self.info.balance = new_balance;
+        }
+        if let Some((code, code_hash)) = &update.code {
+            revert.code = Some((
+                std::mem::replace(&mut self.info.code, code.clone()),
+                std::mem::replace(&mut self.info.code_hash, *code_hash),
+            ));
+        }
+        let mut revert_storage = HashMap::new();
+        for index in &update.deleted_slots {
+            self.temp_storage.remove(index);
+            if let Some(previous) = self.permanent_storage.remove(index) {
+                revert_storage.insert(*index, previous);
+            }
+        }
+        for (index, value) in update.storage.iter().flatten() {
+            match self
+                .permanent_storage
+                .insert(*index, *value)
+            {
+                Some(previous) => {
+                    revert_storage
+                        .entry(*index)
+                        .or_insert(previous);
+                }
+                None if !revert_storage.contains_key(index) => revert.deleted_slots.push(*index),
+                None => {}
+            }
+        }
+        if update.storage.is_some() || !revert_storage.is_empty() {
+            revert.storage = Some(revert_storage);
+        }
+        revert
+    }
+}
+
 #[derive(Default, Clone, PartialEq, Eq, Debug)]
 pub struct StateUpdate {
     pub storage: Option<HashMap<U256, U256>>,
     pub balance: Option<U256>,
+    /// Slots reset to zero and no longer tracked. Deleting a slot that isn't set is a no-op.
+    pub deleted_slots: Vec<U256>,
+    /// New code of the account and its hash, `None` if it didn't change. The code itself is `None`
+    /// if it isn't loaded, like in [`AccountInfo`].
+    pub code: Option<(Option<Bytecode>, B256)>,
 }
 #[derive(Clone, Default, Debug)]
 /// A simpler implementation of CacheDB that can't query a node. It just stores data.
@@ -90,20 +141,32 @@ impl AccountStorage {
     /// If the `address` is not found in either collection, a warning is logged and no changes are
     /// made.
     pub fn update_account(&mut self, address: &Address, update: &StateUpdate) {
-        if let Some(account) = self.accounts.get_mut(address) {
-            if let Some(new_balance) = update.balance {
-                account.info.balance = new_balance;
-            }
-            if let Some(new_storage) = &update.storage {
-                for (index, value) in new_storage {
-                    account
-                        .permanent_storage
-                        .insert(*index, *value);
-                }
-            }
-        } else {
+        self.apply_update(address, update);
+    }
+
+    /// Like [`Self::update_account`], but returns the update reverting the changes, see
+    /// [`Account::apply_update`]. Returns `None` if the account isn't initialized.
+    pub fn apply_update(&mut self, address: &Address, update: &StateUpdate) -> Option<StateUpdate> {
+        let Some(account) = self.accounts.get_mut(address) else {
             warn!(?address, "Tried to update account {:x?} that was not initialized", address);
-        }
+            return None;
+        };
+        Some(account.apply_update(update))
+    }
+
+    /// Inserts `account` at `address`, replacing the account stored there.
+    pub fn insert_account(&mut self, address: Address, account: Account) {
+        self.accounts.insert(address, account);
+    }
+
+    /// Returns the account with the given address, if present.
+    pub fn get_account(&self, address: &Address) -> Option<&Account> {
+        self.accounts.get(address)
+    }
+
+    /// Removes the account with the given address, returning it if it was present.
+    pub fn remove_account(&mut self, address: &Address) -> Option<Account> {
+        self.accounts.remove(address)
     }
 
     /// Retrieves the account information for a given address.
@@ -125,6 +188,11 @@ impl AccountStorage {
             .map(|acc| &acc.info)
     }
 
+    /// Iterates over all accounts in the storage.
+    pub fn accounts(&self) -> impl Iterator<Item = (&Address, &Account)> {
+        self.accounts.iter()
+    }
+
     /// Checks if an account with the given address is present in the storage.
     ///
     /// # Arguments
@@ -215,6 +283,28 @@ impl AccountStorage {
             .for_each(|acc| acc.temp_storage.clear());
     }
 
+    /// Number of temp storage values over all accounts.
+    pub fn temp_slots(&self) -> usize {
+        self.accounts
+            .values()
+            .map(|acc| acc.temp_storage.len())
+            .sum()
+    }
+
+    /// Clears the temp storage of one account after the other until at least `slots` values are
+    /// removed, or all of them. Returns the number of values removed.
+    pub fn shed_temp_storage(&mut self, slots: usize) -> usize {
+        let mut removed = 0;
+        for acc in self.accounts.values_mut() {
+            if removed >= slots {
+                break;
+            }
+            removed += acc.temp_storage.len();
+            acc.temp_storage.clear();
+        }
+        removed
+    }
+
     /// Checks if an account is mocked based on its address.
     ///
     /// # Arguments
@@ -296,8 +386,11 @@ mod tests {
         let updated_storage_value = U256::from_str("999").unwrap();
         let mut updated_storage = HashMap::new();
         updated_storage.insert(storage_index, updated_storage_value);
-        let state_update =
-            StateUpdate { balance: Some(updated_balance), storage: Some(updated_storage) };
+        let state_update = StateUpdate {
+            balance: Some(updated_balance),
+            storage: Some(updated_storage),
+            ..Default::default()
+        };
 
         account_storage.update_account(&acc_address, &state_update);
 
@@ -319,6 +412,91 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_apply_update_deleted_slots() {
+        let mut account_storage = AccountStorage::default();
+        let address = Address::from_str("0xb4e16d0168e52d35cacd2c6185b44281ec28c9dc").unwrap();
+        let original_storage =
+            HashMap::from([(U256::from(1), U256::from(5)), (U256::from(2), U256::from(7))]);
+        account_storage.init_account(
+            address,
+            AccountInfo { balance: U256::from(500), ..Default::default() },
+            Some(original_storage.clone()),
+            false,
+        );
+        let update = StateUpdate {
+            storage: Some(HashMap::from([
+                (U256::from(2), U256::from(8)),
+                (U256::from(4), U256::from(9)),
+            ])),
+            balance: Some(U256::from(100)),
+            // slot 3 was never set
+            deleted_slots: vec![U256::from(1), U256::from(3)],
+            ..Default::default()
+        };
+
+        let revert = account_storage
+            .apply_update(&address, &update)
+            .unwrap();
+
+        assert_eq!(account_storage.get_storage(&address, &U256::from(1)), None);
+        assert_eq!(account_storage.get_storage(&address, &U256::from(2)), Some(U256::from(8)));
+        assert_eq!(account_storage.get_storage(&address, &U256::from(3)), None);
+        assert_eq!(account_storage.get_storage(&address, &U256::from(4)), Some(U256::from(9)));
+        assert_eq!(
+            revert,
+            StateUpdate {
+                storage: Some(original_storage.clone()),
+                balance: Some(U256::from(500)),
+                deleted_slots: vec![U256::from(4)],
+                code: None,
+            }
+        );
+
+        account_storage.update_account(&address, &revert);
+
+        assert_eq!(account_storage.accounts[&address].permanent_storage, original_storage);
+        assert_eq!(
+            account_storage
+                .get_account_info(&address)
+                .unwrap()
+                .balance,
+            U256::from(500)
+        );
+    }
+
+    #[test]
+    fn test_revert_restores_unloaded_code() {
+        let mut account_storage = AccountStorage::default();
+        let address = Address::repeat_byte(1);
+        // a contract whose code wasn't loaded, only its hash is known
+        let info =
+            AccountInfo { code_hash: B256::repeat_byte(2), code: None, ..Default::default() };
+        account_storage.init_account(address, info.clone(), None, false);
+        let code = Bytecode::new_raw(revm::primitives::Bytes::from_static(&[0x60, 0x00]));
+        let update = StateUpdate {
+            code: Some((Some(code.clone()), code.hash_slow())),
+            ..Default::default()
+        };
+
+        let revert = account_storage
+            .apply_update(&address, &update)
+            .unwrap();
+
+        assert_eq!(
+            account_storage
+                .get_account_info(&address)
+                .unwrap()
+                .code,
+            Some(code)
+        );
+        assert_eq!(revert.code, Some((None, B256::repeat_byte(2))));
+
+        account_storage.update_account(&address, &revert);
+
+        assert_eq!(account_storage.get_account_info(&address), Some(&info));
+    }
+
     #[test]
     fn test_get_account_info() {
         let mut account_storage = AccountStorage::default();
@@ -514,6 +692,30 @@ mod tests {
         assert_eq!(account_2_temp_storage, 0, "Temporary storage of account 2 should be cleared");
     }
 
+    #[test]
+    fn test_shed_temp_storage() {
+        let mut account_storage = AccountStorage::default();
+        for i in 1..=3u8 {
+            let mut account = Account::default();
+            account
+                .temp_storage
+                .extend([(U256::from(1), U256::from(i)), (U256::from(2), U256::from(i))]);
+            account
+                .permanent_storage
+                .insert(U256::from(3), U256::from(i));
+            account_storage
+                .accounts
+                .insert(Address::repeat_byte(i), account);
+        }
+
+        assert_eq!(account_storage.temp_slots(), 6);
+        assert_eq!(account_storage.shed_temp_storage(3), 4);
+        assert_eq!(account_storage.temp_slots(), 2);
+        assert!(account_storage
+            .accounts()
+            .all(|(_, account)| account.permanent_storage.len() == 1));
+    }
+
     #[test]
     fn test_get_permanent_storage() {
         let mut account_storage = AccountStorage::default();
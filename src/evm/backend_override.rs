@@ -0,0 +1,282 @@
+//! Per-pool overrides of the backend simulating a pool.
+//!
+//! When the native implementation of a protocol is suspected to mis-simulate a specific pool,
+//! e.g. one with an unusual token or fee tier, the pool can be moved to the VM implementation of
+//! its protocol at runtime through a [`QuoteBackendOverride`]. The stream decoder picks up changed
+//! overrides on the next block and re-decodes the affected pools from their latest snapshot, with
+//! all deltas received since applied. Until then the pool keeps being served by its previous
+//! backend, so it stays quotable throughout the switch.
+use std::{
+    collections::{HashMap, HashSet},
+    sync::RwLock,
+};
+
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::protocol::models::Backend;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum BackendOverrideError {
+    #[error("No VM contract data is available for pool {0}")]
+    NoVmData(String),
+}
+
+#[derive(Debug, Default)]
+struct OverrideState {
+    overrides: HashMap<String, Backend>,
+    /// Pools whose protocol has a VM implementation and whose contracts are known.
+    vm_capable: HashSet<String>,
+    /// Pools whose backend changed since the decoder last checked, with the override they had
+    /// before.
+    pending: HashMap<String, Option<Backend>>,
+}
+
+/// Registry of the backend to use for individual pools.
+///
+/// Pools without an override use the backend of the decoder registered for their protocol. The
+/// registry is internally synchronized and meant to be shared behind an `Arc` between the stream
+/// decoder and whatever changes the overrides, e.g. an admin endpoint or a config watcher.
+#[derive(Debug, Default)]
+pub struct QuoteBackendOverride {
+    state: RwLock<OverrideState>,
+}
+
+impl QuoteBackendOverride {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Simulates `pool_id` with `backend` from the next block on.
+    ///
+    /// # Errors
+    ///
+    /// * `BackendOverrideError::NoVmData` - if `backend` is [`Backend::Vm`] but the pool has no VM
+    ///   implementation, or the decoder hasn't received its snapshot yet.
+    pub fn set(&self, pool_id: &str, backend: Backend) -> Result<(), BackendOverrideError> {
+        let mut state = self.state.write().unwrap();
+        if backend == Backend::Vm && !state.vm_capable.contains(pool_id) {
+            return Err(BackendOverrideError::NoVmData(pool_id.to_string()));
+        }
+        let previous = state
+            .overrides
+            .insert(pool_id.to_string(), backend);
+        if previous != Some(backend) {
+            info!(pool = pool_id, ?backend, "QuoteBackendOverridden");
+            state
+                .pending
+                .entry(pool_id.to_string())
+                .or_insert(previous);
+        }
+        Ok(())
+    }
+
+    /// Removes the override of `pool_id`, returning it to its default backend.
+    pub fn clear(&self, pool_id: &str) {
+        let mut state = self.state.write().unwrap();
+        if let Some(previous) = state.overrides.remove(pool_id) {
+            info!(pool = pool_id, "QuoteBackendOverrideCleared");
+            state
+                .pending
+                .entry(pool_id.to_string())
+                .or_insert(Some(previous));
+        }
+    }
+
+    /// Replaces all overrides, e.g. after the config they are loaded from changed.
+    ///
+    /// # Errors
+    ///
+    /// * `BackendOverrideError::NoVmData` - as for [`Self::set`]. No override is changed if any of
+    ///   them is rejected.
+    pub fn reload(&self, overrides: HashMap<String, Backend>) -> Result<(), BackendOverrideError> {
+        let mut state = self.state.write().unwrap();
+        if let Some((pool_id, _)) = overrides
+            .iter()
+            .find(|(id, backend)| **backend == Backend::Vm && !state.vm_capable.contains(*id))
+        {
+            return Err(BackendOverrideError::NoVmData(pool_id.clone()));
+        }
+        let changed: Vec<_> = state
+            .overrides
+            .keys()
+            .chain(overrides.keys())
+            .filter(|id| state.overrides.get(*id) != overrides.get(*id))
+            .cloned()
+            .collect();
+        info!(n = overrides.len(), changed = changed.len(), "QuoteBackendOverridesReloaded");
+        for id in changed {
+            let previous = state.overrides.get(&id).copied();
+            state
+                .pending
+                .entry(id)
+                .or_insert(previous);
+        }
+        state.overrides = overrides;
+        Ok(())
+    }
+
+    /// The backend `pool_id` is overridden to, if any.
+    pub fn backend(&self, pool_id: &str) -> Option<Backend> {
+        self.state
+            .read()
+            .unwrap()
+            .overrides
+            .get(pool_id)
+            .copied()
+    }
+
+    pub fn overrides(&self) -> HashMap<String, Backend> {
+        self.state
+            .read()
+            .unwrap()
+            .overrides
+            .clone()
+    }
+
+    /// Records whether `pool_id` can be simulated in the VM.
+    pub(crate) fn set_vm_capable(&self, pool_id: &str, capable: bool) {
+        let mut state = self.state.write().unwrap();
+        if capable {
+            state
+                .vm_capable
+                .insert(pool_id.to_string());
+        } else {
+            state.vm_capable.remove(pool_id);
+        }
+    }
+
+    /// Takes the pools whose backend changed since the last call, with the override each had
+    /// before.
+    pub(crate) fn take_pending(&self) -> HashMap<String, Option<Backend>> {
+        std::mem::take(&mut self.state.write().unwrap().pending)
+    }
+
+    /// Rolls the override of `pool_id` back to `previous` after switching its backend failed, so
+    /// the registry matches the backend the pool is still served by. Overrides changed again since
+    /// they were taken are left alone.
+    pub(crate) fn restore(&self, pool_id: &str, previous: Option<Backend>) {
+        let mut state = self.state.write().unwrap();
+        if state.pending.contains_key(pool_id) {
+            return;
+        }
+        warn!(pool = pool_id, backend = ?previous, "QuoteBackendOverrideRolledBack");
+        match previous {
+            Some(backend) => state
+                .overrides
+                .insert(pool_id.to_string(), backend),
+            None => state.overrides.remove(pool_id),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> QuoteBackendOverride {
+        let registry = QuoteBackendOverride::new();
+        registry.set_vm_capable("0xaa", true);
+        registry
+    }
+
+    #[test]
+    fn test_set_requires_vm_data() {
+        let registry = registry();
+
+        assert_eq!(
+            registry.set("0xbb", Backend::Vm),
+            Err(BackendOverrideError::NoVmData("0xbb".to_string()))
+        );
+        registry
+            .set("0xbb", Backend::Native)
+            .unwrap();
+        registry
+            .set("0xaa", Backend::Vm)
+            .unwrap();
+
+        assert_eq!(registry.backend("0xaa"), Some(Backend::Vm));
+        assert_eq!(
+            registry.take_pending(),
+            HashMap::from([("0xaa".to_string(), None), ("0xbb".to_string(), None)])
+        );
+        assert!(registry.take_pending().is_empty());
+    }
+
+    #[test]
+    fn test_unchanged_override_is_not_pending() {
+        let registry = registry();
+        registry
+            .set("0xaa", Backend::Vm)
+            .unwrap();
+        registry.take_pending();
+
+        registry
+            .set("0xaa", Backend::Vm)
+            .unwrap();
+        registry.clear("0xbb");
+
+        assert!(registry.take_pending().is_empty());
+    }
+
+    #[test]
+    fn test_reload() {
+        let registry = registry();
+        registry.set_vm_capable("0xcc", true);
+        registry
+            .set("0xaa", Backend::Vm)
+            .unwrap();
+        registry
+            .set("0xbb", Backend::Native)
+            .unwrap();
+        registry.take_pending();
+
+        let rejected = registry.reload(HashMap::from([("0xdd".to_string(), Backend::Vm)]));
+        registry
+            .reload(HashMap::from([
+                ("0xaa".to_string(), Backend::Vm),
+                ("0xcc".to_string(), Backend::Vm),
+            ]))
+            .unwrap();
+
+        assert_eq!(rejected, Err(BackendOverrideError::NoVmData("0xdd".to_string())));
+        assert_eq!(registry.backend("0xbb"), None);
+        assert_eq!(
+            registry.take_pending(),
+            HashMap::from([
+                ("0xbb".to_string(), Some(Backend::Native)),
+                ("0xcc".to_string(), None)
+            ])
+        );
+    }
+
+    #[test]
+    fn test_restore() {
+        let registry = registry();
+        registry
+            .set("0xaa", Backend::Vm)
+            .unwrap();
+        let pending = registry.take_pending();
+
+        registry.restore("0xaa", pending["0xaa"]);
+
+        assert_eq!(registry.backend("0xaa"), None);
+        assert!(registry.take_pending().is_empty());
+
+        // a newer change isn't rolled back
+        registry
+            .set("0xaa", Backend::Vm)
+            .unwrap();
+        registry.take_pending();
+        registry
+            .set("0xaa", Backend::Native)
+            .unwrap();
+        registry.restore("0xaa", None);
+
+        assert_eq!(registry.backend("0xaa"), Some(Backend::Native));
+        assert_eq!(
+            registry.take_pending(),
+            HashMap::from([("0xaa".to_string(), Some(Backend::Vm))])
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! Ordering of the blocks applied to the simulation state.
+//!
+//! Block numbers normally increase by one with every block. The sequencers of some L2s however
+//! occasionally replace the block at the tip, in which case the stream emits a block whose number
+//! is the same as or one below the previous one, with a later timestamp. A [`BlockOrderingPolicy`]
+//! decides whether such a block is rejected, replaces the tip, or is simply applied on top.
+use thiserror::Error;
+use tycho_common::models::Chain;
+
+use crate::evm::engine_db::simulation_db::BlockHeader;
+
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum BlockOrderingError {
+    #[error("Block {received} does not follow block {tip}")]
+    NotIncreasing { tip: u64, received: u64 },
+    #[error(
+        "Block {received} at {received_timestamp} is older than block {tip} at {tip_timestamp}"
+    )]
+    TimestampRegressed { tip: u64, tip_timestamp: u64, received: u64, received_timestamp: u64 },
+}
+
+/// How to apply a block that passed a [`BlockOrderingPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockOrdering {
+    /// Apply the block on top of the current state.
+    Advance,
+    /// Roll back the current tip, then apply the block in its place.
+    ReplaceTip,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlockOrderingPolicy {
+    /// Rejects every block whose number doesn't exceed the tip's.
+    #[default]
+    StrictIncreasing,
+    /// Treats a block with the same number as the tip, or one less, but a different hash as a
+    /// replacement of the tip. Other blocks not exceeding the tip are rejected.
+    AllowSingleStepReplace,
+    /// Only requires timestamps not to decrease. A block with a regressing number is applied on
+    /// top of the current state without rolling anything back.
+    TimestampMonotonic,
+}
+
+impl BlockOrderingPolicy {
+    /// Tip replacements are tolerated on chains whose sequencer is known to replace blocks,
+    /// all other chains require strictly increasing block numbers.
+    pub fn for_chain(chain: Chain) -> Self {
+        match chain {
+            Chain::Arbitrum | Chain::Base | Chain::Unichain => Self::AllowSingleStepReplace,
+            _ => Self::StrictIncreasing,
+        }
+    }
+
+    /// Decides how to apply `block` on top of `tip`, the last applied block.
+    pub fn check(
+        &self,
+        tip: Option<&BlockHeader>,
+        block: &BlockHeader,
+    ) -> Result<BlockOrdering, BlockOrderingError> {
+        let Some(tip) = tip else {
+            return Ok(BlockOrdering::Advance);
+        };
+        match self {
+            Self::TimestampMonotonic if block.timestamp < tip.timestamp => {
+                Err(BlockOrderingError::TimestampRegressed {
+                    tip: tip.number,
+                    tip_timestamp: tip.timestamp,
+                    received: block.number,
+                    received_timestamp: block.timestamp,
+                })
+            }
+            Self::TimestampMonotonic => Ok(BlockOrdering::Advance),
+            _ if block.number > tip.number => Ok(BlockOrdering::Advance),
+            Self::AllowSingleStepReplace
+                if block.number + 1 >= tip.number && block.hash != tip.hash =>
+            {
+                Ok(BlockOrdering::ReplaceTip)
+            }
+            _ => Err(BlockOrderingError::NotIncreasing { tip: tip.number, received: block.number }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use revm::primitives::B256;
+    use rstest::rstest;
+
+    use super::*;
+
+    fn header(number: u64, hash: u8, timestamp: u64) -> BlockHeader {
+        BlockHeader { number, hash: B256::repeat_byte(hash), timestamp }
+    }
+
+    #[rstest]
+    #[case::strict_next(
+        BlockOrderingPolicy::StrictIncreasing,
+        header(11, 2, 13),
+        Ok(BlockOrdering::Advance)
+    )]
+    #[case::strict_same(
+        BlockOrderingPolicy::StrictIncreasing,
+        header(10, 2, 13),
+        Err(BlockOrderingError::NotIncreasing { tip: 10, received: 10 })
+    )]
+    #[case::replace_same(
+        BlockOrderingPolicy::AllowSingleStepReplace,
+        header(10, 2, 13),
+        Ok(BlockOrdering::ReplaceTip)
+    )]
+    #[case::replace_minus_one(
+        BlockOrderingPolicy::AllowSingleStepReplace,
+        header(9, 2, 13),
+        Ok(BlockOrdering::ReplaceTip)
+    )]
+    #[case::replace_duplicate(
+        BlockOrderingPolicy::AllowSingleStepReplace,
+        header(10, 1, 12),
+        Err(BlockOrderingError::NotIncreasing { tip: 10, received: 10 })
+    )]
+    #[case::replace_too_deep(
+        BlockOrderingPolicy::AllowSingleStepReplace,
+        header(8, 2, 13),
+        Err(BlockOrderingError::NotIncreasing { tip: 10, received: 8 })
+    )]
+    #[case::timestamp_regressing_number(
+        BlockOrderingPolicy::TimestampMonotonic,
+        header(9, 2, 13),
+        Ok(BlockOrdering::Advance)
+    )]
+    #[case::timestamp_regressed(
+        BlockOrderingPolicy::TimestampMonotonic,
+        header(11, 2, 11),
+        Err(BlockOrderingError::TimestampRegressed {
+            tip: 10,
+            tip_timestamp: 12,
+            received: 11,
+            received_timestamp: 11
+        })
+    )]
+    fn test_check(
+        #[case] policy: BlockOrderingPolicy,
+        #[case] block: BlockHeader,
+        #[case] expected: Result<BlockOrdering, BlockOrderingError>,
+    ) {
+        let tip = header(10, 1, 12);
+
+        assert_eq!(policy.check(Some(&tip), &block), expected);
+        assert_eq!(policy.check(None, &block), Ok(BlockOrdering::Advance));
+    }
+}
@@ -0,0 +1,102 @@
+//! Block times of the supported chains.
+//!
+//! Converts between block counts and wall-clock time, e.g. to tell how long until a lock that
+//! expires in 100 blocks opens. All chains below produce blocks at a fixed interval set by the
+//! protocol, and their averages only exceed it when blocks are missed:
+//!
+//! - Ethereum: 12 second slots since the Merge (block 15,537,394). Missed slots push the average
+//!   about 1% higher.
+//! - Base and Unichain: 2 second blocks, the default of the OP Stack.
+//! - Arbitrum One: the Nitro sequencer produces a block every 250 ms while there are transactions.
+//!
+//! For other chains, or to measure the average of a specific block range, use
+//! [`ChainBlockTime::from_history`].
+use std::time::Duration;
+
+use tycho_common::models::Chain;
+
+pub struct ChainBlockTime;
+
+impl ChainBlockTime {
+    /// Average time between two blocks of `chain` in seconds. Chains without a known block time
+    /// default to Ethereum's.
+    pub fn average_seconds(chain: Chain) -> f64 {
+        match chain {
+            Chain::Ethereum => 12.0,
+            Chain::Base | Chain::Unichain => 2.0,
+            Chain::Arbitrum => 0.25,
+            _ => 12.0,
+        }
+    }
+
+    /// Expected time until `blocks` more blocks are produced on `chain`.
+    pub fn blocks_to_duration(chain: Chain, blocks: u64) -> Duration {
+        Duration::from_secs_f64(Self::average_seconds(chain) * blocks as f64)
+    }
+
+    /// Number of blocks `chain` is expected to produce within `duration`, rounded down.
+    pub fn duration_to_blocks(chain: Chain, duration: Duration) -> u64 {
+        (duration.as_secs_f64() / Self::average_seconds(chain)).floor() as u64
+    }
+
+    /// Average block time in seconds of consecutive blocks with the given timestamps, in block
+    /// order. Returns `None` for fewer than two blocks or timestamps that aren't ascending.
+    pub fn from_history(timestamps: &[u64]) -> Option<f64> {
+        let (first, last) = (timestamps.first()?, timestamps.last()?);
+        if timestamps.len() < 2 || last < first {
+            return None;
+        }
+        Some((last - first) as f64 / (timestamps.len() - 1) as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case::ethereum(Chain::Ethereum, 100, Duration::from_secs(1_200))]
+    #[case::base(Chain::Base, 100, Duration::from_secs(200))]
+    #[case::unichain(Chain::Unichain, 100, Duration::from_secs(200))]
+    #[case::arbitrum(Chain::Arbitrum, 100, Duration::from_secs(25))]
+    fn test_blocks_to_duration(
+        #[case] chain: Chain,
+        #[case] blocks: u64,
+        #[case] expected: Duration,
+    ) {
+        assert_eq!(ChainBlockTime::blocks_to_duration(chain, blocks), expected);
+    }
+
+    #[rstest]
+    #[case::ethereum(Chain::Ethereum, 5)]
+    #[case::base(Chain::Base, 30)]
+    #[case::unichain(Chain::Unichain, 30)]
+    #[case::arbitrum(Chain::Arbitrum, 240)]
+    fn test_duration_to_blocks(#[case] chain: Chain, #[case] expected: u64) {
+        assert_eq!(ChainBlockTime::duration_to_blocks(chain, Duration::from_secs(60)), expected);
+    }
+
+    #[test]
+    fn test_duration_to_blocks_rounds_down() {
+        let blocks = ChainBlockTime::duration_to_blocks(Chain::Ethereum, Duration::from_secs(23));
+
+        assert_eq!(blocks, 1);
+    }
+
+    #[test]
+    fn test_from_history() {
+        let timestamps = [1_000, 1_012, 1_024, 1_048, 1_060];
+
+        assert_eq!(ChainBlockTime::from_history(&timestamps), Some(15.0));
+    }
+
+    #[rstest]
+    #[case::empty(&[])]
+    #[case::single_block(&[1_000])]
+    #[case::descending(&[1_012, 1_000])]
+    fn test_from_history_invalid(#[case] timestamps: &[u64]) {
+        assert_eq!(ChainBlockTime::from_history(timestamps), None);
+    }
+}
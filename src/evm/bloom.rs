@@ -0,0 +1,316 @@
+//! Bloom filters over contract addresses.
+//!
+//! A block touches far more accounts than a consumer tracks. [`AccountFilter`] answers most
+//! "is this account relevant?" checks with a single probe of a [`BloomFilter`], and only the few
+//! accounts that may be tracked proceed to the exact lookup. Removed accounts stay in the bloom
+//! filter until it is rebuilt, which happens once they make up a large part of it, so the false
+//! positive rate stays close to the one the filter was sized for.
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use alloy_primitives::Address;
+
+use super::tycho_models::AccountSet;
+
+/// False positive rate [`AccountFilter`] sizes its bloom filter for.
+pub const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.01;
+/// Number of accounts an empty [`AccountFilter`] is sized for.
+const MIN_CAPACITY: usize = 1_024;
+
+/// A bloom filter over byte strings, e.g. addresses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+    items: usize,
+}
+
+impl BloomFilter {
+    /// Creates a filter holding `capacity` items at a false positive rate of about
+    /// `false_positive_rate`.
+    pub fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let capacity = capacity.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-capacity * false_positive_rate.ln() / (ln2 * ln2)).ceil() as usize;
+        let words = bits.div_ceil(64).max(1);
+        let hashes = ((words * 64) as f64 / capacity * ln2).round() as u32;
+        Self { bits: vec![0; words], hashes: hashes.clamp(1, 16), items: 0 }
+    }
+
+    pub fn insert(&mut self, item: &[u8]) {
+        let len = self.len_bits();
+        for bit in probes(item, self.hashes, len) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+        self.items += 1;
+    }
+
+    /// Whether `item` may have been inserted. `false` is definite, `true` may be a false
+    /// positive.
+    pub fn might_contain(&self, item: &[u8]) -> bool {
+        probes(item, self.hashes, self.len_bits())
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Number of insertions, counting repeated items repeatedly.
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+
+    /// The false positive rate expected at the current number of insertions.
+    pub fn expected_false_positive_rate(&self) -> f64 {
+        let len = self.len_bits() as f64;
+        let hashes = f64::from(self.hashes);
+        (1.0 - (-hashes * self.items as f64 / len).exp()).powf(hashes)
+    }
+
+    fn len_bits(&self) -> usize {
+        self.bits.len() * 64
+    }
+}
+
+/// The bits of `item` in a filter of `len` bits, by double hashing.
+fn probes(item: &[u8], hashes: u32, len: usize) -> impl Iterator<Item = usize> {
+    let h1 = item
+        .chunks(8)
+        .fold(0x9e37_79b9_7f4a_7c15, |hash, chunk| {
+            let mut word = [0u8; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            mix(hash ^ u64::from_le_bytes(word))
+        });
+    let h2 = mix(h1) | 1;
+    (0..u64::from(hashes)).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len as u64) as usize)
+}
+
+/// The finalizer of SplitMix64, spreading every input bit over the whole output.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Counters of the probes of an [`AccountFilter`].
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AccountFilterStats {
+    pub probes: u64,
+    /// Probes ruled out by the bloom filter alone.
+    pub bloom_rejections: u64,
+    /// Probes the bloom filter let through for accounts that aren't tracked.
+    pub false_positives: u64,
+    pub accounts: usize,
+    /// Number of times the bloom filter was rebuilt.
+    pub compactions: u64,
+}
+
+impl AccountFilterStats {
+    /// The measured false positive rate: the share of untracked accounts the bloom filter let
+    /// through, 0 before any untracked account was probed.
+    pub fn false_positive_rate(&self) -> f64 {
+        let untracked = self.bloom_rejections + self.false_positives;
+        if untracked == 0 {
+            return 0.0;
+        }
+        self.false_positives as f64 / untracked as f64
+    }
+}
+
+/// The set of tracked accounts, with a bloom filter in front of the exact lookup.
+///
+/// Inserting accounts beyond the capacity of the bloom filter, or removing a quarter of the
+/// accounts it holds, rebuilds it from the tracked accounts.
+#[derive(Debug)]
+pub struct AccountFilter {
+    bloom: BloomFilter,
+    accounts: HashSet<Address>,
+    capacity: usize,
+    false_positive_rate: f64,
+    probes: AtomicU64,
+    bloom_rejections: AtomicU64,
+    false_positives: AtomicU64,
+    compactions: u64,
+}
+
+impl Default for AccountFilter {
+    fn default() -> Self {
+        Self::new(DEFAULT_FALSE_POSITIVE_RATE)
+    }
+}
+
+impl AccountFilter {
+    /// Creates an empty filter whose bloom filter keeps false positives at about
+    /// `false_positive_rate`.
+    pub fn new(false_positive_rate: f64) -> Self {
+        Self {
+            bloom: BloomFilter::new(MIN_CAPACITY, false_positive_rate),
+            accounts: HashSet::new(),
+            capacity: MIN_CAPACITY,
+            false_positive_rate,
+            probes: AtomicU64::new(0),
+            bloom_rejections: AtomicU64::new(0),
+            false_positives: AtomicU64::new(0),
+            compactions: 0,
+        }
+    }
+
+    pub fn insert(&mut self, account: Address) {
+        if !self.accounts.insert(account) {
+            return;
+        }
+        if self.bloom.len() >= self.capacity {
+            self.compact();
+        } else {
+            self.bloom.insert(account.as_slice());
+        }
+    }
+
+    pub fn remove(&mut self, account: &Address) {
+        if self.accounts.remove(account) && self.stale() * 4 > self.bloom.len() {
+            self.compact();
+        }
+    }
+
+    /// Whether `account` is tracked, probing the bloom filter before the exact lookup.
+    pub fn contains(&self, account: &Address) -> bool {
+        self.probes
+            .fetch_add(1, Ordering::Relaxed);
+        if !self
+            .bloom
+            .might_contain(account.as_slice())
+        {
+            self.bloom_rejections
+                .fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        let tracked = self.accounts.contains(account);
+        if !tracked {
+            self.false_positives
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        tracked
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    pub fn stats(&self) -> AccountFilterStats {
+        AccountFilterStats {
+            probes: self.probes.load(Ordering::Relaxed),
+            bloom_rejections: self
+                .bloom_rejections
+                .load(Ordering::Relaxed),
+            false_positives: self
+                .false_positives
+                .load(Ordering::Relaxed),
+            accounts: self.accounts.len(),
+            compactions: self.compactions,
+        }
+    }
+
+    /// Number of removed accounts still set in the bloom filter.
+    fn stale(&self) -> usize {
+        self.bloom.len() - self.accounts.len()
+    }
+
+    /// Rebuilds the bloom filter from the tracked accounts, with room to grow to twice as many.
+    fn compact(&mut self) {
+        self.capacity = (self.accounts.len() * 2).max(MIN_CAPACITY);
+        self.bloom = BloomFilter::new(self.capacity, self.false_positive_rate);
+        for account in &self.accounts {
+            self.bloom.insert(account.as_slice());
+        }
+        self.compactions += 1;
+    }
+}
+
+impl AccountSet for AccountFilter {
+    fn contains_account(&self, address: &Address) -> bool {
+        self.contains(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn address(i: u64) -> Address {
+        Address::left_padding_from(&i.to_be_bytes())
+    }
+
+    #[rstest]
+    #[case::sequential(false)]
+    #[case::random(true)]
+    fn test_no_false_negatives(#[case] random: bool) {
+        let mut filter = AccountFilter::default();
+        let mut tracked = HashSet::new();
+        for i in 0..20_000u64 {
+            let account = if random { Address::random() } else { address(i) };
+            filter.insert(account);
+            tracked.insert(account);
+            // drop every third account again, compacting the bloom filter along the way
+            if i % 3 == 0 {
+                filter.remove(&account);
+                tracked.remove(&account);
+            }
+        }
+
+        for account in &tracked {
+            assert!(filter.contains(account), "{account} is tracked");
+        }
+        assert_eq!(filter.len(), tracked.len());
+        assert!(filter.stats().compactions > 0);
+    }
+
+    #[test]
+    fn test_false_positive_rate() {
+        let mut filter = AccountFilter::default();
+        for i in 0..10_000 {
+            filter.insert(address(i));
+        }
+
+        let untracked = (10_000..110_000)
+            .filter(|i| filter.contains(&address(*i)))
+            .count();
+
+        let stats = filter.stats();
+        assert_eq!(untracked, 0);
+        assert_eq!(stats.probes, 100_000);
+        assert!(stats.false_positive_rate() < 2.0 * DEFAULT_FALSE_POSITIVE_RATE, "{stats:?}");
+        assert!(
+            filter
+                .bloom
+                .expected_false_positive_rate() <
+                2.0 * DEFAULT_FALSE_POSITIVE_RATE
+        );
+    }
+
+    #[test]
+    fn test_removal_compacts_bloom_filter() {
+        let mut filter = AccountFilter::default();
+        for i in 0..4_000 {
+            filter.insert(address(i));
+        }
+        let compactions = filter.stats().compactions;
+
+        for i in 0..2_000 {
+            filter.remove(&address(i));
+        }
+
+        assert!(filter.stats().compactions > compactions);
+        assert!(filter.stale() * 4 <= filter.bloom.len());
+        assert!(!filter.contains(&address(0)));
+        assert!(filter.contains(&address(3_999)));
+    }
+}
@@ -0,0 +1,419 @@
+//! Catching up on missed blocks.
+//!
+//! After a disconnect the missed blocks can be replayed one by one, which costs a full
+//! `update` pass per block. For larger gaps it is cheaper to collapse all deltas into their net
+//! effect with [`compact_deltas`] and apply that once. [`catch_up`] picks the strategy from the
+//! size of the gap. [`apply_messages`] applies the messages of a subscription to a database and
+//! catches up on every backlog of changes with it, e.g. the missed blocks a server backfills after
+//! a reconnect.
+use futures::{Stream, StreamExt};
+use tracing::debug;
+
+use crate::evm::{
+    engine_db::tycho_db::PreCachedDB,
+    tycho_models::{AccountUpdate, BlockAccountChanges, SubscriptionEvent},
+};
+
+/// Gaps of at least this many blocks are compacted by default.
+pub const DEFAULT_COMPACTION_THRESHOLD: usize = 16;
+
+/// Most messages taken from a stream at once by [`apply_messages`].
+const MAX_BACKLOG: usize = 10_000;
+
+/// How the deltas of a gap are applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatchUpMode {
+    /// Every block is applied on its own.
+    Replay,
+    /// All blocks are merged and applied as one update.
+    Compact,
+}
+
+/// Decides between replaying and compacting the deltas of a gap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CatchUpPolicy {
+    /// Minimum number of blocks in a gap for it to be compacted.
+    pub compaction_threshold: usize,
+}
+
+impl Default for CatchUpPolicy {
+    fn default() -> Self {
+        Self { compaction_threshold: DEFAULT_COMPACTION_THRESHOLD }
+    }
+}
+
+impl CatchUpPolicy {
+    pub fn new(compaction_threshold: usize) -> Self {
+        Self { compaction_threshold }
+    }
+
+    /// Returns the mode for a gap of `gap_size` blocks.
+    pub fn mode(&self, gap_size: usize) -> CatchUpMode {
+        if gap_size >= self.compaction_threshold {
+            CatchUpMode::Compact
+        } else {
+            CatchUpMode::Replay
+        }
+    }
+}
+
+/// Collapses the deltas of consecutive blocks, given in block order, into a single delta.
+///
+/// The result carries the header of the last block and the net update of every account. Pools
+/// created and removed within the sequence are dropped entirely. Returns an empty delta if
+/// `deltas` is empty.
+pub fn compact_deltas(deltas: Vec<BlockAccountChanges>) -> BlockAccountChanges {
+    let mut deltas = deltas.into_iter();
+    let Some(mut compacted) = deltas.next() else {
+        return BlockAccountChanges::default();
+    };
+    for delta in deltas {
+        compacted.merge(delta);
+    }
+    compacted
+}
+
+/// Applies the deltas of a gap, given in block order, to `db`.
+///
+/// Afterwards `db` is at the last block of the gap, regardless of the mode used.
+///
+/// # Returns
+///
+/// The mode chosen by `policy`.
+pub fn catch_up(
+    db: &PreCachedDB,
+    deltas: Vec<BlockAccountChanges>,
+    policy: &CatchUpPolicy,
+) -> CatchUpMode {
+    let mode = policy.mode(deltas.len());
+    debug!(blocks = deltas.len(), ?mode, "Catching up on missed blocks");
+    match mode {
+        CatchUpMode::Replay => {
+            for delta in deltas {
+                apply(db, delta);
+            }
+        }
+        CatchUpMode::Compact => {
+            if !deltas.is_empty() {
+                apply(db, compact_deltas(deltas));
+            }
+        }
+    }
+    mode
+}
+
+/// Applies the events of `messages`, e.g. the data forwarded by a
+/// [`SubscriptionSession`](super::subscription::SubscriptionSession), to `db` until the stream
+/// ends.
+///
+/// The block changes that are already waiting when the next ones are applied form a gap and are
+/// applied with [`catch_up`], so a backlog of at least `policy.compaction_threshold` blocks is
+/// compacted and smaller ones are replayed. A snapshot is loaded after the changes received
+/// before it.
+///
+/// # Returns
+///
+/// The mode used for each gap, in the order the gaps were applied.
+pub async fn apply_messages<S>(
+    db: &PreCachedDB,
+    messages: &mut S,
+    policy: &CatchUpPolicy,
+) -> Vec<CatchUpMode>
+where
+    S: Stream<Item = SubscriptionEvent> + Unpin,
+{
+    let mut modes = Vec::new();
+    let mut backlogs = messages.ready_chunks(MAX_BACKLOG);
+    while let Some(backlog) = backlogs.next().await {
+        let mut gap = Vec::new();
+        for msg in backlog {
+            match msg {
+                SubscriptionEvent::Changes(changes) => gap.push(changes),
+                SubscriptionEvent::Snapshot(snapshot) => {
+                    if !gap.is_empty() {
+                        modes.push(catch_up(db, std::mem::take(&mut gap), policy));
+                    }
+                    db.update(
+                        snapshot
+                            .accounts
+                            .into_iter()
+                            .map(AccountUpdate::from)
+                            .collect(),
+                        Some(snapshot.block.into()),
+                    );
+                }
+            }
+        }
+        if !gap.is_empty() {
+            modes.push(catch_up(db, gap, policy));
+        }
+    }
+    modes
+}
+
+fn apply(db: &PreCachedDB, delta: BlockAccountChanges) {
+    db.update(
+        delta
+            .account_updates
+            .into_values()
+            .collect(),
+        Some(delta.block.into()),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use alloy_primitives::{Address, U256};
+    use rstest::rstest;
+
+    use super::*;
+    use crate::evm::tycho_models::{AccountUpdate, Block, Chain, ChangeType, SwapPool};
+
+    const GAP: u64 = 500;
+    const ROUTER: Address = Address::repeat_byte(0xff);
+
+    fn pool_address(k: u64) -> Address {
+        Address::left_padding_from(&k.to_be_bytes())
+    }
+
+    fn update(address: Address, slots: &[(u64, u64)], change: ChangeType) -> AccountUpdate {
+        AccountUpdate::new(
+            address,
+            Chain::Ethereum,
+            slots
+                .iter()
+                .map(|(k, v)| (U256::from(*k), U256::from(*v)))
+                .collect(),
+            Some(U256::from(slots.len())),
+            (change == ChangeType::Creation).then(Vec::new),
+            change,
+        )
+    }
+
+    /// A gap in which the router is updated every block and a pool is created every 25 blocks.
+    /// Every other pool is removed again 10 blocks after its creation, the others receive updates.
+    fn synthetic_gap() -> Vec<BlockAccountChanges> {
+        (1..=GAP)
+            .map(|number| {
+                let mut updates = vec![update(ROUTER, &[(number % 7, number)], ChangeType::Update)];
+                let mut new_pools = HashMap::new();
+                if number % 25 == 0 {
+                    let pool = pool_address(number / 25);
+                    updates.push(update(pool, &[(0, number), (1, 1)], ChangeType::Creation));
+                    new_pools.insert(pool, SwapPool {});
+                }
+                let since_creation = number % 25;
+                let k = number / 25;
+                if k > 0 && since_creation == 10 {
+                    let change = if k % 2 == 1 { ChangeType::Deletion } else { ChangeType::Update };
+                    updates.push(update(pool_address(k), &[(1, number)], change));
+                }
+                BlockAccountChanges::new(
+                    "vm:test".to_string(),
+                    Chain::Ethereum,
+                    Block { number, ..Default::default() },
+                    updates
+                        .into_iter()
+                        .map(|u| (u.address, u))
+                        .collect(),
+                    new_pools,
+                )
+            })
+            .collect()
+    }
+
+    fn seeded_db() -> PreCachedDB {
+        let db = PreCachedDB::new().unwrap();
+        db.update(
+            vec![update(ROUTER, &[(0, 0)], ChangeType::Creation)],
+            Some(Block::default().into()),
+        );
+        db
+    }
+
+    #[test]
+    fn test_compacted_state_equals_replay() {
+        let replayed = seeded_db();
+        let compacted = seeded_db();
+
+        assert_eq!(
+            catch_up(&replayed, synthetic_gap(), &CatchUpPolicy::new(usize::MAX)),
+            CatchUpMode::Replay
+        );
+        assert_eq!(
+            catch_up(&compacted, synthetic_gap(), &CatchUpPolicy::new(1)),
+            CatchUpMode::Compact
+        );
+
+        let replayed = replayed.get_account_storage();
+        let compacted = compacted.get_account_storage();
+        assert_eq!(replayed.accounts().count(), compacted.accounts().count());
+        for (address, account) in replayed.accounts() {
+            assert!(compacted.account_present(address), "{address} missing");
+            assert_eq!(compacted.get_account_info(address), Some(&account.info));
+            for (slot, value) in &account.permanent_storage {
+                assert_eq!(compacted.get_storage(address, slot), Some(*value));
+            }
+        }
+        assert_eq!(replayed.accounts().count(), 1 + (GAP / 25 / 2) as usize);
+    }
+
+    #[test]
+    fn test_created_and_removed_pool_nets_out() {
+        let compacted = compact_deltas(synthetic_gap());
+
+        assert_eq!(compacted.block.number, GAP);
+        for k in 1..=GAP / 25 {
+            let created_and_removed = k % 2 == 1 && k * 25 + 10 <= GAP;
+            assert_eq!(
+                compacted
+                    .account_updates
+                    .contains_key(&pool_address(k)),
+                !created_and_removed
+            );
+            assert_eq!(
+                compacted
+                    .new_pools
+                    .contains_key(&pool_address(k)),
+                !created_and_removed
+            );
+        }
+        let router = &compacted.account_updates[&ROUTER];
+        assert_eq!(router.change, ChangeType::Update);
+        assert_eq!(router.slots[&U256::from(GAP % 7)], U256::from(GAP));
+    }
+
+    #[test]
+    fn test_recreated_account_stays_deleted() {
+        // the router exists before the gap, so deleting it after re-creating it doesn't cancel
+        // out
+        let gap: Vec<_> = [ChangeType::Deletion, ChangeType::Creation, ChangeType::Deletion]
+            .into_iter()
+            .zip(1..)
+            .map(|(change, number)| {
+                BlockAccountChanges::new(
+                    "vm:test".to_string(),
+                    Chain::Ethereum,
+                    Block { number, ..Default::default() },
+                    HashMap::from([(ROUTER, update(ROUTER, &[(0, number)], change))]),
+                    HashMap::new(),
+                )
+            })
+            .collect();
+        let replayed = seeded_db();
+        let compacted = seeded_db();
+
+        catch_up(&replayed, gap.clone(), &CatchUpPolicy::new(usize::MAX));
+        catch_up(&compacted, gap.clone(), &CatchUpPolicy::new(1));
+
+        assert_eq!(compact_deltas(gap).account_updates[&ROUTER].change, ChangeType::Deletion);
+        assert!(!replayed
+            .get_account_storage()
+            .account_present(&ROUTER));
+        assert!(!compacted
+            .get_account_storage()
+            .account_present(&ROUTER));
+    }
+
+    #[rstest]
+    #[case::update_then_update(ChangeType::Update, ChangeType::Update, Some(ChangeType::Update))]
+    #[case::create_then_update(
+        ChangeType::Creation,
+        ChangeType::Update,
+        Some(ChangeType::Creation)
+    )]
+    #[case::create_then_delete(ChangeType::Creation, ChangeType::Deletion, None)]
+    #[case::update_then_delete(
+        ChangeType::Update,
+        ChangeType::Deletion,
+        Some(ChangeType::Deletion)
+    )]
+    #[case::delete_then_create(
+        ChangeType::Deletion,
+        ChangeType::Creation,
+        Some(ChangeType::Creation)
+    )]
+    #[case::delete_then_update(
+        ChangeType::Deletion,
+        ChangeType::Update,
+        Some(ChangeType::Deletion)
+    )]
+    fn test_account_update_merge(
+        #[case] older: ChangeType,
+        #[case] newer: ChangeType,
+        #[case] expected: Option<ChangeType>,
+    ) {
+        let merged =
+            update(ROUTER, &[(0, 1), (1, 1)], older).merge(update(ROUTER, &[(1, 2)], newer));
+
+        assert_eq!(
+            merged
+                .as_ref()
+                .map(|update| update.change),
+            expected
+        );
+        if expected == Some(ChangeType::Update) {
+            let slots = merged.unwrap().slots;
+            assert_eq!(slots[&U256::from(0)], U256::from(1));
+            assert_eq!(slots[&U256::from(1)], U256::from(2));
+        }
+    }
+
+    #[test]
+    fn test_account_update_merge_deleted_slots() {
+        let older = update(ROUTER, &[(0, 1), (1, 1)], ChangeType::Update)
+            .with_deleted_slots(vec![U256::from(2)]);
+        let newer =
+            update(ROUTER, &[(2, 3)], ChangeType::Update).with_deleted_slots(vec![U256::from(0)]);
+
+        let merged = older.merge(newer).unwrap();
+
+        assert_eq!(
+            merged.slots,
+            HashMap::from([(U256::from(1), U256::from(1)), (U256::from(2), U256::from(3))])
+        );
+        assert_eq!(merged.deleted_slots, vec![U256::from(0)]);
+    }
+
+    #[rstest]
+    #[case::replay(usize::MAX, CatchUpMode::Replay)]
+    #[case::compact(1, CatchUpMode::Compact)]
+    #[tokio::test]
+    async fn test_apply_messages_catches_up_on_backlog(
+        #[case] threshold: usize,
+        #[case] mode: CatchUpMode,
+    ) {
+        let replayed = seeded_db();
+        for delta in synthetic_gap() {
+            apply(&replayed, delta);
+        }
+        let db = seeded_db();
+        let (tx, mut rx) = futures::channel::mpsc::unbounded();
+        // the whole gap is waiting before anything is applied, like after a reconnect
+        for delta in synthetic_gap() {
+            tx.unbounded_send(SubscriptionEvent::Changes(delta))
+                .unwrap();
+        }
+        drop(tx);
+
+        let modes = apply_messages(&db, &mut rx, &CatchUpPolicy::new(threshold)).await;
+
+        assert_eq!(modes, vec![mode]);
+        assert_eq!(db.block_number(), Some(GAP));
+        let (replayed, db) = (replayed.get_account_storage(), db.get_account_storage());
+        assert_eq!(db.accounts().count(), replayed.accounts().count());
+        for (address, account) in replayed.accounts() {
+            assert_eq!(db.get_account_info(address), Some(&account.info));
+        }
+    }
+
+    #[test]
+    fn test_policy_mode() {
+        let policy = CatchUpPolicy::default();
+
+        assert_eq!(policy.mode(DEFAULT_COMPACTION_THRESHOLD - 1), CatchUpMode::Replay);
+        assert_eq!(policy.mode(DEFAULT_COMPACTION_THRESHOLD), CatchUpMode::Compact);
+    }
+}
@@ -0,0 +1,164 @@
+//! Time sources for time-dependent computations.
+//!
+//! Comparing block timestamps against the host's wall clock breaks on hosts with a skewed clock:
+//! a healthy stream looks stale and time-weighted computations over- or under-shoot.
+//! [`BlockAnchoredClock`] avoids this by deriving "now" from the latest observed block timestamp
+//! plus the monotonic time elapsed since that block was received.
+use std::{
+    fmt::Debug,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// A source of the current time as a unix timestamp in seconds.
+pub trait ClockSource: Debug + Send + Sync {
+    fn now(&self) -> u64;
+
+    /// Time passed since the given unix timestamp. Zero if the timestamp lies in the future.
+    fn elapsed_since(&self, timestamp: u64) -> Duration {
+        Duration::from_secs(self.now().saturating_sub(timestamp))
+    }
+}
+
+/// The host's wall clock.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl ClockSource for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs()
+    }
+}
+
+/// A clock standing still at a fixed unix timestamp, e.g. to quote time-dependent pools
+/// deterministically.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FixedClock(pub u64);
+
+impl ClockSource for FixedClock {
+    fn now(&self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Anchor {
+    block_timestamp: u64,
+    received_at: Instant,
+    skew: i64,
+}
+
+/// Estimates the current time from the latest block timestamp and the monotonic time elapsed
+/// since that block was received, which makes it immune to wall clock skew.
+///
+/// Falls back to the wall clock until the first block has been observed.
+#[derive(Debug)]
+pub struct BlockAnchoredClock<C: ClockSource = SystemClock> {
+    wall_clock: C,
+    anchor: RwLock<Option<Anchor>>,
+}
+
+impl Default for BlockAnchoredClock<SystemClock> {
+    fn default() -> Self {
+        Self::new(SystemClock)
+    }
+}
+
+impl<C: ClockSource> BlockAnchoredClock<C> {
+    /// Creates a new clock. `wall_clock` is only used to measure skew and as a fallback before
+    /// the first block is observed.
+    pub fn new(wall_clock: C) -> Self {
+        Self { wall_clock, anchor: RwLock::new(None) }
+    }
+
+    /// Anchors the clock to a newly received block.
+    ///
+    /// Blocks older than the current anchor are ignored, so reverts or late messages never move
+    /// the clock backwards.
+    pub fn observe_block(&self, block_timestamp: u64) {
+        let mut anchor = self.anchor.write().unwrap();
+        if anchor.is_some_and(|a| a.block_timestamp > block_timestamp) {
+            return;
+        }
+        let skew = self.wall_clock.now() as i64 - block_timestamp as i64;
+        *anchor = Some(Anchor { block_timestamp, received_at: Instant::now(), skew });
+    }
+
+    /// Wall clock time minus block time at receipt of the latest block, in seconds.
+    ///
+    /// Includes block propagation delay, so values of a few seconds are expected. Large values
+    /// indicate a host with a skewed clock.
+    pub fn skew(&self) -> Option<i64> {
+        self.anchor
+            .read()
+            .unwrap()
+            .map(|a| a.skew)
+    }
+}
+
+impl<C: ClockSource> ClockSource for BlockAnchoredClock<C> {
+    fn now(&self) -> u64 {
+        match *self.anchor.read().unwrap() {
+            Some(anchor) => anchor.block_timestamp + anchor.received_at.elapsed().as_secs(),
+            None => self.wall_clock.now(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK_TIMESTAMP: u64 = 1_700_000_000;
+    const SKEW: u64 = 300;
+
+    /// A wall clock running 5 minutes ahead of the chain.
+    #[derive(Debug)]
+    struct SkewedClock;
+
+    impl ClockSource for SkewedClock {
+        fn now(&self) -> u64 {
+            BLOCK_TIMESTAMP + SKEW
+        }
+    }
+
+    #[test]
+    fn test_skewed_wall_clock_reports_stale_block() {
+        let max_age = Duration::from_secs(60);
+
+        assert!(SkewedClock.elapsed_since(BLOCK_TIMESTAMP) > max_age);
+    }
+
+    #[test]
+    fn test_block_anchored_clock_ignores_skew() {
+        let max_age = Duration::from_secs(60);
+        let clock = BlockAnchoredClock::new(SkewedClock);
+
+        clock.observe_block(BLOCK_TIMESTAMP);
+
+        assert!(clock.elapsed_since(BLOCK_TIMESTAMP) <= max_age);
+        assert!(clock.now() - BLOCK_TIMESTAMP < 5);
+        assert_eq!(clock.skew(), Some(SKEW as i64));
+    }
+
+    #[test]
+    fn test_block_anchored_clock_falls_back_to_wall_clock() {
+        let clock = BlockAnchoredClock::new(SkewedClock);
+
+        assert_eq!(clock.now(), BLOCK_TIMESTAMP + SKEW);
+        assert_eq!(clock.skew(), None);
+    }
+
+    #[test]
+    fn test_block_anchored_clock_never_moves_backwards() {
+        let clock = BlockAnchoredClock::new(SkewedClock);
+
+        clock.observe_block(BLOCK_TIMESTAMP);
+        clock.observe_block(BLOCK_TIMESTAMP - 12);
+
+        assert!(clock.now() >= BLOCK_TIMESTAMP);
+    }
+}
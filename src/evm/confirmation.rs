@@ -0,0 +1,192 @@
+//! Reorg-safe buffering of block changes.
+//!
+//! Protocols that require finality should only act on blocks that are buried under a number of
+//! newer blocks. [`ConfirmationBuffer`] holds back the most recent `depth` blocks and releases a
+//! block once enough descendants have been observed. Reorgs within the buffered range are
+//! resolved by discarding the orphaned blocks.
+use std::collections::VecDeque;
+
+use alloy_primitives::B256;
+use tracing::{debug, warn};
+
+use crate::evm::tycho_models::{Block, BlockAccountChanges};
+
+/// A block that is at least `depth` blocks deep and considered final.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfirmedBlock {
+    pub changes: BlockAccountChanges,
+}
+
+impl ConfirmedBlock {
+    pub fn block(&self) -> &Block {
+        &self.changes.block
+    }
+}
+
+/// Buffers the last `depth` blocks of changes until they are confirmed.
+#[derive(Debug)]
+pub struct ConfirmationBuffer {
+    depth: usize,
+    pending: VecDeque<BlockAccountChanges>,
+    confirmed: VecDeque<ConfirmedBlock>,
+}
+
+impl ConfirmationBuffer {
+    /// Creates a new buffer holding back the last `depth` blocks.
+    pub fn new(depth: usize) -> Self {
+        Self { depth, pending: VecDeque::with_capacity(depth + 1), confirmed: VecDeque::new() }
+    }
+
+    /// Adds the changes of a new block.
+    ///
+    /// If the block does not extend the current tip, buffered blocks are rolled back to the last
+    /// common ancestor: every pending block at or above the new block's number is discarded, as
+    /// is every block that is not the new block's parent. Once more than `depth` blocks are
+    /// pending, the oldest is confirmed.
+    ///
+    /// # Returns
+    ///
+    /// The orphaned blocks that were rolled back, newest first. Their changes must be reverted
+    /// and the new block re-processed on top of the common ancestor.
+    pub fn push(&mut self, changes: BlockAccountChanges) -> Vec<BlockAccountChanges> {
+        let new_block = changes.block;
+        let mut orphaned = Vec::new();
+        while let Some(tip) = self.pending.back() {
+            let is_ancestor = tip.block.number < new_block.number &&
+                (new_block.parent_hash == B256::ZERO ||
+                    tip.block.number + 1 < new_block.number ||
+                    tip.block.hash == new_block.parent_hash);
+            if is_ancestor {
+                break;
+            }
+            orphaned.extend(self.pending.pop_back());
+        }
+        if !orphaned.is_empty() {
+            debug!(
+                n_orphaned = orphaned.len(),
+                new_block = new_block.number,
+                "Reorg detected, rolled back buffered blocks"
+            );
+            if self.pending.is_empty() &&
+                self.confirmed_tip()
+                    .is_some_and(|confirmed| confirmed.number >= new_block.number)
+            {
+                warn!(new_block = new_block.number, "Reorg deeper than confirmation depth");
+            }
+        }
+
+        self.pending.push_back(changes);
+        while self.pending.len() > self.depth {
+            if let Some(changes) = self.pending.pop_front() {
+                self.confirmed
+                    .push_back(ConfirmedBlock { changes });
+            }
+        }
+        orphaned
+    }
+
+    /// Returns the oldest confirmed block that has not been taken yet.
+    pub fn take_confirmed(&mut self) -> Option<ConfirmedBlock> {
+        self.confirmed.pop_front()
+    }
+
+    /// The blocks that are not confirmed yet, oldest first.
+    pub fn pending(&self) -> impl Iterator<Item = &Block> {
+        self.pending.iter().map(|c| &c.block)
+    }
+
+    fn confirmed_tip(&self) -> Option<&Block> {
+        self.confirmed.back().map(|c| c.block())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(number: u64, fork: u8, parent_fork: u8) -> BlockAccountChanges {
+        BlockAccountChanges {
+            block: Block {
+                number,
+                hash: B256::with_last_byte(number as u8).with_first_byte(fork),
+                parent_hash: B256::with_last_byte(number as u8 - 1).with_first_byte(parent_fork),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    trait WithFirstByte {
+        fn with_first_byte(self, byte: u8) -> Self;
+    }
+
+    impl WithFirstByte for B256 {
+        fn with_first_byte(mut self, byte: u8) -> Self {
+            self.0[0] = byte;
+            self
+        }
+    }
+
+    fn drain(buffer: &mut ConfirmationBuffer) -> Vec<(u64, u8)> {
+        std::iter::from_fn(|| buffer.take_confirmed())
+            .map(|c| (c.block().number, c.block().hash.0[0]))
+            .collect()
+    }
+
+    #[test]
+    fn test_confirms_after_depth() {
+        let mut buffer = ConfirmationBuffer::new(3);
+
+        for number in 1..=3 {
+            buffer.push(block(number, 0, 0));
+        }
+        assert_eq!(buffer.take_confirmed(), None);
+
+        buffer.push(block(4, 0, 0));
+        assert_eq!(drain(&mut buffer), vec![(1, 0)]);
+    }
+
+    #[test]
+    fn test_two_block_reorg() {
+        let mut buffer = ConfirmationBuffer::new(3);
+        for number in 1..=5 {
+            buffer.push(block(number, 0, 0));
+        }
+
+        // Blocks 4 and 5 are replaced by a fork starting at block 4
+        let orphaned = buffer.push(block(4, 1, 0));
+        assert_eq!(
+            orphaned
+                .iter()
+                .map(|c| c.block.number)
+                .collect::<Vec<_>>(),
+            vec![5, 4]
+        );
+        buffer.push(block(5, 1, 1));
+        buffer.push(block(6, 1, 1));
+        buffer.push(block(7, 1, 1));
+
+        assert_eq!(drain(&mut buffer), vec![(1, 0), (2, 0), (3, 0), (4, 1)]);
+        assert_eq!(
+            buffer
+                .pending()
+                .map(|b| b.number)
+                .collect::<Vec<_>>(),
+            vec![5, 6, 7]
+        );
+    }
+
+    #[test]
+    fn test_reorg_detected_by_parent_hash() {
+        let mut buffer = ConfirmationBuffer::new(3);
+        for number in 1..=3 {
+            buffer.push(block(number, 0, 0));
+        }
+
+        // Block 4 builds on a different block 3
+        let orphaned = buffer.push(block(4, 1, 1));
+
+        assert_eq!(orphaned.len(), 1);
+        assert_eq!(orphaned[0].block.number, 3);
+    }
+}
@@ -1,35 +1,53 @@
 use std::{
     collections::{hash_map::Entry, HashMap, HashSet},
     future::Future,
+    panic::AssertUnwindSafe,
     pin::Pin,
     str::FromStr,
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 
 use alloy_primitives::Address;
+use futures::FutureExt;
 use thiserror::Error;
-use tokio::sync::{RwLock, RwLockReadGuard};
+use tokio::sync::{mpsc::UnboundedSender, RwLock, RwLockReadGuard};
 use tracing::{debug, error, info, warn};
 use tycho_client::feed::{synchronizer::ComponentWithState, FeedMessage, Header};
 use tycho_common::{dto::ProtocolStateDelta, Bytes};
 
 use crate::{
     evm::{
-        engine_db::{update_engine, SHARED_TYCHO_DB},
+        backend_override::QuoteBackendOverride,
+        block_ordering::{BlockOrdering, BlockOrderingError, BlockOrderingPolicy},
+        bloom::{AccountFilter, AccountFilterStats},
+        clock::ClockSource,
+        engine_db::{
+            simulation_db::BlockHeader, tycho_db::AccountsCheckpoint, update_engine,
+            SHARED_TYCHO_DB,
+        },
         tycho_models::{AccountUpdate, ResponseAccount},
+        warmup::{WarmupEvent, WarmupPriority, WarmupTracker},
     },
     models::{Balances, Token},
     protocol::{
-        errors::InvalidSnapshotError,
-        models::{BlockUpdate, ProtocolComponent, TryFromWithBlock},
+        errors::{error_chain, guard_panic, panic_message, InvalidSnapshotError, SimulationError},
+        models::{Backend, BlockUpdate, ComponentConflict, ProtocolComponent, TryFromWithBlock},
         state::ProtocolSim,
     },
+    token_registry::{TokenRegistry, DEFAULT_TOKEN_QUALITY},
 };
 
+/// Priority of native extractors, e.g. `uniswap_v3`, unless configured otherwise.
+pub const NATIVE_EXTRACTOR_PRIORITY: u32 = 1;
+/// Priority of VM extractors, e.g. `vm:ambient`, unless configured otherwise.
+pub const VM_EXTRACTOR_PRIORITY: u32 = 0;
+
 #[derive(Error, Debug)]
 pub enum StreamDecodeError {
     #[error("{0}")]
     Fatal(String),
+    #[error(transparent)]
+    BlockOrdering(#[from] BlockOrderingError),
 }
 
 #[derive(Default)]
@@ -38,6 +56,80 @@ struct DecoderState {
     states: HashMap<String, Box<dyn ProtocolSim>>,
     // maps contract address to the pools they affect
     contracts_map: HashMap<Bytes, HashSet<String>>,
+    // the contracts of `contracts_map`, to rule out unrelated account updates cheaply
+    contract_filter: AccountFilter,
+    // latest snapshots of the pools that can be re-decoded into another backend
+    snapshots: HashMap<String, StoredSnapshot>,
+    // the last decoded block
+    tip: Option<BlockHeader>,
+    // what the tip changed, to roll it back if it is replaced
+    tip_journal: TipJournal,
+}
+
+/// The state from before the tip, to roll the tip back if it is replaced.
+#[derive(Default)]
+struct TipJournal {
+    // the block before the tip
+    parent: Option<BlockHeader>,
+    // the states of the pools changed by the tip, from before it
+    states: HashMap<String, Box<dyn ProtocolSim>>,
+    // the contract accounts changed by the tip, from before it
+    vm_accounts: Option<AccountsCheckpoint>,
+}
+
+impl DecoderState {
+    /// Restores the states and contract accounts changed by the tip and the block before it,
+    /// returning the restored states. Pools added by the tip keep their state.
+    fn revert_tip(&mut self) -> HashMap<String, Box<dyn ProtocolSim>> {
+        let TipJournal { parent, states, vm_accounts } = std::mem::take(&mut self.tip_journal);
+        info!(tip = ?self.tip.map(|tip| tip.number), n = states.len(), "RevertingReplacedBlock");
+        for (id, state) in &states {
+            self.states
+                .insert(id.clone(), state.clone_box());
+        }
+        if let Some(checkpoint) = vm_accounts {
+            SHARED_TYCHO_DB.restore(checkpoint);
+        }
+        self.tip = parent;
+        states
+    }
+
+    /// Maps `contract` to the pool `id`.
+    fn track_contract(&mut self, contract: Bytes, id: String) {
+        if let Ok(address) = Address::try_from(contract.as_ref()) {
+            self.contract_filter.insert(address);
+        }
+        self.contracts_map
+            .entry(contract)
+            .or_default()
+            .insert(id);
+    }
+
+    /// Unmaps the contracts of the removed pool `id`.
+    fn untrack_pool(&mut self, id: &str) {
+        let filter = &mut self.contract_filter;
+        self.contracts_map
+            .retain(|contract, pools| {
+                pools.remove(id);
+                if pools.is_empty() {
+                    if let Ok(address) = Address::try_from(contract.as_ref()) {
+                        filter.remove(&address);
+                    }
+                }
+                !pools.is_empty()
+            });
+    }
+
+    /// Whether `contract` is mapped to any pool. Most other contracts are ruled out by a single
+    /// bloom filter probe.
+    fn is_tracked_contract(&self, contract: &Bytes) -> bool {
+        match Address::try_from(contract.as_ref()) {
+            Ok(address) => self.contract_filter.contains(&address),
+            Err(_) => self
+                .contracts_map
+                .contains_key(contract),
+        }
+    }
 }
 
 type DecodeFut =
@@ -48,6 +140,128 @@ type RegistryFn = dyn Fn(ComponentWithState, Header, AccountBalances, Arc<RwLock
     + Sync;
 type FilterFn = fn(&ComponentWithState) -> bool;
 
+/// The latest snapshot of a pool, with all deltas received since applied.
+#[derive(Clone)]
+struct StoredSnapshot {
+    protocol: String,
+    snapshot: ComponentWithState,
+    // balances of the pool's contracts
+    account_balances: AccountBalances,
+}
+
+impl StoredSnapshot {
+    fn apply(&mut self, delta: Option<&ProtocolStateDelta>, balances: &Balances) {
+        let state = &mut self.snapshot.state;
+        if let Some(delta) = delta {
+            state
+                .attributes
+                .extend(delta.updated_attributes.clone());
+            for attribute in &delta.deleted_attributes {
+                state.attributes.remove(attribute);
+            }
+        }
+        if let Some(component_balances) = balances
+            .component_balances
+            .get(&state.component_id)
+        {
+            state
+                .balances
+                .extend(component_balances.clone());
+        }
+        for contract in &self.snapshot.component.contract_ids {
+            if let Some(contract_balances) = balances.account_balances.get(contract) {
+                self.account_balances
+                    .entry(contract.clone())
+                    .or_default()
+                    .extend(contract_balances.clone());
+            }
+        }
+    }
+}
+
+/// The extractor whose reports of each component are applied in the current block.
+#[derive(Default)]
+struct ComponentClaims {
+    owners: HashMap<String, String>,
+    conflicts: Vec<ComponentConflict>,
+}
+
+impl ComponentClaims {
+    /// Claims `id` for `extractor`. Returns false and records a conflict if another extractor
+    /// claimed it before.
+    fn claim(&mut self, id: &str, extractor: &str) -> bool {
+        match self.owners.entry(id.to_string()) {
+            Entry::Vacant(entry) => {
+                entry.insert(extractor.to_string());
+                true
+            }
+            Entry::Occupied(entry) if entry.get() == extractor => true,
+            Entry::Occupied(entry) => {
+                let conflict = ComponentConflict {
+                    component_id: id.to_string(),
+                    winner: entry.get().clone(),
+                    loser: extractor.to_string(),
+                };
+                debug!(?conflict, "ComponentReportedTwice");
+                if !self.conflicts.contains(&conflict) {
+                    self.conflicts.push(conflict);
+                }
+                false
+            }
+        }
+    }
+
+    fn into_conflicts(mut self) -> Vec<ComponentConflict> {
+        self.conflicts.sort();
+        self.conflicts
+    }
+}
+
+/// The prioritized components of a snapshot that is decoded in two batches.
+#[derive(Debug)]
+pub(super) struct WarmupSplit {
+    prioritized: HashSet<String>,
+    // the claims of the prioritized batch, continued by the remainder
+    owners: Mutex<HashMap<String, String>>,
+}
+
+/// The part of a message processed by one decoding pass.
+#[derive(Debug, Clone)]
+pub(super) enum DecodeBatch {
+    /// The whole message.
+    Full,
+    /// Only the snapshots of the prioritized components, with the tokens and contract storage
+    /// they need.
+    Prioritized(Arc<WarmupSplit>),
+    /// Everything the prioritized batch left out, decoded after it was committed.
+    Remainder(Arc<WarmupSplit>),
+}
+
+impl DecodeBatch {
+    fn includes_snapshot(&self, id: &str) -> bool {
+        match self {
+            DecodeBatch::Full => true,
+            DecodeBatch::Prioritized(split) => split.prioritized.contains(id),
+            DecodeBatch::Remainder(split) => !split.prioritized.contains(id),
+        }
+    }
+
+    /// Whether deltas and removed components are processed.
+    fn includes_changes(&self) -> bool {
+        !matches!(self, DecodeBatch::Prioritized(_))
+    }
+
+    /// Whether the batch continues a batch of the same block that was already committed.
+    fn is_continuation(&self) -> bool {
+        matches!(self, DecodeBatch::Remainder(_))
+    }
+
+    /// Whether the batch completes its message.
+    pub(super) fn is_last(&self) -> bool {
+        !matches!(self, DecodeBatch::Prioritized(_))
+    }
+}
+
 /// A decoder to process raw messages.
 ///
 /// This struct decodes incoming messages of type `FeedMessage` and converts it into the
@@ -64,8 +278,15 @@ pub(super) struct TychoStreamDecoder {
     state: Arc<RwLock<DecoderState>>,
     skip_state_decode_failures: bool,
     min_token_quality: u32,
+    token_registry: Arc<TokenRegistry>,
     registry: HashMap<String, Box<RegistryFn>>,
     inclusion_filters: HashMap<String, FilterFn>,
+    warmup: Option<WarmupTracker>,
+    extractor_priorities: HashMap<String, u32>,
+    vm_fallbacks: HashMap<String, Box<RegistryFn>>,
+    backend_overrides: Option<Arc<QuoteBackendOverride>>,
+    block_ordering: Option<BlockOrderingPolicy>,
+    clock: Option<Arc<dyn ClockSource>>,
 }
 
 impl TychoStreamDecoder {
@@ -74,24 +295,110 @@ impl TychoStreamDecoder {
             state: Arc::new(RwLock::new(DecoderState::default())),
             skip_state_decode_failures: false,
             min_token_quality: 51,
+            token_registry: Arc::new(TokenRegistry::new()),
             registry: HashMap::new(),
             inclusion_filters: HashMap::new(),
+            warmup: None,
+            extractor_priorities: HashMap::new(),
+            vm_fallbacks: HashMap::new(),
+            backend_overrides: None,
+            block_ordering: None,
+            clock: None,
         }
     }
 
+    /// Rejects blocks that don't follow the last decoded block according to `policy`. Without a
+    /// policy, blocks are decoded in the order they are received.
+    ///
+    /// A block replacing the tip is decoded on top of the pool states and the contract storage of
+    /// VM pools from before the tip. Reverts announced by the server are always decoded.
+    pub fn set_block_ordering(&mut self, policy: BlockOrderingPolicy) {
+        self.block_ordering = Some(policy);
+    }
+
+    /// Sets the time source of the decoded states whose quotes depend on the current time, see
+    /// [`ProtocolSim::set_clock`]. Without a clock, such states use the host's wall clock.
+    pub fn set_clock(&mut self, clock: Arc<dyn ClockSource>) {
+        self.clock = Some(clock);
+    }
+
     /// Sets the currently known tokens which will be considered during decoding.
     ///
     /// Protocol components containing tokens which are not included in this initial list, or
     /// added when applying deltas, will not be decoded.
     pub async fn set_tokens(&self, tokens: HashMap<Bytes, Token>) {
+        for token in tokens.values() {
+            self.token_registry
+                .get_or_insert(token.clone(), DEFAULT_TOKEN_QUALITY);
+        }
         let mut guard = self.state.write().await;
         guard.tokens = tokens;
     }
 
+    /// The registry's instance of `token`, so that all components share it.
+    fn shared_token(&self, token: &Token) -> Arc<Token> {
+        self.token_registry
+            .by_address(&token.address)
+            .unwrap_or_else(|| {
+                self.token_registry
+                    .get_or_insert(token.clone(), DEFAULT_TOKEN_QUALITY)
+            })
+    }
+
+    /// The registry holding the shared token instances and their quality.
+    ///
+    /// Quality updates made through the registry apply to all components decoded afterwards.
+    pub fn token_registry(&self) -> Arc<TokenRegistry> {
+        self.token_registry.clone()
+    }
+
+    /// Probe counters of the filter ruling out account updates unrelated to any pool, including
+    /// its measured false positive rate.
+    pub async fn contract_filter_stats(&self) -> AccountFilterStats {
+        self.state
+            .read()
+            .await
+            .contract_filter
+            .stats()
+    }
+
     pub fn skip_state_decode_failures(&mut self, skip: bool) {
         self.skip_state_decode_failures = skip;
     }
 
+    /// Decodes the components of `priority` first when processing the initial snapshot.
+    ///
+    /// The first snapshot containing any of them is decoded in two batches, see
+    /// [`Self::batches`]. Once the prioritized batch is committed, a
+    /// [`WarmupEvent::PartialReady`] is sent to `events`.
+    pub fn set_warmup_priority(
+        &mut self,
+        priority: WarmupPriority,
+        events: UnboundedSender<WarmupEvent>,
+    ) {
+        self.warmup = Some(WarmupTracker::new(priority, events));
+    }
+
+    /// Sets the priority of `extractor` for components reported by several extractors in the same
+    /// block.
+    ///
+    /// Only the reports of the extractor with the highest priority are applied to such a
+    /// component, including its removal. By default native extractors take precedence over VM
+    /// extractors, see [`NATIVE_EXTRACTOR_PRIORITY`] and [`VM_EXTRACTOR_PRIORITY`]. Ties are
+    /// broken by extractor name.
+    pub fn set_extractor_priority(&mut self, extractor: &str, priority: u32) {
+        self.extractor_priorities
+            .insert(extractor.to_string(), priority);
+    }
+
+    fn extractor_priority(&self, extractor: &str) -> u32 {
+        match self.extractor_priorities.get(extractor) {
+            Some(priority) => *priority,
+            None if extractor.starts_with("vm:") => VM_EXTRACTOR_PRIORITY,
+            None => NATIVE_EXTRACTOR_PRIORITY,
+        }
+    }
+
     /// Registers a decoder for a given exchange.
     ///
     /// This method maps an exchange identifier to a specific protocol simulation type.
@@ -110,7 +417,42 @@ impl TychoStreamDecoder {
             + Send
             + 'static,
     {
-        let decoder = Box::new(
+        self.registry
+            .insert(exchange.to_string(), Self::decoder_fn::<T>());
+    }
+
+    /// Registers `T` as the VM implementation of `exchange`.
+    ///
+    /// Pools of `exchange` overridden to [`Backend::Vm`] through the registry set with
+    /// [`Self::set_backend_overrides`] are decoded as `T` instead of the type registered with
+    /// [`Self::register_decoder`]. Only pools with contracts can be overridden.
+    pub fn register_vm_fallback<T>(&mut self, exchange: &str)
+    where
+        T: ProtocolSim
+            + TryFromWithBlock<ComponentWithState, Error = InvalidSnapshotError>
+            + Send
+            + 'static,
+    {
+        self.vm_fallbacks
+            .insert(exchange.to_string(), Self::decoder_fn::<T>());
+    }
+
+    /// Consults `overrides` for the backend of each pool.
+    ///
+    /// Pools whose override changed are re-decoded from their latest snapshot at the end of the
+    /// next decoded block, and included in its `BlockUpdate`.
+    pub fn set_backend_overrides(&mut self, overrides: Arc<QuoteBackendOverride>) {
+        self.backend_overrides = Some(overrides);
+    }
+
+    fn decoder_fn<T>() -> Box<RegistryFn>
+    where
+        T: ProtocolSim
+            + TryFromWithBlock<ComponentWithState, Error = InvalidSnapshotError>
+            + Send
+            + 'static,
+    {
+        Box::new(
             move |component: ComponentWithState,
                   header: Header,
                   account_balances: AccountBalances,
@@ -122,9 +464,30 @@ impl TychoStreamDecoder {
                         .map(|c| Box::new(c) as Box<dyn ProtocolSim>)
                 }) as DecodeFut
             },
-        );
-        self.registry
-            .insert(exchange.to_string(), decoder);
+        )
+    }
+
+    /// The decoder of `protocol` for the backend `pool_id` is overridden to.
+    fn decoder_for(&self, protocol: &str, pool_id: &str) -> Option<&RegistryFn> {
+        let overridden = self
+            .backend_overrides
+            .as_ref()
+            .and_then(|overrides| overrides.backend(pool_id));
+        match overridden {
+            Some(Backend::Vm) => self
+                .vm_fallbacks
+                .get(protocol)
+                .or_else(|| self.registry.get(protocol)),
+            _ => self.registry.get(protocol),
+        }
+        .map(Box::as_ref)
+    }
+
+    /// The override registry, if pools of `protocol` can be switched to the VM.
+    fn switchable(&self, protocol: &str) -> Option<&QuoteBackendOverride> {
+        self.backend_overrides
+            .as_deref()
+            .filter(|_| self.vm_fallbacks.contains_key(protocol))
     }
 
     /// Registers a client-side filter function for a given exchange.
@@ -147,14 +510,66 @@ impl TychoStreamDecoder {
             .insert(exchange.to_string(), predicate);
     }
 
+    /// The batches to decode `msg` in.
+    ///
+    /// The first snapshot containing prioritized components is split into a batch of just those
+    /// components and a batch of the rest, across all protocols. All other messages are decoded
+    /// in one batch.
+    pub(super) fn batches(&self, msg: &FeedMessage) -> Vec<DecodeBatch> {
+        let prioritized = self.warmup.as_ref().and_then(|warmup| {
+            warmup.start(
+                msg.state_msgs
+                    .values()
+                    .flat_map(|protocol_msg| {
+                        protocol_msg
+                            .snapshots
+                            .get_states()
+                            .keys()
+                    }),
+            )
+        });
+        match prioritized {
+            Some(prioritized) => {
+                let split = Arc::new(WarmupSplit { prioritized, owners: Mutex::default() });
+                vec![DecodeBatch::Prioritized(split.clone()), DecodeBatch::Remainder(split)]
+            }
+            None => vec![DecodeBatch::Full],
+        }
+    }
+
     /// Decodes a `FeedMessage` into a `BlockUpdate` containing the updated states of protocol
     /// components
+    ///
+    /// A message split by the warm-up priority is decoded batch by batch and returned as one
+    /// update; the stream yields the batches separately instead.
     pub async fn decode(&self, msg: FeedMessage) -> Result<BlockUpdate, StreamDecodeError> {
+        let mut update: Option<BlockUpdate> = None;
+        for batch in self.batches(&msg) {
+            let next = self.decode_batch(&msg, batch).await?;
+            update = Some(match update {
+                Some(update) => merge_updates(update, next),
+                None => next,
+            });
+        }
+        Ok(update.expect("Every message is decoded in at least one batch"))
+    }
+
+    /// Decodes the part of `msg` selected by `batch` and commits it.
+    pub(super) async fn decode_batch(
+        &self,
+        msg: &FeedMessage,
+        batch: DecodeBatch,
+    ) -> Result<BlockUpdate, StreamDecodeError> {
         // stores all states updated in this tick/msg
         let mut updated_states = HashMap::new();
         let mut new_pairs = HashMap::new();
         let mut removed_pairs = HashMap::new();
         let mut contracts_map = HashMap::new();
+        let mut new_snapshots = HashMap::new();
+        // deltas to apply to the stored snapshots
+        let mut snapshot_deltas = Vec::new();
+        // pools whose implementation panicked while applying a delta
+        let mut quarantined = HashSet::new();
 
         let block = msg
             .state_msgs
@@ -163,8 +578,51 @@ impl TychoStreamDecoder {
             .ok_or_else(|| StreamDecodeError::Fatal("Missing block!".into()))?
             .header
             .clone();
+        let mut header = BlockHeader::from(block.clone());
+        if let Some(clock) = &self.clock {
+            // tycho headers carry no timestamp, the block is stamped on receipt
+            header.timestamp = clock.now();
+        }
+        if let Some(policy) = self
+            .block_ordering
+            .filter(|_| !block.revert && !batch.is_continuation())
+        {
+            let mut state_guard = self.state.write().await;
+            if policy.check(state_guard.tip.as_ref(), &header)? == BlockOrdering::ReplaceTip {
+                updated_states.extend(state_guard.revert_tip());
+            }
+        }
+        // only a tip that may be replaced needs to be rolled back
+        let journal_tip = self.block_ordering == Some(BlockOrderingPolicy::AllowSingleStepReplace);
+        // a continued block keeps recording into the checkpoint of its first batch
+        let mut vm_checkpoint = if batch.is_continuation() {
+            self.state
+                .write()
+                .await
+                .tip_journal
+                .vm_accounts
+                .take()
+        } else {
+            journal_tip.then(|| SHARED_TYCHO_DB.checkpoint())
+        };
+
+        // Extractors are processed by descending priority, so the first to claim a component is
+        // the one whose reports are applied.
+        let mut protocol_msgs: Vec<_> = msg.state_msgs.iter().collect();
+        protocol_msgs.sort_by(|(a, _), (b, _)| {
+            self.extractor_priority(b)
+                .cmp(&self.extractor_priority(a))
+                .then_with(|| a.cmp(b))
+        });
+        let mut claims = match &batch {
+            DecodeBatch::Remainder(split) => ComponentClaims {
+                owners: std::mem::take(&mut *split.owners.lock().unwrap()),
+                conflicts: Vec::new(),
+            },
+            _ => ComponentClaims::default(),
+        };
 
-        for (protocol, protocol_msg) in msg.state_msgs.iter() {
+        for (protocol, protocol_msg) in protocol_msgs {
             // Add any new tokens
             if let Some(deltas) = protocol_msg.deltas.as_ref() {
                 let mut state_guard = self.state.write().await;
@@ -172,16 +630,24 @@ impl TychoStreamDecoder {
                     .new_tokens
                     .iter()
                     .filter_map(|(addr, t)| {
-                        if t.quality < self.min_token_quality ||
+                        let quality = self
+                            .token_registry
+                            .quality(addr)
+                            .unwrap_or(t.quality);
+                        if quality < self.min_token_quality ||
                             // Do not add the token if it's already included in the state_guard
                             state_guard.tokens.contains_key(addr)
                         {
                             return None;
                         }
 
-                        let token = t.clone().try_into();
+                        let token = Token::try_from_unmeasured(t.clone());
                         let result = match token {
-                            Ok(t) => Ok((addr.clone(), t)),
+                            Ok(token) => {
+                                self.token_registry
+                                    .get_or_insert(Token::clone(&token), t.quality);
+                                Ok((addr.clone(), token))
+                            }
                             Err(e) => Err(StreamDecodeError::Fatal(format!(
                                 "Failed decoding token {e} {addr:#044x}"
                             ))),
@@ -202,6 +668,7 @@ impl TychoStreamDecoder {
                 protocol_msg
                     .removed_components
                     .iter()
+                    .filter(|_| batch.includes_changes())
                     .flat_map(|(id, comp)| match Bytes::from_str(id) {
                         Ok(addr) => Some(Ok((id, addr, comp))),
                         Err(e) => {
@@ -214,11 +681,13 @@ impl TychoStreamDecoder {
                     })
                     .collect::<Result<Vec<_>, StreamDecodeError>>()?
                     .into_iter()
+                    .filter(|(id, _, _)| claims.claim(id, protocol))
                     .flat_map(|(id, _, comp)| {
                         let tokens = comp
                             .tokens
                             .iter()
-                            .flat_map(|addr| state_guard.tokens.get(addr).cloned())
+                            .flat_map(|addr| state_guard.tokens.get(addr))
+                            .map(|token| self.shared_token(token))
                             .collect::<Vec<_>>();
 
                         if tokens.len() == comp.tokens.len() {
@@ -236,13 +705,28 @@ impl TychoStreamDecoder {
             );
 
             // UPDATE VM STORAGE
-            let storage_by_address: HashMap<Address, ResponseAccount> = protocol_msg
-                .clone()
-                .snapshots
-                .get_vm_storage()
-                .iter()
-                .map(|(key, value)| (Address::from_slice(&key[..20]), value.clone().into()))
-                .collect();
+            // the storage was applied with the prioritized batch already
+            if !batch.is_continuation() {
+                let storage_by_address: HashMap<Address, ResponseAccount> = protocol_msg
+                    .clone()
+                    .snapshots
+                    .get_vm_storage()
+                    .iter()
+                    .map(|(key, value)| (Address::from_slice(&key[..20]), value.clone().into()))
+                    .collect();
+                info!("Updating engine with {} snapshots", storage_by_address.len());
+                if let Some(checkpoint) = &mut vm_checkpoint {
+                    SHARED_TYCHO_DB.record(checkpoint, storage_by_address.keys());
+                }
+                update_engine(
+                    SHARED_TYCHO_DB.clone(),
+                    header,
+                    Some(storage_by_address),
+                    HashMap::new(),
+                )
+                .await;
+                info!("Engine updated");
+            }
             let account_balances = protocol_msg
                 .clone()
                 .snapshots
@@ -256,15 +740,6 @@ impl TychoStreamDecoder {
                     Some((addr.clone(), balances))
                 })
                 .collect::<AccountBalances>();
-            info!("Updating engine with {} snapshots", storage_by_address.len());
-            update_engine(
-                SHARED_TYCHO_DB.clone(),
-                block.clone().into(),
-                Some(storage_by_address),
-                HashMap::new(),
-            )
-            .await;
-            info!("Engine updated");
 
             let mut new_components = HashMap::new();
 
@@ -274,6 +749,10 @@ impl TychoStreamDecoder {
                 .get_states()
                 .clone()
             {
+                if !batch.includes_snapshot(&id) {
+                    continue;
+                }
+
                 // Skip any unsupported pools
                 if let Some(predicate) = self
                     .inclusion_filters
@@ -288,7 +767,15 @@ impl TychoStreamDecoder {
                 let mut component_tokens = Vec::new();
                 for token in snapshot.component.tokens.clone() {
                     match state_guard.tokens.get(&token) {
-                        Some(token) => component_tokens.push(token.clone()),
+                        Some(_)
+                            if !self
+                                .token_registry
+                                .meets_quality(&token, self.min_token_quality) =>
+                        {
+                            debug!("Token quality too low {}, ignoring pool {:x?}", token, id);
+                            continue 'outer;
+                        }
+                        Some(token) => component_tokens.push(self.shared_token(token)),
                         None => {
                             debug!("Token not found {}, ignoring pool {:x?}", token, id);
                             continue 'outer;
@@ -314,28 +801,69 @@ impl TychoStreamDecoder {
                     }
                 }
 
+                if !claims.claim(&id, protocol) {
+                    continue 'outer;
+                }
                 new_pairs.insert(id.clone(), component);
 
+                // Keep the snapshot to re-decode the pool if its backend is overridden later
+                if let Some(overrides) = self.switchable(protocol) {
+                    let contract_ids = &snapshot.component.contract_ids;
+                    // the VM can only simulate the pool once the storage of all its contracts
+                    // was decoded into the engine
+                    let vm_capable = !contract_ids.is_empty() &&
+                        contract_ids.iter().all(|contract| {
+                            contract.len() >= 20 &&
+                                SHARED_TYCHO_DB
+                                    .contains_account(&Address::from_slice(&contract[..20]))
+                        });
+                    overrides.set_vm_capable(&id, vm_capable);
+                    new_snapshots.insert(
+                        id.clone(),
+                        StoredSnapshot {
+                            protocol: protocol.clone(),
+                            snapshot: snapshot.clone(),
+                            account_balances: account_balances
+                                .iter()
+                                .filter(|(addr, _)| contract_ids.contains(*addr))
+                                .map(|(addr, balances)| (addr.clone(), balances.clone()))
+                                .collect(),
+                        },
+                    );
+                }
+
                 // Construct state from snapshot
-                if let Some(state_decode_f) = self.registry.get(protocol.as_str()) {
-                    match state_decode_f(
+                if let Some(state_decode_f) = self.decoder_for(protocol, &id) {
+                    // Registered decoders may panic on unexpected snapshots, contain it to
+                    // this component.
+                    match AssertUnwindSafe(state_decode_f(
                         snapshot,
                         block.clone(),
                         account_balances.clone(),
                         self.state.clone(),
-                    )
+                    ))
+                    .catch_unwind()
                     .await
-                    {
-                        Ok(state) => {
+                    .unwrap_or_else(|payload| {
+                        Err(InvalidSnapshotError::VMError(SimulationError::internal_panic(
+                            &id,
+                            payload.as_ref(),
+                        )))
+                    }) {
+                        Ok(mut state) => {
+                            if let Some(clock) = &self.clock {
+                                state.set_clock(clock.clone());
+                            }
                             new_components.insert(id.clone(), state);
                         }
                         Err(e) => {
+                            let chain = error_chain(&e);
                             if self.skip_state_decode_failures {
-                                warn!(pool = id, error = %e, "StateDecodingFailure");
+                                warn!(pool = id, error = %chain, "StateDecodingFailure");
                                 continue 'outer;
                             } else {
-                                error!(pool = id, error = %e, "StateDecodingFailure");
-                                return Err(StreamDecodeError::Fatal(format!("{e}")));
+                                error!(pool = id, error = %chain, "StateDecodingFailure");
+                                return Err(StreamDecodeError::Fatal(chain));
                             }
                         }
                     }
@@ -356,43 +884,46 @@ impl TychoStreamDecoder {
             updated_states.extend(new_components);
 
             // PROCESS DELTAS
-            if let Some(deltas) = protocol_msg.deltas.clone() {
-                // Update engine with account changes
-                let account_update_by_address: HashMap<Address, AccountUpdate> = deltas
-                    .account_updates
-                    .clone()
-                    .iter()
-                    .map(|(key, value)| (Address::from_slice(&key[..20]), value.clone().into()))
-                    .collect();
-                info!("Updating engine with {} contract deltas", deltas.state_updates.len());
-                update_engine(
-                    SHARED_TYCHO_DB.clone(),
-                    block.clone().into(),
-                    None,
-                    account_update_by_address,
-                )
-                .await;
-                info!("Engine updated");
-
-                // Collect all pools related to the updated accounts
+            if let Some(deltas) = protocol_msg
+                .deltas
+                .clone()
+                .filter(|_| batch.includes_changes())
+            {
+                // Collect all pools related to the updated accounts, skipping the updates of
+                // accounts no pool tracks before converting them for the engine
                 let mut pools_to_update = HashSet::new();
-                for (account, _update) in deltas.account_updates {
-                    // get new pools related to the account updated
-                    pools_to_update.extend(
-                        contracts_map
-                            .get(&account)
-                            .cloned()
-                            .unwrap_or_default(),
-                    );
-                    // get existing pools related to the account updated
+                let mut account_update_by_address: HashMap<Address, AccountUpdate> = HashMap::new();
+                for (account, update) in deltas.account_updates {
+                    // new pools related to the account updated
+                    let new_pools = contracts_map.get(&account);
+                    // existing pools related to the account updated
+                    let existing_pools = if state_guard.is_tracked_contract(&account) {
+                        state_guard.contracts_map.get(&account)
+                    } else {
+                        None
+                    };
+                    if new_pools.is_none() && existing_pools.is_none() {
+                        continue;
+                    }
+                    pools_to_update.extend(new_pools.into_iter().flatten().cloned());
                     pools_to_update.extend(
-                        state_guard
-                            .contracts_map
-                            .get(&account)
-                            .cloned()
-                            .unwrap_or_default(),
+                        existing_pools
+                            .into_iter()
+                            .flatten()
+                            .cloned(),
                     );
+                    account_update_by_address
+                        .insert(Address::from_slice(&account[..20]), update.into());
+                }
+
+                // Update engine with account changes
+                info!("Updating engine with {} contract deltas", account_update_by_address.len());
+                if let Some(checkpoint) = &mut vm_checkpoint {
+                    SHARED_TYCHO_DB.record(checkpoint, account_update_by_address.keys());
                 }
+                update_engine(SHARED_TYCHO_DB.clone(), header, None, account_update_by_address)
+                    .await;
+                info!("Engine updated");
 
                 // Collect all balance changes this block
                 let all_balances = Balances {
@@ -427,47 +958,213 @@ impl TychoStreamDecoder {
                         .collect(),
                 };
 
+                // only the deltas of pools with a stored snapshot are kept to apply to it
+                let mut state_updates = HashMap::new();
+
                 // update states with protocol state deltas (attribute changes etc.)
                 for (id, update) in deltas.state_updates {
+                    pools_to_update.remove(&id);
+                    if !claims.claim(&id, protocol) {
+                        continue;
+                    }
+                    if state_guard.snapshots.contains_key(&id) || new_snapshots.contains_key(&id) {
+                        state_updates.insert(id.clone(), update.clone());
+                    }
                     Self::apply_update(
                         &id,
                         update,
                         &mut updated_states,
                         &state_guard,
                         &all_balances,
+                        &mut quarantined,
                     )?;
-                    pools_to_update.remove(&id);
                 }
 
                 // update remaining pools linked to updated contracts/updated balances
                 for pool in pools_to_update {
+                    if !claims.claim(&pool, protocol) {
+                        continue;
+                    }
                     Self::apply_update(
                         &pool,
                         ProtocolStateDelta::default(),
                         &mut updated_states,
                         &state_guard,
                         &all_balances,
+                        &mut quarantined,
                     )?;
                 }
+
+                if self.switchable(protocol).is_some() {
+                    snapshot_deltas.push((state_updates, all_balances));
+                }
             };
         }
 
         // Persist the newly added/updated states
         let mut state_guard = self.state.write().await;
+        let states = if journal_tip {
+            updated_states
+                .keys()
+                .chain(&quarantined)
+                .filter_map(|id| {
+                    let previous = state_guard.states.get(id)?;
+                    Some((id.clone(), previous.clone_box()))
+                })
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        if batch.is_continuation() {
+            // the first batch of the block recorded the states from before it
+            let journal = &mut state_guard.tip_journal;
+            for (id, state) in states {
+                journal
+                    .states
+                    .entry(id)
+                    .or_insert(state);
+            }
+            journal.vm_accounts = vm_checkpoint;
+        } else {
+            state_guard.tip_journal = TipJournal {
+                parent: state_guard.tip.replace(header),
+                states,
+                vm_accounts: vm_checkpoint,
+            };
+        }
+        for id in &quarantined {
+            state_guard.states.remove(id);
+        }
+        state_guard
+            .snapshots
+            .extend(new_snapshots);
+        for (state_updates, balances) in &snapshot_deltas {
+            for (id, stored) in state_guard.snapshots.iter_mut() {
+                stored.apply(state_updates.get(id), balances);
+            }
+        }
+        for id in removed_pairs.keys() {
+            if state_guard
+                .snapshots
+                .remove(id)
+                .is_some()
+            {
+                if let Some(overrides) = &self.backend_overrides {
+                    overrides.set_vm_capable(id, false);
+                }
+            }
+        }
         state_guard
             .states
             .extend(updated_states.clone().into_iter());
-        for (key, values) in contracts_map {
-            state_guard
-                .contracts_map
-                .entry(key)
-                .or_insert_with(HashSet::new)
-                .extend(values);
+        for id in removed_pairs.keys() {
+            state_guard.untrack_pool(id);
+        }
+        for (contract, ids) in contracts_map {
+            for id in ids {
+                state_guard.track_contract(contract.clone(), id);
+            }
+        }
+        let pools_ready = state_guard.states.len();
+        drop(state_guard);
+        updated_states.extend(
+            self.apply_backend_overrides(&block)
+                .await,
+        );
+        if let DecodeBatch::Prioritized(split) = &batch {
+            *split.owners.lock().unwrap() = claims.owners.clone();
+            if let Some(warmup) = &self.warmup {
+                warmup.ready(pools_ready);
+            }
+        }
+        // the first message of a stream carries the snapshots of all subscribed extractors
+        if batch.is_last() &&
+            msg.state_msgs
+                .values()
+                .any(|protocol_msg| {
+                    !protocol_msg
+                        .snapshots
+                        .get_states()
+                        .is_empty()
+                })
+        {
+            SHARED_TYCHO_DB.complete_snapshot_phase();
         }
 
         // Send the tick with all updated states
         Ok(BlockUpdate::new(block.number, updated_states, new_pairs)
-            .set_removed_pairs(removed_pairs))
+            .set_block_hash(block.hash)
+            .set_removed_pairs(removed_pairs)
+            .set_conflicts(claims.into_conflicts()))
+    }
+
+    /// Re-decodes the pools whose backend override changed from their stored snapshots.
+    ///
+    /// Pools that fail to decode keep their current state, so they stay quotable, and their
+    /// override is rolled back to match it.
+    async fn apply_backend_overrides(
+        &self,
+        block: &Header,
+    ) -> HashMap<String, Box<dyn ProtocolSim>> {
+        let Some(overrides) = &self.backend_overrides else {
+            return HashMap::new();
+        };
+        let mut redecoded = HashMap::new();
+        for (id, previous) in overrides.take_pending() {
+            let Some(stored) = self
+                .state
+                .read()
+                .await
+                .snapshots
+                .get(&id)
+                .cloned()
+            else {
+                // the pool was removed, or its protocol can't be switched
+                debug!(pool = id, reason = "MissingSnapshot", "BackendSwitchSkipped");
+                continue;
+            };
+            let Some(state_decode_f) = self.decoder_for(&stored.protocol, &id) else {
+                debug!(pool = id, reason = "MissingDecoderRegistration", "BackendSwitchSkipped");
+                continue;
+            };
+            match AssertUnwindSafe(state_decode_f(
+                stored.snapshot,
+                block.clone(),
+                stored.account_balances,
+                self.state.clone(),
+            ))
+            .catch_unwind()
+            .await
+            {
+                Ok(Ok(mut state)) => {
+                    if let Some(clock) = &self.clock {
+                        state.set_clock(clock.clone());
+                    }
+                    info!(pool = id, backend = ?state.backend(), "BackendSwitched");
+                    redecoded.insert(id, state);
+                }
+                Ok(Err(e)) => {
+                    error!(pool = id, error = %error_chain(&e), "BackendSwitchFailed");
+                    overrides.restore(&id, previous);
+                }
+                Err(payload) => {
+                    error!(
+                        pool = id,
+                        error = %panic_message(payload.as_ref()),
+                        "BackendSwitchFailed"
+                    );
+                    overrides.restore(&id, previous);
+                }
+            }
+        }
+        if !redecoded.is_empty() {
+            self.state.write().await.states.extend(
+                redecoded
+                    .iter()
+                    .map(|(id, state)| (id.clone(), state.clone())),
+            );
+        }
+        redecoded
     }
 
     fn apply_update(
@@ -476,17 +1173,16 @@ impl TychoStreamDecoder {
         updated_states: &mut HashMap<String, Box<dyn ProtocolSim>>,
         state_guard: &RwLockReadGuard<'_, DecoderState>,
         all_balances: &Balances,
+        quarantined: &mut HashSet<String>,
     ) -> Result<(), StreamDecodeError> {
         match updated_states.entry(id.clone()) {
             Entry::Occupied(mut entry) => {
                 // If state exists in updated_states, apply the delta to it
                 let state: &mut Box<dyn ProtocolSim> = entry.get_mut();
-                state
-                    .delta_transition(update, &state_guard.tokens, all_balances)
-                    .map_err(|e| {
-                        error!(pool = id, error = ?e, "DeltaTransitionError");
-                        StreamDecodeError::Fatal(format!("TransitionFailure: {e:?}"))
-                    })?;
+                if !Self::transition_state(id, state, update, &state_guard.tokens, all_balances)? {
+                    entry.remove();
+                    quarantined.insert(id.clone());
+                }
             }
             Entry::Vacant(_) => {
                 match state_guard.states.get(id) {
@@ -494,13 +1190,17 @@ impl TychoStreamDecoder {
                     // state
                     Some(stored_state) => {
                         let mut state = stored_state.clone();
-                        state
-                            .delta_transition(update, &state_guard.tokens, all_balances)
-                            .map_err(|e| {
-                                error!(pool = id, error = ?e, "DeltaTransitionError");
-                                StreamDecodeError::Fatal(format!("TransitionFailure: {e:?}"))
-                            })?;
-                        updated_states.insert(id.clone(), state);
+                        if Self::transition_state(
+                            id,
+                            &mut state,
+                            update,
+                            &state_guard.tokens,
+                            all_balances,
+                        )? {
+                            updated_states.insert(id.clone(), state);
+                        } else {
+                            quarantined.insert(id.clone());
+                        }
                     }
                     None => debug!(pool = id, reason = "MissingState", "DeltaTransitionError"),
                 }
@@ -508,22 +1208,143 @@ impl TychoStreamDecoder {
         }
         Ok(())
     }
+
+    /// Applies a delta to a state, containing any panic raised by the state's implementation.
+    ///
+    /// Returns `Ok(false)` if the transition panicked, in which case the state is no longer
+    /// trustworthy and must be dropped.
+    fn transition_state(
+        id: &String,
+        state: &mut Box<dyn ProtocolSim>,
+        update: ProtocolStateDelta,
+        tokens: &HashMap<Bytes, Token>,
+        all_balances: &Balances,
+    ) -> Result<bool, StreamDecodeError> {
+        match guard_panic(id, || state.delta_transition(update, tokens, all_balances)) {
+            Ok(result) => {
+                result.map_err(|e| {
+                    error!(pool = id, error = ?e, "DeltaTransitionError");
+                    StreamDecodeError::Fatal(format!("TransitionFailure: {e:?}"))
+                })?;
+                Ok(true)
+            }
+            Err(err) => {
+                error!(pool = id, error = %err, "DeltaTransitionPanic, quarantining pool");
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Combines the updates of two batches of the same block, `later` decoded after `earlier`.
+fn merge_updates(mut earlier: BlockUpdate, later: BlockUpdate) -> BlockUpdate {
+    earlier.states.extend(later.states);
+    earlier
+        .new_pairs
+        .extend(later.new_pairs);
+    earlier
+        .removed_pairs
+        .extend(later.removed_pairs);
+    earlier
+        .conflicts
+        .extend(later.conflicts);
+    earlier.conflicts.sort();
+    earlier.conflicts.dedup();
+    earlier
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{fs, path::Path};
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
 
+    use alloy_primitives::{hex, U256};
     use mockall::predicate::*;
-    use num_bigint::ToBigUint;
+    use num_bigint::{BigUint, ToBigUint};
     use rstest::*;
 
     use super::*;
     use crate::{
-        evm::protocol::uniswap_v2::state::UniswapV2State, models::Token,
-        protocol::state::MockProtocolSim,
+        evm::{
+            backend_override::BackendOverrideError,
+            protocol::uniswap_v2::state::UniswapV2State,
+            scenario::{QuoteExpectation, Scenario, ScenarioRunner},
+        },
+        models::Token,
+        protocol::{errors::TransitionError, models::GetAmountOutResult, state::MockProtocolSim},
     };
 
+    /// A state whose decoding and transitions always panic.
+    #[derive(Debug, Clone)]
+    struct PanickingState;
+
+    impl TryFromWithBlock<ComponentWithState> for PanickingState {
+        type Error = InvalidSnapshotError;
+
+        async fn try_from_with_block(
+            _value: ComponentWithState,
+            _block: Header,
+            _account_balances: &AccountBalances,
+            _all_tokens: &HashMap<Bytes, Token>,
+        ) -> Result<Self, Self::Error> {
+            panic!("decoder exploded")
+        }
+    }
+
+    impl ProtocolSim for PanickingState {
+        fn fee(&self) -> f64 {
+            unimplemented!()
+        }
+
+        fn spot_price(&self, _base: &Token, _quote: &Token) -> Result<f64, SimulationError> {
+            unimplemented!()
+        }
+
+        fn get_amount_out(
+            &self,
+            _amount_in: num_bigint::BigUint,
+            _token_in: &Token,
+            _token_out: &Token,
+        ) -> Result<GetAmountOutResult, SimulationError> {
+            unimplemented!()
+        }
+
+        fn get_limits(
+            &self,
+            _sell_token: Address,
+            _buy_token: Address,
+        ) -> Result<(num_bigint::BigUint, num_bigint::BigUint), SimulationError> {
+            unimplemented!()
+        }
+
+        fn delta_transition(
+            &mut self,
+            _delta: ProtocolStateDelta,
+            _tokens: &HashMap<Bytes, Token>,
+            _balances: &Balances,
+        ) -> Result<(), TransitionError<String>> {
+            panic!("transition exploded")
+        }
+
+        fn clone_box(&self) -> Box<dyn ProtocolSim> {
+            Box::new(self.clone())
+        }
+
+        fn as_any(&self) -> &dyn std::any::Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+            self
+        }
+
+        fn eq(&self, _other: &dyn ProtocolSim) -> bool {
+            false
+        }
+    }
+
     async fn setup_decoder(set_tokens: bool) -> TychoStreamDecoder {
         let mut decoder = TychoStreamDecoder::new();
         decoder.register_decoder::<UniswapV2State>("uniswap_v2");
@@ -543,14 +1364,47 @@ mod tests {
         decoder
     }
 
-    fn load_test_msg(name: &str) -> FeedMessage {
+    fn test_asset_path(name: &str) -> PathBuf {
         let project_root = env!("CARGO_MANIFEST_DIR");
-        let asset_path =
-            Path::new(project_root).join(format!("tests/assets/decoder/{}.json", name));
-        let json_data = fs::read_to_string(asset_path).expect("Failed to read test asset");
+        Path::new(project_root).join(format!("tests/assets/decoder/{}.json", name))
+    }
+
+    fn load_test_msg(name: &str) -> FeedMessage {
+        let json_data =
+            fs::read_to_string(test_asset_path(name)).expect("Failed to read test asset");
         serde_json::from_str(&json_data).expect("Failed to deserialize FeedMsg json!")
     }
 
+    /// A scenario runner set up like the decoder of [`setup_decoder`].
+    async fn setup_runner(skip_failures: bool) -> ScenarioRunner {
+        let mut runner = ScenarioRunner::new();
+        runner.register_decoder::<UniswapV2State>("uniswap_v2");
+        runner.skip_state_decode_failures(skip_failures);
+        let tokens = [WETH, USDT]
+            .into_iter()
+            .map(|addr| {
+                let token = Token::new(addr, 18, addr, 100_000.to_biguint().unwrap());
+                (token.address.clone(), token)
+            })
+            .collect();
+        runner.set_tokens(tokens).await;
+        runner
+    }
+
+    const WETH: &str = "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2";
+    const USDT: &str = "0xdac17f958d2ee523a2206206994597c13d831ec7";
+
+    /// Expects 1 WETH to buy `usdt_out` on the uniswap_v2 pool of the test assets.
+    fn weth_usdt_quote(usdt_out: u64) -> QuoteExpectation {
+        QuoteExpectation::new(
+            DUAL_POOL,
+            Bytes::from(WETH),
+            Bytes::from(USDT),
+            BigUint::from(10u64).pow(18),
+            BigUint::from(usdt_out),
+        )
+    }
+
     #[tokio::test]
     async fn test_decode() {
         let decoder = setup_decoder(true).await;
@@ -570,6 +1424,79 @@ mod tests {
         assert_eq!(res2.states.len(), 1);
     }
 
+    #[tokio::test]
+    async fn test_decode_scenario() {
+        let scenario = Scenario::new("uniswap_v2 snapshot and delta")
+            .message_file(test_asset_path("uniswap_v2_snapshot"))
+            .expect_updated(&[DUAL_POOL])
+            .expect_quote(weth_usdt_quote(3_578_876_851))
+            .message_file(test_asset_path("uniswap_v2_delta"))
+            .expect_updated(&[DUAL_POOL])
+            .expect_quote(weth_usdt_quote(3_577_541_567));
+
+        if let Err(failure) = setup_runner(false)
+            .await
+            .run(&scenario)
+            .await
+        {
+            panic!("{failure}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_decode_warmup_priority() {
+        let mut decoder = setup_decoder(true).await;
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        decoder.set_warmup_priority(WarmupPriority::List(vec!["0xbb".to_string()]), tx);
+        // Serve the same pool under several ids
+        let asset_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/assets/decoder/uniswap_v2_snapshot.json");
+        let mut msg: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(asset_path).unwrap()).unwrap();
+        let states = msg["state_msgs"]["uniswap_v2"]["snapshots"]["states"]
+            .as_object_mut()
+            .unwrap();
+        let pool = states
+            .remove("0x0d4a11d5eeaac28ec3f61d100daf4d40471f1852")
+            .unwrap();
+        for id in ["0xaa", "0xbb", "0xcc"] {
+            states.insert(id.to_string(), pool.clone());
+        }
+        let msg: FeedMessage = serde_json::from_value(msg).unwrap();
+        let batches = decoder.batches(&msg);
+        assert_eq!(batches.len(), 2);
+
+        let prioritized = decoder
+            .decode_batch(&msg, batches[0].clone())
+            .await
+            .expect("decode failure");
+
+        assert_eq!(
+            prioritized
+                .states
+                .keys()
+                .collect::<Vec<_>>(),
+            ["0xbb"]
+        );
+        assert_eq!(rx.try_recv().unwrap(), WarmupEvent::PartialReady { pools_ready: 1 });
+
+        let remainder = decoder
+            .decode_batch(&msg, batches[1].clone())
+            .await
+            .expect("decode failure");
+
+        assert_eq!(
+            remainder
+                .new_pairs
+                .keys()
+                .collect::<HashSet<_>>(),
+            HashSet::from([&"0xaa".to_string(), &"0xcc".to_string()])
+        );
+        assert!(rx.try_recv().is_err());
+        // only the first snapshot is split
+        assert!(matches!(decoder.batches(&msg).as_slice(), [DecodeBatch::Full]));
+    }
+
     #[tokio::test]
     async fn test_decode_component_missing_token() {
         let decoder = setup_decoder(false).await;
@@ -591,6 +1518,47 @@ mod tests {
         assert_eq!(res1.states.len(), 0);
     }
 
+    #[tokio::test]
+    async fn test_decode_skips_tokens_downgraded_in_registry() {
+        let decoder = setup_decoder(true).await;
+        let weth = Bytes::from("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2").lpad(20, 0);
+
+        assert!(decoder
+            .token_registry()
+            .update_quality(&weth, 10));
+        let msg = load_test_msg("uniswap_v2_snapshot");
+        let res = decoder
+            .decode(msg)
+            .await
+            .expect("decode failure");
+
+        assert_eq!(res.states.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_decoded_components_share_registry_tokens() {
+        let decoder = setup_decoder(true).await;
+
+        let msg = load_test_msg("uniswap_v2_snapshot");
+        let res = decoder
+            .decode(msg)
+            .await
+            .expect("decode failure");
+
+        let registry = decoder.token_registry();
+        let component = res
+            .new_pairs
+            .values()
+            .next()
+            .expect("no component decoded");
+        for token in &component.tokens {
+            let shared = registry
+                .by_address(&token.address)
+                .unwrap();
+            assert!(Arc::ptr_eq(token, &shared));
+        }
+    }
+
     #[rstest]
     #[case(true)]
     #[case(false)]
@@ -621,6 +1589,32 @@ mod tests {
         }
     }
 
+    #[rstest]
+    #[case(true)]
+    #[case(false)]
+    #[tokio::test]
+    async fn test_decode_component_bad_id_scenario(#[case] skip_failures: bool) {
+        let msg = test_asset_path("uniswap_v2_snapshot_broken_id");
+        let scenario = if skip_failures {
+            Scenario::new("bad id skipped")
+                .message_file(msg)
+                .expect_updated(&[DUAL_POOL])
+        } else {
+            Scenario::new("bad id raised").expect_decode_error(
+                msg,
+                "Failed to parse bytes: Invalid hex: Invalid character 'Z' at position 0",
+            )
+        };
+
+        if let Err(failure) = setup_runner(skip_failures)
+            .await
+            .run(&scenario)
+            .await
+        {
+            panic!("{failure}");
+        }
+    }
+
     #[rstest]
     #[case(true)]
     #[case(false)]
@@ -685,10 +1679,9 @@ mod tests {
             .state
             .write()
             .await
-            .contracts_map
-            .insert(
+            .track_contract(
                 Bytes::from("0xba12222222228d8ba445958a75a0704d566bf2c8").lpad(20, 0),
-                HashSet::from([pool_id.clone()]),
+                pool_id.clone(),
             );
 
         // Load a test message containing a contract update
@@ -702,4 +1695,500 @@ mod tests {
 
         // The mock framework will assert that `delta_transition` was called exactly once
     }
+
+    #[rstest]
+    #[case(true)]
+    #[case(false)]
+    #[tokio::test]
+    async fn test_decode_component_panicking_decoder(#[case] skip_failures: bool) {
+        let mut decoder = setup_decoder(true).await;
+        decoder.register_decoder::<PanickingState>("uniswap_v2");
+        decoder.skip_state_decode_failures = skip_failures;
+
+        let msg = load_test_msg("uniswap_v2_snapshot");
+        match decoder.decode(msg).await {
+            Err(StreamDecodeError::Fatal(msg)) => {
+                assert!(!skip_failures, "Expected failures to be ignored. Err: {}", msg);
+                assert!(msg.contains("decoder exploded"));
+            }
+            Ok(res) => {
+                assert!(skip_failures, "Expected failures to be raised");
+                assert_eq!(res.states.len(), 0);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_decode_quarantines_panicking_state() {
+        let decoder = setup_decoder(true).await;
+        let pool_id =
+            "0x93d199263632a4ef4bb438f1feb99e57b4b5f0bd0000000000000000000005c2".to_string();
+        decoder
+            .state
+            .write()
+            .await
+            .states
+            .insert(pool_id.clone(), Box::new(PanickingState) as Box<dyn ProtocolSim>);
+        decoder
+            .state
+            .write()
+            .await
+            .track_contract(
+                Bytes::from("0xba12222222228d8ba445958a75a0704d566bf2c8").lpad(20, 0),
+                pool_id.clone(),
+            );
+
+        let res = decoder
+            .decode(load_test_msg("balancer_v2_delta"))
+            .await
+            .expect("decode failure");
+
+        assert!(!res.states.contains_key(&pool_id));
+        assert!(!decoder
+            .state
+            .read()
+            .await
+            .states
+            .contains_key(&pool_id));
+
+        // Subsequent blocks are processed normally
+        decoder
+            .decode(load_test_msg("balancer_v2_delta"))
+            .await
+            .expect("decode failure");
+    }
+
+    const DUAL_POOL: &str = "0x0d4a11d5eeaac28ec3f61d100daf4d40471f1852";
+
+    /// The uniswap_v2 snapshot, with the pool also reported by `vm:uniswap_v2` with a different
+    /// reserve0. Returns the message and the reserve0 of the VM report.
+    fn dual_report_msg(native_removes: bool) -> (FeedMessage, U256) {
+        let asset_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/assets/decoder/uniswap_v2_snapshot.json");
+        let mut msg: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(asset_path).unwrap()).unwrap();
+        let native = &mut msg["state_msgs"]["uniswap_v2"];
+        let mut vm = native.clone();
+        if native_removes {
+            native["snapshots"]["states"] = serde_json::json!({});
+        } else {
+            native["removed_components"] = serde_json::json!({});
+        }
+        vm["removed_components"] = serde_json::json!({});
+        vm["snapshots"]["states"][DUAL_POOL]["state"]["attributes"]["reserve0"] =
+            serde_json::json!("0x01");
+        msg["state_msgs"]["vm:uniswap_v2"] = vm;
+        (serde_json::from_value(msg).unwrap(), U256::from(1u64))
+    }
+
+    fn reserve0(res: &BlockUpdate) -> U256 {
+        res.states[DUAL_POOL]
+            .as_any()
+            .downcast_ref::<UniswapV2State>()
+            .unwrap()
+            .reserve0
+    }
+
+    #[tokio::test]
+    async fn test_decode_native_report_wins_by_default() {
+        let (msg, _) = dual_report_msg(false);
+        let mut decoder = setup_decoder(true).await;
+        decoder.register_decoder::<UniswapV2State>("vm:uniswap_v2");
+
+        let res = decoder
+            .decode(msg)
+            .await
+            .expect("decode failure");
+
+        assert_eq!(reserve0(&res), U256::from(0x02a15edc6893fcfad4ca_u128));
+        assert_eq!(
+            res.conflicts,
+            vec![ComponentConflict {
+                component_id: DUAL_POOL.to_string(),
+                winner: "uniswap_v2".to_string(),
+                loser: "vm:uniswap_v2".to_string(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_decode_configured_priority_wins() {
+        let (msg, vm_reserve0) = dual_report_msg(false);
+        let mut decoder = setup_decoder(true).await;
+        decoder.register_decoder::<UniswapV2State>("vm:uniswap_v2");
+        decoder.set_extractor_priority("vm:uniswap_v2", NATIVE_EXTRACTOR_PRIORITY + 1);
+
+        let res = decoder
+            .decode(msg)
+            .await
+            .expect("decode failure");
+
+        assert_eq!(reserve0(&res), vm_reserve0);
+        assert_eq!(res.conflicts[0].winner, "vm:uniswap_v2");
+        assert_eq!(
+            decoder.state.read().await.states[DUAL_POOL]
+                .as_any()
+                .downcast_ref::<UniswapV2State>()
+                .unwrap()
+                .reserve0,
+            vm_reserve0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_decode_removal_wins_over_lower_priority_report() {
+        let (msg, _) = dual_report_msg(true);
+        let mut decoder = setup_decoder(true).await;
+        decoder.register_decoder::<UniswapV2State>("vm:uniswap_v2");
+
+        let res = decoder
+            .decode(msg)
+            .await
+            .expect("decode failure");
+
+        assert!(res
+            .removed_pairs
+            .contains_key(DUAL_POOL));
+        assert!(!res.new_pairs.contains_key(DUAL_POOL));
+        assert!(!res.states.contains_key(DUAL_POOL));
+        assert_eq!(res.conflicts.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_decode_dual_report_is_stable() {
+        let mut outcomes = HashSet::new();
+        for _ in 0..10 {
+            let (msg, _) = dual_report_msg(false);
+            let mut decoder = setup_decoder(true).await;
+            decoder.register_decoder::<UniswapV2State>("vm:uniswap_v2");
+
+            let res = decoder
+                .decode(msg)
+                .await
+                .expect("decode failure");
+
+            outcomes.insert((reserve0(&res), res.conflicts));
+        }
+
+        assert_eq!(outcomes.len(), 1);
+    }
+
+    /// A uniswap_v2 state standing in for the VM implementation of the protocol.
+    #[derive(Debug, Clone)]
+    struct VmUniswapV2State(UniswapV2State);
+
+    impl TryFromWithBlock<ComponentWithState> for VmUniswapV2State {
+        type Error = InvalidSnapshotError;
+
+        async fn try_from_with_block(
+            value: ComponentWithState,
+            block: Header,
+            account_balances: &AccountBalances,
+            all_tokens: &HashMap<Bytes, Token>,
+        ) -> Result<Self, Self::Error> {
+            UniswapV2State::try_from_with_block(value, block, account_balances, all_tokens)
+                .await
+                .map(Self)
+        }
+    }
+
+    impl ProtocolSim for VmUniswapV2State {
+        fn fee(&self) -> f64 {
+            self.0.fee()
+        }
+
+        fn spot_price(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
+            self.0.spot_price(base, quote)
+        }
+
+        fn get_amount_out(
+            &self,
+            amount_in: num_bigint::BigUint,
+            token_in: &Token,
+            token_out: &Token,
+        ) -> Result<GetAmountOutResult, SimulationError> {
+            let mut res = self
+                .0
+                .get_amount_out(amount_in, token_in, token_out)?;
+            let new_state = res
+                .new_state
+                .as_any()
+                .downcast_ref::<UniswapV2State>()
+                .unwrap()
+                .clone();
+            res.new_state = Box::new(Self(new_state));
+            Ok(res)
+        }
+
+        fn get_limits(
+            &self,
+            sell_token: Address,
+            buy_token: Address,
+        ) -> Result<(num_bigint::BigUint, num_bigint::BigUint), SimulationError> {
+            self.0.get_limits(sell_token, buy_token)
+        }
+
+        fn backend(&self) -> Backend {
+            Backend::Vm
+        }
+
+        fn delta_transition(
+            &mut self,
+            delta: ProtocolStateDelta,
+            tokens: &HashMap<Bytes, Token>,
+            balances: &Balances,
+        ) -> Result<(), TransitionError<String>> {
+            self.0
+                .delta_transition(delta, tokens, balances)
+        }
+
+        fn clone_box(&self) -> Box<dyn ProtocolSim> {
+            Box::new(self.clone())
+        }
+
+        fn as_any(&self) -> &dyn std::any::Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+            self
+        }
+
+        fn eq(&self, other: &dyn ProtocolSim) -> bool {
+            other
+                .as_any()
+                .downcast_ref::<Self>()
+                .is_some_and(|other| other.0 == self.0)
+        }
+    }
+
+    /// The uniswap_v2 snapshot, with `contract_ids` set on the pool and the storage of
+    /// `with_storage` included.
+    fn snapshot_with_contracts(contract_ids: &[&str], with_storage: &[&str]) -> FeedMessage {
+        let asset_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/assets/decoder/uniswap_v2_snapshot.json");
+        let mut msg: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(asset_path).unwrap()).unwrap();
+        let snapshots = &mut msg["state_msgs"]["uniswap_v2"]["snapshots"];
+        snapshots["states"][DUAL_POOL]["component"]["contract_ids"] =
+            serde_json::json!(contract_ids);
+        let zero_hash = format!("0x{}", "00".repeat(32));
+        for address in with_storage {
+            snapshots["vm_storage"][*address] = serde_json::json!({
+                "chain": "ethereum",
+                "address": address,
+                "title": address,
+                "slots": {},
+                "native_balance": "0x00",
+                "token_balances": {},
+                "code": "0x00",
+                "code_hash": zero_hash,
+                "balance_modify_tx": zero_hash,
+                "code_modify_tx": zero_hash,
+                "creation_tx": null
+            });
+        }
+        serde_json::from_value(msg).unwrap()
+    }
+
+    async fn switchable_decoder() -> (TychoStreamDecoder, Arc<QuoteBackendOverride>) {
+        let mut decoder = setup_decoder(true).await;
+        decoder.register_vm_fallback::<VmUniswapV2State>("uniswap_v2");
+        let overrides = Arc::new(QuoteBackendOverride::new());
+        decoder.set_backend_overrides(overrides.clone());
+        (decoder, overrides)
+    }
+
+    /// Quotes the stored state of the dual pool and returns the backend of the result.
+    async fn quote_backend(decoder: &TychoStreamDecoder) -> Backend {
+        let guard = decoder.state.read().await;
+        let weth = &guard.tokens[&Bytes::from("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2")];
+        let usdt = &guard.tokens[&Bytes::from("0xdac17f958d2ee523a2206206994597c13d831ec7")];
+        guard.states[DUAL_POOL]
+            .get_amount_out(1_000_000u64.into(), weth, usdt)
+            .expect("pool must stay quotable")
+            .new_state
+            .backend()
+    }
+
+    #[tokio::test]
+    async fn test_backend_override_switches_pool_at_runtime() {
+        let (decoder, overrides) = switchable_decoder().await;
+        decoder
+            .decode(snapshot_with_contracts(&[DUAL_POOL], &[DUAL_POOL]))
+            .await
+            .expect("decode failure");
+        assert_eq!(quote_backend(&decoder).await, Backend::Native);
+
+        overrides
+            .set(DUAL_POOL, Backend::Vm)
+            .unwrap();
+        // the pool is served by its previous backend until the next block
+        assert_eq!(quote_backend(&decoder).await, Backend::Native);
+        let res = decoder
+            .decode(load_test_msg("uniswap_v2_delta"))
+            .await
+            .expect("decode failure");
+
+        let state = res.states[DUAL_POOL]
+            .as_any()
+            .downcast_ref::<VmUniswapV2State>()
+            .expect("pool must be re-decoded into the VM backend");
+        // re-decoded from the snapshot with the block's delta applied
+        assert_eq!(state.0.reserve0, U256::from(0x02a17f13e7674e01a281u128));
+        assert_eq!(quote_backend(&decoder).await, Backend::Vm);
+
+        overrides.clear(DUAL_POOL);
+        let res = decoder
+            .decode(load_test_msg("uniswap_v2_delta"))
+            .await
+            .expect("decode failure");
+
+        assert_eq!(res.states[DUAL_POOL].backend(), Backend::Native);
+        assert_eq!(quote_backend(&decoder).await, Backend::Native);
+    }
+
+    #[rstest]
+    #[case::no_contracts(&[])]
+    // no storage was received for the contract
+    #[case::no_storage(&["0x00000000000000000000000000000000000000aa"])]
+    #[tokio::test]
+    async fn test_backend_override_rejected_without_vm_data(#[case] contract_ids: &[&str]) {
+        let (decoder, overrides) = switchable_decoder().await;
+        decoder
+            .decode(snapshot_with_contracts(contract_ids, &[]))
+            .await
+            .expect("decode failure");
+
+        let res = overrides.set(DUAL_POOL, Backend::Vm);
+
+        assert_eq!(res, Err(BackendOverrideError::NoVmData(DUAL_POOL.to_string())));
+        assert_eq!(quote_backend(&decoder).await, Backend::Native);
+    }
+
+    #[tokio::test]
+    async fn test_backend_override_rolled_back_on_failed_switch() {
+        let mut decoder = setup_decoder(true).await;
+        decoder.register_vm_fallback::<PanickingState>("uniswap_v2");
+        let overrides = Arc::new(QuoteBackendOverride::new());
+        decoder.set_backend_overrides(overrides.clone());
+        decoder
+            .decode(snapshot_with_contracts(&[DUAL_POOL], &[DUAL_POOL]))
+            .await
+            .expect("decode failure");
+
+        overrides
+            .set(DUAL_POOL, Backend::Vm)
+            .unwrap();
+        let res = decoder
+            .decode(load_test_msg("uniswap_v2_delta"))
+            .await
+            .expect("decode failure");
+
+        // the pool keeps its native state, and the override no longer claims otherwise
+        assert_eq!(res.states[DUAL_POOL].backend(), Backend::Native);
+        assert_eq!(quote_backend(&decoder).await, Backend::Native);
+        assert_eq!(overrides.backend(DUAL_POOL), None);
+    }
+
+    /// The delta of `uniswap_v2_delta` moved to block `number`, updating the reserves of
+    /// `DUAL_POOL` to `reserves`, or nothing.
+    fn delta_at(number: u64, hash: u8, reserves: Option<(&str, &str)>) -> FeedMessage {
+        let asset_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/assets/decoder/uniswap_v2_delta.json");
+        let mut msg: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(asset_path).unwrap()).unwrap();
+        let protocol_msg = &mut msg["state_msgs"]["uniswap_v2"];
+        protocol_msg["header"]["number"] = serde_json::json!(number);
+        protocol_msg["header"]["hash"] =
+            serde_json::json!(format!("0x{}", hex::encode([hash; 32])));
+        let deltas = &mut protocol_msg["deltas"];
+        deltas["component_balances"] = serde_json::json!({});
+        deltas["state_updates"] = match reserves {
+            Some((reserve0, reserve1)) => serde_json::json!({
+                DUAL_POOL: {
+                    "component_id": DUAL_POOL,
+                    "updated_attributes": { "reserve0": reserve0, "reserve1": reserve1 },
+                    "deleted_attributes": []
+                }
+            }),
+            None => serde_json::json!({}),
+        };
+        serde_json::from_value(msg).unwrap()
+    }
+
+    #[rstest]
+    #[case::strict(BlockOrderingPolicy::StrictIncreasing, None)]
+    #[case::replace(
+        BlockOrderingPolicy::AllowSingleStepReplace,
+        Some((Some(0x02a15edc6893fcfad4ca_u128), 0x02a15edc6893fcfad4ca_u128))
+    )]
+    #[case::timestamp(BlockOrderingPolicy::TimestampMonotonic, Some((None, 0x05)))]
+    #[tokio::test]
+    async fn test_decode_minus_one_replacement(
+        #[case] policy: BlockOrderingPolicy,
+        #[case] expected: Option<(Option<u128>, u128)>,
+    ) {
+        let mut decoder = setup_decoder(true).await;
+        decoder.set_block_ordering(policy);
+        let snapshot = load_test_msg("uniswap_v2_snapshot");
+        decoder
+            .decode(snapshot)
+            .await
+            .expect("decode failure");
+        decoder
+            .decode(delta_at(21284146, 1, Some(("0x05", "0x06"))))
+            .await
+            .expect("decode failure");
+
+        let res = decoder
+            .decode(delta_at(21284145, 2, None))
+            .await;
+
+        let stored_reserve0 = decoder.state.read().await.states[DUAL_POOL]
+            .as_any()
+            .downcast_ref::<UniswapV2State>()
+            .unwrap()
+            .reserve0;
+        match expected {
+            None => {
+                assert!(matches!(
+                    res,
+                    Err(StreamDecodeError::BlockOrdering(BlockOrderingError::NotIncreasing {
+                        tip: 21284146,
+                        received: 21284145
+                    }))
+                ));
+                assert_eq!(stored_reserve0, U256::from(0x05));
+            }
+            Some((emitted, stored)) => {
+                let res = res.expect("decode failure");
+                assert_eq!(
+                    res.states
+                        .contains_key(DUAL_POOL)
+                        .then(|| reserve0(&res)),
+                    emitted.map(U256::from)
+                );
+                assert_eq!(stored_reserve0, U256::from(stored));
+            }
+        }
+    }
+
+    #[test]
+    fn test_contract_filter_follows_pools() {
+        let mut state = DecoderState::default();
+        let contract = Bytes::from(vec![1u8; 20]);
+        let unrelated = Bytes::from(vec![2u8; 20]);
+        state.track_contract(contract.clone(), "a".to_string());
+        state.track_contract(contract.clone(), "b".to_string());
+
+        assert!(state.is_tracked_contract(&contract));
+        assert!(!state.is_tracked_contract(&unrelated));
+        state.untrack_pool("a");
+        assert!(state.is_tracked_contract(&contract));
+        state.untrack_pool("b");
+        assert!(!state.is_tracked_contract(&contract));
+        assert!(state.contracts_map.is_empty());
+        assert_eq!(state.contract_filter.stats().probes, 4);
+    }
 }
@@ -0,0 +1,130 @@
+//! Contract address prediction.
+//!
+//! Derives the address a contract will be deployed to without executing the deployment, e.g. to
+//! locate a pool before simulating its creation.
+use alloy_primitives::{keccak256, Address, B256};
+
+/// Predicts the address of a contract deployed with `CREATE2` (EIP-1014).
+///
+/// The address is `keccak256(0xff ++ deployer ++ salt ++ keccak256(init_code))[12:]`.
+pub fn predict_create2(deployer: Address, salt: B256, init_code: &[u8]) -> Address {
+    predict_create2_from_hash(deployer, salt, keccak256(init_code))
+}
+
+/// Like [`predict_create2`], but takes the hash of the init code.
+///
+/// Factories usually publish only this hash, e.g. the `POOL_INIT_CODE_HASH` of Uniswap V3.
+pub fn predict_create2_from_hash(deployer: Address, salt: B256, init_code_hash: B256) -> Address {
+    let mut preimage = [0u8; 85];
+    preimage[0] = 0xff;
+    preimage[1..21].copy_from_slice(deployer.as_slice());
+    preimage[21..53].copy_from_slice(salt.as_slice());
+    preimage[53..].copy_from_slice(init_code_hash.as_slice());
+    Address::from_slice(&keccak256(preimage)[12..])
+}
+
+/// Predicts the address of a contract deployed with `CREATE` by `deployer` at `nonce`.
+///
+/// The address is `keccak256(rlp([deployer, nonce]))[12:]`.
+pub fn predict_create(deployer: Address, nonce: u64) -> Address {
+    // The list payload is at most 21 + 9 bytes, so it always takes the short list form.
+    let mut encoded = Vec::with_capacity(31);
+    encoded.push(0);
+    encoded.push(0x80 + 20);
+    encoded.extend_from_slice(deployer.as_slice());
+    match nonce {
+        0 => encoded.push(0x80),
+        1..=0x7f => encoded.push(nonce as u8),
+        _ => {
+            let bytes = nonce.to_be_bytes();
+            let significant = &bytes[nonce.leading_zeros() as usize / 8..];
+            encoded.push(0x80 + significant.len() as u8);
+            encoded.extend_from_slice(significant);
+        }
+    }
+    encoded[0] = 0xc0 + (encoded.len() - 1) as u8;
+    Address::from_slice(&keccak256(&encoded)[12..])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use alloy_primitives::hex;
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    // Examples from EIP-1014
+    #[case::zero(
+        "0x0000000000000000000000000000000000000000",
+        "0x0000000000000000000000000000000000000000000000000000000000000000",
+        "0x00",
+        "0x4D1A2e2bB4F88F0250f26Ffff098B0b30B26BF38"
+    )]
+    #[case::deployer(
+        "0xdeadbeef00000000000000000000000000000000",
+        "0x0000000000000000000000000000000000000000000000000000000000000000",
+        "0x00",
+        "0xB928f69Bb1D91Cd65274e3c79d8986362984fDA3"
+    )]
+    #[case::salt(
+        "0x00000000000000000000000000000000deadbeef",
+        "0x00000000000000000000000000000000000000000000000000000000cafebabe",
+        "0xdeadbeef",
+        "0x60f3f640a8508fC6a86d45DF051962668E1e8AC7"
+    )]
+    #[case::empty_init_code(
+        "0x0000000000000000000000000000000000000000",
+        "0x0000000000000000000000000000000000000000000000000000000000000000",
+        "0x",
+        "0xE33C0C7F7df4809055C3ebA6c09CFe4BaF1BD9e0"
+    )]
+    fn test_predict_create2(
+        #[case] deployer: &str,
+        #[case] salt: &str,
+        #[case] init_code: &str,
+        #[case] expected: &str,
+    ) {
+        let res = predict_create2(
+            Address::from_str(deployer).unwrap(),
+            B256::from_str(salt).unwrap(),
+            &hex::decode(init_code).unwrap(),
+        );
+
+        assert_eq!(res, Address::from_str(expected).unwrap());
+    }
+
+    #[test]
+    fn test_predict_create2_uniswap_v3_pool() {
+        let factory = Address::from_str("0x1F98431c8aD98523631AE4a59f267346ea31F984").unwrap();
+        let init_code_hash =
+            B256::from_str("0xe34f199b19b2b4f47f68442619d555527d244f78a3297ea89325f843f87b8b54")
+                .unwrap();
+        // keccak256(abi.encode(USDC, WETH, 500))
+        let salt =
+            B256::from_str("0x08374668a423750b443f65d645c5693995d43722b42cd84f7eeba28b008a40a2")
+                .unwrap();
+
+        let res = predict_create2_from_hash(factory, salt, init_code_hash);
+
+        // USDC/WETH 0.05%
+        assert_eq!(res, Address::from_str("0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640").unwrap());
+    }
+
+    #[rstest]
+    #[case::nonce_0(0, "0xcd234a471b72ba2f1ccf0a70fcaba648a5eecd8d")]
+    #[case::nonce_1(1, "0x343c43a37d37dff08ae8c4a11544c718abb4fcf8")]
+    #[case::nonce_2(2, "0xf778b86fa74e846c4f0a1fbd1335fe81c00a0c91")]
+    #[case::nonce_3(3, "0xfffd933a0bc612844eaf0c6fe3e5b8e9b6c1d19c")]
+    #[case::single_byte_max(0x7f, "0x06d9a77f5e4b311bae8d559db9cdb4df94104aa0")]
+    #[case::prefixed_byte(0x80, "0x08e190dcb7b73f5fcdabb43e102215c83659a76d")]
+    #[case::two_bytes(0xffff, "0x65260eecff4edebabe134f76f1f39a91defde56c")]
+    #[case::max(u64::MAX, "0x9bc924993b60399df164c3763a964301d3db95ca")]
+    fn test_predict_create(#[case] nonce: u64, #[case] expected: &str) {
+        let deployer = Address::from_str("0x6ac7ea33f8831ea9dcc53393aaa88b25a785dbf0").unwrap();
+
+        assert_eq!(predict_create(deployer, nonce), Address::from_str(expected).unwrap());
+    }
+}
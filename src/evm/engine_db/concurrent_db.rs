@@ -0,0 +1,387 @@
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    sync::{Arc, RwLock},
+    thread,
+};
+
+use alloy_primitives::{Address, B256, U256};
+use revm::{
+    db::DatabaseRef,
+    primitives::{AccountInfo, Bytecode},
+};
+use tracing::{debug, warn};
+
+use crate::evm::{
+    account_storage::{Account, StateUpdate},
+    engine_db::{
+        engine_db_interface::EngineDatabaseInterface,
+        simulation_db::BlockHeader,
+        tycho_db::{to_analysed, PreCachedDBError},
+    },
+};
+
+type AccountEntry = Arc<RwLock<Account>>;
+
+/// A database whose accounts can be read and updated concurrently.
+///
+/// Every account sits behind its own `RwLock`, so simulations reading one account never wait
+/// for writes to an unrelated account. The account map itself is only write-locked when a new
+/// account is inserted.
+#[derive(Clone, Debug, Default)]
+pub struct ConcurrentSimulationDB {
+    accounts: Arc<RwLock<HashMap<Address, AccountEntry>>>,
+    block: Arc<RwLock<Option<BlockHeader>>>,
+}
+
+impl ConcurrentSimulationDB {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn get_entry(&self, address: &Address) -> Option<AccountEntry> {
+        self.accounts
+            .read()
+            .unwrap()
+            .get(address)
+            .cloned()
+    }
+
+    /// Applies a single update to an account and returns the update reverting it, see
+    /// `Account::apply_update`.
+    ///
+    /// Only the lock of the given account is held while the update is applied. Updates to
+    /// accounts that were never initialized are ignored, mirroring `AccountStorage`.
+    fn apply_update(&self, address: &Address, update: &StateUpdate) -> Option<StateUpdate> {
+        let Some(entry) = self.get_entry(address) else {
+            warn!(?address, "Tried to update account {:x?} that was not initialized", address);
+            return None;
+        };
+        let revert = entry
+            .write()
+            .unwrap()
+            .apply_update(update);
+        Some(revert)
+    }
+
+    /// Update the simulation state sequentially.
+    ///
+    /// # Arguments
+    ///
+    /// * `updates`: All the state changes for a particular block.
+    /// * `block`: The block the changes belong to.
+    ///
+    /// # Returns
+    ///
+    /// The state updates required to revert the applied changes.
+    pub fn update_state(
+        &self,
+        updates: &HashMap<Address, StateUpdate>,
+        block: BlockHeader,
+    ) -> HashMap<Address, StateUpdate> {
+        *self.block.write().unwrap() = Some(block);
+        updates
+            .iter()
+            .filter_map(|(address, update)| {
+                self.apply_update(address, update)
+                    .map(|revert| (*address, revert))
+            })
+            .collect()
+    }
+
+    /// Update the simulation state, spreading the accounts over all available cores.
+    ///
+    /// Each worker only locks the accounts it is currently updating, so readers of other
+    /// accounts are not blocked while the updates are applied.
+    ///
+    /// # Arguments
+    ///
+    /// * `changes`: All the state changes for a particular block.
+    /// * `block`: The block the changes belong to.
+    ///
+    /// # Returns
+    ///
+    /// The state updates required to revert the applied changes.
+    pub fn apply_updates_parallel(
+        &self,
+        changes: &HashMap<Address, StateUpdate>,
+        block: BlockHeader,
+    ) -> HashMap<Address, StateUpdate> {
+        *self.block.write().unwrap() = Some(block);
+
+        let changes: Vec<_> = changes.iter().collect();
+        let workers = thread::available_parallelism()
+            .map(NonZeroUsize::get)
+            .unwrap_or(1);
+        let chunk_size = changes.len().div_ceil(workers).max(1);
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = changes
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .filter_map(|(address, update)| {
+                                self.apply_update(address, update)
+                                    .map(|revert| (**address, revert))
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| {
+                    handle
+                        .join()
+                        .expect("State update worker panicked")
+                })
+                .collect()
+        })
+    }
+
+    /// If block is set, returns the number. Otherwise returns None.
+    pub fn block_number(&self) -> Option<u64> {
+        self.block
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|header| header.number)
+    }
+}
+
+impl EngineDatabaseInterface for ConcurrentSimulationDB {
+    type Error = String;
+
+    /// Sets up a single account
+    ///
+    /// Accounts that are already present are left untouched.
+    fn init_account(
+        &self,
+        address: Address,
+        account: AccountInfo,
+        permanent_storage: Option<HashMap<U256, U256>>,
+        mocked: bool,
+    ) {
+        self.accounts
+            .write()
+            .unwrap()
+            .entry(address)
+            .or_insert_with(|| {
+                debug!(
+                    "Inserted a {} account {:x?}",
+                    if mocked { "mocked" } else { "non-mocked" },
+                    address
+                );
+                Arc::new(RwLock::new(Account {
+                    info: to_analysed(account),
+                    permanent_storage: permanent_storage.unwrap_or_default(),
+                    temp_storage: HashMap::new(),
+                    mocked,
+                }))
+            });
+    }
+
+    fn clear_temp_storage(&mut self) {
+        for account in self.accounts.read().unwrap().values() {
+            account
+                .write()
+                .unwrap()
+                .temp_storage
+                .clear();
+        }
+    }
+}
+
+impl DatabaseRef for ConcurrentSimulationDB {
+    type Error = PreCachedDBError;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        self.get_entry(&address)
+            .map(|entry| Some(entry.read().unwrap().info.clone()))
+            .ok_or(PreCachedDBError::MissingAccount(address))
+    }
+
+    fn code_by_hash_ref(&self, _code_hash: B256) -> Result<Bytecode, Self::Error> {
+        panic!("Code by hash is not implemented")
+    }
+
+    /// Retrieves the storage value at the specified address and index.
+    ///
+    /// Temp storage takes priority over permanent storage. Slots missing on a known account are
+    /// zero, as only non-zero values are stored.
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        let entry = self
+            .get_entry(&address)
+            .ok_or(PreCachedDBError::MissingAccount(address))?;
+        let account = entry.read().unwrap();
+        Ok(account
+            .temp_storage
+            .get(&index)
+            .or_else(|| account.permanent_storage.get(&index))
+            .copied()
+            .unwrap_or(U256::ZERO))
+    }
+
+    /// If block header is set, returns the hash. Otherwise returns a zero hash.
+    fn block_hash_ref(&self, _number: u64) -> Result<B256, Self::Error> {
+        match *self.block.read().unwrap() {
+            Some(header) => Ok(header.hash),
+            None => Ok(B256::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn header(number: u64) -> BlockHeader {
+        BlockHeader { number, hash: B256::default(), timestamp: 0 }
+    }
+
+    fn db_with_accounts(n: u64) -> ConcurrentSimulationDB {
+        let db = ConcurrentSimulationDB::new();
+        for i in 0..n {
+            db.init_account(
+                Address::left_padding_from(&i.to_be_bytes()),
+                AccountInfo::default(),
+                Some(HashMap::from([(U256::from(1), U256::from(i))])),
+                true,
+            );
+        }
+        db
+    }
+
+    fn updates(n: u64) -> HashMap<Address, StateUpdate> {
+        (0..n)
+            .map(|i| {
+                (
+                    Address::left_padding_from(&i.to_be_bytes()),
+                    StateUpdate {
+                        storage: Some(HashMap::from([(U256::from(1), U256::from(i + 1000))])),
+                        balance: Some(U256::from(i)),
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[rstest]
+    #[case::sequential(false)]
+    #[case::parallel(true)]
+    fn test_update_state(#[case] parallel: bool) {
+        let db = db_with_accounts(100);
+        let changes = updates(100);
+
+        let reverts = if parallel {
+            db.apply_updates_parallel(&changes, header(1))
+        } else {
+            db.update_state(&changes, header(1))
+        };
+
+        assert_eq!(reverts.len(), 100);
+        assert_eq!(db.block_number(), Some(1));
+        for i in 0..100u64 {
+            let address = Address::left_padding_from(&i.to_be_bytes());
+            assert_eq!(
+                db.storage_ref(address, U256::from(1))
+                    .unwrap(),
+                U256::from(i + 1000)
+            );
+            assert_eq!(
+                db.basic_ref(address)
+                    .unwrap()
+                    .unwrap()
+                    .balance,
+                U256::from(i)
+            );
+            assert_eq!(
+                reverts[&address],
+                StateUpdate {
+                    storage: Some(HashMap::from([(U256::from(1), U256::from(i))])),
+                    balance: Some(U256::ZERO),
+                    ..Default::default()
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_reads_during_parallel_updates() {
+        let db = db_with_accounts(100);
+        let changes = updates(100);
+
+        let reverts = thread::scope(|scope| {
+            let readers: Vec<_> = (0..4)
+                .map(|_| {
+                    let db = db.clone();
+                    scope.spawn(move || {
+                        for _ in 0..50 {
+                            for i in 0..100u64 {
+                                let value = db
+                                    .storage_ref(
+                                        Address::left_padding_from(&i.to_be_bytes()),
+                                        U256::from(1),
+                                    )
+                                    .unwrap();
+                                assert!(value == U256::from(i) || value == U256::from(i + 1000));
+                            }
+                        }
+                    })
+                })
+                .collect();
+            let reverts = db.apply_updates_parallel(&changes, header(1));
+            for reader in readers {
+                reader.join().unwrap();
+            }
+            reverts
+        });
+
+        assert_eq!(reverts.len(), 100);
+        for i in 0..100u64 {
+            assert_eq!(
+                db.storage_ref(Address::left_padding_from(&i.to_be_bytes()), U256::from(1))
+                    .unwrap(),
+                U256::from(i + 1000)
+            );
+        }
+    }
+
+    #[test]
+    fn test_update_state_ignores_unknown_account() {
+        let db = db_with_accounts(1);
+
+        let reverts = db.apply_updates_parallel(&updates(2), header(1));
+
+        assert_eq!(reverts.len(), 1);
+        assert!(db
+            .basic_ref(Address::left_padding_from(&1u64.to_be_bytes()))
+            .is_err());
+    }
+
+    #[test]
+    fn test_storage_ref() {
+        let db = db_with_accounts(2);
+        let address = Address::left_padding_from(&1u64.to_be_bytes());
+
+        assert_eq!(
+            db.storage_ref(address, U256::from(1))
+                .unwrap(),
+            U256::from(1)
+        );
+        assert_eq!(
+            db.storage_ref(address, U256::from(2))
+                .unwrap(),
+            U256::ZERO
+        );
+        assert!(db
+            .storage_ref(Address::with_last_byte(9), U256::from(1))
+            .is_err());
+    }
+}
@@ -0,0 +1,285 @@
+//! Resolution of conflicting account updates from concurrent extractor streams.
+//!
+//! When several extractors track the same contract, each of their block changes carries an update
+//! for it. Applied one after the other, the second update silently overwrites whatever the first
+//! one set. [`resolve_conflicts`] combines the changes into one update per account according to a
+//! [`ConflictResolutionStrategy`].
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+
+use alloy_primitives::Address;
+use thiserror::Error;
+use tracing::warn;
+
+use crate::evm::tycho_models::{AccountUpdate, BlockAccountChanges, ChangeType};
+
+/// How to combine updates of the same account coming from different changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictResolutionStrategy {
+    /// Within a block, the update of the later changes replaces the earlier one completely. Every
+    /// replacement is logged as a [`ConflictEvent`]. Updates from different blocks are merged.
+    LastWins,
+    /// Updates are merged, with the later value winning for every slot written by both.
+    #[default]
+    MergeSlots,
+    /// Two updates of the same account within the same block are rejected. Updates from
+    /// different blocks are merged.
+    FailOnConflict,
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ConflictError {
+    #[error("Account {address} is updated by both {first} and {second} in block {block}")]
+    DuplicatePoolUpdate { address: Address, block: u64, first: String, second: String },
+}
+
+/// An update discarded in favour of a later one, see [`ConflictResolutionStrategy::LastWins`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictEvent {
+    pub address: Address,
+    pub block: u64,
+    /// Extractor of the discarded update.
+    pub overwritten: String,
+    /// Extractor of the update that was kept.
+    pub winner: String,
+    /// Slots of the discarded update the kept update doesn't set.
+    pub lost_slots: usize,
+}
+
+/// Combines `changes`, given in the order they were received, into one update per account.
+///
+/// The result is ordered by address.
+pub fn resolve_conflicts(
+    changes: Vec<BlockAccountChanges>,
+    strategy: ConflictResolutionStrategy,
+) -> Result<Vec<AccountUpdate>, ConflictError> {
+    // The extractor and block of the latest update of each account.
+    let mut origins: HashMap<Address, (String, u64)> = HashMap::new();
+    let mut resolved: HashMap<Address, AccountUpdate> = HashMap::new();
+    // Accounts whose resolved creation replaces an account that existed before the changes.
+    let mut recreated = HashSet::new();
+
+    for change in changes {
+        let extractor = change.extractor().to_string();
+        let block = change.block.number;
+        for (address, update) in change.account_updates {
+            let (older, (older_extractor, older_block)) = match resolved.entry(address) {
+                Entry::Vacant(entry) => {
+                    entry.insert(update);
+                    origins.insert(address, (extractor.clone(), block));
+                    continue;
+                }
+                Entry::Occupied(entry) => (
+                    entry.remove(),
+                    origins
+                        .remove(&address)
+                        .expect("Every resolved update has an origin"),
+                ),
+            };
+            let combined = match strategy {
+                ConflictResolutionStrategy::LastWins if older_block == block => {
+                    let event = ConflictEvent {
+                        address,
+                        block,
+                        lost_slots: older
+                            .slots
+                            .keys()
+                            .filter(|slot| !update.slots.contains_key(slot))
+                            .count(),
+                        overwritten: older_extractor,
+                        winner: extractor.clone(),
+                    };
+                    warn!(?event, "Conflicting account update replaced");
+                    if update.change != ChangeType::Creation {
+                        recreated.remove(&address);
+                    }
+                    Some(update)
+                }
+                ConflictResolutionStrategy::FailOnConflict if older_block == block => {
+                    return Err(ConflictError::DuplicatePoolUpdate {
+                        address,
+                        block,
+                        first: older_extractor,
+                        second: extractor,
+                    });
+                }
+                ConflictResolutionStrategy::LastWins |
+                ConflictResolutionStrategy::MergeSlots |
+                ConflictResolutionStrategy::FailOnConflict => {
+                    older.merge_tracking_recreations(update, &mut recreated)
+                }
+            };
+            if let Some(combined) = combined {
+                resolved.insert(address, combined);
+                origins.insert(address, (extractor.clone(), block));
+            }
+        }
+    }
+
+    let mut updates: Vec<_> = resolved.into_values().collect();
+    updates.sort_by_key(|update| update.address);
+    Ok(updates)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use alloy_primitives::U256;
+
+    use super::*;
+    use crate::evm::tycho_models::{Block, Chain};
+
+    const POOL: Address = Address::new([0x11; 20]);
+
+    fn changes(extractor: &str, block: u64, slots: &[(u64, u64)]) -> BlockAccountChanges {
+        let slots = slots
+            .iter()
+            .map(|(slot, value)| (U256::from(*slot), U256::from(*value)))
+            .collect();
+        BlockAccountChanges::new(
+            extractor.to_string(),
+            Chain::Ethereum,
+            Block { number: block, ..Default::default() },
+            HashMap::from([(
+                POOL,
+                AccountUpdate::new(POOL, Chain::Ethereum, slots, None, None, ChangeType::Update),
+            )]),
+            HashMap::new(),
+        )
+    }
+
+    fn slots(updates: &[AccountUpdate]) -> HashMap<U256, U256> {
+        assert_eq!(updates.len(), 1);
+        updates[0].slots.clone()
+    }
+
+    fn expected(slots: &[(u64, u64)]) -> HashMap<U256, U256> {
+        slots
+            .iter()
+            .map(|(slot, value)| (U256::from(*slot), U256::from(*value)))
+            .collect()
+    }
+
+    #[test]
+    fn test_last_wins_keeps_latest_update() {
+        let res = resolve_conflicts(
+            vec![changes("vm:a", 1, &[(0, 1), (1, 1)]), changes("vm:b", 1, &[(0, 2)])],
+            ConflictResolutionStrategy::LastWins,
+        )
+        .unwrap();
+
+        assert_eq!(slots(&res), expected(&[(0, 2)]));
+    }
+
+    #[test]
+    fn test_last_wins_merges_different_blocks() {
+        let res = resolve_conflicts(
+            vec![changes("vm:a", 1, &[(0, 1), (1, 1)]), changes("vm:b", 2, &[(0, 2)])],
+            ConflictResolutionStrategy::LastWins,
+        )
+        .unwrap();
+
+        assert_eq!(slots(&res), expected(&[(0, 2), (1, 1)]));
+    }
+
+    #[test]
+    fn test_deleting_recreated_account_is_kept() {
+        let change = |block, change| {
+            let mut changes = changes("vm:a", block, &[]);
+            changes
+                .account_updates
+                .get_mut(&POOL)
+                .unwrap()
+                .change = change;
+            changes
+        };
+
+        let res = resolve_conflicts(
+            vec![
+                change(1, ChangeType::Deletion),
+                change(2, ChangeType::Creation),
+                change(3, ChangeType::Deletion),
+            ],
+            ConflictResolutionStrategy::MergeSlots,
+        )
+        .unwrap();
+
+        assert_eq!(
+            res.iter()
+                .map(|update| update.change)
+                .collect::<Vec<_>>(),
+            vec![ChangeType::Deletion]
+        );
+    }
+
+    #[test]
+    fn test_merge_slots_keeps_union() {
+        let res = resolve_conflicts(
+            vec![changes("vm:a", 1, &[(0, 1), (1, 1)]), changes("vm:b", 1, &[(0, 2), (2, 2)])],
+            ConflictResolutionStrategy::MergeSlots,
+        )
+        .unwrap();
+
+        assert_eq!(slots(&res), expected(&[(0, 2), (1, 1), (2, 2)]));
+    }
+
+    #[test]
+    fn test_fail_on_conflict_in_same_block() {
+        let res = resolve_conflicts(
+            vec![changes("vm:a", 1, &[(0, 1)]), changes("vm:b", 1, &[(0, 2)])],
+            ConflictResolutionStrategy::FailOnConflict,
+        );
+
+        assert_eq!(
+            res,
+            Err(ConflictError::DuplicatePoolUpdate {
+                address: POOL,
+                block: 1,
+                first: "vm:a".to_string(),
+                second: "vm:b".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_fail_on_conflict_merges_different_blocks() {
+        let res = resolve_conflicts(
+            vec![changes("vm:a", 1, &[(0, 1), (1, 1)]), changes("vm:a", 2, &[(0, 2)])],
+            ConflictResolutionStrategy::FailOnConflict,
+        )
+        .unwrap();
+
+        assert_eq!(slots(&res), expected(&[(0, 2), (1, 1)]));
+    }
+
+    #[test]
+    fn test_no_conflict_is_unaffected_by_strategy() {
+        let other = Address::repeat_byte(0x22);
+        let mut second = changes("vm:b", 1, &[(0, 2)]);
+        let mut update = second
+            .account_updates
+            .remove(&POOL)
+            .unwrap();
+        update.address = other;
+        second
+            .account_updates
+            .insert(other, update);
+
+        for strategy in [
+            ConflictResolutionStrategy::LastWins,
+            ConflictResolutionStrategy::MergeSlots,
+            ConflictResolutionStrategy::FailOnConflict,
+        ] {
+            let res =
+                resolve_conflicts(vec![changes("vm:a", 1, &[(0, 1)]), second.clone()], strategy)
+                    .unwrap();
+
+            assert_eq!(
+                res.iter()
+                    .map(|u| u.address)
+                    .collect::<Vec<_>>(),
+                vec![POOL, other]
+            );
+        }
+    }
+}
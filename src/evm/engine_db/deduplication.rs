@@ -0,0 +1,194 @@
+//! Protection against applying the same account update twice.
+//!
+//! Account updates overwrite slots, so applying a duplicate delivered after newer updates, e.g. a
+//! websocket message received twice, reverts those newer changes. [`DeduplicatingApplier`]
+//! remembers the fingerprints of recently applied updates and skips repeats.
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Mutex,
+};
+
+use tracing::trace;
+
+use crate::evm::{
+    engine_db::{simulation_db::BlockHeader, tycho_db::PreCachedDB},
+    tycho_models::AccountUpdate,
+};
+
+/// Number of fingerprints remembered; older ones are forgotten first.
+pub const DEDUPLICATION_CAPACITY: usize = 1024;
+
+#[derive(Debug, Default)]
+struct RecentFingerprints {
+    seen: HashSet<[u8; 32]>,
+    order: VecDeque<[u8; 32]>,
+}
+
+impl RecentFingerprints {
+    /// Records `fingerprint`, returning whether it was new.
+    fn insert(&mut self, fingerprint: [u8; 32]) -> bool {
+        if !self.seen.insert(fingerprint) {
+            return false;
+        }
+        self.order.push_back(fingerprint);
+        if self.order.len() > DEDUPLICATION_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+/// Applies account updates to a [`PreCachedDB`], skipping updates it applied recently.
+#[derive(Debug)]
+pub struct DeduplicatingApplier {
+    db: PreCachedDB,
+    recent: Mutex<RecentFingerprints>,
+}
+
+impl DeduplicatingApplier {
+    pub fn new(db: PreCachedDB) -> Self {
+        Self { db, recent: Mutex::new(RecentFingerprints::default()) }
+    }
+
+    pub fn db(&self) -> &PreCachedDB {
+        &self.db
+    }
+
+    /// Applies `update` at `block`, unless the same update was already applied at that block.
+    ///
+    /// Returns whether the update was applied.
+    pub fn apply(&self, update: &AccountUpdate, block: BlockHeader) -> bool {
+        let fingerprint = update.fingerprint(block.number);
+        if !self
+            .recent
+            .lock()
+            .unwrap()
+            .insert(fingerprint)
+        {
+            trace!(
+                address = %update.address,
+                block = block.number,
+                "Skipping duplicate account update"
+            );
+            return false;
+        }
+        self.db
+            .update(vec![update.clone()], Some(block));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use alloy_primitives::{Address, U256};
+    use revm::DatabaseRef;
+
+    use super::*;
+    use crate::evm::tycho_models::{Chain, ChangeType};
+
+    const POOL: Address = Address::new([0x11; 20]);
+
+    fn update(slots: &[(u64, u64)]) -> AccountUpdate {
+        let slots = slots
+            .iter()
+            .map(|(slot, value)| (U256::from(*slot), U256::from(*value)))
+            .collect();
+        AccountUpdate::new(POOL, Chain::Ethereum, slots, None, None, ChangeType::Update)
+    }
+
+    fn block(number: u64) -> BlockHeader {
+        BlockHeader { number, ..Default::default() }
+    }
+
+    fn applier() -> DeduplicatingApplier {
+        let db = PreCachedDB::new().unwrap();
+        db.update(
+            vec![AccountUpdate::new(
+                POOL,
+                Chain::Ethereum,
+                HashMap::new(),
+                Some(U256::ZERO),
+                Some(vec![]),
+                ChangeType::Creation,
+            )],
+            Some(block(1)),
+        );
+        DeduplicatingApplier::new(db)
+    }
+
+    fn slot(applier: &DeduplicatingApplier, slot: u64) -> U256 {
+        applier
+            .db()
+            .storage_ref(POOL, U256::from(slot))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_duplicate_update_is_skipped() {
+        let once = applier();
+        let twice = applier();
+        let update = update(&[(0, 5), (1, 7)]);
+
+        assert!(once.apply(&update, block(2)));
+        assert!(twice.apply(&update, block(2)));
+        assert!(!twice.apply(&update, block(2)));
+
+        assert_eq!((slot(&twice, 0), slot(&twice, 1)), (slot(&once, 0), slot(&once, 1)));
+    }
+
+    #[test]
+    fn test_replayed_update_does_not_revert_newer_update() {
+        let applier = applier();
+        let older = update(&[(0, 5)]);
+
+        applier.apply(&older, block(2));
+        applier.apply(&update(&[(0, 6)]), block(3));
+        let applied = applier.apply(&older, block(2));
+
+        assert!(!applied);
+        assert_eq!(slot(&applier, 0), U256::from(6u64));
+    }
+
+    #[test]
+    fn test_same_update_at_another_block_is_applied() {
+        let applier = applier();
+        let update = update(&[(0, 5)]);
+
+        assert!(applier.apply(&update, block(2)));
+        assert!(applier.apply(&update, block(3)));
+    }
+
+    #[test]
+    fn test_fingerprint_ignores_slot_order() {
+        let a = update(&[(0, 5), (1, 7), (2, 9)]);
+        let mut b = update(&[]);
+        for (slot, value) in [(2, 9), (0, 5), (1, 7)] {
+            b.slots
+                .insert(U256::from(slot), U256::from(value));
+        }
+
+        assert_eq!(a.fingerprint(2), b.fingerprint(2));
+        assert_ne!(a.fingerprint(2), update(&[(0, 5), (1, 7), (2, 8)]).fingerprint(2));
+    }
+
+    #[test]
+    fn test_capacity_forgets_oldest_fingerprint() {
+        let fingerprint = |i: usize| {
+            let mut fingerprint = [0u8; 32];
+            fingerprint[..8].copy_from_slice(&(i as u64).to_be_bytes());
+            fingerprint
+        };
+        let mut recent = RecentFingerprints::default();
+        for i in 0..=DEDUPLICATION_CAPACITY {
+            assert!(recent.insert(fingerprint(i)));
+        }
+
+        assert_eq!(recent.order.len(), DEDUPLICATION_CAPACITY);
+        assert!(!recent.insert(fingerprint(DEDUPLICATION_CAPACITY)));
+        assert!(recent.insert(fingerprint(0)));
+    }
+}
@@ -1,8 +1,16 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, error::Error, fmt::Debug};
 
 use alloy_primitives::U256;
 use revm::{precompile::Address, primitives::AccountInfo, DatabaseRef};
 
+/// Errors of a database backing a `SimulationEngine`.
+///
+/// Database errors are kept as the source of the simulation error they cause, so they need to be
+/// convertible into a boxed error. Implemented for all such types, including `String`.
+pub trait EngineDatabaseError: Debug + Into<Box<dyn Error + Send + Sync>> {}
+
+impl<T: Debug + Into<Box<dyn Error + Send + Sync>>> EngineDatabaseError for T {}
+
 pub trait EngineDatabaseInterface: DatabaseRef + Send + Sync {
     type Error;
 
@@ -0,0 +1,422 @@
+//! Read-through cache of recent block headers.
+//!
+//! The EVM can only read the hashes of the 256 most recent blocks, so a [`HeaderCache`] keeps
+//! exactly that window. It is filled when the database's block advances and answers `BLOCKHASH`
+//! and basefee lookups without querying a node. A cache can be shared between databases through
+//! an `Arc`; headers already fetched by one of them are never fetched again.
+use std::{
+    collections::VecDeque,
+    mem,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use revm::primitives::B256;
+use tracing::debug;
+
+use super::simulation_db::BlockHeader;
+use crate::memory_budget::{BudgetHandle, MemoryAccounted, MemoryBudget};
+
+/// Number of headers kept, the range of blocks `BLOCKHASH` can access.
+pub const HEADER_WINDOW: u64 = 256;
+
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Default)]
+pub struct CachedHeader {
+    pub number: u64,
+    pub hash: B256,
+    pub parent_hash: B256,
+    /// EIP-1559 base fee, `None` for blocks before London.
+    pub basefee: Option<u64>,
+    pub timestamp: u64,
+}
+
+/// Something headers can be fetched from, usually a node.
+pub trait HeaderSource {
+    type Error;
+
+    /// Fetches the header of block `number`, `None` if the block doesn't exist.
+    fn fetch_header(&self, number: u64) -> Result<Option<CachedHeader>, Self::Error>;
+}
+
+#[derive(Debug, Default)]
+pub struct HeaderCache {
+    /// Headers in ascending block order, at most [`HEADER_WINDOW`].
+    headers: RwLock<VecDeque<CachedHeader>>,
+    misses: AtomicU64,
+    memory: BudgetHandle,
+}
+
+impl HeaderCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enforces `budget` whenever headers are added. The cache must be registered with the budget
+    /// as well to be asked to shed.
+    pub fn with_memory_budget(mut self, budget: &Arc<MemoryBudget>) -> Self {
+        self.memory = budget.handle();
+        self
+    }
+
+    /// Moves the window to end at `head`, fetching the headers not cached yet from `source`.
+    ///
+    /// Advancing by one block fetches a single header; the first call fetches the full window.
+    /// Cached headers of blocks after `head`, or a cached `head` with a different hash, are
+    /// discarded as reorged. The parent hashes of the fetched headers are checked against the
+    /// cached ones, refetching cached ancestors until the chains join again.
+    ///
+    /// Headers are fetched without holding the cache's lock, so lookups aren't blocked while
+    /// the window is filled.
+    pub fn advance<S: HeaderSource>(&self, source: &S, head: &BlockHeader) -> Result<(), S::Error> {
+        self.advance_window(source, head)?;
+        self.memory.enforce();
+        Ok(())
+    }
+
+    fn advance_window<S: HeaderSource>(
+        &self,
+        source: &S,
+        head: &BlockHeader,
+    ) -> Result<(), S::Error> {
+        let start = head
+            .number
+            .saturating_sub(HEADER_WINDOW - 1);
+        let resume = self
+            .headers
+            .read()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|header| {
+                header.number < head.number ||
+                    (header.number == head.number && header.hash == head.hash)
+            })
+            .map(|header| header.number + 1);
+        let first_missing = resume.map_or(start, |number| number.max(start));
+
+        let mut fetched = VecDeque::new();
+        for number in first_missing..=head.number {
+            if let Some(header) = source.fetch_header(number)? {
+                fetched.push_back(header);
+            }
+        }
+        // refetch cached ancestors until the fetched chain builds on a cached header
+        while let Some(&first) = fetched.front() {
+            if first.number <= start {
+                break;
+            }
+            match self.cached_header(first.number - 1) {
+                Some(parent) if parent.hash != first.parent_hash => {
+                    debug!(block = parent.number, "Discarding reorged header");
+                    match source.fetch_header(parent.number)? {
+                        Some(header) => fetched.push_front(header),
+                        None => break,
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        let first_fetched = fetched
+            .front()
+            .map_or(head.number + 1, |header| header.number);
+        let mut headers = self.headers.write().unwrap();
+        headers.retain(|header| header.number >= start && header.number < first_fetched);
+        headers.extend(fetched);
+        Ok(())
+    }
+
+    /// Returns the cached header of block `number`, counting a miss if it's not cached.
+    pub fn header(&self, number: u64) -> Option<CachedHeader> {
+        let header = self.cached_header(number);
+        if header.is_none() {
+            self.misses
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        header
+    }
+
+    fn cached_header(&self, number: u64) -> Option<CachedHeader> {
+        let headers = self.headers.read().unwrap();
+        headers
+            .binary_search_by_key(&number, |header| header.number)
+            .ok()
+            .map(|index| headers[index])
+    }
+
+    pub fn block_hash(&self, number: u64) -> Option<B256> {
+        self.header(number)
+            .map(|header| header.hash)
+    }
+
+    pub fn basefee(&self, number: u64) -> Option<u64> {
+        self.header(number)
+            .and_then(|header| header.basefee)
+    }
+
+    /// Number of lookups of blocks outside the cached window.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.headers.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl MemoryAccounted for HeaderCache {
+    fn approx_bytes(&self) -> usize {
+        self.len() * mem::size_of::<CachedHeader>()
+    }
+
+    /// Drops the oldest headers until `target` bytes are freed. Lookups of dropped blocks count
+    /// as misses until the window is filled again.
+    fn shed(&self, target: usize) -> usize {
+        let mut headers = self.headers.write().unwrap();
+        let dropped = target
+            .div_ceil(mem::size_of::<CachedHeader>())
+            .min(headers.len());
+        headers.drain(..dropped);
+        dropped * mem::size_of::<CachedHeader>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mockall::{mock, predicate::eq};
+
+    use super::*;
+
+    mock! {
+        Source {}
+        impl HeaderSource for Source {
+            type Error = String;
+            fn fetch_header(&self, number: u64) -> Result<Option<CachedHeader>, String>;
+        }
+    }
+
+    fn hash(number: u64) -> B256 {
+        B256::left_padding_from(&number.to_be_bytes())
+    }
+
+    fn cached(number: u64) -> CachedHeader {
+        CachedHeader {
+            number,
+            hash: hash(number),
+            parent_hash: hash(number.saturating_sub(1)),
+            basefee: Some(number * 10),
+            timestamp: number,
+        }
+    }
+
+    fn head(number: u64) -> BlockHeader {
+        BlockHeader { number, hash: hash(number), timestamp: number }
+    }
+
+    fn expect_fetch(source: &mut MockSource, number: u64) {
+        source
+            .expect_fetch_header()
+            .with(eq(number))
+            .times(1)
+            .returning(|number| Ok(Some(cached(number))));
+    }
+
+    fn filled_cache(head_number: u64) -> HeaderCache {
+        let cache = HeaderCache::new();
+        let mut source = MockSource::new();
+        source
+            .expect_fetch_header()
+            .returning(|number| Ok(Some(cached(number))));
+        cache
+            .advance(&source, &head(head_number))
+            .unwrap();
+        cache
+    }
+
+    #[test]
+    fn test_advance_prefetches_window() {
+        let cache = HeaderCache::new();
+        let mut source = MockSource::new();
+        for number in 1_000 - HEADER_WINDOW + 1..=1_000 {
+            expect_fetch(&mut source, number);
+        }
+
+        cache
+            .advance(&source, &head(1_000))
+            .unwrap();
+
+        assert_eq!(cache.len(), 256);
+        assert_eq!(cache.block_hash(1_000), Some(hash(1_000)));
+        assert_eq!(cache.basefee(745), Some(7_450));
+        assert_eq!(cache.misses(), 0);
+    }
+
+    #[test]
+    fn test_advance_fetches_one_header_per_block() {
+        let cache = filled_cache(1_000);
+        let mut source = MockSource::new();
+        expect_fetch(&mut source, 1_001);
+        expect_fetch(&mut source, 1_002);
+
+        cache
+            .advance(&source, &head(1_001))
+            .unwrap();
+        cache
+            .advance(&source, &head(1_001))
+            .unwrap();
+        cache
+            .advance(&source, &head(1_002))
+            .unwrap();
+
+        assert_eq!(cache.block_hash(1_002), Some(hash(1_002)));
+    }
+
+    #[test]
+    fn test_window_evicts_oldest_header() {
+        let cache = filled_cache(1_000);
+        let mut source = MockSource::new();
+        expect_fetch(&mut source, 1_001);
+
+        cache
+            .advance(&source, &head(1_001))
+            .unwrap();
+
+        assert_eq!(cache.len(), 256);
+        assert_eq!(cache.block_hash(745), None);
+        assert_eq!(cache.block_hash(746), Some(hash(746)));
+        assert_eq!(cache.misses(), 1);
+    }
+
+    #[test]
+    fn test_misses_outside_window_are_counted() {
+        let cache = filled_cache(1_000);
+
+        assert_eq!(cache.block_hash(1_001), None);
+        assert_eq!(cache.basefee(10), None);
+        assert_eq!(cache.block_hash(900), Some(hash(900)));
+
+        assert_eq!(cache.misses(), 2);
+    }
+
+    #[test]
+    fn test_shed_drops_oldest_headers() {
+        let cache = filled_cache(1_000);
+        let header_size = mem::size_of::<CachedHeader>();
+
+        let freed = cache.shed(header_size * 10 - 1);
+
+        assert_eq!(freed, header_size * 10);
+        assert_eq!(cache.approx_bytes(), header_size * 246);
+        assert_eq!(cache.block_hash(754), None);
+        assert_eq!(cache.block_hash(755), Some(hash(755)));
+    }
+
+    #[test]
+    fn test_reorged_head_is_refetched() {
+        let cache = filled_cache(1_000);
+        let mut source = MockSource::new();
+        let reorged = BlockHeader { number: 1_000, hash: B256::repeat_byte(1), timestamp: 1_000 };
+        source
+            .expect_fetch_header()
+            .with(eq(1_000))
+            .times(1)
+            .returning(|number| {
+                Ok(Some(CachedHeader { hash: B256::repeat_byte(1), ..cached(number) }))
+            });
+
+        cache
+            .advance(&source, &reorged)
+            .unwrap();
+
+        assert_eq!(cache.block_hash(1_000), Some(B256::repeat_byte(1)));
+        assert_eq!(cache.len(), 256);
+    }
+
+    #[test]
+    fn test_reorged_ancestors_are_refetched() {
+        let cache = filled_cache(1_000);
+        let mut source = MockSource::new();
+        let (reorged_999, reorged_1_000) = (B256::repeat_byte(1), B256::repeat_byte(2));
+        source
+            .expect_fetch_header()
+            .with(eq(1_001))
+            .times(1)
+            .returning(move |number| {
+                Ok(Some(CachedHeader { parent_hash: reorged_1_000, ..cached(number) }))
+            });
+        source
+            .expect_fetch_header()
+            .with(eq(1_000))
+            .times(1)
+            .returning(move |number| {
+                Ok(Some(CachedHeader {
+                    hash: reorged_1_000,
+                    parent_hash: reorged_999,
+                    ..cached(number)
+                }))
+            });
+        source
+            .expect_fetch_header()
+            .with(eq(999))
+            .times(1)
+            .returning(move |number| {
+                Ok(Some(CachedHeader { hash: reorged_999, ..cached(number) }))
+            });
+
+        cache
+            .advance(&source, &head(1_001))
+            .unwrap();
+
+        assert_eq!(cache.block_hash(1_001), Some(hash(1_001)));
+        assert_eq!(cache.block_hash(1_000), Some(reorged_1_000));
+        assert_eq!(cache.block_hash(999), Some(reorged_999));
+        assert_eq!(cache.block_hash(998), Some(hash(998)));
+        assert_eq!(cache.len(), 256);
+    }
+
+    /// Reads the cache it fills, which deadlocks if the cache is locked while fetching.
+    struct ReadingSource<'a>(&'a HeaderCache);
+
+    impl HeaderSource for ReadingSource<'_> {
+        type Error = String;
+
+        fn fetch_header(&self, number: u64) -> Result<Option<CachedHeader>, String> {
+            self.0.block_hash(number - 1);
+            Ok(Some(cached(number)))
+        }
+    }
+
+    #[test]
+    fn test_advance_fetches_without_lock() {
+        let cache = filled_cache(1_000);
+
+        cache
+            .advance(&ReadingSource(&cache), &head(1_002))
+            .unwrap();
+
+        assert_eq!(cache.block_hash(1_002), Some(hash(1_002)));
+    }
+
+    #[test]
+    fn test_shared_cache_fetches_once() {
+        let cache = std::sync::Arc::new(filled_cache(1_000));
+        let shared = cache.clone();
+        let mut source = MockSource::new();
+        expect_fetch(&mut source, 1_001);
+
+        cache
+            .advance(&source, &head(1_001))
+            .unwrap();
+        shared
+            .advance(&source, &head(1_001))
+            .unwrap();
+
+        assert_eq!(shared.block_hash(1_001), Some(hash(1_001)));
+    }
+}
@@ -10,7 +10,8 @@ use revm::{
 use crate::{
     evm::{
         engine_db::{
-            engine_db_interface::EngineDatabaseInterface, simulation_db::BlockHeader,
+            engine_db_interface::{EngineDatabaseError, EngineDatabaseInterface},
+            simulation_db::BlockHeader,
             tycho_db::PreCachedDB,
         },
         simulation::SimulationEngine,
@@ -19,8 +20,15 @@ use crate::{
     protocol::errors::SimulationError,
 };
 
+pub mod concurrent_db;
+pub mod conflict;
+pub mod deduplication;
+#[doc(hidden)]
 pub mod engine_db_interface;
+pub mod header_cache;
+pub mod network_policy;
 pub mod simulation_db;
+pub mod snapshot;
 pub mod tycho_db;
 
 lazy_static! {
@@ -40,7 +48,7 @@ pub fn create_engine<D: EngineDatabaseInterface + Clone + Debug>(
 ) -> Result<SimulationEngine<D>, SimulationError>
 where
     <D as EngineDatabaseInterface>::Error: Debug,
-    <D as DatabaseRef>::Error: Debug,
+    <D as DatabaseRef>::Error: EngineDatabaseError,
 {
     let engine = SimulationEngine::new(db.clone(), trace);
 
@@ -93,6 +101,7 @@ pub async fn update_engine(
                 balance: Some(vm_storage_values.native_balance),
                 code: Some(vm_storage_values.code.clone()),
                 change: ChangeType::Creation,
+                deleted_slots: Vec::new(),
             });
         }
     }
@@ -0,0 +1,250 @@
+//! Control over the node queries of a `SimulationDB`.
+//!
+//! A `SimulationDB` transparently fetches state it doesn't have from a node. While the initial
+//! state is loaded that is intended, but once the snapshot is complete every fetch means a
+//! contract is not tracked and the quote waits on a round trip to the node. A [`NetworkPolicy`]
+//! decides whether such fetches are allowed, and [`NetworkGuard`] records the accounts fetched
+//! in violation of it.
+use std::{
+    collections::BTreeSet,
+    fmt,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use revm::primitives::{Address, U256};
+use serde::Serialize;
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Default, Serialize)]
+pub enum NetworkPolicy {
+    /// Fetch missing state from the node.
+    #[default]
+    Allow,
+    /// Fetch missing state, but record the account and warn the first time it is fetched.
+    WarnOnce,
+    /// Fail instead of fetching missing state.
+    Deny,
+}
+
+/// Called with the account and slot (`None` for account info) of every fetch that violates the
+/// policy, e.g. to feed a metrics counter.
+pub type NetworkViolationHook = Arc<dyn Fn(Address, Option<U256>) + Send + Sync>;
+
+/// Network access status, as exposed to stats consumers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NetworkStats {
+    pub policy: NetworkPolicy,
+    pub snapshot_phase: bool,
+    /// Accounts fetched despite the policy.
+    pub violations: BTreeSet<Address>,
+}
+
+#[derive(Debug)]
+struct GuardState {
+    /// Policy while the snapshot is being loaded.
+    during_snapshot: NetworkPolicy,
+    /// Policy once the snapshot is complete.
+    after_snapshot: NetworkPolicy,
+    snapshot_phase: bool,
+}
+
+/// Enforces a [`NetworkPolicy`] for a database and all its clones.
+///
+/// The policy depends on the phase: the snapshot phase, while the initial state is loaded, and
+/// the live phase after it. A guard starts in the snapshot phase and switches once
+/// [`complete_snapshot_phase`](Self::complete_snapshot_phase) is called.
+#[derive(Default)]
+pub struct NetworkGuard {
+    state: RwLock<GuardState>,
+    violations: Mutex<BTreeSet<Address>>,
+    hook: RwLock<Option<NetworkViolationHook>>,
+}
+
+impl Default for GuardState {
+    fn default() -> Self {
+        Self {
+            during_snapshot: NetworkPolicy::Allow,
+            after_snapshot: NetworkPolicy::Allow,
+            snapshot_phase: true,
+        }
+    }
+}
+
+impl fmt::Debug for NetworkGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NetworkGuard")
+            .field("state", &self.state)
+            .field("violations", &self.violations)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A fetch rejected by the [`NetworkPolicy::Deny`] policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkDenied;
+
+impl NetworkGuard {
+    /// A guard applying `policy` in both phases.
+    pub fn new(policy: NetworkPolicy) -> Self {
+        Self::phased(policy, policy)
+    }
+
+    /// A guard applying `during_snapshot` until the snapshot phase completes and
+    /// `after_snapshot` afterwards.
+    pub fn phased(during_snapshot: NetworkPolicy, after_snapshot: NetworkPolicy) -> Self {
+        Self {
+            state: RwLock::new(GuardState {
+                during_snapshot,
+                after_snapshot,
+                snapshot_phase: true,
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// The policy of a quoting pipeline: fetches are allowed while the snapshot is loaded and
+    /// denied afterwards.
+    pub fn pipeline() -> Self {
+        Self::phased(NetworkPolicy::Allow, NetworkPolicy::Deny)
+    }
+
+    pub fn set_violation_hook(&self, hook: NetworkViolationHook) {
+        *self.hook.write().unwrap() = Some(hook);
+    }
+
+    /// The policy of the current phase.
+    pub fn policy(&self) -> NetworkPolicy {
+        let state = self.state.read().unwrap();
+        if state.snapshot_phase {
+            state.during_snapshot
+        } else {
+            state.after_snapshot
+        }
+    }
+
+    /// Overrides the policy of both phases.
+    pub fn set_policy(&self, policy: NetworkPolicy) {
+        let mut state = self.state.write().unwrap();
+        state.during_snapshot = policy;
+        state.after_snapshot = policy;
+    }
+
+    /// Overrides the policy of each phase, like [`Self::phased`].
+    pub fn set_phased_policy(&self, during_snapshot: NetworkPolicy, after_snapshot: NetworkPolicy) {
+        let mut state = self.state.write().unwrap();
+        state.during_snapshot = during_snapshot;
+        state.after_snapshot = after_snapshot;
+    }
+
+    pub fn is_snapshot_phase(&self) -> bool {
+        self.state
+            .read()
+            .unwrap()
+            .snapshot_phase
+    }
+
+    /// Switches to the live phase and its policy.
+    pub fn complete_snapshot_phase(&self) {
+        self.state
+            .write()
+            .unwrap()
+            .snapshot_phase = false;
+    }
+
+    /// Checks whether a fetch of `address` (and `slot`) may go to the node.
+    pub fn check(&self, address: Address, slot: Option<U256>) -> Result<(), NetworkDenied> {
+        match self.policy() {
+            NetworkPolicy::Allow => Ok(()),
+            NetworkPolicy::WarnOnce => {
+                if self
+                    .violations
+                    .lock()
+                    .unwrap()
+                    .insert(address)
+                {
+                    warn!(%address, ?slot, "Fetching untracked account from node");
+                }
+                if let Some(hook) = self.hook.read().unwrap().as_ref() {
+                    hook(address, slot);
+                }
+                Ok(())
+            }
+            NetworkPolicy::Deny => {
+                if let Some(hook) = self.hook.read().unwrap().as_ref() {
+                    hook(address, slot);
+                }
+                Err(NetworkDenied)
+            }
+        }
+    }
+
+    /// Accounts fetched under the [`NetworkPolicy::WarnOnce`] policy.
+    pub fn violations(&self) -> BTreeSet<Address> {
+        self.violations.lock().unwrap().clone()
+    }
+
+    pub fn stats(&self) -> NetworkStats {
+        NetworkStats {
+            policy: self.policy(),
+            snapshot_phase: self.is_snapshot_phase(),
+            violations: self.violations(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    const ACCOUNT: Address = Address::new([0x11; 20]);
+
+    #[test]
+    fn test_allow() {
+        let guard = NetworkGuard::new(NetworkPolicy::Allow);
+
+        assert_eq!(guard.check(ACCOUNT, None), Ok(()));
+        assert!(guard.violations().is_empty());
+    }
+
+    #[test]
+    fn test_warn_once_records_violations() {
+        let guard = NetworkGuard::new(NetworkPolicy::WarnOnce);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        guard.set_violation_hook(Arc::new(move |_, _| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }));
+
+        assert_eq!(guard.check(ACCOUNT, Some(U256::from(1u64))), Ok(()));
+        assert_eq!(guard.check(ACCOUNT, Some(U256::from(2u64))), Ok(()));
+
+        assert_eq!(guard.violations(), BTreeSet::from([ACCOUNT]));
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_deny() {
+        let guard = NetworkGuard::new(NetworkPolicy::Deny);
+
+        assert_eq!(guard.check(ACCOUNT, None), Err(NetworkDenied));
+    }
+
+    #[test]
+    fn test_pipeline_denies_after_snapshot_phase() {
+        let guard = NetworkGuard::pipeline();
+        assert_eq!(guard.policy(), NetworkPolicy::Allow);
+
+        guard.complete_snapshot_phase();
+
+        assert_eq!(
+            guard.stats(),
+            NetworkStats {
+                policy: NetworkPolicy::Deny,
+                snapshot_phase: false,
+                violations: BTreeSet::new()
+            }
+        );
+    }
+}
@@ -1,22 +1,64 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fmt::Debug,
-    sync::{Arc, RwLock},
+    io::{Read, Write},
+    sync::{Arc, Mutex, RwLock, Weak},
 };
 
-use alloy::providers::Provider;
+use alloy::{eips::BlockNumberOrTag, providers::Provider, transports::TransportError};
 use alloy_primitives::StorageValue;
 use revm::{
     db::DatabaseRef,
     interpreter::analysis::to_analysed,
     primitives::{AccountInfo, Address, Bytecode, B256, U256},
 };
-use tracing::{debug, info};
+use thiserror::Error;
+use tracing::{debug, info, warn};
 
 use super::{
     super::account_storage::{AccountStorage, StateUpdate},
     engine_db_interface::EngineDatabaseInterface,
+    header_cache::{CachedHeader, HeaderCache, HeaderSource},
+    network_policy::{NetworkGuard, NetworkPolicy},
+    snapshot::{read_snapshot, write_snapshot, write_snapshot_with, Compression, SnapshotError},
 };
+#[cfg(feature = "profiling")]
+use crate::profiling::{profile, ProfiledFn};
+use crate::{
+    evm::block_ordering::{BlockOrdering, BlockOrderingError, BlockOrderingPolicy},
+    memory_budget::{BudgetHandle, MemoryAccounted, MemoryBudget},
+};
+
+/// Approximate memory of a storage slot fetched from the node, its index and value.
+const FETCHED_SLOT_BYTES: usize = 2 * std::mem::size_of::<U256>();
+
+/// Errors of querying a node for state that is not cached in a `SimulationDB`.
+#[derive(Error, Debug)]
+pub enum SimulationDBError {
+    #[error("Failed to query account {address} at block {block:?}")]
+    Account {
+        address: Address,
+        block: Option<u64>,
+        #[source]
+        source: TransportError,
+    },
+    #[error("Failed to query slot {slot} of account {address} at block {block:?}")]
+    Storage {
+        address: Address,
+        slot: U256,
+        block: Option<u64>,
+        #[source]
+        source: TransportError,
+    },
+    #[error("Failed to query header of block {number}")]
+    Header {
+        number: u64,
+        #[source]
+        source: TransportError,
+    },
+    #[error("Network access is disabled, can't fetch account {address} (slot {slot:?})")]
+    NetworkDisabled { address: Address, slot: Option<U256> },
+}
 
 /// A wrapper over an actual SimulationDB that allows overriding specific storage slots
 pub struct OverriddenSimulationDB<'a, DB: DatabaseRef> {
@@ -25,6 +67,10 @@ pub struct OverriddenSimulationDB<'a, DB: DatabaseRef> {
     /// A mapping from account address to storage.
     /// Storage is a mapping from slot index to slot value.
     pub overrides: &'a HashMap<Address, HashMap<U256, U256>>,
+    /// A mapping from account address to its native balance.
+    pub balance_overrides: Option<&'a HashMap<Address, U256>>,
+    /// A mapping from account address to its nonce.
+    pub nonce_overrides: Option<&'a HashMap<Address, u64>>,
 }
 
 impl<'a, DB: DatabaseRef> OverriddenSimulationDB<'a, DB> {
@@ -39,7 +85,28 @@ impl<'a, DB: DatabaseRef> OverriddenSimulationDB<'a, DB> {
     ///
     /// A new instance of OverriddenSimulationDB.
     pub fn new(inner_db: &'a DB, overrides: &'a HashMap<Address, HashMap<U256, U256>>) -> Self {
-        OverriddenSimulationDB { inner_db, overrides }
+        OverriddenSimulationDB {
+            inner_db,
+            overrides,
+            balance_overrides: None,
+            nonce_overrides: None,
+        }
+    }
+
+    /// Overrides the native balances of the given accounts.
+    ///
+    /// Overridden accounts the inner database reports as nonexistent are treated as empty
+    /// accounts. Errors of the inner database are returned as they are.
+    pub fn with_balance_overrides(mut self, balance_overrides: &'a HashMap<Address, U256>) -> Self {
+        self.balance_overrides = Some(balance_overrides);
+        self
+    }
+
+    /// Overrides the nonces of the given accounts, like [`Self::with_balance_overrides`] does
+    /// for balances.
+    pub fn with_nonce_overrides(mut self, nonce_overrides: &'a HashMap<Address, u64>) -> Self {
+        self.nonce_overrides = Some(nonce_overrides);
+        self
     }
 }
 
@@ -47,7 +114,28 @@ impl<DB: DatabaseRef> DatabaseRef for OverriddenSimulationDB<'_, DB> {
     type Error = DB::Error;
 
     fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
-        self.inner_db.basic_ref(address)
+        let balance = self
+            .balance_overrides
+            .and_then(|balances| balances.get(&address));
+        let nonce = self
+            .nonce_overrides
+            .and_then(|nonces| nonces.get(&address));
+        if balance.is_none() && nonce.is_none() {
+            return self.inner_db.basic_ref(address);
+        }
+        let mut info = self
+            .inner_db
+            .basic_ref(address)?
+            .unwrap_or_default();
+        if let Some(balance) = balance {
+            debug!(%address, %balance, "Overriding balance of account");
+            info.balance = *balance;
+        }
+        if let Some(nonce) = nonce {
+            debug!(%address, nonce, "Overriding nonce of account");
+            info.nonce = *nonce;
+        }
+        Ok(Some(info))
     }
 
     fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
@@ -84,6 +172,36 @@ pub struct BlockHeader {
     pub timestamp: u64,
 }
 
+/// The changes needed to roll back the last applied block.
+#[derive(Clone, Debug)]
+struct RevertJournal {
+    /// The block before the last applied one.
+    parent: Option<BlockHeader>,
+    reverts: HashMap<Address, StateUpdate>,
+}
+
+/// Accounts read from a database, with the storage slots read of each.
+type AccessedState = BTreeMap<Address, BTreeSet<U256>>;
+
+/// Records the accounts and storage slots read from a [`SimulationDB`] while it is alive, see
+/// [`SimulationDB::begin_access_recording`].
+#[derive(Debug)]
+pub struct AccessRecordingGuard {
+    accessed: Arc<Mutex<AccessedState>>,
+}
+
+impl AccessRecordingGuard {
+    /// Stops the recording and returns everything read as an EIP-2930 access list: each accessed
+    /// address with the storage keys read from it, both in ascending order.
+    pub fn into_access_list(self) -> Vec<(Address, Vec<U256>)> {
+        let accessed = std::mem::take(&mut *self.accessed.lock().unwrap());
+        accessed
+            .into_iter()
+            .map(|(address, slots)| (address, slots.into_iter().collect()))
+            .collect()
+    }
+}
+
 /// A wrapper over an Alloy Provider with local storage cache and overrides.
 #[derive(Clone, Debug)]
 pub struct SimulationDB<P: Provider + Debug> {
@@ -93,8 +211,25 @@ pub struct SimulationDB<P: Provider + Debug> {
     account_storage: Arc<RwLock<AccountStorage>>,
     /// Current block
     block: Option<BlockHeader>,
+    /// Accounts that weren't cached and were fetched from the node at the current block. They
+    /// are dropped from the cache whenever the block changes, so they are fetched again at the
+    /// new block instead of serving stale state.
+    missed_accounts: Arc<RwLock<HashSet<Address>>>,
     /// Tokio runtime to execute async code
     pub runtime: Option<Arc<tokio::runtime::Runtime>>,
+    /// Active access recordings. A recording ends when its guard is dropped.
+    access_recordings: Arc<RwLock<Vec<Weak<Mutex<AccessedState>>>>>,
+    /// Recent block headers, serving block hash and basefee lookups
+    header_cache: Option<Arc<HeaderCache>>,
+    /// Decides whether missing state may be fetched from the node
+    network: Arc<NetworkGuard>,
+    /// Decides how blocks that don't follow the current block are applied, `None` applies every
+    /// block on top of the current state
+    block_ordering: Option<BlockOrderingPolicy>,
+    /// Rolls back the last block applied with `update_state` if a block ordering policy is set
+    revert_journal: Option<RevertJournal>,
+    /// Enforced whenever storage is fetched from the node
+    memory: BudgetHandle,
 }
 
 impl<P: Provider + Debug + 'static> SimulationDB<P> {
@@ -107,19 +242,166 @@ impl<P: Provider + Debug + 'static> SimulationDB<P> {
             client,
             account_storage: Arc::new(RwLock::new(AccountStorage::new())),
             block,
+            missed_accounts: Arc::new(RwLock::new(HashSet::new())),
             runtime,
+            access_recordings: Arc::new(RwLock::new(Vec::new())),
+            header_cache: None,
+            network: Arc::new(NetworkGuard::default()),
+            block_ordering: None,
+            revert_journal: None,
+            memory: BudgetHandle::default(),
+        }
+    }
+
+    /// Enforces `budget` whenever storage is fetched from the node. The database must be
+    /// registered with the budget as well, usually as [`ShedPriority::LazyAccounts`], to be asked
+    /// to shed.
+    ///
+    /// [`ShedPriority::LazyAccounts`]: crate::memory_budget::ShedPriority::LazyAccounts
+    pub fn with_memory_budget(mut self, budget: &Arc<MemoryBudget>) -> Self {
+        self.memory = budget.handle();
+        self
+    }
+
+    /// Checks the blocks passed to `update_state` against `policy`. Without a policy, every block
+    /// is applied on top of the current state. See [`BlockOrderingPolicy::for_chain`] for the
+    /// policy of a chain.
+    pub fn with_block_ordering(mut self, policy: BlockOrderingPolicy) -> Self {
+        self.block_ordering = Some(policy);
+        self
+    }
+
+    /// Restricts fetching missing state from the node according to `guard`. Without a guard,
+    /// fetching is always allowed.
+    ///
+    /// Use [`NetworkGuard::pipeline`] to deny fetches once the snapshot phase is complete.
+    pub fn with_network_guard(mut self, guard: Arc<NetworkGuard>) -> Self {
+        self.network = guard;
+        self
+    }
+
+    pub fn network_policy(&self) -> NetworkPolicy {
+        self.network.policy()
+    }
+
+    /// Accounts fetched from the node under the [`NetworkPolicy::WarnOnce`] policy.
+    pub fn network_violations(&self) -> BTreeSet<Address> {
+        self.network.violations()
+    }
+
+    /// Marks the initial state as loaded, switching to the network policy of the live phase.
+    ///
+    /// Called automatically when a snapshot is loaded.
+    pub fn complete_snapshot_phase(&self) {
+        if self.network.is_snapshot_phase() {
+            info!(policy = ?self.network.policy(), "Snapshot phase complete");
         }
+        self.network.complete_snapshot_phase();
+    }
+
+    /// Serves block hashes and basefees from `cache`, which is refreshed whenever the block
+    /// advances. The cache may be shared with other databases following the same chain.
+    pub fn with_header_cache(mut self, cache: Arc<HeaderCache>) -> Self {
+        self.header_cache = Some(cache);
+        self.refresh_header_cache();
+        self
     }
 
     /// Set the block that will be used when querying a node
     pub fn set_block(&mut self, block: Option<BlockHeader>) {
+        if block != self.block {
+            self.drop_missed_accounts();
+        }
         self.block = block;
+        self.revert_journal = None;
+        self.refresh_header_cache();
+    }
+
+    /// Basefee of the current block, if it is known to the header cache.
+    pub fn basefee(&self) -> Option<u64> {
+        let cache = self.header_cache.as_ref()?;
+        cache.basefee(self.block?.number)
+    }
+
+    fn refresh_header_cache(&self) {
+        if let (Some(cache), Some(block)) = (&self.header_cache, &self.block) {
+            if let Err(err) = cache.advance(self, block) {
+                warn!(block = block.number, %err, "Failed to refresh header cache");
+            }
+        }
+    }
+
+    /// Starts recording every account and storage slot read from this database, including its
+    /// clones, until the returned guard is dropped.
+    ///
+    /// Use [`AccessRecordingGuard::into_access_list`] after a simulation to build an EIP-2930
+    /// access list for the simulated transaction.
+    pub fn begin_access_recording(&self) -> AccessRecordingGuard {
+        let accessed = Arc::new(Mutex::new(AccessedState::new()));
+        let mut recordings = self.access_recordings.write().unwrap();
+        recordings.retain(|recording| recording.strong_count() > 0);
+        recordings.push(Arc::downgrade(&accessed));
+        AccessRecordingGuard { accessed }
+    }
+
+    fn record_access(&self, address: Address, slot: Option<U256>) {
+        for recording in self
+            .access_recordings
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(Weak::upgrade)
+        {
+            let mut accessed = recording.lock().unwrap();
+            let slots = accessed.entry(address).or_default();
+            if let Some(slot) = slot {
+                slots.insert(slot);
+            }
+        }
+    }
+
+    /// Writes the cached accounts and the current block as a binary snapshot.
+    ///
+    /// See [`snapshot`](super::snapshot) for the format.
+    pub fn write_snapshot(&self, writer: impl Write) -> Result<(), SnapshotError> {
+        let account_storage = self.account_storage.read().unwrap();
+        write_snapshot(&account_storage, self.block.as_ref(), writer)
+    }
+
+    /// Like [`write_snapshot`](Self::write_snapshot), compressing the snapshot with `compression`.
+    pub fn write_snapshot_with(
+        &self,
+        writer: impl Write,
+        compression: Compression,
+    ) -> Result<(), SnapshotError> {
+        let account_storage = self.account_storage.read().unwrap();
+        write_snapshot_with(&account_storage, self.block.as_ref(), writer, compression)
+    }
+
+    /// Replaces the cached accounts and the current block with the contents of a snapshot.
+    ///
+    /// The cache is left untouched if the snapshot can't be read.
+    pub fn load_snapshot(&mut self, reader: impl Read) -> Result<(), SnapshotError> {
+        let (account_storage, block) = read_snapshot(reader)?;
+        *self.account_storage.write().unwrap() = account_storage;
+        self.missed_accounts
+            .write()
+            .unwrap()
+            .clear();
+        self.block = block;
+        self.revert_journal = None;
+        self.complete_snapshot_phase();
+        Ok(())
     }
 
     /// Update the simulation state.
     ///
-    /// Updates the underlying smart contract storage. Any previously missed account,
-    /// which was queried and whose state now is in the account_storage will be cleared.
+    /// Applies the storage, balance and code changes of `updates` to the cached accounts and
+    /// advances to `block`. Updates of accounts that aren't cached are skipped, as their state is
+    /// fetched from the node at `block` once they are queried. Accounts fetched from the node at
+    /// the previous block are dropped from the cache first, so they are fetched again as well.
+    /// Applying the returned updates at the next block restores the state from before this
+    /// update.
     ///
     /// # Arguments
     ///
@@ -127,51 +409,80 @@ impl<P: Provider + Debug + 'static> SimulationDB<P> {
     /// * `block` - The newest block
     ///
     /// Returns a state update struct to revert this update.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `BlockOrderingError` if `block` is rejected by the block ordering policy set
+    /// with [`Self::with_block_ordering`], in which case the state is left untouched. A block
+    /// replacing the current one rolls back the last update before it is applied. Without a
+    /// policy, this never fails.
     pub fn update_state(
         &mut self,
         updates: &HashMap<Address, StateUpdate>,
         block: BlockHeader,
-    ) -> HashMap<Address, StateUpdate> {
+    ) -> Result<HashMap<Address, StateUpdate>, BlockOrderingError> {
+        #[cfg(feature = "profiling")]
+        let _profile = profile(ProfiledFn::UpdateState);
         info!("Received account state update.");
+        if let Some(policy) = self.block_ordering {
+            if policy.check(self.block.as_ref(), &block)? == BlockOrdering::ReplaceTip {
+                self.revert_tip();
+            }
+        }
+        let parent = self.block;
         let mut revert_updates = HashMap::new();
+        self.drop_missed_accounts();
         self.block = Some(block);
+        self.refresh_header_cache();
+        let mut account_storage = self.account_storage.write().unwrap();
         for (address, update_info) in updates.iter() {
-            let mut revert_entry = StateUpdate::default();
-            if let Some(current_account) = self
-                .account_storage
-                .read()
-                .unwrap()
-                .get_account_info(address)
-            {
-                revert_entry.balance = Some(current_account.balance);
-            }
-            if update_info.storage.is_some() {
-                let mut revert_storage = HashMap::default();
-                for index in update_info
-                    .storage
-                    .as_ref()
-                    .unwrap()
-                    .keys()
-                {
-                    if let Some(s) = self
-                        .account_storage
-                        .read()
-                        .unwrap()
-                        .get_permanent_storage(address, index)
-                    {
-                        revert_storage.insert(*index, s);
-                    }
-                }
-                revert_entry.storage = Some(revert_storage);
-            }
+            let revert_entry = account_storage
+                .apply_update(address, update_info)
+                .unwrap_or_default();
             revert_updates.insert(*address, revert_entry);
+        }
+        drop(account_storage);
+        if self.block_ordering.is_some() {
+            self.revert_journal = Some(RevertJournal { parent, reverts: revert_updates.clone() });
+        }
+        Ok(revert_updates)
+    }
 
-            self.account_storage
-                .write()
-                .unwrap()
-                .update_account(address, update_info);
+    /// Removes the accounts fetched from the node at the current block from the cache and clears
+    /// the missed accounts.
+    fn drop_missed_accounts(&self) {
+        let missed = std::mem::take(&mut *self.missed_accounts.write().unwrap());
+        if missed.is_empty() {
+            return;
         }
-        revert_updates
+        debug!(accounts = missed.len(), "Dropping accounts fetched at the previous block");
+        let mut account_storage = self.account_storage.write().unwrap();
+        for address in &missed {
+            account_storage.remove_account(address);
+        }
+    }
+
+    /// Caches an account fetched from the node at the current block and records it as missed.
+    fn cache_fetched_account(&self, address: Address, account_info: AccountInfo) {
+        self.init_account(address, account_info, None, false);
+        self.missed_accounts
+            .write()
+            .unwrap()
+            .insert(address);
+    }
+
+    /// Rolls back the last update, restoring the block before it.
+    fn revert_tip(&mut self) {
+        let Some(journal) = self.revert_journal.take() else {
+            warn!(block = ?self.block, "No update to roll back, applying replacement on top");
+            return;
+        };
+        info!(tip = ?self.block, parent = ?journal.parent, "Rolling back replaced block");
+        let mut account_storage = self.account_storage.write().unwrap();
+        for (address, revert) in journal.reverts.iter() {
+            account_storage.update_account(address, revert);
+        }
+        self.block = journal.parent;
     }
 
     /// Query information about an Ethereum account.
@@ -190,6 +501,7 @@ impl<P: Provider + Debug + 'static> SimulationDB<P> {
         address: Address,
     ) -> Result<AccountInfo, <SimulationDB<P> as DatabaseRef>::Error> {
         debug!("Querying account info of {:x?} at block {:?}", address, self.block);
+        self.check_network(address, None)?;
 
         let (balance, nonce, code) = self.block_on(async {
             let mut balance_request = self.client.get_balance(address);
@@ -206,9 +518,18 @@ impl<P: Provider + Debug + 'static> SimulationDB<P> {
 
             tokio::join!(balance_request, nonce_request, code_request,)
         });
-        let code = to_analysed(Bytecode::new_raw(revm::primitives::Bytes::copy_from_slice(&code?)));
-
-        Ok(AccountInfo::new(balance?, nonce?, code.hash_slow(), code))
+        let block = self.block.map(|header| header.number);
+        let account_error = |source| SimulationDBError::Account { address, block, source };
+        let code = to_analysed(Bytecode::new_raw(revm::primitives::Bytes::copy_from_slice(
+            &code.map_err(account_error)?,
+        )));
+
+        Ok(AccountInfo::new(
+            balance.map_err(account_error)?,
+            nonce.map_err(account_error)?,
+            code.hash_slow(),
+            code,
+        ))
     }
 
     /// Queries a value from storage at the specified index for a given Ethereum account.
@@ -227,6 +548,7 @@ impl<P: Provider + Debug + 'static> SimulationDB<P> {
         address: Address,
         index: U256,
     ) -> Result<StorageValue, <SimulationDB<P> as DatabaseRef>::Error> {
+        self.check_network(address, Some(index))?;
         let storage = self.block_on(async {
             let mut request = self
                 .client
@@ -234,10 +556,21 @@ impl<P: Provider + Debug + 'static> SimulationDB<P> {
             if let Some(block) = &self.block {
                 request = request.number(block.number);
             }
-            request.await.unwrap()
+            request.await
         });
 
-        Ok(storage)
+        storage.map_err(|source| SimulationDBError::Storage {
+            address,
+            slot: index,
+            block: self.block.map(|header| header.number),
+            source,
+        })
+    }
+
+    fn check_network(&self, address: Address, slot: Option<U256>) -> Result<(), SimulationDBError> {
+        self.network
+            .check(address, slot)
+            .map_err(|_| SimulationDBError::NetworkDisabled { address, slot })
     }
 
     fn block_on<F: core::future::Future>(&self, f: F) -> F::Output {
@@ -251,6 +584,24 @@ impl<P: Provider + Debug + 'static> SimulationDB<P> {
     }
 }
 
+impl<P: Provider + Debug + 'static> HeaderSource for SimulationDB<P> {
+    type Error = TransportError;
+
+    fn fetch_header(&self, number: u64) -> Result<Option<CachedHeader>, Self::Error> {
+        let block = self.block_on(
+            self.client
+                .get_block_by_number(BlockNumberOrTag::Number(number), false),
+        )?;
+        Ok(block.map(|block| CachedHeader {
+            number,
+            hash: block.header.hash,
+            parent_hash: block.header.parent_hash,
+            basefee: block.header.base_fee_per_gas,
+            timestamp: block.header.timestamp,
+        }))
+    }
+}
+
 impl<P: Provider + Debug> EngineDatabaseInterface for SimulationDB<P>
 where
     P: Provider + Send + Sync + 'static,
@@ -298,11 +649,34 @@ where
     }
 }
 
+/// Storage fetched from the node is the lazily loaded part of the database: it is kept in the temp
+/// storage of the accounts and fetched again when it is read after being shed.
+impl<P> MemoryAccounted for SimulationDB<P>
+where
+    P: Provider + Debug + Send + Sync + 'static,
+{
+    fn approx_bytes(&self) -> usize {
+        self.account_storage
+            .read()
+            .unwrap()
+            .temp_slots() *
+            FETCHED_SLOT_BYTES
+    }
+
+    fn shed(&self, target: usize) -> usize {
+        self.account_storage
+            .write()
+            .unwrap()
+            .shed_temp_storage(target.div_ceil(FETCHED_SLOT_BYTES)) *
+            FETCHED_SLOT_BYTES
+    }
+}
+
 impl<P: Provider> DatabaseRef for SimulationDB<P>
 where
     P: Provider + Debug + Send + Sync + 'static,
 {
-    type Error = Box<dyn std::error::Error + Send + Sync>;
+    type Error = SimulationDBError;
 
     /// Retrieves basic information about an account.
     ///
@@ -335,6 +709,9 @@ where
     ///   from the contract, initializes the account in the storage with the retrieved information,
     ///   and returns a clone of the account information.
     fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        #[cfg(feature = "profiling")]
+        let _profile = profile(ProfiledFn::Basic);
+        self.record_access(address, None);
         if let Some(account) = self
             .account_storage
             .read()
@@ -344,7 +721,7 @@ where
             return Ok(Some(account.clone()));
         }
         let account_info = self.query_account_info(address)?;
-        self.init_account(address, account_info.clone(), None, false);
+        self.cache_fetched_account(address, account_info.clone());
         Ok(Some(account_info))
     }
 
@@ -387,7 +764,10 @@ where
     ///   value from a node, initializes the account locally with the retrieved information, and
     ///   returns the storage value.
     fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        #[cfg(feature = "profiling")]
+        let _profile = profile(ProfiledFn::Storage);
         debug!("Requested storage of account {:x?} slot {}", address, index);
+        self.record_access(address, Some(index));
         let is_mocked; // will be None if we don't have this account at all
         {
             let account_storage = self.account_storage.read().unwrap();
@@ -410,34 +790,52 @@ where
             }
             Some(false) => {
                 let storage_value = self.query_storage(address, index)?;
-                let mut account_storage = self.account_storage.write().unwrap();
-
-                account_storage.set_temp_storage(address, index, storage_value);
+                self.account_storage
+                    .write()
+                    .unwrap()
+                    .set_temp_storage(address, index, storage_value);
                 debug!(
                     "This is a non-mocked account for which we didn't have data. Fetched value: {}",
                     storage_value
                 );
+                self.memory.enforce();
                 Ok(storage_value)
             }
             None => {
                 let account_info = self.query_account_info(address)?;
                 let storage_value = self.query_storage(address, index)?;
-                self.init_account(address, account_info, None, false);
-                let mut account_storage = self.account_storage.write().unwrap();
-                account_storage.set_temp_storage(address, index, storage_value);
+                self.cache_fetched_account(address, account_info);
+                self.account_storage
+                    .write()
+                    .unwrap()
+                    .set_temp_storage(address, index, storage_value);
                 debug!("This is non-initialised account. Fetched value: {}", storage_value);
+                self.memory.enforce();
                 Ok(storage_value)
             }
         }
     }
 
-    /// If block header is set, returns the hash. Otherwise returns a zero hash
-    /// instead of querying a node.
-    fn block_hash_ref(&self, _number: u64) -> Result<B256, Self::Error> {
-        match &self.block {
-            Some(header) => Ok(header.hash),
-            None => Ok(B256::ZERO),
+    /// Returns the hash from the header cache if one is set, querying the node for blocks outside
+    /// of its window.
+    ///
+    /// Without a header cache, returns the hash of the current block header if it is set and a
+    /// zero hash otherwise, without querying a node.
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        let Some(cache) = &self.header_cache else {
+            return match &self.block {
+                Some(header) => Ok(header.hash),
+                None => Ok(B256::ZERO),
+            };
+        };
+        if let Some(hash) = cache.block_hash(number) {
+            return Ok(hash);
         }
+        debug!("Block {} is not in the header cache, querying node", number);
+        let header = self
+            .fetch_header(number)
+            .map_err(|source| SimulationDBError::Header { number, source })?;
+        Ok(header.map_or(B256::ZERO, |header| header.hash))
     }
 }
 
@@ -449,11 +847,17 @@ mod tests {
         providers::{ProviderBuilder, RootProvider},
         transports::BoxTransport,
     };
+    use alloy_primitives::hex;
+    use alloy_sol_types::SolValue;
     use dotenv::dotenv;
     use rstest::rstest;
     use tokio::runtime::Runtime;
 
     use super::*;
+    use crate::{
+        evm::simulation::{SimulationEngine, SimulationParameters},
+        memory_budget::ShedPriority,
+    };
 
     fn get_runtime() -> Option<Arc<Runtime>> {
         let runtime = tokio::runtime::Handle::try_current()
@@ -552,6 +956,62 @@ mod tests {
         assert_eq!(storage, U256::ZERO);
     }
 
+    #[rstest]
+    fn test_access_recording_v3_swap() {
+        let mut db = SimulationDB::new(get_client(), get_runtime(), None);
+        db.set_block(Some(BlockHeader {
+            number: 20308186,
+            hash: B256::from_str(
+                "0x61c51e3640b02ae58a03201be0271e84e02dac8a4826501995cbe4da24174b52",
+            )
+            .unwrap(),
+            timestamp: 234,
+        }));
+        let quoter = Address::from_str("0x61fFE014bA17989E743c5F6cB21bF9697530B21e").unwrap();
+        let pool = Address::from_str("0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640").unwrap();
+        let weth = Address::from_str("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2").unwrap();
+        let usdc = Address::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap();
+        // quoteExactInputSingle((address,address,uint256,uint24,uint160)) swapping 1 WETH
+        let mut data = hex!("c6a5026a").to_vec();
+        data.extend(
+            (weth, usdc, U256::from(10u64).pow(U256::from(18u64)), U256::from(500u64), U256::ZERO)
+                .abi_encode(),
+        );
+        let engine = SimulationEngine::new(db.clone(), false);
+
+        let recording = db.begin_access_recording();
+        engine
+            .simulate(&SimulationParameters {
+                caller: Address::ZERO,
+                to: quoter,
+                data,
+                value: U256::ZERO,
+                overrides: None,
+                balance_overrides: None,
+                nonce: None,
+                gas_limit: None,
+                block_number: 20308186,
+                timestamp: 234,
+            })
+            .unwrap();
+        let access_list = recording.into_access_list();
+
+        let (_, pool_slots) = access_list
+            .iter()
+            .find(|(address, _)| *address == pool)
+            .expect("Pool not accessed");
+        assert!(pool_slots.contains(&U256::ZERO));
+        assert!(access_list
+            .iter()
+            .any(|(address, _)| *address == quoter));
+
+        // The recording stopped with the guard
+        let recording = db.begin_access_recording();
+        drop(db.begin_access_recording());
+        db.basic_ref(pool).unwrap();
+        assert_eq!(recording.into_access_list(), vec![(pool, vec![])]);
+    }
+
     #[rstest]
     fn test_update_state() {
         let mut db = SimulationDB::new(get_client(), get_runtime(), None);
@@ -562,12 +1022,18 @@ mod tests {
         let new_storage_value_index = U256::from_limbs_slice(&[123]);
         new_storage.insert(new_storage_value_index, new_storage_value_index);
         let new_balance = U256::from_limbs_slice(&[500]);
-        let update = StateUpdate { storage: Some(new_storage), balance: Some(new_balance) };
+        let update = StateUpdate {
+            storage: Some(new_storage),
+            balance: Some(new_balance),
+            ..Default::default()
+        };
         let mut updates = HashMap::default();
         updates.insert(address, update);
         let new_block = BlockHeader { number: 1, hash: B256::default(), timestamp: 234 };
 
-        let reverse_update = db.update_state(&updates, new_block);
+        let reverse_update = db
+            .update_state(&updates, new_block)
+            .unwrap();
 
         assert_eq!(
             db.account_storage
@@ -605,6 +1071,56 @@ mod tests {
         );
     }
 
+    #[rstest]
+    #[case::strict(BlockOrderingPolicy::StrictIncreasing, false, 2, (2, 20))]
+    #[case::replace(BlockOrderingPolicy::AllowSingleStepReplace, true, 1, (3, 10))]
+    #[case::timestamp(BlockOrderingPolicy::TimestampMonotonic, true, 1, (3, 20))]
+    fn test_update_state_minus_one_replacement(
+        #[case] policy: BlockOrderingPolicy,
+        #[case] accepted: bool,
+        #[case] expected_block: u64,
+        #[case] expected_slots: (u64, u64),
+    ) {
+        let mut db =
+            SimulationDB::new(get_client(), get_runtime(), None).with_block_ordering(policy);
+        let address = Address::from_str("0xb4e16d0168e52d35cacd2c6185b44281ec28c9dc").unwrap();
+        db.init_account(address, AccountInfo::default(), None, false);
+        let (slot_a, slot_b) = (U256::from(1), U256::from(2));
+        let update = |slots: &[(U256, u64)]| {
+            let storage = slots
+                .iter()
+                .map(|(slot, value)| (*slot, U256::from(*value)))
+                .collect();
+            HashMap::from([(address, StateUpdate { storage: Some(storage), ..Default::default() })])
+        };
+        let header = |number, hash, timestamp| BlockHeader {
+            number,
+            hash: B256::repeat_byte(hash),
+            timestamp,
+        };
+
+        db.update_state(&update(&[(slot_a, 1), (slot_b, 10)]), header(1, 1, 10))
+            .unwrap();
+        db.update_state(&update(&[(slot_a, 2), (slot_b, 20)]), header(2, 2, 20))
+            .unwrap();
+        let replacement = db.update_state(&update(&[(slot_a, 3)]), header(1, 3, 22));
+
+        assert_eq!(replacement.is_ok(), accepted);
+        assert_eq!(db.block.unwrap().number, expected_block);
+        let storage = db.account_storage.read().unwrap();
+        assert_eq!(
+            (
+                storage
+                    .get_storage(&address, &slot_a)
+                    .unwrap(),
+                storage
+                    .get_storage(&address, &slot_b)
+                    .unwrap()
+            ),
+            (U256::from(expected_slots.0), U256::from(expected_slots.1))
+        );
+    }
+
     #[rstest]
     fn test_overridden_db() {
         let db = SimulationDB::new(get_client(), get_runtime(), None);
@@ -689,4 +1205,208 @@ mod tests {
             "Overridden slot of an overridden non-existent account should hold an overriden value."
         );
     }
+
+    #[rstest]
+    fn test_overridden_db_balances() {
+        let db = SimulationDB::new(get_client(), get_runtime(), None);
+        let address1 = Address::from_str("0000000000000000000000000000000000000001").unwrap();
+        let address2 = Address::from_str("0000000000000000000000000000000000000002").unwrap();
+        let original = AccountInfo { balance: U256::from(5), nonce: 7, ..Default::default() };
+        db.init_account(address1, original.clone(), None, false);
+        db.init_account(address2, original.clone(), None, false);
+
+        let overrides = HashMap::new();
+        let balances: HashMap<Address, U256> = [(address1, U256::from(1000))].into();
+        let overriden_db =
+            OverriddenSimulationDB::new(&db, &overrides).with_balance_overrides(&balances);
+
+        let info1 = overriden_db
+            .basic_ref(address1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(info1.balance, U256::from(1000));
+        assert_eq!(info1.nonce, 7, "Only the balance should be overridden.");
+        assert_eq!(
+            overriden_db
+                .basic_ref(address2)
+                .unwrap()
+                .unwrap(),
+            original
+        );
+    }
+
+    #[rstest]
+    fn test_overridden_db_nonces() {
+        let db = SimulationDB::new(get_client(), get_runtime(), None);
+        let address = Address::from_str("0000000000000000000000000000000000000001").unwrap();
+        let original = AccountInfo { balance: U256::from(5), nonce: 7, ..Default::default() };
+        db.init_account(address, original, None, false);
+
+        let overrides = HashMap::new();
+        let balances: HashMap<Address, U256> = [(address, U256::from(1000))].into();
+        let nonces: HashMap<Address, u64> = [(address, 9)].into();
+        let overriden_db = OverriddenSimulationDB::new(&db, &overrides)
+            .with_balance_overrides(&balances)
+            .with_nonce_overrides(&nonces);
+
+        let info = overriden_db
+            .basic_ref(address)
+            .unwrap()
+            .unwrap();
+        assert_eq!(info.balance, U256::from(1000));
+        assert_eq!(info.nonce, 9);
+    }
+
+    #[rstest]
+    fn test_overridden_db_balance_propagates_errors() {
+        let db = offline_db(NetworkGuard::new(NetworkPolicy::Deny));
+        let address = Address::repeat_byte(0x11);
+        let overrides = HashMap::new();
+        let balances: HashMap<Address, U256> = [(address, U256::from(1000))].into();
+        let overriden_db =
+            OverriddenSimulationDB::new(&db, &overrides).with_balance_overrides(&balances);
+
+        assert!(matches!(
+            overriden_db.basic_ref(address),
+            Err(SimulationDBError::NetworkDisabled { .. })
+        ));
+    }
+
+    /// A database whose node is unreachable, so every fetch that gets through fails.
+    fn offline_db(policy: NetworkGuard) -> SimulationDB<RootProvider<BoxTransport>> {
+        let runtime = get_runtime().unwrap();
+        let client = runtime.block_on(async {
+            ProviderBuilder::new()
+                .on_builtin("http://127.0.0.1:1")
+                .await
+                .unwrap()
+        });
+        SimulationDB::new(Arc::new(client), Some(runtime), None)
+            .with_network_guard(Arc::new(policy))
+    }
+
+    #[rstest]
+    fn test_network_policy_deny() {
+        let db = offline_db(NetworkGuard::new(NetworkPolicy::Deny));
+        let address = Address::repeat_byte(0x11);
+        db.init_account(Address::repeat_byte(0x22), AccountInfo::default(), None, false);
+
+        let account = db.basic_ref(address);
+        let storage = db.storage_ref(Address::repeat_byte(0x22), U256::from(3));
+
+        assert!(matches!(
+            account,
+            Err(SimulationDBError::NetworkDisabled { address: a, slot: None }) if a == address
+        ));
+        assert!(matches!(
+            storage,
+            Err(SimulationDBError::NetworkDisabled { slot: Some(slot), .. }) if slot == U256::from(3)
+        ));
+    }
+
+    #[rstest]
+    fn test_network_policy_warn_once() {
+        let db = offline_db(NetworkGuard::new(NetworkPolicy::WarnOnce));
+        let address = Address::repeat_byte(0x11);
+
+        let res = db.basic_ref(address);
+
+        // The fetch went through to the unreachable node
+        assert!(matches!(res, Err(SimulationDBError::Account { .. })));
+        assert_eq!(db.network_violations(), BTreeSet::from([address]));
+    }
+
+    #[rstest]
+    fn test_network_policy_allows_cached_state() {
+        let db = offline_db(NetworkGuard::new(NetworkPolicy::Deny));
+        let address = Address::repeat_byte(0x11);
+        db.init_account(
+            address,
+            AccountInfo::default(),
+            Some([(U256::ZERO, U256::from(1))].into()),
+            false,
+        );
+
+        assert_eq!(
+            db.storage_ref(address, U256::ZERO)
+                .unwrap(),
+            U256::from(1)
+        );
+        assert!(db.basic_ref(address).unwrap().is_some());
+    }
+
+    #[rstest]
+    fn test_loading_snapshot_completes_snapshot_phase() {
+        let mut db = offline_db(NetworkGuard::pipeline());
+        let mut snapshot = Vec::new();
+        db.write_snapshot(&mut snapshot)
+            .unwrap();
+        let address = Address::repeat_byte(0x11);
+        assert_eq!(db.network_policy(), NetworkPolicy::Allow);
+        assert!(matches!(db.basic_ref(address), Err(SimulationDBError::Account { .. })));
+
+        db.load_snapshot(snapshot.as_slice())
+            .unwrap();
+
+        assert_eq!(db.network_policy(), NetworkPolicy::Deny);
+        assert!(matches!(db.basic_ref(address), Err(SimulationDBError::NetworkDisabled { .. })));
+    }
+
+    #[rstest]
+    fn test_shed_fetched_storage() {
+        let db = offline_db(NetworkGuard::default());
+        let address = Address::repeat_byte(0x11);
+        db.init_account(
+            address,
+            AccountInfo::default(),
+            Some([(U256::ZERO, U256::from(1))].into()),
+            false,
+        );
+        for slot in 1..=4u64 {
+            db.account_storage
+                .write()
+                .unwrap()
+                .set_temp_storage(address, U256::from(slot), U256::from(slot));
+        }
+        let memory = MemoryBudget::new(2 * FETCHED_SLOT_BYTES);
+        memory.register("accounts", ShedPriority::LazyAccounts, Arc::new(db.clone()));
+
+        assert_eq!(memory.usage(), 4 * FETCHED_SLOT_BYTES);
+        assert_eq!(memory.enforce()[0].freed, 4 * FETCHED_SLOT_BYTES);
+
+        assert_eq!(memory.usage(), 0);
+        // state loaded into the database isn't shed
+        assert_eq!(
+            db.storage_ref(address, U256::ZERO)
+                .unwrap(),
+            U256::from(1)
+        );
+    }
+
+    #[rstest]
+    fn test_missed_accounts_are_fetched_again_at_next_block() {
+        let mut db = offline_db(NetworkGuard::default());
+        let missed = Address::repeat_byte(0x11);
+        let tracked = Address::repeat_byte(0x22);
+        db.init_account(tracked, AccountInfo::default(), None, false);
+        // as if `missed` was fetched from the node at block 1
+        db.set_block(Some(BlockHeader { number: 1, ..Default::default() }));
+        db.cache_fetched_account(missed, AccountInfo::default());
+        assert!(db.basic_ref(missed).unwrap().is_some());
+
+        db.update_state(&HashMap::new(), BlockHeader { number: 2, ..Default::default() })
+            .unwrap();
+
+        assert!(db
+            .missed_accounts
+            .read()
+            .unwrap()
+            .is_empty());
+        // the missed account is queried from the unreachable node again, the tracked one isn't
+        assert!(matches!(
+            db.basic_ref(missed),
+            Err(SimulationDBError::Account { address, block: Some(2), .. }) if address == missed
+        ));
+        assert!(db.basic_ref(tracked).unwrap().is_some());
+    }
 }
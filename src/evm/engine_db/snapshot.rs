@@ -0,0 +1,566 @@
+//! Compact binary snapshots of cached account state.
+//!
+//! Used to persist the cache of a [`SimulationDB`](super::simulation_db::SimulationDB) for warm
+//! restarts. Both the writer and the reader stream record by record, so neither ever holds the
+//! serialized form of the whole state in memory.
+//!
+//! # Format
+//!
+//! ```text
+//! header:   magic "TSNP" | version: u16 LE | flags: u16 LE
+//! section:  tag: u8 | chunk* | 0u32 | keccak256 of all chunk payloads (32 bytes)
+//! chunk:    length: u32 LE (non-zero) | payload
+//! end:      tag 0
+//! ```
+//!
+//! With the `ZSTD` flag (`0x0001`) set, everything after the header, from the first section to
+//! the end tag, is a single zstd frame.
+//!
+//! Known sections, in the order they are written:
+//!
+//! - `BLOCK`: one chunk with `number: u64 LE | hash: [u8; 32] | timestamp: u64 LE`.
+//! - `CODE`: one chunk per distinct bytecode: `hash: [u8; 32] | len: varint | code`.
+//! - `ACCOUNTS`: one chunk per account: `address: [u8; 20] | balance: [u8; 32] BE | nonce: varint |
+//!   code_hash: [u8; 32] | mocked: u8 | n_slots: varint | (slot: [u8; 32] BE | value: [u8; 32]
+//!   BE)*`. Accounts refer to their code by hash only.
+//!
+//! # Compatibility
+//!
+//! Readers reject snapshots with a higher version or with unknown flags, so snapshots compressed
+//! with [`Compression::Zstd`] can't be read by readers predating the flag. Sections with unknown
+//! tags are skipped, which allows adding new sections without a version bump. Only permanent
+//! storage is persisted; temporary storage is discarded.
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+};
+
+use alloy_primitives::Keccak256;
+use revm::primitives::{AccountInfo, Address, Bytecode, B256, KECCAK_EMPTY, U256};
+use thiserror::Error;
+
+use super::simulation_db::BlockHeader;
+use crate::evm::account_storage::AccountStorage;
+
+const MAGIC: &[u8; 4] = b"TSNP";
+pub const SNAPSHOT_VERSION: u16 = 1;
+const FLAG_ZSTD: u16 = 1;
+/// zstd level of [`Compression::Zstd`] unless configured otherwise, zstd's own default.
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+/// Upper bound for a single chunk, protecting against huge allocations on corrupted input.
+const MAX_CHUNK_LEN: u32 = 1 << 30;
+
+const TAG_END: u8 = 0;
+const TAG_BLOCK: u8 = 1;
+const TAG_CODE: u8 = 2;
+const TAG_ACCOUNTS: u8 = 3;
+
+/// Compression of a snapshot's sections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    /// zstd at the given level, from 1 (fastest) to 22 (smallest).
+    Zstd(i32),
+}
+
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Snapshot is truncated")]
+    Truncated,
+    #[error("Not a snapshot: invalid magic bytes")]
+    InvalidMagic,
+    #[error("Unsupported snapshot version {0}")]
+    UnsupportedVersion(u16),
+    #[error("Unsupported snapshot flags {0:#06x}")]
+    UnsupportedFlags(u16),
+    #[error("Checksum mismatch in section {0}")]
+    ChecksumMismatch(u8),
+    #[error("Malformed snapshot: {0}")]
+    Malformed(String),
+}
+
+impl SnapshotError {
+    fn from_io(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            SnapshotError::Truncated
+        } else {
+            SnapshotError::Io(err)
+        }
+    }
+}
+
+/// Writes `storage` and `block` as an uncompressed snapshot.
+pub fn write_snapshot(
+    storage: &AccountStorage,
+    block: Option<&BlockHeader>,
+    writer: impl Write,
+) -> Result<(), SnapshotError> {
+    write_snapshot_with(storage, block, writer, Compression::None)
+}
+
+/// Writes `storage` and `block` as a snapshot compressed with `compression`.
+///
+/// Compression streams as well: the compressed form is written as the records are encoded.
+pub fn write_snapshot_with(
+    storage: &AccountStorage,
+    block: Option<&BlockHeader>,
+    mut writer: impl Write,
+    compression: Compression,
+) -> Result<(), SnapshotError> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+    match compression {
+        Compression::None => {
+            writer.write_all(&0u16.to_le_bytes())?;
+            write_sections(storage, block, &mut writer)?;
+        }
+        Compression::Zstd(level) => {
+            writer.write_all(&FLAG_ZSTD.to_le_bytes())?;
+            let mut encoder = zstd::Encoder::new(&mut writer, level)?;
+            write_sections(storage, block, &mut encoder)?;
+            encoder.finish()?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes all sections and the end tag.
+fn write_sections(
+    storage: &AccountStorage,
+    block: Option<&BlockHeader>,
+    mut writer: impl Write,
+) -> Result<(), SnapshotError> {
+    if let Some(block) = block {
+        let mut section = SectionWriter::start(&mut writer, TAG_BLOCK)?;
+        section.record(|buf| {
+            buf.extend_from_slice(&block.number.to_le_bytes());
+            buf.extend_from_slice(block.hash.as_slice());
+            buf.extend_from_slice(&block.timestamp.to_le_bytes());
+        })?;
+        section.finish()?;
+    }
+
+    let mut codes: HashMap<B256, &Bytecode> = HashMap::new();
+    for (_, account) in storage.accounts() {
+        if let Some(code) = &account.info.code {
+            if account.info.code_hash != KECCAK_EMPTY {
+                codes
+                    .entry(account.info.code_hash)
+                    .or_insert(code);
+            }
+        }
+    }
+    let mut section = SectionWriter::start(&mut writer, TAG_CODE)?;
+    for (hash, code) in codes {
+        let code = code.original_bytes();
+        section.record(|buf| {
+            buf.extend_from_slice(hash.as_slice());
+            write_varint(buf, code.len() as u64);
+            buf.extend_from_slice(&code);
+        })?;
+    }
+    section.finish()?;
+
+    let mut section = SectionWriter::start(&mut writer, TAG_ACCOUNTS)?;
+    for (address, account) in storage.accounts() {
+        section.record(|buf| {
+            buf.extend_from_slice(address.as_slice());
+            buf.extend_from_slice(&account.info.balance.to_be_bytes::<32>());
+            write_varint(buf, account.info.nonce);
+            buf.extend_from_slice(account.info.code_hash.as_slice());
+            buf.push(account.mocked as u8);
+            write_varint(buf, account.permanent_storage.len() as u64);
+            for (slot, value) in &account.permanent_storage {
+                buf.extend_from_slice(&slot.to_be_bytes::<32>());
+                buf.extend_from_slice(&value.to_be_bytes::<32>());
+            }
+        })?;
+    }
+    section.finish()?;
+
+    writer.write_all(&[TAG_END])?;
+    Ok(())
+}
+
+/// Reads a snapshot written by [`write_snapshot`] or [`write_snapshot_with`], with any
+/// [`Compression`].
+pub fn read_snapshot(
+    mut reader: impl Read,
+) -> Result<(AccountStorage, Option<BlockHeader>), SnapshotError> {
+    let mut magic = [0u8; 4];
+    read_exact(&mut reader, &mut magic)?;
+    if &magic != MAGIC {
+        return Err(SnapshotError::InvalidMagic);
+    }
+    let version = u16::from_le_bytes(read_array(&mut reader)?);
+    if version > SNAPSHOT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+    match u16::from_le_bytes(read_array(&mut reader)?) {
+        0 => read_sections(reader),
+        FLAG_ZSTD => read_sections(zstd::Decoder::new(reader)?),
+        flags => Err(SnapshotError::UnsupportedFlags(flags)),
+    }
+}
+
+/// Reads all sections up to the end tag.
+fn read_sections(
+    mut reader: impl Read,
+) -> Result<(AccountStorage, Option<BlockHeader>), SnapshotError> {
+    let mut storage = AccountStorage::new();
+    let mut block = None;
+    let mut codes: HashMap<B256, Bytecode> = HashMap::new();
+    loop {
+        let [tag] = read_array(&mut reader)?;
+        if tag == TAG_END {
+            break;
+        }
+        let mut section = SectionReader::new(&mut reader, tag);
+        while let Some(chunk) = section.next_chunk()? {
+            let mut record = Record(&chunk);
+            match tag {
+                TAG_BLOCK => {
+                    block = Some(BlockHeader {
+                        number: u64::from_le_bytes(record.array()?),
+                        hash: B256::from(record.array::<32>()?),
+                        timestamp: u64::from_le_bytes(record.array()?),
+                    });
+                }
+                TAG_CODE => {
+                    let hash = B256::from(record.array::<32>()?);
+                    let len = record.varint()? as usize;
+                    let code = record.bytes(len)?;
+                    codes.insert(hash, Bytecode::new_raw(code.to_vec().into()));
+                }
+                TAG_ACCOUNTS => {
+                    let address = Address::from(record.array::<20>()?);
+                    let balance = U256::from_be_bytes(record.array::<32>()?);
+                    let nonce = record.varint()?;
+                    let code_hash = B256::from(record.array::<32>()?);
+                    let [mocked] = record.array()?;
+                    let n_slots = record.varint()?;
+                    let mut slots = HashMap::with_capacity(
+                        usize::try_from(n_slots)
+                            .unwrap_or_default()
+                            .min(chunk.len() / 64),
+                    );
+                    for _ in 0..n_slots {
+                        slots.insert(
+                            U256::from_be_bytes(record.array::<32>()?),
+                            U256::from_be_bytes(record.array::<32>()?),
+                        );
+                    }
+                    let code = codes.get(&code_hash).cloned();
+                    storage.init_account(
+                        address,
+                        AccountInfo { balance, nonce, code_hash, code },
+                        Some(slots),
+                        mocked != 0,
+                    );
+                }
+                // Sections added by later versions
+                _ => continue,
+            }
+            record.finish()?;
+        }
+    }
+    Ok((storage, block))
+}
+
+struct SectionWriter<'a, W: Write> {
+    writer: &'a mut W,
+    hasher: Keccak256,
+    buf: Vec<u8>,
+}
+
+impl<'a, W: Write> SectionWriter<'a, W> {
+    fn start(writer: &'a mut W, tag: u8) -> io::Result<Self> {
+        writer.write_all(&[tag])?;
+        Ok(Self { writer, hasher: Keccak256::new(), buf: Vec::new() })
+    }
+
+    fn record(&mut self, encode: impl FnOnce(&mut Vec<u8>)) -> io::Result<()> {
+        self.buf.clear();
+        encode(&mut self.buf);
+        let len = u32::try_from(self.buf.len())
+            .ok()
+            .filter(|len| *len <= MAX_CHUNK_LEN)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "record too large"))?;
+        self.writer
+            .write_all(&len.to_le_bytes())?;
+        self.writer.write_all(&self.buf)?;
+        self.hasher.update(&self.buf);
+        Ok(())
+    }
+
+    fn finish(self) -> io::Result<()> {
+        self.writer
+            .write_all(&0u32.to_le_bytes())?;
+        self.writer
+            .write_all(self.hasher.finalize().as_slice())
+    }
+}
+
+struct SectionReader<'a, R: Read> {
+    reader: &'a mut R,
+    tag: u8,
+    hasher: Option<Keccak256>,
+}
+
+impl<'a, R: Read> SectionReader<'a, R> {
+    fn new(reader: &'a mut R, tag: u8) -> Self {
+        Self { reader, tag, hasher: Some(Keccak256::new()) }
+    }
+
+    /// Returns the next chunk, or `None` once the section ended and its checksum was verified.
+    fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, SnapshotError> {
+        let Some(hasher) = self.hasher.as_mut() else {
+            return Ok(None);
+        };
+        let len = u32::from_le_bytes(read_array(self.reader)?);
+        if len == 0 {
+            let checksum: [u8; 32] = read_array(self.reader)?;
+            let expected = self
+                .hasher
+                .take()
+                .expect("hasher is present until the section ends")
+                .finalize();
+            if checksum != expected.0 {
+                return Err(SnapshotError::ChecksumMismatch(self.tag));
+            }
+            return Ok(None);
+        }
+        if len > MAX_CHUNK_LEN {
+            return Err(SnapshotError::Malformed(format!("chunk of {len} bytes")));
+        }
+        let mut chunk = vec![0u8; len as usize];
+        read_exact(self.reader, &mut chunk)?;
+        hasher.update(&chunk);
+        Ok(Some(chunk))
+    }
+}
+
+/// Decodes the fields of a single record.
+struct Record<'a>(&'a [u8]);
+
+impl<'a> Record<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.0.len() < len {
+            return Err(SnapshotError::Malformed("record ends prematurely".to_string()));
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], SnapshotError> {
+        Ok(self
+            .bytes(N)?
+            .try_into()
+            .expect("slice has the requested length"))
+    }
+
+    fn varint(&mut self) -> Result<u64, SnapshotError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let [byte] = self.array()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(SnapshotError::Malformed("varint exceeds 64 bits".to_string()))
+    }
+
+    fn finish(self) -> Result<(), SnapshotError> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(SnapshotError::Malformed(format!("{} trailing bytes in record", self.0.len())))
+        }
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn read_exact(reader: &mut impl Read, buf: &mut [u8]) -> Result<(), SnapshotError> {
+    reader
+        .read_exact(buf)
+        .map_err(SnapshotError::from_io)
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N], SnapshotError> {
+    let mut buf = [0u8; N];
+    read_exact(reader, &mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use revm::primitives::{keccak256, Bytes};
+    use rstest::rstest;
+
+    use super::*;
+
+    fn synthetic_storage(n_accounts: u64) -> AccountStorage {
+        let code = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x00, 0x60, 0x00, 0xf3]));
+        let code_hash = keccak256(code.original_bytes());
+        let mut storage = AccountStorage::new();
+        for i in 0..n_accounts {
+            let address = Address::left_padding_from(&i.to_be_bytes());
+            let slots = (0..4)
+                .map(|s| (U256::from(s), U256::from(i * s)))
+                .collect();
+            let info = if i % 2 == 0 {
+                AccountInfo {
+                    balance: U256::from(i),
+                    nonce: i,
+                    code_hash,
+                    code: Some(code.clone()),
+                }
+            } else {
+                AccountInfo { balance: U256::from(i), nonce: 1, ..Default::default() }
+            };
+            storage.init_account(address, info, Some(slots), i % 3 == 0);
+        }
+        storage
+    }
+
+    fn block() -> BlockHeader {
+        BlockHeader { number: 21_000_000, hash: B256::repeat_byte(0xab), timestamp: 1_700_000_000 }
+    }
+
+    fn snapshot(storage: &AccountStorage) -> Vec<u8> {
+        compressed_snapshot(storage, Compression::None)
+    }
+
+    fn compressed_snapshot(storage: &AccountStorage, compression: Compression) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_snapshot_with(storage, Some(&block()), &mut buf, compression).unwrap();
+        buf
+    }
+
+    #[rstest]
+    #[case::uncompressed(Compression::None)]
+    #[case::zstd(Compression::Zstd(DEFAULT_ZSTD_LEVEL))]
+    fn test_roundtrip(#[case] compression: Compression) {
+        let storage = synthetic_storage(100);
+
+        let (restored, restored_block) =
+            read_snapshot(compressed_snapshot(&storage, compression).as_slice()).unwrap();
+
+        assert_eq!(restored_block, Some(block()));
+        assert_eq!(restored.accounts().count(), 100);
+        for (address, account) in storage.accounts() {
+            let info = restored
+                .get_account_info(address)
+                .unwrap();
+            assert_eq!(info.balance, account.info.balance);
+            assert_eq!(info.nonce, account.info.nonce);
+            assert_eq!(info.code_hash, account.info.code_hash);
+            assert_eq!(
+                info.code
+                    .as_ref()
+                    .map(|c| c.original_bytes()),
+                account
+                    .info
+                    .code
+                    .as_ref()
+                    .map(|c| c.original_bytes())
+            );
+            assert_eq!(restored.is_mocked_account(address), Some(account.mocked));
+            for (slot, value) in &account.permanent_storage {
+                assert_eq!(restored.get_permanent_storage(address, slot), Some(*value));
+            }
+        }
+    }
+
+    /// The `snapshot` bench compares the formats on 50k accounts.
+    #[test]
+    fn test_smaller_than_json() {
+        let storage = synthetic_storage(5_000);
+        let as_json: Vec<_> = storage
+            .accounts()
+            .map(|(address, account)| (address, &account.info, &account.permanent_storage))
+            .collect();
+
+        let json = serde_json::to_vec(&as_json).unwrap();
+        let binary = snapshot(&storage);
+        let compressed = compressed_snapshot(&storage, Compression::Zstd(DEFAULT_ZSTD_LEVEL));
+
+        assert!(binary.len() * 2 < json.len());
+        assert!(compressed.len() < binary.len());
+    }
+
+    #[test]
+    fn test_compressed_snapshot_is_flagged() {
+        let mut buf = compressed_snapshot(&synthetic_storage(1), Compression::Zstd(1));
+        assert_eq!(buf[6..8], FLAG_ZSTD.to_le_bytes());
+
+        buf[6..8].copy_from_slice(&2u16.to_le_bytes());
+
+        assert!(matches!(read_snapshot(buf.as_slice()), Err(SnapshotError::UnsupportedFlags(2))));
+    }
+
+    #[test]
+    fn test_truncated_snapshot() {
+        let buf = snapshot(&synthetic_storage(10));
+
+        for len in [0, 3, 10, buf.len() / 2, buf.len() - 1] {
+            let res = read_snapshot(&buf[..len]);
+
+            assert!(matches!(res, Err(SnapshotError::Truncated)), "len {len}: {res:?}");
+        }
+    }
+
+    #[test]
+    fn test_bad_checksum() {
+        let mut buf = snapshot(&synthetic_storage(10));
+        // Flip a bit in the balance of the last account
+        let idx = buf.len() - 1 - 4 - 32 - 100;
+        buf[idx] ^= 1;
+
+        let res = read_snapshot(buf.as_slice());
+
+        assert!(matches!(res, Err(SnapshotError::ChecksumMismatch(TAG_ACCOUNTS))), "{res:?}");
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let mut buf = snapshot(&synthetic_storage(1));
+        buf[4..6].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+
+        assert!(matches!(
+            read_snapshot(buf.as_slice()),
+            Err(SnapshotError::UnsupportedVersion(v)) if v == SNAPSHOT_VERSION + 1
+        ));
+        assert!(matches!(read_snapshot(&b"JSON{}"[..]), Err(SnapshotError::InvalidMagic)));
+    }
+
+    #[test]
+    fn test_unknown_sections_are_skipped() {
+        let buf = snapshot(&synthetic_storage(3));
+        // Insert an unknown section right before the end marker
+        let mut extended = buf[..buf.len() - 1].to_vec();
+        let mut section = SectionWriter::start(&mut extended, 42).unwrap();
+        section
+            .record(|buf| buf.extend_from_slice(b"from the future"))
+            .unwrap();
+        section.finish().unwrap();
+        extended.push(TAG_END);
+
+        let (restored, _) = read_snapshot(extended.as_slice()).unwrap();
+
+        assert_eq!(restored.accounts().count(), 3);
+    }
+}
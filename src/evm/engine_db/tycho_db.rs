@@ -1,6 +1,7 @@
 use std::{
     collections::HashMap,
     sync::{Arc, RwLock},
+    time::Duration,
 };
 
 use alloy_primitives::{Address, B256, U256};
@@ -12,10 +13,21 @@ use thiserror::Error;
 use tracing::{debug, error, info, instrument, warn};
 
 use crate::evm::{
-    account_storage::{AccountStorage, StateUpdate},
-    engine_db::{engine_db_interface::EngineDatabaseInterface, simulation_db::BlockHeader},
-    tycho_models::{AccountUpdate, ChangeType},
+    account_storage::{Account, AccountStorage, StateUpdate},
+    engine_db::{
+        conflict::{resolve_conflicts, ConflictError, ConflictResolutionStrategy},
+        engine_db_interface::EngineDatabaseInterface,
+        network_policy::{NetworkGuard, NetworkPolicy},
+        simulation_db::BlockHeader,
+    },
+    http_client::{get_state_chunked, FetchBudget, TychoHttpClient},
+    tycho_models::{
+        AccountUpdate, BlockAccountChanges, ChangeType, ExtractorIdentity, StateRequestBody,
+        StateRequestParameters,
+    },
 };
+#[cfg(feature = "profiling")]
+use crate::profiling::{profile, ProfiledFn};
 
 /// Perform bytecode analysis on the code of an account.
 pub fn to_analysed(account_info: AccountInfo) -> AccountInfo {
@@ -36,7 +48,24 @@ pub enum TychoClientError {
     #[error("Unexpected HTTP client error: {0}")]
     HttpClient(String),
     #[error("Failed to parse response: {0}")]
-    ParseResponse(String),
+    ParseResponse(String, #[source] Option<serde_json::Error>),
+    #[error("Subscription to {extractor} was not confirmed within {waited:?}")]
+    SubscriptionTimeout { extractor: ExtractorIdentity, waited: Duration },
+    #[error("Connection closed: {0}")]
+    ConnectionClosed(String),
+    #[error("Failed to connect to {0}: {1}")]
+    Connect(String, String),
+    #[error("Connection to {address} was not established within {waited:?}")]
+    ConnectTimeout { address: String, waited: Duration },
+    #[error("Request was not answered within {waited:?}")]
+    RequestTimeout { waited: Duration },
+    #[error("Not subscribed to {0}")]
+    NotSubscribed(ExtractorIdentity),
+    #[error(
+        "Deadline exceeded with {remaining_chunks} chunks left, after fetching {fetched_accounts} \
+         accounts"
+    )]
+    DeadlineExceeded { fetched_accounts: usize, remaining_chunks: usize },
 }
 
 #[derive(Error, Debug)]
@@ -45,7 +74,7 @@ pub enum PreCachedDBError {
     MissingAccount(Address),
     #[error("Block needs to be set")]
     BlockNotSet(),
-    #[error("Tycho Client error: {0}")]
+    #[error("Tycho Client error")]
     TychoClientError(#[from] TychoClientError),
 }
 
@@ -57,6 +86,15 @@ pub struct PreCachedDBInner {
     block: Option<BlockHeader>,
 }
 
+/// The accounts of a [`PreCachedDB`] as they were before they were changed, and the block the
+/// database was at, to roll the changes back with [`PreCachedDB::restore`].
+#[derive(Clone, Debug, Default)]
+pub struct AccountsCheckpoint {
+    block: Option<BlockHeader>,
+    /// The recorded accounts, `None` if an account wasn't present
+    accounts: HashMap<Address, Option<Account>>,
+}
+
 #[derive(Clone, Debug)]
 pub struct PreCachedDB {
     /// Cached inner data
@@ -65,6 +103,9 @@ pub struct PreCachedDB {
     /// exclusive write access to the data and `Arc` for shared ownership of the lock across
     /// threads.
     pub inner: Arc<RwLock<PreCachedDBInner>>,
+    /// Records reads of accounts that aren't loaded. The database can't fetch them, so such
+    /// reads fail under every policy.
+    network: Arc<NetworkGuard>,
 }
 
 impl PreCachedDB {
@@ -75,11 +116,50 @@ impl PreCachedDB {
                 accounts: AccountStorage::new(),
                 block: None,
             })),
+            network: Arc::new(NetworkGuard::default()),
         })
     }
 
+    /// Checks reads of accounts that aren't loaded against `guard`, e.g. to warn about or count
+    /// untracked contracts once the snapshot is loaded. The guard may be shared with the
+    /// `SimulationDB`s following the same stream, so completing the snapshot phase here switches
+    /// them as well.
+    pub fn with_network_guard(mut self, guard: Arc<NetworkGuard>) -> Self {
+        self.network = guard;
+        self
+    }
+
+    /// The guard of this database and its clones. Its policy can be changed in place, e.g. with
+    /// [`NetworkGuard::set_phased_policy`] on the shared database of the protocol streams.
+    pub fn network_guard(&self) -> Arc<NetworkGuard> {
+        self.network.clone()
+    }
+
+    pub fn network_policy(&self) -> NetworkPolicy {
+        self.network.policy()
+    }
+
+    /// Marks the initial state as loaded, switching to the network policy of the live phase.
+    ///
+    /// Called automatically once a complete snapshot is loaded with
+    /// [`Self::load_state_chunked`], or decoded from a protocol stream.
+    pub fn complete_snapshot_phase(&self) {
+        if self.network.is_snapshot_phase() {
+            info!(policy = ?self.network.policy(), "Snapshot phase complete");
+        }
+        self.network.complete_snapshot_phase();
+    }
+
+    fn missing_account(&self, address: Address, slot: Option<U256>) -> PreCachedDBError {
+        // the read fails either way, the guard only warns about it or reports it to its hook
+        let _ = self.network.check(address, slot);
+        PreCachedDBError::MissingAccount(address)
+    }
+
     #[instrument(skip_all)]
     pub fn update(&self, account_updates: Vec<AccountUpdate>, block: Option<BlockHeader>) {
+        #[cfg(feature = "profiling")]
+        let _profile = profile(ProfiledFn::UpdateState);
         // Hold the write lock for the duration of the function so that no other thread can
         // write to the storage.
         let mut write_guard = self.inner.write().unwrap();
@@ -98,17 +178,35 @@ impl PreCachedDB {
                         &StateUpdate {
                             storage: Some(update.slots.clone()),
                             balance: update.balance,
+                            deleted_slots: update.deleted_slots.clone(),
+                            code: update.code.clone().map(|code| {
+                                let code = Bytecode::new_raw(Bytes::from(code));
+                                let code_hash = code.hash_slow();
+                                (Some(code), code_hash)
+                            }),
                         },
                     );
                 }
                 ChangeType::Deletion => {
                     info!(%update.address, "Deleting account");
 
-                    warn!(%update.address, "Deletion not implemented");
+                    if write_guard
+                        .accounts
+                        .remove_account(&update.address)
+                        .is_none()
+                    {
+                        warn!(%update.address, "Tried to delete an account that does not exist");
+                    }
                 }
                 ChangeType::Creation => {
                     info!(%update.address, "Creating account");
 
+                    // A created account starts from scratch, even if an account with the same
+                    // address was tracked before.
+                    write_guard
+                        .accounts
+                        .remove_account(&update.address);
+
                     // We expect the code and balance to be present.
                     let code = Bytecode::new_raw(Bytes::from(
                         update
@@ -134,6 +232,111 @@ impl PreCachedDB {
         }
     }
 
+    /// Starts a checkpoint at the current block. Accounts are added to it with
+    /// [`Self::record`] before they are changed.
+    pub fn checkpoint(&self) -> AccountsCheckpoint {
+        AccountsCheckpoint { block: self.inner.read().unwrap().block, accounts: HashMap::new() }
+    }
+
+    /// Records the current state of `addresses` in `checkpoint`. Accounts recorded before keep
+    /// their earlier state.
+    pub fn record<'a>(
+        &self,
+        checkpoint: &mut AccountsCheckpoint,
+        addresses: impl IntoIterator<Item = &'a Address>,
+    ) {
+        let read_guard = self.inner.read().unwrap();
+        for address in addresses {
+            checkpoint
+                .accounts
+                .entry(*address)
+                .or_insert_with(|| {
+                    read_guard
+                        .accounts
+                        .get_account(address)
+                        .cloned()
+                });
+        }
+    }
+
+    /// Rolls the recorded accounts and the block back to `checkpoint`. Accounts that weren't
+    /// present when they were recorded are removed.
+    pub fn restore(&self, checkpoint: AccountsCheckpoint) {
+        let mut write_guard = self.inner.write().unwrap();
+        info!(n = checkpoint.accounts.len(), block = ?checkpoint.block, "Restoring accounts");
+        for (address, account) in checkpoint.accounts {
+            match account {
+                Some(account) => write_guard
+                    .accounts
+                    .insert_account(address, account),
+                None => {
+                    write_guard
+                        .accounts
+                        .remove_account(&address);
+                }
+            }
+        }
+        write_guard.block = checkpoint.block;
+    }
+
+    /// Applies the changes of concurrent extractor streams at once, combining updates of the same
+    /// account according to `strategy`. The database ends up at the latest block of `changes`.
+    ///
+    /// Nothing is applied if the changes conflict under
+    /// [`ConflictResolutionStrategy::FailOnConflict`].
+    pub fn update_from_changes(
+        &self,
+        changes: Vec<BlockAccountChanges>,
+        strategy: ConflictResolutionStrategy,
+    ) -> Result<(), ConflictError> {
+        let block = changes
+            .iter()
+            .map(|change| change.block)
+            .max_by_key(|block| block.number);
+        let updates = resolve_conflicts(changes, strategy)?;
+        self.update(updates, block.map(Into::into));
+        Ok(())
+    }
+
+    /// Fetches the state selected by `request` with [`get_state_chunked`] and loads it at `block`.
+    ///
+    /// If `budget` runs out before all chunks were fetched, the accounts fetched so far are loaded
+    /// anyway. Returns the number of chunks that weren't fetched, `0` if the state is complete.
+    ///
+    /// # Errors
+    ///
+    /// * `PreCachedDBError::TychoClientError` - if a chunk couldn't be fetched. Nothing is loaded.
+    pub async fn load_state_chunked<C: TychoHttpClient + ?Sized>(
+        &self,
+        client: &C,
+        filters: &StateRequestParameters,
+        request: &StateRequestBody,
+        chunk_size: usize,
+        budget: FetchBudget,
+        block: BlockHeader,
+    ) -> Result<usize, PreCachedDBError> {
+        let state = get_state_chunked(client, filters, request, chunk_size, budget, |_| {}).await?;
+        let complete = state.is_complete();
+        if !complete {
+            warn!(
+                fetched_accounts = state.response.accounts.len(),
+                remaining_chunks = state.remaining_chunks,
+                "Snapshot deadline exceeded, loading partial state and staying in snapshot phase"
+            );
+        }
+        let updates = state
+            .response
+            .accounts
+            .into_iter()
+            .map(AccountUpdate::from)
+            .collect();
+        self.update(updates, Some(block));
+        if complete {
+            self.complete_snapshot_phase();
+        }
+        Ok(state.remaining_chunks)
+    }
+
     /// Retrieves the storage value at the specified index for the given account, if it exists.
     ///
     /// If the account exists in the storage, the storage value at the specified `index` is returned
@@ -157,6 +360,15 @@ impl PreCachedDB {
             .get_storage(address, index)
     }
 
+    /// Whether the storage of the account at `address` was loaded into the database.
+    pub fn contains_account(&self, address: &Address) -> bool {
+        self.inner
+            .read()
+            .unwrap()
+            .accounts
+            .account_present(address)
+    }
+
     /// Update the simulation state.
     ///
     /// This method modifies the current state of the simulation by applying the provided updates to
@@ -171,6 +383,8 @@ impl PreCachedDB {
         updates: &HashMap<Address, StateUpdate>,
         block: BlockHeader,
     ) -> HashMap<Address, StateUpdate> {
+        #[cfg(feature = "profiling")]
+        let _profile = profile(ProfiledFn::UpdateState);
         // Hold the write lock for the duration of the function so that no other thread can
         // write to the storage.
         let mut write_guard = self.inner.write().unwrap();
@@ -179,36 +393,11 @@ impl PreCachedDB {
         write_guard.block = Some(block);
 
         for (address, update_info) in updates.iter() {
-            let mut revert_entry = StateUpdate::default();
-
-            if let Some(current_account) = write_guard
+            let revert_entry = write_guard
                 .accounts
-                .get_account_info(address)
-            {
-                revert_entry.balance = Some(current_account.balance);
-            }
-
-            if update_info.storage.is_some() {
-                let mut revert_storage = HashMap::default();
-                for index in update_info
-                    .storage
-                    .as_ref()
-                    .unwrap()
-                    .keys()
-                {
-                    if let Some(s) = write_guard
-                        .accounts
-                        .get_storage(address, index)
-                    {
-                        revert_storage.insert(*index, s);
-                    }
-                }
-                revert_entry.storage = Some(revert_storage);
-            }
+                .apply_update(address, update_info)
+                .unwrap_or_default();
             revert_updates.insert(*address, revert_entry);
-            write_guard
-                .accounts
-                .update_account(address, update_info);
         }
 
         revert_updates
@@ -282,13 +471,15 @@ impl DatabaseRef for PreCachedDB {
     /// Returns a `Result` containing the account information or an error if the account is not
     /// found.
     fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        #[cfg(feature = "profiling")]
+        let _profile = profile(ProfiledFn::Basic);
         self.inner
             .read()
             .unwrap()
             .accounts
             .get_account_info(&address)
             .map(|acc| Some(acc.clone()))
-            .ok_or(PreCachedDBError::MissingAccount(address))
+            .ok_or_else(|| self.missing_account(address, None))
     }
 
     fn code_by_hash_ref(&self, _code_hash: B256) -> Result<Bytecode, Self::Error> {
@@ -310,6 +501,8 @@ impl DatabaseRef for PreCachedDB {
     ///
     /// Returns an error if the storage value is not found.
     fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        #[cfg(feature = "profiling")]
+        let _profile = profile(ProfiledFn::Storage);
         debug!(%address, %index, "Requested storage of account");
         let read_guard = self.inner.read().unwrap();
         if let Some(storage_value) = read_guard
@@ -331,7 +524,7 @@ impl DatabaseRef for PreCachedDB {
             } else {
                 // At this point we know we don't have data for this address.
                 debug!(%address, %index, "Account not found");
-                Err(PreCachedDBError::MissingAccount(address))
+                Err(self.missing_account(address, Some(index)))
             }
         }
     }
@@ -354,16 +547,13 @@ mod tests {
     use rstest::{fixture, rstest};
 
     use super::*;
-    use crate::evm::tycho_models::{AccountUpdate, Block, Chain, ChangeType};
+    use crate::evm::tycho_models::{
+        AccountUpdate, Block, Chain, ChangeType, ResponseAccount, StateRequestResponse, Version,
+    };
 
     #[fixture]
     pub fn mock_db() -> PreCachedDB {
-        PreCachedDB {
-            inner: Arc::new(RwLock::new(PreCachedDBInner {
-                accounts: AccountStorage::new(),
-                block: None,
-            })),
-        }
+        PreCachedDB::new().unwrap()
     }
 
     #[rstest]
@@ -449,7 +639,11 @@ mod tests {
         let new_storage_value_index = U256::from_limbs_slice(&[123]);
         new_storage.insert(new_storage_value_index, new_storage_value_index);
         let new_balance = U256::from_limbs_slice(&[500]);
-        let update = StateUpdate { storage: Some(new_storage), balance: Some(new_balance) };
+        let update = StateUpdate {
+            storage: Some(new_storage),
+            balance: Some(new_balance),
+            ..Default::default()
+        };
         let new_block = Block {
             number: 1,
             hash: B256::default(),
@@ -511,15 +705,40 @@ mod tests {
         Ok(())
     }
 
+    #[rstest]
+    fn test_restore_checkpoint(mut mock_db: PreCachedDB) {
+        let updated = Address::repeat_byte(1);
+        let created = Address::repeat_byte(2);
+        mock_db.init_account(updated, AccountInfo::default(), None, false);
+        let mut checkpoint = mock_db.checkpoint();
+        mock_db.record(&mut checkpoint, [&updated, &created]);
+
+        let update = StateUpdate {
+            storage: Some(HashMap::from([(U256::from(1), U256::from(10))])),
+            balance: Some(U256::from(500)),
+            ..Default::default()
+        };
+        mock_db.update_state(
+            &HashMap::from([(updated, update)]),
+            BlockHeader { number: 1, ..Default::default() },
+        );
+        mock_db.init_account(created, AccountInfo::default(), None, false);
+        // recording an account again keeps its earlier state
+        mock_db.record(&mut checkpoint, [&updated]);
+
+        mock_db.restore(checkpoint);
+
+        let accounts = mock_db.get_account_storage();
+        assert_eq!(accounts.get_account_info(&updated), Some(&AccountInfo::default()));
+        assert_eq!(accounts.get_storage(&updated, &U256::from(1)), None);
+        assert!(!accounts.account_present(&created));
+        assert_eq!(mock_db.block_number(), None);
+    }
+
     #[rstest]
     #[tokio::test]
     async fn test_update() {
-        let mock_db = PreCachedDB {
-            inner: Arc::new(RwLock::new(PreCachedDBInner {
-                accounts: AccountStorage::new(),
-                block: None,
-            })),
-        };
+        let mock_db = PreCachedDB::new().unwrap();
 
         let account_update = AccountUpdate::new(
             Address::from_str("0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D").unwrap(),
@@ -572,6 +791,207 @@ mod tests {
         );
     }
 
+    #[rstest]
+    fn test_update_deleted_slots(mock_db: PreCachedDB) {
+        let pool = Address::repeat_byte(0x11);
+        mock_db.init_account(
+            pool,
+            AccountInfo::default(),
+            Some(HashMap::from([(U256::from(0), U256::from(1)), (U256::from(1), U256::from(2))])),
+            true,
+        );
+        let update = AccountUpdate::new(
+            pool,
+            Chain::Ethereum,
+            HashMap::from([(U256::from(1), U256::from(3))]),
+            None,
+            None,
+            ChangeType::Update,
+        )
+        .with_deleted_slots(vec![U256::from(0), U256::from(5)]);
+
+        mock_db.update(vec![update], Some(BlockHeader { number: 2, ..Default::default() }));
+
+        assert_eq!(mock_db.get_storage(&pool, &U256::from(0)), None);
+        assert_eq!(mock_db.get_storage(&pool, &U256::from(1)), Some(U256::from(3)));
+        assert_eq!(mock_db.get_storage(&pool, &U256::from(5)), None);
+    }
+
+    #[rstest]
+    fn test_update_from_changes_merges_slots(mock_db: PreCachedDB) {
+        let pool = Address::repeat_byte(0x11);
+        mock_db.init_account(pool, AccountInfo::default(), None, true);
+        let changes = |extractor: &str, block: u64, slot: u64, value: u64| {
+            BlockAccountChanges::new(
+                extractor.to_string(),
+                Chain::Ethereum,
+                Block { number: block, ..Default::default() },
+                HashMap::from([(
+                    pool,
+                    AccountUpdate::new(
+                        pool,
+                        Chain::Ethereum,
+                        HashMap::from([(U256::from(slot), U256::from(value))]),
+                        None,
+                        None,
+                        ChangeType::Update,
+                    ),
+                )]),
+                HashMap::new(),
+            )
+        };
+
+        mock_db
+            .update_from_changes(
+                vec![changes("vm:a", 5, 0, 1), changes("vm:b", 5, 1, 2)],
+                ConflictResolutionStrategy::MergeSlots,
+            )
+            .unwrap();
+
+        assert_eq!(mock_db.get_storage(&pool, &U256::from(0)), Some(U256::from(1)));
+        assert_eq!(mock_db.get_storage(&pool, &U256::from(1)), Some(U256::from(2)));
+        assert_eq!(mock_db.block_number(), Some(5));
+    }
+
+    #[rstest]
+    fn test_update_from_changes_conflict_applies_nothing(mock_db: PreCachedDB) {
+        let pool = Address::repeat_byte(0x11);
+        mock_db.init_account(pool, AccountInfo::default(), None, true);
+        let update = AccountUpdate::new(
+            pool,
+            Chain::Ethereum,
+            HashMap::from([(U256::from(0), U256::from(1))]),
+            None,
+            None,
+            ChangeType::Update,
+        );
+        let changes = |extractor: &str| {
+            BlockAccountChanges::new(
+                extractor.to_string(),
+                Chain::Ethereum,
+                Block { number: 5, ..Default::default() },
+                HashMap::from([(pool, update.clone())]),
+                HashMap::new(),
+            )
+        };
+
+        let res = mock_db.update_from_changes(
+            vec![changes("vm:a"), changes("vm:b")],
+            ConflictResolutionStrategy::FailOnConflict,
+        );
+
+        assert!(matches!(res, Err(ConflictError::DuplicatePoolUpdate { block: 5, .. })));
+        assert_eq!(mock_db.get_storage(&pool, &U256::from(0)), None);
+    }
+
+    /// Answers each request after 80ms with an account per requested contract.
+    struct SlowStateClient;
+
+    impl TychoHttpClient for SlowStateClient {
+        fn get_state<'a>(
+            &'a self,
+            _filters: &'a StateRequestParameters,
+            request: &'a StateRequestBody,
+        ) -> futures::future::BoxFuture<'a, Result<StateRequestResponse, TychoClientError>>
+        {
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(80)).await;
+                let accounts = request
+                    .contract_ids
+                    .iter()
+                    .flatten()
+                    .map(|id| ResponseAccount {
+                        address: id.address,
+                        native_balance: U256::from(1),
+                        ..Default::default()
+                    })
+                    .collect();
+                Ok(StateRequestResponse::new(accounts))
+            })
+        }
+    }
+
+    #[rstest]
+    #[tokio::test(start_paused = true)]
+    async fn test_load_state_chunked_keeps_partial_state(mock_db: PreCachedDB) {
+        let mock_db = mock_db.with_network_guard(Arc::new(NetworkGuard::pipeline()));
+        let request = StateRequestBody::new(
+            Some(
+                (1..=4)
+                    .map(Address::repeat_byte)
+                    .collect(),
+            ),
+            Version::default(),
+        );
+        // the first chunk fits the budget, the second doesn't
+        let budget =
+            FetchBudget::new(Duration::from_millis(100)).with_budget(Duration::from_millis(120));
+        let block = BlockHeader { number: 1, ..Default::default() };
+
+        let remaining_chunks = mock_db
+            .load_state_chunked(
+                &SlowStateClient,
+                &StateRequestParameters::default(),
+                &request,
+                2,
+                budget,
+                block,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(remaining_chunks, 1);
+        assert!(mock_db.contains_account(&Address::repeat_byte(2)));
+        assert!(!mock_db.contains_account(&Address::repeat_byte(3)));
+        assert_eq!(mock_db.block_number(), Some(1));
+        // the missing accounts may still be loaded
+        assert_eq!(mock_db.network_policy(), NetworkPolicy::Allow);
+    }
+
+    #[rstest]
+    #[tokio::test(start_paused = true)]
+    async fn test_load_state_chunked_completes_snapshot_phase(mock_db: PreCachedDB) {
+        let mock_db = mock_db.with_network_guard(Arc::new(NetworkGuard::pipeline()));
+        let request = StateRequestBody::new(
+            Some(
+                (1..=4)
+                    .map(Address::repeat_byte)
+                    .collect(),
+            ),
+            Version::default(),
+        );
+        let block = BlockHeader { number: 1, ..Default::default() };
+
+        let remaining_chunks = mock_db
+            .load_state_chunked(
+                &SlowStateClient,
+                &StateRequestParameters::default(),
+                &request,
+                2,
+                FetchBudget::new(Duration::from_secs(1)),
+                block,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(remaining_chunks, 0);
+        assert_eq!(mock_db.network_policy(), NetworkPolicy::Deny);
+    }
+
+    #[rstest]
+    fn test_missing_account_is_checked_against_network_policy(mock_db: PreCachedDB) {
+        let guard = Arc::new(NetworkGuard::new(NetworkPolicy::WarnOnce));
+        let mock_db = mock_db.with_network_guard(guard.clone());
+        let missing = Address::repeat_byte(0x11);
+
+        assert!(matches!(
+            mock_db.storage_ref(missing, U256::ZERO),
+            Err(PreCachedDBError::MissingAccount(address)) if address == missing
+        ));
+        assert!(matches!(mock_db.basic_ref(missing), Err(PreCachedDBError::MissingAccount(_))));
+        assert_eq!(guard.violations(), [missing].into());
+    }
+
     /// This test requires a running TychoDB instance.
     ///
     /// To run this test, start TychoDB with the following command:
@@ -0,0 +1,308 @@
+//! Typed pool events from the logs of a simulated transaction.
+//!
+//! [`EventExtractor`] decodes the logs emitted by registered pools into [`PoolEvent`]s, so
+//! downstream indexers can follow swaps and liquidity changes without decoding raw logs.
+use alloy_primitives::{keccak256, B256, I256, U256};
+use lazy_static::lazy_static;
+use revm::primitives::Log;
+
+use super::{
+    inferrer::{PoolId, StorageLayout},
+    protocol::{
+        decoder::PoolRegistry, u256_num::u256_to_f64,
+        utils::uniswap::sqrt_price_math::sqrt_price_q96_to_f64,
+    },
+    simulation::SimulationResult,
+};
+
+lazy_static! {
+    static ref V2_SWAP: B256 = keccak256("Swap(address,uint256,uint256,uint256,uint256,address)");
+    static ref V2_SYNC: B256 = keccak256("Sync(uint112,uint112)");
+    static ref V3_SWAP: B256 =
+        keccak256("Swap(address,address,int256,int256,uint160,uint128,int24)");
+    static ref V3_MINT: B256 =
+        keccak256("Mint(address,address,int24,int24,uint128,uint256,uint256)");
+    static ref V3_BURN: B256 = keccak256("Burn(address,int24,int24,uint128,uint256,uint256)");
+}
+
+/// An event emitted by a pool.
+///
+/// Amounts are signed from the pool's point of view: positive amounts were paid into the pool,
+/// negative amounts were paid out of it. Prices are of token 0 in token 1, in raw token units.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PoolEvent {
+    Swap {
+        pool: PoolId,
+        amount0: I256,
+        amount1: I256,
+        price_after: f64,
+    },
+    /// Liquidity added to a Uniswap V3 position.
+    Mint {
+        pool: PoolId,
+        tick_lower: i32,
+        tick_upper: i32,
+        amount: u128,
+    },
+    /// Liquidity removed from a Uniswap V3 position.
+    Burn {
+        pool: PoolId,
+        tick_lower: i32,
+        tick_upper: i32,
+        amount: u128,
+    },
+    /// Reserves of a Uniswap V2 pair after a swap, mint or burn.
+    Sync {
+        pool: PoolId,
+        reserve0: U256,
+        reserve1: U256,
+    },
+}
+
+pub struct EventExtractor;
+
+impl EventExtractor {
+    /// Decodes the events of all registered pools from the logs of `result`, in emission order.
+    ///
+    /// Logs of other contracts, and logs that don't decode as an event of the pool's layout, are
+    /// skipped. Uniswap V2 mints and burns are not extracted, as the pair doesn't log the
+    /// liquidity; the `Sync` they emit reports the new reserves.
+    pub fn extract(result: &SimulationResult, registry: &PoolRegistry) -> Vec<PoolEvent> {
+        // a V2 pair emits `Sync` before `Swap`, the price after a swap is the one of its reserves
+        let mut last_sync: Option<(PoolId, f64)> = None;
+        let mut events = Vec::new();
+        for log in &result.logs {
+            let Some(pool) = registry.get(&log.address) else {
+                continue;
+            };
+            let event = match pool.layout {
+                StorageLayout::UniswapV2 => Self::decode_v2(pool, log, last_sync),
+                StorageLayout::UniswapV3 => Self::decode_v3(pool, log),
+            };
+            if let Some(event) = event {
+                if let PoolEvent::Sync { reserve0, reserve1, .. } = &event {
+                    last_sync = Some((pool, u256_to_f64(*reserve1) / u256_to_f64(*reserve0)));
+                }
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    fn decode_v2(pool: PoolId, log: &Log, last_sync: Option<(PoolId, f64)>) -> Option<PoolEvent> {
+        let topic = log.data.topics().first()?;
+        let data = &log.data.data;
+        if topic == &*V2_SYNC {
+            Some(PoolEvent::Sync { pool, reserve0: word(data, 0)?, reserve1: word(data, 1)? })
+        } else if topic == &*V2_SWAP {
+            let [amount0_in, amount1_in, amount0_out, amount1_out] =
+                [word(data, 0)?, word(data, 1)?, word(data, 2)?, word(data, 3)?];
+            let price_after = match last_sync {
+                Some((synced, price)) if synced == pool => price,
+                _ => f64::NAN,
+            };
+            Some(PoolEvent::Swap {
+                pool,
+                amount0: I256::try_from(amount0_in).ok()? - I256::try_from(amount0_out).ok()?,
+                amount1: I256::try_from(amount1_in).ok()? - I256::try_from(amount1_out).ok()?,
+                price_after,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn decode_v3(pool: PoolId, log: &Log) -> Option<PoolEvent> {
+        let topics = log.data.topics();
+        let topic = topics.first()?;
+        let data = &log.data.data;
+        if topic == &*V3_SWAP {
+            let sqrt_price = word(data, 2)?;
+            if sqrt_price >= U256::from(1u64) << 160 {
+                return None;
+            }
+            Some(PoolEvent::Swap {
+                pool,
+                amount0: I256::from_raw(word(data, 0)?),
+                amount1: I256::from_raw(word(data, 1)?),
+                price_after: sqrt_price_q96_to_f64(sqrt_price, 0, 0),
+            })
+        } else if topic == &*V3_MINT || topic == &*V3_BURN {
+            // `Mint` logs the sender before the amount, `Burn` doesn't
+            let amount_index = if topic == &*V3_MINT { 1 } else { 0 };
+            let amount = u128::try_from(word(data, amount_index)?).ok()?;
+            let tick_lower = int24(topics.get(2)?)?;
+            let tick_upper = int24(topics.get(3)?)?;
+            Some(if topic == &*V3_MINT {
+                PoolEvent::Mint { pool, tick_lower, tick_upper, amount }
+            } else {
+                PoolEvent::Burn { pool, tick_lower, tick_upper, amount }
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// The `index`th 32 byte word of ABI encoded `data`.
+fn word(data: &[u8], index: usize) -> Option<U256> {
+    data.get(index * 32..(index + 1) * 32)
+        .map(U256::from_be_slice)
+}
+
+/// Decodes an indexed `int24`, which is sign extended to the full word.
+fn int24(topic: &B256) -> Option<i32> {
+    let value = i32::from_be_bytes(topic[28..].try_into().ok()?);
+    (-(1 << 23)..(1 << 23))
+        .contains(&value)
+        .then_some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::{b256, hex, Address, Bytes, LogData};
+    use approx::assert_ulps_eq;
+
+    use super::*;
+
+    fn pool(i: u8, layout: StorageLayout) -> PoolId {
+        PoolId { address: Address::repeat_byte(i), layout }
+    }
+
+    fn log(pool: PoolId, topics: Vec<B256>, data: &str) -> Log {
+        Log {
+            address: pool.address,
+            data: LogData::new_unchecked(topics, Bytes::from(hex::decode(data).unwrap())),
+        }
+    }
+
+    fn registry(pools: &[PoolId]) -> PoolRegistry {
+        let mut registry = PoolRegistry::new();
+        for pool in pools {
+            registry.register(pool.address, pool.layout);
+        }
+        registry
+    }
+
+    fn extract(logs: Vec<Log>, pools: &[PoolId]) -> Vec<PoolEvent> {
+        let result = SimulationResult { logs, ..Default::default() };
+        EventExtractor::extract(&result, &registry(pools))
+    }
+
+    fn tick_topic(tick: i32) -> B256 {
+        B256::from(
+            I256::try_from(tick)
+                .unwrap()
+                .to_be_bytes::<32>(),
+        )
+    }
+
+    #[test]
+    fn test_event_signatures() {
+        assert_eq!(
+            *V2_SWAP,
+            b256!("d78ad95fa46c994b6551d0da85fc275fe613ce37657fb8d5e3d130840159d822")
+        );
+        assert_eq!(
+            *V3_SWAP,
+            b256!("c42079f94a6350d7e6235f29174924f928cc2ac818eb64fed8004e115fbcca67")
+        );
+    }
+
+    #[test]
+    fn test_extract_v3_swap() {
+        let v3 = pool(1, StorageLayout::UniswapV3);
+        // amount0 = -1000, amount1 = 500, sqrtPriceX96 = 2^96, liquidity = 1e18, tick = -1
+        let data = concat!(
+            "fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffc18",
+            "00000000000000000000000000000000000000000000000000000000000001f4",
+            "0000000000000000000000000000000000000001000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000de0b6b3a7640000",
+            "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+        );
+        let topics = vec![*V3_SWAP, B256::repeat_byte(0xaa), B256::repeat_byte(0xbb)];
+
+        let events = extract(vec![log(v3, topics, data)], &[v3]);
+
+        let [PoolEvent::Swap { pool, amount0, amount1, price_after }] = events.as_slice() else {
+            panic!("expected a single swap, got {events:?}");
+        };
+        assert_eq!(*pool, v3);
+        assert_eq!(*amount0, I256::try_from(-1000).unwrap());
+        assert_eq!(*amount1, I256::try_from(500).unwrap());
+        assert_ulps_eq!(*price_after, 1.0);
+    }
+
+    #[test]
+    fn test_extract_v2_sync_and_swap() {
+        let v2 = pool(1, StorageLayout::UniswapV2);
+        let unregistered = pool(2, StorageLayout::UniswapV2);
+        // reserves 2000 and 1000 after swapping 100 token0 in for 50 token1 out
+        let sync = concat!(
+            "00000000000000000000000000000000000000000000000000000000000007d0",
+            "00000000000000000000000000000000000000000000000000000000000003e8",
+        );
+        let swap = concat!(
+            "0000000000000000000000000000000000000000000000000000000000000064",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000000000000000032",
+        );
+        let swap_topics = vec![*V2_SWAP, B256::repeat_byte(0xaa), B256::repeat_byte(0xbb)];
+
+        let events = extract(
+            vec![
+                log(unregistered, vec![*V2_SYNC], sync),
+                log(v2, vec![*V2_SYNC], sync),
+                log(v2, swap_topics, swap),
+            ],
+            &[v2],
+        );
+
+        assert_eq!(
+            events,
+            vec![
+                PoolEvent::Sync {
+                    pool: v2,
+                    reserve0: U256::from(2000),
+                    reserve1: U256::from(1000)
+                },
+                PoolEvent::Swap {
+                    pool: v2,
+                    amount0: I256::try_from(100).unwrap(),
+                    amount1: I256::try_from(-50).unwrap(),
+                    price_after: 0.5,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_v3_mint_and_burn() {
+        let v3 = pool(1, StorageLayout::UniswapV3);
+        let owner = B256::repeat_byte(0xaa);
+        let topics = |signature| vec![signature, owner, tick_topic(-887220), tick_topic(60)];
+        let mint = concat!(
+            "000000000000000000000000aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+            "00000000000000000000000000000000000000000000000000000000000003e8",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000000000000000000000000000002",
+        );
+        let burn = concat!(
+            "00000000000000000000000000000000000000000000000000000000000001f4",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000000000000000000000000000002",
+        );
+
+        let events =
+            extract(vec![log(v3, topics(*V3_MINT), mint), log(v3, topics(*V3_BURN), burn)], &[v3]);
+
+        assert_eq!(
+            events,
+            vec![
+                PoolEvent::Mint { pool: v3, tick_lower: -887220, tick_upper: 60, amount: 1000 },
+                PoolEvent::Burn { pool: v3, tick_lower: -887220, tick_upper: 60, amount: 500 },
+            ]
+        );
+    }
+}
@@ -0,0 +1,147 @@
+//! Rejects quoting on stale data.
+//!
+//! A [`FreshnessGuard`] tracks the timestamp of the latest received block and reports the data as
+//! stale once that block is older than the configured maximum age. The age is measured with a
+//! [`ClockSource`], so a guard built on a [`BlockAnchoredClock`](super::clock::BlockAnchoredClock)
+//! doesn't mistake a host with a skewed clock for a stalled stream.
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use thiserror::Error;
+
+use crate::evm::{
+    clock::{ClockSource, SystemClock},
+    pipeline_config::PipelineConfig,
+};
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum FreshnessError {
+    #[error("No block received yet")]
+    NoBlock,
+    #[error("Latest block is {age:?} old, exceeding the maximum of {max_age:?}")]
+    Stale { age: Duration, max_age: Duration },
+}
+
+#[derive(Debug)]
+pub struct FreshnessGuard {
+    max_age: Duration,
+    clock: Arc<dyn ClockSource>,
+    latest_block: RwLock<Option<u64>>,
+}
+
+impl FreshnessGuard {
+    /// Creates a guard measuring block age with the host's wall clock.
+    pub fn new(max_age: Duration) -> Self {
+        Self { max_age, clock: Arc::new(SystemClock), latest_block: RwLock::new(None) }
+    }
+
+    /// Measures block age with `clock` instead of the host's wall clock.
+    pub fn with_clock(mut self, clock: Arc<dyn ClockSource>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// A guard enforcing the `max_block_age_secs` of `config`, `None` if it isn't set.
+    pub fn from_config(config: &PipelineConfig) -> Option<Self> {
+        config
+            .max_block_age_secs
+            .map(|secs| Self::new(Duration::from_secs(secs)))
+    }
+
+    /// Records a received block. Older blocks than the latest one are ignored.
+    pub fn observe_block(&self, block_timestamp: u64) {
+        let mut latest = self.latest_block.write().unwrap();
+        if latest.is_none_or(|ts| ts < block_timestamp) {
+            *latest = Some(block_timestamp);
+        }
+    }
+
+    /// Records a block received now, for feeds whose blocks carry no timestamp, like Tycho's.
+    pub fn block_received(&self) {
+        self.observe_block(self.clock.now());
+    }
+
+    /// Age of the latest received block, `None` before the first block.
+    pub fn age(&self) -> Option<Duration> {
+        self.latest_block
+            .read()
+            .unwrap()
+            .map(|ts| self.clock.elapsed_since(ts))
+    }
+
+    /// Fails if no block was received yet or the latest block exceeds the maximum age.
+    pub fn check(&self) -> Result<(), FreshnessError> {
+        let age = self
+            .age()
+            .ok_or(FreshnessError::NoBlock)?;
+        if age > self.max_age {
+            return Err(FreshnessError::Stale { age, max_age: self.max_age });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm::clock::{BlockAnchoredClock, FixedClock};
+
+    const BLOCK_TIMESTAMP: u64 = 1_700_000_000;
+
+    #[test]
+    fn test_check() {
+        let guard = FreshnessGuard::new(Duration::from_secs(60))
+            .with_clock(Arc::new(FixedClock(BLOCK_TIMESTAMP + 30)));
+
+        assert_eq!(guard.check(), Err(FreshnessError::NoBlock));
+
+        guard.observe_block(BLOCK_TIMESTAMP);
+        assert_eq!(guard.check(), Ok(()));
+
+        guard.observe_block(BLOCK_TIMESTAMP - 120);
+        assert_eq!(guard.age(), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_check_rejects_stale_block() {
+        let guard = FreshnessGuard::new(Duration::from_secs(60))
+            .with_clock(Arc::new(FixedClock(BLOCK_TIMESTAMP + 61)));
+
+        guard.observe_block(BLOCK_TIMESTAMP);
+
+        assert_eq!(
+            guard.check(),
+            Err(FreshnessError::Stale {
+                age: Duration::from_secs(61),
+                max_age: Duration::from_secs(60)
+            })
+        );
+    }
+
+    #[test]
+    fn test_from_config() {
+        assert!(FreshnessGuard::from_config(&PipelineConfig::default()).is_none());
+
+        let config = PipelineConfig { max_block_age_secs: Some(60), ..Default::default() };
+        let guard = FreshnessGuard::from_config(&config).unwrap();
+        assert_eq!(guard.max_age, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_block_anchored_clock_ignores_skew() {
+        // a wall clock running 5 minutes ahead of the chain
+        let wall_clock = FixedClock(BLOCK_TIMESTAMP + 300);
+        let skewed = FreshnessGuard::new(Duration::from_secs(60)).with_clock(Arc::new(wall_clock));
+        let clock = Arc::new(BlockAnchoredClock::new(wall_clock));
+        let anchored = FreshnessGuard::new(Duration::from_secs(60)).with_clock(clock.clone());
+
+        clock.observe_block(BLOCK_TIMESTAMP);
+        skewed.observe_block(BLOCK_TIMESTAMP);
+        anchored.observe_block(BLOCK_TIMESTAMP);
+
+        assert!(matches!(skewed.check(), Err(FreshnessError::Stale { .. })));
+        assert_eq!(anchored.check(), Ok(()));
+    }
+}
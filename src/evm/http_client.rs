@@ -0,0 +1,1117 @@
+//! Fetching contract state from Tycho over HTTP.
+//!
+//! [`AsyncTychoHttpClientImpl`] sends the requests with reqwest's async client, so fetching state
+//! never blocks a thread of the runtime it is called from.
+//!
+//! Threads that start up at the same time tend to request the state of the same contracts.
+//! [`DeduplicatingHttpClient`] coalesces identical requests while they are in flight: the first
+//! caller sends the request and everyone else waits for its response. Nothing is cached once the
+//! response arrived, so later calls always fetch fresh state.
+//!
+//! [`RetryingTychoHttpClient`] retries requests that failed on the way, e.g. a reset connection
+//! or an overloaded server, instead of handing the first error to the caller.
+//!
+//! Large snapshots are fetched in chunks with [`get_state_chunked`], which shares a
+//! [`FetchBudget`] across all chunks so a slow server can't stretch the startup indefinitely.
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use alloy_primitives::{keccak256, B256};
+use futures::future::BoxFuture;
+use reqwest::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use tokio::{sync::OnceCell, time::Instant};
+use tracing::{trace, warn};
+
+use crate::evm::{
+    engine_db::tycho_db::TychoClientError,
+    tycho_models::{StateRequestBody, StateRequestParameters, StateRequestResponse},
+};
+
+pub trait TychoHttpClient: Send + Sync {
+    /// Fetches the state of the contracts selected by `request`.
+    fn get_state<'a>(
+        &'a self,
+        filters: &'a StateRequestParameters,
+        request: &'a StateRequestBody,
+    ) -> BoxFuture<'a, Result<StateRequestResponse, TychoClientError>>;
+}
+
+/// Version prefix of the paths of the Tycho server's HTTP API.
+pub const TYCHO_SERVER_VERSION: &str = "v1";
+
+/// A client of the Tycho server's HTTP API.
+#[derive(Debug, Clone)]
+pub struct AsyncTychoHttpClientImpl {
+    http_client: reqwest::Client,
+    base_uri: String,
+    /// The value of the `Authorization` header, either an API key or a bearer token. Marked
+    /// sensitive, so it isn't printed by `Debug`.
+    authorization: Option<HeaderValue>,
+    timeout: Option<Duration>,
+}
+
+/// Builds an [`AsyncTychoHttpClientImpl`], see [`AsyncTychoHttpClientImpl::builder`].
+#[derive(Clone)]
+pub struct AsyncTychoHttpClientBuilder {
+    base_uri: String,
+    authorization: Option<String>,
+    timeout: Option<Duration>,
+}
+
+impl std::fmt::Debug for AsyncTychoHttpClientBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsyncTychoHttpClientBuilder")
+            .field("base_uri", &self.base_uri)
+            .field(
+                "authorization",
+                &self
+                    .authorization
+                    .as_ref()
+                    .map(|_| "<redacted>"),
+            )
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+/// The `Authorization` header with `value`, marked sensitive.
+fn authorization_header(value: &str) -> Result<HeaderValue, TychoClientError> {
+    let mut header = HeaderValue::from_str(value).map_err(|_| {
+        TychoClientError::FormatRequest(
+            "Authorization contains characters invalid in a header".to_string(),
+        )
+    })?;
+    header.set_sensitive(true);
+    Ok(header)
+}
+
+impl AsyncTychoHttpClientBuilder {
+    /// Sends `auth_key` as the `Authorization` header of every request.
+    pub fn auth_key(mut self, auth_key: String) -> Self {
+        self.authorization = Some(auth_key);
+        self
+    }
+
+    /// Sends `Authorization: Bearer <token>` with every request, replacing an API key.
+    pub fn auth_token(mut self, token: &str) -> Self {
+        self.authorization = Some(format!("Bearer {token}"));
+        self
+    }
+
+    /// Fails requests the server doesn't answer within `timeout`, see
+    /// [`AsyncTychoHttpClientImpl::with_timeout`].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// # Errors
+    ///
+    /// * `TychoClientError::UriParsing` - if the base URI isn't a valid URL.
+    /// * `TychoClientError::FormatRequest` - if the API key or token can't be sent as a header.
+    pub fn build(self) -> Result<AsyncTychoHttpClientImpl, TychoClientError> {
+        reqwest::Url::parse(&self.base_uri)
+            .map_err(|err| TychoClientError::UriParsing(self.base_uri.clone(), err.to_string()))?;
+        let authorization = self
+            .authorization
+            .as_deref()
+            .map(authorization_header)
+            .transpose()?;
+        Ok(AsyncTychoHttpClientImpl {
+            http_client: reqwest::Client::new(),
+            base_uri: self
+                .base_uri
+                .trim_end_matches('/')
+                .to_string(),
+            authorization,
+            timeout: self.timeout,
+        })
+    }
+}
+
+impl AsyncTychoHttpClientImpl {
+    /// Creates a client of the server at `base_uri`, e.g. `http://localhost:4242`.
+    ///
+    /// Requests wait for the server as long as it takes; use [`Self::with_timeout`] to bound
+    /// them.
+    pub fn new(base_uri: &str) -> Result<Self, TychoClientError> {
+        Self::builder(base_uri).build()
+    }
+
+    /// Creates a client of the server at `base_uri` whose requests fail with
+    /// `TychoClientError::RequestTimeout` if the server doesn't answer within `timeout`.
+    ///
+    /// The timeout covers each request from sending it until its whole response is read.
+    pub fn with_timeout(base_uri: &str, timeout: Duration) -> Result<Self, TychoClientError> {
+        Self::builder(base_uri)
+            .timeout(timeout)
+            .build()
+    }
+
+    /// Creates a client of the server at `base_uri` authenticating with the bearer `token`.
+    pub fn with_auth(base_uri: &str, token: &str) -> Result<Self, TychoClientError> {
+        Self::builder(base_uri)
+            .auth_token(token)
+            .build()
+    }
+
+    pub fn builder(base_uri: &str) -> AsyncTychoHttpClientBuilder {
+        AsyncTychoHttpClientBuilder {
+            base_uri: base_uri.to_string(),
+            authorization: None,
+            timeout: None,
+        }
+    }
+
+    /// Sends `auth_key` as the `Authorization` header of every request.
+    ///
+    /// # Errors
+    ///
+    /// * `TychoClientError::FormatRequest` - if the API key can't be sent as a header.
+    pub fn with_auth_key(mut self, auth_key: String) -> Result<Self, TychoClientError> {
+        self.authorization = Some(authorization_header(&auth_key)?);
+        Ok(self)
+    }
+
+    /// Sends the requests with `http_client`, e.g. to use a proxy. The timeout of the client
+    /// still applies to every request. Timeouts configured on `http_client` itself fail with
+    /// `TychoClientError::RequestTimeout` too.
+    pub fn with_http_client(mut self, http_client: reqwest::Client) -> Self {
+        self.http_client = http_client;
+        self
+    }
+
+    pub async fn get_contract_state(
+        &self,
+        filters: &StateRequestParameters,
+        request: &StateRequestBody,
+    ) -> Result<StateRequestResponse, TychoClientError> {
+        let url = format!(
+            "{}/{}/contract_state?{}",
+            self.base_uri,
+            TYCHO_SERVER_VERSION,
+            filters.to_query_string()
+        );
+        let body = serde_json::to_string(request)
+            .map_err(|err| TychoClientError::FormatRequest(err.to_string()))?;
+        let mut http_request = self
+            .http_client
+            .post(&url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body);
+        if let Some(authorization) = &self.authorization {
+            http_request = http_request.header(AUTHORIZATION, authorization.clone());
+        }
+        if let Some(timeout) = self.timeout {
+            http_request = http_request.timeout(timeout);
+        }
+        trace!(%url, "Sending contract state request");
+
+        let started = Instant::now();
+        let response = http_request
+            .send()
+            .await
+            .map_err(|err| self.request_error(err, started))?;
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .map_err(|err| self.request_error(err, started))?;
+        if !status.is_success() {
+            return Err(TychoClientError::HttpClient(format!("{url} returned {status}: {body}")));
+        }
+        serde_json::from_str(&body)
+            .map_err(|err| TychoClientError::ParseResponse("state response".to_string(), Some(err)))
+    }
+
+    /// Maps a failed request sent at `started`. The timeout may come from this client or from
+    /// one passed to [`Self::with_http_client`], whose timeout is unknown, so the time waited is
+    /// measured then.
+    fn request_error(&self, err: reqwest::Error, started: Instant) -> TychoClientError {
+        if !err.is_timeout() {
+            return TychoClientError::HttpClient(err.to_string());
+        }
+        let waited = self
+            .timeout
+            .unwrap_or_else(|| started.elapsed());
+        TychoClientError::RequestTimeout { waited }
+    }
+}
+
+impl TychoHttpClient for AsyncTychoHttpClientImpl {
+    fn get_state<'a>(
+        &'a self,
+        filters: &'a StateRequestParameters,
+        request: &'a StateRequestBody,
+    ) -> BoxFuture<'a, Result<StateRequestResponse, TychoClientError>> {
+        Box::pin(self.get_contract_state(filters, request))
+    }
+}
+
+/// The outcome of a request, shared with all its callers. Errors are shared by message, as
+/// `TychoClientError` can't be cloned.
+type SharedResponse = Arc<OnceCell<Result<StateRequestResponse, String>>>;
+
+/// Wraps a client so concurrent identical requests are only sent once.
+///
+/// Callers waiting on another caller's request receive its error as
+/// `TychoClientError::HttpClient`; only the caller that sent the request receives the original
+/// error.
+#[derive(Debug)]
+pub struct DeduplicatingHttpClient<C> {
+    inner: C,
+    in_flight: Mutex<HashMap<B256, SharedResponse>>,
+}
+
+impl<C: TychoHttpClient> DeduplicatingHttpClient<C> {
+    pub fn new(inner: C) -> Self {
+        Self { inner, in_flight: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    /// Number of distinct requests currently in flight.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.lock().unwrap().len()
+    }
+
+    async fn get_state_deduplicated(
+        &self,
+        filters: &StateRequestParameters,
+        request: &StateRequestBody,
+    ) -> Result<StateRequestResponse, TychoClientError> {
+        let key = request_hash(filters, request)?;
+        let shared = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_default()
+            .clone();
+
+        let mut own_error = None;
+        let sent_error = &mut own_error;
+        let outcome = shared
+            .get_or_init(move || async move {
+                trace!(%key, "Sending state request");
+                self.inner
+                    .get_state(filters, request)
+                    .await
+                    .map_err(|err| {
+                        let message = err.to_string();
+                        *sent_error = Some(err);
+                        message
+                    })
+            })
+            .await;
+
+        {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            if in_flight
+                .get(&key)
+                .is_some_and(|current| Arc::ptr_eq(current, &shared))
+            {
+                in_flight.remove(&key);
+            }
+        }
+
+        match outcome {
+            Ok(response) => Ok(response.clone()),
+            Err(message) => {
+                Err(own_error.unwrap_or_else(|| TychoClientError::HttpClient(message.clone())))
+            }
+        }
+    }
+}
+
+impl<C: TychoHttpClient> TychoHttpClient for DeduplicatingHttpClient<C> {
+    fn get_state<'a>(
+        &'a self,
+        filters: &'a StateRequestParameters,
+        request: &'a StateRequestBody,
+    ) -> BoxFuture<'a, Result<StateRequestResponse, TychoClientError>> {
+        Box::pin(self.get_state_deduplicated(filters, request))
+    }
+}
+
+/// Wraps a client so requests failing transiently are retried.
+///
+/// `TychoClientError::HttpClient` and `TychoClientError::RequestTimeout` errors are retried up to
+/// `max_retries` times, `delay` apart, and the last error is returned once the retries are used
+/// up. Any other error, e.g. a response that can't be parsed, is returned right away.
+#[derive(Debug)]
+pub struct RetryingTychoHttpClient<C> {
+    inner: C,
+    max_retries: u32,
+    delay: Duration,
+}
+
+impl<C: TychoHttpClient> RetryingTychoHttpClient<C> {
+    pub fn new(inner: C, max_retries: u32, delay: Duration) -> Self {
+        Self { inner, max_retries, delay }
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    async fn get_state_retrying(
+        &self,
+        filters: &StateRequestParameters,
+        request: &StateRequestBody,
+    ) -> Result<StateRequestResponse, TychoClientError> {
+        let mut attempt = 0;
+        loop {
+            match self
+                .inner
+                .get_state(filters, request)
+                .await
+            {
+                Err(err) if attempt < self.max_retries && is_transient(&err) => {
+                    attempt += 1;
+                    warn!(attempt, max_retries = self.max_retries, %err, "Retrying state request");
+                    tokio::time::sleep(self.delay).await;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+impl<C: TychoHttpClient> TychoHttpClient for RetryingTychoHttpClient<C> {
+    fn get_state<'a>(
+        &'a self,
+        filters: &'a StateRequestParameters,
+        request: &'a StateRequestBody,
+    ) -> BoxFuture<'a, Result<StateRequestResponse, TychoClientError>> {
+        Box::pin(self.get_state_retrying(filters, request))
+    }
+}
+
+/// Whether a request failing with `err` may succeed when sent again.
+fn is_transient(err: &TychoClientError) -> bool {
+    matches!(err, TychoClientError::HttpClient(_) | TychoClientError::RequestTimeout { .. })
+}
+
+/// Time limits of a chunked state fetch.
+///
+/// Each request may take up to the request timeout, and all requests together must finish by the
+/// deadline: a request's timeout is capped by the budget left when it is sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FetchBudget {
+    request_timeout: Duration,
+    deadline: Option<Instant>,
+}
+
+impl FetchBudget {
+    /// A budget without deadline, limiting each request to `request_timeout`.
+    pub fn new(request_timeout: Duration) -> Self {
+        Self { request_timeout, deadline: None }
+    }
+
+    /// Limits all requests together to `budget` from now.
+    pub fn with_budget(self, budget: Duration) -> Self {
+        self.with_deadline(Instant::now() + budget)
+    }
+
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn request_timeout(&self) -> Duration {
+        self.request_timeout
+    }
+
+    /// The time left until the deadline, `None` without deadline.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// The timeout of a request sent now, and whether it is capped by the deadline.
+    fn next_timeout(&self) -> (Duration, bool) {
+        match self.remaining() {
+            Some(remaining) if remaining < self.request_timeout => (remaining, true),
+            _ => (self.request_timeout, false),
+        }
+    }
+}
+
+/// Progress of [`get_state_chunked`], reported after each chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchProgress {
+    pub fetched_chunks: usize,
+    pub total_chunks: usize,
+    pub fetched_accounts: usize,
+    /// The budget left, `None` without deadline.
+    pub remaining_budget: Option<Duration>,
+}
+
+/// The state fetched by [`get_state_chunked`], complete unless the budget ran out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkedState {
+    /// The accounts of all chunks fetched before the deadline.
+    pub response: StateRequestResponse,
+    /// The chunks not fetched because the budget ran out.
+    pub remaining_chunks: usize,
+}
+
+impl ChunkedState {
+    pub fn is_complete(&self) -> bool {
+        self.remaining_chunks == 0
+    }
+
+    /// The fetched state, if all chunks were fetched.
+    ///
+    /// # Errors
+    ///
+    /// * `TychoClientError::DeadlineExceeded` - if the budget ran out before all chunks were
+    ///   fetched.
+    pub fn into_complete(self) -> Result<StateRequestResponse, TychoClientError> {
+        if !self.is_complete() {
+            return Err(TychoClientError::DeadlineExceeded {
+                fetched_accounts: self.response.accounts.len(),
+                remaining_chunks: self.remaining_chunks,
+            });
+        }
+        Ok(self.response)
+    }
+}
+
+/// Fetches the state of the contracts selected by `request` in chunks of at most `chunk_size`
+/// contracts, one chunk after the other, calling `on_progress` after each chunk.
+///
+/// A request for all contracts can't be split and is sent as a single chunk. If the budget runs
+/// out before all chunks were fetched, the accounts fetched so far are returned together with the
+/// number of chunks left, so callers can decide to proceed with partial state or abort.
+///
+/// # Errors
+///
+/// * `TychoClientError::RequestTimeout` - if a chunk isn't fetched within the request timeout.
+/// * Any error of `client`.
+pub async fn get_state_chunked<C: TychoHttpClient + ?Sized>(
+    client: &C,
+    filters: &StateRequestParameters,
+    request: &StateRequestBody,
+    chunk_size: usize,
+    budget: FetchBudget,
+    mut on_progress: impl FnMut(&FetchProgress),
+) -> Result<ChunkedState, TychoClientError> {
+    let chunks: Vec<StateRequestBody> = match &request.contract_ids {
+        Some(ids) => ids
+            .chunks(chunk_size.max(1))
+            .map(|chunk| StateRequestBody {
+                contract_ids: Some(chunk.to_vec()),
+                version: request.version.clone(),
+            })
+            .collect(),
+        None => vec![StateRequestBody { contract_ids: None, version: request.version.clone() }],
+    };
+    let total_chunks = chunks.len();
+
+    let mut accounts = Vec::new();
+    for (index, chunk) in chunks.iter().enumerate() {
+        let partial = |accounts| ChunkedState {
+            response: StateRequestResponse::new(accounts),
+            remaining_chunks: total_chunks - index,
+        };
+        let (timeout, capped) = budget.next_timeout();
+        if timeout.is_zero() {
+            return Ok(partial(accounts));
+        }
+        match tokio::time::timeout(timeout, client.get_state(filters, chunk)).await {
+            Ok(response) => accounts.extend(response?.accounts),
+            Err(_) if capped => return Ok(partial(accounts)),
+            Err(_) => return Err(TychoClientError::RequestTimeout { waited: timeout }),
+        }
+
+        let progress = FetchProgress {
+            fetched_chunks: index + 1,
+            total_chunks,
+            fetched_accounts: accounts.len(),
+            remaining_budget: budget.remaining(),
+        };
+        trace!(?progress, "Fetched state chunk");
+        on_progress(&progress);
+    }
+    Ok(ChunkedState { response: StateRequestResponse::new(accounts), remaining_chunks: 0 })
+}
+
+/// Identifies a request by its query string and body.
+fn request_hash(
+    filters: &StateRequestParameters,
+    request: &StateRequestBody,
+) -> Result<B256, TychoClientError> {
+    let mut preimage = filters.to_query_string().into_bytes();
+    preimage.push(b'\n');
+    preimage.extend(
+        serde_json::to_vec(request).map_err(|e| TychoClientError::FormatRequest(e.to_string()))?,
+    );
+    Ok(keccak256(preimage))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use alloy_primitives::Address;
+    use futures::future::join_all;
+    use rstest::rstest;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        task::JoinHandle,
+    };
+
+    use super::*;
+    use crate::evm::tycho_models::{ResponseAccount, Version};
+
+    /// Answers every request after a delay, or fails it if `fail` is set.
+    #[derive(Debug, Default)]
+    struct SlowClient {
+        calls: AtomicUsize,
+        fail: bool,
+    }
+
+    impl SlowClient {
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
+        }
+    }
+
+    impl TychoHttpClient for SlowClient {
+        fn get_state<'a>(
+            &'a self,
+            _filters: &'a StateRequestParameters,
+            _request: &'a StateRequestBody,
+        ) -> BoxFuture<'a, Result<StateRequestResponse, TychoClientError>> {
+            Box::pin(async move {
+                self.calls
+                    .fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                if self.fail {
+                    return Err(TychoClientError::HttpClient("connection refused".to_string()));
+                }
+                Ok(StateRequestResponse::new(vec![]))
+            })
+        }
+    }
+
+    /// Fails with the queued errors, one per call, then succeeds.
+    #[derive(Default)]
+    struct FlakyClient {
+        errors: Mutex<Vec<TychoClientError>>,
+        calls: AtomicUsize,
+    }
+
+    impl FlakyClient {
+        fn failing_with(errors: Vec<TychoClientError>) -> Self {
+            Self { errors: Mutex::new(errors), ..Default::default() }
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
+        }
+    }
+
+    impl TychoHttpClient for FlakyClient {
+        fn get_state<'a>(
+            &'a self,
+            _filters: &'a StateRequestParameters,
+            _request: &'a StateRequestBody,
+        ) -> BoxFuture<'a, Result<StateRequestResponse, TychoClientError>> {
+            Box::pin(async move {
+                self.calls
+                    .fetch_add(1, Ordering::SeqCst);
+                let mut errors = self.errors.lock().unwrap();
+                if errors.is_empty() {
+                    return Ok(StateRequestResponse::new(vec![]));
+                }
+                Err(errors.remove(0))
+            })
+        }
+    }
+
+    fn request(address: u8) -> StateRequestBody {
+        StateRequestBody::new(Some(vec![Address::repeat_byte(address)]), Version::default())
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_identical_requests_are_sent_once() {
+        let client = DeduplicatingHttpClient::new(SlowClient::default());
+        let filters = StateRequestParameters::default();
+        let request = request(1);
+
+        let results = join_all((0..10).map(|_| client.get_state(&filters, &request))).await;
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(client.inner().calls(), 1);
+        assert_eq!(client.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_completed_requests_are_not_cached() {
+        let client = DeduplicatingHttpClient::new(SlowClient::default());
+        let filters = StateRequestParameters::default();
+        let request = request(1);
+
+        client
+            .get_state(&filters, &request)
+            .await
+            .unwrap();
+        client
+            .get_state(&filters, &request)
+            .await
+            .unwrap();
+
+        assert_eq!(client.inner().calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_distinct_requests_are_sent_separately() {
+        let client = DeduplicatingHttpClient::new(SlowClient::default());
+        let filters = StateRequestParameters::default();
+        let (first, second) = (request(1), request(2));
+
+        let (a, b) =
+            tokio::join!(client.get_state(&filters, &first), client.get_state(&filters, &second));
+
+        assert!(a.is_ok() && b.is_ok());
+        assert_eq!(client.inner().calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_error_is_shared() {
+        let client = DeduplicatingHttpClient::new(SlowClient { fail: true, ..Default::default() });
+        let filters = StateRequestParameters::default();
+        let request = request(1);
+
+        let results = join_all((0..3).map(|_| client.get_state(&filters, &request))).await;
+
+        assert_eq!(client.inner().calls(), 1);
+        for res in results {
+            let Err(TychoClientError::HttpClient(msg)) = &res else {
+                panic!("Expected an HTTP client error, got {res:?}");
+            };
+            assert!(msg.contains("connection refused"));
+        }
+    }
+
+    /// Answers the `n`th request after `latencies_ms[n]` with an account per requested contract.
+    #[derive(Debug, Default)]
+    struct LatencyClient {
+        latencies_ms: Vec<u64>,
+        calls: AtomicUsize,
+    }
+
+    impl LatencyClient {
+        fn new(latencies_ms: Vec<u64>) -> Self {
+            Self { latencies_ms, ..Default::default() }
+        }
+    }
+
+    impl TychoHttpClient for LatencyClient {
+        fn get_state<'a>(
+            &'a self,
+            _filters: &'a StateRequestParameters,
+            request: &'a StateRequestBody,
+        ) -> BoxFuture<'a, Result<StateRequestResponse, TychoClientError>> {
+            Box::pin(async move {
+                let call = self
+                    .calls
+                    .fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(self.latencies_ms[call])).await;
+                let accounts = request
+                    .contract_ids
+                    .iter()
+                    .flatten()
+                    .map(|id| ResponseAccount { address: id.address, ..Default::default() })
+                    .collect();
+                Ok(StateRequestResponse::new(accounts))
+            })
+        }
+    }
+
+    fn contracts(n: u8) -> StateRequestBody {
+        StateRequestBody::new(
+            Some(
+                (1..=n)
+                    .map(Address::repeat_byte)
+                    .collect(),
+            ),
+            Version::default(),
+        )
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_chunked_fetch_within_budget() {
+        let client = LatencyClient::new(vec![50; 3]);
+        let budget = FetchBudget::new(ms(100)).with_budget(ms(1_000));
+        let mut progress = Vec::new();
+
+        let response = get_state_chunked(
+            &client,
+            &StateRequestParameters::default(),
+            &contracts(5),
+            2,
+            budget,
+            |p| progress.push(p.clone()),
+        )
+        .await
+        .unwrap();
+
+        assert!(response.is_complete());
+        let addresses: Vec<_> = response
+            .response
+            .accounts
+            .iter()
+            .map(|account| account.address)
+            .collect();
+        assert_eq!(
+            addresses,
+            (1..=5)
+                .map(Address::repeat_byte)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            progress.last(),
+            Some(&FetchProgress {
+                fetched_chunks: 3,
+                total_chunks: 3,
+                fetched_accounts: 5,
+                remaining_budget: Some(ms(850)),
+            })
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_budget_is_divided_across_chunks() {
+        let client = LatencyClient::new(vec![80; 4]);
+        // each chunk fits the request timeout, but not all four fit the budget
+        let budget = FetchBudget::new(ms(100)).with_budget(ms(250));
+        let mut remaining = Vec::new();
+
+        let res = get_state_chunked(
+            &client,
+            &StateRequestParameters::default(),
+            &contracts(8),
+            2,
+            budget,
+            |p| remaining.push(p.remaining_budget),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(remaining, vec![Some(ms(170)), Some(ms(90)), Some(ms(10))]);
+        assert_eq!(res.response.accounts.len(), 6);
+        assert_eq!(res.remaining_chunks, 1);
+        assert!(matches!(
+            res.into_complete(),
+            Err(TychoClientError::DeadlineExceeded { fetched_accounts: 6, remaining_chunks: 1 })
+        ));
+        // the last chunk was sent with the 10ms left
+        assert_eq!(client.calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_exhausted_budget_sends_no_more_chunks() {
+        let client = LatencyClient::new(vec![80; 4]);
+        let budget = FetchBudget::new(ms(100)).with_budget(ms(160));
+
+        let res = get_state_chunked(
+            &client,
+            &StateRequestParameters::default(),
+            &contracts(8),
+            2,
+            budget,
+            |_| {},
+        )
+        .await
+        .unwrap();
+
+        assert_eq!((res.response.accounts.len(), res.remaining_chunks), (4, 2));
+        assert_eq!(client.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_chunk_request_timeout() {
+        let client = LatencyClient::new(vec![150]);
+
+        let res = get_state_chunked(
+            &client,
+            &StateRequestParameters::default(),
+            &StateRequestBody::new(None, Version::default()),
+            2,
+            FetchBudget::new(ms(100)),
+            |_| {},
+        )
+        .await;
+
+        assert!(
+            matches!(res, Err(TychoClientError::RequestTimeout { waited }) if waited == ms(100))
+        );
+    }
+
+    /// Serves a single request with `status` and `body`, returning the server's address and
+    /// the request it received.
+    async fn mock_server(status: &str, body: &str) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let response = format!(
+            "HTTP/1.1 {status}\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\
+             connection: close\r\n\r\n{body}",
+            body.len()
+        );
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            // read the head, then as much of the body as its content length announces
+            let complete = |request: &[u8]| {
+                let text = String::from_utf8_lossy(request);
+                let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                    return false;
+                };
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        line.to_lowercase()
+                            .strip_prefix("content-length:")
+                            .map(|length| length.trim().parse::<usize>().unwrap())
+                    })
+                    .unwrap_or(0);
+                body.len() >= length
+            };
+            while !complete(&request) {
+                let read = stream.read(&mut buf).await.unwrap();
+                assert!(read > 0, "connection closed before the request was complete");
+                request.extend_from_slice(&buf[..read]);
+            }
+            stream
+                .write_all(response.as_bytes())
+                .await
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (address, server)
+    }
+
+    #[tokio::test]
+    async fn test_async_client_requests_contract_state() {
+        let (address, server) = mock_server("200 OK", r#"{"accounts": []}"#).await;
+        let client = AsyncTychoHttpClientImpl::new(&address)
+            .unwrap()
+            .with_auth_key("secret".to_string())
+            .unwrap();
+        let filters = StateRequestParameters::default();
+        let request = request(1);
+
+        let response = client
+            .get_state(&filters, &request)
+            .await
+            .unwrap();
+
+        assert_eq!(response, StateRequestResponse::new(vec![]));
+        let received = server.await.unwrap();
+        let (head, body) = received.split_once("\r\n\r\n").unwrap();
+        let head = head.to_lowercase();
+        assert!(head.starts_with(&format!(
+            "post /v1/contract_state?{} http/1.1\r\n",
+            filters.to_query_string()
+        )));
+        assert!(head.contains("\r\nauthorization: secret"));
+        assert!(head.contains("\r\ncontent-type: application/json"));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(body).unwrap(),
+            serde_json::to_value(&request).unwrap()
+        );
+    }
+
+    #[rstest]
+    #[tokio::test]
+    #[case::server_error("500 Internal Server Error", "{}")]
+    #[case::invalid_response("200 OK", "[]")]
+    async fn test_async_client_errors(#[case] status: &str, #[case] body: &str) {
+        let (address, server) = mock_server(status, body).await;
+        let client = AsyncTychoHttpClientImpl::new(&address).unwrap();
+
+        let res = client
+            .get_state(&StateRequestParameters::default(), &request(1))
+            .await;
+
+        server.await.unwrap();
+        match res {
+            Err(TychoClientError::HttpClient(message)) => assert!(message.contains("500")),
+            Err(TychoClientError::ParseResponse(_, Some(_))) => assert!(status.starts_with("200")),
+            other => panic!("Expected an error, got {other:?}"),
+        }
+    }
+
+    fn http_error() -> TychoClientError {
+        TychoClientError::HttpClient("503 Service Unavailable".to_string())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_transient_errors_are_retried() {
+        let inner = FlakyClient::failing_with(vec![
+            http_error(),
+            TychoClientError::RequestTimeout { waited: ms(100) },
+        ]);
+        let client = RetryingTychoHttpClient::new(inner, 3, ms(500));
+        let start = Instant::now();
+
+        let res = client
+            .get_state(&StateRequestParameters::default(), &request(1))
+            .await;
+
+        assert!(res.is_ok(), "{res:?}");
+        assert_eq!(client.inner().calls(), 3);
+        assert_eq!(start.elapsed(), ms(1_000));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries_are_limited() {
+        let inner = FlakyClient::failing_with((0..5).map(|_| http_error()).collect());
+        let client = RetryingTychoHttpClient::new(inner, 2, ms(10));
+
+        let res = client
+            .get_state(&StateRequestParameters::default(), &request(1))
+            .await;
+
+        assert!(matches!(res, Err(TychoClientError::HttpClient(_))), "{res:?}");
+        assert_eq!(client.inner().calls(), 3);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    #[case::parse_response(TychoClientError::ParseResponse("state response".to_string(), None))]
+    #[case::format_request(TychoClientError::FormatRequest("invalid".to_string()))]
+    async fn test_permanent_errors_are_not_retried(#[case] error: TychoClientError) {
+        let client =
+            RetryingTychoHttpClient::new(FlakyClient::failing_with(vec![error]), 3, ms(10));
+
+        let res = client
+            .get_state(&StateRequestParameters::default(), &request(1))
+            .await;
+
+        assert!(res.is_err());
+        assert_eq!(client.inner().calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_async_client_times_out() {
+        // the server accepts the connection but never answers
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let client = AsyncTychoHttpClientImpl::builder(&address)
+            .auth_key("key".to_string())
+            .timeout(ms(100))
+            .build()
+            .unwrap();
+
+        let res = client
+            .get_state(&StateRequestParameters::default(), &request(1))
+            .await;
+
+        assert!(
+            matches!(res, Err(TychoClientError::RequestTimeout { waited }) if waited == ms(100)),
+            "{res:?}"
+        );
+        drop(listener);
+    }
+
+    #[tokio::test]
+    async fn test_async_client_maps_timeout_of_custom_http_client() {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let http_client = reqwest::Client::builder()
+            .timeout(ms(100))
+            .build()
+            .unwrap();
+        let client = AsyncTychoHttpClientImpl::new(&address)
+            .unwrap()
+            .with_http_client(http_client);
+
+        let res = client
+            .get_state(&StateRequestParameters::default(), &request(1))
+            .await;
+
+        assert!(
+            matches!(res, Err(TychoClientError::RequestTimeout { waited }) if waited >= ms(100)),
+            "{res:?}"
+        );
+        drop(listener);
+    }
+
+    #[tokio::test]
+    async fn test_async_client_sends_bearer_token() {
+        let (address, server) = mock_server("200 OK", r#"{"accounts": []}"#).await;
+        let client = AsyncTychoHttpClientImpl::with_auth(&address, "secret").unwrap();
+
+        client
+            .get_state(&StateRequestParameters::default(), &request(1))
+            .await
+            .unwrap();
+
+        let received = server.await.unwrap();
+        let (head, _) = received.split_once("\r\n\r\n").unwrap();
+        assert!(head
+            .to_lowercase()
+            .contains("\r\nauthorization: bearer secret\r\n"));
+    }
+
+    #[test]
+    fn test_async_client_invalid_token() {
+        assert!(matches!(
+            AsyncTychoHttpClientImpl::with_auth("http://localhost:4242", "line\nbreak"),
+            Err(TychoClientError::FormatRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_async_client_invalid_auth_key() {
+        let client = AsyncTychoHttpClientImpl::new("http://localhost:4242").unwrap();
+
+        assert!(matches!(
+            client.with_auth_key("line\nbreak".to_string()),
+            Err(TychoClientError::FormatRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_debug_redacts_authorization() {
+        let builder =
+            AsyncTychoHttpClientImpl::builder("http://localhost:4242").auth_token("secret");
+        let client = builder.clone().build().unwrap();
+
+        assert!(!format!("{builder:?}").contains("secret"));
+        assert!(!format!("{client:?}").contains("secret"));
+    }
+
+    #[test]
+    fn test_async_client_invalid_uri() {
+        assert!(matches!(
+            AsyncTychoHttpClientImpl::new("localhost 4242"),
+            Err(TychoClientError::UriParsing(..))
+        ));
+    }
+}
@@ -0,0 +1,200 @@
+//! Pool state inference from EVM traces.
+//!
+//! When a pool's state isn't available, e.g. because it depends on a transaction in a private
+//! mempool, parts of it can still be recovered from a trace of that transaction. Every `SLOAD` and
+//! `SSTORE` on the pool reveals the value of a storage slot; [`PoolStateInferrer`] maps the slots
+//! of known storage layouts to pool fields and keeps the last value seen for each, i.e. the state
+//! after the traced execution.
+use alloy_primitives::{Address, U256};
+use revm::interpreter::opcode::{SLOAD, SSTORE};
+
+use crate::evm::storage_layout::{
+    bits, UniswapV2Reserves, UniswapV3Slot0, V2_RESERVES_SLOT, V3_LIQUIDITY_SLOT, V3_SLOT0_SLOT,
+};
+
+/// A storage access of a traced execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodeTrace {
+    /// Contract whose storage was accessed.
+    pub address: Address,
+    /// [`SLOAD`] or [`SSTORE`]; other opcodes are ignored.
+    pub opcode: u8,
+    pub slot: U256,
+    /// Value read by an `SLOAD` or written by an `SSTORE`.
+    pub value: U256,
+}
+
+/// The storage layouts state can be inferred for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StorageLayout {
+    UniswapV2,
+    UniswapV3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PoolId {
+    pub address: Address,
+    pub layout: StorageLayout,
+}
+
+/// The fields of a pool's state observed in a trace. Unobserved fields are `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartialPoolState {
+    UniswapV2 { reserve0: U256, reserve1: U256, block_timestamp_last: u32 },
+    UniswapV3 { sqrt_price: Option<U256>, tick: Option<i32>, liquidity: Option<u128> },
+}
+
+pub struct PoolStateInferrer;
+
+impl PoolStateInferrer {
+    /// Infers the state of `pool` after the traced execution.
+    ///
+    /// Returns `None` if the trace doesn't access any known slot of the pool.
+    pub fn infer_from_trace(pool: PoolId, trace: &[OpcodeTrace]) -> Option<PartialPoolState> {
+        let last_value = |slot: U256| {
+            trace
+                .iter()
+                .rev()
+                .find(|step| {
+                    step.address == pool.address &&
+                        step.slot == slot &&
+                        (step.opcode == SLOAD || step.opcode == SSTORE)
+                })
+                .map(|step| step.value)
+        };
+
+        match pool.layout {
+            StorageLayout::UniswapV2 => {
+                let reserves = UniswapV2Reserves::unpack(last_value(V2_RESERVES_SLOT)?);
+                Some(PartialPoolState::UniswapV2 {
+                    reserve0: reserves.reserve0,
+                    reserve1: reserves.reserve1,
+                    block_timestamp_last: reserves.block_timestamp_last,
+                })
+            }
+            StorageLayout::UniswapV3 => {
+                let slot0 = last_value(V3_SLOT0_SLOT).map(UniswapV3Slot0::unpack);
+                let liquidity = last_value(V3_LIQUIDITY_SLOT);
+                if slot0.is_none() && liquidity.is_none() {
+                    return None;
+                }
+                Some(PartialPoolState::UniswapV3 {
+                    sqrt_price: slot0.map(|slot0| slot0.sqrt_price),
+                    tick: slot0.map(|slot0| slot0.tick),
+                    liquidity: liquidity.map(|liquidity| bits(liquidity, 0, 128).to::<u128>()),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    const POOL: Address = Address::new([0x11; 20]);
+    const OTHER: Address = Address::new([0x22; 20]);
+
+    fn access(address: Address, opcode: u8, slot: U256, value: U256) -> OpcodeTrace {
+        OpcodeTrace { address, opcode, slot, value }
+    }
+
+    fn v2_reserves(reserve0: u64, reserve1: u64, timestamp: u32) -> U256 {
+        UniswapV2Reserves {
+            reserve0: U256::from(reserve0),
+            reserve1: U256::from(reserve1),
+            block_timestamp_last: timestamp,
+        }
+        .pack()
+    }
+
+    fn v3_slot0(sqrt_price: U256, tick: i32) -> U256 {
+        UniswapV3Slot0 {
+            sqrt_price,
+            tick,
+            observation_index: 5,
+            observation_cardinality: 100,
+            observation_cardinality_next: 100,
+            fee_protocol: 0,
+            unlocked: true,
+        }
+        .pack()
+    }
+
+    #[test]
+    fn test_infer_v2_reserves_after_swap() {
+        let pool = PoolId { address: POOL, layout: StorageLayout::UniswapV2 };
+        let trace = [
+            access(POOL, SLOAD, V2_RESERVES_SLOT, v2_reserves(1_000, 2_000, 100)),
+            access(OTHER, SSTORE, V2_RESERVES_SLOT, v2_reserves(1, 1, 1)),
+            access(POOL, SSTORE, V2_RESERVES_SLOT, v2_reserves(1_100, 1_819, 112)),
+            access(POOL, SLOAD, U256::from(12u64), U256::from(1u64)),
+        ];
+
+        let state = PoolStateInferrer::infer_from_trace(pool, &trace);
+
+        assert_eq!(
+            state,
+            Some(PartialPoolState::UniswapV2 {
+                reserve0: U256::from(1_100u64),
+                reserve1: U256::from(1_819u64),
+                block_timestamp_last: 112
+            })
+        );
+    }
+
+    #[rstest]
+    #[case::positive_tick(200_311)]
+    #[case::negative_tick(-887_272)]
+    fn test_infer_v3_state(#[case] tick: i32) {
+        let pool = PoolId { address: POOL, layout: StorageLayout::UniswapV3 };
+        let sqrt_price =
+            U256::from_str("1461446703485210103287273052203988822378723970341").unwrap();
+        let trace = [
+            access(POOL, SLOAD, V3_SLOT0_SLOT, v3_slot0(sqrt_price, tick)),
+            access(POOL, SLOAD, V3_LIQUIDITY_SLOT, U256::from(123_456_789u64)),
+        ];
+
+        let state = PoolStateInferrer::infer_from_trace(pool, &trace);
+
+        assert_eq!(
+            state,
+            Some(PartialPoolState::UniswapV3 {
+                sqrt_price: Some(sqrt_price),
+                tick: Some(tick),
+                liquidity: Some(123_456_789)
+            })
+        );
+    }
+
+    #[test]
+    fn test_infer_v3_partial_state() {
+        let pool = PoolId { address: POOL, layout: StorageLayout::UniswapV3 };
+        let trace = [access(POOL, SLOAD, V3_LIQUIDITY_SLOT, U256::from(42u64))];
+
+        let state = PoolStateInferrer::infer_from_trace(pool, &trace);
+
+        assert_eq!(
+            state,
+            Some(PartialPoolState::UniswapV3 { sqrt_price: None, tick: None, liquidity: Some(42) })
+        );
+    }
+
+    #[rstest]
+    #[case::v2(StorageLayout::UniswapV2)]
+    #[case::v3(StorageLayout::UniswapV3)]
+    fn test_infer_without_pool_accesses(#[case] layout: StorageLayout) {
+        let pool = PoolId { address: POOL, layout };
+        let trace = [
+            access(OTHER, SLOAD, V3_SLOT0_SLOT, U256::from(1u64)),
+            access(OTHER, SLOAD, V2_RESERVES_SLOT, U256::from(1u64)),
+            access(POOL, SLOAD, U256::from(7u64), U256::from(1u64)),
+        ];
+
+        assert_eq!(PoolStateInferrer::infer_from_trace(pool, &trace), None);
+    }
+}
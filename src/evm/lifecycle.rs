@@ -0,0 +1,260 @@
+//! Lifecycle transitions of a client, published for programmatic consumers.
+//!
+//! Every transition is logged; with a broadcast channel set through [`Lifecycle::with_events`] it
+//! is also published as a [`LifecycleEvent`], so downstream services can react to a client
+//! reconnecting or falling behind without scraping logs. A transition is published once per
+//! change of the [`LifecycleState`]: repeating the current state publishes nothing.
+//!
+//! Both clients publish to a lifecycle: a
+//! [`SubscriptionSession`](super::subscription::SubscriptionSession) reports every transition of
+//! its own connection, while the protocol stream, whose connection is managed by tycho-client,
+//! reports the transitions visible in the messages it delivers through [`Lifecycle::observe`].
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+use tycho_client::feed::{FeedMessage, SynchronizerState};
+use uuid::Uuid;
+
+use super::tycho_models::ExtractorIdentity;
+
+/// Capacity of the channel created by [`Lifecycle::channel`].
+pub const DEFAULT_LIFECYCLE_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LifecycleEvent {
+    /// The connection to the server at `address` was established.
+    Connected { address: String },
+    /// The server confirmed the subscription to `extractor`.
+    Subscribed { extractor: ExtractorIdentity, subscription_id: Uuid },
+    /// The snapshot at `block` was delivered.
+    SnapshotApplied { block: u64 },
+    /// The block `block` was delivered and the extractor reports no later block, i.e. all
+    /// replayed deltas have been delivered.
+    CaughtUp { block: u64 },
+    /// The connection was lost, `attempt` counts the attempts to reconnect since.
+    Reconnecting { attempt: u32, reason: String },
+    /// The extractor reported that it isn't synced with the chain.
+    Degraded { reason: String },
+    /// The client stopped for good.
+    ShutDown { reason: String },
+}
+
+/// The state a [`LifecycleEvent`] transitions to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LifecycleState {
+    Connected,
+    Subscribed,
+    SnapshotApplied,
+    CaughtUp,
+    Reconnecting { attempt: u32 },
+    Degraded,
+    ShutDown,
+}
+
+impl LifecycleEvent {
+    pub fn state(&self) -> LifecycleState {
+        match self {
+            LifecycleEvent::Connected { .. } => LifecycleState::Connected,
+            LifecycleEvent::Subscribed { .. } => LifecycleState::Subscribed,
+            LifecycleEvent::SnapshotApplied { .. } => LifecycleState::SnapshotApplied,
+            LifecycleEvent::CaughtUp { .. } => LifecycleState::CaughtUp,
+            LifecycleEvent::Reconnecting { attempt, .. } => {
+                LifecycleState::Reconnecting { attempt: *attempt }
+            }
+            LifecycleEvent::Degraded { .. } => LifecycleState::Degraded,
+            LifecycleEvent::ShutDown { .. } => LifecycleState::ShutDown,
+        }
+    }
+}
+
+/// Tracks the lifecycle state of a client and publishes its transitions.
+///
+/// Clones share the state and the channel.
+#[derive(Debug, Clone, Default)]
+pub struct Lifecycle {
+    state: Arc<Mutex<Option<LifecycleState>>>,
+    events: Option<broadcast::Sender<LifecycleEvent>>,
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a lifecycle publishing to a new channel of [`DEFAULT_LIFECYCLE_CAPACITY`].
+    pub fn channel() -> (Self, broadcast::Receiver<LifecycleEvent>) {
+        let (events, receiver) = broadcast::channel(DEFAULT_LIFECYCLE_CAPACITY);
+        (Self::new().with_events(events), receiver)
+    }
+
+    /// Publishes transitions to `events`.
+    pub fn with_events(mut self, events: broadcast::Sender<LifecycleEvent>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// A new receiver of the transitions published from now on, `None` without a channel.
+    pub fn subscribe(&self) -> Option<broadcast::Receiver<LifecycleEvent>> {
+        self.events
+            .as_ref()
+            .map(broadcast::Sender::subscribe)
+    }
+
+    /// The current state, `None` before the first transition.
+    pub fn state(&self) -> Option<LifecycleState> {
+        *self.state.lock().unwrap()
+    }
+
+    /// Transitions to the state of `event` and publishes it.
+    ///
+    /// Returns whether the state changed; an event for the current state is dropped.
+    pub fn publish(&self, event: LifecycleEvent) -> bool {
+        let state = event.state();
+        {
+            let mut current = self.state.lock().unwrap();
+            if *current == Some(state) {
+                return false;
+            }
+            *current = Some(state);
+        }
+        match &event {
+            LifecycleEvent::Reconnecting { .. } | LifecycleEvent::Degraded { .. } => {
+                warn!(?event, "LifecycleTransition")
+            }
+            _ => info!(?event, "LifecycleTransition"),
+        }
+        if let Some(events) = &self.events {
+            // Sending only fails without receivers, i.e. when nobody listens.
+            let _ = events.send(event);
+        }
+        true
+    }
+
+    /// Publishes the transitions reported by `msg`, once it is delivered.
+    ///
+    /// `SnapshotApplied` if `msg` contains a snapshot, then `Degraded` if an extractor is delayed
+    /// or stale, or `CaughtUp` once all extractors are ready.
+    pub fn observe(&self, msg: &FeedMessage) {
+        let Some(block) = msg
+            .state_msgs
+            .values()
+            .map(|protocol_msg| protocol_msg.header.number)
+            .max()
+        else {
+            return;
+        };
+        if msg
+            .state_msgs
+            .values()
+            .any(|protocol_msg| {
+                !protocol_msg
+                    .snapshots
+                    .get_states()
+                    .is_empty()
+            })
+        {
+            self.publish(LifecycleEvent::SnapshotApplied { block });
+        }
+
+        let mut lagging: Vec<_> = msg
+            .sync_states
+            .iter()
+            .filter(|(_, state)| {
+                matches!(state, SynchronizerState::Delayed(_) | SynchronizerState::Stale(_))
+            })
+            .map(|(extractor, state)| format!("{extractor} is {state:?}"))
+            .collect();
+        if !lagging.is_empty() {
+            lagging.sort();
+            self.publish(LifecycleEvent::Degraded { reason: lagging.join(", ") });
+        } else if !msg.sync_states.is_empty() &&
+            msg.sync_states
+                .values()
+                .all(|state| matches!(state, SynchronizerState::Ready(_)))
+        {
+            self.publish(LifecycleEvent::CaughtUp { block });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::*;
+
+    fn load_msg(name: &str) -> serde_json::Value {
+        let path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join(format!("tests/assets/decoder/{name}.json"));
+        serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_observe_feed_messages() {
+        let (lifecycle, mut events) = Lifecycle::channel();
+        let snapshot: FeedMessage =
+            serde_json::from_value(load_msg("uniswap_v2_snapshot")).unwrap();
+        let delta: FeedMessage = serde_json::from_value(load_msg("uniswap_v2_delta")).unwrap();
+        let mut stale = load_msg("uniswap_v2_delta");
+        stale["sync_states"]["uniswap_v2"]["status"] = "stale".into();
+        let stale: FeedMessage = serde_json::from_value(stale).unwrap();
+
+        for msg in [&snapshot, &delta, &stale, &delta] {
+            lifecycle.observe(msg);
+        }
+
+        let mut published = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            published.push(event);
+        }
+        assert_eq!(
+            published,
+            vec![
+                LifecycleEvent::SnapshotApplied { block: 21284145 },
+                LifecycleEvent::CaughtUp { block: 21284145 },
+                LifecycleEvent::Degraded {
+                    reason: format!("uniswap_v2 is {:?}", stale.sync_states["uniswap_v2"]),
+                },
+                LifecycleEvent::CaughtUp { block: 21284148 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_publish_once_per_state_change() {
+        let (lifecycle, mut events) = Lifecycle::channel();
+
+        assert!(lifecycle.publish(LifecycleEvent::CaughtUp { block: 1 }));
+        assert!(!lifecycle.publish(LifecycleEvent::CaughtUp { block: 2 }));
+        assert!(lifecycle
+            .publish(LifecycleEvent::Reconnecting { attempt: 1, reason: "closed".to_string() }));
+        assert!(lifecycle
+            .publish(LifecycleEvent::Reconnecting { attempt: 2, reason: "closed".to_string() }));
+        assert!(lifecycle.publish(LifecycleEvent::CaughtUp { block: 3 }));
+
+        let mut published = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            published.push(event.state());
+        }
+        assert_eq!(
+            published,
+            vec![
+                LifecycleState::CaughtUp,
+                LifecycleState::Reconnecting { attempt: 1 },
+                LifecycleState::Reconnecting { attempt: 2 },
+                LifecycleState::CaughtUp,
+            ]
+        );
+        assert_eq!(lifecycle.state(), Some(LifecycleState::CaughtUp));
+    }
+
+    #[test]
+    fn test_publish_without_channel() {
+        let lifecycle = Lifecycle::new();
+
+        assert!(lifecycle.subscribe().is_none());
+        assert!(lifecycle.publish(LifecycleEvent::Degraded { reason: "lagging".to_string() }));
+        assert_eq!(lifecycle.clone().state(), Some(LifecycleState::Degraded));
+    }
+}
@@ -0,0 +1,237 @@
+//! Liveness of the extractors a client is subscribed to.
+//!
+//! Extractors periodically send a heartbeat with their sync status, whether or not they have new
+//! block changes. Heartbeats carry no state, so they are consumed by a [`LivenessTracker`] instead
+//! of being forwarded with the block changes. Consumers learn about status transitions, like an
+//! extractor falling behind the chain, through [`LivenessEvent`]s.
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::Instant,
+};
+
+use serde::Serialize;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{info, warn};
+
+use crate::evm::{
+    tycho_models::{ExtractorStatus, SyncStatus},
+    watchdog::AlertLevel,
+};
+
+/// The status of an extractor changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LivenessEvent {
+    pub extractor: String,
+    /// The previous status, `None` for the first heartbeat of the extractor.
+    pub previous: Option<SyncStatus>,
+    pub current: SyncStatus,
+    pub latest_block: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExtractorLiveness {
+    pub status: SyncStatus,
+    pub latest_block: u64,
+    pub seconds_since_heartbeat: u64,
+}
+
+/// Liveness of all extractors, as exposed to stats consumers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LivenessStats {
+    pub alert_level: AlertLevel,
+    pub extractors: BTreeMap<String, ExtractorLiveness>,
+}
+
+#[derive(Debug)]
+struct ExtractorState {
+    status: SyncStatus,
+    latest_block: u64,
+    last_heartbeat: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct LivenessTracker {
+    extractors: Mutex<HashMap<String, ExtractorState>>,
+    events: Option<UnboundedSender<LivenessEvent>>,
+}
+
+impl LivenessTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends status transitions to `events`.
+    pub fn with_events(mut self, events: UnboundedSender<LivenessEvent>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Records a heartbeat and returns the status transition it caused, if any.
+    pub fn heartbeat(&self, status: &ExtractorStatus) -> Option<LivenessEvent> {
+        self.heartbeat_at(status, Instant::now())
+    }
+
+    fn heartbeat_at(&self, status: &ExtractorStatus, now: Instant) -> Option<LivenessEvent> {
+        let previous = self
+            .extractors
+            .lock()
+            .unwrap()
+            .insert(
+                status.extractor.clone(),
+                ExtractorState {
+                    status: status.status.clone(),
+                    latest_block: status.latest_block,
+                    last_heartbeat: now,
+                },
+            )
+            .map(|state| state.status);
+        if previous.as_ref() == Some(&status.status) {
+            return None;
+        }
+
+        match &status.status {
+            SyncStatus::Synced => {
+                info!(extractor = status.extractor, ?previous, "ExtractorSynced")
+            }
+            current => warn!(
+                extractor = status.extractor,
+                ?previous,
+                ?current,
+                latest_block = status.latest_block,
+                "ExtractorNotSynced"
+            ),
+        }
+        let event = LivenessEvent {
+            extractor: status.extractor.clone(),
+            previous,
+            current: status.status.clone(),
+            latest_block: status.latest_block,
+        };
+        if let Some(events) = &self.events {
+            // A dropped receiver only means nobody listens to transitions anymore.
+            let _ = events.send(event.clone());
+        }
+        Some(event)
+    }
+
+    /// The last reported status of `extractor`, `None` before its first heartbeat.
+    pub fn status(&self, extractor: &str) -> Option<SyncStatus> {
+        self.extractors
+            .lock()
+            .unwrap()
+            .get(extractor)
+            .map(|state| state.status.clone())
+    }
+
+    /// `AlertLevel::Warning` if any extractor is not synced, `AlertLevel::Healthy` otherwise.
+    pub fn alert_level(&self) -> AlertLevel {
+        let all_synced = self
+            .extractors
+            .lock()
+            .unwrap()
+            .values()
+            .all(|state| state.status == SyncStatus::Synced);
+        if all_synced {
+            AlertLevel::Healthy
+        } else {
+            AlertLevel::Warning
+        }
+    }
+
+    pub fn stats(&self) -> LivenessStats {
+        self.stats_at(Instant::now())
+    }
+
+    fn stats_at(&self, now: Instant) -> LivenessStats {
+        let extractors = self
+            .extractors
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, state)| {
+                (
+                    name.clone(),
+                    ExtractorLiveness {
+                        status: state.status.clone(),
+                        latest_block: state.latest_block,
+                        seconds_since_heartbeat: now
+                            .saturating_duration_since(state.last_heartbeat)
+                            .as_secs(),
+                    },
+                )
+            })
+            .collect();
+        LivenessStats { alert_level: self.alert_level(), extractors }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::evm::tycho_models::Chain;
+
+    fn status(extractor: &str, status: SyncStatus, latest_block: u64) -> ExtractorStatus {
+        ExtractorStatus {
+            extractor: extractor.to_string(),
+            chain: Chain::Ethereum,
+            status,
+            latest_block,
+        }
+    }
+
+    #[test]
+    fn test_transitions() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let tracker = LivenessTracker::new().with_events(tx);
+
+        tracker.heartbeat(&status("vm:ambient", SyncStatus::Synced, 100));
+        tracker.heartbeat(&status("vm:ambient", SyncStatus::Synced, 101));
+        tracker.heartbeat(&status("vm:ambient", SyncStatus::Lagging, 101));
+        assert_eq!(tracker.alert_level(), AlertLevel::Warning);
+        tracker.heartbeat(&status("vm:ambient", SyncStatus::Synced, 104));
+        drop(tracker);
+
+        let mut events = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            events.push((event.previous, event.current, event.latest_block));
+        }
+        assert_eq!(
+            events,
+            vec![
+                (None, SyncStatus::Synced, 100),
+                (Some(SyncStatus::Synced), SyncStatus::Lagging, 101),
+                (Some(SyncStatus::Lagging), SyncStatus::Synced, 104),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        let t0 = Instant::now();
+        let tracker = LivenessTracker::new();
+        tracker.heartbeat_at(&status("uniswap_v2", SyncStatus::Synced, 100), t0);
+        tracker.heartbeat_at(
+            &status("vm:ambient", SyncStatus::Other("catching_up".to_string()), 90),
+            t0 + Duration::from_secs(5),
+        );
+
+        let stats = tracker.stats_at(t0 + Duration::from_secs(12));
+
+        assert_eq!(stats.alert_level, AlertLevel::Warning);
+        assert_eq!(
+            stats.extractors["uniswap_v2"],
+            ExtractorLiveness {
+                status: SyncStatus::Synced,
+                latest_block: 100,
+                seconds_since_heartbeat: 12
+            }
+        );
+        assert_eq!(stats.extractors["vm:ambient"].seconds_since_heartbeat, 7);
+        assert_eq!(tracker.status("unknown"), None);
+    }
+}
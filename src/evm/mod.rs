@@ -1,14 +1,44 @@
 use alloy_primitives::U256;
 use tycho_common::keccak256;
 
+pub mod abi;
 pub mod account_storage;
+pub mod backend_override;
+pub mod block_ordering;
+pub mod block_time;
+pub mod bloom;
+pub mod catch_up;
+pub mod clock;
+pub mod confirmation;
 pub mod decoder;
+pub mod deploy;
 pub mod engine_db;
+pub mod events;
+pub mod freshness;
+pub mod http_client;
+pub mod inferrer;
+pub mod lifecycle;
+pub mod liveness;
+pub mod monitoring;
+pub mod pending_block;
+pub mod pipeline_config;
 pub mod protocol;
+pub mod revision;
+#[cfg(any(test, feature = "testing"))]
+pub mod scenario;
+pub mod self_test;
+pub mod sequence;
+#[doc(hidden)]
 pub mod simulation;
+pub mod storage_layout;
 pub mod stream;
+pub mod subscription;
+pub mod token_gas;
 pub mod traces;
+pub mod transport;
 pub mod tycho_models;
+pub mod warmup;
+pub mod watchdog;
 
 pub type SlotId = U256;
 
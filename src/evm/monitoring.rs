@@ -0,0 +1,187 @@
+//! Detection of missed blocks.
+//!
+//! Blocks that never arrive, or arrive late, point to a flaky websocket connection or a problem
+//! with the chain itself. Unlike the [`PipelineWatchdog`](crate::evm::watchdog::PipelineWatchdog),
+//! which notices a stream that stopped entirely, [`BlockGapMonitor`] inspects every received
+//! block and warns when it skipped too many block numbers or took too long to arrive.
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tracing::warn;
+use tycho_common::models::Chain;
+
+use crate::evm::block_time::ChainBlockTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockGapConfig {
+    /// Number of skipped block numbers tolerated between two received blocks.
+    pub max_missed_blocks: u64,
+    /// Time tolerated between two received blocks.
+    pub max_block_gap_duration: Duration,
+}
+
+impl BlockGapConfig {
+    /// Tolerates a single missed block, and up to three block times between two blocks.
+    pub fn for_chain(chain: Chain) -> Self {
+        Self {
+            max_missed_blocks: 1,
+            max_block_gap_duration: ChainBlockTime::blocks_to_duration(chain, 3),
+        }
+    }
+}
+
+/// An unusual gap before a received block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockGap {
+    /// Block numbers between `previous` and `received` were never received.
+    MissedBlocks { previous: u64, received: u64, missed: u64 },
+    /// The block arrived `elapsed` after the previous one.
+    Delayed { received: u64, elapsed: Duration },
+}
+
+/// Gap statistics, as exposed to stats consumers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct GapStats {
+    /// Block numbers skipped over the lifetime of the monitor.
+    pub total_missed: u64,
+    /// Most block numbers skipped at once.
+    pub max_gap: u64,
+    /// Latest block received without an unusual gap.
+    pub last_normal_block: u64,
+}
+
+#[derive(Debug, Default)]
+struct GapState {
+    last: Option<(u64, Instant)>,
+    stats: GapStats,
+}
+
+#[derive(Debug)]
+pub struct BlockGapMonitor {
+    config: BlockGapConfig,
+    state: Mutex<GapState>,
+}
+
+impl BlockGapMonitor {
+    pub fn new(config: BlockGapConfig) -> Self {
+        Self { config, state: Mutex::new(GapState::default()) }
+    }
+
+    /// Records a received block and returns the unusual gaps before it, each of which is also
+    /// logged as a warning.
+    ///
+    /// Blocks at or below the previous block number, e.g. after a revert, are never reported as
+    /// a gap.
+    pub fn block_received(&self, block_number: u64) -> Vec<BlockGap> {
+        self.block_received_at(block_number, Instant::now())
+    }
+
+    fn block_received_at(&self, block_number: u64, now: Instant) -> Vec<BlockGap> {
+        let mut state = self.state.lock().unwrap();
+        let mut gaps = Vec::new();
+        if let Some((previous, received_at)) = state.last {
+            let missed = block_number.saturating_sub(previous.saturating_add(1));
+            state.stats.total_missed += missed;
+            state.stats.max_gap = state.stats.max_gap.max(missed);
+            if missed > self.config.max_missed_blocks {
+                warn!(previous, received = block_number, missed, "Blocks missed");
+                gaps.push(BlockGap::MissedBlocks { previous, received: block_number, missed });
+            }
+            let elapsed = now.saturating_duration_since(received_at);
+            if block_number > previous && elapsed > self.config.max_block_gap_duration {
+                warn!(received = block_number, ?elapsed, "Block arrived late");
+                gaps.push(BlockGap::Delayed { received: block_number, elapsed });
+            }
+        }
+        if gaps.is_empty() {
+            state.stats.last_normal_block = block_number;
+        }
+        state.last = Some((block_number, now));
+        gaps
+    }
+
+    pub fn stats(&self) -> GapStats {
+        self.state.lock().unwrap().stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: BlockGapConfig =
+        BlockGapConfig { max_missed_blocks: 2, max_block_gap_duration: Duration::from_secs(30) };
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn test_consecutive_blocks() {
+        let t0 = Instant::now();
+        let monitor = BlockGapMonitor::new(CONFIG);
+
+        for (i, block) in (100..110).enumerate() {
+            assert_eq!(monitor.block_received_at(block, t0 + secs(12 * i as u64)), vec![]);
+        }
+
+        assert_eq!(
+            monitor.stats(),
+            GapStats { total_missed: 0, max_gap: 0, last_normal_block: 109 }
+        );
+    }
+
+    #[test]
+    fn test_five_block_gap() {
+        let t0 = Instant::now();
+        let monitor = BlockGapMonitor::new(CONFIG);
+        monitor.block_received_at(100, t0);
+        monitor.block_received_at(101, t0 + secs(12));
+
+        let gaps = monitor.block_received_at(107, t0 + secs(24));
+
+        assert_eq!(gaps, vec![BlockGap::MissedBlocks { previous: 101, received: 107, missed: 5 }]);
+        assert_eq!(
+            monitor.stats(),
+            GapStats { total_missed: 5, max_gap: 5, last_normal_block: 101 }
+        );
+    }
+
+    #[test]
+    fn test_tolerated_gap_is_counted() {
+        let t0 = Instant::now();
+        let monitor = BlockGapMonitor::new(CONFIG);
+        monitor.block_received_at(100, t0);
+
+        assert_eq!(monitor.block_received_at(103, t0 + secs(12)), vec![]);
+        assert_eq!(
+            monitor.stats(),
+            GapStats { total_missed: 2, max_gap: 2, last_normal_block: 103 }
+        );
+    }
+
+    #[test]
+    fn test_delayed_block() {
+        let t0 = Instant::now();
+        let monitor = BlockGapMonitor::new(CONFIG);
+        monitor.block_received_at(100, t0);
+
+        let gaps = monitor.block_received_at(101, t0 + secs(45));
+
+        assert_eq!(gaps, vec![BlockGap::Delayed { received: 101, elapsed: secs(45) }]);
+    }
+
+    #[test]
+    fn test_revert_is_not_a_gap() {
+        let t0 = Instant::now();
+        let monitor = BlockGapMonitor::new(CONFIG);
+        monitor.block_received_at(100, t0);
+
+        assert_eq!(monitor.block_received_at(99, t0 + secs(60)), vec![]);
+        assert_eq!(monitor.block_received_at(100, t0 + secs(72)), vec![]);
+        assert_eq!(monitor.stats().total_missed, 0);
+    }
+}
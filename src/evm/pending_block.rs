@@ -0,0 +1,513 @@
+//! Quoting on top of pending transactions.
+//!
+//! [`PendingBlockSimulator`] executes transactions that are expected to land before a swap, e.g.
+//! pending mempool transactions, one after the other on a database snapshot. Their state changes
+//! are collected in an overlay that is never written to the snapshot;
+//! [`PendingBlockSimulator::state`] exposes the snapshot with the overlay applied. Pools whose
+//! contracts were touched are then rebuilt against that state with [`rebuild_pools`] to quote "as
+//! if" the transactions landed.
+//!
+//! The overlay tracks storage and balances. Contracts deployed by pending transactions are not
+//! tracked.
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+};
+
+use revm::{
+    primitives::{Address, U256},
+    DatabaseRef,
+};
+use thiserror::Error;
+use tracing::{debug, warn};
+
+use crate::{
+    evm::{
+        engine_db::{
+            engine_db_interface::{EngineDatabaseError, EngineDatabaseInterface},
+            simulation_db::OverriddenSimulationDB,
+        },
+        simulation::{
+            SimulationEngine, SimulationEngineError, SimulationParameters, SimulationResult,
+        },
+    },
+    protocol::{errors::SimulationError, state::ProtocolSim},
+};
+
+/// Gas limit of a block, unless configured otherwise.
+pub const DEFAULT_BLOCK_GAS_LIMIT: u64 = 30_000_000;
+
+#[derive(Error, Debug, Clone)]
+pub enum PendingBlockError {
+    #[error("Pending transaction {index} failed: {source}")]
+    TransactionFailed {
+        index: usize,
+        #[source]
+        source: SimulationEngineError,
+    },
+    #[error("Pending transaction {index} needs {gas_used} gas, but only {remaining} is left")]
+    BlockGasLimitExceeded { index: usize, gas_used: u64, remaining: u64 },
+}
+
+/// What to do when a pending transaction fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PendingFailurePolicy {
+    /// Leave the transaction out and continue with the next one.
+    #[default]
+    Skip,
+    /// Abort, leaving the state as it was before the batch.
+    Fatal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PendingBlockConfig {
+    pub block_gas_limit: u64,
+    pub on_failure: PendingFailurePolicy,
+}
+
+impl Default for PendingBlockConfig {
+    fn default() -> Self {
+        Self { block_gas_limit: DEFAULT_BLOCK_GAS_LIMIT, on_failure: PendingFailurePolicy::Skip }
+    }
+}
+
+/// Changes of the pending transactions applied so far.
+#[derive(Debug, Clone, Default)]
+struct Overlay {
+    storage: HashMap<Address, HashMap<U256, U256>>,
+    balances: HashMap<Address, U256>,
+    sent: HashMap<Address, u64>,
+    touched: HashSet<Address>,
+    gas_used: u64,
+}
+
+impl Overlay {
+    fn apply(&mut self, caller: Address, result: &SimulationResult) {
+        for (address, update) in &result.state_updates {
+            if let Some(balance) = update.balance {
+                self.balances.insert(*address, balance);
+            }
+            if let Some(slots) = &update.storage {
+                self.storage
+                    .entry(*address)
+                    .or_default()
+                    .extend(slots);
+                self.touched.insert(*address);
+            }
+        }
+        *self.sent.entry(caller).or_default() += 1;
+        self.gas_used += result.gas_used;
+    }
+}
+
+/// Executes pending transactions on a database snapshot without modifying it.
+#[derive(Debug)]
+pub struct PendingBlockSimulator<D: EngineDatabaseInterface + Clone + Debug>
+where
+    <D as DatabaseRef>::Error: EngineDatabaseError,
+    <D as EngineDatabaseInterface>::Error: Debug,
+{
+    engine: SimulationEngine<D>,
+    config: PendingBlockConfig,
+    overlay: Overlay,
+}
+
+impl<D: EngineDatabaseInterface + Clone + Debug> PendingBlockSimulator<D>
+where
+    <D as DatabaseRef>::Error: EngineDatabaseError,
+    <D as EngineDatabaseInterface>::Error: Debug,
+{
+    pub fn new(db_snapshot: D) -> Self {
+        Self {
+            engine: SimulationEngine::new(db_snapshot, false),
+            config: PendingBlockConfig::default(),
+            overlay: Overlay::default(),
+        }
+    }
+
+    pub fn with_config(mut self, config: PendingBlockConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Executes `txs` in order, each one on the state left by the previous ones.
+    ///
+    /// Returns one result per transaction. Transactions that fail, or that don't fit into the
+    /// remaining block gas, are left out under [`PendingFailurePolicy::Skip`]. Under
+    /// [`PendingFailurePolicy::Fatal`] the first such transaction aborts the batch and none of
+    /// its transactions are applied.
+    ///
+    /// Each transaction is sent with its caller's nonce after the previously applied transactions,
+    /// any nonce set on it is ignored. Overrides of a transaction's parameters take precedence
+    /// over the pending state, for that transaction only.
+    pub fn apply_pending(
+        &mut self,
+        txs: &[SimulationParameters],
+    ) -> Result<Vec<Result<SimulationResult, PendingBlockError>>, PendingBlockError> {
+        let mut overlay = self.overlay.clone();
+        let mut results = Vec::with_capacity(txs.len());
+        for (index, tx) in txs.iter().enumerate() {
+            let res = self
+                .simulate_on(&overlay, tx)
+                .map_err(|source| PendingBlockError::TransactionFailed { index, source })
+                .and_then(|result| {
+                    let remaining = self
+                        .config
+                        .block_gas_limit
+                        .saturating_sub(overlay.gas_used);
+                    if result.gas_used > remaining {
+                        return Err(PendingBlockError::BlockGasLimitExceeded {
+                            index,
+                            gas_used: result.gas_used,
+                            remaining,
+                        });
+                    }
+                    Ok(result)
+                });
+            match res {
+                Ok(result) => {
+                    debug!(index, gas_used = result.gas_used, "Applied pending transaction");
+                    overlay.apply(tx.caller, &result);
+                    results.push(Ok(result));
+                }
+                Err(err) => match self.config.on_failure {
+                    PendingFailurePolicy::Skip => {
+                        warn!(%err, "Skipping pending transaction");
+                        results.push(Err(err));
+                    }
+                    PendingFailurePolicy::Fatal => return Err(err),
+                },
+            }
+        }
+        self.overlay = overlay;
+        Ok(results)
+    }
+
+    fn simulate_on(
+        &self,
+        overlay: &Overlay,
+        tx: &SimulationParameters,
+    ) -> Result<SimulationResult, SimulationEngineError> {
+        let mut overrides = overlay.storage.clone();
+        for (address, slots) in tx.overrides.iter().flatten() {
+            overrides
+                .entry(*address)
+                .or_default()
+                .extend(slots);
+        }
+        let mut balance_overrides = overlay.balances.clone();
+        balance_overrides.extend(tx.balance_overrides.iter().flatten());
+        self.engine
+            .simulate(&SimulationParameters {
+                caller: tx.caller,
+                to: tx.to,
+                data: tx.data.clone(),
+                value: tx.value,
+                overrides: Some(overrides),
+                balance_overrides: Some(balance_overrides),
+                nonce: Some(self.nonce_on(overlay, tx.caller)),
+                gas_limit: tx.gas_limit,
+                block_number: tx.block_number,
+                timestamp: tx.timestamp,
+            })
+    }
+
+    /// The snapshot with the changes of all applied pending transactions.
+    pub fn state(&self) -> OverriddenSimulationDB<'_, D> {
+        OverriddenSimulationDB::new(&self.engine.state, &self.overlay.storage)
+            .with_balance_overrides(&self.overlay.balances)
+    }
+
+    /// The unmodified snapshot.
+    pub fn base_state(&self) -> &D {
+        &self.engine.state
+    }
+
+    /// Accounts whose storage was changed by the applied pending transactions.
+    pub fn touched_accounts(&self) -> &HashSet<Address> {
+        &self.overlay.touched
+    }
+
+    /// Gas used by the applied pending transactions.
+    pub fn gas_used(&self) -> u64 {
+        self.overlay.gas_used
+    }
+
+    /// The nonce of `address` after the applied pending transactions.
+    pub fn nonce(&self, address: Address) -> u64 {
+        self.nonce_on(&self.overlay, address)
+    }
+
+    fn nonce_on(&self, overlay: &Overlay, address: Address) -> u64 {
+        let base = self
+            .engine
+            .state
+            .basic_ref(address)
+            .ok()
+            .flatten()
+            .map_or(0, |info| info.nonce);
+        base + overlay
+            .sent
+            .get(&address)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Discards all applied pending transactions.
+    pub fn reset(&mut self) {
+        self.overlay = Overlay::default();
+    }
+}
+
+/// Rebuilds the pools affected by `touched_accounts` against `db`.
+///
+/// `contracts` maps contract addresses to the ids of the pools they affect. Only those pools are
+/// passed to `rebuild`, along with their current state; all other pools are left alone. Returns
+/// the rebuilt states by pool id.
+pub fn rebuild_pools<DB, F>(
+    states: &HashMap<String, Box<dyn ProtocolSim>>,
+    contracts: &HashMap<Address, HashSet<String>>,
+    touched_accounts: &HashSet<Address>,
+    db: &DB,
+    rebuild: F,
+) -> Result<HashMap<String, Box<dyn ProtocolSim>>, SimulationError>
+where
+    DB: DatabaseRef,
+    F: Fn(&str, &dyn ProtocolSim, &DB) -> Result<Box<dyn ProtocolSim>, SimulationError>,
+{
+    let affected: HashSet<&String> = touched_accounts
+        .iter()
+        .filter_map(|address| contracts.get(address))
+        .flatten()
+        .collect();
+    let mut rebuilt = HashMap::with_capacity(affected.len());
+    for id in affected {
+        let Some(state) = states.get(id) else {
+            continue;
+        };
+        rebuilt.insert(id.clone(), rebuild(id, state.as_ref(), db)?);
+    }
+    Ok(rebuilt)
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigUint;
+    use revm::primitives::{AccountInfo, Bytecode, Bytes};
+
+    use super::*;
+    use crate::{
+        evm::{
+            engine_db::tycho_db::PreCachedDB,
+            protocol::uniswap_v2::state::UniswapV2State,
+            storage_layout::{UniswapV2Reserves, V2_RESERVES_SLOT},
+        },
+        models::Token,
+    };
+
+    /// `sstore(8, calldataload(0))`: overwrites the reserves of a fake pair with the calldata.
+    const SET_RESERVES_CODE: &str = "60003560085500";
+    const PAIR: Address = Address::new([0x22; 20]);
+    const SENDER: Address = Address::new([0x11; 20]);
+    const POOL_ID: &str = "pair";
+
+    fn packed_reserves(reserve0: u64, reserve1: u64) -> U256 {
+        UniswapV2Reserves {
+            reserve0: U256::from(reserve0),
+            reserve1: U256::from(reserve1),
+            block_timestamp_last: 0,
+        }
+        .pack()
+    }
+
+    fn setup_db() -> PreCachedDB {
+        let db = PreCachedDB::new().unwrap();
+        let code = Bytecode::new_raw(Bytes::from(hex::decode(SET_RESERVES_CODE).unwrap()));
+        db.init_account(
+            PAIR,
+            AccountInfo::new(U256::ZERO, 0, code.hash_slow(), code),
+            Some(HashMap::from([(V2_RESERVES_SLOT, packed_reserves(1_000_000, 1_000_000))])),
+            false,
+        );
+        for eoa in [SENDER, Address::ZERO] {
+            db.init_account(eoa, AccountInfo::default(), None, false);
+        }
+        db
+    }
+
+    fn set_reserves_tx(reserve0: u64, reserve1: u64) -> SimulationParameters {
+        SimulationParameters {
+            caller: SENDER,
+            to: PAIR,
+            data: packed_reserves(reserve0, reserve1)
+                .to_be_bytes::<32>()
+                .to_vec(),
+            value: U256::ZERO,
+            overrides: None,
+            balance_overrides: None,
+            nonce: None,
+            gas_limit: Some(100_000),
+            block_number: 1,
+            timestamp: 1,
+        }
+    }
+
+    fn failing_tx() -> SimulationParameters {
+        // calling an account without code that doesn't exist in the snapshot
+        SimulationParameters { to: Address::new([0x33; 20]), ..set_reserves_tx(0, 0) }
+    }
+
+    fn rebuild_v2(
+        _id: &str,
+        _state: &dyn ProtocolSim,
+        db: &OverriddenSimulationDB<'_, PreCachedDB>,
+    ) -> Result<Box<dyn ProtocolSim>, SimulationError> {
+        let value = db
+            .storage_ref(PAIR, V2_RESERVES_SLOT)
+            .map_err(|err| SimulationError::FatalError(err.to_string()))?;
+        Ok(Box::new(UniswapV2State::from(UniswapV2Reserves::unpack(value))))
+    }
+
+    fn quote(state: &dyn ProtocolSim) -> BigUint {
+        let token0 =
+            Token::new("0x0000000000000000000000000000000000000000", 18, "T0", 0u64.into());
+        let token1 =
+            Token::new("0x0000000000000000000000000000000000000001", 18, "T1", 0u64.into());
+        state
+            .get_amount_out(BigUint::from(1_000u64), &token0, &token1)
+            .unwrap()
+            .amount
+    }
+
+    #[test]
+    fn test_quote_reflects_pending_swap() {
+        let mut simulator = PendingBlockSimulator::new(setup_db());
+        let base_pool = UniswapV2State::new(U256::from(1_000_000u64), U256::from(1_000_000u64));
+        let states: HashMap<String, Box<dyn ProtocolSim>> =
+            HashMap::from([(POOL_ID.to_string(), Box::new(base_pool.clone()) as Box<_>)]);
+        let contracts = HashMap::from([(PAIR, HashSet::from([POOL_ID.to_string()]))]);
+
+        let results = simulator
+            .apply_pending(&[set_reserves_tx(2_000_000, 500_000)])
+            .unwrap();
+        let rebuilt = rebuild_pools(
+            &states,
+            &contracts,
+            simulator.touched_accounts(),
+            &simulator.state(),
+            rebuild_v2,
+        )
+        .unwrap();
+
+        assert!(results[0].is_ok());
+        assert_eq!(simulator.touched_accounts(), &HashSet::from([PAIR]));
+        assert_eq!(simulator.nonce(SENDER), 1);
+        let pool = &rebuilt[POOL_ID];
+        assert_eq!(
+            pool.as_any()
+                .downcast_ref::<UniswapV2State>(),
+            Some(&UniswapV2State::new(U256::from(2_000_000u64), U256::from(500_000u64)))
+        );
+        assert!(quote(pool.as_ref()) < quote(&base_pool));
+        assert_eq!(
+            simulator
+                .base_state()
+                .storage_ref(PAIR, V2_RESERVES_SLOT)
+                .unwrap(),
+            packed_reserves(1_000_000, 1_000_000)
+        );
+    }
+
+    #[test]
+    fn test_failed_transaction_is_skipped() {
+        let mut simulator = PendingBlockSimulator::new(setup_db());
+
+        let results = simulator
+            .apply_pending(&[failing_tx(), set_reserves_tx(2_000_000, 500_000)])
+            .unwrap();
+
+        assert!(matches!(results[0], Err(PendingBlockError::TransactionFailed { index: 0, .. })));
+        assert!(results[1].is_ok());
+        assert_eq!(simulator.nonce(SENDER), 1);
+    }
+
+    #[test]
+    fn test_transactions_are_sent_with_consecutive_nonces() {
+        let mut simulator = PendingBlockSimulator::new(setup_db());
+
+        let results = simulator
+            .apply_pending(&[
+                set_reserves_tx(2_000_000, 500_000),
+                SimulationParameters { nonce: Some(5), ..set_reserves_tx(3_000_000, 400_000) },
+            ])
+            .unwrap();
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(simulator.nonce(SENDER), 2);
+    }
+
+    #[test]
+    fn test_fatal_failure_discards_batch() {
+        let mut simulator =
+            PendingBlockSimulator::new(setup_db()).with_config(PendingBlockConfig {
+                on_failure: PendingFailurePolicy::Fatal,
+                ..Default::default()
+            });
+
+        let res = simulator.apply_pending(&[set_reserves_tx(2_000_000, 500_000), failing_tx()]);
+
+        assert!(matches!(res, Err(PendingBlockError::TransactionFailed { index: 1, .. })));
+        assert!(simulator.touched_accounts().is_empty());
+        assert_eq!(simulator.gas_used(), 0);
+    }
+
+    #[test]
+    fn test_block_gas_limit() {
+        let mut simulator = PendingBlockSimulator::new(setup_db())
+            .with_config(PendingBlockConfig { block_gas_limit: 50_000, ..Default::default() });
+
+        let results = simulator
+            .apply_pending(&[
+                set_reserves_tx(2_000_000, 500_000),
+                set_reserves_tx(3_000_000, 400_000),
+            ])
+            .unwrap();
+
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            results[1],
+            Err(PendingBlockError::BlockGasLimitExceeded { index: 1, .. })
+        ));
+        assert!(simulator.gas_used() <= 50_000);
+        assert_eq!(
+            simulator
+                .state()
+                .storage_ref(PAIR, V2_RESERVES_SLOT)
+                .unwrap(),
+            packed_reserves(2_000_000, 500_000)
+        );
+    }
+
+    #[test]
+    fn test_rebuild_skips_untouched_pools() {
+        let states: HashMap<String, Box<dyn ProtocolSim>> = HashMap::from([(
+            POOL_ID.to_string(),
+            Box::new(UniswapV2State::new(U256::from(1u64), U256::from(1u64))) as Box<_>,
+        )]);
+        let contracts = HashMap::from([(PAIR, HashSet::from([POOL_ID.to_string()]))]);
+        let db = setup_db();
+
+        let rebuilt = rebuild_pools(
+            &states,
+            &contracts,
+            &HashSet::from([SENDER]),
+            &db,
+            |_, _, _| -> Result<Box<dyn ProtocolSim>, SimulationError> {
+                panic!("untouched pool rebuilt")
+            },
+        )
+        .unwrap();
+
+        assert!(rebuilt.is_empty());
+    }
+}
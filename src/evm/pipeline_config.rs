@@ -0,0 +1,441 @@
+//! Persistent, hot-reloadable pipeline configuration.
+//!
+//! A [`PipelineConfig`] describes what a process tracks: which extractors it subscribes to, the
+//! TVL thresholds of each, the tokens it accepts, how stale data may get and gas overrides per
+//! protocol. [`Pipeline`] holds the running configuration and the pools tracked under it, and
+//! applies new configurations atomically: a config is validated completely before anything
+//! changes, and every successful reload produces a [`ReloadSummary`] of its effects.
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs,
+    path::Path,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::info;
+use tycho_client::feed::component_tracker::ComponentFilter;
+use tycho_common::Bytes;
+
+use super::{
+    subscription::{SubscriptionSession, DEFAULT_SUBSCRIPTION_TIMEOUT},
+    tycho_models::ExtractorIdentity,
+};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum PipelineConfigError {
+    #[error("Failed to read config: {0}")]
+    Io(String),
+    #[error("Failed to parse config: {0}")]
+    Parse(String),
+    #[error("Unknown protocol: {0}")]
+    UnknownProtocol(String),
+    #[error("Invalid config: {0}")]
+    Invalid(String),
+}
+
+/// Subscription settings of a single extractor.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExtractorConfig {
+    pub protocol_system: String,
+    /// Pools are tracked once their TVL exceeds this threshold, in native token.
+    pub add_tvl_threshold: f64,
+    /// Tracked pools are dropped once their TVL falls below this threshold, in native token.
+    pub remove_tvl_threshold: f64,
+}
+
+impl ExtractorConfig {
+    pub fn new(protocol_system: &str, add_tvl_threshold: f64, remove_tvl_threshold: f64) -> Self {
+        Self {
+            protocol_system: protocol_system.to_string(),
+            add_tvl_threshold,
+            remove_tvl_threshold,
+        }
+    }
+
+    /// The filter to subscribe to this extractor with.
+    pub fn component_filter(&self) -> ComponentFilter {
+        ComponentFilter::with_tvl_range(self.remove_tvl_threshold, self.add_tvl_threshold)
+    }
+}
+
+/// Everything that determines what the pipeline tracks.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineConfig {
+    pub extractors: Vec<ExtractorConfig>,
+    /// If set, only pools consisting exclusively of these tokens are tracked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_allowlist: Option<HashSet<Bytes>>,
+    /// Maximum age of the latest block before data is considered stale, in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_block_age_secs: Option<u64>,
+    /// Gas cost overrides by protocol system.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub gas_overrides: HashMap<String, u64>,
+    /// How long to wait for the server to confirm a subscription, in seconds. Defaults to
+    /// [`DEFAULT_SUBSCRIPTION_TIMEOUT`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subscription_timeout_secs: Option<u64>,
+}
+
+impl PipelineConfig {
+    /// Reads a JSON config file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, PipelineConfigError> {
+        let content =
+            fs::read_to_string(path).map_err(|err| PipelineConfigError::Io(err.to_string()))?;
+        serde_json::from_str(&content).map_err(|err| PipelineConfigError::Parse(err.to_string()))
+    }
+
+    /// How long to wait for the server to confirm a subscription.
+    pub fn subscription_timeout(&self) -> Duration {
+        self.subscription_timeout_secs
+            .map_or(DEFAULT_SUBSCRIPTION_TIMEOUT, Duration::from_secs)
+    }
+
+    /// A session subscribing to `extractor`, waiting [`Self::subscription_timeout`] for each
+    /// confirmation.
+    pub fn subscription_session(&self, extractor: ExtractorIdentity) -> SubscriptionSession {
+        SubscriptionSession::new(extractor).with_subscription_timeout(self.subscription_timeout())
+    }
+
+    pub fn extractor(&self, protocol_system: &str) -> Option<&ExtractorConfig> {
+        self.extractors
+            .iter()
+            .find(|e| e.protocol_system == protocol_system)
+    }
+
+    /// Checks that all protocols are known and all thresholds are consistent.
+    pub fn validate(&self, known_protocols: &HashSet<String>) -> Result<(), PipelineConfigError> {
+        let mut seen = HashSet::new();
+        for extractor in &self.extractors {
+            let name = &extractor.protocol_system;
+            if !known_protocols.contains(name) {
+                return Err(PipelineConfigError::UnknownProtocol(name.clone()));
+            }
+            if !seen.insert(name) {
+                return Err(PipelineConfigError::Invalid(format!("duplicate extractor {name}")));
+            }
+            if extractor.remove_tvl_threshold > extractor.add_tvl_threshold {
+                return Err(PipelineConfigError::Invalid(format!(
+                    "remove threshold of {name} exceeds its add threshold"
+                )));
+            }
+        }
+        if let Some(protocol) = self
+            .gas_overrides
+            .keys()
+            .find(|p| !known_protocols.contains(*p))
+        {
+            return Err(PipelineConfigError::UnknownProtocol(protocol.clone()));
+        }
+        Ok(())
+    }
+
+    /// Whether `pool` qualifies under this config. New pools have to reach the add threshold of
+    /// their extractor, pools that are `tracked` already are kept until they fall below its
+    /// remove threshold.
+    fn accepts(&self, pool: &TrackedPool, tracked: bool) -> bool {
+        let Some(extractor) = self.extractor(&pool.protocol_system) else {
+            return false;
+        };
+        let threshold =
+            if tracked { extractor.remove_tvl_threshold } else { extractor.add_tvl_threshold };
+        pool.tvl >= threshold &&
+            self.token_allowlist
+                .as_ref()
+                .map_or(true, |allowed| {
+                    pool.tokens
+                        .iter()
+                        .all(|t| allowed.contains(t))
+                })
+    }
+}
+
+/// A pool tracked by the pipeline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackedPool {
+    pub protocol_system: String,
+    pub tvl: f64,
+    pub tokens: Vec<Bytes>,
+}
+
+/// The effects of applying a new configuration.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ReloadSummary {
+    /// Extractors to subscribe to.
+    pub subscribed: BTreeSet<String>,
+    /// Extractors to unsubscribe from.
+    pub unsubscribed: BTreeSet<String>,
+    /// Extractors whose filter changed and need to be resubscribed.
+    pub resubscribed: BTreeSet<String>,
+    /// Pools that no longer qualify and were removed.
+    pub removed_pools: BTreeSet<String>,
+    pub token_allowlist_changed: bool,
+    pub freshness_changed: bool,
+    pub gas_overrides_changed: bool,
+}
+
+impl ReloadSummary {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// The running configuration and the pools tracked under it.
+#[derive(Debug)]
+pub struct Pipeline {
+    config: PipelineConfig,
+    known_protocols: HashSet<String>,
+    pools: HashMap<String, TrackedPool>,
+}
+
+impl Pipeline {
+    /// Creates a pipeline from a validated config. `known_protocols` are the protocol systems
+    /// decoders are available for.
+    pub fn new(
+        config: PipelineConfig,
+        known_protocols: HashSet<String>,
+    ) -> Result<Self, PipelineConfigError> {
+        config.validate(&known_protocols)?;
+        Ok(Self { config, known_protocols, pools: HashMap::new() })
+    }
+
+    pub fn config(&self) -> &PipelineConfig {
+        &self.config
+    }
+
+    pub fn pools(&self) -> &HashMap<String, TrackedPool> {
+        &self.pools
+    }
+
+    /// Starts tracking a pool, if the current config accepts it.
+    pub fn track(&mut self, id: &str, pool: TrackedPool) -> bool {
+        let accepted = self.config.accepts(&pool, false);
+        if accepted {
+            self.pools.insert(id.to_string(), pool);
+        }
+        accepted
+    }
+
+    /// Loads the config at `path` and applies it.
+    pub fn reload(&mut self, path: impl AsRef<Path>) -> Result<ReloadSummary, PipelineConfigError> {
+        let config = PipelineConfig::load(path)?;
+        self.apply(config)
+    }
+
+    /// Applies a new config. Invalid configs are rejected without changing anything.
+    pub fn apply(&mut self, config: PipelineConfig) -> Result<ReloadSummary, PipelineConfigError> {
+        config.validate(&self.known_protocols)?;
+
+        let mut summary = ReloadSummary::default();
+        for extractor in &config.extractors {
+            match self
+                .config
+                .extractor(&extractor.protocol_system)
+            {
+                None => {
+                    summary
+                        .subscribed
+                        .insert(extractor.protocol_system.clone());
+                }
+                Some(old) if old != extractor => {
+                    summary
+                        .resubscribed
+                        .insert(extractor.protocol_system.clone());
+                }
+                Some(_) => {}
+            }
+        }
+        summary.unsubscribed = self
+            .config
+            .extractors
+            .iter()
+            .filter(|e| {
+                config
+                    .extractor(&e.protocol_system)
+                    .is_none()
+            })
+            .map(|e| e.protocol_system.clone())
+            .collect();
+        summary.token_allowlist_changed = config.token_allowlist != self.config.token_allowlist;
+        summary.freshness_changed = config.max_block_age_secs != self.config.max_block_age_secs;
+        summary.gas_overrides_changed = config.gas_overrides != self.config.gas_overrides;
+
+        self.pools.retain(|id, pool| {
+            let keep = config.accepts(pool, true);
+            if !keep {
+                summary.removed_pools.insert(id.clone());
+            }
+            keep
+        });
+        self.config = config;
+
+        info!(
+            subscribed = ?summary.subscribed,
+            unsubscribed = ?summary.unsubscribed,
+            resubscribed = ?summary.resubscribed,
+            n_removed_pools = summary.removed_pools.len(),
+            token_allowlist_changed = summary.token_allowlist_changed,
+            freshness_changed = summary.freshness_changed,
+            gas_overrides_changed = summary.gas_overrides_changed,
+            "Applied pipeline config"
+        );
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    fn known_protocols() -> HashSet<String> {
+        ["uniswap_v2", "uniswap_v3", "ekubo_v2"]
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    fn write_config(json: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(json.as_bytes()).unwrap();
+        file
+    }
+
+    fn pool(protocol_system: &str, tvl: f64) -> TrackedPool {
+        TrackedPool {
+            protocol_system: protocol_system.to_string(),
+            tvl,
+            tokens: vec![Bytes::from("0x01"), Bytes::from("0x02")],
+        }
+    }
+
+    fn pipeline() -> Pipeline {
+        let config = PipelineConfig {
+            extractors: vec![ExtractorConfig::new("uniswap_v2", 10.0, 5.0)],
+            ..Default::default()
+        };
+        let mut pipeline = Pipeline::new(config, known_protocols()).unwrap();
+        assert!(pipeline.track("small", pool("uniswap_v2", 20.0)));
+        assert!(pipeline.track("large", pool("uniswap_v2", 200.0)));
+        pipeline
+    }
+
+    #[test]
+    fn test_reload_adds_extractor_and_tightens_filter() {
+        let mut pipeline = pipeline();
+        let file = write_config(
+            r#"{
+                "extractors": [
+                    {"protocol_system": "uniswap_v2", "add_tvl_threshold": 100.0, "remove_tvl_threshold": 50.0},
+                    {"protocol_system": "uniswap_v3", "add_tvl_threshold": 10.0, "remove_tvl_threshold": 5.0}
+                ],
+                "max_block_age_secs": 60
+            }"#,
+        );
+
+        let summary = pipeline.reload(file.path()).unwrap();
+
+        assert_eq!(summary.subscribed, BTreeSet::from(["uniswap_v3".to_string()]));
+        assert_eq!(summary.resubscribed, BTreeSet::from(["uniswap_v2".to_string()]));
+        assert!(summary.unsubscribed.is_empty());
+        assert_eq!(summary.removed_pools, BTreeSet::from(["small".to_string()]));
+        assert!(summary.freshness_changed);
+        assert!(!summary.gas_overrides_changed);
+        assert_eq!(
+            pipeline
+                .pools()
+                .keys()
+                .collect::<Vec<_>>(),
+            vec!["large"]
+        );
+        assert_eq!(pipeline.config().max_block_age_secs, Some(60));
+    }
+
+    #[test]
+    fn test_thresholds_of_new_and_tracked_pools() {
+        let mut pipeline = pipeline();
+
+        assert!(!pipeline.track("new", pool("uniswap_v2", 7.0)));
+
+        let config = PipelineConfig {
+            extractors: vec![ExtractorConfig::new("uniswap_v2", 30.0, 15.0)],
+            ..Default::default()
+        };
+        let summary = pipeline.apply(config).unwrap();
+
+        assert!(summary.removed_pools.is_empty());
+        assert!(pipeline.pools().contains_key("small"));
+        assert!(!pipeline.track("new", pool("uniswap_v2", 20.0)));
+    }
+
+    #[test]
+    fn test_reload_removes_extractor() {
+        let mut pipeline = pipeline();
+        let config = PipelineConfig {
+            extractors: vec![ExtractorConfig::new("uniswap_v3", 10.0, 5.0)],
+            ..Default::default()
+        };
+
+        let summary = pipeline.apply(config).unwrap();
+
+        assert_eq!(summary.unsubscribed, BTreeSet::from(["uniswap_v2".to_string()]));
+        assert_eq!(summary.removed_pools.len(), 2);
+        assert!(pipeline.pools().is_empty());
+    }
+
+    #[test]
+    fn test_reload_rejects_unknown_protocol_atomically() {
+        let mut pipeline = pipeline();
+        let before = pipeline.config().clone();
+        let file = write_config(
+            r#"{
+                "extractors": [
+                    {"protocol_system": "uniswap_v2", "add_tvl_threshold": 1000.0, "remove_tvl_threshold": 500.0},
+                    {"protocol_system": "unknown_dex", "add_tvl_threshold": 10.0, "remove_tvl_threshold": 5.0}
+                ]
+            }"#,
+        );
+
+        let res = pipeline.reload(file.path());
+
+        assert_eq!(res, Err(PipelineConfigError::UnknownProtocol("unknown_dex".to_string())));
+        assert_eq!(pipeline.config(), &before);
+        assert_eq!(pipeline.pools().len(), 2);
+    }
+
+    #[test]
+    fn test_token_allowlist_filters_pools() {
+        let mut pipeline = pipeline();
+        let config = PipelineConfig {
+            token_allowlist: Some(HashSet::from([Bytes::from("0x01")])),
+            ..pipeline.config().clone()
+        };
+
+        let summary = pipeline.apply(config).unwrap();
+
+        assert!(summary.token_allowlist_changed);
+        assert_eq!(summary.removed_pools.len(), 2);
+    }
+
+    #[test]
+    fn test_subscription_timeout() {
+        let file = write_config(r#"{"extractors": [], "subscription_timeout_secs": 5}"#);
+
+        let config = PipelineConfig::load(file.path()).unwrap();
+
+        assert_eq!(config.subscription_timeout(), Duration::from_secs(5));
+        assert_eq!(PipelineConfig::default().subscription_timeout(), DEFAULT_SUBSCRIPTION_TIMEOUT);
+        let session = config.subscription_session(ExtractorIdentity::new(
+            crate::evm::tycho_models::Chain::Ethereum,
+            "uniswap_v2",
+        ));
+        assert_eq!(session.subscription_timeout(), Duration::from_secs(5));
+    }
+}
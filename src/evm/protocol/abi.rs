@@ -0,0 +1,36 @@
+//! Shared helpers for protocol specific ABI codecs.
+//!
+//! Read-only view calls with a fixed signature don't need the EVM: their calldata is just the
+//! function selector and their return data can be decoded directly. The protocol specific codecs
+//! built on these helpers encode such calls, e.g. for an `eth_call`, and decode their results.
+use alloy_primitives::Keccak256;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum AbiError {
+    #[error("Failed to decode {function} return value: {source}")]
+    Decode {
+        function: &'static str,
+        #[source]
+        source: alloy_sol_types::Error,
+    },
+}
+
+/// Returns the 4 byte selector of the given function signature, e.g. `getReserves()`.
+pub fn selector(signature: &str) -> [u8; 4] {
+    let mut hasher = Keccak256::new();
+    hasher.update(signature.as_bytes());
+    let hash = hasher.finalize();
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selector() {
+        assert_eq!(selector("getReserves()"), [0x09, 0x02, 0xf1, 0xac]);
+        assert_eq!(selector("slot0()"), [0x38, 0x50, 0xc7, 0xbd]);
+    }
+}
@@ -0,0 +1,185 @@
+//! Balancer's 18 decimal fixed point arithmetic.
+//!
+//! Mirrors `FixedPoint.sol`, including its rounding directions. `LogExpMath.pow` is replaced by
+//! a higher precision series evaluation; `pow_down` still applies the contract's
+//! relative error margin, so results agree with the contract up to a few wei.
+use alloy_primitives::{I256, U256};
+
+use crate::{
+    evm::protocol::safe_math::{div_u256, safe_add_u256, safe_mul_u256, Rounding},
+    protocol::errors::SimulationError,
+};
+
+pub(super) const ONE: U256 = U256::from_limbs([1_000_000_000_000_000_000, 0, 0, 0]);
+const TWO: U256 = U256::from_limbs([2_000_000_000_000_000_000, 0, 0, 0]);
+/// `FixedPoint.MAX_POW_RELATIVE_ERROR`, 1e-14.
+const MAX_POW_RELATIVE_ERROR: U256 = U256::from_limbs([10_000, 0, 0, 0]);
+
+/// 1 with 36 decimals, the precision of the intermediate `ln`/`exp` computations.
+const ONE_36: I256 =
+    I256::from_raw(U256::from_limbs([12_919_594_847_110_692_864, 54_210_108_624_275_221, 0, 0]));
+const TWO_I256: I256 = I256::from_raw(U256::from_limbs([2, 0, 0, 0]));
+/// ln(2) with 36 decimals.
+const LN2_36: I256 =
+    I256::from_raw(U256::from_limbs([9_456_716_947_207_598_648, 37_575_583_950_764_745, 0, 0]));
+
+/// `a * b` of two fixed point numbers (`FixedPoint.mulDown`, `FixedPoint.mulUp`).
+pub(super) fn mul(a: U256, b: U256, rounding: Rounding) -> Result<U256, SimulationError> {
+    div_u256(safe_mul_u256(a, b)?, ONE, rounding)
+}
+
+/// `a / b` of two fixed point numbers (`FixedPoint.divDown`, `FixedPoint.divUp`).
+pub(super) fn div(a: U256, b: U256, rounding: Rounding) -> Result<U256, SimulationError> {
+    div_u256(safe_mul_u256(a, ONE)?, b, rounding)
+}
+
+/// `x^y`, rounded down by the contract's error margin.
+pub(super) fn pow_down(x: U256, y: U256) -> Result<U256, SimulationError> {
+    if y == ONE {
+        return Ok(x);
+    }
+    if y == TWO {
+        return mul(x, x, Rounding::Down);
+    }
+    let raw = pow(x, y)?;
+    let max_error = safe_add_u256(mul(raw, MAX_POW_RELATIVE_ERROR, Rounding::Up)?, U256::from(1))?;
+    Ok(raw.saturating_sub(max_error))
+}
+
+/// `x^y` for 18 decimal fixed point numbers, computed as `exp(y * ln(x))`.
+fn pow(x: U256, y: U256) -> Result<U256, SimulationError> {
+    if y.is_zero() {
+        return Ok(ONE);
+    }
+    if x.is_zero() {
+        return Ok(U256::ZERO);
+    }
+    let overflow = || SimulationError::FatalError("Balancer pow overflow".to_string());
+    let ln_x = ln_36(I256::try_from(safe_mul_u256(x, ONE)?).map_err(|_| overflow())?);
+    let y = I256::try_from(y).map_err(|_| overflow())?;
+    let exponent = ln_x
+        .checked_mul(y)
+        .ok_or_else(overflow)? /
+        I256::try_from(ONE).map_err(|_| overflow())?;
+    let res = exp_36(exponent).ok_or_else(overflow)?;
+    Ok(res / ONE)
+}
+
+/// Natural logarithm of a positive 36 decimal fixed point number.
+///
+/// Scales `a` into `[1, 2)` by powers of two and evaluates `ln(m) = 2 atanh((m - 1) / (m + 1))`,
+/// whose series converges quickly on that interval.
+fn ln_36(a: I256) -> I256 {
+    let two = TWO_I256;
+    let mut m = a;
+    let mut k = I256::ZERO;
+    while m >= ONE_36 * two {
+        m /= two;
+        k += I256::ONE;
+    }
+    while m < ONE_36 {
+        m *= two;
+        k -= I256::ONE;
+    }
+
+    let z = (m - ONE_36) * ONE_36 / (m + ONE_36);
+    let z_squared = z * z / ONE_36;
+    let mut term = z;
+    let mut sum = I256::ZERO;
+    let mut n = I256::ONE;
+    while !term.is_zero() {
+        sum += term / n;
+        term = term * z_squared / ONE_36;
+        n += two;
+    }
+    k * LN2_36 + sum * two
+}
+
+/// `e^y` for a 36 decimal fixed point number. `None` if the result doesn't fit into 256 bits.
+///
+/// Splits `y = k ln(2) + r` with `0 <= r < ln(2)`, evaluates the Taylor series of `e^r` and
+/// shifts by `k`.
+fn exp_36(y: I256) -> Option<U256> {
+    let mut k = y / LN2_36;
+    if y.is_negative() && k * LN2_36 != y {
+        k -= I256::ONE;
+    }
+    let r = y - k * LN2_36;
+
+    let mut term = ONE_36;
+    let mut sum = ONE_36;
+    let mut n = I256::ONE;
+    while !term.is_zero() {
+        term = term * r / ONE_36 / n;
+        sum += term;
+        n += I256::ONE;
+    }
+
+    let sum = sum.into_raw();
+    let shift = usize::try_from(k.unsigned_abs()).ok()?;
+    if k.is_negative() {
+        Some(
+            sum.checked_shr(shift)
+                .unwrap_or_default(),
+        )
+    } else {
+        let shifted = sum.checked_shl(shift)?;
+        (shifted >> shift == sum).then_some(shifted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    fn fp(x: &str) -> U256 {
+        U256::from_str(x).unwrap()
+    }
+
+    #[test]
+    fn test_constants() {
+        assert_eq!(ONE, U256::from(10).pow(U256::from(18)));
+        assert_eq!(ONE_36.into_raw(), U256::from(10).pow(U256::from(36)));
+        assert_eq!(LN2_36.into_raw(), fp("693147180559945309417232121458176568"));
+    }
+
+    #[rstest]
+    #[case::square_root("4000000000000000000", "500000000000000000", "2000000000000000000")]
+    #[case::cube("3000000000000000000", "3000000000000000000", "27000000000000000000")]
+    #[case::fraction("250000000000000000", "500000000000000000", "500000000000000000")]
+    #[case::weight("1100000000000000000", "800000000000000000", "1079230345298890765")]
+    fn test_pow(#[case] x: &str, #[case] y: &str, #[case] expected: &str) {
+        let res = pow(fp(x), fp(y)).unwrap();
+
+        let diff = if res > fp(expected) { res - fp(expected) } else { fp(expected) - res };
+        assert!(diff <= U256::from(1), "{res} != {expected}");
+    }
+
+    #[test]
+    fn test_pow_down_applies_error_margin() {
+        let (x, y) = (fp("1100000000000000000"), fp("800000000000000000"));
+
+        let raw = pow(x, y).unwrap();
+
+        assert_eq!(
+            pow_down(x, y).unwrap(),
+            raw - mul(raw, U256::from(10_000), Rounding::Up).unwrap() - U256::from(1)
+        );
+        assert_eq!(pow_down(x, ONE).unwrap(), x);
+    }
+
+    #[test]
+    fn test_rounding() {
+        let third = div(ONE, U256::from(3) * ONE, Rounding::Down).unwrap();
+
+        assert_eq!(third, fp("333333333333333333"));
+        assert_eq!(mul(third, U256::from(3), Rounding::Down).unwrap(), U256::ZERO);
+        assert_eq!(mul(third, U256::from(3), Rounding::Up).unwrap(), U256::from(1));
+        assert_eq!(div(ONE, U256::from(3) * ONE, Rounding::Up).unwrap(), third + U256::from(1));
+        assert_eq!(div(ONE, ONE, Rounding::Up).unwrap(), ONE);
+    }
+}
@@ -0,0 +1,203 @@
+//! Balancer V2 linear pool swaps between the main and the wrapped token
+//!
+//! Native implementation of `LinearMath.sol`. A linear pool holds a main token and its wrapped,
+//! yield-bearing version (e.g. USDC and aUSDC) and trades them at the wrapped token's rate as long
+//! as the main balance stays within `[lower_target, upper_target]`. Outside of that range, the
+//! main balance is converted to a lower "nominal" balance with a fee growing linearly with the
+//! distance to the target, which incentivizes swaps that bring the balance back into range.
+//!
+//! All amounts are expected to be upscaled to 18 decimals, the way the pool contract does before
+//! applying its math. The wrapped token rate is the amount of main token one wrapped token is
+//! worth, as an 18 decimal fixed point number.
+use alloy_primitives::U256;
+
+use super::{
+    fixed_point::{div, mul, ONE},
+    weighted_pool::PoolError,
+};
+use crate::evm::protocol::safe_math::{safe_add_u256, safe_sub_u256, Rounding};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BalancerLinearPool {
+    main_balance: U256,
+    wrapped_balance: U256,
+    lower_target: U256,
+    upper_target: U256,
+    swap_fee: U256,
+    wrapped_token_rate: U256,
+}
+
+impl BalancerLinearPool {
+    /// Creates a pool from its upscaled balances and targets, its swap fee percentage (18
+    /// decimals, e.g. `1e16` for 1%) and the rate of the wrapped token.
+    pub fn new(
+        main_balance: U256,
+        wrapped_balance: U256,
+        lower_target: U256,
+        upper_target: U256,
+        swap_fee: U256,
+        wrapped_token_rate: U256,
+    ) -> Result<Self, PoolError> {
+        if lower_target > upper_target {
+            return Err(PoolError::InvalidPool(format!(
+                "lower target {lower_target} is above the upper target {upper_target}"
+            )));
+        }
+        if swap_fee >= ONE {
+            return Err(PoolError::InvalidPool(format!("swap fee {swap_fee} is not below 1")));
+        }
+        if wrapped_token_rate.is_zero() {
+            return Err(PoolError::InvalidPool("wrapped token rate must not be zero".to_string()));
+        }
+        Ok(Self {
+            main_balance,
+            wrapped_balance,
+            lower_target,
+            upper_target,
+            swap_fee,
+            wrapped_token_rate,
+        })
+    }
+
+    /// Computes the amount of the other token received for exactly `amount_in`
+    /// (`LinearMath._calcWrappedOutPerMainIn`, `LinearMath._calcMainOutPerWrappedIn`).
+    pub fn swap_given_in(
+        &self,
+        amount_in: U256,
+        token_in_is_main: bool,
+    ) -> Result<U256, PoolError> {
+        let previous_nominal_main = self.to_nominal(self.main_balance)?;
+        let (amount_out, balance_out) = if token_in_is_main {
+            let after_nominal_main =
+                self.to_nominal(safe_add_u256(self.main_balance, amount_in)?)?;
+            let delta_nominal_main = safe_sub_u256(after_nominal_main, previous_nominal_main)?;
+            (
+                div(delta_nominal_main, self.wrapped_token_rate, Rounding::Down)?,
+                self.wrapped_balance,
+            )
+        } else {
+            let delta_nominal_main = mul(amount_in, self.wrapped_token_rate, Rounding::Down)?;
+            if delta_nominal_main > previous_nominal_main {
+                return Err(PoolError::InsufficientBalance {
+                    amount_out: delta_nominal_main,
+                    balance: self.main_balance,
+                });
+            }
+            let new_main_balance = self.from_nominal(previous_nominal_main - delta_nominal_main)?;
+            (
+                self.main_balance
+                    .saturating_sub(new_main_balance),
+                self.main_balance,
+            )
+        };
+        if amount_out > balance_out {
+            return Err(PoolError::InsufficientBalance { amount_out, balance: balance_out });
+        }
+        Ok(amount_out)
+    }
+
+    /// Converts a real main balance to its nominal value, deducting the fee charged on the part
+    /// outside of the targets (`LinearMath._toNominal`).
+    fn to_nominal(&self, real: U256) -> Result<U256, PoolError> {
+        let fees = if real < self.lower_target {
+            mul(self.lower_target - real, self.swap_fee, Rounding::Down)?
+        } else if real <= self.upper_target {
+            U256::ZERO
+        } else {
+            mul(real - self.upper_target, self.swap_fee, Rounding::Down)?
+        };
+        Ok(safe_sub_u256(real, fees)?)
+    }
+
+    /// Inverse of [`Self::to_nominal`] (`LinearMath._fromNominal`).
+    fn from_nominal(&self, nominal: U256) -> Result<U256, PoolError> {
+        if nominal < self.lower_target {
+            let fees = mul(self.swap_fee, self.lower_target, Rounding::Down)?;
+            Ok(div(safe_add_u256(nominal, fees)?, ONE + self.swap_fee, Rounding::Down)?)
+        } else if nominal <= self.upper_target {
+            Ok(nominal)
+        } else {
+            let fees = mul(self.swap_fee, self.upper_target, Rounding::Down)?;
+            Ok(div(safe_sub_u256(nominal, fees)?, ONE - self.swap_fee, Rounding::Down)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    fn e18(x: u64) -> U256 {
+        U256::from(x) * ONE
+    }
+
+    /// A pool with targets at 100 and 1000 main tokens and a 1% fee.
+    fn pool(main_balance: u64, wrapped_token_rate: U256) -> BalancerLinearPool {
+        BalancerLinearPool::new(
+            e18(main_balance),
+            e18(1_000),
+            e18(100),
+            e18(1_000),
+            U256::from_str("10000000000000000").unwrap(),
+            wrapped_token_rate,
+        )
+        .unwrap()
+    }
+
+    #[rstest]
+    #[case::main_in(true, e18(110), e18(100))]
+    #[case::wrapped_in(false, e18(100), e18(110))]
+    fn test_swap_within_targets(
+        #[case] token_in_is_main: bool,
+        #[case] amount_in: U256,
+        #[case] expected: U256,
+    ) {
+        let pool = pool(500, U256::from_str("1100000000000000000").unwrap());
+
+        let amount_out = pool
+            .swap_given_in(amount_in, token_in_is_main)
+            .unwrap();
+
+        assert_eq!(amount_out, expected);
+    }
+
+    #[rstest]
+    #[case::up_to_upper_target(900, e18(100))]
+    #[case::across_upper_target(950, U256::from_str("99500000000000000000").unwrap())]
+    fn test_swap_at_target_boundary(#[case] main_balance: u64, #[case] expected: U256) {
+        let amount_out = pool(main_balance, ONE)
+            .swap_given_in(e18(100), true)
+            .unwrap();
+
+        assert_eq!(amount_out, expected);
+    }
+
+    #[test]
+    fn test_swap_above_target_charges_fee() {
+        let amount_out = pool(1_000, ONE)
+            .swap_given_in(e18(100), true)
+            .unwrap();
+
+        assert_eq!(amount_out, e18(99));
+    }
+
+    #[test]
+    fn test_swap_below_target_charges_fee() {
+        let amount_out = pool(150, ONE)
+            .swap_given_in(e18(100), false)
+            .unwrap();
+
+        assert_eq!(amount_out, U256::from_str("99504950495049504951").unwrap());
+    }
+
+    #[test]
+    fn test_swap_exceeding_balance() {
+        let res = pool(500, ONE).swap_given_in(e18(2_000), true);
+
+        assert!(matches!(res, Err(PoolError::InsufficientBalance { .. })));
+    }
+}
@@ -0,0 +1,4 @@
+//! Balancer V2 native pool math
+mod fixed_point;
+pub mod linear_pool;
+pub mod weighted_pool;
@@ -0,0 +1,292 @@
+//! Balancer V2 weighted pool joins and exits
+//!
+//! Native implementation of `WeightedMath.sol` for adding and removing liquidity. Swaps on
+//! Balancer pools are simulated through the VM adapter; this module covers the BPT (Balancer
+//! pool token) accounting that swaps don't need.
+//!
+//! All amounts are expected to be upscaled to 18 decimals, the way the pool contract does before
+//! applying its math, and weights are normalized 18 decimal fixed point numbers.
+use alloy_primitives::U256;
+use thiserror::Error;
+
+use super::fixed_point::{div, mul, pow_down, ONE};
+use crate::{
+    evm::protocol::safe_math::{safe_add_u256, safe_sub_u256, Rounding},
+    protocol::errors::SimulationError,
+};
+
+#[derive(Debug, Error)]
+pub enum PoolError {
+    #[error("Expected {expected} amounts, got {actual}")]
+    LengthMismatch { expected: usize, actual: usize },
+    #[error("Invalid pool: {0}")]
+    InvalidPool(String),
+    #[error("BPT out {bpt_out} is below the minimum {min_bpt_out}")]
+    BptOutBelowMin { bpt_out: U256, min_bpt_out: U256 },
+    #[error("Amount out {amount_out} of token {index} is below the minimum {min_amount_out}")]
+    AmountOutBelowMin { index: usize, amount_out: U256, min_amount_out: U256 },
+    #[error("Amount out {amount_out} exceeds the pool balance {balance}")]
+    InsufficientBalance { amount_out: U256, balance: U256 },
+    #[error(transparent)]
+    Math(#[from] SimulationError),
+}
+
+/// The outcome of an `EXACT_TOKENS_IN_FOR_BPT_OUT` join.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JoinResult {
+    pub bpt_out: U256,
+    pub amounts_in: Vec<U256>,
+    /// The swap fee charged on each token, in token units.
+    pub fees: Vec<U256>,
+}
+
+/// The outcome of an `EXACT_BPT_IN_FOR_TOKENS_OUT` exit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExitResult {
+    pub bpt_in: U256,
+    pub amounts_out: Vec<U256>,
+    /// The swap fee charged on each token, in token units.
+    pub fees: Vec<U256>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BalancerWeightedPool {
+    balances: Vec<U256>,
+    normalized_weights: Vec<U256>,
+    total_supply: U256,
+    swap_fee: U256,
+}
+
+impl BalancerWeightedPool {
+    /// Creates a pool from its upscaled balances, normalized weights, BPT supply and swap fee
+    /// percentage (18 decimals, e.g. `3e15` for 0.3%).
+    pub fn new(
+        balances: Vec<U256>,
+        normalized_weights: Vec<U256>,
+        total_supply: U256,
+        swap_fee: U256,
+    ) -> Result<Self, PoolError> {
+        if balances.len() != normalized_weights.len() {
+            return Err(PoolError::LengthMismatch {
+                expected: balances.len(),
+                actual: normalized_weights.len(),
+            });
+        }
+        if balances.len() < 2 {
+            return Err(PoolError::InvalidPool("a pool needs at least two tokens".to_string()));
+        }
+        if balances.iter().any(U256::is_zero) {
+            return Err(PoolError::InvalidPool("balances must not be zero".to_string()));
+        }
+        let weight_sum = normalized_weights
+            .iter()
+            .try_fold(U256::ZERO, |acc, w| safe_add_u256(acc, *w))?;
+        if weight_sum != ONE {
+            return Err(PoolError::InvalidPool(format!("weights sum up to {weight_sum}")));
+        }
+        if swap_fee >= ONE {
+            return Err(PoolError::InvalidPool(format!("swap fee {swap_fee} is not below 1")));
+        }
+        Ok(Self { balances, normalized_weights, total_supply, swap_fee })
+    }
+
+    /// Computes the BPT minted for depositing exactly `amounts_in`
+    /// (`WeightedMath._calcBptOutGivenExactTokensIn`).
+    ///
+    /// The part of a deposit exceeding the pool's proportions is effectively swapped into the
+    /// other tokens and pays the swap fee; a proportional deposit pays none.
+    pub fn join_exact_tokens_in(
+        &self,
+        amounts_in: &[U256],
+        min_bpt_out: U256,
+    ) -> Result<JoinResult, PoolError> {
+        self.check_len(amounts_in.len())?;
+
+        let mut balance_ratios_with_fee = Vec::with_capacity(amounts_in.len());
+        let mut invariant_ratio_with_fees = U256::ZERO;
+        for ((balance, amount_in), weight) in self
+            .balances
+            .iter()
+            .zip(amounts_in)
+            .zip(&self.normalized_weights)
+        {
+            let ratio = div(safe_add_u256(*balance, *amount_in)?, *balance, Rounding::Down)?;
+            invariant_ratio_with_fees =
+                safe_add_u256(invariant_ratio_with_fees, mul(ratio, *weight, Rounding::Down)?)?;
+            balance_ratios_with_fee.push(ratio);
+        }
+
+        let mut invariant_ratio = ONE;
+        let mut fees = Vec::with_capacity(amounts_in.len());
+        for (i, amount_in) in amounts_in.iter().enumerate() {
+            let balance = self.balances[i];
+            let mut fee = U256::ZERO;
+            let amount_in_without_fee = if balance_ratios_with_fee[i] > invariant_ratio_with_fees {
+                let non_taxable =
+                    mul(balance, invariant_ratio_with_fees.saturating_sub(ONE), Rounding::Down)?;
+                let taxable = safe_sub_u256(*amount_in, non_taxable)?;
+                fee = mul(taxable, self.swap_fee, Rounding::Up)?;
+                safe_sub_u256(*amount_in, fee)?
+            } else {
+                *amount_in
+            };
+            fees.push(fee);
+
+            let balance_ratio =
+                div(safe_add_u256(balance, amount_in_without_fee)?, balance, Rounding::Down)?;
+            invariant_ratio = mul(
+                invariant_ratio,
+                pow_down(balance_ratio, self.normalized_weights[i])?,
+                Rounding::Down,
+            )?;
+        }
+
+        let bpt_out = if invariant_ratio > ONE {
+            mul(self.total_supply, invariant_ratio - ONE, Rounding::Down)?
+        } else {
+            U256::ZERO
+        };
+        if bpt_out < min_bpt_out {
+            return Err(PoolError::BptOutBelowMin { bpt_out, min_bpt_out });
+        }
+        Ok(JoinResult { bpt_out, amounts_in: amounts_in.to_vec(), fees })
+    }
+
+    /// Computes the tokens returned for burning exactly `bpt_in`
+    /// (`WeightedMath._calcTokensOutGivenExactBptIn`).
+    ///
+    /// This exit is always proportional to the pool's balances, so there is no imbalanced
+    /// portion to charge the swap fee on and the reported fees are zero.
+    pub fn exit_exact_bpt_in(
+        &self,
+        bpt_in: U256,
+        min_amounts_out: &[U256],
+    ) -> Result<ExitResult, PoolError> {
+        self.check_len(min_amounts_out.len())?;
+        if bpt_in > self.total_supply {
+            return Err(PoolError::InvalidPool(format!(
+                "BPT in {bpt_in} exceeds the total supply {}",
+                self.total_supply
+            )));
+        }
+
+        let bpt_ratio = div(bpt_in, self.total_supply, Rounding::Down)?;
+        let mut amounts_out = Vec::with_capacity(self.balances.len());
+        for (index, (balance, min_amount_out)) in self
+            .balances
+            .iter()
+            .zip(min_amounts_out)
+            .enumerate()
+        {
+            let amount_out = mul(*balance, bpt_ratio, Rounding::Down)?;
+            if amount_out < *min_amount_out {
+                return Err(PoolError::AmountOutBelowMin {
+                    index,
+                    amount_out,
+                    min_amount_out: *min_amount_out,
+                });
+            }
+            amounts_out.push(amount_out);
+        }
+        Ok(ExitResult { bpt_in, amounts_out, fees: vec![U256::ZERO; self.balances.len()] })
+    }
+
+    fn check_len(&self, len: usize) -> Result<(), PoolError> {
+        if len != self.balances.len() {
+            return Err(PoolError::LengthMismatch { expected: self.balances.len(), actual: len });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    fn e18(x: u64) -> U256 {
+        U256::from(x) * ONE
+    }
+
+    fn pool_with_fee(swap_fee: &str) -> BalancerWeightedPool {
+        BalancerWeightedPool::new(
+            vec![e18(1_000), e18(250)],
+            vec![
+                U256::from_str("800000000000000000").unwrap(),
+                U256::from_str("200000000000000000").unwrap(),
+            ],
+            e18(1_000),
+            U256::from_str(swap_fee).unwrap(),
+        )
+        .unwrap()
+    }
+
+    /// An 80/20 pool with 0.3% swap fee.
+    fn pool() -> BalancerWeightedPool {
+        pool_with_fee("3000000000000000")
+    }
+
+    #[test]
+    fn test_balanced_join_charges_no_fee() {
+        let res = pool()
+            .join_exact_tokens_in(&[e18(100), e18(25)], U256::ZERO)
+            .unwrap();
+
+        assert_eq!(res.fees, vec![U256::ZERO, U256::ZERO]);
+        // A 10% deposit mints ~10% of the supply, minus the pow rounding margin.
+        assert!(res.bpt_out <= e18(100));
+        assert!(e18(100) - res.bpt_out < U256::from(100_000_000_000_000u64));
+    }
+
+    #[rstest]
+    #[case::single_token(vec![100, 0])]
+    #[case::skewed(vec![100, 5])]
+    fn test_imbalanced_join_charges_fee(#[case] amounts: Vec<u64>) {
+        let amounts: Vec<U256> = amounts.into_iter().map(e18).collect();
+
+        let res = pool()
+            .join_exact_tokens_in(&amounts, U256::ZERO)
+            .unwrap();
+        let without_fee = pool_with_fee("0")
+            .join_exact_tokens_in(&amounts, U256::ZERO)
+            .unwrap();
+
+        assert!(res.fees[0] > U256::ZERO);
+        assert_eq!(res.fees[1], U256::ZERO);
+        assert!(res.bpt_out < without_fee.bpt_out);
+    }
+
+    #[test]
+    fn test_join_min_bpt_out() {
+        let res = pool().join_exact_tokens_in(&[e18(100), e18(25)], e18(101));
+
+        assert!(matches!(res, Err(PoolError::BptOutBelowMin { .. })));
+    }
+
+    #[test]
+    fn test_exit_is_proportional() {
+        let res = pool()
+            .exit_exact_bpt_in(e18(100), &[e18(100), e18(25)])
+            .unwrap();
+
+        assert_eq!(res.amounts_out, vec![e18(100), e18(25)]);
+        assert_eq!(res.fees, vec![U256::ZERO, U256::ZERO]);
+    }
+
+    #[test]
+    fn test_exit_min_amounts_out() {
+        let res = pool().exit_exact_bpt_in(e18(100), &[e18(100), e18(26)]);
+
+        assert!(matches!(res, Err(PoolError::AmountOutBelowMin { index: 1, .. })));
+    }
+
+    #[test]
+    fn test_length_mismatch() {
+        let res = pool().join_exact_tokens_in(&[e18(1)], U256::ZERO);
+
+        assert!(matches!(res, Err(PoolError::LengthMismatch { expected: 2, actual: 1 })));
+    }
+}
@@ -0,0 +1,3 @@
+//! Curve native pool math
+pub mod stable_pool;
+pub mod tricrypto;
@@ -0,0 +1,510 @@
+//! Curve StableSwap pool parameters and their governance actions
+//!
+//! Models the fee and amplification coefficient (A) of a StableSwap pool together with the admin
+//! functions that change them, so the impact of governance actions can be simulated before they
+//! are executed. Limits and A interpolation mirror `StableSwap.vy`.
+//!
+//! A is stored multiplied by [`A_PRECISION`], like the contract does; fees are fractions of
+//! [`FEE_DENOMINATOR`]. Balances are expected in a common precision, i.e. already multiplied by
+//! the pool's rates, as the invariant is computed on them directly.
+use alloy_primitives::U256;
+use thiserror::Error;
+use tracing::info;
+
+pub const FEE_DENOMINATOR: u64 = 10_000_000_000;
+/// Maximum swap fee, 50%.
+pub const MAX_FEE: u64 = 5_000_000_000;
+/// Maximum admin fee, 100% of the swap fee.
+pub const MAX_ADMIN_FEE: u64 = 10_000_000_000;
+pub const A_PRECISION: u64 = 100;
+/// Upper bound (exclusive) of A, unscaled.
+pub const MAX_A: u64 = 1_000_000;
+/// Maximum factor by which a single ramp may increase or decrease A.
+pub const MAX_A_CHANGE: u64 = 10;
+/// Minimum duration of a ramp and minimum time between the starts of two ramps, in seconds.
+pub const MIN_RAMP_TIME: u64 = 86_400;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum AdminError {
+    #[error("Fee {fee} exceeds the maximum of {max}")]
+    FeeTooHigh { fee: u64, max: u64 },
+    #[error("A ramp can't start before {earliest}, now is {now}")]
+    RampTooSoon { now: u64, earliest: u64 },
+    #[error("A ramp must last until at least {earliest}, got {future_time}")]
+    RampTooShort { future_time: u64, earliest: u64 },
+    #[error("Future A {0} must be between 1 and {MAX_A}")]
+    InvalidA(u64),
+    #[error(
+        "A can change by at most a factor of {MAX_A_CHANGE} per ramp, from {current} to {future}"
+    )]
+    AChangeTooLarge { current: u64, future: u64 },
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum MathError {
+    #[error("Expected an amount for each of the {expected} coins, got {got}")]
+    AmountsLengthMismatch { expected: usize, got: usize },
+    #[error("Withdrawal of {amount} exceeds the balance {balance} of coin {index}")]
+    InsufficientBalance { index: usize, amount: U256, balance: U256 },
+    #[error("The pool is empty")]
+    EmptyPool,
+    #[error("Overflow while computing {0}")]
+    Overflow(&'static str),
+    #[error("D did not converge")]
+    DidNotConverge,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurveStablePool {
+    balances: Vec<U256>,
+    fee: u64,
+    admin_fee: u64,
+    initial_a: u64,
+    future_a: u64,
+    initial_a_time: u64,
+    future_a_time: u64,
+}
+
+impl CurveStablePool {
+    /// Creates a pool with a constant, unscaled `a` and the given swap and admin fees.
+    pub fn new(balances: Vec<U256>, a: u64, fee: u64, admin_fee: u64) -> Self {
+        Self {
+            balances,
+            fee,
+            admin_fee,
+            initial_a: a * A_PRECISION,
+            future_a: a * A_PRECISION,
+            initial_a_time: 0,
+            future_a_time: 0,
+        }
+    }
+
+    /// Restores an ongoing ramp, e.g. from the pool's `initial_A`, `future_A`, `initial_A_time`
+    /// and `future_A_time`. Both A values are scaled by [`A_PRECISION`].
+    pub fn with_ramp(
+        mut self,
+        initial_a: u64,
+        future_a: u64,
+        initial_a_time: u64,
+        future_a_time: u64,
+    ) -> Self {
+        self.initial_a = initial_a;
+        self.future_a = future_a;
+        self.initial_a_time = initial_a_time;
+        self.future_a_time = future_a_time;
+        self
+    }
+
+    pub fn balances(&self) -> &[U256] {
+        &self.balances
+    }
+
+    pub fn fee(&self) -> u64 {
+        self.fee
+    }
+
+    pub fn admin_fee(&self) -> u64 {
+        self.admin_fee
+    }
+
+    /// A at `current_time`, scaled by [`A_PRECISION`] (`A_precise`).
+    ///
+    /// During a ramp A moves linearly from its initial to its future value.
+    pub fn a_precise(&self, current_time: u64) -> u64 {
+        if current_time >= self.future_a_time {
+            return self.future_a;
+        }
+        let elapsed = u128::from(current_time.saturating_sub(self.initial_a_time));
+        let duration = u128::from(self.future_a_time - self.initial_a_time);
+        let (a0, a1) = (u128::from(self.initial_a), u128::from(self.future_a));
+        let a = if a1 > a0 {
+            a0 + (a1 - a0) * elapsed / duration
+        } else {
+            a0 - (a0 - a1) * elapsed / duration
+        };
+        a as u64
+    }
+
+    /// A at `current_time`, unscaled (`A`).
+    pub fn a(&self, current_time: u64) -> u64 {
+        self.a_precise(current_time) / A_PRECISION
+    }
+
+    /// Sets the swap fee, at most [`MAX_FEE`].
+    pub fn admin_set_fee(&mut self, new_fee: u64) -> Result<(), AdminError> {
+        if new_fee > MAX_FEE {
+            return Err(AdminError::FeeTooHigh { fee: new_fee, max: MAX_FEE });
+        }
+        info!(old_fee = self.fee, new_fee, "Curve swap fee changed");
+        self.fee = new_fee;
+        Ok(())
+    }
+
+    /// Sets the share of the swap fee going to the admin, at most [`MAX_ADMIN_FEE`].
+    pub fn admin_set_admin_fee(&mut self, new_admin_fee: u64) -> Result<(), AdminError> {
+        if new_admin_fee > MAX_ADMIN_FEE {
+            return Err(AdminError::FeeTooHigh { fee: new_admin_fee, max: MAX_ADMIN_FEE });
+        }
+        info!(old_admin_fee = self.admin_fee, new_admin_fee, "Curve admin fee changed");
+        self.admin_fee = new_admin_fee;
+        Ok(())
+    }
+
+    /// Starts ramping A from its current value to the unscaled `future_a`, reached at
+    /// `future_time` (`ramp_A`).
+    ///
+    /// A ramp can only start [`MIN_RAMP_TIME`] after the previous one started, must last at
+    /// least [`MIN_RAMP_TIME`] and may change A by at most a factor of [`MAX_A_CHANGE`].
+    pub fn admin_ramp_a(
+        &mut self,
+        future_a: u64,
+        future_time: u64,
+        current_time: u64,
+    ) -> Result<(), AdminError> {
+        let earliest = self.initial_a_time + MIN_RAMP_TIME;
+        if current_time < earliest {
+            return Err(AdminError::RampTooSoon { now: current_time, earliest });
+        }
+        let earliest = current_time + MIN_RAMP_TIME;
+        if future_time < earliest {
+            return Err(AdminError::RampTooShort { future_time, earliest });
+        }
+        if future_a == 0 || future_a >= MAX_A {
+            return Err(AdminError::InvalidA(future_a));
+        }
+        let initial_a = self.a_precise(current_time);
+        let future_a_precise = future_a * A_PRECISION;
+        let within_limit = if future_a_precise < initial_a {
+            future_a_precise * MAX_A_CHANGE >= initial_a
+        } else {
+            future_a_precise <= initial_a * MAX_A_CHANGE
+        };
+        if !within_limit {
+            return Err(AdminError::AChangeTooLarge {
+                current: initial_a / A_PRECISION,
+                future: future_a,
+            });
+        }
+
+        info!(
+            initial_a = initial_a / A_PRECISION,
+            future_a,
+            start = current_time,
+            end = future_time,
+            "Curve A ramp started"
+        );
+        self.initial_a = initial_a;
+        self.future_a = future_a_precise;
+        self.initial_a_time = current_time;
+        self.future_a_time = future_time;
+        Ok(())
+    }
+
+    /// LP tokens minted by depositing, or burned by withdrawing, `amounts` at `current_time`
+    /// (`calc_token_amount`).
+    ///
+    /// Like the contract, this compares the invariant D before and after changing the balances
+    /// and scales the difference by `lp_total_supply`. Fees charged on imbalanced deposits and
+    /// withdrawals are not included, so the result is an estimate for display purposes rather
+    /// than an exact amount.
+    pub fn calc_token_amount(
+        &self,
+        amounts: &[U256],
+        is_deposit: bool,
+        lp_total_supply: U256,
+        current_time: u64,
+    ) -> Result<U256, MathError> {
+        if amounts.len() != self.balances.len() {
+            return Err(MathError::AmountsLengthMismatch {
+                expected: self.balances.len(),
+                got: amounts.len(),
+            });
+        }
+        let amp = U256::from(self.a_precise(current_time));
+        let d0 = get_d(&self.balances, amp)?;
+        if d0.is_zero() {
+            return Err(MathError::EmptyPool);
+        }
+        let balances = self
+            .balances
+            .iter()
+            .zip(amounts)
+            .enumerate()
+            .map(|(index, (&balance, &amount))| {
+                if is_deposit {
+                    balance
+                        .checked_add(amount)
+                        .ok_or(MathError::Overflow("balances"))
+                } else {
+                    balance
+                        .checked_sub(amount)
+                        .ok_or(MathError::InsufficientBalance { index, amount, balance })
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let d1 = get_d(&balances, amp)?;
+        let diff = if is_deposit { d1.saturating_sub(d0) } else { d0.saturating_sub(d1) };
+        Ok(diff
+            .checked_mul(lp_total_supply)
+            .ok_or(MathError::Overflow("token amount"))? /
+            d0)
+    }
+
+    /// Stops an ongoing ramp, fixing A at its current value (`stop_ramp_A`).
+    pub fn admin_stop_ramp_a(&mut self, current_time: u64) {
+        let current_a = self.a_precise(current_time);
+        info!(a = current_a / A_PRECISION, at = current_time, "Curve A ramp stopped");
+        self.initial_a = current_a;
+        self.future_a = current_a;
+        self.initial_a_time = current_time;
+        self.future_a_time = current_time;
+    }
+}
+
+/// The StableSwap invariant D of `xp` for `amp` scaled by [`A_PRECISION`] (`get_D`).
+///
+/// Solved by Newton's method, which stops once D changes by at most 1 between iterations.
+fn get_d(xp: &[U256], amp: U256) -> Result<U256, MathError> {
+    let overflow = || MathError::Overflow("D");
+    let n_coins = U256::from(xp.len());
+    let a_precision = U256::from(A_PRECISION);
+    let s = xp
+        .iter()
+        .try_fold(U256::ZERO, |sum, x| sum.checked_add(*x))
+        .ok_or_else(overflow)?;
+    if s.is_zero() {
+        return Ok(U256::ZERO);
+    }
+
+    let ann = amp * n_coins;
+    let mut d = s;
+    for _ in 0..255 {
+        let mut d_p = d;
+        for x in xp {
+            // Like the contract, a zero balance makes D unsolvable.
+            if x.is_zero() {
+                return Err(MathError::DidNotConverge);
+            }
+            d_p = d_p
+                .checked_mul(d)
+                .ok_or_else(overflow)? /
+                (*x * n_coins);
+        }
+        let d_prev = d;
+        let numerator = (ann * s / a_precision + d_p * n_coins)
+            .checked_mul(d)
+            .ok_or_else(overflow)?;
+        let denominator = (ann - a_precision) * d / a_precision + (n_coins + U256::from(1)) * d_p;
+        d = numerator / denominator;
+        if d.abs_diff(d_prev) <= U256::from(1) {
+            return Ok(d);
+        }
+    }
+    Err(MathError::DidNotConverge)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    /// Start time of the test ramps, far enough from 0 to allow a first ramp.
+    const T0: u64 = 1_000_000;
+
+    fn pool() -> CurveStablePool {
+        CurveStablePool::new(vec![U256::from(1_000u64); 3], 100, 4_000_000, 5_000_000_000)
+    }
+
+    #[rstest]
+    #[case::max(MAX_FEE, true)]
+    #[case::above_max(MAX_FEE + 1, false)]
+    #[case::zero(0, true)]
+    fn test_set_fee_ceiling(#[case] fee: u64, #[case] ok: bool) {
+        let mut pool = pool();
+
+        let res = pool.admin_set_fee(fee);
+
+        if ok {
+            assert_eq!(res, Ok(()));
+            assert_eq!(pool.fee(), fee);
+        } else {
+            assert_eq!(res, Err(AdminError::FeeTooHigh { fee, max: MAX_FEE }));
+            assert_eq!(pool.fee(), 4_000_000);
+        }
+    }
+
+    #[test]
+    fn test_set_admin_fee_ceiling() {
+        let mut pool = pool();
+
+        assert!(pool
+            .admin_set_admin_fee(MAX_ADMIN_FEE + 1)
+            .is_err());
+        pool.admin_set_admin_fee(0).unwrap();
+
+        assert_eq!(pool.admin_fee(), 0);
+    }
+
+    #[test]
+    fn test_ramp_interpolates_a() {
+        let mut pool = pool();
+
+        pool.admin_ramp_a(200, T0 + 2 * MIN_RAMP_TIME, T0)
+            .unwrap();
+
+        assert_eq!(pool.a(T0), 100);
+        assert_eq!(pool.a(T0 + MIN_RAMP_TIME / 2), 125);
+        assert_eq!(pool.a(T0 + MIN_RAMP_TIME), 150);
+        assert_eq!(pool.a(T0 + 2 * MIN_RAMP_TIME), 200);
+        assert_eq!(pool.a(T0 + 3 * MIN_RAMP_TIME), 200);
+    }
+
+    #[test]
+    fn test_ramp_down_interpolates_a() {
+        let mut pool = pool();
+
+        pool.admin_ramp_a(50, T0 + MIN_RAMP_TIME, T0)
+            .unwrap();
+
+        assert_eq!(pool.a_precise(T0 + MIN_RAMP_TIME / 2), 7_500);
+    }
+
+    #[rstest]
+    #[case::too_short(200, T0 + MIN_RAMP_TIME - 1, AdminError::RampTooShort {
+        future_time: T0 + MIN_RAMP_TIME - 1,
+        earliest: T0 + MIN_RAMP_TIME,
+    })]
+    #[case::zero_a(0, T0 + MIN_RAMP_TIME, AdminError::InvalidA(0))]
+    #[case::a_too_large(MAX_A, T0 + MIN_RAMP_TIME, AdminError::InvalidA(MAX_A))]
+    #[case::increase_too_large(1_001, T0 + MIN_RAMP_TIME, AdminError::AChangeTooLarge {
+        current: 100,
+        future: 1_001,
+    })]
+    #[case::decrease_too_large(9, T0 + MIN_RAMP_TIME, AdminError::AChangeTooLarge {
+        current: 100,
+        future: 9,
+    })]
+    fn test_ramp_constraints(
+        #[case] future_a: u64,
+        #[case] future_time: u64,
+        #[case] expected: AdminError,
+    ) {
+        let mut pool = pool();
+
+        let res = pool.admin_ramp_a(future_a, future_time, T0);
+
+        assert_eq!(res, Err(expected));
+        assert_eq!(pool.a(T0 + MIN_RAMP_TIME), 100);
+    }
+
+    #[test]
+    fn test_ramp_too_soon_after_previous_ramp() {
+        let mut pool = pool();
+        pool.admin_ramp_a(200, T0 + MIN_RAMP_TIME, T0)
+            .unwrap();
+
+        let res = pool.admin_ramp_a(300, T0 + 3 * MIN_RAMP_TIME, T0 + MIN_RAMP_TIME - 1);
+
+        assert_eq!(
+            res,
+            Err(AdminError::RampTooSoon {
+                now: T0 + MIN_RAMP_TIME - 1,
+                earliest: T0 + MIN_RAMP_TIME
+            })
+        );
+        pool.admin_ramp_a(300, T0 + 3 * MIN_RAMP_TIME, T0 + MIN_RAMP_TIME)
+            .unwrap();
+    }
+
+    fn e18(amount: u64) -> U256 {
+        U256::from(amount) * U256::from(10u64).pow(U256::from(18))
+    }
+
+    fn liquid_pool() -> CurveStablePool {
+        CurveStablePool::new(vec![e18(1_000_000); 3], 100, 4_000_000, 5_000_000_000)
+    }
+
+    #[rstest]
+    #[case::balanced([e18(100), e18(100), e18(100)])]
+    #[case::single_coin([e18(300), U256::ZERO, U256::ZERO])]
+    #[case::nothing([U256::ZERO; 3])]
+    #[case::dust([U256::from(1), U256::ZERO, U256::from(1)])]
+    #[case::larger_than_pool([e18(5_000_000), e18(1), U256::ZERO])]
+    fn test_calc_token_amount_deposit(#[case] amounts: [U256; 3]) {
+        let pool = liquid_pool();
+
+        let minted = pool
+            .calc_token_amount(&amounts, true, e18(3_000_000), T0)
+            .unwrap();
+
+        let total: U256 = amounts.iter().sum();
+        assert!(minted <= total);
+    }
+
+    #[test]
+    fn test_calc_token_amount_balanced_deposit_mints_more() {
+        let pool = liquid_pool();
+        let supply = e18(3_000_000);
+
+        let balanced = pool
+            .calc_token_amount(&[e18(100_000); 3], true, supply, T0)
+            .unwrap();
+        let imbalanced = pool
+            .calc_token_amount(&[e18(300_000), U256::ZERO, U256::ZERO], true, supply, T0)
+            .unwrap();
+
+        // A balanced pool at a supply equal to D mints one LP token per deposited coin.
+        assert_eq!(balanced, e18(300_000));
+        assert!(imbalanced < balanced);
+    }
+
+    #[test]
+    fn test_calc_token_amount_withdrawal() {
+        let pool = liquid_pool();
+        let supply = e18(1_500_000);
+
+        let burned = pool
+            .calc_token_amount(&[e18(100_000); 3], false, supply, T0)
+            .unwrap();
+        let res =
+            pool.calc_token_amount(&[e18(1_000_001), U256::ZERO, U256::ZERO], false, supply, T0);
+
+        assert_eq!(burned, e18(150_000));
+        assert_eq!(
+            res,
+            Err(MathError::InsufficientBalance {
+                index: 0,
+                amount: e18(1_000_001),
+                balance: e18(1_000_000)
+            })
+        );
+    }
+
+    #[test]
+    fn test_calc_token_amount_invalid_input() {
+        let pool = liquid_pool();
+        let empty = CurveStablePool::new(vec![U256::ZERO; 3], 100, 4_000_000, 5_000_000_000);
+
+        assert_eq!(
+            pool.calc_token_amount(&[e18(1); 2], true, e18(1), T0),
+            Err(MathError::AmountsLengthMismatch { expected: 3, got: 2 })
+        );
+        assert_eq!(
+            empty.calc_token_amount(&[e18(1); 3], true, U256::ZERO, T0),
+            Err(MathError::EmptyPool)
+        );
+    }
+
+    #[test]
+    fn test_stop_ramp_freezes_current_a() {
+        let mut pool = pool();
+        pool.admin_ramp_a(200, T0 + 2 * MIN_RAMP_TIME, T0)
+            .unwrap();
+
+        pool.admin_stop_ramp_a(T0 + MIN_RAMP_TIME);
+
+        assert_eq!(pool.a(T0 + MIN_RAMP_TIME), 150);
+        assert_eq!(pool.a(T0 + 2 * MIN_RAMP_TIME), 150);
+    }
+}
@@ -0,0 +1,810 @@
+//! Curve TriCrypto pool math
+//!
+//! Models a 3-coin CryptoSwap pool like TriCrypto2 (USDT/WBTC/WETH), mirroring the integer math of
+//! `CurveCryptoMath3.vy` and `CurveCryptoViews3.vy` so quotes match the contract to the wei.
+//!
+//! Balances are raw token amounts. The invariant is computed on them after scaling to 18 decimals
+//! and converting coins 1 and 2 to coin 0 with the price scale, which is quoted in coin 0 with 18
+//! decimals. A is stored as `A * N**N * A_MULTIPLIER` (`ANN`), like the contract does; fees are
+//! fractions of [`FEE_DENOMINATOR`].
+//!
+//! The Newton solvers keep the contract's safety limits: parameters and balances outside of them
+//! are rejected with [`TriCryptoError::UnsafeValue`] rather than risking a wrong quote.
+use alloy_primitives::U256;
+use thiserror::Error;
+
+pub use super::stable_pool::FEE_DENOMINATOR;
+
+pub const N_COINS: usize = 3;
+/// Precision of balances scaled to a common unit, and of the price scale.
+pub const PRECISION: U256 = U256::from_limbs([1_000_000_000_000_000_000, 0, 0, 0]);
+pub const A_MULTIPLIER: u64 = 10_000;
+/// Lower bound of `ANN`.
+pub const MIN_A: u64 = 27 * A_MULTIPLIER / 100;
+/// Upper bound of `ANN`.
+pub const MAX_A: u64 = 27 * A_MULTIPLIER * 1_000;
+pub const MIN_GAMMA: u64 = 10_000_000_000;
+pub const MAX_GAMMA: u64 = 50_000_000_000_000_000;
+/// Fee charged on every deposit and withdrawal, balanced or not.
+pub const NOISE_FEE: u64 = 100_000;
+
+/// Upper bound of a scaled balance and of D, `10**15 * 10**18`.
+const MAX_VALUE: U256 = U256::from_limbs([4_089_650_035_136_921_600, 54_210_108_624_275, 0, 0]);
+/// Upper bound of a scaled balance relative to D, `10**20`.
+const MAX_FRAC: U256 = U256::from_limbs([7_766_279_631_452_241_920, 5, 0, 0]);
+const MIN_FRAC: U256 = U256::from_limbs([10_000_000_000_000_000, 0, 0, 0]);
+/// Bits of each price in a packed price scale.
+const PRICE_SIZE: usize = 128;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TriCryptoError {
+    #[error("Coin index {0} is out of range")]
+    CoinIndexOutOfRange(usize),
+    #[error("Can't exchange coin {0} for itself")]
+    SameCoin(usize),
+    #[error("Can't exchange 0 coins")]
+    ZeroAmount,
+    #[error("Unsafe value of {0}")]
+    UnsafeValue(&'static str),
+    #[error("Withdrawal of {amount} exceeds the balance {balance} of coin {index}")]
+    InsufficientBalance { index: usize, amount: U256, balance: U256 },
+    #[error("{0} did not converge")]
+    DidNotConverge(&'static str),
+    #[error("Arithmetic overflow, underflow or division by zero")]
+    Arithmetic,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurveTriCryptoPool {
+    balances: [U256; N_COINS],
+    /// Factor scaling each coin to 18 decimals (`PRECISIONS`).
+    precisions: [U256; N_COINS],
+    ann: U256,
+    gamma: U256,
+    price_scale: [U256; N_COINS - 1],
+    d: Option<U256>,
+    mid_fee: U256,
+    out_fee: U256,
+    fee_gamma: U256,
+}
+
+impl CurveTriCryptoPool {
+    /// Creates a pool without fees from the pool's balances, the decimals of its coins, its
+    /// packed `A_gamma` (see [`unpack_a_gamma`]) and the price scales of coins 1 and 2.
+    ///
+    /// # Panics
+    ///
+    /// If a coin has more than 18 decimals.
+    pub fn new(
+        balances: [U256; N_COINS],
+        decimals: [u8; N_COINS],
+        a_gamma: U256,
+        price_scale: [U256; N_COINS - 1],
+    ) -> Self {
+        let (ann, gamma) = unpack_a_gamma(a_gamma);
+        Self {
+            balances,
+            precisions: decimals.map(|d| U256::from(10u64).pow(U256::from(18 - d))),
+            ann,
+            gamma,
+            price_scale,
+            d: None,
+            mid_fee: U256::ZERO,
+            out_fee: U256::ZERO,
+            fee_gamma: U256::ZERO,
+        }
+    }
+
+    /// Sets the fees, e.g. from the pool's `mid_fee`, `out_fee` and `fee_gamma`.
+    ///
+    /// The fee moves from `mid_fee` in a balanced pool to `out_fee` in an imbalanced one, the
+    /// faster the smaller `fee_gamma` is.
+    pub fn with_fees(mut self, mid_fee: u64, out_fee: u64, fee_gamma: u64) -> Self {
+        self.mid_fee = U256::from(mid_fee);
+        self.out_fee = U256::from(out_fee);
+        self.fee_gamma = U256::from(fee_gamma);
+        self
+    }
+
+    /// Uses the pool's stored `D`, as the contract does as long as `future_A_gamma_time` is 0.
+    ///
+    /// Without it D is solved from the balances, as the contract does once A or gamma were
+    /// ramped.
+    pub fn with_d(mut self, d: U256) -> Self {
+        self.d = Some(d);
+        self
+    }
+
+    pub fn balances(&self) -> &[U256; N_COINS] {
+        &self.balances
+    }
+
+    /// A as `ANN`, i.e. multiplied by `N**N` and [`A_MULTIPLIER`].
+    pub fn a(&self) -> U256 {
+        self.ann
+    }
+
+    pub fn gamma(&self) -> U256 {
+        self.gamma
+    }
+
+    pub fn price_scale(&self) -> &[U256; N_COINS - 1] {
+        &self.price_scale
+    }
+
+    /// Amount of coin `j` received for `dx` of coin `i`, net of fees (`get_dy`).
+    pub fn get_dy(&self, i: usize, j: usize, dx: U256) -> Result<U256, TriCryptoError> {
+        for index in [i, j] {
+            if index >= N_COINS {
+                return Err(TriCryptoError::CoinIndexOutOfRange(index));
+            }
+        }
+        if i == j {
+            return Err(TriCryptoError::SameCoin(i));
+        }
+        if dx.is_zero() {
+            return Err(TriCryptoError::ZeroAmount);
+        }
+
+        let d = self.d()?;
+        let mut balances = self.balances;
+        balances[i] = add(balances[i], dx)?;
+        let mut xp = self.scale(balances)?;
+        let y = newton_y(self.ann, self.gamma, xp, d, j)?;
+        let mut dy = sub(sub(xp[j], y)?, U256::from(1))?;
+        xp[j] = y;
+        if j > 0 {
+            dy = div(mul(dy, PRECISION)?, self.price_scale[j - 1])?;
+        }
+        dy /= self.precisions[j];
+        let fee = div(mul(self.fee(xp)?, dy)?, U256::from(FEE_DENOMINATOR))?;
+        sub(dy, fee)
+    }
+
+    /// LP tokens minted by depositing, or burned by withdrawing, `amounts`
+    /// (`calc_token_amount`).
+    ///
+    /// Includes the fee charged on the imbalance of `amounts`, so this is the exact amount the
+    /// contract quotes.
+    pub fn calc_token_amount_tricrypto(
+        &self,
+        amounts: &[U256; N_COINS],
+        is_deposit: bool,
+        lp_total_supply: U256,
+    ) -> Result<U256, TriCryptoError> {
+        let mut balances = self.balances;
+        for (index, (balance, &amount)) in balances
+            .iter_mut()
+            .zip(amounts)
+            .enumerate()
+        {
+            *balance = if is_deposit {
+                add(*balance, amount)?
+            } else {
+                balance
+                    .checked_sub(amount)
+                    .ok_or(TriCryptoError::InsufficientBalance {
+                        index,
+                        amount,
+                        balance: *balance,
+                    })?
+            };
+        }
+        let xp = self.scale(balances)?;
+        let amounts = self.scale(*amounts)?;
+
+        let d0 = self.d()?;
+        let d1 = newton_d(self.ann, self.gamma, xp)?;
+        let scaled_supply = div(mul(lp_total_supply, d1)?, d0)?;
+        let d_token = if is_deposit {
+            sub(scaled_supply, lp_total_supply)?
+        } else {
+            sub(lp_total_supply, scaled_supply)?
+        };
+        let fee = div(mul(self.token_fee(&amounts, xp)?, d_token)?, U256::from(FEE_DENOMINATOR))?;
+        sub(d_token, add(fee, U256::from(1))?)
+    }
+
+    /// The stored D, or D solved from the balances.
+    fn d(&self) -> Result<U256, TriCryptoError> {
+        match self.d {
+            Some(d) => Ok(d),
+            None => newton_d(self.ann, self.gamma, self.scale(self.balances)?),
+        }
+    }
+
+    /// Scales raw amounts to 18 decimals and converts them to coin 0 (`xp`).
+    fn scale(&self, amounts: [U256; N_COINS]) -> Result<[U256; N_COINS], TriCryptoError> {
+        let mut xp = amounts;
+        xp[0] = mul(xp[0], self.precisions[0])?;
+        for (k, x) in xp.iter_mut().enumerate().skip(1) {
+            let price = mul(self.price_scale[k - 1], self.precisions[k])?;
+            *x = div(mul(*x, price)?, PRECISION)?;
+        }
+        Ok(xp)
+    }
+
+    /// The swap fee at the scaled balances `xp` (`_fee`).
+    fn fee(&self, xp: [U256; N_COINS]) -> Result<U256, TriCryptoError> {
+        let f = reduction_coefficient(xp, self.fee_gamma)?;
+        let mid = mul(self.mid_fee, f)?;
+        let out = mul(self.out_fee, sub(PRECISION, f)?)?;
+        Ok(add(mid, out)? / PRECISION)
+    }
+
+    /// The fee on a deposit or withdrawal of the scaled `amounts` (`_calc_token_fee`).
+    fn token_fee(
+        &self,
+        amounts: &[U256; N_COINS],
+        xp: [U256; N_COINS],
+    ) -> Result<U256, TriCryptoError> {
+        let n = U256::from(N_COINS);
+        let fee = mul(self.fee(xp)?, n)? / U256::from(4 * (N_COINS - 1));
+        let sum = amounts
+            .iter()
+            .try_fold(U256::ZERO, |sum, amount| add(sum, *amount))?;
+        let avg = sum / n;
+        let diff = amounts
+            .iter()
+            .fold(U256::ZERO, |diff, amount| diff + amount.abs_diff(avg));
+        add(div(mul(fee, diff)?, sum)?, U256::from(NOISE_FEE))
+    }
+}
+
+/// Splits a packed `A_gamma` into `ANN`, stored in the upper 128 bits, and gamma.
+pub fn unpack_a_gamma(a_gamma: U256) -> (U256, U256) {
+    let mask = (U256::from(1) << 128) - U256::from(1);
+    (a_gamma >> 128, a_gamma & mask)
+}
+
+/// Packs `ANN` and gamma into `A_gamma`, like the contract stores them.
+pub fn pack_a_gamma(ann: U256, gamma: U256) -> U256 {
+    (ann << 128) | gamma
+}
+
+/// Splits a packed `price_scale_packed` into the price scales of coins 1 and 2.
+pub fn unpack_price_scale(packed: U256) -> [U256; N_COINS - 1] {
+    let mask = (U256::from(1) << PRICE_SIZE) - U256::from(1);
+    [packed & mask, (packed >> PRICE_SIZE) & mask]
+}
+
+/// The geometric mean of `x`, sorted from high to low (`geometric_mean`).
+pub fn geometric_mean(x: [U256; N_COINS]) -> Result<U256, TriCryptoError> {
+    let n = U256::from(N_COINS);
+    let mut d = x[0];
+    for _ in 0..255 {
+        let d_prev = d;
+        let mut tmp = PRECISION;
+        for x_i in x {
+            tmp = div(mul(tmp, x_i)?, d)?;
+        }
+        d = div(mul(d, add((n - U256::from(1)) * PRECISION, tmp)?)?, n * PRECISION)?;
+        let diff = d.abs_diff(d_prev);
+        if diff <= U256::from(1) || mul(diff, PRECISION)? < d {
+            return Ok(d);
+        }
+    }
+    Err(TriCryptoError::DidNotConverge("Geometric mean"))
+}
+
+/// The CryptoSwap invariant D of the scaled balances `x_unsorted` (`newton_D`).
+///
+/// Solved by Newton's method starting from the constant product invariant. Converges once D
+/// changes by less than `max(10**16, D) / 10**14` between iterations; unlike the 2 coin
+/// invariant this can take tens of iterations for imbalanced pools with a large A.
+pub fn newton_d(
+    ann: U256,
+    gamma: U256,
+    x_unsorted: [U256; N_COINS],
+) -> Result<U256, TriCryptoError> {
+    check_a_gamma(ann, gamma)?;
+    let x = sort(x_unsorted);
+    if x[0] < U256::from(1_000_000_000u64) || x[0] > MAX_VALUE {
+        return Err(TriCryptoError::UnsafeValue("x"));
+    }
+    for x_i in &x[1..] {
+        if div(mul(*x_i, PRECISION)?, x[0])? < U256::from(100_000_000_000u64) {
+            return Err(TriCryptoError::UnsafeValue("x"));
+        }
+    }
+
+    let n = U256::from(N_COINS);
+    let two = U256::from(2);
+    let mut d = mul(n, geometric_mean(x)?)?;
+    let s = x
+        .iter()
+        .try_fold(U256::ZERO, |sum, x_i| add(sum, *x_i))?;
+    for _ in 0..255 {
+        let d_prev = d;
+        let mut k0 = PRECISION;
+        for x_i in x {
+            k0 = div(mul(mul(k0, x_i)?, n)?, d)?;
+        }
+        let g1k0 = (gamma + PRECISION).abs_diff(k0) + U256::from(1);
+        let mul1 = get_mul1(ann, gamma, d, g1k0)?;
+        let mul2 = div(mul(two * PRECISION * n, k0)?, g1k0)?;
+
+        let neg_fprime = sub(
+            add(add(s, div(mul(s, mul2)?, PRECISION)?)?, div(mul(mul1, n)?, k0)?)?,
+            div(mul(mul2, d)?, PRECISION)?,
+        )?;
+        let d_plus = div(mul(d, add(neg_fprime, s)?)?, neg_fprime)?;
+        let mut d_minus = div(mul(d, d)?, neg_fprime)?;
+        let correction = div(
+            mul(div(mul(d, div(mul1, neg_fprime)?)?, PRECISION)?, PRECISION.abs_diff(k0))?,
+            k0,
+        )?;
+        d_minus =
+            if PRECISION > k0 { add(d_minus, correction)? } else { sub(d_minus, correction)? };
+        d = if d_plus > d_minus { d_plus - d_minus } else { (d_minus - d_plus) / two };
+
+        let diff = d.abs_diff(d_prev);
+        let limit = d.max(U256::from(10_000_000_000_000_000u64));
+        if mul(diff, U256::from(100_000_000_000_000u64))? < limit {
+            // the next newton_y must be safe too
+            for x_i in x {
+                check_frac(x_i, d, "x")?;
+            }
+            return Ok(d);
+        }
+    }
+    Err(TriCryptoError::DidNotConverge("D"))
+}
+
+/// The scaled balance of coin `i` for which the other balances `x` keep the invariant at `d`
+/// (`newton_y`).
+pub fn newton_y(
+    ann: U256,
+    gamma: U256,
+    x: [U256; N_COINS],
+    d: U256,
+    i: usize,
+) -> Result<U256, TriCryptoError> {
+    check_a_gamma(ann, gamma)?;
+    if i >= N_COINS {
+        return Err(TriCryptoError::CoinIndexOutOfRange(i));
+    }
+    if d < U256::from(100_000_000_000_000_000u64) || d > MAX_VALUE {
+        return Err(TriCryptoError::UnsafeValue("D"));
+    }
+    for (k, x_k) in x.iter().enumerate() {
+        if k != i {
+            check_frac(*x_k, d, "x")?;
+        }
+    }
+
+    let n = U256::from(N_COINS);
+    let e14 = U256::from(100_000_000_000_000u64);
+    let mut y = d / n;
+    let mut k0_i = PRECISION;
+    let mut s_i = U256::ZERO;
+    let mut x_sorted = x;
+    x_sorted[i] = U256::ZERO;
+    let x_sorted = sort(x_sorted);
+
+    let convergence_limit = (x_sorted[0] / e14)
+        .max(d / e14)
+        .max(U256::from(100));
+    // small balances first, the last one is the zero of coin i
+    for x_j in x_sorted[..N_COINS - 1].iter().rev() {
+        y = div(mul(y, d)?, mul(*x_j, n)?)?;
+        s_i = add(s_i, *x_j)?;
+    }
+    for x_j in &x_sorted[..N_COINS - 1] {
+        k0_i = div(mul(mul(k0_i, *x_j)?, n)?, d)?;
+    }
+
+    for _ in 0..255 {
+        let y_prev = y;
+        let k0 = div(mul(mul(k0_i, y)?, n)?, d)?;
+        let s = add(s_i, y)?;
+        let g1k0 = (gamma + PRECISION).abs_diff(k0) + U256::from(1);
+        let mul1 = get_mul1(ann, gamma, d, g1k0)?;
+        let mul2 = add(PRECISION, div(mul(U256::from(2) * PRECISION, k0)?, g1k0)?)?;
+
+        let yfprime = add(add(mul(PRECISION, y)?, mul(s, mul2)?)?, mul1)?;
+        let dyfprime = mul(d, mul2)?;
+        if yfprime < dyfprime {
+            // overshot, restart from half the previous guess
+            y = y_prev / U256::from(2);
+            continue;
+        }
+        let yfprime = yfprime - dyfprime;
+        let fprime = div(yfprime, y)?;
+
+        let mut y_minus = div(mul1, fprime)?;
+        let y_plus = add(
+            div(add(yfprime, mul(PRECISION, d)?)?, fprime)?,
+            div(mul(y_minus, PRECISION)?, k0)?,
+        )?;
+        y_minus = add(y_minus, div(mul(PRECISION, s)?, fprime)?)?;
+        y = if y_plus < y_minus { y_prev / U256::from(2) } else { y_plus - y_minus };
+
+        if y.abs_diff(y_prev) < convergence_limit.max(y / e14) {
+            check_frac(y, d, "y")?;
+            return Ok(y);
+        }
+    }
+    Err(TriCryptoError::DidNotConverge("y"))
+}
+
+/// The share of `mid_fee` in the fee, `fee_gamma / (fee_gamma + (1 - K))` with
+/// `K = prod(x) / (sum(x) / N)**N`, 1 in a balanced pool (`reduction_coefficient`).
+pub fn reduction_coefficient(x: [U256; N_COINS], fee_gamma: U256) -> Result<U256, TriCryptoError> {
+    let n = U256::from(N_COINS);
+    let s = x
+        .iter()
+        .try_fold(U256::ZERO, |sum, x_i| add(sum, *x_i))?;
+    let mut k = PRECISION;
+    for x_i in x {
+        k = div(mul(mul(k, n)?, x_i)?, s)?;
+    }
+    if fee_gamma.is_zero() {
+        return Ok(k);
+    }
+    div(mul(fee_gamma, PRECISION)?, sub(add(fee_gamma, PRECISION)?, k)?)
+}
+
+/// `D / ANN * g1k0**2 / gamma**2`, scaled by [`PRECISION`] and [`A_MULTIPLIER`].
+fn get_mul1(ann: U256, gamma: U256, d: U256, g1k0: U256) -> Result<U256, TriCryptoError> {
+    let mul1 = div(mul(PRECISION, d)?, gamma)?;
+    let mul1 = div(mul(mul1, g1k0)?, gamma)?;
+    div(mul(mul(mul1, g1k0)?, U256::from(A_MULTIPLIER))?, ann)
+}
+
+fn check_a_gamma(ann: U256, gamma: U256) -> Result<(), TriCryptoError> {
+    if ann < U256::from(MIN_A) || ann > U256::from(MAX_A) {
+        return Err(TriCryptoError::UnsafeValue("A"));
+    }
+    if gamma < U256::from(MIN_GAMMA) || gamma > U256::from(MAX_GAMMA) {
+        return Err(TriCryptoError::UnsafeValue("gamma"));
+    }
+    Ok(())
+}
+
+/// Checks that `x` is between 1% and 100 times `d`.
+fn check_frac(x: U256, d: U256, name: &'static str) -> Result<(), TriCryptoError> {
+    let frac = div(mul(x, PRECISION)?, d)?;
+    if frac < MIN_FRAC || frac > MAX_FRAC {
+        return Err(TriCryptoError::UnsafeValue(name));
+    }
+    Ok(())
+}
+
+/// Sorts from high to low.
+fn sort(mut x: [U256; N_COINS]) -> [U256; N_COINS] {
+    x.sort_unstable_by(|a, b| b.cmp(a));
+    x
+}
+
+fn add(a: U256, b: U256) -> Result<U256, TriCryptoError> {
+    a.checked_add(b)
+        .ok_or(TriCryptoError::Arithmetic)
+}
+
+fn sub(a: U256, b: U256) -> Result<U256, TriCryptoError> {
+    a.checked_sub(b)
+        .ok_or(TriCryptoError::Arithmetic)
+}
+
+fn mul(a: U256, b: U256) -> Result<U256, TriCryptoError> {
+    a.checked_mul(b)
+        .ok_or(TriCryptoError::Arithmetic)
+}
+
+fn div(a: U256, b: U256) -> Result<U256, TriCryptoError> {
+    a.checked_div(b)
+        .ok_or(TriCryptoError::Arithmetic)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use alloy::{
+        providers::{Provider, ProviderBuilder, RootProvider},
+        rpc::types::{BlockId, TransactionRequest},
+        transports::BoxTransport,
+    };
+    use alloy_primitives::{address, Address, Bytes};
+    use alloy_sol_types::{sol, SolCall};
+    use dotenv::dotenv;
+    use rstest::rstest;
+
+    use super::*;
+
+    /// TriCrypto2's `ANN` and gamma.
+    const A: u64 = 1_707_629;
+    const GAMMA: u64 = 11_809_167_828_997;
+
+    fn e18(amount: u64) -> U256 {
+        U256::from(amount) * PRECISION
+    }
+
+    /// A balanced pool with TriCrypto2's parameters, holding 30M USD of each coin at prices of
+    /// 30k USDT per WBTC and 3k USDT per WETH.
+    fn pool() -> CurveTriCryptoPool {
+        CurveTriCryptoPool::new(
+            [U256::from(30_000_000_000_000u64), U256::from(100_000_000_000u64), e18(10_000)],
+            [6, 8, 18],
+            pack_a_gamma(U256::from(A), U256::from(GAMMA)),
+            [e18(30_000), e18(3_000)],
+        )
+        .with_fees(3_000_000, 30_000_000, 500_000_000_000_000)
+    }
+
+    #[test]
+    fn test_unpack() {
+        let packed_prices = (e18(3_000) << PRICE_SIZE) | e18(30_000);
+
+        assert_eq!(
+            unpack_a_gamma(pack_a_gamma(U256::from(A), U256::from(GAMMA))),
+            (U256::from(A), U256::from(GAMMA))
+        );
+        assert_eq!(unpack_price_scale(packed_prices), [e18(30_000), e18(3_000)]);
+    }
+
+    #[test]
+    fn test_newton_d_balanced() {
+        let d = newton_d(U256::from(A), U256::from(GAMMA), [e18(30_000_000); 3]).unwrap();
+
+        assert_eq!(d, e18(90_000_000));
+    }
+
+    #[rstest]
+    #[case::tricrypto2(A, GAMMA, 1)]
+    #[case::min_a_min_gamma(MIN_A, MIN_GAMMA, 100)]
+    #[case::min_a_max_gamma(MIN_A, MAX_GAMMA, 100)]
+    #[case::max_a_min_gamma(MAX_A, MIN_GAMMA, 100)]
+    #[case::max_a_max_gamma(MAX_A, MAX_GAMMA, 100)]
+    #[case::max_a_max_gamma_mild(MAX_A, MAX_GAMMA, 10)]
+    fn test_newton_y_inverts_newton_d(
+        #[case] ann: u64,
+        #[case] gamma: u64,
+        #[case] imbalance: u64,
+    ) {
+        let (ann, gamma) = (U256::from(ann), U256::from(gamma));
+        let x = [e18(1_000_000), e18(1_000_000 / imbalance), e18(1_000_000 / imbalance)];
+
+        let d = newton_d(ann, gamma, x).unwrap();
+
+        for (i, x_i) in x.into_iter().enumerate() {
+            let y = newton_y(ann, gamma, x, d, i).unwrap();
+            assert!(y.abs_diff(x_i) * U256::from(1_000_000_000_000u64) < x_i, "{i}: {y} != {x_i}");
+        }
+    }
+
+    #[rstest]
+    #[case::a_too_small(MIN_A - 1, GAMMA, [e18(1); 3], "A")]
+    #[case::a_too_large(MAX_A + 1, GAMMA, [e18(1); 3], "A")]
+    #[case::gamma_too_small(A, MIN_GAMMA - 1, [e18(1); 3], "gamma")]
+    #[case::gamma_too_large(A, MAX_GAMMA + 1, [e18(1); 3], "gamma")]
+    #[case::dust(A, GAMMA, [U256::from(999_999_999); 3], "x")]
+    #[case::imbalanced(A, GAMMA, [e18(10_000_000), e18(1), e18(1)], "x")]
+    fn test_newton_d_unsafe_values(
+        #[case] ann: u64,
+        #[case] gamma: u64,
+        #[case] x: [U256; 3],
+        #[case] name: &'static str,
+    ) {
+        let res = newton_d(U256::from(ann), U256::from(gamma), x);
+
+        assert_eq!(res, Err(TriCryptoError::UnsafeValue(name)));
+    }
+
+    #[rstest]
+    // 30k USDT for a WBTC and a WETH for 3k USDT, less the 0.03% mid fee
+    #[case::usdt_wbtc(0, 1, U256::from(30_000_000_000u64), 99_960_000, 99_970_000)]
+    #[case::weth_usdt(2, 0, e18(1), 2_998_800_000, 2_999_100_000)]
+    fn test_get_dy_balanced(
+        #[case] i: usize,
+        #[case] j: usize,
+        #[case] dx: U256,
+        #[case] min: u64,
+        #[case] max: u64,
+    ) {
+        let dy = pool().get_dy(i, j, dx).unwrap();
+
+        assert!(dy > U256::from(min) && dy < U256::from(max), "{dy}");
+    }
+
+    #[test]
+    fn test_get_dy_slippage() {
+        let pool = pool();
+        let amounts = [1_000_000u64, 1_000_000_000, 1_000_000_000_000, 10_000_000_000_000];
+
+        let rates = amounts
+            .into_iter()
+            .map(|dx| {
+                let dy = pool
+                    .get_dy(0, 2, U256::from(dx))
+                    .unwrap();
+                (dy, dy / U256::from(dx))
+            })
+            .collect::<Vec<_>>();
+
+        for pair in rates.windows(2) {
+            let [(dy, rate), (next_dy, next_rate)] = pair else { unreachable!() };
+            assert!(next_dy > dy);
+            assert!(next_rate <= rate);
+        }
+    }
+
+    #[rstest]
+    #[case::index_out_of_range(3, 0, e18(1), TriCryptoError::CoinIndexOutOfRange(3))]
+    #[case::same_coin(1, 1, e18(1), TriCryptoError::SameCoin(1))]
+    #[case::zero(0, 1, U256::ZERO, TriCryptoError::ZeroAmount)]
+    #[case::larger_than_pool(
+        0,
+        1,
+        U256::from(300_000_000_000_000_000u64),
+        TriCryptoError::UnsafeValue("x")
+    )]
+    fn test_get_dy_invalid(
+        #[case] i: usize,
+        #[case] j: usize,
+        #[case] dx: U256,
+        #[case] expected: TriCryptoError,
+    ) {
+        assert_eq!(pool().get_dy(i, j, dx), Err(expected));
+    }
+
+    #[test]
+    fn test_calc_token_amount() {
+        let pool = pool();
+        let supply = e18(1_000_000);
+        let balanced = [U256::from(300_000_000_000u64), U256::from(1_000_000_000u64), e18(100)];
+        let imbalanced = [U256::from(900_000_000_000u64), U256::ZERO, U256::ZERO];
+
+        let minted = pool
+            .calc_token_amount_tricrypto(&balanced, true, supply)
+            .unwrap();
+        let minted_imbalanced = pool
+            .calc_token_amount_tricrypto(&imbalanced, true, supply)
+            .unwrap();
+        let burned = pool
+            .calc_token_amount_tricrypto(&balanced, false, supply)
+            .unwrap();
+
+        // 1% of the pool for 1% of the supply, less the noise fee and 1 wei
+        assert_eq!(minted, e18(10_000) - U256::from(100_000_000_000_000_000u64) - U256::from(1));
+        assert!(minted_imbalanced < minted);
+        assert_eq!(burned, minted);
+    }
+
+    #[test]
+    fn test_calc_token_amount_insufficient_balance() {
+        let amounts = [U256::ZERO, U256::from(100_000_000_001u64), U256::ZERO];
+
+        let res = pool().calc_token_amount_tricrypto(&amounts, false, e18(1_000_000));
+
+        assert_eq!(
+            res,
+            Err(TriCryptoError::InsufficientBalance {
+                index: 1,
+                amount: U256::from(100_000_000_001u64),
+                balance: U256::from(100_000_000_000u64)
+            })
+        );
+    }
+
+    sol! {
+        interface ICurveTriCrypto {
+            function balances(uint256 i) external view returns (uint256 balance);
+            function price_scale(uint256 k) external view returns (uint256 price);
+            function A() external view returns (uint256 a);
+            function gamma() external view returns (uint256 gamma);
+            function D() external view returns (uint256 d);
+            function future_A_gamma_time() external view returns (uint256 time);
+            function mid_fee() external view returns (uint256 fee);
+            function out_fee() external view returns (uint256 fee);
+            function fee_gamma() external view returns (uint256 fee_gamma);
+            function token() external view returns (address token);
+            function get_dy(uint256 i, uint256 j, uint256 dx) external view returns (uint256 dy);
+            function calc_token_amount(uint256[3] amounts, bool deposit)
+                external view returns (uint256 amount);
+            function totalSupply() external view returns (uint256 supply);
+        }
+    }
+
+    const TRICRYPTO2: Address = address!("D51a44d3FaE010294C616388b506AcdA1bfAAE46");
+    const BLOCK: u64 = 19_000_000;
+
+    async fn call<C: SolCall>(
+        provider: &RootProvider<BoxTransport>,
+        to: Address,
+        call: C,
+    ) -> C::Return {
+        let tx = TransactionRequest::default()
+            .to(to)
+            .input(Bytes::from(call.abi_encode()).into());
+        let output = provider
+            .call(&tx)
+            .block(BlockId::number(BLOCK))
+            .await
+            .expect("eth_call failed");
+        C::abi_decode_returns(&output, true).expect("Unexpected return data")
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    #[cfg_attr(not(feature = "network_tests"), ignore)]
+    async fn test_matches_tricrypto2() {
+        use ICurveTriCrypto::*;
+
+        let rpc_url = env::var("RPC_URL").unwrap_or_else(|_| {
+            dotenv().expect("Missing .env file");
+            env::var("RPC_URL").expect("Missing RPC_URL in .env file")
+        });
+        let provider = ProviderBuilder::new()
+            .on_builtin(&rpc_url)
+            .await
+            .unwrap();
+        let p = &provider;
+
+        let mut balances = [U256::ZERO; 3];
+        for (i, balance) in balances.iter_mut().enumerate() {
+            *balance = call(p, TRICRYPTO2, balancesCall { i: U256::from(i) })
+                .await
+                .balance;
+        }
+        let mut price_scale = [U256::ZERO; 2];
+        for (k, price) in price_scale.iter_mut().enumerate() {
+            *price = call(p, TRICRYPTO2, price_scaleCall { k: U256::from(k) })
+                .await
+                .price;
+        }
+        let a = call(p, TRICRYPTO2, ACall {}).await.a;
+        let gamma = call(p, TRICRYPTO2, gammaCall {})
+            .await
+            .gamma;
+        let fee = |fee: U256| u64::try_from(fee).unwrap();
+        let mid_fee = fee(call(p, TRICRYPTO2, mid_feeCall {})
+            .await
+            .fee);
+        let out_fee = fee(call(p, TRICRYPTO2, out_feeCall {})
+            .await
+            .fee);
+        let fee_gamma = fee(call(p, TRICRYPTO2, fee_gammaCall {})
+            .await
+            .fee_gamma);
+        let mut pool =
+            CurveTriCryptoPool::new(balances, [6, 8, 18], pack_a_gamma(a, gamma), price_scale)
+                .with_fees(mid_fee, out_fee, fee_gamma);
+        if call(p, TRICRYPTO2, future_A_gamma_timeCall {})
+            .await
+            .time
+            .is_zero()
+        {
+            pool = pool.with_d(call(p, TRICRYPTO2, DCall {}).await.d);
+        }
+
+        // 10k USD of each coin, swapped for each other coin
+        let amounts = [U256::from(10_000_000_000u64), U256::from(25_000_000u64), e18(4)];
+        for (i, dx) in amounts.into_iter().enumerate() {
+            for j in (0..3).filter(|&j| j != i) {
+                let expected =
+                    call(p, TRICRYPTO2, get_dyCall { i: U256::from(i), j: U256::from(j), dx })
+                        .await
+                        .dy;
+                assert_eq!(pool.get_dy(i, j, dx).unwrap(), expected, "{i} -> {j}");
+            }
+        }
+
+        let token = call(p, TRICRYPTO2, tokenCall {})
+            .await
+            .token;
+        let supply = call(p, token, totalSupplyCall {})
+            .await
+            .supply;
+        for deposit in [true, false] {
+            let expected = call(p, TRICRYPTO2, calc_token_amountCall { amounts, deposit })
+                .await
+                .amount;
+            assert_eq!(
+                pool.calc_token_amount_tricrypto(&amounts, deposit, supply)
+                    .unwrap(),
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Decoding of pool states from the account updates of a block.
+//!
+//! VM streams report pools as raw storage changes. [`BulkDecoder`] looks up every updated account
+//! in a [`PoolRegistry`] once and decodes the slots of registered pools with the decoder of their
+//! [`StorageLayout`], instead of searching the block's updates separately for each pool.
+use std::collections::HashMap;
+
+use alloy_primitives::{Address, U256};
+use revm::interpreter::opcode::SSTORE;
+use thiserror::Error;
+
+use crate::evm::{
+    inferrer::{OpcodeTrace, PartialPoolState, PoolId, PoolStateInferrer, StorageLayout},
+    tycho_models::{AccountUpdate, BlockAccountChanges, ChangeType},
+};
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    #[error("Account of pool {0} was deleted")]
+    AccountDeleted(Address),
+}
+
+/// The pools to decode, by contract address.
+#[derive(Debug, Clone, Default)]
+pub struct PoolRegistry {
+    pools: HashMap<Address, StorageLayout>,
+}
+
+impl PoolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a pool, replacing the layout of a pool registered at the same address.
+    pub fn register(&mut self, address: Address, layout: StorageLayout) {
+        self.pools.insert(address, layout);
+    }
+
+    pub fn get(&self, address: &Address) -> Option<PoolId> {
+        self.pools
+            .get(address)
+            .map(|layout| PoolId { address: *address, layout: *layout })
+    }
+
+    pub fn len(&self) -> usize {
+        self.pools.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pools.is_empty()
+    }
+}
+
+/// The outcome of decoding a block, each list ordered by address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BulkDecodeResult {
+    /// Registered pools whose update changed a decoded slot, with the fields it changed.
+    pub updated: Vec<(PoolId, PartialPoolState)>,
+    pub errors: Vec<(Address, DecodeError)>,
+    /// Updated accounts that aren't registered pools.
+    pub unknown: Vec<Address>,
+}
+
+pub struct BulkDecoder;
+
+impl BulkDecoder {
+    /// Decodes the updates of all registered pools in `changes`.
+    ///
+    /// Pools whose update doesn't touch a slot of their layout are in neither list of the
+    /// result.
+    pub fn decode_all(changes: &BlockAccountChanges, registry: &PoolRegistry) -> BulkDecodeResult {
+        let mut result = BulkDecodeResult::default();
+        for (address, update) in &changes.account_updates {
+            let Some(pool) = registry.get(address) else {
+                result.unknown.push(*address);
+                continue;
+            };
+            match Self::decode(pool, update) {
+                Ok(Some(state)) => result.updated.push((pool, state)),
+                Ok(None) => {}
+                Err(err) => result.errors.push((*address, err)),
+            }
+        }
+        result
+            .updated
+            .sort_unstable_by_key(|(pool, _)| pool.address);
+        result
+            .errors
+            .sort_unstable_by_key(|(address, _)| *address);
+        result.unknown.sort_unstable();
+        result
+    }
+
+    /// Decodes the update of a single pool, `None` if it doesn't touch a slot of its layout.
+    ///
+    /// Written slots are decoded like `SSTORE`s of a trace, deleted slots as writes of zero.
+    pub fn decode(
+        pool: PoolId,
+        update: &AccountUpdate,
+    ) -> Result<Option<PartialPoolState>, DecodeError> {
+        if update.change == ChangeType::Deletion {
+            return Err(DecodeError::AccountDeleted(pool.address));
+        }
+        let writes: Vec<_> = update
+            .deleted_slots
+            .iter()
+            .map(|slot| (*slot, U256::ZERO))
+            .chain(
+                update
+                    .slots
+                    .iter()
+                    .map(|(slot, value)| (*slot, *value)),
+            )
+            .map(|(slot, value)| OpcodeTrace { address: pool.address, opcode: SSTORE, slot, value })
+            .collect();
+        Ok(PoolStateInferrer::infer_from_trace(pool, &writes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm::{
+        storage_layout::{UniswapV2Reserves, V2_RESERVES_SLOT, V3_LIQUIDITY_SLOT},
+        tycho_models::{Block, Chain},
+    };
+
+    fn address(i: u8) -> Address {
+        Address::repeat_byte(i)
+    }
+
+    fn update(address: Address, slots: &[(U256, U256)], change: ChangeType) -> AccountUpdate {
+        AccountUpdate::new(
+            address,
+            Chain::Ethereum,
+            slots.iter().copied().collect(),
+            None,
+            None,
+            change,
+        )
+    }
+
+    #[test]
+    fn test_decode_all() {
+        let mut registry = PoolRegistry::new();
+        registry.register(address(1), StorageLayout::UniswapV2);
+        registry.register(address(2), StorageLayout::UniswapV3);
+        registry.register(address(3), StorageLayout::UniswapV3);
+        registry.register(address(4), StorageLayout::UniswapV2);
+        let reserves = UniswapV2Reserves {
+            reserve0: U256::from(100),
+            reserve1: U256::from(200),
+            block_timestamp_last: 7,
+        };
+        let updates = [
+            update(address(1), &[(V2_RESERVES_SLOT, reserves.pack())], ChangeType::Update),
+            update(address(2), &[(V3_LIQUIDITY_SLOT, U256::from(5))], ChangeType::Update),
+            // no slot of the layout changed
+            update(address(3), &[(U256::from(100), U256::from(1))], ChangeType::Update),
+            update(address(4), &[], ChangeType::Deletion),
+            update(address(5), &[(V2_RESERVES_SLOT, reserves.pack())], ChangeType::Update),
+        ];
+        let changes = BlockAccountChanges::new(
+            "vm:test".to_string(),
+            Chain::Ethereum,
+            Block::default(),
+            updates
+                .into_iter()
+                .map(|update| (update.address, update))
+                .collect(),
+            HashMap::new(),
+        );
+
+        let result = BulkDecoder::decode_all(&changes, &registry);
+
+        assert_eq!(
+            result,
+            BulkDecodeResult {
+                updated: vec![
+                    (
+                        registry.get(&address(1)).unwrap(),
+                        PartialPoolState::UniswapV2 {
+                            reserve0: U256::from(100),
+                            reserve1: U256::from(200),
+                            block_timestamp_last: 7
+                        }
+                    ),
+                    (
+                        registry.get(&address(2)).unwrap(),
+                        PartialPoolState::UniswapV3 {
+                            sqrt_price: None,
+                            tick: None,
+                            liquidity: Some(5)
+                        }
+                    ),
+                ],
+                errors: vec![(address(4), DecodeError::AccountDeleted(address(4)))],
+                unknown: vec![address(5)],
+            }
+        );
+    }
+
+    #[test]
+    fn test_decode_deleted_slot() {
+        let pool = PoolId { address: address(1), layout: StorageLayout::UniswapV3 };
+        let update =
+            update(address(1), &[], ChangeType::Update).with_deleted_slots(vec![V3_LIQUIDITY_SLOT]);
+
+        let state = BulkDecoder::decode(pool, &update).unwrap();
+
+        assert_eq!(
+            state,
+            Some(PartialPoolState::UniswapV3 { sqrt_price: None, tick: None, liquidity: Some(0) })
+        );
+    }
+}
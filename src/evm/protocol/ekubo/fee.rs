@@ -0,0 +1,147 @@
+//! Validation of Ekubo pool fees
+//!
+//! Ekubo fees are fractions of 2^64. Pools can be created permissionlessly with any fee, so the
+//! decoder accepts every fee. [`EkuboFeeValidator`] restricts pools to a set of fees, either the
+//! known default tiers or a set configured on-chain, for users who opt in, for example with
+//! [`ekubo_default_fee_filter`](crate::evm::protocol::filters::ekubo_default_fee_filter).
+use std::fmt::Debug;
+
+use alloy_primitives::{Address, U256};
+use alloy_sol_types::SolValue;
+use revm::DatabaseRef;
+use thiserror::Error;
+
+use crate::{
+    evm::{
+        engine_db::{
+            engine_db_interface::{EngineDatabaseError, EngineDatabaseInterface},
+            simulation_db::BlockHeader,
+        },
+        protocol::vm::tycho_simulation_contract::TychoSimulationContract,
+        simulation::SimulationEngine,
+    },
+    protocol::errors::SimulationError,
+};
+
+/// The known fee tiers: no fee (oracle pools), 0.01%, 0.05%, 0.3% and 1%.
+pub const DEFAULT_FEES: [u64; 5] = [
+    0,
+    1_844_674_407_370_955,
+    9_223_372_036_854_775,
+    55_340_232_221_128_654,
+    184_467_440_737_095_516,
+];
+
+/// A fee as a fraction of the swapped amount, e.g. 0.003 for a fee of 0.3% of 2^64.
+pub fn fee_to_f64(fee: u64) -> f64 {
+    fee as f64 / 2f64.powi(64)
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum FeeValidationError {
+    #[error("Unsupported fee {fee}, supported fees are {supported:?}")]
+    UnsupportedFee { fee: u64, supported: Vec<u64> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EkuboFeeValidator {
+    /// Supported fees in ascending order.
+    supported: Vec<u64>,
+}
+
+impl Default for EkuboFeeValidator {
+    fn default() -> Self {
+        Self::new(DEFAULT_FEES.to_vec())
+    }
+}
+
+impl EkuboFeeValidator {
+    pub fn new(mut supported: Vec<u64>) -> Self {
+        supported.sort_unstable();
+        supported.dedup();
+        Self { supported }
+    }
+
+    /// Reads the supported fees from `supportedFees()` of the contract at `registry`.
+    pub fn from_onchain<D: EngineDatabaseInterface + Clone + Debug>(
+        engine: &SimulationEngine<D>,
+        registry: Address,
+        block: &BlockHeader,
+    ) -> Result<Self, SimulationError>
+    where
+        <D as DatabaseRef>::Error: EngineDatabaseError,
+        <D as EngineDatabaseInterface>::Error: std::fmt::Debug,
+    {
+        let contract = TychoSimulationContract::new(registry, engine.clone())?;
+        let res = contract
+            .call(
+                "supportedFees()",
+                (),
+                block.number,
+                Some(block.timestamp),
+                None,
+                None,
+                U256::ZERO,
+            )?
+            .return_value;
+        let fees = Vec::<u64>::abi_decode(&res, true).map_err(|e| {
+            SimulationError::FatalError(format!("Failed to decode supported fees: {:?}", e))
+        })?;
+        Ok(Self::new(fees))
+    }
+
+    pub fn supported(&self) -> &[u64] {
+        &self.supported
+    }
+
+    pub fn validate(&self, fee: u64) -> Result<(), FeeValidationError> {
+        if self
+            .supported
+            .binary_search(&fee)
+            .is_err()
+        {
+            return Err(FeeValidationError::UnsupportedFee {
+                fee,
+                supported: self.supported.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case::zero(0)]
+    #[case::five_bps(9_223_372_036_854_775)]
+    #[case::one_percent(184_467_440_737_095_516)]
+    fn test_default_fees_are_valid(#[case] fee: u64) {
+        assert_eq!(EkuboFeeValidator::default().validate(fee), Ok(()));
+    }
+
+    #[rstest]
+    #[case::one(1)]
+    #[case::off_by_one(9_223_372_036_854_776)]
+    #[case::max(u64::MAX)]
+    fn test_unknown_fee_is_rejected(#[case] fee: u64) {
+        let res = EkuboFeeValidator::default().validate(fee);
+
+        assert_eq!(
+            res,
+            Err(FeeValidationError::UnsupportedFee { fee, supported: DEFAULT_FEES.to_vec() })
+        );
+    }
+
+    #[test]
+    fn test_custom_fees() {
+        let validator = EkuboFeeValidator::new(vec![30, 10, 30]);
+
+        assert_eq!(validator.supported(), &[10, 30]);
+        assert_eq!(validator.validate(10), Ok(()));
+        assert!(validator.validate(0).is_err());
+    }
+}
@@ -0,0 +1,191 @@
+//! Swap limits of Ekubo pools, computed from the liquidity of their tick ranges.
+use alloy_primitives::U256;
+use evm_ekubo_sdk::{
+    math::{
+        tick::{to_sqrt_ratio, MAX_SQRT_RATIO, MIN_SQRT_RATIO},
+        uint,
+    },
+    quoting::types::Tick,
+};
+
+use crate::{
+    evm::protocol::{
+        safe_math::{div_u256, safe_add_u256, Rounding},
+        utils::uniswap::solidity_math::mul_div,
+    },
+    protocol::errors::SimulationError,
+};
+
+/// 2^128, the unit of sqrt ratios.
+const Q128: U256 = U256::from_limbs([0, 0, 1, 0]);
+/// 2^64, the unit of fees.
+const Q64: U256 = U256::from_limbs([0, 1, 0, 0]);
+
+fn to_alloy(x: uint::U256) -> U256 {
+    U256::from_limbs(x.0)
+}
+
+/// Amount of token0 between two sqrt ratios at `liquidity`.
+fn amount0_delta(
+    a: U256,
+    b: U256,
+    liquidity: u128,
+    rounding: Rounding,
+) -> Result<U256, SimulationError> {
+    let (lower, upper) = if a < b { (a, b) } else { (b, a) };
+    let scaled_liquidity = U256::from(liquidity) << 128;
+    div_u256(mul_div(scaled_liquidity, upper - lower, upper, rounding)?, lower, rounding)
+}
+
+/// Amount of token1 between two sqrt ratios at `liquidity`.
+fn amount1_delta(
+    a: U256,
+    b: U256,
+    liquidity: u128,
+    rounding: Rounding,
+) -> Result<U256, SimulationError> {
+    let (lower, upper) = if a < b { (a, b) } else { (b, a) };
+    mul_div(U256::from(liquidity), upper - lower, Q128, rounding)
+}
+
+/// The largest amount a pool can swap in one direction, including the `fee`, and the amount it
+/// pays out for it.
+///
+/// Walks the tick ranges from `sqrt_ratio` towards `MIN_SQRT_RATIO` when selling token0, or
+/// towards `MAX_SQRT_RATIO` when selling token1, summing up the amounts of the ranges with
+/// liquidity. Amounts paid into the pool are rounded up, amounts paid out of it down.
+pub(super) fn swap_limits(
+    sqrt_ratio: uint::U256,
+    mut liquidity: u128,
+    ticks: &[Tick],
+    fee: u64,
+    sell_token0: bool,
+) -> Result<(uint::U256, uint::U256), SimulationError> {
+    let mut price = to_alloy(sqrt_ratio);
+
+    // the sqrt ratios of the ticks the swap crosses, closest first, and the change in liquidity
+    // when crossing them
+    let mut boundaries = Vec::new();
+    for tick in ticks {
+        let tick_ratio = to_sqrt_ratio(tick.index)
+            .map(to_alloy)
+            .ok_or_else(|| {
+                SimulationError::FatalError(format!("Tick {} is out of range", tick.index))
+            })?;
+        if sell_token0 && tick_ratio <= price {
+            boundaries.push((tick_ratio, -tick.liquidity_delta));
+        } else if !sell_token0 && tick_ratio > price {
+            boundaries.push((tick_ratio, tick.liquidity_delta));
+        }
+    }
+    if sell_token0 {
+        boundaries.reverse();
+    }
+    let bound = if sell_token0 { MIN_SQRT_RATIO } else { MAX_SQRT_RATIO };
+    boundaries.push((to_alloy(bound), 0));
+
+    let (mut amount_in, mut amount_out) = (U256::ZERO, U256::ZERO);
+    for (next, liquidity_change) in boundaries {
+        if liquidity > 0 {
+            let (range_in, range_out) = if sell_token0 {
+                (
+                    amount0_delta(price, next, liquidity, Rounding::Up)?,
+                    amount1_delta(price, next, liquidity, Rounding::Down)?,
+                )
+            } else {
+                (
+                    amount1_delta(price, next, liquidity, Rounding::Up)?,
+                    amount0_delta(price, next, liquidity, Rounding::Down)?,
+                )
+            };
+            amount_in = safe_add_u256(amount_in, range_in)?;
+            amount_out = safe_add_u256(amount_out, range_out)?;
+        }
+        price = next;
+        liquidity = liquidity
+            .checked_add_signed(liquidity_change)
+            .ok_or_else(|| {
+                SimulationError::FatalError(
+                    "Liquidity of the pool is inconsistent with its ticks".to_string(),
+                )
+            })?;
+    }
+
+    let amount_in = mul_div(amount_in, Q64, Q64 - U256::from(fee), Rounding::Up)?;
+    Ok((uint::U256(amount_in.into_limbs()), uint::U256(amount_out.into_limbs())))
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    const LIQUIDITY: u128 = 1_000_000;
+
+    fn ticks() -> Vec<Tick> {
+        vec![
+            Tick { index: -10, liquidity_delta: LIQUIDITY as i128 },
+            Tick { index: 10, liquidity_delta: -(LIQUIDITY as i128) },
+        ]
+    }
+
+    #[rstest]
+    #[case::sell_token0(true)]
+    #[case::sell_token1(false)]
+    fn test_limits_of_range(#[case] sell_token0: bool) {
+        let tick = if sell_token0 { -10 } else { 10 };
+        let boundary = to_alloy(to_sqrt_ratio(tick).unwrap());
+
+        let (amount_in, amount_out) =
+            swap_limits(uint::U256([0, 0, 1, 0]), LIQUIDITY, &ticks(), 0, sell_token0).unwrap();
+
+        // the amounts of the range between the current price and the tick liquidity ends at
+        let (expected_in, expected_out) = if sell_token0 {
+            (
+                amount0_delta(Q128, boundary, LIQUIDITY, Rounding::Up).unwrap(),
+                amount1_delta(Q128, boundary, LIQUIDITY, Rounding::Down).unwrap(),
+            )
+        } else {
+            (
+                amount1_delta(Q128, boundary, LIQUIDITY, Rounding::Up).unwrap(),
+                amount0_delta(Q128, boundary, LIQUIDITY, Rounding::Down).unwrap(),
+            )
+        };
+        assert_eq!((to_alloy(amount_in), to_alloy(amount_out)), (expected_in, expected_out));
+        // 10 ticks move the sqrt ratio by about 0.0005%, i.e. about 5 of either token
+        assert_eq!((expected_in, expected_out), (U256::from(6), U256::from(4)));
+    }
+
+    #[test]
+    fn test_limits_include_fee() {
+        let (without_fee, out) =
+            swap_limits(uint::U256([0, 0, 1, 0]), LIQUIDITY, &ticks(), 0, true).unwrap();
+        let (with_fee, out_with_fee) =
+            swap_limits(uint::U256([0, 0, 1, 0]), LIQUIDITY, &ticks(), 1 << 63, true).unwrap();
+
+        // a fee of half the input doubles it
+        assert_eq!(out, out_with_fee);
+        assert_eq!(to_alloy(with_fee), to_alloy(without_fee) * U256::from(2));
+    }
+
+    #[rstest]
+    #[case::sell_token0(true, true)]
+    #[case::sell_token1(false, false)]
+    fn test_one_sided_pool(#[case] sell_token0: bool, #[case] has_liquidity: bool) {
+        // the price is above all positions, so only selling token0 moves it into liquidity
+        let sqrt_ratio = to_sqrt_ratio(20).unwrap();
+
+        let (amount_in, amount_out) = swap_limits(sqrt_ratio, 0, &ticks(), 0, sell_token0).unwrap();
+
+        assert_eq!(!amount_in.is_zero(), has_liquidity);
+        assert_eq!(!amount_out.is_zero(), has_liquidity);
+    }
+
+    #[test]
+    fn test_inconsistent_liquidity() {
+        let res = swap_limits(uint::U256([0, 0, 1, 0]), 0, &ticks(), 0, false);
+
+        assert!(matches!(res, Err(SimulationError::FatalError(_))));
+    }
+}
@@ -1,4 +1,7 @@
-mod pool;
+pub mod fee;
+mod limits;
+#[doc(hidden)]
+pub mod pool;
 pub mod state;
 mod tick;
 mod tycho_decoder;
@@ -1,5 +1,8 @@
 use evm_ekubo_sdk::{
-    math::{tick::to_sqrt_ratio, uint::U256},
+    math::{
+        tick::{to_sqrt_ratio, MIN_SQRT_RATIO},
+        uint::U256,
+    },
     quoting::{
         self,
         base_pool::{BasePoolError, BasePoolResources, BasePoolState},
@@ -8,12 +11,15 @@ use evm_ekubo_sdk::{
     },
 };
 
-use super::{EkuboPool, EkuboPoolQuote};
+use super::{quote_exact, EkuboPool, EkuboPoolQuote, EkuboQuoteError};
 use crate::{
     evm::protocol::ekubo::tick::Ticks,
     protocol::errors::{InvalidSnapshotError, SimulationError, TransitionError},
 };
 
+/// The price of a pool that never had any liquidity.
+pub const UNINITIALIZED_SQRT_RATIO: U256 = MIN_SQRT_RATIO;
+
 #[derive(Debug, Clone, Eq)]
 pub struct BasePool {
     state: BasePoolState,
@@ -62,19 +68,51 @@ impl BasePool {
         })
     }
 
+    /// Approximate heap memory of the pool in bytes.
+    pub fn approx_heap_size(&self) -> usize {
+        // the quoting implementation keeps its own copy of the ticks
+        2 * self.ticks.heap_size()
+    }
+
     pub fn set_active_tick(&mut self, tick: i32) {
         self.active_tick = Some(tick);
     }
 
+    /// Resets the pool to its state right after deployment: no liquidity, no initialized ticks and
+    /// the price at [`UNINITIALIZED_SQRT_RATIO`]. Quotes yield no output until a new state is set.
+    pub fn reset(&mut self) -> Result<(), TransitionError<String>> {
+        self.state = BasePoolState {
+            sqrt_ratio: UNINITIALIZED_SQRT_RATIO,
+            liquidity: 0,
+            active_tick_index: None,
+        };
+        self.ticks = Ticks::new(vec![]);
+        self.active_tick = None;
+        self.reinstantiate()
+    }
+
     pub fn quote(&self, token_amount: TokenAmount) -> Result<EkuboPoolQuote, SimulationError> {
+        self.quote_with_limit(token_amount, None)
+    }
+
+    /// Like [`Self::quote`], but stops the swap once the sqrt ratio reaches `sqrt_ratio_limit`.
+    pub fn quote_with_limit(
+        &self,
+        token_amount: TokenAmount,
+        sqrt_ratio_limit: Option<U256>,
+    ) -> Result<EkuboPoolQuote, SimulationError> {
+        if !self.is_instantiated() {
+            return Ok(EkuboPoolQuote {
+                consumed_amount: 0,
+                calculated_amount: 0,
+                gas: Self::BASE_GAS_COST,
+                new_state: self.clone().into(),
+            });
+        }
+
         let quote = self
             .imp
-            .quote(QuoteParams {
-                token_amount,
-                sqrt_ratio_limit: None,
-                override_state: None,
-                meta: (),
-            })
+            .quote(QuoteParams { token_amount, sqrt_ratio_limit, override_state: None, meta: () })
             .map_err(|err| SimulationError::RecoverableError(format!("{err:?}")))?;
 
         let state_after = quote.state_after;
@@ -110,10 +148,23 @@ impl EkuboPool for BasePool {
         self.imp.get_key()
     }
 
+    /// Whether the pool was initialized, i.e. has a price or liquidity.
+    fn is_instantiated(&self) -> bool {
+        self.state.liquidity != 0 || self.state.sqrt_ratio != UNINITIALIZED_SQRT_RATIO
+    }
+
     fn sqrt_ratio(&self) -> U256 {
         self.state.sqrt_ratio
     }
 
+    fn liquidity(&self) -> u128 {
+        self.state.liquidity
+    }
+
+    fn ticks(&self) -> Vec<Tick> {
+        self.ticks.inner().clone()
+    }
+
     fn set_sqrt_ratio(&mut self, sqrt_ratio: U256) {
         self.state.sqrt_ratio = sqrt_ratio;
     }
@@ -147,7 +198,27 @@ impl EkuboPool for BasePool {
         Ok(())
     }
 
+    fn quote(&self, token_in: U256, amount: i128) -> Result<EkuboPoolQuote, EkuboQuoteError> {
+        quote_exact(self, token_in, amount, false, |pool, token_amount| {
+            pool.quote_with_limit(token_amount, None)
+        })
+    }
+
+    fn quote_exact_out(
+        &self,
+        token_out: U256,
+        amount: i128,
+    ) -> Result<EkuboPoolQuote, EkuboQuoteError> {
+        quote_exact(self, token_out, amount, true, |pool, token_amount| {
+            pool.quote_with_limit(token_amount, None)
+        })
+    }
+
     fn get_limit(&self, token_in: U256) -> Result<u128, SimulationError> {
+        if !self.is_instantiated() {
+            return Ok(0);
+        }
+
         let max_in_token_amount = TokenAmount { amount: i128::MAX, token: token_in };
 
         let sqrt_ratio = self.sqrt_ratio();
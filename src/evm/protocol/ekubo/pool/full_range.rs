@@ -10,7 +10,7 @@ use evm_ekubo_sdk::{
     },
 };
 
-use super::{EkuboPool, EkuboPoolQuote};
+use super::{quote_exact, EkuboPool, EkuboPoolQuote, EkuboQuoteError};
 use crate::protocol::errors::{InvalidSnapshotError, SimulationError, TransitionError};
 
 #[derive(Debug, Clone, Eq)]
@@ -27,6 +27,18 @@ fn impl_from_state(
     quoting::full_range_pool::FullRangePool::new(key, state)
 }
 
+/// The ticks of a position spanning the whole price range.
+pub(super) fn full_range_ticks(liquidity: u128) -> Vec<Tick> {
+    if liquidity == 0 {
+        return vec![];
+    }
+    let liquidity_delta = i128::try_from(liquidity).unwrap_or(i128::MAX);
+    vec![
+        Tick { index: MIN_TICK, liquidity_delta },
+        Tick { index: MAX_TICK, liquidity_delta: -liquidity_delta },
+    ]
+}
+
 impl PartialEq for FullRangePool {
     // The other properties are just helpers for keeping the underlying pool implementation
     // up-to-date
@@ -49,14 +61,18 @@ impl FullRangePool {
     }
 
     pub fn quote(&self, token_amount: TokenAmount) -> Result<EkuboPoolQuote, SimulationError> {
+        self.quote_with_limit(token_amount, None)
+    }
+
+    /// Like [`Self::quote`], but stops the swap once the sqrt ratio reaches `sqrt_ratio_limit`.
+    pub fn quote_with_limit(
+        &self,
+        token_amount: TokenAmount,
+        sqrt_ratio_limit: Option<U256>,
+    ) -> Result<EkuboPoolQuote, SimulationError> {
         let quote = self
             .imp
-            .quote(QuoteParams {
-                token_amount,
-                sqrt_ratio_limit: None,
-                override_state: None,
-                meta: (),
-            })
+            .quote(QuoteParams { token_amount, sqrt_ratio_limit, override_state: None, meta: () })
             .map_err(|err| SimulationError::RecoverableError(format!("{err:?}")))?;
 
         let state_after = quote.state_after;
@@ -91,6 +107,14 @@ impl EkuboPool for FullRangePool {
         self.state.sqrt_ratio
     }
 
+    fn liquidity(&self) -> u128 {
+        self.state.liquidity
+    }
+
+    fn ticks(&self) -> Vec<Tick> {
+        full_range_ticks(self.liquidity())
+    }
+
     fn set_sqrt_ratio(&mut self, sqrt_ratio: U256) {
         self.state.sqrt_ratio = sqrt_ratio;
     }
@@ -111,6 +135,22 @@ impl EkuboPool for FullRangePool {
         Ok(())
     }
 
+    fn quote(&self, token_in: U256, amount: i128) -> Result<EkuboPoolQuote, EkuboQuoteError> {
+        quote_exact(self, token_in, amount, false, |pool, token_amount| {
+            pool.quote_with_limit(token_amount, None)
+        })
+    }
+
+    fn quote_exact_out(
+        &self,
+        token_out: U256,
+        amount: i128,
+    ) -> Result<EkuboPoolQuote, EkuboQuoteError> {
+        quote_exact(self, token_out, amount, true, |pool, token_amount| {
+            pool.quote_with_limit(token_amount, None)
+        })
+    }
+
     fn get_limit(&self, token_in: U256) -> Result<u128, SimulationError> {
         let max_in_token_amount = TokenAmount { amount: i128::MAX, token: token_in };
 
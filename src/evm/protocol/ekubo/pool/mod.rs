@@ -4,17 +4,31 @@ pub mod oracle;
 
 use evm_ekubo_sdk::{
     math::uint::U256,
-    quoting::types::{NodeKey, Tick},
+    quoting::types::{NodeKey, Tick, TokenAmount},
 };
+use thiserror::Error;
 
-use super::state::EkuboState;
-use crate::protocol::errors::{SimulationError, TransitionError};
+use super::{fee::fee_to_f64, limits::swap_limits, state::EkuboState};
+use crate::{
+    evm::protocol::u256_num::u256_to_f64,
+    models::Token,
+    protocol::errors::{SimulationError, TransitionError},
+};
 
 #[enum_delegate::register]
 pub trait EkuboPool {
     fn key(&self) -> &NodeKey;
 
+    /// Whether the pool holds a state to quote. Pools that aren't instantiated quote nothing.
+    fn is_instantiated(&self) -> bool {
+        true
+    }
+
     fn sqrt_ratio(&self) -> U256;
+    fn liquidity(&self) -> u128;
+
+    /// The initialized ticks of the pool, ordered by index.
+    fn ticks(&self) -> Vec<Tick>;
 
     fn set_sqrt_ratio(&mut self, sqrt_ratio: U256);
     fn set_liquidity(&mut self, liquidity: u128);
@@ -22,12 +36,191 @@ pub trait EkuboPool {
 
     fn get_limit(&self, token_in: U256) -> Result<u128, SimulationError>;
 
+    /// The largest amount of `token_in` the pool can swap before the liquidity in that direction
+    /// runs out, and the amount of the other token it pays out for it.
+    ///
+    /// The amounts are computed from the liquidity of the tick ranges the swap crosses instead of
+    /// by quoting, so they may differ from a quote of the same input by rounding. A pool without
+    /// liquidity in the direction of the swap, e.g. one whose price is above all positions when
+    /// selling token1, has limits of zero, like a pool that isn't instantiated.
+    ///
+    /// # Errors
+    ///
+    /// * `SimulationError::InvalidInput` - if `token_in` isn't a token of the pool.
+    fn swap_limits(&self, token_in: U256) -> Result<(U256, U256), SimulationError> {
+        let key = self.key();
+        if token_in != key.token0 && token_in != key.token1 {
+            return Err(SimulationError::InvalidInput(
+                format!("Token {token_in} is not a token of the pool"),
+                None,
+            ));
+        }
+        if !self.is_instantiated() {
+            return Ok((U256::zero(), U256::zero()));
+        }
+        swap_limits(
+            self.sqrt_ratio(),
+            self.liquidity(),
+            &self.ticks(),
+            self.fee_raw(),
+            token_in == key.token0,
+        )
+    }
+
+    /// Quotes swapping exactly `amount` of `token_in` for the other token of the pool.
+    ///
+    /// A zero `amount` is quoted as zero, for no gas, without quoting the pool.
+    ///
+    /// # Errors
+    ///
+    /// * `EkuboQuoteError::UnknownToken` - if `token_in` isn't a token of the pool.
+    /// * `EkuboQuoteError::NegativeAmount` - if `amount` is negative.
+    /// * `EkuboQuoteError::InsufficientLiquidity` - if the pool can only swap part of `amount`.
+    fn quote(&self, token_in: U256, amount: i128) -> Result<EkuboPoolQuote, EkuboQuoteError>;
+
+    /// Quotes swapping each of `amounts` of `token_in`, e.g. to sample the price curve of the pool.
+    ///
+    /// The quotes are returned in the order of `amounts`, which don't need to be sorted, and are
+    /// identical to quoting each amount with [`Self::quote`]. Each amount is quoted from the
+    /// current state of the pool, as continuing a swap from the state a smaller amount left
+    /// behind rounds differently than swapping the larger amount at once. The amounts are quoted
+    /// in ascending order, so repeated amounts are only quoted once and quoting stops at the first
+    /// amount the pool can't swap.
+    ///
+    /// # Errors
+    ///
+    /// The error of [`Self::quote`] for the smallest amount that can't be quoted.
+    fn quote_many(
+        &self,
+        token_in: U256,
+        amounts: &[i128],
+    ) -> Result<Vec<EkuboPoolQuote>, EkuboQuoteError> {
+        let mut order: Vec<usize> = (0..amounts.len()).collect();
+        order.sort_by_key(|&i| amounts[i]);
+
+        let mut quotes: Vec<Option<EkuboPoolQuote>> = vec![None; amounts.len()];
+        let mut previous: Option<usize> = None;
+        for i in order {
+            let quote = match previous {
+                Some(j) if amounts[j] == amounts[i] => quotes[j].clone(),
+                _ => Some(self.quote(token_in, amounts[i])?),
+            };
+            quotes[i] = quote;
+            previous = Some(i);
+        }
+        Ok(quotes.into_iter().flatten().collect())
+    }
+
+    /// Quotes buying exactly `amount` of `token_out` with the other token of the pool.
+    ///
+    /// The `consumed_amount` of the quote is `-amount` and its `calculated_amount` the amount to
+    /// pay, rounded up like the pool does, so paying it always buys at least `amount`. A zero
+    /// `amount` is quoted as zero, for no gas, without quoting the pool.
+    ///
+    /// # Errors
+    ///
+    /// * `EkuboQuoteError::UnknownToken` - if `token_out` isn't a token of the pool.
+    /// * `EkuboQuoteError::NegativeAmount` - if `amount` is negative.
+    /// * `EkuboQuoteError::InsufficientLiquidity` - if the pool can only provide part of `amount`.
+    fn quote_exact_out(
+        &self,
+        token_out: U256,
+        amount: i128,
+    ) -> Result<EkuboPoolQuote, EkuboQuoteError>;
+
+    /// The fee of the pool as a fraction of 2^64, as it is configured in its key.
+    fn fee_raw(&self) -> u64 {
+        self.key().config.fee
+    }
+
+    /// The fee of the pool as a fraction of the swapped amount, e.g. 0.003 for 0.3%.
+    fn fee(&self) -> f64 {
+        fee_to_f64(self.fee_raw())
+    }
+
+    /// The price of `base` in units of `quote`, adjusted for the decimals of both tokens.
+    ///
+    /// The price of token0 in token1 is the square of `sqrt_ratio`, a Q128.128 fixed point
+    /// number; if `base` isn't the token0 of the pool, its inverse is returned.
+    fn spot_price(&self, base: &Token, quote: &Token) -> f64 {
+        let sqrt_ratio = self.sqrt_ratio();
+        if U256::from_big_endian(&base.address) == self.key().token0 {
+            sqrt_price_q128_to_f64(sqrt_ratio, (base.decimals, quote.decimals))
+        } else {
+            1.0 / sqrt_price_q128_to_f64(sqrt_ratio, (quote.decimals, base.decimals))
+        }
+    }
+
+    /// Rebuilds the quoting implementation from the state set through the setters.
+    ///
+    /// The setters only record the new state; quotes keep using the previous one until this is
+    /// called, e.g. at the end of a delta transition. The state itself is left as it is.
     fn reinstantiate(&mut self) -> Result<(), TransitionError<String>>;
 }
 
+#[derive(Debug, Error)]
+pub enum EkuboQuoteError {
+    #[error("Token {0} is not a token of the pool")]
+    UnknownToken(U256),
+    #[error("Amount must not be negative, got {0}")]
+    NegativeAmount(i128),
+    #[error("Pool does not have enough liquidity to swap {amount}, it can only swap {consumed}")]
+    InsufficientLiquidity { amount: i128, consumed: i128 },
+    #[error(transparent)]
+    Simulation(#[from] SimulationError),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct EkuboPoolQuote {
     pub consumed_amount: i128,
     pub calculated_amount: i128,
     pub gas: u64,
     pub new_state: EkuboState,
 }
+
+/// The price of token0 in token1 of a pool at `x`, a Q128.128 square root price.
+pub(super) fn sqrt_price_q128_to_f64(
+    x: U256,
+    (token0_decimals, token1_decimals): (usize, usize),
+) -> f64 {
+    let token_correction = 10f64.powi(token0_decimals as i32 - token1_decimals as i32);
+
+    let price = u256_to_f64(alloy_primitives::U256::from_limbs(x.0)) / 2.0f64.powi(128);
+    price.powi(2) * token_correction
+}
+
+/// Validates a quote of exactly `amount` of `token`, bought if `exact_out` and sold otherwise, and
+/// runs it with `quote`.
+fn quote_exact<P: EkuboPool + Clone + Into<EkuboState>>(
+    pool: &P,
+    token: U256,
+    amount: i128,
+    exact_out: bool,
+    quote: impl FnOnce(&P, TokenAmount) -> Result<EkuboPoolQuote, SimulationError>,
+) -> Result<EkuboPoolQuote, EkuboQuoteError> {
+    let key = pool.key();
+    if token != key.token0 && token != key.token1 {
+        return Err(EkuboQuoteError::UnknownToken(token));
+    }
+    if amount < 0 {
+        return Err(EkuboQuoteError::NegativeAmount(amount));
+    }
+    if amount == 0 {
+        return Ok(EkuboPoolQuote {
+            consumed_amount: 0,
+            calculated_amount: 0,
+            gas: 0,
+            new_state: pool.clone().into(),
+        });
+    }
+    // the sdk takes bought amounts as negative amounts of the bought token
+    let specified = if exact_out { -amount } else { amount };
+    let res = quote(pool, TokenAmount { token, amount: specified })?;
+    if res.consumed_amount != specified {
+        return Err(EkuboQuoteError::InsufficientLiquidity {
+            amount,
+            consumed: res.consumed_amount.abs(),
+        });
+    }
+    Ok(res)
+}
@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use evm_ekubo_sdk::{
     math::{
         tick::{MAX_TICK, MIN_TICK},
@@ -10,13 +12,21 @@ use evm_ekubo_sdk::{
     },
 };
 
-use super::{full_range::FullRangePool, EkuboPool, EkuboPoolQuote};
-use crate::protocol::errors::{InvalidSnapshotError, SimulationError, TransitionError};
+use super::{
+    full_range::{full_range_ticks, FullRangePool},
+    quote_exact, EkuboPool, EkuboPoolQuote, EkuboQuoteError,
+};
+use crate::{
+    evm::clock::{ClockSource, SystemClock},
+    protocol::errors::{InvalidSnapshotError, SimulationError, TransitionError},
+};
 
-#[derive(Debug, Eq, Clone)]
+#[derive(Debug, Clone)]
 pub struct OraclePool {
     imp: quoting::oracle_pool::OraclePool,
     state: OraclePoolState,
+    /// The time swaps are quoted at, which determines whether they write a new snapshot.
+    clock: Arc<dyn ClockSource>,
 }
 
 impl PartialEq for OraclePool {
@@ -27,6 +37,8 @@ impl PartialEq for OraclePool {
     }
 }
 
+impl Eq for OraclePool {}
+
 fn impl_from_state(
     key: &NodeKey,
     state: &OraclePoolState,
@@ -49,6 +61,7 @@ impl OraclePool {
                 InvalidSnapshotError::ValueError(format!("creating oracle pool: {err:?}"))
             })?,
             state,
+            clock: Arc::new(SystemClock),
         })
     }
 
@@ -56,18 +69,34 @@ impl OraclePool {
         self.state.last_snapshot_time = last_snapshot_time;
     }
 
-    // TODO Add parameter when timestamps are supported
-    pub fn quote(
+    /// The time of the latest oracle snapshot, as a unix timestamp in seconds.
+    pub fn last_snapshot_time(&self) -> u64 {
+        self.state.last_snapshot_time
+    }
+
+    /// Quotes swaps at the time of `clock` instead of the host's wall clock.
+    pub fn set_clock(&mut self, clock: Arc<dyn ClockSource>) {
+        self.clock = clock;
+    }
+
+    /// Quotes a swap at the current time of the pool's clock.
+    pub fn quote(&self, token_amount: TokenAmount) -> Result<EkuboPoolQuote, SimulationError> {
+        self.quote_with_limit(token_amount, None)
+    }
+
+    /// Like [`Self::quote`], but stops the swap once the sqrt ratio reaches `sqrt_ratio_limit`.
+    pub fn quote_with_limit(
         &self,
-        token_amount: TokenAmount, /* block_timestamp: u64 */
+        token_amount: TokenAmount,
+        sqrt_ratio_limit: Option<U256>,
     ) -> Result<EkuboPoolQuote, SimulationError> {
         let quote = self
             .imp
             .quote(QuoteParams {
                 token_amount,
-                sqrt_ratio_limit: None,
+                sqrt_ratio_limit,
                 override_state: None,
-                meta: 0, // TODO Set to timestamp
+                meta: self.clock.now(),
             })
             .map_err(|err| SimulationError::RecoverableError(format!("{err:?}")))?;
 
@@ -78,6 +107,7 @@ impl OraclePool {
                 SimulationError::RecoverableError(format!("recreating oracle pool: {err:?}"))
             })?,
             state: state_after,
+            clock: self.clock.clone(),
         }
         .into();
 
@@ -102,6 +132,16 @@ impl EkuboPool for OraclePool {
             .sqrt_ratio
     }
 
+    fn liquidity(&self) -> u128 {
+        self.state
+            .full_range_pool_state
+            .liquidity
+    }
+
+    fn ticks(&self) -> Vec<Tick> {
+        full_range_ticks(self.liquidity())
+    }
+
     fn set_sqrt_ratio(&mut self, sqrt_ratio: U256) {
         self.state
             .full_range_pool_state
@@ -149,6 +189,22 @@ impl EkuboPool for OraclePool {
         Ok(())
     }
 
+    fn quote(&self, token_in: U256, amount: i128) -> Result<EkuboPoolQuote, EkuboQuoteError> {
+        quote_exact(self, token_in, amount, false, |pool, token_amount| {
+            pool.quote_with_limit(token_amount, None)
+        })
+    }
+
+    fn quote_exact_out(
+        &self,
+        token_out: U256,
+        amount: i128,
+    ) -> Result<EkuboPoolQuote, EkuboQuoteError> {
+        quote_exact(self, token_out, amount, true, |pool, token_amount| {
+            pool.quote_with_limit(token_amount, None)
+        })
+    }
+
     fn get_limit(&self, token_in: U256) -> Result<u128, SimulationError> {
         let max_in_token_amount = TokenAmount { amount: i128::MAX, token: token_in };
 
@@ -158,7 +214,7 @@ impl EkuboPool for OraclePool {
                 token_amount: max_in_token_amount,
                 sqrt_ratio_limit: None,
                 override_state: None,
-                meta: 0,
+                meta: self.clock.now(),
             })
             .map_err(|err| SimulationError::RecoverableError(format!("quoting error: {err:?}")))?;
 
@@ -1,23 +1,34 @@
-use std::{any::Any, collections::HashMap, fmt::Debug};
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt::{self, Debug, Display, Write},
+    sync::Arc,
+};
 
 use alloy_primitives::Address;
 use evm_ekubo_sdk::{
-    math::uint::U256,
+    math::{
+        tick::{MAX_SQRT_RATIO, MAX_TICK, MIN_SQRT_RATIO, MIN_TICK},
+        uint::U256,
+    },
     quoting::types::{NodeKey, Tick, TokenAmount},
 };
 use num_bigint::BigUint;
 use tycho_common::{dto::ProtocolStateDelta, Bytes};
 
 use super::{
-    pool::{base::BasePool, full_range::FullRangePool, oracle::OraclePool, EkuboPool},
+    pool::{
+        base::BasePool, full_range::FullRangePool, oracle::OraclePool, sqrt_price_q128_to_f64,
+        EkuboPool, EkuboPoolQuote,
+    },
     tick::ticks_from_attributes,
 };
 use crate::{
-    evm::protocol::u256_num::u256_to_f64,
+    evm::clock::ClockSource,
     models::{Balances, Token},
     protocol::{
-        errors::{SimulationError, TransitionError},
-        models::GetAmountOutResult,
+        errors::{LimitedSwapError, PriceLimitError, SimulationError, TransitionError},
+        models::{GetAmountOutResult, LimitedSwapResult},
         state::ProtocolSim,
     },
 };
@@ -30,30 +41,239 @@ pub enum EkuboState {
     Oracle(OraclePool),
 }
 
-fn sqrt_price_q128_to_f64(x: U256, (token0_decimals, token1_decimals): (usize, usize)) -> f64 {
-    let token_correction = 10f64.powi(token0_decimals as i32 - token1_decimals as i32);
+/// Width of the liquidity bars in the tick ladder, in characters.
+const LADDER_BAR_WIDTH: usize = 40;
+
+impl EkuboState {
+    /// The tick corresponding to the current `sqrt_ratio`.
+    ///
+    /// Computed with floating point arithmetic, so it may be off by one close to tick boundaries.
+    /// Only meant for display purposes.
+    pub fn approximate_tick(&self) -> i32 {
+        let price = sqrt_price_q128_to_f64(self.sqrt_ratio(), (0, 0));
+        let tick = (price.ln() / 1.000001f64.ln()).floor();
+        (tick as i32).clamp(MIN_TICK, MAX_TICK)
+    }
+
+    /// A one line summary of the current price and liquidity of the pool.
+    ///
+    /// Prices are given in raw token units, i.e. without adjusting for decimals.
+    pub fn current_price_summary(&self) -> String {
+        let price = sqrt_price_q128_to_f64(self.sqrt_ratio(), (0, 0));
+        format!(
+            "sqrt_ratio: {}, tick: {}, price token1/token0: {}, price token0/token1: {}, liquidity: {}",
+            self.sqrt_ratio(),
+            self.approximate_tick(),
+            price,
+            1.0 / price,
+            self.liquidity()
+        )
+    }
+
+    /// Renders the net liquidity of the `width` closest initialized ticks on each side of the
+    /// current tick as an ASCII chart.
+    ///
+    /// Ticks are listed from highest to lowest index, with a marker showing the current tick and
+    /// the liquidity in range. Bars are scaled relative to the largest displayed liquidity delta,
+    /// `+` for liquidity added and `-` for liquidity removed when crossing the tick upwards.
+    ///
+    /// ```
+    /// use evm_ekubo_sdk::{
+    ///     math::uint::U256,
+    ///     quoting::{
+    ///         base_pool::BasePoolState,
+    ///         types::{Config, NodeKey, Tick},
+    ///     },
+    /// };
+    /// use tycho_simulation::evm::protocol::ekubo::{pool::base::BasePool, state::EkuboState};
+    ///
+    /// let key = NodeKey {
+    ///     token0: U256::from(1),
+    ///     token1: U256::from(2),
+    ///     config: Config { fee: 0, tick_spacing: 10, extension: U256::zero() },
+    /// };
+    /// let state = EkuboState::Base(
+    ///     BasePool::new(
+    ///         key,
+    ///         BasePoolState {
+    ///             sqrt_ratio: U256([0, 0, 1, 0]),
+    ///             liquidity: 200,
+    ///             active_tick_index: Some(1),
+    ///         },
+    ///         vec![
+    ///             Tick { index: -20, liquidity_delta: 100 },
+    ///             Tick { index: -10, liquidity_delta: 100 },
+    ///             Tick { index: 10, liquidity_delta: -200 },
+    ///         ]
+    ///         .into(),
+    ///         0,
+    ///     )
+    ///     .unwrap(),
+    /// );
+    ///
+    /// assert_eq!(
+    ///     state.debug_tick_ladder(10),
+    ///     concat!(
+    ///         "         10 | ---------------------------------------- -200\n",
+    ///         "          0 <- current tick, liquidity 200\n",
+    ///         "        -10 | ++++++++++++++++++++                     +100\n",
+    ///         "        -20 | ++++++++++++++++++++                     +100\n",
+    ///     )
+    /// );
+    /// ```
+    pub fn debug_tick_ladder(&self, width: u32) -> String {
+        let ticks = self.ticks();
+        let current_tick = self.approximate_tick();
+
+        let split = ticks.partition_point(|t| t.index <= current_tick);
+        let shown = &ticks[split.saturating_sub(width as usize)..
+            split
+                .saturating_add(width as usize)
+                .min(ticks.len())];
+        let split = split.min(width as usize);
+        let max_delta = shown
+            .iter()
+            .map(|t| t.liquidity_delta.unsigned_abs())
+            .max()
+            .unwrap_or_default();
+
+        let mut ladder = String::new();
+        for (i, tick) in shown.iter().enumerate().rev() {
+            if i + 1 == split {
+                Self::write_current_tick(&mut ladder, current_tick, self.liquidity());
+            }
+            let len = (tick.liquidity_delta.unsigned_abs() as f64 / max_delta as f64 *
+                LADDER_BAR_WIDTH as f64)
+                .ceil() as usize;
+            let bar = if tick.liquidity_delta < 0 { "-" } else { "+" }.repeat(len);
+            let _ = writeln!(
+                ladder,
+                "{:>11} | {bar:<LADDER_BAR_WIDTH$} {:+}",
+                tick.index, tick.liquidity_delta
+            );
+        }
+        if split == 0 {
+            Self::write_current_tick(&mut ladder, current_tick, self.liquidity());
+        }
+        ladder
+    }
+
+    fn write_current_tick(ladder: &mut String, tick: i32, liquidity: u128) {
+        let _ = writeln!(ladder, "{tick:>11} <- current tick, liquidity {liquidity}");
+    }
+
+    /// Checks a sqrt ratio limit the way the core contract does. It reverts with
+    /// `InvalidSqrtRatioLimit` for limits outside the valid range and with
+    /// `SqrtRatioLimitWrongDirection` for limits the swap moves the price away from.
+    pub fn check_sqrt_ratio_limit(
+        &self,
+        token_amount: &TokenAmount,
+        sqrt_ratio_limit: U256,
+    ) -> Result<(), PriceLimitError> {
+        let to_alloy = |x: U256| alloy_primitives::U256::from_limbs(x.0);
+        if !(MIN_SQRT_RATIO..=MAX_SQRT_RATIO).contains(&sqrt_ratio_limit) {
+            return Err(PriceLimitError::OutOfRange(to_alloy(sqrt_ratio_limit)));
+        }
+        let is_token1 = token_amount.token == self.key().token1;
+        let increasing = (token_amount.amount < 0) != is_token1;
+        let sqrt_ratio = self.sqrt_ratio();
+        if (increasing && sqrt_ratio_limit < sqrt_ratio) ||
+            (!increasing && sqrt_ratio_limit > sqrt_ratio)
+        {
+            return Err(PriceLimitError::WrongDirection {
+                limit: to_alloy(sqrt_ratio_limit),
+                current: to_alloy(sqrt_ratio),
+            });
+        }
+        Ok(())
+    }
+
+    /// Quotes selling `amount_in`, stopping early if the price reaches `sqrt_ratio_limit`.
+    pub fn get_amount_out_with_price_limit(
+        &self,
+        amount_in: BigUint,
+        token_in: &Token,
+        token_out: &Token,
+        sqrt_ratio_limit: U256,
+    ) -> Result<LimitedSwapResult, LimitedSwapError> {
+        let token_amount = TokenAmount {
+            token: U256::from_big_endian(&token_in.address),
+            amount: amount_in.try_into().map_err(|_| {
+                SimulationError::InvalidInput("amount in must fit into a i128".to_string(), None)
+            })?,
+        };
+        self.limited_quote(token_amount, sqrt_ratio_limit, token_in, token_out)
+    }
+
+    /// Quotes buying `amount_out`, stopping early if the price reaches `sqrt_ratio_limit`.
+    pub fn get_amount_in_with_price_limit(
+        &self,
+        amount_out: BigUint,
+        token_in: &Token,
+        token_out: &Token,
+        sqrt_ratio_limit: U256,
+    ) -> Result<LimitedSwapResult, LimitedSwapError> {
+        let amount: i128 = amount_out.try_into().map_err(|_| {
+            SimulationError::InvalidInput("amount out must fit into a i128".to_string(), None)
+        })?;
+        let token_amount =
+            TokenAmount { token: U256::from_big_endian(&token_out.address), amount: -amount };
+        self.limited_quote(token_amount, sqrt_ratio_limit, token_in, token_out)
+    }
+
+    fn limited_quote(
+        &self,
+        token_amount: TokenAmount,
+        sqrt_ratio_limit: U256,
+        token_in: &Token,
+        token_out: &Token,
+    ) -> Result<LimitedSwapResult, LimitedSwapError> {
+        self.check_sqrt_ratio_limit(&token_amount, sqrt_ratio_limit)?;
+        let quote = self.pool_quote(token_amount, Some(sqrt_ratio_limit))?;
+
+        let consumed = alloy_primitives::U256::from(quote.consumed_amount.unsigned_abs());
+        let calculated = alloy_primitives::U256::from(quote.calculated_amount.unsigned_abs());
+        let (amount_in, amount_out) =
+            if token_amount.amount >= 0 { (consumed, calculated) } else { (calculated, consumed) };
+
+        Ok(LimitedSwapResult {
+            amount_in: crate::types::TokenAmount::of(amount_in, token_in),
+            amount_out: crate::types::TokenAmount::of(amount_out, token_out),
+            gas: quote.gas.into(),
+            new_state: Box::new(quote.new_state),
+            limit_reached: quote.consumed_amount != token_amount.amount,
+        })
+    }
+
+    fn pool_quote(
+        &self,
+        token_amount: TokenAmount,
+        sqrt_ratio_limit: Option<U256>,
+    ) -> Result<EkuboPoolQuote, SimulationError> {
+        match self {
+            Self::Base(p) => p.quote_with_limit(token_amount, sqrt_ratio_limit),
+            Self::FullRange(p) => p.quote_with_limit(token_amount, sqrt_ratio_limit),
+            Self::Oracle(p) => p.quote_with_limit(token_amount, sqrt_ratio_limit),
+        }
+    }
+}
 
-    let price = u256_to_f64(alloy_primitives::U256::from_limbs(x.0)) / 2.0f64.powi(128);
-    price.powi(2) * token_correction
+impl Display for EkuboState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.current_price_summary())?;
+        write!(f, "{}", self.debug_tick_ladder(10))
+    }
 }
 
 impl ProtocolSim for EkuboState {
     fn fee(&self) -> f64 {
-        self.key().config.fee as f64 / (2f64.powi(64))
+        EkuboPool::fee(self)
     }
 
     fn spot_price(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
-        let sqrt_ratio = self.sqrt_ratio();
-        let (base_decimals, quote_decimals) = (base.decimals, quote.decimals);
-
-        Ok(if base < quote {
-            sqrt_price_q128_to_f64(sqrt_ratio, (base_decimals, quote_decimals))
-        } else {
-            1.0f64 / sqrt_price_q128_to_f64(sqrt_ratio, (quote_decimals, base_decimals))
-        })
+        Ok(EkuboPool::spot_price(self, base, quote))
     }
 
-    // TODO Need a timestamp here for the Oracle pool (and TWAMM in the future)
     fn get_amount_out(
         &self,
         amount_in: BigUint,
@@ -67,19 +287,15 @@ impl ProtocolSim for EkuboState {
             })?,
         };
 
-        let quote = match self {
-            Self::Base(p) => p.quote(token_amount),
-            Self::FullRange(p) => p.quote(token_amount),
-            Self::Oracle(p) => p.quote(token_amount),
-        }?;
+        let quote = self.pool_quote(token_amount, None)?;
 
-        let res = GetAmountOutResult {
-            amount: BigUint::try_from(quote.calculated_amount).map_err(|_| {
+        let res = GetAmountOutResult::new(
+            BigUint::try_from(quote.calculated_amount).map_err(|_| {
                 SimulationError::FatalError("output amount must be non-negative".to_string())
             })?,
-            gas: quote.gas.into(),
-            new_state: Box::new(quote.new_state),
-        };
+            quote.gas.into(),
+            Box::new(quote.new_state),
+        );
 
         if quote.consumed_amount != token_amount.amount {
             return Err(SimulationError::InvalidInput(
@@ -141,6 +357,19 @@ impl ProtocolSim for EkuboState {
         self.reinstantiate()
     }
 
+    fn approx_heap_size(&self) -> usize {
+        match self {
+            Self::Base(pool) => pool.approx_heap_size(),
+            Self::FullRange(_) | Self::Oracle(_) => 0,
+        }
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn ClockSource>) {
+        if let Self::Oracle(pool) = self {
+            pool.set_clock(clock);
+        }
+    }
+
     fn clone_box(&self) -> Box<dyn ProtocolSim> {
         Box::new(self.clone())
     }
@@ -160,16 +389,29 @@ impl ProtocolSim for EkuboState {
             .is_some_and(|other_state| self == other_state)
     }
 
+    /// The input that takes the price to the last initialized tick, as computed by the pool's
+    /// quoter.
+    fn max_input(
+        &self,
+        token_in: &Token,
+        _token_out: &Token,
+    ) -> Result<alloy_primitives::U256, SimulationError> {
+        let limit = self.get_limit(U256::from_big_endian(&token_in.address))?;
+        if limit == 0 {
+            return Err(SimulationError::RecoverableError("No liquidity".to_string()));
+        }
+        Ok(alloy_primitives::U256::from(limit))
+    }
+
     fn get_limits(
         &self,
         sell_token: Address,
         _buy_token: Address,
     ) -> Result<(BigUint, BigUint), SimulationError> {
-        // TODO Update once exact out is supported
+        let (max_in, max_out) = self.swap_limits(U256::from_big_endian(sell_token.as_slice()))?;
         Ok((
-            self.get_limit(U256::from_big_endian(sell_token.as_slice()))?
-                .into(),
-            BigUint::ZERO,
+            BigUint::from_bytes_be(&max_in.to_big_endian()),
+            BigUint::from_bytes_be(&max_out.to_big_endian()),
         ))
     }
 }
@@ -177,13 +419,29 @@ impl ProtocolSim for EkuboState {
 #[cfg(test)]
 mod tests {
     use evm_ekubo_sdk::{
-        math::tick::{MIN_SQRT_RATIO, MIN_TICK},
-        quoting::base_pool::BasePoolState,
+        math::tick::to_sqrt_ratio,
+        quoting::{
+            self,
+            base_pool::BasePoolState,
+            full_range_pool::FullRangePoolState,
+            oracle_pool::OraclePoolState,
+            types::{Config, Pool, QuoteParams},
+        },
     };
     use num_traits::Zero;
+    use rstest::rstest;
 
     use super::*;
-    use crate::evm::protocol::ekubo::test_pool::*;
+    use crate::{
+        evm::{
+            clock::{BlockAnchoredClock, FixedClock},
+            protocol::{
+                ekubo::{pool::EkuboQuoteError, test_pool::*},
+                u256_num::u256_to_f64,
+            },
+        },
+        protocol::conformance::{run_conformance_suite, ConformanceSpec},
+    };
 
     #[test]
     fn test_delta_transition() {
@@ -233,22 +491,637 @@ mod tests {
         assert_eq!(tycho_out, reference_out);
     }
 
+    #[test]
+    fn test_reinstantiate_keeps_state() {
+        let mut reinstantiated = state();
+
+        reinstantiated.reinstantiate().unwrap();
+
+        assert_eq!(reinstantiated, state());
+    }
+
+    #[rstest]
+    #[case::token0(true)]
+    #[case::token1(false)]
+    fn test_reset_pool_quotes_nothing(#[case] sell_token0: bool) {
+        let EkuboState::Base(mut pool) = state() else {
+            panic!();
+        };
+        let (token_in, token_out) =
+            if sell_token0 { (token0(), token1()) } else { (token1(), token0()) };
+
+        pool.reset().unwrap();
+
+        assert!(!pool.is_instantiated());
+        assert_eq!(pool.liquidity(), 0);
+        assert_eq!(
+            pool.swap_limits(U256::from_big_endian(&token_in.address))
+                .unwrap(),
+            (U256::zero(), U256::zero())
+        );
+        let quote = pool
+            .quote_with_limit(
+                TokenAmount { token: U256::from_big_endian(&token_in.address), amount: 100 },
+                None,
+            )
+            .unwrap();
+        assert_eq!((quote.consumed_amount, quote.calculated_amount), (0, 0));
+        let res =
+            EkuboState::Base(pool).get_amount_out(BigUint::from(100u8), &token_in, &token_out);
+        let Err(SimulationError::InvalidInput(_, Some(partial))) = res else {
+            panic!("Expected a partial quote, got {res:?}");
+        };
+        assert!(partial.amount.is_zero());
+    }
+
+    const ORACLE_EXTENSION: U256 = U256([3, 0, 0, 0]);
+
+    const BLOCK_TIMESTAMP: u64 = 1_700_000_000;
+    const SKEW: u64 = 300;
+
+    /// A wall clock running 5 minutes ahead of the chain.
+    #[derive(Debug)]
+    struct SkewedClock;
+
+    impl ClockSource for SkewedClock {
+        fn now(&self) -> u64 {
+            BLOCK_TIMESTAMP + SKEW
+        }
+    }
+
+    fn oracle_pool(last_snapshot_time: u64, clock: Arc<dyn ClockSource>) -> EkuboState {
+        let key = NodeKey {
+            token0: U256::zero(),
+            token1: POOL_KEY.token1,
+            config: Config { fee: 0, tick_spacing: 0, extension: ORACLE_EXTENSION },
+        };
+        let state = OraclePoolState {
+            full_range_pool_state: FullRangePoolState {
+                sqrt_ratio: SQRT_RATIO_BETWEEN,
+                liquidity: LIQUIDITY_BETWEEN,
+            },
+            last_snapshot_time,
+        };
+        let mut pool = OraclePool::new(&key, state).unwrap();
+        pool.set_clock(clock);
+        EkuboState::Oracle(pool)
+    }
+
+    fn oracle_state() -> EkuboState {
+        oracle_pool(0, Arc::new(FixedClock(BLOCK_TIMESTAMP)))
+    }
+
+    /// The consumed and calculated amounts of the sdk's quoter for the pools of [`state`] and
+    /// [`oracle_state`].
+    fn sdk_quote(state: &EkuboState, token_in: U256, amount: i128) -> (i128, i128) {
+        let token_amount = TokenAmount { token: token_in, amount };
+        match state {
+            EkuboState::Oracle(_) => {
+                let quote = quoting::oracle_pool::OraclePool::new(
+                    POOL_KEY.token1,
+                    ORACLE_EXTENSION,
+                    SQRT_RATIO_BETWEEN,
+                    LIQUIDITY_BETWEEN,
+                    0,
+                )
+                .unwrap()
+                .quote(QuoteParams {
+                    token_amount,
+                    sqrt_ratio_limit: None,
+                    override_state: None,
+                    meta: BLOCK_TIMESTAMP,
+                })
+                .unwrap();
+                (quote.consumed_amount, quote.calculated_amount)
+            }
+            _ => {
+                let pool_state = BasePoolState {
+                    sqrt_ratio: SQRT_RATIO_BETWEEN,
+                    liquidity: LIQUIDITY_BETWEEN,
+                    active_tick_index: Some(0),
+                };
+                let quote = quoting::base_pool::BasePool::new(
+                    POOL_KEY,
+                    pool_state,
+                    vec![LOWER_TICK, UPPER_TICK],
+                )
+                .unwrap()
+                .quote(QuoteParams {
+                    token_amount,
+                    sqrt_ratio_limit: None,
+                    override_state: None,
+                    meta: (),
+                })
+                .unwrap();
+                (quote.consumed_amount, quote.calculated_amount)
+            }
+        }
+    }
+
+    #[rstest]
+    #[case::base_token0(state(), true)]
+    #[case::base_token1(state(), false)]
+    #[case::oracle_token0(oracle_state(), true)]
+    #[case::oracle_token1(oracle_state(), false)]
+    fn test_quote_matches_sdk(#[case] state: EkuboState, #[case] sell_token0: bool) {
+        let key = *state.key();
+        let (token_in, token_out) =
+            if sell_token0 { (key.token0, key.token1) } else { (key.token1, key.token0) };
+        let amount = 1_000;
+
+        let quote = state.quote(token_in, amount).unwrap();
+
+        assert_eq!(
+            (quote.consumed_amount, quote.calculated_amount),
+            sdk_quote(&state, token_in, amount)
+        );
+        assert!(quote.gas > 0);
+        // Without fees, swapping back only loses to rounding and price impact.
+        let back = quote
+            .new_state
+            .quote(token_out, quote.calculated_amount)
+            .unwrap();
+        assert!(back.calculated_amount <= amount);
+        assert!(back.calculated_amount >= amount - amount / 100);
+    }
+
+    #[rstest]
+    #[case::base_token0(state(), true)]
+    #[case::base_token1(state(), false)]
+    #[case::oracle_token0(oracle_state(), true)]
+    #[case::oracle_token1(oracle_state(), false)]
+    fn test_quote_exact_out_is_executable(#[case] state: EkuboState, #[case] buy_token0: bool) {
+        let key = *state.key();
+        let (token_in, token_out) =
+            if buy_token0 { (key.token1, key.token0) } else { (key.token0, key.token1) };
+        let amount = 1_000;
+
+        let quote = state
+            .quote_exact_out(token_out, amount)
+            .unwrap();
+
+        assert_eq!(quote.consumed_amount, -amount);
+        assert!(quote.calculated_amount > 0);
+        // Paying the quoted amount buys at least the requested amount.
+        let paid = state
+            .quote(token_in, quote.calculated_amount)
+            .unwrap();
+        assert!(paid.calculated_amount >= amount);
+    }
+
+    #[rstest]
+    #[case::base(state())]
+    #[case::oracle(oracle_state())]
+    fn test_quote_exact_out_edge_cases(#[case] state: EkuboState) {
+        let token_out = state.key().token1;
+
+        let quote = state
+            .quote_exact_out(token_out, 0)
+            .unwrap();
+        assert_eq!((quote.consumed_amount, quote.calculated_amount, quote.gas), (0, 0, 0));
+        assert_eq!(quote.new_state, state);
+
+        let res = state.quote_exact_out(token_out, 1_000_000_000_000);
+        let Err(EkuboQuoteError::InsufficientLiquidity { amount, consumed }) = res else {
+            panic!("Expected insufficient liquidity");
+        };
+        assert_eq!(amount, 1_000_000_000_000);
+        assert!(consumed > 0 && consumed < amount);
+    }
+
+    #[test]
+    fn test_quote_errors() {
+        let state = state();
+        let unknown = U256::from(3u64);
+
+        let res = state.quote(unknown, 100);
+        assert!(matches!(res, Err(EkuboQuoteError::UnknownToken(token)) if token == unknown));
+        let res = state.quote(POOL_KEY.token0, -100);
+        assert!(matches!(res, Err(EkuboQuoteError::NegativeAmount(-100))));
+        let res = state.quote(POOL_KEY.token0, i128::MAX);
+        assert!(matches!(
+            res,
+            Err(EkuboQuoteError::InsufficientLiquidity { amount: i128::MAX, consumed })
+                if consumed < i128::MAX
+        ));
+    }
+
+    fn erc20(address: &str, decimals: usize, symbol: &str) -> Token {
+        Token::new(address, decimals, symbol, BigUint::default())
+    }
+
+    /// An ETH/USDC pool with ETH at 2000 USDC, as an oracle pool of native ETH or a base pool
+    /// of WETH.
+    fn eth_usdc_state(oracle: bool) -> (EkuboState, Token, Token) {
+        let usdc = erc20("0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48", 6, "USDC");
+        let usdc_address = U256::from_big_endian(&usdc.address);
+        // a tick is a factor of 1.000001 in the price of token0 in token1
+        let tick_at = |price: f64| (price.ln() / 1.000001f64.ln()).round() as i32;
+        if oracle {
+            let eth = erc20("0x0000000000000000000000000000000000000000", 18, "ETH");
+            let key = NodeKey {
+                token0: U256::zero(),
+                token1: usdc_address,
+                config: Config { fee: 0, tick_spacing: 0, extension: ORACLE_EXTENSION },
+            };
+            let state = OraclePoolState {
+                full_range_pool_state: FullRangePoolState {
+                    sqrt_ratio: to_sqrt_ratio(tick_at(2000e6 / 1e18)).unwrap(),
+                    liquidity: 0,
+                },
+                last_snapshot_time: 0,
+            };
+            let mut pool = OraclePool::new(&key, state).unwrap();
+            pool.set_clock(Arc::new(FixedClock(BLOCK_TIMESTAMP)));
+            (EkuboState::Oracle(pool), eth, usdc)
+        } else {
+            let weth = erc20("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2", 18, "WETH");
+            let key = NodeKey {
+                token0: usdc_address,
+                token1: U256::from_big_endian(&weth.address),
+                config: POOL_KEY.config,
+            };
+            let tick = tick_at(1e18 / 2000e6);
+            let state = BasePoolState {
+                sqrt_ratio: to_sqrt_ratio(tick).unwrap(),
+                liquidity: 0,
+                active_tick_index: None,
+            };
+            (EkuboState::Base(BasePool::new(key, state, vec![].into(), tick).unwrap()), weth, usdc)
+        }
+    }
+
+    #[rstest]
+    #[case::oracle(true)]
+    #[case::base(false)]
+    fn test_spot_price(#[case] oracle: bool) {
+        let (state, eth, usdc) = eth_usdc_state(oracle);
+
+        let eth_price = EkuboPool::spot_price(&state, &eth, &usdc);
+        let usdc_price = EkuboPool::spot_price(&state, &usdc, &eth);
+
+        // the tick the pool is at rounds the price by at most half a tick
+        assert!((eth_price / 2000.0 - 1.0).abs() < 1e-6, "{eth_price}");
+        assert!((eth_price * usdc_price - 1.0).abs() < 1e-12, "{usdc_price}");
+        assert_eq!(ProtocolSim::spot_price(&state, &eth, &usdc).unwrap(), eth_price);
+    }
+
+    #[test]
+    fn test_spot_price_adjusts_decimals() {
+        let (base, quote) = (token0(), token1());
+        let pool = state();
+
+        assert_eq!(EkuboPool::spot_price(&pool, &base, &quote), 1.0);
+        assert_eq!(
+            EkuboPool::spot_price(&pool, &Token { decimals: 8, ..base.clone() }, &quote),
+            1e8
+        );
+        let inverse = EkuboPool::spot_price(&pool, &Token { decimals: 8, ..quote }, &base);
+        assert!((inverse / 1e8 - 1.0).abs() < 1e-12, "{inverse}");
+    }
+
+    #[rstest]
+    #[case::no_fee(0, 0.0)]
+    #[case::one_bp(1_844_674_407_370_955, 0.0001)]
+    #[case::five_bps(9_223_372_036_854_775, 0.0005)]
+    #[case::thirty_bps(55_340_232_221_128_654, 0.003)]
+    #[case::one_percent(184_467_440_737_095_516, 0.01)]
+    fn test_fee(#[case] fee: u64, #[case] fraction: f64) {
+        let config = Config { fee, ..POOL_KEY.config };
+        let full_range_config = Config { fee, tick_spacing: 0, extension: U256::zero() };
+        let pools = [
+            EkuboState::Base(
+                BasePool::new(
+                    NodeKey { config, ..POOL_KEY },
+                    BasePoolState {
+                        sqrt_ratio: SQRT_RATIO_BETWEEN,
+                        liquidity: 0,
+                        active_tick_index: None,
+                    },
+                    vec![].into(),
+                    TICK_INDEX_BETWEEN,
+                )
+                .unwrap(),
+            ),
+            EkuboState::FullRange(
+                FullRangePool::new(
+                    NodeKey { config: full_range_config, ..POOL_KEY },
+                    FullRangePoolState { sqrt_ratio: SQRT_RATIO_BETWEEN, liquidity: 0 },
+                )
+                .unwrap(),
+            ),
+        ];
+
+        for pool in pools {
+            assert_eq!(pool.fee_raw(), fee);
+            assert!((EkuboPool::fee(&pool) - fraction).abs() < 1e-15, "{pool:?}");
+            assert_eq!(ProtocolSim::fee(&pool), EkuboPool::fee(&pool));
+        }
+    }
+
+    #[test]
+    fn test_oracle_pool_has_no_fee() {
+        let pool = oracle_state();
+
+        assert_eq!((pool.fee_raw(), EkuboPool::fee(&pool)), (0, 0.0));
+    }
+
+    #[test]
+    fn test_conformance() {
+        let amounts = vec![BigUint::from(1u8), BigUint::from(10u8), BigUint::from(100u8)];
+        let spec = ConformanceSpec::new(token0(), token1(), amounts);
+
+        let report = run_conformance_suite(state, spec);
+
+        report.assert_ok();
+    }
+
+    #[test]
+    fn test_display() {
+        let state = state();
+
+        let display = state.to_string();
+
+        assert_eq!(
+            display,
+            concat!(
+                "sqrt_ratio: 340282366920938463463374607431768211456, tick: 0, price token1/token0: 1, price token0/token1: 1, liquidity: 100000000\n",
+                "         10 | ---------------------------------------- -100000000\n",
+                "          0 <- current tick, liquidity 100000000\n",
+                "        -10 | ++++++++++++++++++++++++++++++++++++++++ +100000000\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_debug_tick_ladder_limits_width() {
+        let state = state();
+
+        let ladder = state.debug_tick_ladder(0);
+
+        assert_eq!(ladder, "          0 <- current tick, liquidity 100000000\n");
+    }
+
     #[test]
     fn test_get_limits() {
         let state = state();
 
-        let max_amount_in = state
+        let (max_amount_in, max_amount_out) = state
             .get_limits(
                 Address::from_word(POOL_KEY.token0.to_big_endian().into()),
                 Address::from_word(POOL_KEY.token1.to_big_endian().into()),
             )
-            .unwrap()
-            .0;
+            .unwrap();
 
         assert!(!max_amount_in.is_zero());
+        assert!(!max_amount_out.is_zero());
 
         state
             .get_amount_out(max_amount_in, &token0(), &token1())
             .unwrap();
     }
+
+    #[rstest]
+    #[case::base_token0(state(), true)]
+    #[case::base_token1(state(), false)]
+    #[case::oracle_token0(oracle_state(), true)]
+    #[case::oracle_token1(oracle_state(), false)]
+    fn test_pool_limits_match_quote(#[case] state: EkuboState, #[case] sell_token0: bool) {
+        let token_in = if sell_token0 { state.key().token0 } else { state.key().token1 };
+
+        let (max_in, max_out) = state.swap_limits(token_in).unwrap();
+
+        // quoting more than the pool can take swaps everything up to the price bound
+        let quote = state
+            .pool_quote(TokenAmount { token: token_in, amount: i128::MAX }, None)
+            .unwrap();
+        let close = |limit: U256, quoted: i128| {
+            let limit = u256_to_f64(alloy_primitives::U256::from_limbs(limit.0));
+            let quoted = quoted as f64;
+            (limit - quoted).abs() <= 2.0 + limit * 1e-12
+        };
+        assert!(close(max_in, quote.consumed_amount), "{max_in} != {}", quote.consumed_amount);
+        assert!(
+            close(max_out, quote.calculated_amount),
+            "{max_out} != {}",
+            quote.calculated_amount
+        );
+    }
+
+    #[rstest]
+    #[case::base_token0(state(), true)]
+    #[case::base_token1(state(), false)]
+    #[case::oracle_token0(oracle_state(), true)]
+    #[case::oracle_token1(oracle_state(), false)]
+    fn test_quote_many_matches_quote(#[case] state: EkuboState, #[case] sell_token0: bool) {
+        let token_in = if sell_token0 { state.key().token0 } else { state.key().token1 };
+        let (limit, _) = state.swap_limits(token_in).unwrap();
+        let max_in = (limit
+            .min(U256::from(1u128 << 64))
+            .low_u128() /
+            2) as i128;
+        // SplitMix64, so failures are reproducible
+        let mut seed = 0x9e37_79b9_7f4a_7c15_u64;
+        let mut random = move |bound: i128| {
+            seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut x = seed;
+            x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            i128::from(x ^ (x >> 31)) % bound
+        };
+
+        for _ in 0..50 {
+            let len = random(20) as usize;
+            let mut amounts: Vec<i128> = (0..len)
+                .map(|_| random(max_in + 1))
+                .collect();
+            // repeated amounts and zero are quoted like any other amount
+            if len > 1 {
+                amounts.push(amounts[0]);
+                amounts.push(0);
+            }
+
+            let quotes = state
+                .quote_many(token_in, &amounts)
+                .unwrap();
+
+            let expected: Vec<_> = amounts
+                .iter()
+                .map(|&amount| state.quote(token_in, amount).unwrap())
+                .collect();
+            assert_eq!(quotes, expected, "{amounts:?}");
+        }
+    }
+
+    #[test]
+    fn test_quote_many_errors() {
+        let state = state();
+        let max_in = state
+            .swap_limits(POOL_KEY.token0)
+            .unwrap()
+            .0
+            .low_u128() as i128;
+
+        let res = state.quote_many(POOL_KEY.token0, &[10 * max_in, 1, 2 * max_in]);
+
+        // the smallest amount exceeding the liquidity fails
+        let Err(EkuboQuoteError::InsufficientLiquidity { amount, .. }) = res else {
+            panic!("Expected insufficient liquidity, got {res:?}");
+        };
+        assert_eq!(amount, 2 * max_in);
+        assert!(state
+            .quote_many(POOL_KEY.token0, &[])
+            .unwrap()
+            .is_empty());
+        assert!(matches!(
+            state.quote_many(POOL_KEY.token0, &[1, -1]),
+            Err(EkuboQuoteError::NegativeAmount(-1))
+        ));
+    }
+
+    #[test]
+    fn test_pool_limits_of_one_sided_pool() {
+        // the price is above the position of the pool
+        let pool = EkuboState::Base(
+            BasePool::new(
+                POOL_KEY,
+                BasePoolState {
+                    sqrt_ratio: to_sqrt_ratio(2 * UPPER_TICK.index).unwrap(),
+                    liquidity: 0,
+                    active_tick_index: Some(1),
+                },
+                vec![LOWER_TICK, UPPER_TICK].into(),
+                2 * UPPER_TICK.index,
+            )
+            .unwrap(),
+        );
+
+        let (sell_token0, sell_token1) = (
+            pool.swap_limits(POOL_KEY.token0)
+                .unwrap(),
+            pool.swap_limits(POOL_KEY.token1)
+                .unwrap(),
+        );
+
+        assert!(!sell_token0.0.is_zero() && !sell_token0.1.is_zero());
+        assert_eq!(sell_token1, (U256::zero(), U256::zero()));
+        assert!(matches!(pool.swap_limits(U256::from(3)), Err(SimulationError::InvalidInput(..))));
+    }
+
+    #[rstest]
+    #[case::token0(true)]
+    #[case::token1(false)]
+    fn test_max_input_is_swappable(#[case] sell_token0: bool) {
+        let state = state();
+        let (token_in, token_out) =
+            if sell_token0 { (token0(), token1()) } else { (token1(), token0()) };
+
+        let max_input = state
+            .max_input(&token_in, &token_out)
+            .unwrap();
+
+        state
+            .get_amount_out(BigUint::from(max_input.to::<u128>()), &token_in, &token_out)
+            .unwrap();
+        assert!(state
+            .get_amount_out(BigUint::from(max_input.to::<u128>() + 1), &token_in, &token_out)
+            .is_err());
+    }
+
+    fn sqrt_ratio_after(res: &LimitedSwapResult) -> U256 {
+        res.new_state
+            .as_any()
+            .downcast_ref::<EkuboState>()
+            .unwrap()
+            .sqrt_ratio()
+    }
+
+    #[test]
+    fn test_price_limit_not_reached() {
+        let state = state();
+        let limit = to_sqrt_ratio(-5).unwrap();
+
+        let unlimited = state
+            .get_amount_out(BigUint::from(10u8), &token0(), &token1())
+            .unwrap();
+        let limited = state
+            .get_amount_out_with_price_limit(BigUint::from(10u8), &token0(), &token1(), limit)
+            .unwrap();
+
+        assert!(!limited.limit_reached);
+        assert_eq!(BigUint::from(&limited.amount_in), BigUint::from(10u8));
+        assert_eq!(BigUint::from(&limited.amount_out), unlimited.amount);
+        assert!(sqrt_ratio_after(&limited) > limit);
+    }
+
+    #[rstest]
+    #[case::exact_in(true)]
+    #[case::exact_out(false)]
+    fn test_price_limit_reached(#[case] exact_in: bool) {
+        let state = state();
+        let limit = to_sqrt_ratio(-5).unwrap();
+        let amount = BigUint::from(1_000_000u32);
+
+        let res = if exact_in {
+            state.get_amount_out_with_price_limit(amount.clone(), &token0(), &token1(), limit)
+        } else {
+            state.get_amount_in_with_price_limit(amount.clone(), &token0(), &token1(), limit)
+        }
+        .unwrap();
+
+        assert!(res.limit_reached);
+        assert_eq!(sqrt_ratio_after(&res), limit);
+        assert!(!res.amount_in.raw.is_zero() && !res.amount_out.raw.is_zero());
+        assert!(if exact_in {
+            BigUint::from(&res.amount_in) < amount
+        } else {
+            BigUint::from(&res.amount_out) < amount
+        });
+    }
+
+    #[rstest]
+    #[case::wrong_direction(to_sqrt_ratio(5).unwrap(), true)]
+    #[case::out_of_range(MAX_SQRT_RATIO + U256::one(), false)]
+    fn test_price_limit_wrong_side(#[case] limit: U256, #[case] wrong_direction: bool) {
+        let res = state().get_amount_out_with_price_limit(
+            BigUint::from(10u8),
+            &token0(),
+            &token1(),
+            limit,
+        );
+
+        match res {
+            Err(LimitedSwapError::PriceLimit(PriceLimitError::WrongDirection { .. })) => {
+                assert!(wrong_direction)
+            }
+            Err(LimitedSwapError::PriceLimit(PriceLimitError::OutOfRange(_))) => {
+                assert!(!wrong_direction)
+            }
+            other => panic!("unexpected result {other:?}"),
+        }
+    }
+
+    /// Swaps on oracle pools write a snapshot at the current time, so quoting with a skewed wall
+    /// clock moves the snapshot time of the resulting state into the future.
+    #[test]
+    fn test_oracle_snapshot_time_with_clock_skew() {
+        let snapshot_time_after_swap = |clock: Arc<dyn ClockSource>| {
+            let state = oracle_pool(BLOCK_TIMESTAMP - 12, clock);
+            match state
+                .quote(state.key().token0, 1000)
+                .unwrap()
+                .new_state
+            {
+                EkuboState::Oracle(pool) => pool.last_snapshot_time(),
+                _ => unreachable!("oracle pools quote oracle pools"),
+            }
+        };
+
+        assert_eq!(snapshot_time_after_swap(Arc::new(SkewedClock)), BLOCK_TIMESTAMP + SKEW);
+
+        let clock = BlockAnchoredClock::new(SkewedClock);
+        clock.observe_block(BLOCK_TIMESTAMP);
+        let snapshot_time = snapshot_time_after_swap(Arc::new(clock));
+        assert!((BLOCK_TIMESTAMP..BLOCK_TIMESTAMP + 5).contains(&snapshot_time));
+    }
 }
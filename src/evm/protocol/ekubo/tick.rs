@@ -3,6 +3,8 @@ use itertools::Itertools;
 use num_traits::Zero;
 use tycho_common::Bytes;
 
+use crate::evm::protocol::utils::uniswap::tick_list::{self, InitializedTick};
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Ticks(Vec<Tick>);
 
@@ -15,6 +17,29 @@ impl Ticks {
         &self.0
     }
 
+    /// Heap memory of the ticks in bytes.
+    pub fn heap_size(&self) -> usize {
+        self.0.capacity() * std::mem::size_of::<Tick>()
+    }
+
+    /// Liquidity of all positions active at `tick`.
+    ///
+    /// Ekubo follows the Uniswap V3 convention: a position is active while
+    /// `lower <= tick < upper`. Ranges are half-open `[lower, upper)` accordingly.
+    pub fn active_liquidity_at(&self, tick: i32) -> u128 {
+        tick_list::active_liquidity_at(&self.0, tick)
+    }
+
+    /// Liquidity active at every tick of `[lower, upper)`, zero for an empty range.
+    pub fn liquidity_in_range(&self, lower: i32, upper: i32) -> u128 {
+        tick_list::liquidity_in_range(&self.0, lower, upper)
+    }
+
+    /// Sum of the liquidity deltas of the ticks in `[lower, upper)`.
+    pub fn liquidity_delta_in_range(&self, lower: i32, upper: i32) -> i128 {
+        tick_list::liquidity_net_in_range(&self.0, lower, upper)
+    }
+
     pub fn set(&mut self, tick: Tick) {
         let res = self
             .0
@@ -39,6 +64,16 @@ impl Ticks {
     }
 }
 
+impl InitializedTick for Tick {
+    fn index(&self) -> i32 {
+        self.index
+    }
+
+    fn net_liquidity(&self) -> i128 {
+        self.liquidity_delta
+    }
+}
+
 impl From<Vec<Tick>> for Ticks {
     fn from(value: Vec<Tick>) -> Self {
         Self(value)
@@ -64,3 +99,54 @@ pub fn ticks_from_attributes<T: IntoIterator<Item = (String, Bytes)>>(
         })
         .try_collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use evm_ekubo_sdk::math::tick::{MAX_TICK, MIN_TICK};
+    use rstest::rstest;
+
+    use super::*;
+
+    /// A full range position with liquidity 10 and a position `[-100, 100)` with liquidity 5.
+    fn ticks() -> Ticks {
+        Ticks::new(vec![
+            Tick { index: MIN_TICK, liquidity_delta: 10 },
+            Tick { index: -100, liquidity_delta: 5 },
+            Tick { index: 100, liquidity_delta: -5 },
+            Tick { index: MAX_TICK, liquidity_delta: -10 },
+        ])
+    }
+
+    #[rstest]
+    #[case::below_min(MIN_TICK - 1, 0)]
+    #[case::min(MIN_TICK, 10)]
+    #[case::below_lower(-101, 10)]
+    #[case::on_lower(-100, 15)]
+    #[case::below_upper(99, 15)]
+    #[case::on_upper(100, 10)]
+    #[case::below_max(MAX_TICK - 1, 10)]
+    #[case::max(MAX_TICK, 0)]
+    fn test_active_liquidity_at(#[case] tick: i32, #[case] expected: u128) {
+        assert_eq!(ticks().active_liquidity_at(tick), expected);
+    }
+
+    #[rstest]
+    #[case::full_range(MIN_TICK, MAX_TICK, 10)]
+    #[case::endpoints_on_ticks(-100, 100, 15)]
+    #[case::between_ticks(-50, 50, 15)]
+    #[case::upper_excluded(-200, -100, 10)]
+    #[case::beyond_max(MAX_TICK, MAX_TICK + 1, 0)]
+    #[case::empty(100, -100, 0)]
+    fn test_liquidity_in_range(#[case] lower: i32, #[case] upper: i32, #[case] expected: u128) {
+        assert_eq!(ticks().liquidity_in_range(lower, upper), expected);
+    }
+
+    #[test]
+    fn test_liquidity_delta_in_range() {
+        let ticks = ticks();
+
+        assert_eq!(ticks.liquidity_delta_in_range(-100, 100), 5);
+        assert_eq!(ticks.liquidity_delta_in_range(-100, 101), 0);
+        assert_eq!(ticks.liquidity_delta_in_range(MIN_TICK, MAX_TICK + 1), 0);
+    }
+}
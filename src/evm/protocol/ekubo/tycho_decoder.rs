@@ -185,7 +185,13 @@ mod tests {
     use tycho_common::dto::ResponseProtocolState;
 
     use super::*;
-    use crate::evm::protocol::ekubo::test_pool::{attributes, component, state};
+    use crate::evm::protocol::{
+        ekubo::{
+            pool::EkuboPool,
+            test_pool::{attributes, component, state},
+        },
+        filters::ekubo_default_fee_filter,
+    };
 
     #[tokio::test]
     async fn test_try_from_with_block() {
@@ -250,4 +256,39 @@ mod tests {
             InvalidSnapshotError::MissingAttribute(attr) if attr == missing_attribute
         ));
     }
+
+    #[tokio::test]
+    async fn test_try_from_any_fee() {
+        let mut component = component();
+        component
+            .static_attributes
+            .insert("fee".to_string(), 1u64.into());
+        let snapshot = ComponentWithState {
+            state: ResponseProtocolState { attributes: attributes(), ..Default::default() },
+            component,
+        };
+
+        // Pools can be created with any fee, only the opt-in filter restricts them to the tiers
+        assert!(!ekubo_default_fee_filter(&snapshot));
+        let result = EkuboState::try_from_with_block(
+            snapshot,
+            Header::default(),
+            &HashMap::default(),
+            &HashMap::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(result.fee_raw(), 1);
+    }
+
+    #[test]
+    fn test_default_fee_filter() {
+        let snapshot = ComponentWithState {
+            state: ResponseProtocolState { attributes: attributes(), ..Default::default() },
+            component: component(),
+        };
+
+        assert!(ekubo_default_fee_filter(&snapshot));
+    }
 }
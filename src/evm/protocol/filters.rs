@@ -4,7 +4,9 @@ use num_bigint::BigInt;
 use tracing::{debug, info};
 use tycho_client::feed::synchronizer::ComponentWithState;
 
-use crate::evm::protocol::vm::utils::json_deserialize_be_bigint_list;
+use crate::evm::protocol::{
+    ekubo::fee::EkuboFeeValidator, vm::utils::json_deserialize_be_bigint_list,
+};
 
 const ZERO_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
 const ZERO_ADDRESS_ARR: [u8; 20] = [0u8; 20];
@@ -140,3 +142,22 @@ pub fn uniswap_v4_pool_with_hook_filter(component: &ComponentWithState) -> bool
     }
     true
 }
+
+/// Filters out Ekubo pools whose fee is not one of the default tiers, see
+/// [`DEFAULT_FEES`](crate::evm::protocol::ekubo::fee::DEFAULT_FEES). Pools without a readable fee
+/// are left to the decoder.
+pub fn ekubo_default_fee_filter(component: &ComponentWithState) -> bool {
+    let Some(fee) = component
+        .component
+        .static_attributes
+        .get("fee")
+        .and_then(|fee| <[u8; 8]>::try_from(fee.as_ref()).ok())
+    else {
+        return true;
+    };
+    if let Err(err) = EkuboFeeValidator::default().validate(u64::from_be_bytes(fee)) {
+        debug!("Filtering out Ekubo pool {}: {}", component.component.id, err);
+        return false;
+    }
+    true
+}
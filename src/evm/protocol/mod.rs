@@ -1,9 +1,19 @@
+pub mod abi;
+pub mod balancer_v2;
+pub mod curve;
+pub mod decoder;
 pub mod ekubo;
 pub mod filters;
+pub mod quote_diff;
+#[cfg(test)]
+mod rounding_conformance;
+#[doc(hidden)]
 pub mod safe_math;
+#[doc(hidden)]
 pub mod u256_num;
 pub mod uniswap_v2;
 pub mod uniswap_v3;
 pub mod uniswap_v4;
+#[doc(hidden)]
 pub mod utils;
 pub mod vm;
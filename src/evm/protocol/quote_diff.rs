@@ -0,0 +1,399 @@
+//! Explains why the quote of a pool changed between two states.
+//!
+//! [`explain_quote_change`] quotes the same trade on both states and attributes the difference to
+//! the parts of the pool state that moved. For the native Uniswap pools this is done by quoting
+//! hybrid states: first only the price is moved to the new state, then the liquidity, then the
+//! rest. Each step's change in output is reported as the effect of that component. The effects
+//! therefore always add up to the total quote change, but they depend on that order.
+//!
+//! VM pools can't be decomposed like this; for them only the changed balances and storage slots
+//! are listed.
+use alloy_primitives::U256;
+use num_bigint::{BigInt, BigUint};
+use serde::{Serialize, Serializer};
+
+use crate::{
+    evm::{
+        engine_db::tycho_db::PreCachedDB,
+        protocol::{
+            uniswap_v2::state::{sqrt_u256, UniswapV2State},
+            uniswap_v3::state::UniswapV3State,
+            vm::state::EVMPoolState,
+        },
+    },
+    models::Token,
+    protocol::{errors::SimulationError, state::ProtocolSim},
+};
+
+/// A single field that differs between the two states.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StateChange {
+    pub field: String,
+    pub before: String,
+    pub after: String,
+}
+
+/// The difference between the quotes of two states of the same pool, broken down by cause.
+///
+/// Effects are `None` if the pool type can't be decomposed, or if a hybrid state couldn't be
+/// quoted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct QuoteChangeExplanation {
+    #[serde(serialize_with = "serialize_display")]
+    pub amount_out_before: BigUint,
+    #[serde(serialize_with = "serialize_display")]
+    pub amount_out_after: BigUint,
+    /// Change in output caused by the move in price.
+    #[serde(serialize_with = "serialize_opt_display")]
+    pub price_effect: Option<BigInt>,
+    /// Change in output caused by the change in liquidity, at the new price.
+    #[serde(serialize_with = "serialize_opt_display")]
+    pub liquidity_effect: Option<BigInt>,
+    /// Change in output caused by everything else, mostly the fee.
+    #[serde(serialize_with = "serialize_opt_display")]
+    pub fee_effect: Option<BigInt>,
+    /// The fields that differ between the two states.
+    pub state_changes: Vec<StateChange>,
+}
+
+impl QuoteChangeExplanation {
+    /// Total change of the output amount.
+    pub fn total_change(&self) -> BigInt {
+        BigInt::from(self.amount_out_after.clone()) - BigInt::from(self.amount_out_before.clone())
+    }
+
+    /// Serializes the explanation as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Explanation is always serializable")
+    }
+}
+
+/// Explains the change in the quote of `amount` of `token_in` between `pool_before` and
+/// `pool_after`.
+///
+/// Both states must belong to the same pool. Fails if either of the two states can't be quoted.
+pub fn explain_quote_change(
+    pool_before: &dyn ProtocolSim,
+    pool_after: &dyn ProtocolSim,
+    amount: BigUint,
+    token_in: &Token,
+    token_out: &Token,
+) -> Result<QuoteChangeExplanation, SimulationError> {
+    let quote = |state: &dyn ProtocolSim| {
+        state
+            .get_amount_out(amount.clone(), token_in, token_out)
+            .map(|res| res.amount)
+    };
+    let amount_out_before = quote(pool_before)?;
+    let amount_out_after = quote(pool_after)?;
+    let mut explanation = QuoteChangeExplanation {
+        amount_out_before,
+        amount_out_after,
+        price_effect: None,
+        liquidity_effect: None,
+        fee_effect: None,
+        state_changes: Vec::new(),
+    };
+
+    // Outputs of the hybrid states: before with the new price, and before with the new price and
+    // the new liquidity.
+    let hybrids = if let (Some(before), Some(after)) = (
+        pool_before
+            .as_any()
+            .downcast_ref::<UniswapV3State>(),
+        pool_after
+            .as_any()
+            .downcast_ref::<UniswapV3State>(),
+    ) {
+        explanation.state_changes = to_state_changes(before.changed_fields(after));
+        let with_price = before.with_price_of(after);
+        let with_liquidity = with_price.with_liquidity_of(after);
+        quote(&with_price)
+            .and_then(|price| Ok((price, quote(&with_liquidity)?)))
+            .ok()
+    } else if let (Some(before), Some(after)) = (
+        pool_before
+            .as_any()
+            .downcast_ref::<UniswapV2State>(),
+        pool_after
+            .as_any()
+            .downcast_ref::<UniswapV2State>(),
+    ) {
+        explanation.state_changes = v2_changes(before, after);
+        // Moving only the price keeps the constant product of the old state. The fee is fixed, so
+        // moving the liquidity as well yields the new state.
+        v2_with_price_of(before, after)
+            .and_then(|with_price| quote(&with_price).ok())
+            .map(|price| (price, explanation.amount_out_after.clone()))
+    } else {
+        if let (Some(before), Some(after)) = (
+            pool_before
+                .as_any()
+                .downcast_ref::<EVMPoolState<PreCachedDB>>(),
+            pool_after
+                .as_any()
+                .downcast_ref::<EVMPoolState<PreCachedDB>>(),
+        ) {
+            explanation.state_changes = vm_changes(before, after);
+        }
+        None
+    };
+
+    if let Some((with_price, with_liquidity)) = hybrids {
+        let before = BigInt::from(explanation.amount_out_before.clone());
+        let with_price = BigInt::from(with_price);
+        let with_liquidity = BigInt::from(with_liquidity);
+        let after = BigInt::from(explanation.amount_out_after.clone());
+        explanation.price_effect = Some(&with_price - before);
+        explanation.liquidity_effect = Some(&with_liquidity - with_price);
+        explanation.fee_effect = Some(after - with_liquidity);
+    }
+    Ok(explanation)
+}
+
+fn to_state_changes(changes: Vec<(&'static str, String, String)>) -> Vec<StateChange> {
+    changes
+        .into_iter()
+        .map(|(field, before, after)| StateChange { field: field.to_string(), before, after })
+        .collect()
+}
+
+/// The reserves of `after` scaled to the liquidity (`sqrt(reserve0 * reserve1)`) of `before`.
+fn v2_with_price_of(before: &UniswapV2State, after: &UniswapV2State) -> Option<UniswapV2State> {
+    let liquidity_before = sqrt_u256(
+        before
+            .reserve0
+            .checked_mul(before.reserve1)?,
+    );
+    let liquidity_after = sqrt_u256(
+        after
+            .reserve0
+            .checked_mul(after.reserve1)?,
+    );
+    if liquidity_after.is_zero() {
+        return None;
+    }
+    let scale = |reserve: U256| {
+        reserve
+            .checked_mul(liquidity_before)
+            .map(|r| r / liquidity_after)
+    };
+    Some(UniswapV2State::new(scale(after.reserve0)?, scale(after.reserve1)?))
+}
+
+fn v2_changes(before: &UniswapV2State, after: &UniswapV2State) -> Vec<StateChange> {
+    let mut changes = Vec::new();
+    for (field, b, a) in [
+        ("reserve0", before.reserve0, after.reserve0),
+        ("reserve1", before.reserve1, after.reserve1),
+    ] {
+        if b != a {
+            changes.push((field, b.to_string(), a.to_string()));
+        }
+    }
+    if before.total_supply_lp != after.total_supply_lp {
+        changes.push((
+            "total_supply_lp",
+            display_or_unset(before.total_supply_lp.as_ref()),
+            display_or_unset(after.total_supply_lp.as_ref()),
+        ));
+    }
+    to_state_changes(changes)
+}
+
+/// Lists changed component balances and block lasting storage overwrites.
+fn vm_changes(
+    before: &EVMPoolState<PreCachedDB>,
+    after: &EVMPoolState<PreCachedDB>,
+) -> Vec<StateChange> {
+    let mut changes = Vec::new();
+    let mut tokens: Vec<_> = before
+        .balances()
+        .keys()
+        .chain(after.balances().keys())
+        .collect();
+    tokens.sort();
+    tokens.dedup();
+    for token in tokens {
+        let b = before.balances().get(token);
+        let a = after.balances().get(token);
+        if b != a {
+            changes.push(StateChange {
+                field: format!("balance[{token}]"),
+                before: display_or_unset(b),
+                after: display_or_unset(a),
+            });
+        }
+    }
+
+    let mut slots: Vec<_> = before
+        .block_lasting_overwrites()
+        .iter()
+        .chain(after.block_lasting_overwrites())
+        .flat_map(|(address, overwrites)| {
+            overwrites
+                .keys()
+                .map(move |slot| (*address, *slot))
+        })
+        .collect();
+    slots.sort();
+    slots.dedup();
+    for (address, slot) in slots {
+        let get = |state: &EVMPoolState<PreCachedDB>| {
+            state
+                .block_lasting_overwrites()
+                .get(&address)
+                .and_then(|overwrites| overwrites.get(&slot))
+                .copied()
+        };
+        let (b, a) = (get(before), get(after));
+        if b != a {
+            changes.push(StateChange {
+                field: format!("storage[{address}][{slot:#x}]"),
+                before: display_or_unset(b.as_ref()),
+                after: display_or_unset(a.as_ref()),
+            });
+        }
+    }
+    changes
+}
+
+fn display_or_unset(value: Option<&U256>) -> String {
+    value.map_or_else(|| "unset".to_string(), |v| v.to_string())
+}
+
+fn serialize_display<T: std::fmt::Display, S: Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+fn serialize_opt_display<T: std::fmt::Display, S: Serializer>(
+    value: &Option<T>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => serializer.collect_str(value),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use num_traits::Zero;
+
+    use super::*;
+    use crate::evm::protocol::{
+        uniswap_v3::enums::FeeAmount,
+        utils::uniswap::{tick_list::TickInfo, tick_math::get_sqrt_ratio_at_tick},
+    };
+
+    fn tokens() -> (Token, Token) {
+        let token_x = Token::new(
+            "0x6b175474e89094c44da98b954eedeac495271d0f",
+            18,
+            "X",
+            BigUint::from(10_000u64),
+        );
+        let token_y = Token::new(
+            "0xf1ca9cb74685755965c7458528a36934df52a3ef",
+            18,
+            "Y",
+            BigUint::from(10_000u64),
+        );
+        (token_x, token_y)
+    }
+
+    fn v3_pool(liquidity: u128, fee: FeeAmount) -> UniswapV3State {
+        UniswapV3State::new(
+            liquidity,
+            get_sqrt_ratio_at_tick(0).unwrap(),
+            fee,
+            0,
+            vec![TickInfo::new(-600, liquidity as i128), TickInfo::new(600, -(liquidity as i128))],
+        )
+    }
+
+    #[test]
+    fn test_v3_liquidity_change_is_attributed_to_liquidity() {
+        let (token_x, token_y) = tokens();
+        let before = v3_pool(100_000_000_000_000_000_000, FeeAmount::Medium);
+        let after = v3_pool(200_000_000_000_000_000_000, FeeAmount::Medium);
+        let amount = BigUint::from_str("1000000000000000000").unwrap();
+
+        let res = explain_quote_change(&before, &after, amount, &token_x, &token_y).unwrap();
+
+        assert!(res.amount_out_after > res.amount_out_before);
+        assert_eq!(res.price_effect, Some(BigInt::zero()));
+        assert_eq!(res.fee_effect, Some(BigInt::zero()));
+        assert_eq!(res.liquidity_effect, Some(res.total_change()));
+        let fields: Vec<_> = res
+            .state_changes
+            .iter()
+            .map(|c| c.field.as_str())
+            .collect();
+        assert_eq!(fields, vec!["liquidity"]);
+    }
+
+    #[test]
+    fn test_v3_effects_add_up_to_total_change() {
+        let (token_x, token_y) = tokens();
+        let before = v3_pool(100_000_000_000_000_000_000, FeeAmount::Medium);
+        let after = UniswapV3State::new(
+            150_000_000_000_000_000_000,
+            get_sqrt_ratio_at_tick(-60).unwrap(),
+            FeeAmount::Low,
+            -60,
+            vec![
+                TickInfo::new(-600, 150_000_000_000_000_000_000),
+                TickInfo::new(600, -150_000_000_000_000_000_000),
+            ],
+        );
+        let amount = BigUint::from_str("1000000000000000000").unwrap();
+
+        let res = explain_quote_change(&before, &after, amount, &token_x, &token_y).unwrap();
+
+        let sum = res.price_effect.clone().unwrap() +
+            res.liquidity_effect.clone().unwrap() +
+            res.fee_effect.clone().unwrap();
+        assert_eq!(sum, res.total_change());
+        assert!(res.fee_effect.unwrap() > BigInt::zero());
+    }
+
+    #[test]
+    fn test_v2_liquidity_change_is_attributed_to_liquidity() {
+        let (token_x, token_y) = tokens();
+        let before = UniswapV2State::new(U256::from(1_000_000u64), U256::from(4_000_000u64));
+        let after = UniswapV2State::new(U256::from(2_000_000u64), U256::from(8_000_000u64));
+
+        let res =
+            explain_quote_change(&before, &after, BigUint::from(1_000u64), &token_x, &token_y)
+                .unwrap();
+
+        assert_eq!(res.price_effect, Some(BigInt::zero()));
+        assert_eq!(res.fee_effect, Some(BigInt::zero()));
+        assert_eq!(res.liquidity_effect, Some(res.total_change()));
+        assert_eq!(res.state_changes.len(), 2);
+    }
+
+    #[test]
+    fn test_serializes_amounts_as_strings() {
+        let explanation = QuoteChangeExplanation {
+            amount_out_before: BigUint::from(10u64),
+            amount_out_after: BigUint::from(7u64),
+            price_effect: Some(BigInt::from(-3)),
+            liquidity_effect: None,
+            fee_effect: None,
+            state_changes: vec![],
+        };
+
+        let json: serde_json::Value = serde_json::from_str(&explanation.to_json()).unwrap();
+
+        assert_eq!(json["amount_out_before"], "10");
+        assert_eq!(json["price_effect"], "-3");
+        assert!(json["liquidity_effect"].is_null());
+    }
+}
@@ -0,0 +1,128 @@
+//! Rounding conformance of the native pool math.
+//!
+//! Every case sits on a rounding boundary: the exact result has a fractional part, so rounding
+//! in the wrong direction is off by one wei. Expected values are the results of the contracts'
+//! integer formulas (`UniswapV2Pair.swap`, `SwapMath.computeSwapStep`, `SqrtPriceMath`,
+//! `WeightedMath`) for the same inputs.
+use std::str::FromStr;
+
+use alloy_primitives::{I256, U256};
+use num_bigint::{BigUint, ToBigUint};
+use rstest::rstest;
+
+use crate::{
+    evm::protocol::{
+        balancer_v2::weighted_pool::BalancerWeightedPool,
+        safe_math::Rounding,
+        uniswap_v2::state::UniswapV2State,
+        utils::uniswap::{sqrt_price_math::get_amount0_delta, swap_math::compute_swap_step},
+    },
+    models::Token,
+    protocol::state::ProtocolSim,
+};
+
+const Q96: U256 = U256::from_limbs([0, 4294967296, 0, 0]);
+
+fn u256(s: &str) -> U256 {
+    U256::from_str(s).unwrap()
+}
+
+fn i256(s: &str) -> I256 {
+    I256::from_str(s).unwrap()
+}
+
+fn token(address: &str) -> Token {
+    Token::new(address, 18, "T", 10_000.to_biguint().unwrap())
+}
+
+#[rstest]
+#[case::zero_for_one(true, "2988020943119712")]
+#[case::one_for_zero(false, "332222924581397")]
+fn test_uniswap_v2_amount_out_rounds_down(#[case] zero_for_one: bool, #[case] expected: &str) {
+    let pool = UniswapV2State::new(u256("1000000000000000007"), u256("3000000000000000000"));
+    let (t0, t1) = (
+        token("0x0000000000000000000000000000000000000001"),
+        token("0x0000000000000000000000000000000000000002"),
+    );
+    let (token_in, token_out) = if zero_for_one { (&t0, &t1) } else { (&t1, &t0) };
+
+    let res = pool
+        .get_amount_out(BigUint::from(1_000_000_000_000_001u64), token_in, token_out)
+        .unwrap();
+
+    assert_eq!(res.amount, BigUint::from_str(expected).unwrap());
+}
+
+/// `(sqrt price target, amount remaining)`, and the expected
+/// `(sqrt price next, amount in, amount out, fee)` for a pool at price 1 with 1e18 liquidity and
+/// a 0.3% fee.
+#[rstest]
+#[case::exact_in_zero_for_one(
+    "39614081257132168796771975168",
+    "1000000",
+    ("79228162514185347115517307545", "997000", "996999", "3000")
+)]
+#[case::exact_in_one_for_zero(
+    "158456325028528675187087900672",
+    "1000000",
+    ("79228162514343328071570671880", "997000", "996999", "3000")
+)]
+#[case::exact_out_zero_for_one(
+    "39614081257132168796771975168",
+    "-1000000",
+    ("79228162514185109431029685998", "1000001", "1000000", "3010")
+)]
+#[case::exact_out_one_for_zero(
+    "158456325028528675187087900672",
+    "-1000000",
+    ("79228162514343565756058293902", "1000001", "1000000", "3010")
+)]
+#[case::fee_on_reached_target(
+    "79228162414264337593543950336",
+    "1000000000000000000",
+    ("79228162414264337593543950336", "1262177450", "1262177448", "3797927")
+)]
+fn test_uniswap_v3_swap_step_rounds_against_user(
+    #[case] target: &str,
+    #[case] amount_remaining: &str,
+    #[case] expected: (&str, &str, &str, &str),
+) {
+    let res = compute_swap_step(Q96, u256(target), 10u128.pow(18), i256(amount_remaining), 3_000)
+        .unwrap();
+
+    assert_eq!(res, (u256(expected.0), u256(expected.1), u256(expected.2), u256(expected.3)));
+}
+
+#[rstest]
+#[case::inexact_product("79228162514264337593543950336", "105637550019019116791391933781", 4, "0")]
+#[case::exact_product("13204693752377389598923991723", "26409387504754779197847983446", 1, "2")]
+fn test_uniswap_v3_amount0_out_rounds_down(
+    #[case] sqrt_price_a: &str,
+    #[case] sqrt_price_b: &str,
+    #[case] liquidity: u128,
+    #[case] expected: &str,
+) {
+    let res = get_amount0_delta(u256(sqrt_price_a), u256(sqrt_price_b), liquidity, Rounding::Down)
+        .unwrap();
+
+    assert_eq!(res, u256(expected));
+}
+
+#[test]
+fn test_balancer_exit_rounds_down() {
+    let one = u256("1000000000000000000");
+    let half = u256("500000000000000000");
+    let pool = BalancerWeightedPool::new(
+        vec![u256("2000000000000000001"), u256("3000000000000000002")],
+        vec![half, half],
+        one * U256::from(3u64),
+        u256("3000000000000000"),
+    )
+    .unwrap();
+
+    let res = pool
+        .exit_exact_bpt_in(one, &[U256::ZERO, U256::ZERO])
+        .unwrap();
+
+    assert_eq!(res.amounts_out, vec![u256("666666666666666666"), u256("999999999999999999")]);
+}
@@ -9,6 +9,18 @@ use alloy_primitives::{I256, U256, U512};
 
 use crate::protocol::errors::SimulationError;
 
+/// Direction in which the result of a division is rounded.
+///
+/// Amount math has to round exactly like the contract it mirrors, which always rounds against
+/// the user: amounts paid out round down, amounts owed to the pool round up. Rounding the other
+/// way overquotes by a wei, which breaks exact matches with on-chain results and compounds across
+/// hops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    Down,
+    Up,
+}
+
 pub fn safe_mul_u256(a: U256, b: U256) -> Result<U256, SimulationError> {
     let res = a.checked_mul(b);
     _construc_result_u256(res)
@@ -38,7 +50,16 @@ pub fn div_mod_u256(a: U256, b: U256) -> Result<(U256, U256), SimulationError> {
     Ok((result, rest))
 }
 
-pub fn _construc_result_u256(res: Option<U256>) -> Result<U256, SimulationError> {
+/// `a / b`, rounded in the given direction.
+pub fn div_u256(a: U256, b: U256, rounding: Rounding) -> Result<U256, SimulationError> {
+    let (result, rest) = div_mod_u256(a, b)?;
+    match rounding {
+        Rounding::Up if !rest.is_zero() => safe_add_u256(result, U256::from(1u64)),
+        _ => Ok(result),
+    }
+}
+
+fn _construc_result_u256(res: Option<U256>) -> Result<U256, SimulationError> {
     match res {
         None => Err(SimulationError::FatalError("U256 arithmetic overflow".to_string())),
         Some(value) => Ok(value),
@@ -74,7 +95,7 @@ pub fn div_mod_u512(a: U512, b: U512) -> Result<(U512, U512), SimulationError> {
     Ok((result, rest))
 }
 
-pub fn _construc_result_u512(res: Option<U512>) -> Result<U512, SimulationError> {
+fn _construc_result_u512(res: Option<U512>) -> Result<U512, SimulationError> {
     match res {
         None => Err(SimulationError::FatalError("U256 arithmetic overflow".to_string())),
         Some(value) => Ok(value),
@@ -101,7 +122,7 @@ pub fn safe_sub_i256(a: I256, b: I256) -> Result<I256, SimulationError> {
     _construc_result_i256(res)
 }
 
-pub fn _construc_result_i256(res: Option<I256>) -> Result<I256, SimulationError> {
+fn _construc_result_i256(res: Option<I256>) -> Result<I256, SimulationError> {
     match res {
         None => Err(SimulationError::FatalError("U256 arithmetic overflow".to_string())),
         Some(value) => Ok(value),
@@ -364,6 +385,27 @@ mod safe_math_tests {
         }
     }
 
+    #[rstest]
+    #[case::exact(u256("10"), u256("5"), Rounding::Up, u256("2"))]
+    #[case::exact_down(u256("10"), u256("5"), Rounding::Down, u256("2"))]
+    #[case::fraction_up(u256("11"), u256("5"), Rounding::Up, u256("3"))]
+    #[case::fraction_down(u256("11"), u256("5"), Rounding::Down, u256("2"))]
+    #[case::zero(u256("0"), u256("5"), Rounding::Up, u256("0"))]
+    fn test_div_u256(
+        #[case] a: U256,
+        #[case] b: U256,
+        #[case] rounding: Rounding,
+        #[case] expected: U256,
+    ) {
+        assert_eq!(div_u256(a, b, rounding).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_div_u256_by_zero() {
+        assert!(div_u256(U256::from(1u64), U256::ZERO, Rounding::Down).is_err());
+        assert!(div_u256(U256::from(1u64), U256::ZERO, Rounding::Up).is_err());
+    }
+
     #[rstest]
     #[case(i256("1"), i256("0"), true, false, i256("0"))]
     #[case(i256("10"), i256("2"), false, true, i256("5"))]
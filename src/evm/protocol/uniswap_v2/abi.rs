@@ -0,0 +1,116 @@
+//! ABI codec for Uniswap V2 pair view calls.
+use alloy_sol_types::SolValue;
+
+use crate::evm::protocol::abi::{selector, AbiError};
+
+/// Reserves as returned by `getReserves()`: `(reserve0, reserve1, blockTimestampLast)`.
+type GetReservesReturn = (u128, u128, u32);
+
+/// Encodes and decodes calls to a `UniswapV2Pair` without simulating them.
+pub struct UniswapV2Abi;
+
+impl UniswapV2Abi {
+    pub const GET_RESERVES: &'static str = "getReserves()";
+
+    /// Calldata of `getReserves()`.
+    pub fn encode_get_reserves() -> Vec<u8> {
+        selector(Self::GET_RESERVES).to_vec()
+    }
+
+    /// Decodes the return data of `getReserves()` into `(reserve0, reserve1, blockTimestampLast)`.
+    pub fn decode_get_reserves(data: &[u8]) -> Result<(u128, u128, u32), AbiError> {
+        GetReservesReturn::abi_decode(data, true)
+            .map_err(|source| AbiError::Decode { function: Self::GET_RESERVES, source })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use alloy_primitives::{hex, keccak256, Address, U256};
+    use revm::primitives::{AccountInfo, Bytecode};
+
+    use super::*;
+    use crate::evm::{
+        engine_db::{
+            create_engine, engine_db_interface::EngineDatabaseInterface, tycho_db::PreCachedDB,
+        },
+        simulation::{SimulationEngine, SimulationParameters},
+    };
+
+    /// Runtime code answering any call like `UniswapV2Pair.getReserves()`: it unpacks
+    /// `reserve0` (uint112), `reserve1` (uint112) and `blockTimestampLast` (uint32) from slot 8.
+    const GET_RESERVES_CODE: &[u8] = &hex!(
+        "600854"
+        "80 6dffffffffffffffffffffffffffff 16 600052"
+        "80 60701c 6dffffffffffffffffffffffffffff 16 602052"
+        "60e01c 604052"
+        "60606000f3"
+    );
+
+    const RESERVE0: u128 = 1_234_567_890_123_456_789;
+    const RESERVE1: u128 = 987_654_321;
+    const TIMESTAMP: u32 = 1_700_000_000;
+
+    fn pair_engine(pair: Address) -> SimulationEngine<PreCachedDB> {
+        let engine = create_engine(PreCachedDB::new().unwrap(), false).unwrap();
+        let code = Bytecode::new_raw(GET_RESERVES_CODE.into());
+        let packed =
+            U256::from(RESERVE0) | (U256::from(RESERVE1) << 112) | (U256::from(TIMESTAMP) << 224);
+        engine.state.init_account(
+            pair,
+            AccountInfo {
+                balance: U256::ZERO,
+                nonce: 0,
+                code_hash: keccak256(code.bytes()),
+                code: Some(code),
+            },
+            Some(HashMap::from([(U256::from(8), packed)])),
+            true,
+        );
+        engine
+    }
+
+    fn get_reserves_params(pair: Address) -> SimulationParameters {
+        SimulationParameters {
+            caller: Address::ZERO,
+            to: pair,
+            data: UniswapV2Abi::encode_get_reserves(),
+            value: U256::ZERO,
+            overrides: None,
+            balance_overrides: None,
+            nonce: None,
+            gas_limit: None,
+            block_number: 0,
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn test_encode_get_reserves() {
+        assert_eq!(UniswapV2Abi::encode_get_reserves(), hex!("0902f1ac"));
+    }
+
+    #[test]
+    fn test_decode_get_reserves_matches_revm() {
+        let pair = Address::repeat_byte(0x22);
+        let engine = pair_engine(pair);
+
+        let result = engine
+            .simulate(&get_reserves_params(pair))
+            .unwrap();
+
+        assert_eq!(
+            UniswapV2Abi::decode_get_reserves(&result.result).unwrap(),
+            (RESERVE0, RESERVE1, TIMESTAMP)
+        );
+    }
+
+    #[test]
+    fn test_decode_get_reserves_invalid_data() {
+        let res = UniswapV2Abi::decode_get_reserves(&[0u8; 31]);
+
+        assert!(matches!(res, Err(AbiError::Decode { function: "getReserves()", .. })));
+    }
+}
@@ -1,4 +1,7 @@
 //! Uniswap V2 Decentralized Exchange
+pub mod abi;
+pub mod oracle;
 mod reserve_price;
 pub mod state;
+pub mod token_config;
 pub mod tycho_decoder;
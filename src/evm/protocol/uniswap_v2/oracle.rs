@@ -0,0 +1,185 @@
+//! Time weighted average prices from the pair's price accumulators
+//!
+//! A Uniswap V2 pair sums its price over time in `price0CumulativeLast` and
+//! `price1CumulativeLast`. The average price over a window is the difference of two readings of an
+//! accumulator divided by the seconds between them, see `ExampleOracleSimple` of the periphery.
+use std::collections::VecDeque;
+
+use alloy_primitives::U256;
+
+use super::state::UniswapV2State;
+use crate::{evm::protocol::u256_num::u256_to_f64, protocol::errors::SimulationError};
+
+/// Default number of observations kept by [`UniswapV2Oracle`].
+pub const DEFAULT_MAX_OBSERVATIONS: usize = 1_024;
+
+pub struct UniswapV2Twap;
+
+impl UniswapV2Twap {
+    /// Returns the average UQ112.112 price between two readings of an accumulator taken `elapsed`
+    /// seconds apart.
+    ///
+    /// The readings are subtracted with wrapping, like the accumulators themselves overflow.
+    pub fn compute(start: U256, end: U256, elapsed: u32) -> Result<U256, SimulationError> {
+        if elapsed == 0 {
+            return Err(SimulationError::InvalidInput(
+                "TWAP window must be longer than zero seconds".to_string(),
+                None,
+            ));
+        }
+        Ok(end.wrapping_sub(start) / U256::from(elapsed))
+    }
+
+    /// Converts a UQ112.112 price to a float.
+    pub fn to_f64(price: U256) -> f64 {
+        u256_to_f64(price) / 2f64.powi(112)
+    }
+}
+
+/// Reading of a pair's accumulators at a point in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Observation {
+    pub timestamp: u32,
+    pub price0_cumulative: U256,
+    pub price1_cumulative: U256,
+}
+
+/// Keeps observations of a single pair to quote its TWAP over a trailing window.
+#[derive(Debug, Clone)]
+pub struct UniswapV2Oracle {
+    observations: VecDeque<Observation>,
+    max_observations: usize,
+}
+
+impl Default for UniswapV2Oracle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UniswapV2Oracle {
+    pub fn new() -> Self {
+        Self { observations: VecDeque::new(), max_observations: DEFAULT_MAX_OBSERVATIONS }
+    }
+
+    /// Sets the number of observations to keep, dropping the oldest ones first.
+    pub fn with_max_observations(mut self, max_observations: usize) -> Self {
+        self.max_observations = max_observations.max(1);
+        self
+    }
+
+    /// Records the pair's accumulators as they read at `timestamp`.
+    ///
+    /// Observations must be recorded in order; one that isn't newer than the latest is ignored.
+    pub fn observe(&mut self, pair: &UniswapV2State, timestamp: u32) {
+        if let Some(latest) = self.observations.back() {
+            if timestamp <= latest.timestamp {
+                return;
+            }
+        }
+        let (price0_cumulative, price1_cumulative) = pair.current_cumulative_prices(timestamp);
+        self.observations
+            .push_back(Observation { timestamp, price0_cumulative, price1_cumulative });
+        while self.observations.len() > self.max_observations {
+            self.observations.pop_front();
+        }
+    }
+
+    pub fn observations(&self) -> &VecDeque<Observation> {
+        &self.observations
+    }
+
+    /// Returns the average price of token 0 in token 1 over at least the last `secs` seconds.
+    ///
+    /// The window ends at the latest observation, read from the current state `pair`, and starts
+    /// at the newest observation that is at least `secs` older.
+    pub fn twap(&self, pair: &UniswapV2State, secs: u32) -> Result<f64, SimulationError> {
+        let latest = self
+            .observations
+            .back()
+            .ok_or_else(|| SimulationError::RecoverableError("No observations".to_string()))?;
+        let now = latest.timestamp;
+        let start = self
+            .observations
+            .iter()
+            .rev()
+            .find(|observation| now - observation.timestamp >= secs.max(1))
+            .ok_or_else(|| {
+                SimulationError::RecoverableError(format!(
+                    "No observation at least {secs}s older than {now}"
+                ))
+            })?;
+        let (end, _) = pair.current_cumulative_prices(now);
+        let price = UniswapV2Twap::compute(start.price0_cumulative, end, now - start.timestamp)?;
+        Ok(UniswapV2Twap::to_f64(price))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_ulps_eq;
+    use rstest::rstest;
+
+    use super::*;
+
+    const PERIOD: u32 = 24 * 60 * 60;
+
+    fn e18(amount: u64) -> U256 {
+        U256::from(amount) * U256::from(10u64).pow(U256::from(18u64))
+    }
+
+    #[rstest]
+    #[case::one(U256::from(1u64) << 112, 1.0)]
+    #[case::half(U256::from(1u64) << 111, 0.5)]
+    #[case::two(U256::from(2u64) << 112, 2.0)]
+    fn test_to_f64(#[case] price: U256, #[case] expected: f64) {
+        assert_ulps_eq!(UniswapV2Twap::to_f64(price), expected);
+    }
+
+    #[test]
+    fn test_compute_wraps() {
+        let price = U256::from(3u64) << 112;
+        let start = U256::ZERO.wrapping_sub(price);
+
+        let twap = UniswapV2Twap::compute(start, start.wrapping_add(price * U256::from(9u64)), 10)
+            .unwrap();
+
+        assert_eq!(twap, price * U256::from(9u64) / U256::from(10u64));
+        assert!(UniswapV2Twap::compute(start, start, 0).is_err());
+    }
+
+    /// Replays the `ExampleOracleSimple` test of the periphery: 5 token0 against 10 token1
+    /// quote 5 token0 at 10 token1 after a period.
+    #[test]
+    fn test_example_oracle_simple() {
+        let t = 1_600_000_000;
+        let pair =
+            UniswapV2State::new(e18(5), e18(10)).with_cumulative_prices(U256::ZERO, U256::ZERO, t);
+        let mut oracle = UniswapV2Oracle::new();
+        oracle.observe(&pair, t);
+
+        assert!(oracle.twap(&pair, PERIOD).is_err());
+
+        oracle.observe(&pair, t + PERIOD);
+
+        assert_ulps_eq!(oracle.twap(&pair, PERIOD).unwrap() * 5.0, 10.0);
+    }
+
+    #[test]
+    fn test_twap_weights_prices_by_time() {
+        let t = 1_000;
+        let pair =
+            UniswapV2State::new(e18(1), e18(1)).with_cumulative_prices(U256::ZERO, U256::ZERO, t);
+        let mut oracle = UniswapV2Oracle::new().with_max_observations(2);
+        oracle.observe(&pair, t);
+        // price moves to 4 after 30 seconds
+        let (price0, price1) = pair.current_cumulative_prices(t + 30);
+        let pair =
+            UniswapV2State::new(e18(1), e18(4)).with_cumulative_prices(price0, price1, t + 30);
+        oracle.observe(&pair, t + 40);
+        oracle.observe(&pair, t + 40);
+
+        assert_eq!(oracle.observations().len(), 2);
+        assert_ulps_eq!(oracle.twap(&pair, 40).unwrap(), (30.0 + 4.0 * 10.0) / 40.0);
+    }
+}
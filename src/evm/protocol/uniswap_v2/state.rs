@@ -5,11 +5,14 @@ use num_bigint::{BigUint, ToBigUint};
 use num_traits::Zero;
 use tycho_common::{dto::ProtocolStateDelta, Bytes};
 
-use super::reserve_price::spot_price_from_reserves;
+use super::{reserve_price::spot_price_from_reserves, token_config::TokenConfigRegistry};
 use crate::{
-    evm::protocol::{
-        safe_math::{safe_add_u256, safe_div_u256, safe_mul_u256, safe_sub_u256},
-        u256_num::{biguint_to_u256, u256_to_biguint},
+    evm::{
+        protocol::{
+            safe_math::{div_u256, safe_add_u256, safe_mul_u256, safe_sub_u256, Rounding},
+            u256_num::{biguint_to_u256, u256_to_biguint},
+        },
+        storage_layout::UniswapV2Reserves,
     },
     models::{Balances, Token},
     protocol::{
@@ -19,10 +22,25 @@ use crate::{
     },
 };
 
+/// Share of the output reserve, in basis points, that `max_input` allows a swap to take.
+pub const DEFAULT_MAX_RESERVE_FRACTION_BPS: u32 = 9_000;
+
+/// Liquidity permanently locked by the pair on the first mint (`UniswapV2Pair.MINIMUM_LIQUIDITY`).
+pub const MINIMUM_LIQUIDITY: U256 = U256::from_limbs([1_000, 0, 0, 0]);
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct UniswapV2State {
     pub reserve0: U256,
     pub reserve1: U256,
+    /// Total supply of the pair's LP token. `None` if the supply is not indexed for this pool.
+    pub total_supply_lp: Option<U256>,
+    /// The pair's `price0CumulativeLast`: the UQ112.112 price of token 0 in token 1, summed over
+    /// every second up to `block_timestamp_last`. Wraps on overflow.
+    pub price0_cumulative_last: U256,
+    /// The pair's `price1CumulativeLast`, the same for the price of token 1 in token 0.
+    pub price1_cumulative_last: U256,
+    /// Timestamp, modulo 2^32, of the block in which the reserves were last updated.
+    pub block_timestamp_last: u32,
 }
 
 impl UniswapV2State {
@@ -33,8 +51,216 @@ impl UniswapV2State {
     /// * `reserve0` - Reserve of token 0.
     /// * `reserve1` - Reserve of token 1.
     pub fn new(reserve0: U256, reserve1: U256) -> Self {
-        UniswapV2State { reserve0, reserve1 }
+        UniswapV2State {
+            reserve0,
+            reserve1,
+            total_supply_lp: None,
+            price0_cumulative_last: U256::ZERO,
+            price1_cumulative_last: U256::ZERO,
+            block_timestamp_last: 0,
+        }
+    }
+
+    /// Sets the total supply of the pair's LP token.
+    pub fn with_total_supply_lp(mut self, total_supply_lp: U256) -> Self {
+        self.total_supply_lp = Some(total_supply_lp);
+        self
+    }
+
+    /// Sets the pair's price accumulators and the timestamp they were last updated at.
+    pub fn with_cumulative_prices(
+        mut self,
+        price0_cumulative_last: U256,
+        price1_cumulative_last: U256,
+        block_timestamp_last: u32,
+    ) -> Self {
+        self.price0_cumulative_last = price0_cumulative_last;
+        self.price1_cumulative_last = price1_cumulative_last;
+        self.block_timestamp_last = block_timestamp_last;
+        self
+    }
+
+    /// Returns the price accumulators as they would read at `timestamp`, mirroring
+    /// `UniswapV2OracleLibrary.currentCumulativePrices`.
+    ///
+    /// The current prices are added for every second since `block_timestamp_last`, as the pair
+    /// would do on its next update. Both the elapsed time and the accumulators wrap like they do
+    /// on chain, so the difference of two readings stays correct across an overflow.
+    pub fn current_cumulative_prices(&self, timestamp: u32) -> (U256, U256) {
+        let time_elapsed = U256::from(timestamp.wrapping_sub(self.block_timestamp_last));
+        let mut price0_cumulative = self.price0_cumulative_last;
+        let mut price1_cumulative = self.price1_cumulative_last;
+        if time_elapsed > U256::ZERO && self.reserve0 > U256::ZERO && self.reserve1 > U256::ZERO {
+            // UQ112x112.encode(reserve1).uqdiv(reserve0)
+            let price0 = (self.reserve1 << 112) / self.reserve0;
+            let price1 = (self.reserve0 << 112) / self.reserve1;
+            price0_cumulative = price0_cumulative.wrapping_add(price0.wrapping_mul(time_elapsed));
+            price1_cumulative = price1_cumulative.wrapping_add(price1.wrapping_mul(time_elapsed));
+        }
+        (price0_cumulative, price1_cumulative)
+    }
+
+    /// Returns the input that buys `fraction_bps` basis points of the output reserve.
+    ///
+    /// Inverts the constant product formula including the 0.3% fee and rounds down, so swapping
+    /// the result never takes more than the requested fraction.
+    pub fn max_input_for_reserve_fraction(
+        &self,
+        zero2one: bool,
+        fraction_bps: u32,
+    ) -> Result<U256, SimulationError> {
+        if fraction_bps == 0 || fraction_bps >= 10_000 {
+            return Err(SimulationError::InvalidInput(
+                format!("Reserve fraction must be between 0 and 10000 bps, got {fraction_bps}"),
+                None,
+            ));
+        }
+        let reserve_sell = if zero2one { self.reserve0 } else { self.reserve1 };
+        if reserve_sell == U256::from(0u64) {
+            return Err(SimulationError::RecoverableError("No liquidity".to_string()));
+        }
+        // amount_out = f * reserve_buy  <=>  amount_in * 997 = f / (1 - f) * reserve_sell * 1000
+        let numerator = safe_mul_u256(
+            safe_mul_u256(reserve_sell, U256::from(fraction_bps))?,
+            U256::from(1000),
+        )?;
+        let denominator = safe_mul_u256(U256::from(10_000 - fraction_bps), U256::from(997))?;
+        div_u256(numerator, denominator, Rounding::Down)
+    }
+
+    fn known_total_supply_lp(&self) -> Result<U256, SimulationError> {
+        self.total_supply_lp.ok_or_else(|| {
+            SimulationError::InvalidInput("LP total supply is not indexed".to_string(), None)
+        })
     }
+
+    /// Returns the amounts of token 0 and token 1 that burning `lp_amount` LP tokens would
+    /// return, mirroring `UniswapV2Pair.burn`. Fails if the total supply isn't indexed.
+    pub fn lp_value(&self, lp_amount: U256) -> Result<(U256, U256), SimulationError> {
+        let total_supply = self.known_total_supply_lp()?;
+        if total_supply == U256::ZERO {
+            return Err(SimulationError::InvalidInput("LP total supply is zero".to_string(), None));
+        }
+        if lp_amount > total_supply {
+            return Err(SimulationError::InvalidInput(
+                format!("LP amount {lp_amount} exceeds the total supply {total_supply}"),
+                None,
+            ));
+        }
+        let amount0 =
+            div_u256(safe_mul_u256(lp_amount, self.reserve0)?, total_supply, Rounding::Down)?;
+        let amount1 =
+            div_u256(safe_mul_u256(lp_amount, self.reserve1)?, total_supply, Rounding::Down)?;
+        Ok((amount0, amount1))
+    }
+
+    /// Returns the LP tokens minted for depositing `amount0` and `amount1`, mirroring
+    /// `UniswapV2Pair.mint`.
+    ///
+    /// The first mint receives `sqrt(amount0 * amount1) - MINIMUM_LIQUIDITY`, later mints
+    /// `min(amount0 / reserve0, amount1 / reserve1) * total_supply`. Any excess of one token over
+    /// the pool's ratio is donated to the pool. Fails if the total supply isn't indexed.
+    pub fn lp_to_mint(&self, amount0: U256, amount1: U256) -> Result<U256, SimulationError> {
+        let total_supply = self.known_total_supply_lp()?;
+        let liquidity = if total_supply == U256::ZERO {
+            sqrt_u256(safe_mul_u256(amount0, amount1)?).saturating_sub(MINIMUM_LIQUIDITY)
+        } else {
+            let liquidity0 =
+                div_u256(safe_mul_u256(amount0, total_supply)?, self.reserve0, Rounding::Down)?;
+            let liquidity1 =
+                div_u256(safe_mul_u256(amount1, total_supply)?, self.reserve1, Rounding::Down)?;
+            liquidity0.min(liquidity1)
+        };
+        if liquidity == U256::ZERO {
+            return Err(SimulationError::InvalidInput(
+                "Insufficient liquidity minted".to_string(),
+                None,
+            ));
+        }
+        Ok(liquidity)
+    }
+
+    /// Computes the amount out of a swap and the state after it.
+    ///
+    /// `amount_in` is the amount actually received by the pool.
+    fn swap(&self, amount_in: U256, zero2one: bool) -> Result<(U256, Self), SimulationError> {
+        let reserve_sell = if zero2one { self.reserve0 } else { self.reserve1 };
+        let reserve_buy = if zero2one { self.reserve1 } else { self.reserve0 };
+
+        if reserve_sell == U256::from(0u64) || reserve_buy == U256::from(0u64) {
+            return Err(SimulationError::RecoverableError("No liquidity".to_string()));
+        }
+
+        let amount_in_with_fee = safe_mul_u256(amount_in, U256::from(997))?;
+        let numerator = safe_mul_u256(amount_in_with_fee, reserve_buy)?;
+        let denominator =
+            safe_add_u256(safe_mul_u256(reserve_sell, U256::from(1000))?, amount_in_with_fee)?;
+
+        let amount_out = div_u256(numerator, denominator, Rounding::Down)?;
+        let mut new_state = self.clone();
+        if zero2one {
+            new_state.reserve0 = safe_add_u256(self.reserve0, amount_in)?;
+            new_state.reserve1 = safe_sub_u256(self.reserve1, amount_out)?;
+        } else {
+            new_state.reserve0 = safe_sub_u256(self.reserve0, amount_out)?;
+            new_state.reserve1 = safe_add_u256(self.reserve1, amount_in)?;
+        };
+        Ok((amount_out, new_state))
+    }
+
+    /// Like `get_amount_out`, but accounts for tokens that charge a tax on transfer.
+    ///
+    /// The tax of the sell token is deducted from `amount_in` before it reaches the pool, and the
+    /// tax of the buy token is deducted from the amount the pool sends out. The returned amount is
+    /// what the recipient actually receives.
+    pub fn get_amount_out_with_config(
+        &self,
+        amount_in: BigUint,
+        token_in: &Token,
+        token_out: &Token,
+        config: &TokenConfigRegistry,
+    ) -> Result<GetAmountOutResult, SimulationError> {
+        let amount_in = biguint_to_u256(&amount_in);
+        if amount_in == U256::from(0u64) {
+            return Err(SimulationError::InvalidInput("Amount in cannot be zero".to_string(), None));
+        }
+        let zero2one = token_in.address < token_out.address;
+
+        let received_by_pool = config.apply_tax(&token_in.address, amount_in)?;
+        let (amount_out, new_state) = self.swap(received_by_pool, zero2one)?;
+        let received = config.apply_tax(&token_out.address, amount_out)?;
+
+        Ok(GetAmountOutResult::new(
+            u256_to_biguint(received),
+            120_000
+                .to_biguint()
+                .expect("Expected an unsigned integer as gas value"),
+            Box::new(new_state),
+        ))
+    }
+}
+
+/// A state from the pair's reserves slot, e.g. as read from a simulated database.
+impl From<UniswapV2Reserves> for UniswapV2State {
+    fn from(reserves: UniswapV2Reserves) -> Self {
+        let mut state = Self::new(reserves.reserve0, reserves.reserve1);
+        state.block_timestamp_last = reserves.block_timestamp_last;
+        state
+    }
+}
+
+/// Integer square root, rounded down (Babylonian method, as in Uniswap's `Math.sqrt`).
+pub(crate) fn sqrt_u256(y: U256) -> U256 {
+    if y <= U256::from(3u64) {
+        return if y == U256::ZERO { U256::ZERO } else { U256::from(1u64) };
+    }
+    let mut z = y;
+    let mut x = y / U256::from(2u64) + U256::from(1u64);
+    while x < z {
+        z = x;
+        x = (y / x + x) / U256::from(2u64);
+    }
+    z
 }
 
 impl ProtocolSim for UniswapV2State {
@@ -71,27 +297,7 @@ impl ProtocolSim for UniswapV2State {
             return Err(SimulationError::InvalidInput("Amount in cannot be zero".to_string(), None));
         }
         let zero2one = token_in.address < token_out.address;
-        let reserve_sell = if zero2one { self.reserve0 } else { self.reserve1 };
-        let reserve_buy = if zero2one { self.reserve1 } else { self.reserve0 };
-
-        if reserve_sell == U256::from(0u64) || reserve_buy == U256::from(0u64) {
-            return Err(SimulationError::RecoverableError("No liquidity".to_string()));
-        }
-
-        let amount_in_with_fee = safe_mul_u256(amount_in, U256::from(997))?;
-        let numerator = safe_mul_u256(amount_in_with_fee, reserve_buy)?;
-        let denominator =
-            safe_add_u256(safe_mul_u256(reserve_sell, U256::from(1000))?, amount_in_with_fee)?;
-
-        let amount_out = safe_div_u256(numerator, denominator)?;
-        let mut new_state = self.clone();
-        if zero2one {
-            new_state.reserve0 = safe_add_u256(self.reserve0, amount_in)?;
-            new_state.reserve1 = safe_sub_u256(self.reserve1, amount_out)?;
-        } else {
-            new_state.reserve0 = safe_sub_u256(self.reserve0, amount_out)?;
-            new_state.reserve1 = safe_add_u256(self.reserve1, amount_in)?;
-        };
+        let (amount_out, new_state) = self.swap(amount_in, zero2one)?;
         Ok(GetAmountOutResult::new(
             u256_to_biguint(amount_out),
             120_000
@@ -124,7 +330,7 @@ impl ProtocolSim for UniswapV2State {
         //
         // This resolves into x = (√10 - 1) × reserve0 = 2.16 × reserve0
         let amount_in =
-            safe_div_u256(safe_mul_u256(reserve_in, U256::from(216))?, U256::from(100))?;
+            div_u256(safe_mul_u256(reserve_in, U256::from(216))?, U256::from(100), Rounding::Down)?;
 
         // Calculate amount_out using the constant product formula
         // The constant product formula requires:
@@ -133,14 +339,44 @@ impl ProtocolSim for UniswapV2State {
         // amount_out = reserve_out - (reserve_in * reserve_out) (reserve_in + amount_in)
         // which simplifies to:
         // amount_out = (reserve_out * amount_in) / (reserve_in + amount_in)
-        let amount_out = safe_div_u256(
+        let amount_out = div_u256(
             safe_mul_u256(reserve_out, amount_in)?,
             safe_add_u256(reserve_in, amount_in)?,
+            Rounding::Down,
         )?;
 
         Ok((u256_to_biguint(amount_in), u256_to_biguint(amount_out)))
     }
 
+    /// The input that takes `DEFAULT_MAX_RESERVE_FRACTION_BPS` of the output reserve, see
+    /// [`UniswapV2State::max_input_for_reserve_fraction`] for other fractions.
+    fn max_input(&self, token_in: &Token, token_out: &Token) -> Result<U256, SimulationError> {
+        if self.reserve0 == U256::from(0u64) || self.reserve1 == U256::from(0u64) {
+            return Err(SimulationError::RecoverableError("No liquidity".to_string()));
+        }
+        self.max_input_for_reserve_fraction(
+            token_in.address < token_out.address,
+            DEFAULT_MAX_RESERVE_FRACTION_BPS,
+        )
+    }
+
+    fn dump(&self) -> Option<Vec<u8>> {
+        let mut out = Vec::with_capacity(5 * 32 + 5);
+        // an unknown supply and an empty pool's supply of zero must not encode the same
+        out.push(u8::from(self.total_supply_lp.is_some()));
+        for value in [
+            self.reserve0,
+            self.reserve1,
+            self.total_supply_lp.unwrap_or_default(),
+            self.price0_cumulative_last,
+            self.price1_cumulative_last,
+        ] {
+            out.extend_from_slice(&value.to_be_bytes::<32>());
+        }
+        out.extend_from_slice(&self.block_timestamp_last.to_be_bytes());
+        Some(out)
+    }
+
     fn delta_transition(
         &mut self,
         delta: ProtocolStateDelta,
@@ -161,6 +397,13 @@ impl ProtocolSim for UniswapV2State {
                 .get("reserve1")
                 .ok_or(TransitionError::MissingAttribute("reserve1".to_string()))?,
         );
+        // the LP supply is optional, it is only updated if the pool's indexer tracks it
+        if let Some(total_supply) = delta
+            .updated_attributes
+            .get("total_supply")
+        {
+            self.total_supply_lp = Some(U256::from_be_slice(total_supply));
+        }
         Ok(())
     }
 
@@ -200,6 +443,10 @@ mod tests {
     use tycho_common::hex_bytes::Bytes;
 
     use super::*;
+    use crate::{
+        evm::protocol::uniswap_v2::token_config::TokenConfig,
+        protocol::conformance::{run_conformance_suite, ConformanceSpec, ZeroAmountPolicy},
+    };
 
     #[rstest]
     #[case::same_dec(
@@ -257,6 +504,79 @@ mod tests {
         assert_eq!(state.reserve1, r1);
     }
 
+    #[rstest]
+    #[case::one_percent(100, "976196165099162173958")]
+    #[case::five_percent(500, "898941067967474606426")]
+    #[case::ten_percent(1000, "806846017068884090304")]
+    fn test_get_amount_out_with_transfer_tax(#[case] tax_bps: u32, #[case] exp: &str) {
+        let t0 = Token::new(
+            "0x0000000000000000000000000000000000000000",
+            18,
+            "T0",
+            10_000.to_biguint().unwrap(),
+        );
+        let t1 = Token::new(
+            "0x0000000000000000000000000000000000000001",
+            18,
+            "T1",
+            10_000.to_biguint().unwrap(),
+        );
+        let mut config = TokenConfigRegistry::new();
+        config.insert(TokenConfig::new(t0.address.clone(), tax_bps).unwrap());
+        config.insert(TokenConfig::new(t1.address.clone(), tax_bps).unwrap());
+        let reserve = U256::from_str("1000000000000000000000000").unwrap();
+        let amount_in = U256::from_str("1000000000000000000000").unwrap();
+        let state = UniswapV2State::new(reserve, reserve);
+
+        let res = state
+            .get_amount_out_with_config(u256_to_biguint(amount_in), &t0, &t1, &config)
+            .unwrap();
+
+        assert_eq!(res.amount, BigUint::from_str(exp).unwrap());
+        let new_state = res
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV2State>()
+            .unwrap();
+        // The pool only receives the taxed input amount
+        let received_by_pool = amount_in * U256::from(10_000 - tax_bps) / U256::from(10_000);
+        assert_eq!(new_state.reserve0, reserve + received_by_pool);
+        assert!(new_state.reserve1 < reserve - biguint_to_u256(&res.amount));
+    }
+
+    #[test]
+    fn test_get_amount_out_with_config_without_tax() {
+        let t0 = Token::new(
+            "0x0000000000000000000000000000000000000000",
+            18,
+            "T0",
+            10_000.to_biguint().unwrap(),
+        );
+        let t1 = Token::new(
+            "0x0000000000000000000000000000000000000001",
+            6,
+            "T1",
+            10_000.to_biguint().unwrap(),
+        );
+        let state = UniswapV2State::new(
+            U256::from_str("33372357002392258830279").unwrap(),
+            U256::from_str("43356945776493").unwrap(),
+        );
+        let amount_in = BigUint::from_str("10000000000000000000").unwrap();
+
+        let res = state
+            .get_amount_out_with_config(amount_in.clone(), &t0, &t1, &TokenConfigRegistry::new())
+            .unwrap();
+
+        assert_eq!(
+            res.amount,
+            state
+                .get_amount_out(amount_in, &t0, &t1)
+                .unwrap()
+                .amount
+        );
+    }
+
     #[test]
     fn test_get_amount_out_overflow() {
         let r0 = U256::from_str("33372357002392258830279").unwrap();
@@ -314,6 +634,74 @@ mod tests {
         assert_ulps_eq!(res, exp);
     }
 
+    #[test]
+    fn test_from_reserves() {
+        let reserves = UniswapV2Reserves {
+            reserve0: U256::from(1_000u64),
+            reserve1: U256::from(2_000u64),
+            block_timestamp_last: 1_700_000_000,
+        };
+
+        let state = UniswapV2State::from(UniswapV2Reserves::unpack(reserves.pack()));
+
+        assert_eq!(
+            state,
+            UniswapV2State::new(U256::from(1_000u64), U256::from(2_000u64)).with_cumulative_prices(
+                U256::ZERO,
+                U256::ZERO,
+                1_700_000_000
+            )
+        );
+    }
+
+    fn encode_price(reserve0: U256, reserve1: U256) -> (U256, U256) {
+        ((reserve1 << 112) / reserve0, (reserve0 << 112) / reserve1)
+    }
+
+    /// Replays the `price{0,1}CumulativeLast` test of `UniswapV2Pair.spec.ts`.
+    #[test]
+    fn test_current_cumulative_prices() {
+        let e18 = U256::from(10u64).pow(U256::from(18u64));
+        let t = 1_000;
+        let state = UniswapV2State::new(U256::from(3u64) * e18, U256::from(3u64) * e18)
+            .with_cumulative_prices(U256::ZERO, U256::ZERO, t);
+        let initial_price = encode_price(state.reserve0, state.reserve1);
+
+        assert_eq!(state.current_cumulative_prices(t), (U256::ZERO, U256::ZERO));
+        assert_eq!(state.current_cumulative_prices(t + 1), initial_price);
+
+        // swap 3 token0 for 1 token1 at t + 10
+        let (price0, price1) = state.current_cumulative_prices(t + 10);
+        assert_eq!(price0, initial_price.0 * U256::from(10u64));
+        assert_eq!(price1, initial_price.1 * U256::from(10u64));
+        let state = UniswapV2State::new(U256::from(6u64) * e18, U256::from(2u64) * e18)
+            .with_cumulative_prices(price0, price1, t + 10);
+        let new_price = encode_price(state.reserve0, state.reserve1);
+
+        assert_eq!(
+            state.current_cumulative_prices(t + 20),
+            (
+                initial_price.0 * U256::from(10u64) + new_price.0 * U256::from(10u64),
+                initial_price.1 * U256::from(10u64) + new_price.1 * U256::from(10u64)
+            )
+        );
+    }
+
+    #[test]
+    fn test_current_cumulative_prices_wraps() {
+        let state = UniswapV2State::new(U256::from(1u64), U256::from(1u64)).with_cumulative_prices(
+            U256::MAX,
+            U256::MAX,
+            u32::MAX,
+        );
+
+        let (price0, price1) = state.current_cumulative_prices(1);
+
+        // two seconds elapsed across the timestamp overflow, at a price of 1
+        let expected = (U256::from(1u64) << 113) - U256::from(1u64);
+        assert_eq!((price0, price1), (expected, expected));
+    }
+
     #[test]
     fn test_fee() {
         let state = UniswapV2State::new(
@@ -420,4 +808,269 @@ mod tests {
         let expected_price = initial_price / 10.0;
         assert!(expected_price == new_price, "Price impact not 90%.");
     }
+
+    #[test]
+    fn test_delta_transition_total_supply() {
+        let mut state =
+            UniswapV2State::new(U256::from_str("1000").unwrap(), U256::from_str("1000").unwrap())
+                .with_total_supply_lp(U256::from(1000u64));
+        let attributes: HashMap<String, Bytes> = vec![
+            ("reserve0".to_string(), Bytes::from(1500_u64.to_be_bytes().to_vec())),
+            ("reserve1".to_string(), Bytes::from(1500_u64.to_be_bytes().to_vec())),
+            ("total_supply".to_string(), Bytes::from(1500_u64.to_be_bytes().to_vec())),
+        ]
+        .into_iter()
+        .collect();
+        let delta = ProtocolStateDelta {
+            component_id: "State1".to_owned(),
+            updated_attributes: attributes,
+            deleted_attributes: HashSet::new(),
+        };
+
+        state
+            .delta_transition(delta, &HashMap::new(), &Balances::default())
+            .unwrap();
+
+        assert_eq!(state.total_supply_lp, Some(U256::from(1500u64)));
+    }
+
+    #[test]
+    fn test_lp_to_mint_first_mint() {
+        let state = UniswapV2State::new(U256::ZERO, U256::ZERO).with_total_supply_lp(U256::ZERO);
+
+        let minted = state
+            .lp_to_mint(U256::from(4_000_000u64), U256::from(1_000_000u64))
+            .unwrap();
+
+        // sqrt(4e6 * 1e6) - 1000
+        assert_eq!(minted, U256::from(1_999_000u64));
+    }
+
+    #[test]
+    fn test_lp_to_mint_below_minimum_liquidity() {
+        let state = UniswapV2State::new(U256::ZERO, U256::ZERO).with_total_supply_lp(U256::ZERO);
+
+        let res = state.lp_to_mint(U256::from(1_000u64), U256::from(1_000u64));
+
+        assert!(matches!(res, Err(SimulationError::InvalidInput(_, _))));
+    }
+
+    #[test]
+    fn test_lp_to_mint_takes_minimum_ratio() {
+        let state = UniswapV2State::new(U256::from(1_000_000u64), U256::from(2_000_000u64))
+            .with_total_supply_lp(U256::from(1_000_000u64));
+
+        // 10% of token 0, but only 5% of token 1
+        let minted = state
+            .lp_to_mint(U256::from(100_000u64), U256::from(100_000u64))
+            .unwrap();
+
+        assert_eq!(minted, U256::from(50_000u64));
+    }
+
+    #[test]
+    fn test_unknown_total_supply() {
+        let state = UniswapV2State::new(U256::from(1_000u64), U256::from(1_000u64));
+
+        assert!(matches!(
+            state.lp_value(U256::from(1u64)),
+            Err(SimulationError::InvalidInput(_, _))
+        ));
+        assert!(matches!(
+            state.lp_to_mint(U256::from(1u64), U256::from(1u64)),
+            Err(SimulationError::InvalidInput(_, _))
+        ));
+        assert_ne!(
+            state.dump(),
+            state
+                .clone()
+                .with_total_supply_lp(U256::ZERO)
+                .dump()
+        );
+    }
+
+    #[test]
+    fn test_lp_value_exceeds_supply() {
+        let state = UniswapV2State::new(U256::from(1_000u64), U256::from(1_000u64))
+            .with_total_supply_lp(U256::from(1_000u64));
+
+        assert!(state
+            .lp_value(U256::from(1_001u64))
+            .is_err());
+    }
+
+    /// Deposits `amount0` and `amount1`, then burns the minted LP tokens again.
+    fn mint_then_burn(state: &UniswapV2State, amount0: U256, amount1: U256) -> (U256, U256) {
+        let minted = state
+            .lp_to_mint(amount0, amount1)
+            .unwrap();
+        let total_supply = state.total_supply_lp.unwrap();
+        let total_supply = if total_supply == U256::ZERO {
+            minted + MINIMUM_LIQUIDITY
+        } else {
+            total_supply + minted
+        };
+        let after_mint = UniswapV2State::new(state.reserve0 + amount0, state.reserve1 + amount1)
+            .with_total_supply_lp(total_supply);
+        after_mint.lp_value(minted).unwrap()
+    }
+
+    #[rstest]
+    #[case::small_pool("1000000", "2000000", "1414213", "1000", "2000")]
+    #[case::odd_ratio(
+        "33372357002392258830279",
+        "43356945776493",
+        "1202844752718",
+        "3337235700239225",
+        "4335694"
+    )]
+    #[case::large_deposit(
+        "5124813135806900540214",
+        "6770398782322527849696614",
+        "186299287718830215013585",
+        "5124813135806900540214",
+        "6770398782322527849696614"
+    )]
+    #[case::supply_above_reserves("1000", "3000", "1000000000", "7", "21")]
+    fn test_mint_then_burn_returns_deposit(
+        #[case] reserve0: &str,
+        #[case] reserve1: &str,
+        #[case] total_supply: &str,
+        #[case] amount0: &str,
+        #[case] amount1: &str,
+    ) {
+        let state = UniswapV2State::new(
+            U256::from_str(reserve0).unwrap(),
+            U256::from_str(reserve1).unwrap(),
+        )
+        .with_total_supply_lp(U256::from_str(total_supply).unwrap());
+        let (amount0, amount1) =
+            (U256::from_str(amount0).unwrap(), U256::from_str(amount1).unwrap());
+
+        let (out0, out1) = mint_then_burn(&state, amount0, amount1);
+
+        // Rounding always favours the pool, by at most the value of one LP token plus one wei.
+        let total_supply = state.total_supply_lp.unwrap();
+        let lp_token_value0 = state.reserve0 / total_supply + U256::from(1u64);
+        let lp_token_value1 = state.reserve1 / total_supply + U256::from(1u64);
+        assert!(out0 <= amount0 && amount0 - out0 <= lp_token_value0, "{out0} != {amount0}");
+        assert!(out1 <= amount1 && amount1 - out1 <= lp_token_value1, "{out1} != {amount1}");
+    }
+
+    #[rstest]
+    #[case::equal(1_000_000, 1_000_000)]
+    #[case::skewed(4_000_000, 25_000)]
+    fn test_first_mint_then_burn_locks_minimum_liquidity(
+        #[case] amount0: u64,
+        #[case] amount1: u64,
+    ) {
+        let state = UniswapV2State::new(U256::ZERO, U256::ZERO).with_total_supply_lp(U256::ZERO);
+        let (amount0, amount1) = (U256::from(amount0), U256::from(amount1));
+
+        let (out0, out1) = mint_then_burn(&state, amount0, amount1);
+
+        // The locked MINIMUM_LIQUIDITY keeps its share of the deposit in the pool.
+        let liquidity = sqrt_u256(amount0 * amount1);
+        assert_eq!(out0, amount0 * (liquidity - MINIMUM_LIQUIDITY) / liquidity);
+        assert_eq!(out1, amount1 * (liquidity - MINIMUM_LIQUIDITY) / liquidity);
+    }
+
+    #[test]
+    fn test_imbalanced_mint_then_burn_never_returns_more() {
+        let state = UniswapV2State::new(U256::from(1_000_000u64), U256::from(2_000_000u64))
+            .with_total_supply_lp(U256::from(1_000_000u64));
+
+        let (out0, out1) = mint_then_burn(&state, U256::from(100_000u64), U256::from(100_000u64));
+
+        // Only the proportional part of the deposit is credited, the excess token 0 is donated.
+        assert!(out0 < U256::from(100_000u64));
+        assert!(out1 <= U256::from(100_000u64));
+    }
+
+    #[rstest]
+    #[case::small_pool("1000", "100000", 9_000)]
+    #[case::tiny_fraction("33372357002392258830279", "43356945776493", 1)]
+    #[case::half("6770398782322527849696614", "5124813135806900540214", 5_000)]
+    #[case::almost_all("36925554990922", "30314846538607556521556", 9_999)]
+    fn test_max_input_for_reserve_fraction(
+        #[case] r0: &str,
+        #[case] r1: &str,
+        #[case] fraction_bps: u32,
+    ) {
+        let (r0, r1) = (U256::from_str(r0).unwrap(), U256::from_str(r1).unwrap());
+        let state = UniswapV2State::new(r0, r1);
+
+        for zero2one in [true, false] {
+            let reserve_buy = if zero2one { r1 } else { r0 };
+            let max_input = state
+                .max_input_for_reserve_fraction(zero2one, fraction_bps)
+                .unwrap();
+
+            let (amount_out, _) = state.swap(max_input, zero2one).unwrap();
+
+            let max_out = reserve_buy * U256::from(fraction_bps) / U256::from(10_000);
+            assert!(amount_out <= max_out, "{amount_out} > {max_out}");
+        }
+    }
+
+    #[test]
+    fn test_max_input_is_swappable() {
+        let t0 = Token::new(
+            "0x0000000000000000000000000000000000000000",
+            18,
+            "T0",
+            10_000.to_biguint().unwrap(),
+        );
+        let t1 = Token::new(
+            "0x0000000000000000000000000000000000000001",
+            18,
+            "T1",
+            10_000.to_biguint().unwrap(),
+        );
+        let state =
+            UniswapV2State::new(U256::from_str("1000").unwrap(), U256::from_str("100000").unwrap());
+
+        let max_input = state.max_input(&t0, &t1).unwrap();
+
+        let res = state
+            .get_amount_out(u256_to_biguint(max_input), &t0, &t1)
+            .unwrap();
+        assert!(res.amount <= BigUint::from(90_000u32));
+        assert!(UniswapV2State::new(U256::ZERO, U256::ZERO)
+            .max_input(&t0, &t1)
+            .is_err());
+    }
+
+    #[test]
+    fn test_conformance() {
+        let token_x = Token::new(
+            "0x0000000000000000000000000000000000000001",
+            18,
+            "X",
+            10_000.to_biguint().unwrap(),
+        );
+        let token_y = Token::new(
+            "0x0000000000000000000000000000000000000002",
+            6,
+            "Y",
+            10_000.to_biguint().unwrap(),
+        );
+        let amounts = ["1000000000000000", "1000000000000000000", "100000000000000000000"]
+            .map(|amount| BigUint::from_str(amount).unwrap())
+            .to_vec();
+        let spec = ConformanceSpec::new(token_x, token_y, amounts)
+            .with_zero_amount(ZeroAmountPolicy::Rejects);
+
+        let report = run_conformance_suite(
+            || {
+                UniswapV2State::new(
+                    U256::from_str("1000000000000000000000000").unwrap(),
+                    U256::from_str("2000000000000").unwrap(),
+                )
+            },
+            spec,
+        );
+
+        report.assert_ok();
+    }
 }
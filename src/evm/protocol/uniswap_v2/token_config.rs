@@ -0,0 +1,148 @@
+//! Per-token configuration for fee-on-transfer tokens.
+use std::collections::HashMap;
+
+use alloy_primitives::U256;
+use tycho_common::Bytes;
+
+use crate::{
+    evm::protocol::safe_math::{div_u256, safe_mul_u256, Rounding},
+    protocol::errors::SimulationError,
+};
+
+const BPS_DENOMINATOR: u32 = 10_000;
+
+/// Configuration of a single token.
+///
+/// `transfer_tax_bps` is the share of every transfer the token withholds, in basis points. E.g.
+/// a token with a 1% tax has `transfer_tax_bps = 100`, so a recipient of a transfer of `x` ends
+/// up with `x * 0.99`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenConfig {
+    pub address: Bytes,
+    /// At most 10_000, which `apply_tax` relies on.
+    transfer_tax_bps: u32,
+}
+
+impl TokenConfig {
+    pub fn new(address: Bytes, transfer_tax_bps: u32) -> Result<Self, SimulationError> {
+        validate_tax(transfer_tax_bps)?;
+        Ok(Self { address, transfer_tax_bps })
+    }
+
+    pub fn transfer_tax_bps(&self) -> u32 {
+        self.transfer_tax_bps
+    }
+
+    /// Changes the transfer tax, failing like [`Self::new`] if it exceeds 100%.
+    pub fn set_transfer_tax_bps(&mut self, transfer_tax_bps: u32) -> Result<(), SimulationError> {
+        validate_tax(transfer_tax_bps)?;
+        self.transfer_tax_bps = transfer_tax_bps;
+        Ok(())
+    }
+
+    /// Returns the amount a recipient receives if `amount` is transferred.
+    ///
+    /// Rounds down, the conservative direction: tokens that round the withheld tax down deliver
+    /// up to one wei more.
+    pub fn apply_tax(&self, amount: U256) -> Result<U256, SimulationError> {
+        div_u256(
+            safe_mul_u256(amount, U256::from(BPS_DENOMINATOR - self.transfer_tax_bps))?,
+            U256::from(BPS_DENOMINATOR),
+            Rounding::Down,
+        )
+    }
+}
+
+fn validate_tax(transfer_tax_bps: u32) -> Result<(), SimulationError> {
+    if transfer_tax_bps > BPS_DENOMINATOR {
+        return Err(SimulationError::InvalidInput(
+            format!("Transfer tax of {transfer_tax_bps} bps exceeds 100%"),
+            None,
+        ));
+    }
+    Ok(())
+}
+
+/// A lookup of token configurations by token address.
+///
+/// Tokens without a configuration are assumed to transfer without tax.
+#[derive(Debug, Clone, Default)]
+pub struct TokenConfigRegistry {
+    configs: HashMap<Bytes, TokenConfig>,
+}
+
+impl TokenConfigRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or replaces the configuration of a token.
+    pub fn insert(&mut self, config: TokenConfig) {
+        self.configs
+            .insert(config.address.clone(), config);
+    }
+
+    pub fn get(&self, address: &Bytes) -> Option<&TokenConfig> {
+        self.configs.get(address)
+    }
+
+    /// Returns the amount a recipient receives if `amount` of the given token is transferred.
+    pub fn apply_tax(&self, address: &Bytes, amount: U256) -> Result<U256, SimulationError> {
+        match self.get(address) {
+            Some(config) => config.apply_tax(amount),
+            None => Ok(amount),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_config_rejects_tax_above_100_percent() {
+        let res = TokenConfig::new(Bytes::from("0x01"), 10_001);
+
+        assert!(matches!(res, Err(SimulationError::InvalidInput(_, None))));
+    }
+
+    #[test]
+    fn test_set_transfer_tax_bps() {
+        let mut config = TokenConfig::new(Bytes::from("0x01"), 100).unwrap();
+
+        assert!(config
+            .set_transfer_tax_bps(10_001)
+            .is_err());
+        assert_eq!(config.transfer_tax_bps(), 100);
+
+        config
+            .set_transfer_tax_bps(10_000)
+            .unwrap();
+        assert_eq!(
+            config
+                .apply_tax(U256::from(1_000))
+                .unwrap(),
+            U256::ZERO
+        );
+    }
+
+    #[test]
+    fn test_registry_apply_tax() {
+        let taxed = Bytes::from("0x01");
+        let mut registry = TokenConfigRegistry::new();
+        registry.insert(TokenConfig::new(taxed.clone(), 250).unwrap());
+
+        assert_eq!(
+            registry
+                .apply_tax(&taxed, U256::from(10_000))
+                .unwrap(),
+            U256::from(9_750)
+        );
+        assert_eq!(
+            registry
+                .apply_tax(&Bytes::from("0x02"), U256::from(10_000))
+                .unwrap(),
+            U256::from(10_000)
+        );
+    }
+}
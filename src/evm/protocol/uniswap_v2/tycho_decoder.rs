@@ -37,7 +37,16 @@ impl TryFromWithBlock<ComponentWithState> for UniswapV2State {
                 .ok_or(InvalidSnapshotError::MissingAttribute("reserve1".to_string()))?,
         );
 
-        Ok(UniswapV2State::new(reserve0, reserve1))
+        let total_supply = snapshot
+            .state
+            .attributes
+            .get("total_supply")
+            .map(|value| U256::from_be_slice(value));
+
+        Ok(UniswapV2State {
+            total_supply_lp: total_supply,
+            ..UniswapV2State::new(reserve0, reserve1)
+        })
     }
 }
 
@@ -0,0 +1,178 @@
+//! ABI codec for Uniswap V3 pool view calls.
+use alloy_primitives::U256;
+use alloy_sol_types::SolValue;
+
+use crate::evm::protocol::abi::{selector, AbiError};
+
+/// `slot0()` return values, in order. Narrower Solidity types are decoded into the next wider
+/// Rust type, e.g. `int24` into `i32`.
+type Slot0Return = (U256, i32, u16, u16, u16, u8, bool);
+
+/// The values packed into a Uniswap V3 pool's `slot0`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot0 {
+    pub sqrt_price_x96: U256,
+    pub tick: i32,
+    pub observation_index: u16,
+    pub observation_cardinality: u16,
+    pub observation_cardinality_next: u16,
+    pub fee_protocol: u8,
+    pub unlocked: bool,
+}
+
+/// Encodes and decodes calls to a `UniswapV3Pool` without simulating them.
+pub struct UniswapV3Abi;
+
+impl UniswapV3Abi {
+    pub const SLOT0: &'static str = "slot0()";
+
+    /// Calldata of `slot0()`.
+    pub fn encode_slot0() -> Vec<u8> {
+        selector(Self::SLOT0).to_vec()
+    }
+
+    /// Decodes the return data of `slot0()`.
+    pub fn decode_slot0(data: &[u8]) -> Result<Slot0, AbiError> {
+        let (
+            sqrt_price_x96,
+            tick,
+            observation_index,
+            observation_cardinality,
+            observation_cardinality_next,
+            fee_protocol,
+            unlocked,
+        ) = Slot0Return::abi_decode(data, true)
+            .map_err(|source| AbiError::Decode { function: Self::SLOT0, source })?;
+        Ok(Slot0 {
+            sqrt_price_x96,
+            tick,
+            observation_index,
+            observation_cardinality,
+            observation_cardinality_next,
+            fee_protocol,
+            unlocked,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use std::{collections::HashMap, str::FromStr};
+
+    use alloy_primitives::{hex, keccak256, Address, U256};
+    use revm::primitives::{AccountInfo, Bytecode};
+
+    use super::Slot0;
+    use crate::evm::{
+        engine_db::{engine_db_interface::EngineDatabaseInterface, tycho_db::PreCachedDB},
+        storage_layout::UniswapV3Slot0,
+    };
+
+    /// Runtime code answering any call like `UniswapV3Pool.slot0()`: it unpacks all fields of
+    /// slot 0, sign extending the `int24` tick.
+    const SLOT0_CODE: &[u8] = &hex!(
+        "600054"
+        "80 73ffffffffffffffffffffffffffffffffffffffff 16 600052"
+        "80 60a01c 6002 0b 602052"
+        "80 60b81c 61ffff 16 604052"
+        "80 60c81c 61ffff 16 606052"
+        "80 60d81c 61ffff 16 608052"
+        "80 60e81c 60ff 16 60a052"
+        "60f01c 60ff 16 60c052"
+        "60e06000f3"
+    );
+
+    pub(crate) fn slot0() -> Slot0 {
+        Slot0 {
+            sqrt_price_x96: U256::from_str("1461446703485210103287273052203988822378723970341")
+                .unwrap(),
+            tick: -887_271,
+            observation_index: 17,
+            observation_cardinality: 300,
+            observation_cardinality_next: 301,
+            fee_protocol: 0x44,
+            unlocked: true,
+        }
+    }
+
+    fn pack(slot0: &Slot0) -> U256 {
+        UniswapV3Slot0 {
+            sqrt_price: slot0.sqrt_price_x96,
+            tick: slot0.tick,
+            observation_index: slot0.observation_index,
+            observation_cardinality: slot0.observation_cardinality,
+            observation_cardinality_next: slot0.observation_cardinality_next,
+            fee_protocol: slot0.fee_protocol,
+            unlocked: slot0.unlocked,
+        }
+        .pack()
+    }
+
+    /// Deploys a pool at `address` whose `slot0()` returns [`slot0`].
+    pub(crate) fn deploy_slot0_pool(db: &PreCachedDB, address: Address) {
+        let code = Bytecode::new_raw(SLOT0_CODE.into());
+        db.init_account(
+            address,
+            AccountInfo {
+                balance: U256::ZERO,
+                nonce: 0,
+                code_hash: keccak256(code.bytes()),
+                code: Some(code),
+            },
+            Some(HashMap::from([(U256::ZERO, pack(&slot0()))])),
+            true,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::{hex, Address};
+
+    use super::{
+        fixtures::{deploy_slot0_pool, slot0},
+        *,
+    };
+    use crate::evm::{
+        engine_db::{create_engine, tycho_db::PreCachedDB},
+        simulation::SimulationParameters,
+    };
+
+    #[test]
+    fn test_encode_slot0() {
+        assert_eq!(UniswapV3Abi::encode_slot0(), hex!("3850c7bd"));
+    }
+
+    #[test]
+    fn test_decode_slot0_matches_revm() {
+        let pool = Address::repeat_byte(0x33);
+        let engine = create_engine(PreCachedDB::new().unwrap(), false).unwrap();
+        deploy_slot0_pool(&engine.state, pool);
+
+        let result = engine
+            .simulate(&SimulationParameters {
+                caller: Address::ZERO,
+                to: pool,
+                data: UniswapV3Abi::encode_slot0(),
+                value: U256::ZERO,
+                overrides: None,
+                balance_overrides: None,
+                nonce: None,
+                gas_limit: None,
+                block_number: 0,
+                timestamp: 0,
+            })
+            .unwrap();
+
+        assert_eq!(UniswapV3Abi::decode_slot0(&result.result).unwrap(), slot0());
+    }
+
+    #[test]
+    fn test_decode_slot0_truncated() {
+        let data = (U256::from(1), 1i32, 0u16, 0u16, 0u16, 0u8, true).abi_encode();
+
+        let res = UniswapV3Abi::decode_slot0(&data[..6 * 32]);
+
+        assert!(matches!(res, Err(AbiError::Decode { function: "slot0()", .. })));
+    }
+}
@@ -1,4 +1,6 @@
 //! Uniswap V3 Decentralized Exchange
+pub mod abi;
 pub mod enums;
+pub mod pool;
 pub mod state;
 pub mod tycho_decoder;
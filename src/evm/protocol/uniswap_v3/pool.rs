@@ -0,0 +1,504 @@
+//! Fee accounting of a Uniswap V3 pool.
+//!
+//! [`UniswapV3State`](super::state::UniswapV3State) only quotes swaps. [`UniswapV3Pool`] mirrors
+//! the bookkeeping of the pool contract instead, to tell which position earns which fees: the
+//! global fee growth, the fee growth outside of every initialized tick and the fees owed to every
+//! position. All of them are tracked separately for token0 and token1, since a swap charges its
+//! fee in the token it sells to the pool.
+//!
+//! Fee growth is a Q128.128 amount of fees per unit of liquidity. Like in the contract it is
+//! allowed to overflow, so all differences are computed wrapping.
+use std::collections::{BTreeMap, HashMap};
+
+use alloy_primitives::{Address, I256, U256};
+
+use super::{enums::FeeAmount, state::UniswapV3State};
+use crate::{
+    evm::protocol::{
+        safe_math::{safe_add_u256, safe_sub_u256, Rounding},
+        utils::uniswap::{
+            liquidity_math,
+            solidity_math::mul_div,
+            sqrt_price_math::{get_amount0_delta, get_amount1_delta},
+            swap_math,
+            tick_list::is_active,
+            tick_math::{
+                get_sqrt_ratio_at_tick, get_tick_at_sqrt_ratio, MAX_SQRT_RATIO, MAX_TICK,
+                MIN_SQRT_RATIO, MIN_TICK,
+            },
+        },
+    },
+    protocol::errors::SimulationError,
+};
+
+const Q128: U256 = U256::from_limbs([0, 0, 1, 0]);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TickState {
+    pub liquidity_gross: u128,
+    pub liquidity_net: i128,
+    /// Fee growth of token0 on the other side of this tick from the current tick.
+    pub fee_growth_outside_0: U256,
+    /// Fee growth of token1 on the other side of this tick from the current tick.
+    pub fee_growth_outside_1: U256,
+}
+
+impl TickState {
+    /// Flips the fee growth outside of both tokens as the price crosses this tick and returns the
+    /// tick's net liquidity.
+    fn cross(&mut self, fee_growth_global0: U256, fee_growth_global1: U256) -> i128 {
+        self.fee_growth_outside_0 = fee_growth_global0.wrapping_sub(self.fee_growth_outside_0);
+        self.fee_growth_outside_1 = fee_growth_global1.wrapping_sub(self.fee_growth_outside_1);
+        self.liquidity_net
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Position {
+    pub liquidity: u128,
+    pub fee_growth_inside_0_last: U256,
+    pub fee_growth_inside_1_last: U256,
+    pub tokens_owed_0: U256,
+    pub tokens_owed_1: U256,
+}
+
+/// `(owner, tick_lower, tick_upper)`
+pub type PositionKey = (Address, i32, i32);
+
+/// Amounts moved by [`UniswapV3Pool::swap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwapAmounts {
+    /// Amount sold to the pool, fee included.
+    pub amount_in: U256,
+    pub amount_out: U256,
+    /// Fee charged, in the sold token.
+    pub fee: U256,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UniswapV3Pool {
+    fee: FeeAmount,
+    sqrt_price: U256,
+    tick: i32,
+    liquidity: u128,
+    fee_growth_global0: U256,
+    fee_growth_global1: U256,
+    ticks: BTreeMap<i32, TickState>,
+    positions: HashMap<PositionKey, Position>,
+}
+
+impl UniswapV3Pool {
+    /// An empty pool at `sqrt_price`.
+    pub fn new(sqrt_price: U256, fee: FeeAmount) -> Result<Self, SimulationError> {
+        Ok(Self {
+            fee,
+            sqrt_price,
+            tick: get_tick_at_sqrt_ratio(sqrt_price)?,
+            liquidity: 0,
+            fee_growth_global0: U256::ZERO,
+            fee_growth_global1: U256::ZERO,
+            ticks: BTreeMap::new(),
+            positions: HashMap::new(),
+        })
+    }
+
+    pub fn sqrt_price(&self) -> U256 {
+        self.sqrt_price
+    }
+
+    pub fn tick(&self) -> i32 {
+        self.tick
+    }
+
+    /// The liquidity active at the current tick.
+    pub fn liquidity(&self) -> u128 {
+        self.liquidity
+    }
+
+    pub fn fee_growth_global0(&self) -> U256 {
+        self.fee_growth_global0
+    }
+
+    pub fn fee_growth_global1(&self) -> U256 {
+        self.fee_growth_global1
+    }
+
+    pub fn tick_state(&self, index: i32) -> Option<&TickState> {
+        self.ticks.get(&index)
+    }
+
+    pub fn position(&self, owner: Address, tick_lower: i32, tick_upper: i32) -> Option<&Position> {
+        self.positions
+            .get(&(owner, tick_lower, tick_upper))
+    }
+
+    /// Adds `liquidity` to the position of `owner` between `tick_lower` and `tick_upper`.
+    ///
+    /// Fees accrued by an existing position are credited to it first. Returns the amounts of
+    /// token0 and token1 the owner has to pay in.
+    pub fn mint(
+        &mut self,
+        owner: Address,
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: u128,
+    ) -> Result<(U256, U256), SimulationError> {
+        let spacing = UniswapV3State::get_spacing(self.fee) as i32;
+        if tick_lower >= tick_upper ||
+            tick_lower < MIN_TICK ||
+            tick_upper > MAX_TICK ||
+            tick_lower % spacing != 0 ||
+            tick_upper % spacing != 0
+        {
+            return Err(SimulationError::InvalidInput(
+                format!("Invalid tick range [{tick_lower}, {tick_upper}] for spacing {spacing}"),
+                None,
+            ));
+        }
+        if liquidity == 0 || liquidity > i128::MAX as u128 {
+            return Err(SimulationError::InvalidInput(
+                format!("Invalid liquidity {liquidity}"),
+                None,
+            ));
+        }
+
+        self.update_tick(tick_lower, liquidity as i128, false);
+        self.update_tick(tick_upper, liquidity as i128, true);
+        self.update_position((owner, tick_lower, tick_upper), liquidity)?;
+
+        let sqrt_lower = get_sqrt_ratio_at_tick(tick_lower)?;
+        let sqrt_upper = get_sqrt_ratio_at_tick(tick_upper)?;
+        if is_active(self.tick, tick_lower, tick_upper) {
+            self.liquidity = liquidity_math::add_liquidity_delta(self.liquidity, liquidity as i128);
+            Ok((
+                get_amount0_delta(self.sqrt_price, sqrt_upper, liquidity, Rounding::Up)?,
+                get_amount1_delta(sqrt_lower, self.sqrt_price, liquidity, Rounding::Up)?,
+            ))
+        } else if self.tick < tick_lower {
+            Ok((get_amount0_delta(sqrt_lower, sqrt_upper, liquidity, Rounding::Up)?, U256::ZERO))
+        } else {
+            Ok((U256::ZERO, get_amount1_delta(sqrt_lower, sqrt_upper, liquidity, Rounding::Up)?))
+        }
+    }
+
+    fn update_tick(&mut self, index: i32, liquidity_delta: i128, upper: bool) {
+        let (current, global0, global1) =
+            (self.tick, self.fee_growth_global0, self.fee_growth_global1);
+        let tick = self.ticks.entry(index).or_default();
+        if tick.liquidity_gross == 0 && index <= current {
+            // By convention, all fees before a tick was initialized were earned below it.
+            tick.fee_growth_outside_0 = global0;
+            tick.fee_growth_outside_1 = global1;
+        }
+        tick.liquidity_gross =
+            liquidity_math::add_liquidity_delta(tick.liquidity_gross, liquidity_delta);
+        tick.liquidity_net += if upper { -liquidity_delta } else { liquidity_delta };
+    }
+
+    /// Fee growth of token `token_index` (0 or 1) between `tick_lower` and `tick_upper`.
+    pub fn fee_growth_inside(
+        &self,
+        tick_lower: i32,
+        tick_upper: i32,
+        token_index: u8,
+    ) -> Result<U256, SimulationError> {
+        let global = match token_index {
+            0 => self.fee_growth_global0,
+            1 => self.fee_growth_global1,
+            _ => {
+                return Err(SimulationError::InvalidInput(
+                    format!("Invalid token index {token_index}"),
+                    None,
+                ))
+            }
+        };
+        let outside_of = |index: i32| {
+            self.ticks
+                .get(&index)
+                .map(|tick| {
+                    if token_index == 0 {
+                        tick.fee_growth_outside_0
+                    } else {
+                        tick.fee_growth_outside_1
+                    }
+                })
+                .unwrap_or_default()
+        };
+        let below = if self.tick >= tick_lower {
+            outside_of(tick_lower)
+        } else {
+            global.wrapping_sub(outside_of(tick_lower))
+        };
+        let above = if self.tick < tick_upper {
+            outside_of(tick_upper)
+        } else {
+            global.wrapping_sub(outside_of(tick_upper))
+        };
+        Ok(global
+            .wrapping_sub(below)
+            .wrapping_sub(above))
+    }
+
+    /// Credits the fees accrued since the position was last updated, then adds
+    /// `liquidity_delta` to it.
+    fn update_position(
+        &mut self,
+        key: PositionKey,
+        liquidity_delta: u128,
+    ) -> Result<(), SimulationError> {
+        let (_, tick_lower, tick_upper) = key;
+        let inside0 = self.fee_growth_inside(tick_lower, tick_upper, 0)?;
+        let inside1 = self.fee_growth_inside(tick_lower, tick_upper, 1)?;
+        let position = self.positions.entry(key).or_default();
+        let (owed0, owed1) = accrued(position, inside0, inside1)?;
+        position.tokens_owed_0 = safe_add_u256(position.tokens_owed_0, owed0)?;
+        position.tokens_owed_1 = safe_add_u256(position.tokens_owed_1, owed1)?;
+        position.fee_growth_inside_0_last = inside0;
+        position.fee_growth_inside_1_last = inside1;
+        position.liquidity =
+            liquidity_math::add_liquidity_delta(position.liquidity, liquidity_delta as i128);
+        Ok(())
+    }
+
+    /// Fees of token0 and token1 owed to a position, including those accrued since it was last
+    /// updated.
+    pub fn fees_owed(
+        &self,
+        owner: Address,
+        tick_lower: i32,
+        tick_upper: i32,
+    ) -> Result<(U256, U256), SimulationError> {
+        let Some(position) = self.position(owner, tick_lower, tick_upper) else {
+            return Ok((U256::ZERO, U256::ZERO));
+        };
+        let (owed0, owed1) = accrued(
+            position,
+            self.fee_growth_inside(tick_lower, tick_upper, 0)?,
+            self.fee_growth_inside(tick_lower, tick_upper, 1)?,
+        )?;
+        Ok((
+            safe_add_u256(position.tokens_owed_0, owed0)?,
+            safe_add_u256(position.tokens_owed_1, owed1)?,
+        ))
+    }
+
+    /// Sells `amount_in` of token0 (`zero_for_one`) or token1 to the pool, fees included.
+    ///
+    /// The fee of every step is added to the fee growth of the sold token. The swap stops early
+    /// if the price reaches its bound, in which case `amount_in` of the result is smaller.
+    pub fn swap(
+        &mut self,
+        zero_for_one: bool,
+        amount_in: U256,
+    ) -> Result<SwapAmounts, SimulationError> {
+        let mut remaining = I256::try_from(amount_in).map_err(|_| {
+            SimulationError::InvalidInput(format!("Amount {amount_in} too large"), None)
+        })?;
+        if remaining.is_zero() {
+            return Err(SimulationError::InvalidInput("Amount must be positive".to_string(), None));
+        }
+        let price_limit = if zero_for_one {
+            safe_add_u256(MIN_SQRT_RATIO, U256::from(1u64))?
+        } else {
+            safe_sub_u256(MAX_SQRT_RATIO, U256::from(1u64))?
+        };
+        let mut amount_out = U256::ZERO;
+        let mut fee = U256::ZERO;
+
+        while !remaining.is_zero() && self.sqrt_price != price_limit {
+            let next_initialized = if zero_for_one {
+                self.ticks
+                    .range(..=self.tick)
+                    .next_back()
+            } else {
+                self.ticks.range(self.tick + 1..).next()
+            }
+            .map(|(index, _)| *index);
+            let tick_next =
+                next_initialized.unwrap_or(if zero_for_one { MIN_TICK } else { MAX_TICK });
+            let sqrt_price_next = get_sqrt_ratio_at_tick(tick_next)?;
+            let target = if zero_for_one {
+                sqrt_price_next.max(price_limit)
+            } else {
+                sqrt_price_next.min(price_limit)
+            };
+
+            let sqrt_price_start = self.sqrt_price;
+            let (sqrt_price, step_in, step_out, step_fee) = swap_math::compute_swap_step(
+                sqrt_price_start,
+                target,
+                self.liquidity,
+                remaining,
+                self.fee as u32,
+            )?;
+            self.sqrt_price = sqrt_price;
+            remaining -= I256::from_raw(safe_add_u256(step_in, step_fee)?);
+            amount_out = safe_add_u256(amount_out, step_out)?;
+            fee = safe_add_u256(fee, step_fee)?;
+            if self.liquidity > 0 {
+                let growth = mul_div(step_fee, Q128, U256::from(self.liquidity), Rounding::Down)?;
+                if zero_for_one {
+                    self.fee_growth_global0 = self
+                        .fee_growth_global0
+                        .wrapping_add(growth);
+                } else {
+                    self.fee_growth_global1 = self
+                        .fee_growth_global1
+                        .wrapping_add(growth);
+                }
+            }
+
+            if sqrt_price == sqrt_price_next {
+                if let Some(index) = next_initialized {
+                    let (global0, global1) = (self.fee_growth_global0, self.fee_growth_global1);
+                    let liquidity_net = self
+                        .ticks
+                        .get_mut(&index)
+                        .expect("next initialized tick exists")
+                        .cross(global0, global1);
+                    let liquidity_net = if zero_for_one { -liquidity_net } else { liquidity_net };
+                    self.liquidity =
+                        liquidity_math::add_liquidity_delta(self.liquidity, liquidity_net);
+                }
+                self.tick = if zero_for_one { tick_next - 1 } else { tick_next };
+            } else if sqrt_price != sqrt_price_start {
+                self.tick = get_tick_at_sqrt_ratio(sqrt_price)?;
+            }
+        }
+
+        Ok(SwapAmounts {
+            amount_in: safe_sub_u256(amount_in, remaining.into_raw())?,
+            amount_out,
+            fee,
+        })
+    }
+}
+
+/// Fees of token0 and token1 a position earned since its fee growth was last recorded.
+fn accrued(
+    position: &Position,
+    inside0: U256,
+    inside1: U256,
+) -> Result<(U256, U256), SimulationError> {
+    let liquidity = U256::from(position.liquidity);
+    Ok((
+        mul_div(
+            inside0.wrapping_sub(position.fee_growth_inside_0_last),
+            liquidity,
+            Q128,
+            Rounding::Down,
+        )?,
+        mul_div(
+            inside1.wrapping_sub(position.fee_growth_inside_1_last),
+            liquidity,
+            Q128,
+            Rounding::Down,
+        )?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    const OWNER: Address = Address::new([0x11; 20]);
+    const OTHER: Address = Address::new([0x22; 20]);
+    /// Full range of the 0.3% tier.
+    const FULL_RANGE: (i32, i32) = (-887220, 887220);
+    /// A power of two, so fee growth and fees owed are computed without rounding.
+    const LIQUIDITY: u128 = 1 << 70;
+
+    fn pool() -> UniswapV3Pool {
+        // Price 1
+        UniswapV3Pool::new(U256::from(1u64) << 96, FeeAmount::Medium).unwrap()
+    }
+
+    fn amount(value: &str) -> U256 {
+        U256::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn test_fees_owed_match_fees_charged_per_token() {
+        let mut pool = pool();
+        pool.mint(OWNER, FULL_RANGE.0, FULL_RANGE.1, LIQUIDITY)
+            .unwrap();
+        let swaps = [
+            (true, amount("1000000000000000000")),
+            (false, amount("3000000000000000000")),
+            (true, amount("2500000000000000000")),
+            (false, amount("700000000000000000")),
+            (true, amount("123456789012345678")),
+        ];
+
+        let mut charged = [U256::ZERO, U256::ZERO];
+        for (zero_for_one, amount_in) in swaps {
+            let res = pool
+                .swap(zero_for_one, amount_in)
+                .unwrap();
+            assert_eq!(res.amount_in, amount_in);
+            charged[usize::from(!zero_for_one)] += res.fee;
+        }
+
+        let (owed0, owed1) = pool
+            .fees_owed(OWNER, FULL_RANGE.0, FULL_RANGE.1)
+            .unwrap();
+        assert!(charged[0] > U256::ZERO && charged[1] > U256::ZERO);
+        assert_eq!((owed0, owed1), (charged[0], charged[1]));
+        assert_eq!(owed0 + owed1, charged[0] + charged[1]);
+    }
+
+    #[test]
+    fn test_fees_are_split_between_positions_across_ticks() {
+        let mut pool = pool();
+        pool.mint(OWNER, FULL_RANGE.0, FULL_RANGE.1, LIQUIDITY)
+            .unwrap();
+        pool.mint(OTHER, -60, 60, LIQUIDITY)
+            .unwrap();
+        let swaps = [
+            (true, amount("20000000000000000000")),
+            (false, amount("40000000000000000000")),
+            (true, amount("30000000000000000000")),
+            (false, amount("15000000000000000000")),
+            (true, amount("5000000000000000000")),
+        ];
+
+        let mut charged = [U256::ZERO, U256::ZERO];
+        for (zero_for_one, amount_in) in swaps {
+            charged[usize::from(!zero_for_one)] += pool
+                .swap(zero_for_one, amount_in)
+                .unwrap()
+                .fee;
+        }
+
+        // The narrow position was crossed, which flips the fee growth outside of both tokens.
+        let lower = pool.tick_state(-60).unwrap();
+        assert!(lower.fee_growth_outside_0 > U256::ZERO);
+        assert!(lower.fee_growth_outside_1 > U256::ZERO);
+        let wide = pool
+            .fees_owed(OWNER, FULL_RANGE.0, FULL_RANGE.1)
+            .unwrap();
+        let narrow = pool.fees_owed(OTHER, -60, 60).unwrap();
+        // The wide position earned everything while the price was outside the narrow range.
+        assert!(wide.0 > narrow.0 && wide.1 > narrow.1);
+        for (owed, charged) in [(wide.0 + narrow.0, charged[0]), (wide.1 + narrow.1, charged[1])] {
+            // Every position rounds its share down by less than 1 per update.
+            assert!(owed <= charged && charged - owed <= U256::from(2u64 * 5));
+        }
+    }
+
+    #[test]
+    fn test_fee_growth_inside_rejects_unknown_token() {
+        let res = pool().fee_growth_inside(-60, 60, 2);
+
+        assert!(matches!(res, Err(SimulationError::InvalidInput(..))));
+    }
+
+    #[test]
+    fn test_mint_rejects_unaligned_ticks() {
+        let res = pool().mint(OWNER, -61, 60, LIQUIDITY);
+
+        assert!(matches!(res, Err(SimulationError::InvalidInput(..))));
+    }
+}
@@ -1,4 +1,10 @@
-use std::{any::Any, collections::HashMap};
+use std::{
+    any::Any,
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    mem,
+    sync::Arc,
+};
 
 use alloy_primitives::{Address, Sign, I256, U256};
 use num_bigint::BigUint;
@@ -9,26 +15,28 @@ use tycho_common::{dto::ProtocolStateDelta, Bytes};
 use super::enums::FeeAmount;
 use crate::{
     evm::protocol::{
-        safe_math::{safe_add_u256, safe_sub_u256},
+        safe_math::{safe_add_u256, safe_sub_u256, Rounding},
         u256_num::u256_to_biguint,
         utils::uniswap::{
-            i24_be_bytes_to_i32, liquidity_math,
+            i24_be_bytes_to_i32,
             sqrt_price_math::{get_amount0_delta, get_amount1_delta, sqrt_price_q96_to_f64},
             swap_math,
-            tick_list::{TickInfo, TickList, TickListErrorKind},
+            tick_list::{TickInfo, TickList, TickListError, TickListErrorKind, TickRange},
             tick_math::{
                 get_sqrt_ratio_at_tick, get_tick_at_sqrt_ratio, MAX_SQRT_RATIO, MAX_TICK,
                 MIN_SQRT_RATIO, MIN_TICK,
             },
+            tick_provider::TickProvider,
             StepComputation, SwapResults, SwapState,
         },
     },
     models::{Balances, Token},
     protocol::{
-        errors::{SimulationError, TransitionError},
-        models::GetAmountOutResult,
+        errors::{LimitedSwapError, PriceLimitError, SimulationError, TransitionError},
+        models::{GetAmountOutResult, LimitedSwapResult},
         state::ProtocolSim,
     },
+    types::TokenAmount,
 };
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -38,8 +46,59 @@ pub struct UniswapV3State {
     fee: FeeAmount,
     tick: i32,
     ticks: TickList,
+    lazy_ticks: Option<LazyTicks>,
+}
+
+/// Bookkeeping for states that only keep the ticks around the current price in memory.
+#[derive(Clone, Debug)]
+struct LazyTicks {
+    /// Number of ticks loaded at once, in each direction.
+    window: i32,
+    /// The range for which all initialized ticks are held by the state.
+    loaded: TickRange,
+    /// Source of ticks outside the loaded range.
+    provider: Option<Arc<dyn TickProvider>>,
+    /// Net liquidity of the ticks outside the loaded range that were updated since the provider's
+    /// snapshot. Applied over the ticks fetched from the provider.
+    updated: HashMap<i32, i128>,
+}
+
+impl LazyTicks {
+    /// Fetches the ticks in `range` from the provider, with the updates since its snapshot
+    /// applied.
+    fn fetch(&self, range: &TickRange) -> Result<Vec<TickInfo>, SimulationError> {
+        let provider = self.provider.as_ref().ok_or_else(|| {
+            SimulationError::RecoverableError(format!(
+                "TickRangeUnavailable: ticks in [{}, {}] are not loaded",
+                range.lower, range.upper
+            ))
+        })?;
+        let mut ticks: BTreeMap<i32, i128> = provider
+            .ticks_in_range(range.lower, range.upper)?
+            .into_iter()
+            .map(|tick| (tick.index, tick.net_liquidity))
+            .collect();
+        for (&index, &net_liquidity) in &self.updated {
+            if range.contains(index) {
+                ticks.insert(index, net_liquidity);
+            }
+        }
+        Ok(ticks
+            .into_iter()
+            .filter(|(_, net_liquidity)| *net_liquidity != 0)
+            .map(|(index, net_liquidity)| TickInfo::new(index, net_liquidity))
+            .collect())
+    }
+}
+
+impl PartialEq for LazyTicks {
+    fn eq(&self, other: &Self) -> bool {
+        self.window == other.window && self.loaded == other.loaded && self.updated == other.updated
+    }
 }
 
+impl Eq for LazyTicks {}
+
 impl UniswapV3State {
     /// Creates a new instance of `UniswapV3State`.
     ///
@@ -58,10 +117,122 @@ impl UniswapV3State {
     ) -> Self {
         let spacing = UniswapV3State::get_spacing(fee);
         let tick_list = TickList::from(spacing, ticks);
-        UniswapV3State { liquidity, sqrt_price, fee, tick, ticks: tick_list }
+        UniswapV3State { liquidity, sqrt_price, fee, tick, ticks: tick_list, lazy_ticks: None }
+    }
+
+    /// Only keeps the ticks within `window` ticks of the current tick in memory.
+    ///
+    /// Swaps reaching beyond the loaded ticks fetch more ticks from `provider`, `window` ticks at a
+    /// time. Without a provider such swaps fail with a `TickRangeUnavailable` error instead of
+    /// returning a truncated result.
+    ///
+    /// The provider is expected to serve the ticks as of the state's creation. Updates to ticks
+    /// outside of the loaded range are kept and applied over the fetched ticks. Once the price
+    /// leaves the loaded range, the range is widened to the window around the new tick.
+    pub fn with_lazy_ticks(mut self, window: i32, provider: Option<Arc<dyn TickProvider>>) -> Self {
+        let window = window.max(UniswapV3State::get_spacing(self.fee) as i32);
+        let loaded = TickRange::new(self.tick - window, self.tick + window);
+        self.ticks.retain_range(&loaded);
+        self.lazy_ticks = Some(LazyTicks { window, loaded, provider, updated: HashMap::new() });
+        self
+    }
+
+    /// Sets the net liquidity of `tick`. For lazy states, the liquidity of a tick outside the
+    /// loaded range is kept until the tick is loaded.
+    fn set_tick_liquidity(&mut self, tick: i32, net_liquidity: i128) {
+        match &mut self.lazy_ticks {
+            Some(lazy) if !lazy.loaded.contains(tick) => {
+                lazy.updated.insert(tick, net_liquidity);
+            }
+            _ => self
+                .ticks
+                .set_tick_liquidity(tick, net_liquidity),
+        }
     }
 
-    fn get_spacing(fee: FeeAmount) -> u16 {
+    /// Widens the loaded range of a lazy state to the window around the current tick, if the tick
+    /// left it. Without a provider the range is kept, and swaps from outside it fail with a
+    /// `TickRangeUnavailable` error.
+    fn widen_lazy_ticks(&mut self) -> Result<(), SimulationError> {
+        let Some(lazy) = &mut self.lazy_ticks else {
+            return Ok(());
+        };
+        if lazy.loaded.contains(self.tick) || lazy.provider.is_none() {
+            return Ok(());
+        }
+        let loaded = TickRange::new(
+            lazy.loaded
+                .lower
+                .min(self.tick - lazy.window),
+            lazy.loaded
+                .upper
+                .max(self.tick + lazy.window),
+        );
+        let mut fetched = Vec::new();
+        if loaded.lower < lazy.loaded.lower {
+            fetched.extend(lazy.fetch(&TickRange::new(loaded.lower, lazy.loaded.lower - 1))?);
+        }
+        if loaded.upper > lazy.loaded.upper {
+            fetched.extend(lazy.fetch(&TickRange::new(lazy.loaded.upper + 1, loaded.upper))?);
+        }
+        self.ticks.extend(fetched);
+        lazy.updated
+            .retain(|tick, _| !loaded.contains(*tick));
+        lazy.loaded = loaded;
+        Ok(())
+    }
+
+    /// Returns the next initialized tick within one word of `tick`.
+    ///
+    /// For lazy states, ticks missing to determine the result are fetched from the provider into
+    /// `ticks`, extending `loaded` accordingly. Errors if ticks are missing and can't be fetched.
+    fn next_initialized_tick(
+        &self,
+        ticks: &mut Cow<'_, TickList>,
+        loaded: &mut Option<TickRange>,
+        tick: i32,
+        zero_for_one: bool,
+    ) -> Result<Result<(i32, bool), TickListError>, SimulationError> {
+        let Some(lazy) = &self.lazy_ticks else {
+            return Ok(ticks.next_initialized_tick_within_one_word(tick, zero_for_one));
+        };
+        let range = loaded.get_or_insert(lazy.loaded);
+
+        loop {
+            let (lower, upper) = if tick < range.lower {
+                (tick, range.lower - 1)
+            } else if tick > range.upper {
+                (range.upper + 1, tick)
+            } else {
+                match ticks.next_initialized_tick_within_one_word_in_range(
+                    tick,
+                    zero_for_one,
+                    range,
+                ) {
+                    Ok(Some(next)) => return Ok(Ok(next)),
+                    Err(err) => return Ok(Err(err)),
+                    Ok(None) if zero_for_one && range.lower > MIN_TICK => {
+                        (range.lower - lazy.window, range.lower - 1)
+                    }
+                    Ok(None) if !zero_for_one && range.upper < MAX_TICK => {
+                        (range.upper + 1, range.upper + lazy.window)
+                    }
+                    // Everything is loaded and there are no more ticks
+                    Ok(None) => {
+                        return Ok(Err(TickListError { kind: TickListErrorKind::TicksExeeded }))
+                    }
+                }
+            };
+
+            let fetched = TickRange::new(lower, upper);
+            ticks
+                .to_mut()
+                .extend(lazy.fetch(&fetched)?);
+            *range = TickRange::new(range.lower.min(fetched.lower), range.upper.max(fetched.upper));
+        }
+    }
+
+    pub(crate) fn get_spacing(fee: FeeAmount) -> u16 {
         match fee {
             FeeAmount::Lowest => 1,
             FeeAmount::Low => 10,
@@ -107,14 +278,18 @@ impl UniswapV3State {
             liquidity: self.liquidity,
         };
         let mut gas_used = U256::from(130_000);
+        let mut ticks = Cow::Borrowed(&self.ticks);
+        let mut loaded_ticks = None;
 
         while state.amount_remaining != I256::from_raw(U256::from(0u64)) &&
             state.sqrt_price != price_limit
         {
-            let (mut next_tick, initialized) = match self
-                .ticks
-                .next_initialized_tick_within_one_word(state.tick, zero_for_one)
-            {
+            let (mut next_tick, initialized) = match self.next_initialized_tick(
+                &mut ticks,
+                &mut loaded_ticks,
+                state.tick,
+                zero_for_one,
+            )? {
                 Ok((tick, init)) => (tick, init),
                 Err(tick_err) => match tick_err.kind {
                     TickListErrorKind::TicksExeeded => {
@@ -175,14 +350,7 @@ impl UniswapV3State {
             }
             if state.sqrt_price == step.sqrt_price_next {
                 if step.initialized {
-                    let liquidity_raw = self
-                        .ticks
-                        .get_tick(step.tick_next)
-                        .unwrap()
-                        .net_liquidity;
-                    let liquidity_net = if zero_for_one { -liquidity_raw } else { liquidity_raw };
-                    state.liquidity =
-                        liquidity_math::add_liquidity_delta(state.liquidity, liquidity_net);
+                    state.liquidity = ticks.cross(step.tick_next, state.liquidity, zero_for_one);
                 }
                 state.tick = if zero_for_one { step.tick_next - 1 } else { step.tick_next };
             } else if state.sqrt_price != step.sqrt_price_start {
@@ -191,6 +359,7 @@ impl UniswapV3State {
             gas_used = safe_add_u256(gas_used, U256::from(2000))?;
         }
         Ok(SwapResults {
+            amount_remaining: state.amount_remaining,
             amount_calculated: state.amount_calculated,
             sqrt_price: state.sqrt_price,
             liquidity: state.liquidity,
@@ -216,6 +385,144 @@ impl UniswapV3State {
             sqrt_price_next
         }
     }
+
+    /// Returns a copy of this state moved to the price (and tick) of `other`.
+    pub(crate) fn with_price_of(&self, other: &Self) -> Self {
+        Self { sqrt_price: other.sqrt_price, tick: other.tick, ..self.clone() }
+    }
+
+    /// Returns a copy of this state with the active liquidity and ticks of `other`.
+    pub(crate) fn with_liquidity_of(&self, other: &Self) -> Self {
+        Self {
+            liquidity: other.liquidity,
+            ticks: other.ticks.clone(),
+            lazy_ticks: other.lazy_ticks.clone(),
+            ..self.clone()
+        }
+    }
+
+    /// Lists the fields that differ from `other` as `(field, self value, other value)`.
+    pub(crate) fn changed_fields(&self, other: &Self) -> Vec<(&'static str, String, String)> {
+        let mut changes = Vec::new();
+        if self.sqrt_price != other.sqrt_price {
+            changes.push(("sqrt_price", self.sqrt_price.to_string(), other.sqrt_price.to_string()));
+        }
+        if self.tick != other.tick {
+            changes.push(("tick", self.tick.to_string(), other.tick.to_string()));
+        }
+        if self.liquidity != other.liquidity {
+            changes.push(("liquidity", self.liquidity.to_string(), other.liquidity.to_string()));
+        }
+        if self.fee != other.fee {
+            changes.push(("fee", (self.fee as u32).to_string(), (other.fee as u32).to_string()));
+        }
+        if self.ticks != other.ticks {
+            changes.push((
+                "initialized_ticks",
+                self.ticks.len().to_string(),
+                other.ticks.len().to_string(),
+            ));
+        }
+        changes
+    }
+
+    /// Checks a `sqrtPriceLimitX96` the way the pool contract does, which reverts with `SPL` if
+    /// the limit isn't strictly between the current price and the price bound in swap direction.
+    pub fn check_sqrt_price_limit(
+        &self,
+        zero_for_one: bool,
+        sqrt_price_limit: U256,
+    ) -> Result<(), PriceLimitError> {
+        let (wrong_direction, out_of_range) = if zero_for_one {
+            (sqrt_price_limit >= self.sqrt_price, sqrt_price_limit <= MIN_SQRT_RATIO)
+        } else {
+            (sqrt_price_limit <= self.sqrt_price, sqrt_price_limit >= MAX_SQRT_RATIO)
+        };
+        if out_of_range {
+            Err(PriceLimitError::OutOfRange(sqrt_price_limit))
+        } else if wrong_direction {
+            Err(PriceLimitError::WrongDirection {
+                limit: sqrt_price_limit,
+                current: self.sqrt_price,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Quotes selling `amount_in`, stopping early if the price reaches `sqrt_price_limit`.
+    pub fn get_amount_out_with_price_limit(
+        &self,
+        amount_in: BigUint,
+        token_in: &Token,
+        token_out: &Token,
+        sqrt_price_limit: U256,
+    ) -> Result<LimitedSwapResult, LimitedSwapError> {
+        let amount_specified = I256::checked_from_sign_and_abs(
+            Sign::Positive,
+            U256::from_be_slice(&amount_in.to_bytes_be()),
+        )
+        .ok_or_else(|| {
+            SimulationError::InvalidInput("I256 overflow: amount_in".to_string(), None)
+        })?;
+        self.limited_swap(token_in, token_out, amount_specified, sqrt_price_limit)
+    }
+
+    /// Quotes buying `amount_out`, stopping early if the price reaches `sqrt_price_limit`.
+    pub fn get_amount_in_with_price_limit(
+        &self,
+        amount_out: BigUint,
+        token_in: &Token,
+        token_out: &Token,
+        sqrt_price_limit: U256,
+    ) -> Result<LimitedSwapResult, LimitedSwapError> {
+        let amount_specified = I256::checked_from_sign_and_abs(
+            Sign::Negative,
+            U256::from_be_slice(&amount_out.to_bytes_be()),
+        )
+        .ok_or_else(|| {
+            SimulationError::InvalidInput("I256 overflow: amount_out".to_string(), None)
+        })?;
+        self.limited_swap(token_in, token_out, amount_specified, sqrt_price_limit)
+    }
+
+    fn limited_swap(
+        &self,
+        token_in: &Token,
+        token_out: &Token,
+        amount_specified: I256,
+        sqrt_price_limit: U256,
+    ) -> Result<LimitedSwapResult, LimitedSwapError> {
+        let zero_for_one = token_in < token_out;
+        self.check_sqrt_price_limit(zero_for_one, sqrt_price_limit)?;
+        let result = self.swap(zero_for_one, amount_specified, Some(sqrt_price_limit))?;
+
+        let filled = (amount_specified - result.amount_remaining)
+            .abs()
+            .into_raw();
+        let calculated = result
+            .amount_calculated
+            .abs()
+            .into_raw();
+        let (amount_in, amount_out) = if amount_specified.is_positive() {
+            (filled, calculated)
+        } else {
+            (calculated, filled)
+        };
+
+        let mut new_state = self.clone();
+        new_state.liquidity = result.liquidity;
+        new_state.tick = result.tick;
+        new_state.sqrt_price = result.sqrt_price;
+
+        Ok(LimitedSwapResult {
+            amount_in: TokenAmount::of(amount_in, token_in),
+            amount_out: TokenAmount::of(amount_out, token_out),
+            gas: u256_to_biguint(result.gas_used),
+            new_state: Box::new(new_state),
+            limit_reached: !result.amount_remaining.is_zero(),
+        })
+    }
 }
 
 impl ProtocolSim for UniswapV3State {
@@ -283,12 +590,13 @@ impl ProtocolSim for UniswapV3State {
         let mut current_liquidity = self.liquidity;
         let mut total_amount_in = U256::from(0u64);
         let mut total_amount_out = U256::from(0u64);
+        let mut ticks = Cow::Borrowed(&self.ticks);
+        let mut loaded_ticks = None;
 
         // Iterate through all ticks in the direction of the swap
         // Continues until there is no more liquidity in the pool or no more ticks to process
-        while let Ok((tick, initialized)) = self
-            .ticks
-            .next_initialized_tick_within_one_word(current_tick, zero_for_one)
+        while let Ok((tick, initialized)) =
+            self.next_initialized_tick(&mut ticks, &mut loaded_ticks, current_tick, zero_for_one)?
         {
             // Clamp the tick value to ensure it's within valid range
             let next_tick = tick.clamp(MIN_TICK, MAX_TICK);
@@ -303,13 +611,13 @@ impl ProtocolSim for UniswapV3State {
                     sqrt_price_next,
                     current_sqrt_price,
                     current_liquidity,
-                    true,
+                    Rounding::Up,
                 )?;
                 let amount1 = get_amount1_delta(
                     sqrt_price_next,
                     current_sqrt_price,
                     current_liquidity,
-                    false,
+                    Rounding::Down,
                 )?;
                 (amount0, amount1)
             } else {
@@ -317,13 +625,13 @@ impl ProtocolSim for UniswapV3State {
                     sqrt_price_next,
                     current_sqrt_price,
                     current_liquidity,
-                    false,
+                    Rounding::Down,
                 )?;
                 let amount1 = get_amount1_delta(
                     sqrt_price_next,
                     current_sqrt_price,
                     current_liquidity,
-                    true,
+                    Rounding::Up,
                 )?;
                 (amount1, amount0)
             };
@@ -337,14 +645,7 @@ impl ProtocolSim for UniswapV3State {
             // For zero_for_one, liquidity is removed when crossing a tick
             // For one_for_zero, liquidity is added when crossing a tick
             if initialized {
-                let liquidity_raw = self
-                    .ticks
-                    .get_tick(next_tick)
-                    .unwrap()
-                    .net_liquidity;
-                let liquidity_delta = if zero_for_one { -liquidity_raw } else { liquidity_raw };
-                current_liquidity =
-                    liquidity_math::add_liquidity_delta(current_liquidity, liquidity_delta);
+                current_liquidity = ticks.cross(next_tick, current_liquidity, zero_for_one);
             }
 
             // Move to the next tick position
@@ -355,6 +656,92 @@ impl ProtocolSim for UniswapV3State {
         Ok((u256_to_biguint(total_amount_in), u256_to_biguint(total_amount_out)))
     }
 
+    /// Walks the ticks in swap direction until liquidity runs out, summing up the input (fees
+    /// included) each step consumes, exactly as `swap` computes it. Swapping the result therefore
+    /// consumes all liquidity without running past the last tick.
+    fn max_input(&self, token_in: &Token, token_out: &Token) -> Result<U256, SimulationError> {
+        if self.liquidity == 0 {
+            return Err(SimulationError::RecoverableError("No liquidity".to_string()));
+        }
+        let zero_for_one = token_in < token_out;
+        let price_limit = if zero_for_one {
+            safe_add_u256(MIN_SQRT_RATIO, U256::from(1u64))?
+        } else {
+            safe_sub_u256(MAX_SQRT_RATIO, U256::from(1u64))?
+        };
+        let mut current_tick = self.tick;
+        let mut current_sqrt_price = self.sqrt_price;
+        let mut current_liquidity = self.liquidity;
+        let mut total_amount_in = U256::from(0u64);
+        let mut ticks = Cow::Borrowed(&self.ticks);
+        let mut loaded_ticks = None;
+
+        while current_sqrt_price != price_limit {
+            let Ok((tick, initialized)) = self.next_initialized_tick(
+                &mut ticks,
+                &mut loaded_ticks,
+                current_tick,
+                zero_for_one,
+            )?
+            else {
+                break;
+            };
+            let next_tick = tick.clamp(MIN_TICK, MAX_TICK);
+            let sqrt_price_next = get_sqrt_ratio_at_tick(next_tick)?;
+            let (sqrt_price, amount_in, _, fee_amount) = swap_math::compute_swap_step(
+                current_sqrt_price,
+                UniswapV3State::get_sqrt_ratio_target(sqrt_price_next, price_limit, zero_for_one),
+                current_liquidity,
+                I256::MAX,
+                self.fee as u32,
+            )?;
+            total_amount_in =
+                safe_add_u256(total_amount_in, safe_add_u256(amount_in, fee_amount)?)?;
+            current_sqrt_price = sqrt_price;
+
+            if current_sqrt_price == sqrt_price_next {
+                if initialized {
+                    current_liquidity = ticks.cross(next_tick, current_liquidity, zero_for_one);
+                }
+                current_tick = if zero_for_one { next_tick - 1 } else { next_tick };
+            }
+        }
+
+        if total_amount_in.is_zero() {
+            return Err(SimulationError::RecoverableError("No liquidity".to_string()));
+        }
+        Ok(total_amount_in)
+    }
+
+    /// Encodes the ticks held in memory and, for lazily loaded states, the loaded range and the
+    /// updates of the ticks outside of it. The tick provider itself isn't part of the encoding.
+    fn dump(&self) -> Option<Vec<u8>> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&self.liquidity.to_be_bytes());
+        out.extend_from_slice(&self.sqrt_price.to_be_bytes::<32>());
+        out.extend_from_slice(&(self.fee as u32).to_be_bytes());
+        out.extend_from_slice(&self.tick.to_be_bytes());
+        self.ticks.encode(&mut out);
+        if let Some(lazy) = &self.lazy_ticks {
+            out.extend_from_slice(&lazy.loaded.lower.to_be_bytes());
+            out.extend_from_slice(&lazy.loaded.upper.to_be_bytes());
+            let updated: BTreeMap<_, _> = lazy.updated.iter().collect();
+            for (index, net_liquidity) in updated {
+                out.extend_from_slice(&index.to_be_bytes());
+                out.extend_from_slice(&net_liquidity.to_be_bytes());
+            }
+        }
+        Some(out)
+    }
+
+    fn approx_heap_size(&self) -> usize {
+        let updated = self
+            .lazy_ticks
+            .as_ref()
+            .map_or(0, |lazy| lazy.updated.capacity() * mem::size_of::<(i32, i128)>());
+        self.ticks.heap_size() + updated
+    }
+
     fn delta_transition(
         &mut self,
         delta: ProtocolStateDelta,
@@ -413,30 +800,27 @@ impl ProtocolSim for UniswapV3State {
 
         // apply tick changes
         for (key, value) in delta.updated_attributes.iter() {
-            // tick liquidity keys are in the format "tick/{tick_index}/net_liquidity"
+            // tick liquidity keys are in the format "ticks/{tick_index}/net_liquidity"
             if key.starts_with("ticks/") {
                 let parts: Vec<&str> = key.split('/').collect();
-                self.ticks.set_tick_liquidity(
-                    parts[1]
-                        .parse::<i32>()
-                        .map_err(|err| TransitionError::DecodeError(err.to_string()))?,
-                    i128::from(value.clone()),
-                )
+                let tick = parts[1]
+                    .parse::<i32>()
+                    .map_err(|err| TransitionError::DecodeError(err.to_string()))?;
+                self.set_tick_liquidity(tick, i128::from(value.clone()));
             }
         }
         // delete ticks - ignores deletes for attributes other than tick liquidity
         for key in delta.deleted_attributes.iter() {
-            // tick liquidity keys are in the format "tick/{tick_index}/net_liquidity"
-            if key.starts_with("tick/") {
+            // tick liquidity keys are in the format "ticks/{tick_index}/net_liquidity"
+            if key.starts_with("ticks/") {
                 let parts: Vec<&str> = key.split('/').collect();
-                self.ticks.set_tick_liquidity(
-                    parts[1]
-                        .parse::<i32>()
-                        .map_err(|err| TransitionError::DecodeError(err.to_string()))?,
-                    0,
-                )
+                let tick = parts[1]
+                    .parse::<i32>()
+                    .map_err(|err| TransitionError::DecodeError(err.to_string()))?;
+                self.set_tick_liquidity(tick, 0);
             }
         }
+        self.widen_lazy_ticks()?;
         Ok(())
     }
 
@@ -479,12 +863,19 @@ mod tests {
 
     use num_bigint::ToBigUint;
     use num_traits::FromPrimitive;
+    use rstest::rstest;
     use serde_json::Value;
     use tycho_client::feed::synchronizer::ComponentWithState;
     use tycho_common::hex_bytes::Bytes;
 
     use super::*;
-    use crate::{evm::protocol::utils::bytes_to_address, protocol::models::TryFromWithBlock};
+    use crate::{
+        evm::protocol::utils::{bytes_to_address, uniswap::tick_provider::SnapshotTickProvider},
+        protocol::{
+            conformance::{run_conformance_suite, ConformanceSpec},
+            models::TryFromWithBlock,
+        },
+    };
 
     #[test]
     fn test_get_amount_out_full_range_liquidity() {
@@ -741,6 +1132,41 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_delta_transition_deleted_tick() {
+        let mut pool = UniswapV3State::new(
+            1000,
+            U256::from_str("1000").unwrap(),
+            FeeAmount::Low,
+            100,
+            vec![TickInfo::new(255760, 10000), TickInfo::new(255900, -10000)],
+        );
+        let delta = ProtocolStateDelta {
+            component_id: "State1".to_owned(),
+            updated_attributes: HashMap::new(),
+            deleted_attributes: [
+                "ticks/255760/net_liquidity".to_string(),
+                // never present, deleting it is a no-op
+                "ticks/-100/net_liquidity".to_string(),
+            ]
+            .into_iter()
+            .collect(),
+        };
+
+        pool.delta_transition(delta, &HashMap::new(), &Balances::default())
+            .unwrap();
+
+        assert!(pool.ticks.get_tick(255760).is_err());
+        assert!(pool.ticks.get_tick(-100).is_err());
+        assert_eq!(
+            pool.ticks
+                .get_tick(255900)
+                .unwrap()
+                .net_liquidity,
+            -10000
+        );
+    }
+
     #[tokio::test]
     async fn test_get_limits() {
         let project_root = env!("CARGO_MANIFEST_DIR");
@@ -789,6 +1215,510 @@ mod tests {
 
         assert_eq!(&res.1, &out.amount);
     }
+
+    fn lazy_test_ticks() -> Vec<TickInfo> {
+        (1..=100)
+            .flat_map(|k| {
+                [
+                    TickInfo::new(-600 * k, 1_000_000_000_000_000_000),
+                    TickInfo::new(600 * k, -1_000_000_000_000_000_000),
+                ]
+            })
+            .collect()
+    }
+
+    fn lazy_test_pool() -> UniswapV3State {
+        UniswapV3State::new(
+            100_000_000_000_000_000_000,
+            get_sqrt_ratio_at_tick(0).unwrap(),
+            FeeAmount::Medium,
+            0,
+            lazy_test_ticks(),
+        )
+    }
+
+    fn lazy_test_tokens() -> (Token, Token) {
+        let token_x = Token::new(
+            "0x6b175474e89094c44da98b954eedeac495271d0f",
+            18,
+            "X",
+            10_000.to_biguint().unwrap(),
+        );
+        let token_y = Token::new(
+            "0xf1ca9cb74685755965c7458528a36934df52a3ef",
+            18,
+            "Y",
+            10_000.to_biguint().unwrap(),
+        );
+        (token_x, token_y)
+    }
+
+    #[test]
+    fn test_conformance() {
+        let (token_x, token_y) = lazy_test_tokens();
+        let amounts = ["1000000000000000", "1000000000000000000", "100000000000000000000"]
+            .map(|amount| BigUint::from_str(amount).unwrap())
+            .to_vec();
+        let spec = ConformanceSpec::new(token_x, token_y, amounts);
+
+        let report = run_conformance_suite(lazy_test_pool, spec);
+
+        report.assert_ok();
+    }
+
+    #[test]
+    fn test_quote_shares_ticks() {
+        let (token_x, token_y) = lazy_test_tokens();
+        let pool = lazy_test_pool();
+
+        let res = pool
+            .get_amount_out(BigUint::from_str("1000000000000000000").unwrap(), &token_x, &token_y)
+            .unwrap();
+
+        let new_state = res
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap();
+        assert!(new_state
+            .ticks
+            .shares_ticks_with(&pool.ticks));
+    }
+
+    #[test]
+    fn test_sequential_quotes_on_shared_ticks() {
+        let (token_x, token_y) = lazy_test_tokens();
+        let amount_in = BigUint::from_str("50000000000000000000").unwrap();
+        let pool = lazy_test_pool();
+
+        let first = pool
+            .get_amount_out(amount_in.clone(), &token_x, &token_y)
+            .unwrap();
+        let second = first
+            .new_state
+            .get_amount_out(amount_in.clone(), &token_x, &token_y)
+            .unwrap();
+
+        let moved = first
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap();
+        let independent = UniswapV3State::new(
+            moved.liquidity,
+            moved.sqrt_price,
+            FeeAmount::Medium,
+            moved.tick,
+            lazy_test_ticks(),
+        );
+        let expected = independent
+            .get_amount_out(amount_in, &token_x, &token_y)
+            .unwrap();
+        assert_eq!(second.amount, expected.amount);
+        assert_eq!(second.gas, expected.gas);
+    }
+
+    #[test]
+    fn test_delta_transition_copies_shared_ticks() {
+        let (token_x, token_y) = lazy_test_tokens();
+        let pool = lazy_test_pool();
+        let res = pool
+            .get_amount_out(BigUint::from_str("1000000000000000000").unwrap(), &token_x, &token_y)
+            .unwrap();
+        let mut new_state = res.new_state;
+        let attributes: HashMap<String, Bytes> =
+            [("ticks/-600/net_liquidity".to_string(), Bytes::from(1_u64.to_be_bytes().to_vec()))]
+                .into_iter()
+                .collect();
+        let delta = ProtocolStateDelta {
+            component_id: "State1".to_owned(),
+            updated_attributes: attributes,
+            deleted_attributes: HashSet::new(),
+        };
+
+        new_state
+            .delta_transition(delta, &HashMap::new(), &Balances::default())
+            .unwrap();
+
+        let new_state = new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap();
+        assert_eq!(
+            new_state
+                .ticks
+                .get_tick(-600)
+                .unwrap()
+                .net_liquidity,
+            1
+        );
+        assert_eq!(
+            pool.ticks
+                .get_tick(-600)
+                .unwrap()
+                .net_liquidity,
+            1_000_000_000_000_000_000
+        );
+        assert!(!new_state
+            .ticks
+            .shares_ticks_with(&pool.ticks));
+    }
+
+    #[rstest]
+    #[case::within_window("1000000000000000000")]
+    #[case::beyond_window("100000000000000000000")]
+    fn test_lazy_ticks_match_full_state(#[case] amount_in: &str) {
+        let (token_x, token_y) = lazy_test_tokens();
+        let full = lazy_test_pool();
+        let provider = Arc::new(SnapshotTickProvider::new(lazy_test_ticks()));
+        let lazy = lazy_test_pool().with_lazy_ticks(3000, Some(provider));
+        let amount_in = BigUint::from_str(amount_in).unwrap();
+
+        let expected = full
+            .get_amount_out(amount_in.clone(), &token_x, &token_y)
+            .unwrap();
+        let res = lazy
+            .get_amount_out(amount_in, &token_x, &token_y)
+            .unwrap();
+
+        assert_eq!(res.amount, expected.amount);
+        assert_eq!(res.gas, expected.gas);
+        let new_state = res
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap();
+        let expected_state = expected
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap();
+        assert_eq!(new_state.tick, expected_state.tick);
+        assert_eq!(new_state.liquidity, expected_state.liquidity);
+    }
+
+    #[rstest]
+    #[case::zero_for_one(true)]
+    #[case::one_for_zero(false)]
+    fn test_max_input_is_swappable(#[case] zero_for_one: bool) {
+        let (token_x, token_y) = lazy_test_tokens();
+        let (token_in, token_out) =
+            if zero_for_one { (&token_x, &token_y) } else { (&token_y, &token_x) };
+        let pool = lazy_test_pool();
+
+        let max_input = pool
+            .max_input(token_in, token_out)
+            .unwrap();
+
+        let res = pool
+            .get_amount_out(u256_to_biguint(max_input), token_in, token_out)
+            .unwrap();
+        let new_state = res
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap();
+        // All liquidity is consumed
+        assert_eq!(new_state.liquidity, 0);
+        // Any larger amount runs out of ticks
+        let res = pool.get_amount_out(
+            u256_to_biguint(max_input + U256::from(1_000_000u64)),
+            token_in,
+            token_out,
+        );
+        assert!(matches!(res, Err(SimulationError::InvalidInput(_, Some(_)))));
+    }
+
+    #[test]
+    fn test_max_input_lazy_ticks_matches_full_state() {
+        let (token_x, token_y) = lazy_test_tokens();
+        let provider = Arc::new(SnapshotTickProvider::new(lazy_test_ticks()));
+        let lazy = lazy_test_pool().with_lazy_ticks(3000, Some(provider));
+
+        assert_eq!(
+            lazy.max_input(&token_x, &token_y)
+                .unwrap(),
+            lazy_test_pool()
+                .max_input(&token_x, &token_y)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_lazy_ticks_load_beyond_window() {
+        let (token_x, token_y) = lazy_test_tokens();
+        let provider = Arc::new(SnapshotTickProvider::new(lazy_test_ticks()));
+        let lazy = lazy_test_pool().with_lazy_ticks(3000, Some(provider));
+
+        let res = lazy
+            .get_amount_out(BigUint::from_str("100000000000000000000").unwrap(), &token_x, &token_y)
+            .unwrap();
+
+        let new_state = res
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap();
+        assert!(new_state.tick < -3000);
+    }
+
+    #[test]
+    fn test_lazy_ticks_without_provider() {
+        let (token_x, token_y) = lazy_test_tokens();
+        let lazy = lazy_test_pool().with_lazy_ticks(3000, None);
+
+        let res = lazy.get_amount_out(
+            BigUint::from_str("100000000000000000000").unwrap(),
+            &token_x,
+            &token_y,
+        );
+
+        assert!(
+            matches!(res, Err(SimulationError::RecoverableError(msg)) if msg.contains("TickRangeUnavailable"))
+        );
+    }
+
+    /// A delta moving the price to `tick` and setting the net liquidity of tick 6000 to 7.
+    fn lazy_test_delta(tick: i32) -> ProtocolStateDelta {
+        let attributes: HashMap<String, Bytes> = [
+            ("ticks/600/net_liquidity".to_string(), Bytes::from(7_u64.to_be_bytes().to_vec())),
+            ("ticks/6000/net_liquidity".to_string(), Bytes::from(7_u64.to_be_bytes().to_vec())),
+            ("tick".to_string(), Bytes::from(tick.to_be_bytes().to_vec())),
+            (
+                "sqrt_price_x96".to_string(),
+                Bytes::from(
+                    get_sqrt_ratio_at_tick(tick)
+                        .unwrap()
+                        .to_be_bytes::<32>()
+                        .to_vec(),
+                ),
+            ),
+        ]
+        .into_iter()
+        .collect();
+        ProtocolStateDelta {
+            component_id: "State1".to_owned(),
+            updated_attributes: attributes,
+            deleted_attributes: HashSet::new(),
+        }
+    }
+
+    #[rstest]
+    #[case::within_window(600)]
+    #[case::beyond_window(9000)]
+    fn test_lazy_ticks_track_updates_outside_window(#[case] tick: i32) {
+        let (token_x, token_y) = lazy_test_tokens();
+        let mut full = lazy_test_pool();
+        let provider = Arc::new(SnapshotTickProvider::new(lazy_test_ticks()));
+        let mut lazy = lazy_test_pool().with_lazy_ticks(3000, Some(provider));
+
+        for pool in [&mut full, &mut lazy] {
+            pool.delta_transition(lazy_test_delta(tick), &HashMap::new(), &Balances::default())
+                .unwrap();
+        }
+
+        assert!(lazy
+            .lazy_ticks
+            .as_ref()
+            .unwrap()
+            .loaded
+            .contains(tick));
+        let amount_in = BigUint::from_str("1000000000000000000").unwrap();
+        for (token_in, token_out) in [(&token_x, &token_y), (&token_y, &token_x)] {
+            let expected = full
+                .get_amount_out(amount_in.clone(), token_in, token_out)
+                .unwrap();
+            let res = lazy
+                .get_amount_out(amount_in.clone(), token_in, token_out)
+                .unwrap();
+            assert_eq!(res.amount, expected.amount);
+        }
+    }
+
+    #[test]
+    fn test_lazy_ticks_outside_window_without_provider() {
+        let (token_x, token_y) = lazy_test_tokens();
+        let mut lazy = lazy_test_pool().with_lazy_ticks(3000, None);
+
+        lazy.delta_transition(lazy_test_delta(9000), &HashMap::new(), &Balances::default())
+            .unwrap();
+        let res = lazy.get_amount_out(
+            BigUint::from_str("1000000000000000000").unwrap(),
+            &token_x,
+            &token_y,
+        );
+
+        // the ticks around the new price are not known, so the quote fails instead of using
+        // stale liquidity
+        assert!(
+            matches!(res, Err(SimulationError::RecoverableError(msg)) if msg.contains("TickRangeUnavailable"))
+        );
+    }
+
+    #[rstest]
+    #[case::small("1000000000000000")]
+    #[case::large("100000000000000000000")]
+    fn test_price_limit_matches_unlimited_until_reached(#[case] amount_in: &str) {
+        let (token_x, token_y) = lazy_test_tokens();
+        let pool = lazy_test_pool();
+        let amount_in = BigUint::from_str(amount_in).unwrap();
+        let limit = get_sqrt_ratio_at_tick(-300).unwrap();
+
+        let unlimited = pool
+            .get_amount_out(amount_in.clone(), &token_x, &token_y)
+            .unwrap();
+        let limited = pool
+            .get_amount_out_with_price_limit(amount_in.clone(), &token_x, &token_y, limit)
+            .unwrap();
+
+        let unlimited_price = unlimited
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap()
+            .sqrt_price;
+        if unlimited_price > limit {
+            assert!(!limited.limit_reached);
+            assert_eq!(BigUint::from(&limited.amount_in), amount_in);
+            assert_eq!(BigUint::from(&limited.amount_out), unlimited.amount);
+        } else {
+            assert!(limited.limit_reached);
+            assert!(BigUint::from(&limited.amount_in) < amount_in);
+            assert!(BigUint::from(&limited.amount_out) < unlimited.amount);
+        }
+    }
+
+    #[rstest]
+    #[case::zero_for_one_non_binding(true, "1000000000000000", false)]
+    #[case::zero_for_one_binding(true, "100000000000000000000", true)]
+    #[case::one_for_zero_non_binding(false, "1000000000000000", false)]
+    #[case::one_for_zero_binding(false, "100000000000000000000", true)]
+    fn test_price_limit_both_directions(
+        #[case] zero_for_one: bool,
+        #[case] amount_in: &str,
+        #[case] binding: bool,
+    ) {
+        let (token_x, token_y) = lazy_test_tokens();
+        let (token_in, token_out) =
+            if zero_for_one { (&token_x, &token_y) } else { (&token_y, &token_x) };
+        let pool = lazy_test_pool();
+        let amount_in = BigUint::from_str(amount_in).unwrap();
+        let limit = get_sqrt_ratio_at_tick(if zero_for_one { -300 } else { 300 }).unwrap();
+
+        let unlimited = pool
+            .get_amount_out(amount_in.clone(), token_in, token_out)
+            .unwrap();
+        let limited = pool
+            .get_amount_out_with_price_limit(amount_in.clone(), token_in, token_out, limit)
+            .unwrap();
+
+        let limited_price = limited
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap()
+            .sqrt_price;
+        assert_eq!(limited.limit_reached, binding);
+        if binding {
+            assert_eq!(limited_price, limit);
+            assert!(BigUint::from(&limited.amount_in) < amount_in);
+            assert!(BigUint::from(&limited.amount_out) < unlimited.amount);
+        } else {
+            assert_ne!(limited_price, limit);
+            assert_eq!(BigUint::from(&limited.amount_in), amount_in);
+            assert_eq!(BigUint::from(&limited.amount_out), unlimited.amount);
+        }
+    }
+
+    #[test]
+    fn test_price_limit_stop_point_matches_tick_math() {
+        let (token_x, token_y) = lazy_test_tokens();
+        let pool = lazy_test_pool();
+        let limit = get_sqrt_ratio_at_tick(-300).unwrap();
+        // No initialized tick between 0 and -300, so the swap is a single step.
+        let amount0 =
+            get_amount0_delta(limit, pool.sqrt_price, pool.liquidity, Rounding::Up).unwrap();
+        let fee = (amount0 * U256::from(3_000) + U256::from(996_999)) / U256::from(997_000);
+        let amount1 =
+            get_amount1_delta(limit, pool.sqrt_price, pool.liquidity, Rounding::Down).unwrap();
+
+        let res = pool
+            .get_amount_out_with_price_limit(
+                BigUint::from_str("100000000000000000000").unwrap(),
+                &token_x,
+                &token_y,
+                limit,
+            )
+            .unwrap();
+
+        let new_state = res
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap();
+        assert!(res.limit_reached);
+        assert_eq!(new_state.sqrt_price, limit);
+        assert_eq!(res.amount_in, TokenAmount::new(amount0 + fee, 18));
+        assert_eq!(res.amount_out, TokenAmount::new(amount1, 18));
+    }
+
+    #[test]
+    fn test_price_limit_exact_out() {
+        let (token_x, token_y) = lazy_test_tokens();
+        let pool = lazy_test_pool();
+        let limit = get_sqrt_ratio_at_tick(-300).unwrap();
+        let amount1 =
+            get_amount1_delta(limit, pool.sqrt_price, pool.liquidity, Rounding::Down).unwrap();
+
+        let small = pool
+            .get_amount_in_with_price_limit(BigUint::from(1_000_000u64), &token_x, &token_y, limit)
+            .unwrap();
+        let large = pool
+            .get_amount_in_with_price_limit(
+                BigUint::from_str("100000000000000000000").unwrap(),
+                &token_x,
+                &token_y,
+                limit,
+            )
+            .unwrap();
+
+        assert!(!small.limit_reached);
+        assert_eq!(small.amount_out.raw, U256::from(1_000_000u64));
+        assert!(large.limit_reached);
+        assert_eq!(large.amount_out, TokenAmount::new(amount1, 18));
+        let new_state = large
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap();
+        assert_eq!(new_state.sqrt_price, limit);
+    }
+
+    #[rstest]
+    #[case::above_current_price(get_sqrt_ratio_at_tick(300).unwrap(), true)]
+    #[case::at_current_price(get_sqrt_ratio_at_tick(0).unwrap(), true)]
+    #[case::at_min_ratio(MIN_SQRT_RATIO, false)]
+    fn test_price_limit_wrong_side(#[case] limit: U256, #[case] wrong_direction: bool) {
+        let (token_x, token_y) = lazy_test_tokens();
+        let pool = lazy_test_pool();
+
+        let res = pool.get_amount_out_with_price_limit(
+            BigUint::from(1_000u64),
+            &token_x,
+            &token_y,
+            limit,
+        );
+
+        match res {
+            Err(LimitedSwapError::PriceLimit(PriceLimitError::WrongDirection { .. })) => {
+                assert!(wrong_direction)
+            }
+            Err(LimitedSwapError::PriceLimit(PriceLimitError::OutOfRange(_))) => {
+                assert!(!wrong_direction)
+            }
+            other => panic!("unexpected result {other:?}"),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -8,10 +8,10 @@ use tycho_common::{dto::ProtocolStateDelta, Bytes};
 
 use crate::{
     evm::protocol::{
-        safe_math::{safe_add_u256, safe_sub_u256},
+        safe_math::{safe_add_u256, safe_sub_u256, Rounding},
         u256_num::u256_to_biguint,
         utils::uniswap::{
-            i24_be_bytes_to_i32, liquidity_math,
+            i24_be_bytes_to_i32,
             sqrt_price_math::{get_amount0_delta, get_amount1_delta, sqrt_price_q96_to_f64},
             swap_math,
             tick_list::{TickInfo, TickList, TickListErrorKind},
@@ -185,14 +185,9 @@ impl UniswapV4State {
             }
             if state.sqrt_price == step.sqrt_price_next {
                 if step.initialized {
-                    let liquidity_raw = self
-                        .ticks
-                        .get_tick(step.tick_next)
-                        .unwrap()
-                        .net_liquidity;
-                    let liquidity_net = if zero_for_one { -liquidity_raw } else { liquidity_raw };
                     state.liquidity =
-                        liquidity_math::add_liquidity_delta(state.liquidity, liquidity_net);
+                        self.ticks
+                            .cross(step.tick_next, state.liquidity, zero_for_one);
                 }
                 state.tick = if zero_for_one { step.tick_next - 1 } else { step.tick_next };
             } else if state.sqrt_price != step.sqrt_price_start {
@@ -201,6 +196,7 @@ impl UniswapV4State {
             gas_used = safe_add_u256(gas_used, U256::from(2000))?;
         }
         Ok(SwapResults {
+            amount_remaining: state.amount_remaining,
             amount_calculated: state.amount_calculated,
             sqrt_price: state.sqrt_price,
             liquidity: state.liquidity,
@@ -319,13 +315,13 @@ impl ProtocolSim for UniswapV4State {
                     sqrt_price_next,
                     current_sqrt_price,
                     current_liquidity,
-                    true,
+                    Rounding::Up,
                 )?;
                 let amount1 = get_amount1_delta(
                     sqrt_price_next,
                     current_sqrt_price,
                     current_liquidity,
-                    false,
+                    Rounding::Down,
                 )?;
                 (amount0, amount1)
             } else {
@@ -333,13 +329,13 @@ impl ProtocolSim for UniswapV4State {
                     sqrt_price_next,
                     current_sqrt_price,
                     current_liquidity,
-                    false,
+                    Rounding::Down,
                 )?;
                 let amount1 = get_amount1_delta(
                     sqrt_price_next,
                     current_sqrt_price,
                     current_liquidity,
-                    true,
+                    Rounding::Up,
                 )?;
                 (amount1, amount0)
             };
@@ -353,14 +349,9 @@ impl ProtocolSim for UniswapV4State {
             // For zero_for_one, liquidity is removed when crossing a tick
             // For one_for_zero, liquidity is added when crossing a tick
             if initialized {
-                let liquidity_raw = self
+                current_liquidity = self
                     .ticks
-                    .get_tick(next_tick)
-                    .unwrap()
-                    .net_liquidity;
-                let liquidity_delta = if zero_for_one { -liquidity_raw } else { liquidity_raw };
-                current_liquidity =
-                    liquidity_math::add_liquidity_delta(current_liquidity, liquidity_delta);
+                    .cross(next_tick, current_liquidity, zero_for_one);
             }
 
             // Move to the next tick position
@@ -371,6 +362,22 @@ impl ProtocolSim for UniswapV4State {
         Ok((u256_to_biguint(total_amount_in), u256_to_biguint(total_amount_out)))
     }
 
+    fn dump(&self) -> Option<Vec<u8>> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&self.liquidity.to_be_bytes());
+        out.extend_from_slice(&self.sqrt_price.to_be_bytes::<32>());
+        for fee in [self.fees.zero_for_one, self.fees.one_for_zero, self.fees.lp_fee] {
+            out.extend_from_slice(&fee.to_be_bytes());
+        }
+        out.extend_from_slice(&self.tick.to_be_bytes());
+        self.ticks.encode(&mut out);
+        Some(out)
+    }
+
+    fn approx_heap_size(&self) -> usize {
+        self.ticks.heap_size()
+    }
+
     fn delta_transition(
         &mut self,
         delta: ProtocolStateDelta,
@@ -411,7 +418,7 @@ impl ProtocolSim for UniswapV4State {
 
         // apply tick changes
         for (key, value) in delta.updated_attributes.iter() {
-            // tick liquidity keys are in the format "tick/{tick_index}/net_liquidity"
+            // tick liquidity keys are in the format "ticks/{tick_index}/net_liquidity"
             if key.starts_with("ticks/") {
                 let parts: Vec<&str> = key.split('/').collect();
                 self.ticks.set_tick_liquidity(
@@ -424,8 +431,8 @@ impl ProtocolSim for UniswapV4State {
         }
         // delete ticks - ignores deletes for attributes other than tick liquidity
         for key in delta.deleted_attributes.iter() {
-            // tick liquidity keys are in the format "tick/{tick_index}/net_liquidity"
-            if key.starts_with("tick/") {
+            // tick liquidity keys are in the format "ticks/{tick_index}/net_liquidity"
+            if key.starts_with("ticks/") {
                 let parts: Vec<&str> = key.split('/').collect();
                 self.ticks.set_tick_liquidity(
                     parts[1]
@@ -2,11 +2,12 @@ use alloy_primitives::{I256, U256};
 use tycho_common::Bytes;
 
 pub(crate) mod liquidity_math;
-mod solidity_math;
+pub(crate) mod solidity_math;
 pub(crate) mod sqrt_price_math;
 pub(crate) mod swap_math;
 pub mod tick_list;
 pub(crate) mod tick_math;
+pub mod tick_provider;
 
 #[derive(Debug)]
 pub(crate) struct SwapState {
@@ -30,6 +31,7 @@ pub(crate) struct StepComputation {
 
 #[derive(Debug)]
 pub(crate) struct SwapResults {
+    pub(crate) amount_remaining: I256,
     pub(crate) amount_calculated: I256,
     pub(crate) sqrt_price: U256,
     pub(crate) liquidity: u128,
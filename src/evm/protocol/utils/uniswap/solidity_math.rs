@@ -1,29 +1,26 @@
 use alloy_primitives::{U256, U512};
 
 use crate::{
-    evm::protocol::safe_math::{div_mod_u512, safe_div_u512, safe_mul_u512},
+    evm::protocol::safe_math::{div_mod_u512, safe_mul_u512, Rounding},
     protocol::errors::SimulationError,
 };
 
-pub(super) fn mul_div_rounding_up(a: U256, b: U256, denom: U256) -> Result<U256, SimulationError> {
-    let a_big = U512::from(a);
-    let b_big = U512::from(b);
-    let product = safe_mul_u512(a_big, b_big)?;
+/// `a * b / denom` with a 512 bit intermediate product, rounded in the given direction
+/// (`FullMath.mulDiv` and `FullMath.mulDivRoundingUp`).
+pub(crate) fn mul_div(
+    a: U256,
+    b: U256,
+    denom: U256,
+    rounding: Rounding,
+) -> Result<U256, SimulationError> {
+    let product = safe_mul_u512(U512::from(a), U512::from(b))?;
     let (mut result, rest) = div_mod_u512(product, U512::from(denom))?;
-    if rest >= U512::from(0u64) {
+    if rounding == Rounding::Up && !rest.is_zero() {
         result += U512::from(1u64);
     }
     truncate_to_u256(result)
 }
 
-pub(super) fn mul_div(a: U256, b: U256, denom: U256) -> Result<U256, SimulationError> {
-    let a_big = U512::from(a);
-    let b_big = U512::from(b);
-    let product = safe_mul_u512(a_big, b_big)?;
-    let result = safe_div_u512(product, U512::from(denom))?;
-    truncate_to_u256(result)
-}
-
 fn truncate_to_u256(value: U512) -> Result<U256, SimulationError> {
     // Access the limbs of the U512 value
     let limbs = value.as_limbs();
@@ -46,7 +43,17 @@ mod tests {
         let a = U256::from(23);
         let b = U256::from(10);
         let denom = U256::from(50);
-        let res = mul_div_rounding_up(a, b, denom).unwrap();
+        let res = mul_div(a, b, denom, Rounding::Up).unwrap();
+
+        assert_eq!(res, U256::from(5));
+    }
+
+    #[test]
+    fn test_mul_div_rounding_up_exact() {
+        let a = U256::from(25);
+        let b = U256::from(10);
+        let denom = U256::from(50);
+        let res = mul_div(a, b, denom, Rounding::Up).unwrap();
 
         assert_eq!(res, U256::from(5));
     }
@@ -56,7 +63,7 @@ mod tests {
         let (a, b) = (U256::MAX, U256::MAX);
         let denom = U256::from(1);
 
-        let result = mul_div_rounding_up(a, b, denom);
+        let result = mul_div(a, b, denom, Rounding::Up);
 
         assert!(matches!(result, Err(SimulationError::FatalError(_))));
     }
@@ -66,7 +73,7 @@ mod tests {
         let a = U256::from(23);
         let b = U256::from(10);
         let denom = U256::from(50);
-        let res = mul_div(a, b, denom).unwrap();
+        let res = mul_div(a, b, denom, Rounding::Down).unwrap();
 
         assert_eq!(res, U256::from(4));
     }
@@ -76,7 +83,7 @@ mod tests {
         let (a, b) = (U256::MAX, U256::MAX);
         let denom = U256::from(1);
 
-        let result = mul_div(a, b, denom);
+        let result = mul_div(a, b, denom, Rounding::Down);
 
         assert!(matches!(result, Err(SimulationError::FatalError(_))));
     }
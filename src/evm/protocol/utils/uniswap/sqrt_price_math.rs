@@ -1,9 +1,9 @@
 use alloy_primitives::U256;
 
-use super::solidity_math::{mul_div, mul_div_rounding_up};
+use super::solidity_math::mul_div;
 use crate::{
     evm::protocol::{
-        safe_math::{div_mod_u256, safe_add_u256, safe_div_u256, safe_mul_u256, safe_sub_u256},
+        safe_math::{div_u256, safe_add_u256, safe_div_u256, safe_sub_u256, Rounding},
         u256_num::u256_to_f64,
     },
     protocol::errors::SimulationError,
@@ -21,21 +21,13 @@ fn maybe_flip_ratios(a: U256, b: U256) -> (U256, U256) {
     }
 }
 
-fn div_rounding_up(a: U256, b: U256) -> Result<U256, SimulationError> {
-    let (result, rest) = div_mod_u256(a, b)?;
-    if rest > U256::from(0u64) {
-        let res = safe_add_u256(result, U256::from(1u64))?;
-        Ok(res)
-    } else {
-        Ok(result)
-    }
-}
-
+/// Amount of token 0 between two sqrt prices. Amounts owed to the pool must be rounded up,
+/// amounts paid out of it down.
 pub(crate) fn get_amount0_delta(
     a: U256,
     b: U256,
     liquidity: u128,
-    round_up: bool,
+    rounding: Rounding,
 ) -> Result<U256, SimulationError> {
     let (sqrt_ratio_a, sqrt_ratio_b) = maybe_flip_ratios(a, b);
 
@@ -44,28 +36,18 @@ pub(crate) fn get_amount0_delta(
 
     assert!(sqrt_ratio_a > U256::from(0u64));
 
-    if round_up {
-        div_rounding_up(mul_div_rounding_up(numerator1, numerator2, sqrt_ratio_b)?, sqrt_ratio_a)
-    } else {
-        safe_div_u256(mul_div_rounding_up(numerator1, numerator2, sqrt_ratio_b)?, sqrt_ratio_a)
-    }
+    div_u256(mul_div(numerator1, numerator2, sqrt_ratio_b, rounding)?, sqrt_ratio_a, rounding)
 }
 
+/// Amount of token 1 between two sqrt prices, see [`get_amount0_delta`].
 pub(crate) fn get_amount1_delta(
     a: U256,
     b: U256,
     liquidity: u128,
-    round_up: bool,
+    rounding: Rounding,
 ) -> Result<U256, SimulationError> {
     let (sqrt_ratio_a, sqrt_ratio_b) = maybe_flip_ratios(a, b);
-    if round_up {
-        mul_div_rounding_up(U256::from(liquidity), sqrt_ratio_b - sqrt_ratio_a, Q96)
-    } else {
-        safe_div_u256(
-            safe_mul_u256(U256::from(liquidity), safe_sub_u256(sqrt_ratio_b, sqrt_ratio_a)?)?,
-            Q96,
-        )
-    }
+    mul_div(U256::from(liquidity), safe_sub_u256(sqrt_ratio_b, sqrt_ratio_a)?, Q96, rounding)
 }
 
 pub(super) fn get_next_sqrt_price_from_input(
@@ -116,17 +98,21 @@ fn get_next_sqrt_price_from_amount0_rounding_up(
             // No overflow case: liquidity * sqrtPX96 / (liquidity +- amount * sqrtPX96)
             let denominator = safe_add_u256(numerator1, product)?;
             if denominator >= numerator1 {
-                return mul_div_rounding_up(numerator1, sqrt_price, denominator);
+                return mul_div(numerator1, sqrt_price, denominator, Rounding::Up);
             }
         }
         // Overflow: liquidity / (liquidity / sqrtPX96 +- amount)
-        div_rounding_up(numerator1, safe_add_u256(safe_div_u256(numerator1, sqrt_price)?, amount)?)
+        div_u256(
+            numerator1,
+            safe_add_u256(div_u256(numerator1, sqrt_price, Rounding::Down)?, amount)?,
+            Rounding::Up,
+        )
     } else {
         let (product, _) = amount.overflowing_mul(sqrt_price);
         assert!(safe_div_u256(product, amount)? == sqrt_price && numerator1 > product);
         let denominator = safe_sub_u256(numerator1, product)?;
         // No overflow case: liquidity * sqrtPX96 / (liquidity +- amount * sqrtPX96)
-        mul_div_rounding_up(numerator1, sqrt_price, denominator)
+        mul_div(numerator1, sqrt_price, denominator, Rounding::Up)
     }
 }
 
@@ -138,17 +124,17 @@ fn get_next_sqrt_price_from_amount1_rounding_down(
 ) -> Result<U256, SimulationError> {
     if add {
         let quotient = if amount <= U160_MAX {
-            safe_div_u256(amount << RESOLUTION, U256::from(liquidity))
+            div_u256(amount << RESOLUTION, U256::from(liquidity), Rounding::Down)
         } else {
-            mul_div(amount, Q96, U256::from(liquidity))
+            mul_div(amount, Q96, U256::from(liquidity), Rounding::Down)
         };
 
         safe_add_u256(sqrt_price, quotient?)
     } else {
         let quotient = if amount <= U160_MAX {
-            div_rounding_up(amount << RESOLUTION, U256::from(liquidity))?
+            div_u256(amount << RESOLUTION, U256::from(liquidity), Rounding::Up)?
         } else {
-            mul_div_rounding_up(amount, Q96, U256::from(liquidity))?
+            mul_div(amount, Q96, U256::from(liquidity), Rounding::Up)?
         };
 
         assert!(sqrt_price > quotient);
@@ -196,38 +182,38 @@ mod tests {
         u256("646922711029656030980122427077"),
         u256("78833030112140176575862854579"),
         1000000000000u128,
-        true,
+        Rounding::Up,
         u256("882542983628")
     )]
     #[case(
         u256("646922711029656030980122427077"),
         u256("78833030112140176575862854579"),
         1000000000000u128,
-        false,
+        Rounding::Down,
         u256("882542983627")
     )]
     #[case(
         u256("79224201403219477170569942574"),
         u256("79394708140106462983274643745"),
         10000000u128,
-        true,
+        Rounding::Up,
         u256("21477")
     )]
     #[case(
         u256("79224201403219477170569942574"),
         u256("79394708140106462983274643745"),
         10000000u128,
-        false,
+        Rounding::Down,
         u256("21476")
     )]
     fn test_get_amount0_delta(
         #[case] a: U256,
         #[case] b: U256,
         #[case] liquidity: u128,
-        #[case] round_up: bool,
+        #[case] rounding: Rounding,
         #[case] exp: U256,
     ) {
-        let res = get_amount0_delta(a, b, liquidity, round_up).unwrap();
+        let res = get_amount0_delta(a, b, liquidity, rounding).unwrap();
         assert_eq!(res, exp);
     }
 
@@ -236,38 +222,38 @@ mod tests {
         u256("79224201403219477170569942574"),
         u256("79394708140106462983274643745"),
         10000000u128,
-        true,
+        Rounding::Up,
         u256("21521")
     )]
     #[case(
         u256("79224201403219477170569942574"),
         u256("79394708140106462983274643745"),
         10000000u128,
-        false,
+        Rounding::Down,
         u256("21520")
     )]
     #[case(
         u256("646922711029656030980122427077"),
         u256("78833030112140176575862854579"),
         1000000000000u128,
-        true,
+        Rounding::Up,
         u256("7170299838965")
     )]
     #[case(
         u256("646922711029656030980122427077"),
         u256("78833030112140176575862854579"),
         1000000000000u128,
-        false,
+        Rounding::Down,
         u256("7170299838964")
     )]
     fn test_get_amount1_delta(
         #[case] a: U256,
         #[case] b: U256,
         #[case] liquidity: u128,
-        #[case] round_up: bool,
+        #[case] rounding: Rounding,
         #[case] exp: U256,
     ) {
-        let res = get_amount1_delta(a, b, liquidity, round_up).unwrap();
+        let res = get_amount1_delta(a, b, liquidity, rounding).unwrap();
         assert_eq!(res, exp);
     }
 
@@ -1,10 +1,10 @@
 use alloy_primitives::{I256, U256};
 
-use super::{
-    solidity_math::{mul_div, mul_div_rounding_up},
-    sqrt_price_math,
+use super::{solidity_math::mul_div, sqrt_price_math};
+use crate::{
+    evm::protocol::safe_math::{safe_sub_u256, Rounding},
+    protocol::errors::SimulationError,
 };
-use crate::{evm::protocol::safe_math::safe_sub_u256, protocol::errors::SimulationError};
 
 pub(crate) fn compute_swap_step(
     sqrt_ratio_current: U256,
@@ -24,20 +24,21 @@ pub(crate) fn compute_swap_step(
             amount_remaining.into_raw(),
             U256::from(1_000_000 - fee_pips),
             U256::from(1_000_000),
+            Rounding::Down,
         )?;
         amount_in = if zero_for_one {
             sqrt_price_math::get_amount0_delta(
                 sqrt_ratio_target,
                 sqrt_ratio_current,
                 liquidity,
-                true,
+                Rounding::Up,
             )?
         } else {
             sqrt_price_math::get_amount1_delta(
                 sqrt_ratio_current,
                 sqrt_ratio_target,
                 liquidity,
-                true,
+                Rounding::Up,
             )?
         };
         if amount_remaining_less_fee >= amount_in {
@@ -56,14 +57,14 @@ pub(crate) fn compute_swap_step(
                 sqrt_ratio_target,
                 sqrt_ratio_current,
                 liquidity,
-                false,
+                Rounding::Down,
             )?
         } else {
             sqrt_price_math::get_amount0_delta(
                 sqrt_ratio_current,
                 sqrt_ratio_target,
                 liquidity,
-                false,
+                Rounding::Down,
             )?
         };
         if amount_remaining.abs().into_raw() > amount_out {
@@ -88,7 +89,7 @@ pub(crate) fn compute_swap_step(
                 sqrt_ratio_next,
                 sqrt_ratio_current,
                 liquidity,
-                true,
+                Rounding::Up,
             )?
         };
         amount_out = if max && !exact_in {
@@ -98,7 +99,7 @@ pub(crate) fn compute_swap_step(
                 sqrt_ratio_next,
                 sqrt_ratio_current,
                 liquidity,
-                false,
+                Rounding::Down,
             )?
         }
     } else {
@@ -109,7 +110,7 @@ pub(crate) fn compute_swap_step(
                 sqrt_ratio_current,
                 sqrt_ratio_next,
                 liquidity,
-                true,
+                Rounding::Up,
             )?
         };
         amount_out = if max && !exact_in {
@@ -119,7 +120,7 @@ pub(crate) fn compute_swap_step(
                 sqrt_ratio_current,
                 sqrt_ratio_next,
                 liquidity,
-                false,
+                Rounding::Down,
             )?
         };
     }
@@ -131,7 +132,7 @@ pub(crate) fn compute_swap_step(
     let fee_amount = if exact_in && sqrt_ratio_next != sqrt_ratio_target {
         safe_sub_u256(amount_remaining.abs().into_raw(), amount_in)?
     } else {
-        mul_div_rounding_up(amount_in, U256::from(fee_pips), U256::from(1_000_000 - fee_pips))?
+        mul_div(amount_in, U256::from(fee_pips), U256::from(1_000_000 - fee_pips), Rounding::Up)?
     };
     Ok((sqrt_ratio_next, amount_in, amount_out, fee_amount))
 }
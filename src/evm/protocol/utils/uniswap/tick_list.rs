@@ -1,8 +1,11 @@
-use std::cmp;
+use std::{cmp, mem, sync::Arc};
 
 use alloy_primitives::U256;
 
-use super::tick_math::{self, MAX_TICK, MIN_TICK};
+use super::{
+    liquidity_math,
+    tick_math::{self, MAX_TICK, MIN_TICK},
+};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct TickInfo {
@@ -20,6 +23,16 @@ impl TickInfo {
     }
 }
 
+impl InitializedTick for TickInfo {
+    fn index(&self) -> i32 {
+        self.index
+    }
+
+    fn net_liquidity(&self) -> i128 {
+        self.net_liquidity
+    }
+}
+
 impl PartialOrd for TickInfo {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         self.index.partial_cmp(&other.index)
@@ -33,21 +46,126 @@ pub(crate) struct TickListError {
 
 #[derive(Debug, PartialEq)]
 pub(crate) enum TickListErrorKind {
+    #[cfg(test)]
     NotFound,
     BelowSmallest,
     AtOrAboveLargest,
     TicksExeeded,
 }
 
+/// An inclusive range of tick indices for which all initialized ticks are known.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TickRange {
+    pub lower: i32,
+    pub upper: i32,
+}
+
+impl TickRange {
+    pub fn new(lower: i32, upper: i32) -> Self {
+        TickRange { lower: lower.max(MIN_TICK), upper: upper.min(MAX_TICK) }
+    }
+
+    pub fn contains(&self, tick: i32) -> bool {
+        self.lower <= tick && tick <= self.upper
+    }
+
+    /// The same ticks as a half-open range `[lower, upper)`, as taken by the liquidity queries.
+    pub fn half_open(&self) -> (i32, i32) {
+        (self.lower, self.upper + 1)
+    }
+}
+
+/// A tick at which the active liquidity changes as the price crosses it.
+///
+/// Uniswap V3 and Ekubo share the convention for liquidity ranges: a position between `lower` and
+/// `upper` is active while `lower <= tick < upper`, see [`is_active`]. It adds its liquidity to the
+/// net liquidity of `lower` and subtracts it from the one of `upper`. The range queries below take
+/// ranges with the same half-open semantics. Only the tick units and bounds differ between the
+/// protocols, so ranges can't be compared across them by index.
+pub(crate) trait InitializedTick {
+    fn index(&self) -> i32;
+    /// Liquidity added when crossing the tick upwards and removed when crossing it downwards.
+    fn net_liquidity(&self) -> i128;
+}
+
+/// Whether a position between `lower` and `upper` provides liquidity at `tick`.
+pub(crate) fn is_active(tick: i32, lower: i32, upper: i32) -> bool {
+    lower <= tick && tick < upper
+}
+
+/// Sum of the net liquidity of the ticks in `[lower, upper)`.
+///
+/// This is the change of the active liquidity from tick `lower - 1` to tick `upper - 1`.
+pub(crate) fn liquidity_net_in_range<T: InitializedTick>(
+    ticks: &[T],
+    lower: i32,
+    upper: i32,
+) -> i128 {
+    if lower >= upper {
+        return 0;
+    }
+    let start = ticks.partition_point(|t| t.index() < lower);
+    let end = ticks.partition_point(|t| t.index() < upper);
+    ticks[start..end]
+        .iter()
+        .map(InitializedTick::net_liquidity)
+        .sum()
+}
+
+/// Liquidity of all positions active at `tick`, i.e. the sum of the net liquidity of all ticks
+/// at or below it.
+///
+/// `ticks` must be ordered by index and hold all initialized ticks at or below `tick`, otherwise
+/// the result is meaningless and a negative sum is returned as zero.
+pub(crate) fn active_liquidity_at<T: InitializedTick>(ticks: &[T], tick: i32) -> u128 {
+    let end = ticks.partition_point(|t| t.index() <= tick);
+    to_liquidity(
+        ticks[..end]
+            .iter()
+            .map(InitializedTick::net_liquidity)
+            .sum(),
+    )
+}
+
+/// Liquidity active at every tick of `[lower, upper)`, i.e. the lowest active liquidity within
+/// the range. Zero for an empty range.
+///
+/// Has the same requirements on `ticks` as [`active_liquidity_at`] for the tick `lower`.
+pub(crate) fn liquidity_in_range<T: InitializedTick>(ticks: &[T], lower: i32, upper: i32) -> u128 {
+    if lower >= upper {
+        return 0;
+    }
+    let start = ticks.partition_point(|t| t.index() <= lower);
+    let end = ticks.partition_point(|t| t.index() < upper);
+    let mut liquidity: i128 = ticks[..start]
+        .iter()
+        .map(InitializedTick::net_liquidity)
+        .sum();
+    let mut lowest = liquidity;
+    for tick in &ticks[start..end] {
+        liquidity += tick.net_liquidity();
+        lowest = lowest.min(liquidity);
+    }
+    to_liquidity(lowest)
+}
+
+fn to_liquidity(net_liquidity: i128) -> u128 {
+    u128::try_from(net_liquidity).unwrap_or_default()
+}
+
+/// Initialized ticks of a pool, ordered by index.
+///
+/// The ticks are shared between clones and only copied when a clone modifies them. Swaps never
+/// modify ticks, so the states returned by quotes share the ticks of the quoted state.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub(crate) struct TickList {
     tick_spacing: u16,
-    ticks: Vec<TickInfo>,
+    ticks: Arc<Vec<TickInfo>>,
 }
 
 impl TickList {
     pub(crate) fn from(spacing: u16, ticks: Vec<TickInfo>) -> Self {
-        let tick_list = TickList { tick_spacing: spacing, ticks };
+        let tick_list = TickList { tick_spacing: spacing, ticks: Arc::new(ticks) };
         let valid = tick_list.valid_ticks();
         if valid.is_ok() {
             tick_list
@@ -56,6 +174,22 @@ impl TickList {
         }
     }
 
+    /// Number of initialized ticks held by the list.
+    pub(crate) fn len(&self) -> usize {
+        self.ticks.len()
+    }
+
+    /// Heap memory of the ticks in bytes. Clones share the ticks, but each of them counts them.
+    pub(crate) fn heap_size(&self) -> usize {
+        self.ticks.capacity() * mem::size_of::<TickInfo>()
+    }
+
+    /// Whether both lists share the same tick storage, i.e. neither was modified since one was
+    /// cloned from the other.
+    pub(crate) fn shares_ticks_with(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.ticks, &other.ticks)
+    }
+
     // Asserts that all attributes are valid. Checks for:
     // 1. Tick spacing > 0
     // 2. Tick indexes have no rest when divided by tick spacing
@@ -95,43 +229,90 @@ impl TickList {
 
     #[allow(dead_code)]
     fn upsert_tick(&mut self, tick: i32, delta: i128) {
-        match self
-            .ticks
-            .binary_search_by(|t| t.index.cmp(&tick))
-        {
+        let ticks = Arc::make_mut(&mut self.ticks);
+        match ticks.binary_search_by(|t| t.index.cmp(&tick)) {
             Ok(existing_idx) => {
-                let tick = &mut self.ticks[existing_idx];
+                let tick = &mut ticks[existing_idx];
                 tick.net_liquidity += delta;
                 if tick.net_liquidity == 0 {
-                    self.ticks.remove(existing_idx);
+                    ticks.remove(existing_idx);
                 }
             }
             Err(insert_idx) => {
-                self.ticks
-                    .insert(insert_idx, TickInfo::new(tick, delta));
+                ticks.insert(insert_idx, TickInfo::new(tick, delta));
             }
         }
     }
 
     pub(crate) fn set_tick_liquidity(&mut self, tick: i32, liquidity: i128) {
-        match self
-            .ticks
-            .binary_search_by(|t| t.index.cmp(&tick))
-        {
+        let ticks = Arc::make_mut(&mut self.ticks);
+        match ticks.binary_search_by(|t| t.index.cmp(&tick)) {
             Ok(existing_idx) => {
-                let tick = &mut self.ticks[existing_idx];
+                let tick = &mut ticks[existing_idx];
                 tick.net_liquidity = liquidity;
                 if tick.net_liquidity == 0 {
-                    self.ticks.remove(existing_idx);
+                    ticks.remove(existing_idx);
                 }
             }
+            // Clearing a tick that isn't in the list leaves the list unchanged.
+            Err(_) if liquidity == 0 => {}
             Err(insert_idx) => {
-                self.ticks
-                    .insert(insert_idx, TickInfo::new(tick, liquidity));
+                ticks.insert(insert_idx, TickInfo::new(tick, liquidity));
             }
         }
     }
 
+    /// Merges the given ticks into the list, overwriting existing ticks with the same index.
+    pub(crate) fn extend(&mut self, ticks: impl IntoIterator<Item = TickInfo>) {
+        for tick in ticks {
+            self.set_tick_liquidity(tick.index, tick.net_liquidity);
+        }
+    }
+
+    /// Appends a canonical encoding of the spacing and ticks to `out`, for fingerprinting the
+    /// state holding the list.
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.tick_spacing.to_be_bytes());
+        out.extend_from_slice(&(self.ticks.len() as u64).to_be_bytes());
+        for tick in self.ticks.iter() {
+            out.extend_from_slice(&tick.index.to_be_bytes());
+            out.extend_from_slice(&tick.net_liquidity.to_be_bytes());
+        }
+    }
+
+    /// Removes all ticks outside the given range.
+    pub(crate) fn retain_range(&mut self, range: &TickRange) {
+        Arc::make_mut(&mut self.ticks).retain(|t| range.contains(t.index));
+    }
+
+    /// See [`liquidity_net_in_range`].
+    pub(crate) fn liquidity_net_in_range(&self, lower: i32, upper: i32) -> i128 {
+        liquidity_net_in_range(&self.ticks, lower, upper)
+    }
+
+    /// See [`active_liquidity_at`]. Lists that only hold the ticks of a window don't know the
+    /// liquidity below it.
+    pub(crate) fn active_liquidity_at(&self, tick: i32) -> u128 {
+        active_liquidity_at(&self.ticks, tick)
+    }
+
+    /// See [`liquidity_in_range`].
+    pub(crate) fn liquidity_in_range(&self, lower: i32, upper: i32) -> u128 {
+        liquidity_in_range(&self.ticks, lower, upper)
+    }
+
+    /// Returns `liquidity` after the price crossed `tick` downwards (`zero_for_one`) or upwards.
+    ///
+    /// Crossing upwards moves from tick `tick - 1` to `tick`, so the ticks in `[tick, tick + 1)`
+    /// are added; crossing downwards subtracts them again.
+    pub(crate) fn cross(&self, tick: i32, liquidity: u128, zero_for_one: bool) -> u128 {
+        let net_liquidity = self.liquidity_net_in_range(tick, tick + 1);
+        liquidity_math::add_liquidity_delta(
+            liquidity,
+            if zero_for_one { -net_liquidity } else { net_liquidity },
+        )
+    }
+
     fn is_below_smallest(&self, tick: i32) -> bool {
         tick < self.ticks[0].index
     }
@@ -152,6 +333,7 @@ impl TickList {
         tick >= maximum || tick >= MAX_TICK
     }
 
+    #[cfg(test)]
     pub(crate) fn get_tick(&self, index: i32) -> Result<&TickInfo, TickListError> {
         match self
             .ticks
@@ -242,6 +424,58 @@ impl TickList {
             Ok((next_tick_idx, next_tick_idx == idx))
         }
     }
+
+    /// Like `next_initialized_tick_within_one_word`, for a list that only holds the initialized
+    /// ticks within `loaded`.
+    ///
+    /// Returns the same result as the fully loaded list would, or `None` if the result depends on
+    /// ticks outside of `loaded`. Unless `loaded` extends to the end of the tick range in the
+    /// search direction, the nearest initialized tick has to be loaded to know the result, as the
+    /// full list treats its outermost ticks specially. `tick` must lie within `loaded`.
+    pub(crate) fn next_initialized_tick_within_one_word_in_range(
+        &self,
+        tick: i32,
+        lte: bool,
+        loaded: &TickRange,
+    ) -> Result<Option<(i32, bool)>, TickListError> {
+        // If everything in the direction of the search is loaded, the list behaves exactly like
+        // the full list.
+        let exhaustive = if lte { loaded.lower <= MIN_TICK } else { loaded.upper >= MAX_TICK };
+        if exhaustive && !self.ticks.is_empty() {
+            return self
+                .next_initialized_tick_within_one_word(tick, lte)
+                .map(Some);
+        }
+
+        let spacing = self.tick_spacing as i32;
+        let compressed = div_floor(tick, spacing);
+        // index of the first tick above `tick`
+        let pos = self
+            .ticks
+            .partition_point(|t| t.index <= tick);
+        if lte {
+            let min_in_word = ((compressed >> 8) << 8) * spacing;
+            Ok(pos
+                .checked_sub(1)
+                .map(|i| self.ticks[i].index)
+                .filter(|idx| *idx >= loaded.lower)
+                .map(|idx| {
+                    let next_tick_idx = cmp::max(idx, min_in_word);
+                    (next_tick_idx, next_tick_idx == idx)
+                }))
+        } else {
+            let max_in_word = (((((compressed + 1) >> 8) + 1) << 8) - 1) * spacing;
+            Ok(self
+                .ticks
+                .get(pos)
+                .map(|t| t.index)
+                .filter(|idx| *idx <= loaded.upper)
+                .map(|idx| {
+                    let next_tick_idx = cmp::min(max_in_word, idx);
+                    (next_tick_idx, next_tick_idx == idx)
+                }))
+        }
+    }
 }
 
 fn div_floor(lhs: i32, rhs: i32) -> i32 {
@@ -644,4 +878,102 @@ mod tests {
         assert!(tick_list.get_tick(-10).is_err());
         assert!(tick_list.get_tick(10).is_err());
     }
+
+    /// Positions `[-20, 10)` with liquidity 100 and `[0, 30)` with liquidity 50.
+    fn overlapping_positions() -> TickList {
+        TickList::from(
+            10,
+            vec![
+                create_tick_info(-20, 100),
+                create_tick_info(0, 50),
+                create_tick_info(10, -100),
+                create_tick_info(30, -50),
+            ],
+        )
+    }
+
+    #[rstest]
+    #[case::below_lowest(-21, 0)]
+    #[case::on_lowest(-20, 100)]
+    #[case::between(-1, 100)]
+    #[case::on_overlap_start(0, 150)]
+    #[case::below_upper(9, 150)]
+    #[case::on_upper(10, 50)]
+    #[case::below_highest(29, 50)]
+    #[case::on_highest(30, 0)]
+    #[case::min_tick(MIN_TICK, 0)]
+    #[case::max_tick(MAX_TICK, 0)]
+    fn test_active_liquidity_at(#[case] tick: i32, #[case] expected: u128) {
+        assert_eq!(overlapping_positions().active_liquidity_at(tick), expected);
+    }
+
+    #[rstest]
+    #[case::both_positions(-20, 30, 50)]
+    #[case::endpoints_on_ticks(0, 10, 150)]
+    #[case::upper_excluded(-20, 10, 100)]
+    #[case::between_ticks(1, 9, 150)]
+    #[case::single_tick(10, 11, 50)]
+    #[case::ends_at_lowest(-30, -20, 0)]
+    #[case::starts_at_highest(30, 40, 0)]
+    #[case::starts_below_lowest(-30, -10, 0)]
+    #[case::empty(5, 5, 0)]
+    #[case::inverted(10, 0, 0)]
+    #[case::full_range(MIN_TICK, MAX_TICK, 0)]
+    fn test_liquidity_in_range(#[case] lower: i32, #[case] upper: i32, #[case] expected: u128) {
+        assert_eq!(overlapping_positions().liquidity_in_range(lower, upper), expected);
+    }
+
+    #[test]
+    fn test_liquidity_net_in_range_moves_active_liquidity() {
+        let tick_list = overlapping_positions();
+        let ticks = [MIN_TICK, -21, -20, -19, -1, 0, 1, 9, 10, 11, 29, 30, 31, MAX_TICK];
+
+        for lower in ticks {
+            for upper in ticks
+                .into_iter()
+                .filter(|upper| *upper > lower)
+            {
+                assert_eq!(
+                    tick_list.active_liquidity_at(upper - 1) as i128,
+                    tick_list.active_liquidity_at(lower - 1) as i128 +
+                        tick_list.liquidity_net_in_range(lower, upper),
+                    "[{lower}, {upper})"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_liquidity_queries_at_tick_extremes() {
+        let tick_list =
+            TickList::from(1, vec![create_tick_info(MIN_TICK, 7), create_tick_info(MAX_TICK, -7)]);
+
+        assert_eq!(tick_list.active_liquidity_at(MIN_TICK - 1), 0);
+        assert_eq!(tick_list.active_liquidity_at(MIN_TICK), 7);
+        assert_eq!(tick_list.active_liquidity_at(MAX_TICK - 1), 7);
+        assert_eq!(tick_list.active_liquidity_at(MAX_TICK), 0);
+        assert_eq!(tick_list.liquidity_in_range(MIN_TICK, MAX_TICK), 7);
+        assert_eq!(tick_list.liquidity_net_in_range(MIN_TICK, MAX_TICK), 7);
+        assert_eq!(tick_list.liquidity_net_in_range(MIN_TICK, MAX_TICK + 1), 0);
+    }
+
+    #[test]
+    fn test_cross_at_boundary() {
+        let tick_list = overlapping_positions();
+
+        // the current tick 0 sits on an initialized tick, crossing it down leaves its positions
+        assert_eq!(tick_list.cross(0, 150, true), tick_list.active_liquidity_at(-1));
+        assert_eq!(tick_list.cross(10, 150, false), tick_list.active_liquidity_at(10));
+        assert_eq!(tick_list.cross(5, 150, false), 150);
+    }
+
+    #[test]
+    fn test_tick_range_half_open() {
+        let range = TickRange::new(-20, 0);
+
+        assert_eq!(range.half_open(), (-20, 1));
+        let (lower, upper) = range.half_open();
+        assert_eq!(overlapping_positions().liquidity_net_in_range(lower, upper), 150);
+        assert_eq!(TickRange::new(i32::MIN, i32::MAX).half_open(), (MIN_TICK, MAX_TICK + 1));
+    }
 }
@@ -0,0 +1,38 @@
+use std::fmt::Debug;
+
+use super::tick_list::TickInfo;
+use crate::protocol::errors::SimulationError;
+
+/// Supplies initialized ticks of a pool on demand.
+///
+/// Used by states that only keep the ticks around the current price in memory. The provider
+/// serves the ticks as of the state's creation, the states apply later tick updates themselves.
+pub trait TickProvider: Debug + Send + Sync {
+    /// Returns all initialized ticks with `lower <= index <= upper`, ordered by index.
+    fn ticks_in_range(&self, lower: i32, upper: i32) -> Result<Vec<TickInfo>, SimulationError>;
+}
+
+/// A provider serving ticks from a complete snapshot of the pool's ticks.
+#[derive(Debug, Clone)]
+pub struct SnapshotTickProvider {
+    ticks: Vec<TickInfo>,
+}
+
+impl SnapshotTickProvider {
+    pub fn new(mut ticks: Vec<TickInfo>) -> Self {
+        ticks.sort_unstable_by_key(|t| t.index);
+        Self { ticks }
+    }
+}
+
+impl TickProvider for SnapshotTickProvider {
+    fn ticks_in_range(&self, lower: i32, upper: i32) -> Result<Vec<TickInfo>, SimulationError> {
+        let start = self
+            .ticks
+            .partition_point(|t| t.index < lower);
+        let end = self
+            .ticks
+            .partition_point(|t| t.index <= upper);
+        Ok(self.ticks[start..end.max(start)].to_vec())
+    }
+}
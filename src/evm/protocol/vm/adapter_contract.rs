@@ -8,12 +8,15 @@ use alloy_sol_types::SolValue;
 use revm::DatabaseRef;
 
 use super::{
-    erc20_token::Overwrites, models::Capability, tycho_simulation_contract::TychoSimulationContract,
+    constants::{EXTERNAL_ACCOUNT, NATIVE_GAS_HEADROOM, NATIVE_TOKEN},
+    erc20_token::Overwrites,
+    models::Capability,
+    tycho_simulation_contract::TychoSimulationContract,
 };
 use crate::{
     evm::{
         account_storage::StateUpdate,
-        engine_db::engine_db_interface::EngineDatabaseInterface,
+        engine_db::engine_db_interface::{EngineDatabaseError, EngineDatabaseInterface},
         protocol::{u256_num::u256_to_f64, vm::utils::string_to_bytes32},
     },
     protocol::errors::SimulationError,
@@ -51,7 +54,7 @@ type MinGasUsageReturn = U256;
 /// - `min_gas_usage`: Queries the minimum gas usage required for operations within the adapter.
 impl<D: EngineDatabaseInterface + std::clone::Clone + Debug> TychoSimulationContract<D>
 where
-    <D as DatabaseRef>::Error: std::fmt::Debug,
+    <D as DatabaseRef>::Error: EngineDatabaseError,
     <D as EngineDatabaseInterface>::Error: std::fmt::Debug,
 {
     pub fn price(
@@ -92,7 +95,31 @@ where
         let args = (string_to_bytes32(pair_id)?, sell_token, buy_token, is_buy, amount);
         let selector = "swap(bytes32,address,address,uint8,uint256)";
 
-        let res = self.call(selector, args, block, None, overwrites, None, U256::from(0u64))?;
+        // Native token legs are paid for with the call's value and received as a native balance
+        // increase, so the caller is funded with a known balance instead of ERC-20 overwrites.
+        let sells_native = sell_token == NATIVE_TOKEN;
+        let buys_native = buy_token == NATIVE_TOKEN;
+        if sells_native && is_buy {
+            return Err(SimulationError::InvalidInput(
+                "Buy orders selling the native token are not supported".into(),
+                None,
+            ));
+        }
+        let value = if sells_native { amount } else { U256::ZERO };
+        let caller_balance = value.saturating_add(*NATIVE_GAS_HEADROOM);
+        let balance_overrides = (sells_native || buys_native)
+            .then(|| HashMap::from([(*EXTERNAL_ACCOUNT, caller_balance)]));
+
+        let res = self.call_with_balances(
+            selector,
+            args,
+            block,
+            None,
+            overwrites,
+            balance_overrides,
+            None,
+            value,
+        )?;
 
         let decoded: SwapReturn = SwapReturn::abi_decode(&res.return_value, true).map_err(|_| {
             SimulationError::FatalError(format!(
@@ -101,7 +128,12 @@ where
             ))
         })?;
 
-        let (received_amount, gas_used, price_elements) = decoded;
+        let (mut received_amount, gas_used, price_elements) = decoded;
+
+        if buys_native {
+            received_amount =
+                native_amount_received(&res.simulation_result.state_updates, caller_balance)?;
+        }
 
         let price = self
             .calculate_price(vec![price_elements])?
@@ -204,3 +236,60 @@ where
             .collect()
     }
 }
+
+/// The native amount the caller received, from its balance after the call.
+///
+/// Simulations run with a zero gas price, so the caller's balance delta is the amount received
+/// net of gas.
+fn native_amount_received(
+    state_updates: &HashMap<Address, StateUpdate>,
+    caller_balance: U256,
+) -> Result<U256, SimulationError> {
+    let balance_after = state_updates
+        .get(&*EXTERNAL_ACCOUNT)
+        .and_then(|update| update.balance)
+        .ok_or_else(|| {
+            SimulationError::FatalError(
+                "Adapter swap call failed: Caller balance missing from state updates".into(),
+            )
+        })?;
+    Ok(balance_after.saturating_sub(caller_balance))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm::{engine_db::tycho_db::PreCachedDB, simulation::SimulationEngine};
+
+    #[test]
+    fn test_native_amount_received() {
+        let caller_balance = *NATIVE_GAS_HEADROOM;
+        let updates = HashMap::from([(
+            *EXTERNAL_ACCOUNT,
+            StateUpdate {
+                balance: Some(caller_balance + U256::from(500u64)),
+                ..Default::default()
+            },
+        )]);
+
+        assert_eq!(native_amount_received(&updates, caller_balance).unwrap(), U256::from(500u64));
+        assert!(matches!(
+            native_amount_received(&HashMap::new(), caller_balance),
+            Err(SimulationError::FatalError(_))
+        ));
+    }
+
+    #[test]
+    fn test_buying_with_native_token_is_rejected() {
+        let contract = TychoSimulationContract::new(
+            Address::ZERO,
+            SimulationEngine::new(PreCachedDB::new().unwrap(), false),
+        )
+        .unwrap();
+        let buy_token = Address::repeat_byte(1);
+
+        let res = contract.swap("0x01", NATIVE_TOKEN, buy_token, true, U256::from(1u64), 1, None);
+
+        assert!(matches!(res, Err(SimulationError::InvalidInput(_, None))));
+    }
+}
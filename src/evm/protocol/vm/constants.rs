@@ -9,8 +9,14 @@ lazy_static! {
             .expect("Invalid string for external account address"),
     );
     pub static ref MAX_BALANCE: U256 = U256::MAX / U256::from(2);
+    /// Native balance granted to the simulated caller on top of the value it sends, so that the
+    /// call never fails for lack of funds to pay for gas.
+    pub static ref NATIVE_GAS_HEADROOM: U256 = U256::from(10u64).pow(U256::from(18u64));
 }
 
+/// Sentinel address used in place of a token address to represent the chain's native token.
+pub const NATIVE_TOKEN: Address = Address::ZERO;
+
 pub const ERC20_BYTECODE: &[u8] = include_bytes!("assets/ERC20.bin");
 pub const BALANCER_V2: &[u8] = include_bytes!("assets/BalancerV2SwapAdapter.evm.runtime");
 pub const CURVE: &[u8] = include_bytes!("assets/CurveSwapAdapter.evm.runtime");
@@ -11,7 +11,10 @@ use super::{
 };
 use crate::{
     evm::{
-        engine_db::{engine_db_interface::EngineDatabaseInterface, simulation_db::BlockHeader},
+        engine_db::{
+            engine_db_interface::{EngineDatabaseError, EngineDatabaseInterface},
+            simulation_db::BlockHeader,
+        },
         simulation::SimulationEngine,
         ContractCompiler, SlotId,
     },
@@ -136,7 +139,7 @@ pub(crate) fn brute_force_slots<D: EngineDatabaseInterface + Clone + Debug>(
     engine: &SimulationEngine<D>,
 ) -> Result<(ERC20Slots, ContractCompiler), SimulationError>
 where
-    <D as DatabaseRef>::Error: std::fmt::Debug,
+    <D as DatabaseRef>::Error: EngineDatabaseError,
     <D as EngineDatabaseInterface>::Error: std::fmt::Debug,
 {
     let token_contract = TychoSimulationContract::new(*token_addr, engine.clone()).unwrap();
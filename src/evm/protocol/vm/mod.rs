@@ -1,9 +1,11 @@
 mod adapter_contract;
 pub mod constants;
-mod erc20_token;
+pub(crate) mod erc20_token;
 mod models;
+pub mod permit;
 pub mod state;
 pub mod state_builder;
+pub mod token_router;
 pub mod tycho_decoder;
-mod tycho_simulation_contract;
+pub(crate) mod tycho_simulation_contract;
 pub mod utils;
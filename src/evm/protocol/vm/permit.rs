@@ -0,0 +1,438 @@
+//! EIP-2612 permit simulation
+//!
+//! Many interactions approve tokens with a signed `permit()` instead of a separate `approve()`
+//! transaction. [`Erc2612Permit`] builds the calldata of such permits and simulates them against
+//! the token contract to check they actually set the expected allowance.
+use std::{collections::HashMap, fmt::Debug};
+
+use alloy::signers::{local::PrivateKeySigner, SignerSync};
+use alloy_primitives::{keccak256, Address, Keccak256, Signature, B256, U256};
+use alloy_sol_types::SolValue;
+use revm::DatabaseRef;
+use thiserror::Error;
+
+use super::tycho_simulation_contract::TychoSimulationContract;
+use crate::{
+    evm::{
+        engine_db::{
+            engine_db_interface::{EngineDatabaseError, EngineDatabaseInterface},
+            simulation_db::BlockHeader,
+        },
+        simulation::SimulationEngine,
+    },
+    protocol::errors::SimulationError,
+};
+
+const PERMIT_SIGNATURE: &str = "permit(address,address,uint256,uint256,uint8,bytes32,bytes32)";
+const PERMIT_TYPE: &str =
+    "Permit(address owner,address spender,uint256 value,uint256 nonce,uint256 deadline)";
+const DOMAIN_TYPE: &str =
+    "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)";
+
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum PermitError {
+    #[error("Invalid permit parameter: {0}")]
+    InvalidParameter(String),
+    #[error("Permit is not signed")]
+    MissingSignature,
+    #[error("Failed to sign permit: {0}")]
+    SigningFailed(String),
+    #[error("Permit signed by {recovered} instead of owner {owner}")]
+    SignerMismatch { owner: Address, recovered: Address },
+}
+
+impl From<PermitError> for SimulationError {
+    fn from(error: PermitError) -> Self {
+        SimulationError::InvalidInput(error.to_string(), None)
+    }
+}
+
+/// The `(v, r, s)` signature of a permit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PermitSignature {
+    pub v: u8,
+    pub r: B256,
+    pub s: B256,
+}
+
+/// The parameters of a `permit()` call, optionally signed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PermitCalldata {
+    pub owner: Address,
+    pub spender: Address,
+    pub value: U256,
+    pub nonce: U256,
+    pub deadline: u64,
+    pub chain_id: u64,
+    pub signature: Option<PermitSignature>,
+}
+
+impl PermitCalldata {
+    /// The EIP-712 hash of the permit struct.
+    pub fn struct_hash(&self) -> B256 {
+        keccak256(
+            (
+                keccak256(PERMIT_TYPE),
+                self.owner,
+                self.spender,
+                self.value,
+                self.nonce,
+                U256::from(self.deadline),
+            )
+                .abi_encode(),
+        )
+    }
+
+    /// The digest the owner has to sign, given the token's `DOMAIN_SEPARATOR`.
+    pub fn digest(&self, domain_separator: B256) -> B256 {
+        let mut hasher = Keccak256::new();
+        hasher.update([0x19, 0x01]);
+        hasher.update(domain_separator);
+        hasher.update(self.struct_hash());
+        hasher.finalize()
+    }
+
+    /// The domain separator of a token following the standard EIP-712 domain layout.
+    ///
+    /// Tokens are free to define their domain differently, so prefer the value returned by the
+    /// token's `DOMAIN_SEPARATOR()` where possible.
+    pub fn standard_domain_separator(&self, name: &str, version: &str, token: Address) -> B256 {
+        keccak256(
+            (
+                keccak256(DOMAIN_TYPE),
+                keccak256(name),
+                keccak256(version),
+                U256::from(self.chain_id),
+                token,
+            )
+                .abi_encode(),
+        )
+    }
+
+    pub fn with_signature(mut self, signature: PermitSignature) -> Self {
+        self.signature = Some(signature);
+        self
+    }
+
+    /// Signs the permit with the owner's key.
+    pub fn sign(
+        self,
+        domain_separator: B256,
+        signer: &PrivateKeySigner,
+    ) -> Result<Self, PermitError> {
+        if signer.address() != self.owner {
+            return Err(PermitError::SignerMismatch {
+                owner: self.owner,
+                recovered: signer.address(),
+            });
+        }
+        let signature = signer
+            .sign_hash_sync(&self.digest(domain_separator))
+            .map_err(|e| PermitError::SigningFailed(e.to_string()))?;
+        Ok(self.with_signature(PermitSignature {
+            v: signature.v().y_parity_byte() + 27,
+            r: signature.r().into(),
+            s: signature.s().into(),
+        }))
+    }
+
+    /// Recovers the address that signed the permit.
+    pub fn recover_signer(&self, domain_separator: B256) -> Result<Address, PermitError> {
+        let sig = self
+            .signature
+            .ok_or(PermitError::MissingSignature)?;
+        let parity = sig
+            .v
+            .checked_sub(27)
+            .filter(|p| *p <= 1)
+            .ok_or_else(|| PermitError::InvalidParameter(format!("invalid v: {}", sig.v)))?;
+        Signature::from_scalars_and_parity(sig.r, sig.s, parity == 1)
+            .and_then(|signature| {
+                signature.recover_address_from_prehash(&self.digest(domain_separator))
+            })
+            .map_err(|e| PermitError::InvalidParameter(format!("invalid signature: {e}")))
+    }
+
+    /// The ABI encoded `permit()` call.
+    pub fn calldata(&self) -> Result<Vec<u8>, PermitError> {
+        let sig = self
+            .signature
+            .ok_or(PermitError::MissingSignature)?;
+        let mut data = keccak256(PERMIT_SIGNATURE)[..4].to_vec();
+        data.extend(self.call_args(sig).abi_encode());
+        Ok(data)
+    }
+
+    fn call_args(&self, sig: PermitSignature) -> (Address, Address, U256, U256, u8, B256, B256) {
+        (self.owner, self.spender, self.value, U256::from(self.deadline), sig.v, sig.r, sig.s)
+    }
+}
+
+/// The outcome of a simulated `permit()` call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PermitResult {
+    pub domain_separator: B256,
+    /// The allowance of the spender after the permit.
+    pub allowance: U256,
+    pub gas_used: u64,
+}
+
+pub struct Erc2612Permit;
+
+impl Erc2612Permit {
+    /// Builds an unsigned permit.
+    ///
+    /// Sign it with [`PermitCalldata::sign`] or attach an existing signature with
+    /// [`PermitCalldata::with_signature`] before simulating it.
+    pub fn build(
+        owner: Address,
+        spender: Address,
+        value: U256,
+        nonce: U256,
+        deadline: u64,
+        chain_id: u64,
+    ) -> Result<PermitCalldata, PermitError> {
+        if owner == Address::ZERO {
+            return Err(PermitError::InvalidParameter("owner is the zero address".to_string()));
+        }
+        if spender == Address::ZERO {
+            return Err(PermitError::InvalidParameter("spender is the zero address".to_string()));
+        }
+        if chain_id == 0 {
+            return Err(PermitError::InvalidParameter("chain id must not be zero".to_string()));
+        }
+        Ok(PermitCalldata { owner, spender, value, nonce, deadline, chain_id, signature: None })
+    }
+
+    /// Fetches the token's `DOMAIN_SEPARATOR`.
+    pub fn domain_separator<D: EngineDatabaseInterface + Clone + Debug>(
+        engine: &SimulationEngine<D>,
+        token: Address,
+        block: &BlockHeader,
+    ) -> Result<B256, SimulationError>
+    where
+        <D as DatabaseRef>::Error: EngineDatabaseError,
+        <D as EngineDatabaseInterface>::Error: std::fmt::Debug,
+    {
+        let contract = TychoSimulationContract::new(token, engine.clone())?;
+        let res = contract
+            .call(
+                "DOMAIN_SEPARATOR()",
+                (),
+                block.number,
+                Some(block.timestamp),
+                None,
+                None,
+                U256::ZERO,
+            )?
+            .return_value;
+        B256::abi_decode(&res, true).map_err(|e| {
+            SimulationError::FatalError(format!("Failed to decode DOMAIN_SEPARATOR: {:?}", e))
+        })
+    }
+
+    /// Simulates the permit on the token and returns the resulting allowance.
+    ///
+    /// The signature is checked against the token's `DOMAIN_SEPARATOR` before simulating, so
+    /// a permit signed for the wrong domain fails with `InvalidInput` rather than a revert. A
+    /// permit that doesn't result in an allowance of exactly `permit.value` is reported as
+    /// `InvalidInput` too.
+    pub fn simulate_permit<D: EngineDatabaseInterface + Clone + Debug>(
+        engine: &SimulationEngine<D>,
+        token: Address,
+        permit: &PermitCalldata,
+        block: &BlockHeader,
+    ) -> Result<PermitResult, SimulationError>
+    where
+        <D as DatabaseRef>::Error: EngineDatabaseError,
+        <D as EngineDatabaseInterface>::Error: std::fmt::Debug,
+    {
+        let sig = permit
+            .signature
+            .ok_or(PermitError::MissingSignature)?;
+        let domain_separator = Self::domain_separator(engine, token, block)?;
+        let recovered = permit.recover_signer(domain_separator)?;
+        if recovered != permit.owner {
+            return Err(PermitError::SignerMismatch { owner: permit.owner, recovered }.into());
+        }
+
+        let contract = TychoSimulationContract::new(token, engine.clone())?;
+        let permit_res = contract
+            .call(
+                PERMIT_SIGNATURE,
+                permit.call_args(sig),
+                block.number,
+                Some(block.timestamp),
+                None,
+                None,
+                U256::ZERO,
+            )?
+            .simulation_result;
+
+        // Check the allowance on top of the storage written by the permit.
+        let overrides: HashMap<Address, HashMap<U256, U256>> = permit_res
+            .state_updates
+            .into_iter()
+            .filter_map(|(address, update)| Some((address, update.storage?)))
+            .collect();
+        let res = contract
+            .call(
+                "allowance(address,address)",
+                (permit.owner, permit.spender),
+                block.number,
+                Some(block.timestamp),
+                Some(overrides),
+                None,
+                U256::ZERO,
+            )?
+            .return_value;
+        let allowance = U256::abi_decode(&res, true).map_err(|e| {
+            SimulationError::FatalError(format!("Failed to decode allowance: {:?}", e))
+        })?;
+        if allowance != permit.value {
+            return Err(SimulationError::InvalidInput(
+                format!("Permit set allowance to {} instead of {}", allowance, permit.value),
+                None,
+            ));
+        }
+
+        Ok(PermitResult { domain_separator, allowance, gas_used: permit_res.gas_used })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, str::FromStr, sync::Arc};
+
+    use alloy::{
+        providers::{ProviderBuilder, RootProvider},
+        transports::BoxTransport,
+    };
+    use alloy_primitives::hex;
+    use chrono::NaiveDateTime;
+    use dotenv::dotenv;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::evm::engine_db::simulation_db::SimulationDB;
+
+    fn signer() -> PrivateKeySigner {
+        PrivateKeySigner::from_str(
+            "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+        )
+        .unwrap()
+    }
+
+    fn permit(owner: Address) -> PermitCalldata {
+        Erc2612Permit::build(
+            owner,
+            Address::from_str("0x08d967bb0134F2d07f7cfb6E246680c53927DD30").unwrap(),
+            U256::from(1_000_000u64),
+            U256::ZERO,
+            u64::MAX,
+            1,
+        )
+        .unwrap()
+    }
+
+    #[rstest]
+    #[case::zero_owner(Address::ZERO, Address::repeat_byte(1), 1)]
+    #[case::zero_spender(Address::repeat_byte(1), Address::ZERO, 1)]
+    #[case::zero_chain_id(Address::repeat_byte(1), Address::repeat_byte(2), 0)]
+    fn test_build_rejects_invalid_parameters(
+        #[case] owner: Address,
+        #[case] spender: Address,
+        #[case] chain_id: u64,
+    ) {
+        let res = Erc2612Permit::build(owner, spender, U256::from(1), U256::ZERO, 0, chain_id);
+
+        assert!(matches!(res, Err(PermitError::InvalidParameter(_))));
+    }
+
+    #[test]
+    fn test_calldata_requires_signature() {
+        assert_eq!(permit(Address::repeat_byte(1)).calldata(), Err(PermitError::MissingSignature));
+    }
+
+    #[test]
+    fn test_calldata() {
+        let sig = PermitSignature { v: 27, r: B256::repeat_byte(0xaa), s: B256::repeat_byte(0xbb) };
+        let permit = permit(Address::repeat_byte(1)).with_signature(sig);
+
+        let data = permit.calldata().unwrap();
+
+        assert_eq!(&data[..4], &hex!("d505accf"));
+        assert_eq!(data.len(), 4 + 7 * 32);
+        assert_eq!(&data[4 + 12..4 + 32], Address::repeat_byte(1).as_slice());
+        assert_eq!(U256::from_be_slice(&data[4 + 64..4 + 96]), U256::from(1_000_000u64));
+        assert_eq!(U256::from_be_slice(&data[4 + 128..4 + 160]), U256::from(27));
+        assert_eq!(&data[4 + 160..4 + 192], sig.r.as_slice());
+    }
+
+    #[test]
+    fn test_sign_and_recover() {
+        let signer = signer();
+        let domain = permit(signer.address()).standard_domain_separator(
+            "Token",
+            "1",
+            Address::repeat_byte(3),
+        );
+
+        let signed = permit(signer.address())
+            .sign(domain, &signer)
+            .unwrap();
+
+        assert_eq!(signed.recover_signer(domain), Ok(signer.address()));
+        assert_ne!(signed.recover_signer(B256::ZERO), Ok(signer.address()));
+    }
+
+    #[test]
+    fn test_sign_with_wrong_key() {
+        let res = permit(Address::repeat_byte(1)).sign(B256::ZERO, &signer());
+
+        assert!(matches!(res, Err(PermitError::SignerMismatch { .. })));
+    }
+
+    fn new_state() -> SimulationDB<RootProvider<BoxTransport>> {
+        dotenv().ok();
+        let eth_rpc_url = env::var("RPC_URL").expect("Missing RPC_URL in environment");
+        let runtime = tokio::runtime::Handle::try_current()
+            .is_err()
+            .then(|| tokio::runtime::Runtime::new().unwrap())
+            .unwrap();
+        let client = runtime.block_on(async {
+            ProviderBuilder::new()
+                .on_builtin(&eth_rpc_url)
+                .await
+                .unwrap()
+        });
+        SimulationDB::new(Arc::new(client), Some(Arc::new(runtime)), None)
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "network_tests"), ignore)]
+    fn test_simulate_permit_sets_allowance() {
+        let state = new_state();
+        let block = BlockHeader {
+            number: 20_000_000,
+            timestamp: NaiveDateTime::parse_from_str("2024-06-01T22:36:47", "%Y-%m-%dT%H:%M:%S")
+                .unwrap()
+                .and_utc()
+                .timestamp() as u64,
+            ..Default::default()
+        };
+        let engine = SimulationEngine::new(state, false);
+        // USDC
+        let token = Address::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap();
+        let signer = signer();
+        let domain = Erc2612Permit::domain_separator(&engine, token, &block).unwrap();
+        let permit = permit(signer.address())
+            .sign(domain, &signer)
+            .unwrap();
+
+        let res = Erc2612Permit::simulate_permit(&engine, token, &permit, &block).unwrap();
+
+        assert_eq!(res.domain_separator, domain);
+        assert_eq!(res.allowance, U256::from(1_000_000u64));
+    }
+}
@@ -13,15 +13,18 @@ use revm::DatabaseRef;
 use tycho_common::{dto::ProtocolStateDelta, Bytes};
 
 use super::{
-    constants::{EXTERNAL_ACCOUNT, MAX_BALANCE},
+    constants::{EXTERNAL_ACCOUNT, MAX_BALANCE, NATIVE_TOKEN},
     erc20_token::{ERC20OverwriteFactory, ERC20Slots, Overwrites},
     models::Capability,
     tycho_simulation_contract::TychoSimulationContract,
 };
+#[cfg(feature = "profiling")]
+use crate::profiling::{profile, ProfiledFn};
 use crate::{
     evm::{
         engine_db::{
-            engine_db_interface::EngineDatabaseInterface, simulation_db::BlockHeader,
+            engine_db_interface::{EngineDatabaseError, EngineDatabaseInterface},
+            simulation_db::BlockHeader,
             tycho_db::PreCachedDB,
         },
         protocol::{u256_num::u256_to_biguint, utils::bytes_to_address},
@@ -30,7 +33,7 @@ use crate::{
     models::{Balances, Token},
     protocol::{
         errors::{SimulationError, TransitionError},
-        models::GetAmountOutResult,
+        models::{Backend, GetAmountOutResult},
         state::ProtocolSim,
     },
 };
@@ -38,7 +41,7 @@ use crate::{
 #[derive(Clone, Debug)]
 pub struct EVMPoolState<D: EngineDatabaseInterface + Clone + Debug>
 where
-    <D as DatabaseRef>::Error: Debug,
+    <D as DatabaseRef>::Error: EngineDatabaseError,
     <D as EngineDatabaseInterface>::Error: Debug,
 {
     /// The pool's identifier
@@ -82,7 +85,7 @@ where
 impl<D> EVMPoolState<D>
 where
     D: EngineDatabaseInterface + Clone + Debug + 'static,
-    <D as DatabaseRef>::Error: Debug,
+    <D as DatabaseRef>::Error: EngineDatabaseError,
     <D as EngineDatabaseInterface>::Error: Debug,
 {
     /// Creates a new instance of `EVMPoolState` with the given attributes, with the ability to
@@ -197,14 +200,17 @@ where
                 vec![sell_token_address, buy_token_address],
                 overwrites.clone(),
             )?;
-            let price_result = self.adapter_contract.price(
-                &self.id,
-                sell_token_address,
-                buy_token_address,
-                vec![sell_amount_limit / U256::from(100)],
-                self.block.number,
-                overwrites,
-            )?;
+            let price_result = self
+                .adapter_contract
+                .price(
+                    &self.id,
+                    sell_token_address,
+                    buy_token_address,
+                    vec![sell_amount_limit / U256::from(100)],
+                    self.block.number,
+                    overwrites,
+                )
+                .map_err(|e| e.in_pool(&self.id))?;
 
             let price = if self
                 .capabilities
@@ -266,13 +272,10 @@ where
         tokens: Vec<Address>,
         overwrites: Option<HashMap<Address, HashMap<U256, U256>>>,
     ) -> Result<(U256, U256), SimulationError> {
-        let limits = self.adapter_contract.get_limits(
-            &self.id,
-            tokens[0],
-            tokens[1],
-            self.block.number,
-            overwrites,
-        )?;
+        let limits = self
+            .adapter_contract
+            .get_limits(&self.id, tokens[0], tokens[1], self.block.number, overwrites)
+            .map_err(|e| e.in_pool(&self.id))?;
 
         Ok(limits)
     }
@@ -373,23 +376,27 @@ where
             res.push(self.get_balance_overwrites()?);
         }
 
-        let (slots, compiler) = self
-            .token_storage_slots
-            .get(sell_token)
-            .cloned()
-            .unwrap_or((
-                ERC20Slots::new(SlotId::from(0), SlotId::from(1)),
-                ContractCompiler::Solidity,
-            ));
+        // The native token is sent as the call's value, it has neither balance nor allowance
+        // storage to overwrite.
+        if *sell_token != NATIVE_TOKEN {
+            let (slots, compiler) = self
+                .token_storage_slots
+                .get(sell_token)
+                .cloned()
+                .unwrap_or((
+                    ERC20Slots::new(SlotId::from(0), SlotId::from(1)),
+                    ContractCompiler::Solidity,
+                ));
 
-        let mut overwrites = ERC20OverwriteFactory::new(*sell_token, slots.clone(), compiler);
+            let mut overwrites = ERC20OverwriteFactory::new(*sell_token, slots.clone(), compiler);
 
-        overwrites.set_balance(max_amount, Address::from_slice(&*EXTERNAL_ACCOUNT.0));
+            overwrites.set_balance(max_amount, Address::from_slice(&*EXTERNAL_ACCOUNT.0));
 
-        // Set allowance for adapter_address to max_amount
-        overwrites.set_allowance(max_amount, self.adapter_contract.address, *EXTERNAL_ACCOUNT);
+            // Set allowance for adapter_address to max_amount
+            overwrites.set_allowance(max_amount, self.adapter_contract.address, *EXTERNAL_ACCOUNT);
 
-        res.push(overwrites.get_overwrites());
+            res.push(overwrites.get_overwrites());
+        }
 
         // Merge all overwrites into a single HashMap
         Ok(res
@@ -421,7 +428,12 @@ where
             })?),
         };
         if let Some(address) = address {
-            for (token, bal) in &self.balances {
+            // Native balances are part of the account state, not of any token's storage.
+            for (token, bal) in self
+                .balances
+                .iter()
+                .filter(|(token, _)| **token != NATIVE_TOKEN)
+            {
                 let (slots, compiler) = if self.involved_contracts.contains(token) {
                     self.token_storage_slots
                         .get(token)
@@ -445,7 +457,10 @@ where
         // Use contract balances for overrides (will overwrite component balances if they were set
         // for a contract we explicitly track balances for)
         for (contract, balances) in &self.contract_balances {
-            for (token, balance) in balances {
+            for (token, balance) in balances
+                .iter()
+                .filter(|(token, _)| **token != NATIVE_TOKEN)
+            {
                 let (slots, compiler) = self
                     .token_storage_slots
                     .get(token)
@@ -481,6 +496,16 @@ where
         merged
     }
 
+    /// The pool's component balances.
+    pub(crate) fn balances(&self) -> &HashMap<Address, U256> {
+        &self.balances
+    }
+
+    /// Storage overwrites applied to all simulations until the next state update.
+    pub(crate) fn block_lasting_overwrites(&self) -> &HashMap<Address, Overwrites> {
+        &self.block_lasting_overwrites
+    }
+
     #[cfg(test)]
     pub fn get_involved_contracts(&self) -> HashSet<Address> {
         self.involved_contracts.clone()
@@ -501,13 +526,17 @@ where
 impl<D> ProtocolSim for EVMPoolState<D>
 where
     D: EngineDatabaseInterface + Clone + Debug + 'static,
-    <D as DatabaseRef>::Error: Debug,
+    <D as DatabaseRef>::Error: EngineDatabaseError,
     <D as EngineDatabaseInterface>::Error: Debug,
 {
     fn fee(&self) -> f64 {
         todo!()
     }
 
+    fn backend(&self) -> Backend {
+        Backend::Vm
+    }
+
     fn spot_price(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
         let base_address = bytes_to_address(&base.address)?;
         let quote_address = bytes_to_address(&quote.address)?;
@@ -526,6 +555,8 @@ where
         token_in: &Token,
         token_out: &Token,
     ) -> Result<GetAmountOutResult, SimulationError> {
+        #[cfg(feature = "profiling")]
+        let _profile = profile(ProfiledFn::SimulateSwap);
         let sell_token_address = bytes_to_address(&token_in.address)?;
         let buy_token_address = bytes_to_address(&token_out.address)?;
         let sell_amount = U256::from_be_slice(&amount_in.to_bytes_be());
@@ -551,15 +582,18 @@ where
             self.get_overwrites(vec![sell_token_address, buy_token_address], sell_amount_limit)?;
         let complete_overwrites = self.merge(&overwrites, &overwrites_with_sell_limit);
 
-        let (trade, state_changes) = self.adapter_contract.swap(
-            &self.id,
-            sell_token_address,
-            buy_token_address,
-            false,
-            sell_amount_respecting_limit,
-            self.block.number,
-            Some(complete_overwrites),
-        )?;
+        let (trade, state_changes) = self
+            .adapter_contract
+            .swap(
+                &self.id,
+                sell_token_address,
+                buy_token_address,
+                false,
+                sell_amount_respecting_limit,
+                self.block.number,
+                Some(complete_overwrites),
+            )
+            .map_err(|e| e.in_pool(&self.id))?;
 
         let mut new_state = self.clone();
 
@@ -873,6 +907,25 @@ mod tests {
         Ok(())
     }
 
+    #[cfg(feature = "profiling")]
+    #[tokio::test]
+    async fn test_profiling_report() {
+        crate::profiling::reset_profiling();
+        let pool_state = setup_pool_state().await;
+
+        for _ in 0..1000 {
+            pool_state
+                .get_amount_out(BigUint::from_str("1000000000000000000").unwrap(), &dai(), &bal())
+                .unwrap();
+        }
+
+        let report = crate::profiling::profiling_report();
+        for function in ["basic", "storage", "update_state", "simulate_swap", "simulate_tx"] {
+            assert!(report.calls.contains_key(function), "{function} was not profiled");
+        }
+        assert!(report.calls["simulate_swap"].0 >= 1000);
+    }
+
     #[tokio::test]
     async fn test_sequential_get_amount_outs() {
         let pool_state = setup_pool_state().await;
@@ -1047,4 +1100,23 @@ mod tests {
         assert!(overwrites.contains_key(&dai_address));
         assert!(overwrites.contains_key(&bal_address));
     }
+
+    #[tokio::test]
+    async fn test_get_overwrites_skips_native_token() {
+        let mut pool_state: EVMPoolState<PreCachedDB> = setup_pool_state().await;
+        pool_state
+            .balances
+            .insert(NATIVE_TOKEN, U256::from(1000));
+
+        let balance_overwrites = pool_state
+            .get_balance_overwrites()
+            .unwrap();
+        let token_overwrites = pool_state
+            .get_token_overwrites(vec![NATIVE_TOKEN, bal_addr()], *MAX_BALANCE)
+            .unwrap();
+
+        assert!(!balance_overwrites.contains_key(&NATIVE_TOKEN));
+        assert!(balance_overwrites.contains_key(&bal_addr()));
+        assert!(!token_overwrites.contains_key(&NATIVE_TOKEN));
+    }
 }
@@ -26,7 +26,9 @@ use super::{
 use crate::{
     evm::{
         engine_db::{
-            create_engine, engine_db_interface::EngineDatabaseInterface, simulation_db::BlockHeader,
+            create_engine,
+            engine_db_interface::{EngineDatabaseError, EngineDatabaseInterface},
+            simulation_db::BlockHeader,
         },
         protocol::{utils::bytes_to_address, vm::constants::ERC20_BYTECODE},
         simulation::{SimulationEngine, SimulationParameters},
@@ -79,7 +81,7 @@ use crate::{
 /// ```
 pub struct EVMPoolStateBuilder<D: EngineDatabaseInterface + Clone + Debug>
 where
-    <D as DatabaseRef>::Error: Debug,
+    <D as DatabaseRef>::Error: EngineDatabaseError,
     <D as EngineDatabaseInterface>::Error: Debug,
 {
     id: String,
@@ -103,7 +105,7 @@ where
 impl<D> EVMPoolStateBuilder<D>
 where
     D: EngineDatabaseInterface + Clone + Debug + 'static,
-    <D as DatabaseRef>::Error: Debug,
+    <D as DatabaseRef>::Error: EngineDatabaseError,
     <D as EngineDatabaseInterface>::Error: Debug,
 {
     pub fn new(
@@ -446,6 +448,8 @@ where
             block_number: self.block.number,
             timestamp,
             overrides: Some(HashMap::new()),
+            balance_overrides: None,
+            nonce: None,
             caller: *EXTERNAL_ACCOUNT,
             value: U256::from(0u64),
             gas_limit: None,
@@ -0,0 +1,398 @@
+//! Route execution including ERC-20 approvals
+//!
+//! Quoting a route only accounts for the swaps. Executing it additionally requires every token to
+//! be approved to whoever pulls it: the user approves the router for the sold token, and the router
+//! approves each pool it forwards an intermediate token to. [`TokenRouter`] simulates the
+//! `approve()` calls that are still missing before swapping through each hop, so the result
+//! reflects the full cost of the route.
+use std::{collections::HashMap, fmt::Debug};
+
+use alloy_primitives::{Address, U256};
+use alloy_sol_types::SolValue;
+use num_traits::ToPrimitive;
+use revm::DatabaseRef;
+use tracing::debug;
+use tycho_common::Bytes;
+
+use super::tycho_simulation_contract::TychoSimulationContract;
+use crate::{
+    evm::{
+        engine_db::{
+            engine_db_interface::{EngineDatabaseError, EngineDatabaseInterface},
+            simulation_db::BlockHeader,
+        },
+        protocol::{
+            u256_num::{biguint_to_u256, u256_to_biguint},
+            utils::bytes_to_address,
+        },
+        simulation::SimulationEngine,
+    },
+    models::Token,
+    protocol::{errors::SimulationError, state::ProtocolSim},
+    routing::pool_graph::Route,
+};
+
+/// A pool a route can swap through.
+#[derive(Debug)]
+pub struct RoutePool {
+    /// The contract pulling the sold token on a swap.
+    pub address: Address,
+    pub state: Box<dyn ProtocolSim>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteExecutionResult {
+    pub amount_out: U256,
+    /// Gas of all swaps and simulated approvals.
+    pub gas_used_total: u64,
+    /// The `(token, spender)` pairs that had to be approved, in route order.
+    pub approvals_needed: Vec<(Address, Address)>,
+}
+
+/// Executes routes on behalf of `owner` through the router contract at `address`.
+///
+/// Approvals simulated by the router are remembered, so a later route doesn't approve the same
+/// token again. Pool states move along with every executed route.
+pub struct TokenRouter<D: EngineDatabaseInterface + Clone + Debug>
+where
+    <D as DatabaseRef>::Error: EngineDatabaseError,
+    <D as EngineDatabaseInterface>::Error: Debug,
+{
+    engine: SimulationEngine<D>,
+    address: Address,
+    owner: Address,
+    pools: HashMap<String, RoutePool>,
+    tokens: HashMap<Bytes, Token>,
+    /// Token storage written by simulated approvals.
+    overrides: HashMap<Address, HashMap<U256, U256>>,
+}
+
+impl<D: EngineDatabaseInterface + Clone + Debug> TokenRouter<D>
+where
+    <D as DatabaseRef>::Error: EngineDatabaseError,
+    <D as EngineDatabaseInterface>::Error: Debug,
+{
+    pub fn new(engine: SimulationEngine<D>, address: Address, owner: Address) -> Self {
+        Self {
+            engine,
+            address,
+            owner,
+            pools: HashMap::new(),
+            tokens: HashMap::new(),
+            overrides: HashMap::new(),
+        }
+    }
+
+    pub fn with_pool(mut self, id: &str, pool: RoutePool) -> Self {
+        self.pools.insert(id.to_string(), pool);
+        self
+    }
+
+    pub fn with_token(mut self, token: Token) -> Self {
+        self.tokens
+            .insert(token.address.clone(), token);
+        self
+    }
+
+    pub fn pool(&self, id: &str) -> Option<&RoutePool> {
+        self.pools.get(id)
+    }
+
+    /// Simulates selling `amount_in` along `route`, approving tokens where necessary.
+    ///
+    /// Before each hop, the holder of the hop's input token (the owner on the first hop, the
+    /// router afterwards) is checked to have approved the hop's spender (the router on the first
+    /// hop, the pool afterwards). Missing approvals are simulated as `approve(spender, MAX)`.
+    /// Pool states and approvals are only kept if the whole route succeeds.
+    pub fn simulate_route_with_approvals(
+        &mut self,
+        route: &Route,
+        amount_in: U256,
+        block: &BlockHeader,
+    ) -> Result<RouteExecutionResult, SimulationError> {
+        if route.pools.is_empty() || route.tokens.len() != route.pools.len() + 1 {
+            return Err(SimulationError::InvalidInput(
+                format!(
+                    "Route with {} tokens can't have {} hops",
+                    route.tokens.len(),
+                    route.pools.len()
+                ),
+                None,
+            ));
+        }
+
+        let mut overrides = self.overrides.clone();
+        let mut new_states = Vec::with_capacity(route.pools.len());
+        let mut approvals_needed = Vec::new();
+        let mut gas_used_total = 0u64;
+        let mut amount = amount_in;
+        for (i, pool_id) in route.pools.iter().enumerate() {
+            let pool = self.pools.get(pool_id).ok_or_else(|| {
+                SimulationError::InvalidInput(format!("Unknown pool {pool_id}"), None)
+            })?;
+            let token_in = self.token(&route.tokens[i])?;
+            let token_out = self.token(&route.tokens[i + 1])?;
+            let token = bytes_to_address(&token_in.address)?;
+            let (holder, spender) =
+                if i == 0 { (self.owner, self.address) } else { (self.address, pool.address) };
+
+            if self.allowance(token, holder, spender, &overrides, block)? < amount {
+                debug!(%token, %holder, %spender, "Simulating missing approval");
+                gas_used_total += self.approve(token, holder, spender, &mut overrides, block)?;
+                approvals_needed.push((token, spender));
+            }
+
+            let res = pool
+                .state
+                .get_amount_out(u256_to_biguint(amount), token_in, token_out)?;
+            amount = biguint_to_u256(&res.amount);
+            gas_used_total += res.gas.to_u64().ok_or_else(|| {
+                SimulationError::FatalError(format!("Gas of hop {i} doesn't fit a u64"))
+            })?;
+            new_states.push((pool_id.clone(), res.new_state));
+        }
+
+        for (pool_id, state) in new_states {
+            if let Some(pool) = self.pools.get_mut(&pool_id) {
+                pool.state = state;
+            }
+        }
+        self.overrides = overrides;
+        Ok(RouteExecutionResult { amount_out: amount, gas_used_total, approvals_needed })
+    }
+
+    fn token(&self, address: &Bytes) -> Result<&Token, SimulationError> {
+        self.tokens.get(address).ok_or_else(|| {
+            SimulationError::InvalidInput(format!("Unknown token {:?}", address), None)
+        })
+    }
+
+    fn allowance(
+        &self,
+        token: Address,
+        owner: Address,
+        spender: Address,
+        overrides: &HashMap<Address, HashMap<U256, U256>>,
+        block: &BlockHeader,
+    ) -> Result<U256, SimulationError> {
+        let contract = TychoSimulationContract::new(token, self.engine.clone())?;
+        let res = contract
+            .call(
+                "allowance(address,address)",
+                (owner, spender),
+                block.number,
+                Some(block.timestamp),
+                Some(overrides.clone()),
+                None,
+                U256::ZERO,
+            )?
+            .return_value;
+        U256::abi_decode(&res, true).map_err(|e| {
+            SimulationError::FatalError(format!("Failed to decode allowance: {:?}", e))
+        })
+    }
+
+    /// Simulates `approve(spender, MAX)` from `owner`, adding the written storage to `overrides`.
+    ///
+    /// Returns the gas used.
+    fn approve(
+        &self,
+        token: Address,
+        owner: Address,
+        spender: Address,
+        overrides: &mut HashMap<Address, HashMap<U256, U256>>,
+        block: &BlockHeader,
+    ) -> Result<u64, SimulationError> {
+        let contract = TychoSimulationContract::new(token, self.engine.clone())?;
+        let res = contract
+            .call(
+                "approve(address,uint256)",
+                (spender, U256::MAX),
+                block.number,
+                Some(block.timestamp),
+                Some(overrides.clone()),
+                Some(owner),
+                U256::ZERO,
+            )?
+            .simulation_result;
+        for (address, update) in res.state_updates {
+            if let Some(storage) = update.storage {
+                overrides
+                    .entry(address)
+                    .or_default()
+                    .extend(storage);
+            }
+        }
+        Ok(res.gas_used)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use num_bigint::BigUint;
+    use revm::primitives::{AccountInfo, Bytecode, KECCAK_EMPTY};
+
+    use super::*;
+    use crate::evm::{
+        engine_db::{create_engine, tycho_db::PreCachedDB},
+        protocol::{uniswap_v2::state::UniswapV2State, vm::constants::ERC20_BYTECODE},
+        storage_layout::nested_mapping_slot,
+    };
+
+    const ROUTER: Address = Address::new([0xaa; 20]);
+    const OWNER: Address = Address::new([0xbb; 20]);
+    const POOL_AB: Address = Address::new([0x01; 20]);
+    const POOL_BC: Address = Address::new([0x02; 20]);
+    /// Slot of `allowances` in the mocked ERC20 contract.
+    const ALLOWANCES_SLOT: u64 = 1;
+
+    fn token_address(i: u8) -> Address {
+        Address::repeat_byte(0x10 + i)
+    }
+
+    fn token(i: u8) -> Token {
+        Token::new(&token_address(i).to_string(), 18, "T", BigUint::from(10_000u64))
+    }
+
+    fn allowance_slot(owner: Address, spender: Address) -> U256 {
+        let key = |address: Address| U256::from_be_slice(address.as_slice());
+        nested_mapping_slot(&[key(owner), key(spender)], U256::from(ALLOWANCES_SLOT))
+    }
+
+    /// A router over pools A-B and B-C, with `approvals` already set as `(token, holder,
+    /// spender)`.
+    fn router(approvals: &[(u8, Address, Address)]) -> TokenRouter<PreCachedDB> {
+        let engine = create_engine(PreCachedDB::new().unwrap(), false).unwrap();
+        for i in 0..3 {
+            let storage: HashMap<U256, U256> = approvals
+                .iter()
+                .filter(|(token, ..)| *token == i)
+                .map(|(_, holder, spender)| (allowance_slot(*holder, *spender), U256::MAX))
+                .collect();
+            engine.state.init_account(
+                token_address(i),
+                AccountInfo {
+                    balance: U256::ZERO,
+                    nonce: 0,
+                    code_hash: KECCAK_EMPTY,
+                    code: Some(Bytecode::new_raw(ERC20_BYTECODE.into())),
+                },
+                Some(storage),
+                true,
+            );
+        }
+        for account in [OWNER, ROUTER] {
+            engine
+                .state
+                .init_account(account, AccountInfo::default(), None, true);
+        }
+
+        let pool = |address, reserve0: u64, reserve1: u64| RoutePool {
+            address,
+            state: Box::new(UniswapV2State::new(U256::from(reserve0), U256::from(reserve1))),
+        };
+        TokenRouter::new(engine, ROUTER, OWNER)
+            .with_pool("ab", pool(POOL_AB, 1_000_000, 2_000_000))
+            .with_pool("bc", pool(POOL_BC, 3_000_000, 1_000_000))
+            .with_token(token(0))
+            .with_token(token(1))
+            .with_token(token(2))
+    }
+
+    fn route() -> Route {
+        Route {
+            tokens: (0..3)
+                .map(|i| token(i).address)
+                .collect(),
+            pools: vec!["ab".to_string(), "bc".to_string()],
+            rate: 0.0,
+        }
+    }
+
+    fn expected_amount_out(amount_in: U256) -> U256 {
+        let ab = UniswapV2State::new(U256::from(1_000_000u64), U256::from(2_000_000u64));
+        let bc = UniswapV2State::new(U256::from(3_000_000u64), U256::from(1_000_000u64));
+        let first = ab
+            .get_amount_out(u256_to_biguint(amount_in), &token(0), &token(1))
+            .unwrap()
+            .amount;
+        let second = bc
+            .get_amount_out(first, &token(1), &token(2))
+            .unwrap()
+            .amount;
+        biguint_to_u256(&second)
+    }
+
+    #[test]
+    fn test_two_hops_without_approvals() {
+        let mut router = router(&[]);
+        let amount_in = U256::from(10_000u64);
+
+        let res = router
+            .simulate_route_with_approvals(&route(), amount_in, &BlockHeader::default())
+            .unwrap();
+
+        assert_eq!(res.amount_out, expected_amount_out(amount_in));
+        assert_eq!(
+            res.approvals_needed,
+            vec![(token_address(0), ROUTER), (token_address(1), POOL_BC)]
+        );
+        assert!(res.gas_used_total > 240_000);
+    }
+
+    #[test]
+    fn test_two_hops_with_existing_approvals() {
+        let mut router = router(&[(0, OWNER, ROUTER), (1, ROUTER, POOL_BC)]);
+        let amount_in = U256::from(10_000u64);
+
+        let res = router
+            .simulate_route_with_approvals(&route(), amount_in, &BlockHeader::default())
+            .unwrap();
+
+        assert_eq!(res.amount_out, expected_amount_out(amount_in));
+        assert!(res.approvals_needed.is_empty());
+        assert_eq!(res.gas_used_total, 240_000);
+    }
+
+    #[test]
+    fn test_approvals_and_pool_states_persist() {
+        let mut router = router(&[]);
+        let amount_in = U256::from(10_000u64);
+        let first = router
+            .simulate_route_with_approvals(&route(), amount_in, &BlockHeader::default())
+            .unwrap();
+
+        let second = router
+            .simulate_route_with_approvals(&route(), amount_in, &BlockHeader::default())
+            .unwrap();
+
+        assert!(second.approvals_needed.is_empty());
+        assert!(second.amount_out < first.amount_out);
+    }
+
+    #[test]
+    fn test_unknown_pool_keeps_state() {
+        let mut router = router(&[]);
+        let route = Route { pools: vec!["ab".to_string(), "cd".to_string()], ..route() };
+
+        let res = router.simulate_route_with_approvals(
+            &route,
+            U256::from(10_000u64),
+            &BlockHeader::default(),
+        );
+
+        assert!(matches!(res, Err(SimulationError::InvalidInput(..))));
+        let ab = router.pool("ab").unwrap();
+        let untouched = UniswapV2State::new(U256::from(1_000_000u64), U256::from(2_000_000u64));
+        assert_eq!(
+            ab.state
+                .spot_price(&token(0), &token(1))
+                .unwrap(),
+            untouched
+                .spot_price(&token(0), &token(1))
+                .unwrap()
+        );
+    }
+}
@@ -14,8 +14,10 @@ use super::{
 };
 use crate::{
     evm::{
-        engine_db::engine_db_interface::EngineDatabaseInterface,
-        simulation::{SimulationEngine, SimulationParameters, SimulationResult},
+        engine_db::engine_db_interface::{EngineDatabaseError, EngineDatabaseInterface},
+        simulation::{
+            SimulationEngine, SimulationEngineError, SimulationParameters, SimulationResult,
+        },
     },
     protocol::errors::SimulationError,
 };
@@ -52,7 +54,7 @@ pub struct TychoSimulationResponse {
 #[derive(Clone, Debug)]
 pub struct TychoSimulationContract<D: EngineDatabaseInterface + Clone + Debug>
 where
-    <D as DatabaseRef>::Error: std::fmt::Debug,
+    <D as DatabaseRef>::Error: EngineDatabaseError,
     <D as EngineDatabaseInterface>::Error: std::fmt::Debug,
 {
     pub(crate) address: Address,
@@ -61,7 +63,7 @@ where
 
 impl<D: EngineDatabaseInterface + Clone + Debug> TychoSimulationContract<D>
 where
-    <D as DatabaseRef>::Error: std::fmt::Debug,
+    <D as DatabaseRef>::Error: EngineDatabaseError,
     <D as EngineDatabaseInterface>::Error: std::fmt::Debug,
 {
     pub fn new(address: Address, engine: SimulationEngine<D>) -> Result<Self, SimulationError> {
@@ -125,6 +127,32 @@ where
         overrides: Option<HashMap<Address, HashMap<U256, U256>>>,
         caller: Option<Address>,
         value: U256,
+    ) -> Result<TychoSimulationResponse, SimulationError> {
+        self.call_with_balances(
+            selector,
+            args,
+            block_number,
+            timestamp,
+            overrides,
+            None,
+            caller,
+            value,
+        )
+    }
+
+    /// Same as `call`, but additionally overrides the native balances of the given accounts,
+    /// e.g. to fund a caller that sends value along with the call.
+    #[allow(clippy::too_many_arguments)]
+    pub fn call_with_balances(
+        &self,
+        selector: &str,
+        args: impl SolValue,
+        block_number: u64,
+        timestamp: Option<u64>,
+        overrides: Option<HashMap<Address, HashMap<U256, U256>>>,
+        balance_overrides: Option<HashMap<Address, U256>>,
+        caller: Option<Address>,
+        value: U256,
     ) -> Result<TychoSimulationResponse, SimulationError> {
         let call_data = self.encode_input(selector, args);
         let params = SimulationParameters {
@@ -138,6 +166,8 @@ where
                     .timestamp() as u64
             }),
             overrides,
+            balance_overrides,
+            nonce: None,
             caller: caller.unwrap_or(*EXTERNAL_ACCOUNT),
             value,
             gas_limit: None,
@@ -154,13 +184,21 @@ where
     fn simulate(&self, params: SimulationParameters) -> Result<SimulationResult, SimulationError> {
         self.engine
             .simulate(&params)
-            .map_err(|e| coerce_error(&e, "pool_state", params.gas_limit))
+            .map_err(|e| match e {
+                SimulationEngineError::DatabaseError(_) => SimulationError::StateAccess {
+                    contract: self.address,
+                    block: params.block_number,
+                    pool_id: None,
+                    source: Box::new(e),
+                },
+                _ => coerce_error(&e, "pool_state", params.gas_limit),
+            })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::str::FromStr;
+    use std::{error::Error, str::FromStr};
 
     use alloy_primitives::hex;
     use revm::{
@@ -170,7 +208,10 @@ mod tests {
 
     use super::*;
     use crate::evm::{
-        engine_db::engine_db_interface::EngineDatabaseInterface,
+        engine_db::{
+            engine_db_interface::EngineDatabaseInterface,
+            tycho_db::{PreCachedDBError, TychoClientError},
+        },
         protocol::vm::{constants::BALANCER_V2, utils::string_to_bytes32},
     };
 
@@ -222,6 +263,46 @@ mod tests {
         }
     }
 
+    /// A database that fails to provide state of any account, like a node that became
+    /// unreachable.
+    #[derive(Debug, Clone)]
+    struct FailingDatabase;
+
+    impl DatabaseRef for FailingDatabase {
+        type Error = PreCachedDBError;
+
+        fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+            Err(TychoClientError::HttpClient(format!("connection refused for {address}")).into())
+        }
+
+        fn code_by_hash_ref(&self, _code_hash: B256) -> Result<Bytecode, Self::Error> {
+            Ok(Bytecode::new())
+        }
+
+        fn storage_ref(&self, address: Address, _index: U256) -> Result<U256, Self::Error> {
+            Err(PreCachedDBError::MissingAccount(address))
+        }
+
+        fn block_hash_ref(&self, _number: u64) -> Result<B256, Self::Error> {
+            Ok(B256::default())
+        }
+    }
+
+    impl EngineDatabaseInterface for FailingDatabase {
+        type Error = String;
+
+        fn init_account(
+            &self,
+            _address: Address,
+            _account: AccountInfo,
+            _permanent_storage: Option<HashMap<U256, U256>>,
+            _mocked: bool,
+        ) {
+        }
+
+        fn clear_temp_storage(&mut self) {}
+    }
+
     fn create_mock_engine() -> SimulationEngine<MockDatabase> {
         SimulationEngine::new(MockDatabase, false)
     }
@@ -267,4 +348,75 @@ mod tests {
         assert_eq!(&encoded[36..68], &expected_sell_token); // 32 bytes for address (padded)
         assert_eq!(&encoded[68..100], &expected_buy_token); // 32 bytes for address (padded)
     }
+
+    #[test]
+    fn test_call_preserves_database_error_chain() {
+        let contract = TychoSimulationContract::new(
+            Address::ZERO,
+            SimulationEngine::new(FailingDatabase, false),
+        )
+        .unwrap();
+
+        let err = contract
+            .call("getLimits()", (), 7, Some(0), None, None, U256::ZERO)
+            .unwrap_err()
+            .in_pool("pool_1");
+
+        let SimulationError::StateAccess { contract, block, ref pool_id, .. } = err else {
+            panic!("Expected a state access error, got {err:?}");
+        };
+        assert_eq!(contract, Address::ZERO);
+        assert_eq!(block, 7);
+        assert_eq!(pool_id.as_deref(), Some("pool_1"));
+
+        let engine_err = err
+            .source()
+            .and_then(|source| source.downcast_ref::<SimulationEngineError>())
+            .expect("Engine error should be the source");
+        assert!(matches!(engine_err, SimulationEngineError::DatabaseError(_)));
+        let db_err = engine_err
+            .source()
+            .and_then(|source| source.downcast_ref::<PreCachedDBError>())
+            .expect("Database error should be the engine error's source");
+        let client_err = db_err
+            .source()
+            .and_then(|source| source.downcast_ref::<TychoClientError>())
+            .expect("Client error should be the database error's source");
+        assert!(matches!(client_err, TychoClientError::HttpClient(_)));
+
+        let chain = err.error_chain();
+        assert!(chain.starts_with("Failed to access state of contract"));
+        assert!(chain.contains("Database error: Tycho Client error: Unexpected HTTP client error"));
+        assert!(chain.contains("connection refused"));
+    }
+
+    #[test]
+    fn test_call_with_value_requires_funded_caller() {
+        let contract = create_contract();
+        let value = U256::from(10u64).pow(U256::from(18u64));
+
+        let unfunded = contract.call("deposit()", (), 1, Some(0), None, None, value);
+        assert!(unfunded.is_err());
+
+        let caller_balance = value + U256::from(1000u64);
+        let funded = contract
+            .call_with_balances(
+                "deposit()",
+                (),
+                1,
+                Some(0),
+                None,
+                Some(HashMap::from([(*EXTERNAL_ACCOUNT, caller_balance)])),
+                None,
+                value,
+            )
+            .unwrap();
+
+        let caller_update = funded
+            .simulation_result
+            .state_updates
+            .get(&*EXTERNAL_ACCOUNT)
+            .unwrap();
+        assert_eq!(caller_update.balance, Some(caller_balance - value));
+    }
 }
@@ -1,10 +1,10 @@
-use std::{collections::HashMap, env, str::FromStr};
+use std::{env, str::FromStr};
 
 use alloy::{
     providers::{Provider, ProviderBuilder},
     transports::{RpcError, TransportErrorKind},
 };
-use alloy_primitives::{Address, U256};
+use alloy_primitives::Address;
 use alloy_sol_types::SolValue;
 use hex::FromHex;
 use num_bigint::BigInt;
@@ -12,7 +12,14 @@ use revm::primitives::{Bytecode, Bytes};
 use serde_json::Value;
 
 use crate::{
-    evm::{simulation::SimulationEngineError, ContractCompiler, SlotId},
+    evm::{
+        abi::{
+            errors::decode_revert_data,
+            revert::{RevertDecoder, RevertKind},
+        },
+        simulation::SimulationEngineError,
+        ContractCompiler, SlotId,
+    },
     protocol::errors::SimulationError,
 };
 
@@ -93,22 +100,15 @@ fn parse_solidity_error_message(data: &str) -> String {
             Err(_) => return format!("Failed to decode: {}", data),
         };
 
-        // Check for specific error selectors:
-        // Solidity Error(string) signature: 0x08c379a0
-        if data_bytes.starts_with(&[0x08, 0xc3, 0x79, 0xa0]) {
-            if let Ok(decoded) = String::abi_decode(&data_bytes[4..], true) {
-                return decoded;
-            }
+        if let kind @ (RevertKind::ErrorString(_) | RevertKind::Panic(_)) =
+            RevertDecoder::decode(&data_bytes)
+        {
+            return kind.to_string();
+        }
 
-            // Solidity Panic(uint256) signature: 0x4e487b71
-        } else if data_bytes.starts_with(&[0x4e, 0x48, 0x7b, 0x71]) {
-            if let Ok(decoded) = U256::abi_decode(&data_bytes[4..], true) {
-                let panic_codes = get_solidity_panic_codes();
-                return panic_codes
-                    .get(&decoded.as_limbs()[0])
-                    .cloned()
-                    .unwrap_or_else(|| format!("Panic({})", decoded));
-            }
+        // Known custom error
+        if let Some(decoded) = decode_revert_data(&data_bytes) {
+            return decoded.to_string();
         }
 
         // Try decoding as a string (old Solidity revert case)
@@ -187,21 +187,6 @@ pub fn get_storage_slot_index_at_key(
     compiler.compute_map_slot(&mapping_slot_bytes, &key_bytes)
 }
 
-fn get_solidity_panic_codes() -> HashMap<u64, String> {
-    let mut panic_codes = HashMap::new();
-    panic_codes.insert(0, "GenericCompilerPanic".to_string());
-    panic_codes.insert(1, "AssertionError".to_string());
-    panic_codes.insert(17, "ArithmeticOver/Underflow".to_string());
-    panic_codes.insert(18, "ZeroDivisionError".to_string());
-    panic_codes.insert(33, "UnknownEnumMember".to_string());
-    panic_codes.insert(34, "BadStorageByteArrayEncoding".to_string());
-    panic_codes.insert(51, "EmptyArray".to_string());
-    panic_codes.insert(0x32, "OutOfBounds".to_string());
-    panic_codes.insert(0x41, "OutOfMemory".to_string());
-    panic_codes.insert(0x51, "BadFunctionPointer".to_string());
-    panic_codes
-}
-
 /// Fetches the bytecode for a specified contract address, returning an error if the address is
 /// an Externally Owned Account (EOA) or if no code is associated with it.
 ///
@@ -561,6 +546,18 @@ mod tests {
         assert_eq!(result, "AssertionError");
     }
 
+    #[test]
+    fn test_parse_solidity_error_message_custom_error() {
+        // SwapLimit(uint256,uint256) with (100, 120)
+        let data = "0xe2ea151b\
+            0000000000000000000000000000000000000000000000000000000000000064\
+            0000000000000000000000000000000000000000000000000000000000000078";
+
+        let result = parse_solidity_error_message(data);
+
+        assert_eq!(result, "SwapLimit(100, 120)");
+    }
+
     #[test]
     fn test_parse_solidity_error_message_failed_to_decode() {
         // Test failed decoding with invalid data
@@ -0,0 +1,128 @@
+//! EVM revisions active at historical blocks.
+//!
+//! Simulating a historical block with the latest EVM revision can change results: opcodes that
+//! didn't exist yet succeed, gas costs differ and precompiles are available too early.
+//! [`ChainRevisionSchedule`] maps block numbers to the [`SpecId`] that was active at that height.
+use revm::primitives::SpecId;
+use tycho_common::models::Chain;
+
+/// Ethereum mainnet hard forks by activation block.
+const ETHEREUM_FORKS: &[(u64, SpecId)] = &[
+    (0, SpecId::FRONTIER),
+    (200_000, SpecId::FRONTIER_THAWING),
+    (1_150_000, SpecId::HOMESTEAD),
+    (1_920_000, SpecId::DAO_FORK),
+    (2_463_000, SpecId::TANGERINE),
+    (2_675_000, SpecId::SPURIOUS_DRAGON),
+    (4_370_000, SpecId::BYZANTIUM),
+    (7_280_000, SpecId::PETERSBURG),
+    (9_069_000, SpecId::ISTANBUL),
+    (9_200_000, SpecId::MUIR_GLACIER),
+    (12_244_000, SpecId::BERLIN),
+    (12_965_000, SpecId::LONDON),
+    (13_773_000, SpecId::ARROW_GLACIER),
+    (15_050_000, SpecId::GRAY_GLACIER),
+    (15_537_394, SpecId::MERGE),
+    (17_034_870, SpecId::SHANGHAI),
+    (19_426_587, SpecId::CANCUN),
+];
+
+/// Arbitrum One. Revisions are enabled by ArbOS upgrades rather than Ethereum hard forks, and
+/// pre-Nitro blocks can't be executed by revm at all. Nitro blocks are therefore simulated with
+/// the latest revision ArbOS supports.
+const ARBITRUM_FORKS: &[(u64, SpecId)] = &[(22_207_817, SpecId::CANCUN)];
+
+/// Base mainnet. OP Stack hard forks activate by timestamp; with Base's fixed 2 second block
+/// time, Canyon and Ecotone map to the blocks below. Bedrock and Regolith execute as London.
+const BASE_FORKS: &[(u64, SpecId)] =
+    &[(0, SpecId::LONDON), (9_101_527, SpecId::SHANGHAI), (11_792_527, SpecId::CANCUN)];
+
+/// The EVM revisions of a chain, ordered by activation block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainRevisionSchedule {
+    forks: Vec<(u64, SpecId)>,
+}
+
+impl ChainRevisionSchedule {
+    /// Creates a schedule from `(activation_block, revision)` pairs in any order.
+    pub fn new(mut forks: Vec<(u64, SpecId)>) -> Self {
+        forks.sort_by_key(|(block, _)| *block);
+        Self { forks }
+    }
+
+    /// Returns the built-in schedule of a chain, if there is one.
+    pub fn for_chain(chain: Chain) -> Option<Self> {
+        let forks = match chain {
+            Chain::Ethereum => ETHEREUM_FORKS,
+            Chain::Arbitrum => ARBITRUM_FORKS,
+            Chain::Base => BASE_FORKS,
+            _ => return None,
+        };
+        Some(Self { forks: forks.to_vec() })
+    }
+
+    /// Adds or replaces the activation of a revision.
+    pub fn with_fork(mut self, block: u64, spec_id: SpecId) -> Self {
+        self.forks.retain(|(b, _)| *b != block);
+        let idx = self
+            .forks
+            .partition_point(|(b, _)| *b < block);
+        self.forks.insert(idx, (block, spec_id));
+        self
+    }
+
+    /// The revision active at the given block, or `None` if the block predates the schedule.
+    pub fn spec_at(&self, block: u64) -> Option<SpecId> {
+        let idx = self
+            .forks
+            .partition_point(|(b, _)| *b <= block);
+        idx.checked_sub(1)
+            .map(|i| self.forks[i].1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case::frontier(1, SpecId::FRONTIER)]
+    #[case::berlin(12_500_000, SpecId::BERLIN)]
+    #[case::last_berlin_block(12_964_999, SpecId::BERLIN)]
+    #[case::london(12_965_000, SpecId::LONDON)]
+    #[case::shanghai(18_000_000, SpecId::SHANGHAI)]
+    #[case::cancun(21_000_000, SpecId::CANCUN)]
+    fn test_ethereum_schedule(#[case] block: u64, #[case] expected: SpecId) {
+        let schedule = ChainRevisionSchedule::for_chain(Chain::Ethereum).unwrap();
+
+        assert_eq!(schedule.spec_at(block), Some(expected));
+    }
+
+    #[test]
+    fn test_berlin_block_is_not_cancun() {
+        let schedule = ChainRevisionSchedule::for_chain(Chain::Ethereum).unwrap();
+
+        assert_ne!(schedule.spec_at(12_500_000), Some(SpecId::CANCUN));
+    }
+
+    #[test]
+    fn test_block_before_schedule() {
+        let schedule = ChainRevisionSchedule::for_chain(Chain::Arbitrum).unwrap();
+
+        assert_eq!(schedule.spec_at(1_000), None);
+        assert_eq!(schedule.spec_at(200_000_000), Some(SpecId::CANCUN));
+    }
+
+    #[test]
+    fn test_with_fork() {
+        let schedule = ChainRevisionSchedule::new(vec![(10, SpecId::LONDON), (0, SpecId::BERLIN)])
+            .with_fork(20, SpecId::SHANGHAI)
+            .with_fork(10, SpecId::MERGE);
+
+        assert_eq!(schedule.spec_at(5), Some(SpecId::BERLIN));
+        assert_eq!(schedule.spec_at(10), Some(SpecId::MERGE));
+        assert_eq!(schedule.spec_at(25), Some(SpecId::SHANGHAI));
+    }
+}
@@ -0,0 +1,556 @@
+//! Scenarios describing a protocol stream and the quotes expected along it.
+//!
+//! A [`Scenario`] is a sequence of [`Step`]s: feed messages to decode, such as a snapshot followed
+//! by blocks of deltas, interleaved with expectations on the pools they update or remove, on
+//! quotes, and on decoding errors. [`ScenarioRunner`] decodes each message with a stream decoder,
+//! applies the result to a [`PoolStore`] and checks the expectations against it, reporting the
+//! first failed step.
+//!
+//! Scenarios are built in code with the builder methods of [`Scenario`], or loaded from a YAML or
+//! JSON file whose messages can be inlined or refer to files next to it:
+//!
+//! ```yaml
+//! name: uniswap_v2 delta
+//! steps:
+//!   - step: message
+//!     file: uniswap_v2_snapshot.json
+//!   - step: expect_updated
+//!     pools: ["0x0d4a11d5eeaac28ec3f61d100daf4d40471f1852"]
+//!   - step: expect_quote
+//!     pool: "0x0d4a11d5eeaac28ec3f61d100daf4d40471f1852"
+//!     token_in: "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"
+//!     token_out: "0xdac17f958d2ee523a2206206994597c13d831ec7"
+//!     amount_in: "1000000000000000000"
+//!     expected_out: "3578876851"
+//! ```
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use num_bigint::BigUint;
+use serde::Deserialize;
+use thiserror::Error;
+use tycho_client::feed::{synchronizer::ComponentWithState, FeedMessage};
+use tycho_common::Bytes;
+
+use super::decoder::TychoStreamDecoder;
+use crate::{
+    models::Token,
+    protocol::{
+        errors::InvalidSnapshotError, models::TryFromWithBlock, pool_store::PoolStore,
+        state::ProtocolSim,
+    },
+    serde_helpers::decimal,
+};
+
+#[derive(Error, Debug)]
+pub enum ScenarioError {
+    #[error("Failed to access scenario: {0}")]
+    Io(String),
+    #[error("Failed to parse scenario: {0}")]
+    Parse(String),
+}
+
+/// Where the feed message of a [`Step::Message`] comes from.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum MessageSource {
+    Inline {
+        message: FeedMessage,
+    },
+    /// A JSON file holding the message. Relative paths of a scenario loaded with
+    /// [`Scenario::load`] are relative to the scenario file.
+    File {
+        file: PathBuf,
+    },
+}
+
+impl MessageSource {
+    fn load(&self) -> Result<FeedMessage, String> {
+        match self {
+            Self::Inline { message } => Ok(message.clone()),
+            Self::File { file } => {
+                let json = fs::read_to_string(file)
+                    .map_err(|e| format!("failed to read {}: {e}", file.display()))?;
+                serde_json::from_str(&json)
+                    .map_err(|e| format!("failed to parse {}: {e}", file.display()))
+            }
+        }
+    }
+}
+
+impl fmt::Display for MessageSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Inline { message } => match message.state_msgs.values().next() {
+                Some(msg) => write!(f, "block {}", msg.header.number),
+                None => write!(f, "empty message"),
+            },
+            Self::File { file } => write!(f, "{}", file.display()),
+        }
+    }
+}
+
+/// A quote expected from a pool of the store.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct QuoteExpectation {
+    pub pool: String,
+    pub token_in: Bytes,
+    pub token_out: Bytes,
+    #[serde(with = "decimal")]
+    pub amount_in: BigUint,
+    #[serde(with = "decimal")]
+    pub expected_out: BigUint,
+    /// Allowed deviation from `expected_out`, in basis points of it.
+    #[serde(default)]
+    pub tolerance_bps: u32,
+}
+
+impl QuoteExpectation {
+    pub fn new(
+        pool: &str,
+        token_in: Bytes,
+        token_out: Bytes,
+        amount_in: BigUint,
+        expected_out: BigUint,
+    ) -> Self {
+        Self {
+            pool: pool.to_string(),
+            token_in,
+            token_out,
+            amount_in,
+            expected_out,
+            tolerance_bps: 0,
+        }
+    }
+
+    pub fn with_tolerance_bps(mut self, tolerance_bps: u32) -> Self {
+        self.tolerance_bps = tolerance_bps;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum Step {
+    /// Decodes a feed message and applies the resulting block update to the store.
+    Message(MessageSource),
+    /// The pools whose state the last decoded message updated, in any order.
+    ExpectUpdated {
+        pools: Vec<String>,
+    },
+    /// A pool removed by the last decoded message, and no longer in the store.
+    ExpectRemoved {
+        pool: String,
+    },
+    ExpectQuote(QuoteExpectation),
+    /// Decoding a message fails with an error containing `error`. The store is left unchanged.
+    ExpectDecodeError {
+        #[serde(flatten)]
+        message: MessageSource,
+        error: String,
+    },
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Message(source) => write!(f, "decode {source}"),
+            Self::ExpectUpdated { pools } => write!(f, "expect updated pools {pools:?}"),
+            Self::ExpectRemoved { pool } => write!(f, "expect removal of {pool}"),
+            Self::ExpectQuote(quote) => write!(
+                f,
+                "expect quote of {} {} -> {} on {}",
+                quote.amount_in, quote.token_in, quote.token_out, quote.pool
+            ),
+            Self::ExpectDecodeError { message, error } => {
+                write!(f, "expect decoding {message} to fail with `{error}`")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Scenario {
+    pub name: String,
+    pub steps: Vec<Step>,
+}
+
+impl Scenario {
+    pub fn new(name: &str) -> Self {
+        Self { name: name.to_string(), steps: Vec::new() }
+    }
+
+    /// Parses a scenario from JSON. Relative message files are relative to the working directory.
+    pub fn from_json(json: &str) -> Result<Self, ScenarioError> {
+        serde_json::from_str(json).map_err(|e| ScenarioError::Parse(e.to_string()))
+    }
+
+    /// Parses a scenario from YAML. Relative message files are relative to the working directory.
+    pub fn from_yaml(yaml: &str) -> Result<Self, ScenarioError> {
+        serde_yaml::from_str(yaml).map_err(|e| ScenarioError::Parse(e.to_string()))
+    }
+
+    /// Loads a scenario from a YAML file, or from a JSON file if its extension is `.json`.
+    pub fn load(path: &Path) -> Result<Self, ScenarioError> {
+        let content = fs::read_to_string(path).map_err(|e| ScenarioError::Io(e.to_string()))?;
+        let mut scenario = match path.extension() {
+            Some(ext) if ext == "json" => Self::from_json(&content)?,
+            _ => Self::from_yaml(&content)?,
+        };
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for step in &mut scenario.steps {
+            if let Step::Message(MessageSource::File { file }) |
+            Step::ExpectDecodeError { message: MessageSource::File { file }, .. } = step
+            {
+                *file = dir.join(&*file);
+            }
+        }
+        Ok(scenario)
+    }
+
+    pub fn step(mut self, step: Step) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    pub fn message(self, message: FeedMessage) -> Self {
+        self.step(Step::Message(MessageSource::Inline { message }))
+    }
+
+    pub fn message_file(self, file: impl Into<PathBuf>) -> Self {
+        self.step(Step::Message(MessageSource::File { file: file.into() }))
+    }
+
+    pub fn expect_updated(self, pools: &[&str]) -> Self {
+        let pools = pools
+            .iter()
+            .map(|pool| pool.to_string())
+            .collect();
+        self.step(Step::ExpectUpdated { pools })
+    }
+
+    pub fn expect_removed(self, pool: &str) -> Self {
+        self.step(Step::ExpectRemoved { pool: pool.to_string() })
+    }
+
+    pub fn expect_quote(self, quote: QuoteExpectation) -> Self {
+        self.step(Step::ExpectQuote(quote))
+    }
+
+    pub fn expect_decode_error(self, file: impl Into<PathBuf>, error: &str) -> Self {
+        self.step(Step::ExpectDecodeError {
+            message: MessageSource::File { file: file.into() },
+            error: error.to_string(),
+        })
+    }
+}
+
+/// The first step of a scenario whose expectation wasn't met.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Scenario `{scenario}` failed at step {step} ({description}): {reason}")]
+pub struct ScenarioFailure {
+    pub scenario: String,
+    /// Index of the step, starting at 0.
+    pub step: usize,
+    pub description: String,
+    pub reason: String,
+}
+
+/// Runs scenarios against a stream decoder and a pool store.
+///
+/// State carries over between runs, so a scenario can continue where the previous one stopped.
+pub struct ScenarioRunner {
+    decoder: TychoStreamDecoder,
+    store: PoolStore,
+    last_block: Option<DecodedBlock>,
+}
+
+/// The pools changed by the last decoded message.
+struct DecodedBlock {
+    updated: Vec<String>,
+    removed: Vec<String>,
+}
+
+impl Default for ScenarioRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScenarioRunner {
+    pub fn new() -> Self {
+        Self { decoder: TychoStreamDecoder::new(), store: PoolStore::new(), last_block: None }
+    }
+
+    /// Decodes the components of `exchange` as `T`.
+    pub fn register_decoder<T>(&mut self, exchange: &str)
+    where
+        T: ProtocolSim
+            + TryFromWithBlock<ComponentWithState, Error = InvalidSnapshotError>
+            + Send
+            + 'static,
+    {
+        self.decoder
+            .register_decoder::<T>(exchange);
+    }
+
+    /// Sets the tokens known to the decoder, see `TychoStreamDecoder::set_tokens`.
+    pub async fn set_tokens(&self, tokens: HashMap<Bytes, Token>) {
+        self.decoder.set_tokens(tokens).await;
+    }
+
+    pub fn skip_state_decode_failures(&mut self, skip: bool) {
+        self.decoder
+            .skip_state_decode_failures(skip);
+    }
+
+    pub fn store(&self) -> &PoolStore {
+        &self.store
+    }
+
+    /// Runs the steps of `scenario` in order, stopping at the first failed step.
+    pub async fn run(&mut self, scenario: &Scenario) -> Result<(), ScenarioFailure> {
+        for (index, step) in scenario.steps.iter().enumerate() {
+            self.run_step(step)
+                .await
+                .map_err(|reason| ScenarioFailure {
+                    scenario: scenario.name.clone(),
+                    step: index,
+                    description: step.to_string(),
+                    reason,
+                })?;
+        }
+        Ok(())
+    }
+
+    async fn run_step(&mut self, step: &Step) -> Result<(), String> {
+        match step {
+            Step::Message(source) => {
+                let update = self
+                    .decoder
+                    .decode(source.load()?)
+                    .await
+                    .map_err(|e| format!("decoding failed: {e}"))?;
+                self.last_block = Some(DecodedBlock {
+                    updated: update.states.keys().cloned().collect(),
+                    removed: update
+                        .removed_pairs
+                        .keys()
+                        .cloned()
+                        .collect(),
+                });
+                self.store.apply(update);
+                Ok(())
+            }
+            Step::ExpectUpdated { pools } => {
+                let mut updated = self.last_block()?.updated.clone();
+                let mut expected = pools.clone();
+                updated.sort();
+                expected.sort();
+                if updated != expected {
+                    return Err(format!("expected updates of {expected:?}, got {updated:?}"));
+                }
+                Ok(())
+            }
+            Step::ExpectRemoved { pool } => {
+                if !self
+                    .last_block()?
+                    .removed
+                    .contains(pool)
+                {
+                    return Err("the last message didn't remove the pool".to_string());
+                }
+                if self.store.state(pool).is_some() {
+                    return Err("the pool is still in the store".to_string());
+                }
+                Ok(())
+            }
+            Step::ExpectQuote(expectation) => self.check_quote(expectation),
+            Step::ExpectDecodeError { message, error } => {
+                match self
+                    .decoder
+                    .decode(message.load()?)
+                    .await
+                {
+                    Ok(_) => Err("decoding succeeded".to_string()),
+                    Err(e) if e.to_string().contains(error.as_str()) => Ok(()),
+                    Err(e) => Err(format!("decoding failed with a different error: {e}")),
+                }
+            }
+        }
+    }
+
+    fn last_block(&self) -> Result<&DecodedBlock, String> {
+        self.last_block
+            .as_ref()
+            .ok_or_else(|| "no message was decoded yet".to_string())
+    }
+
+    fn check_quote(&self, expectation: &QuoteExpectation) -> Result<(), String> {
+        let token_in = self.token(&expectation.token_in)?;
+        let token_out = self.token(&expectation.token_out)?;
+        let amount_out = self
+            .store
+            .quote(&expectation.pool, expectation.amount_in.clone(), &token_in, &token_out)
+            .map_err(|e| format!("quoting failed: {e}"))?
+            .amount;
+        let expected = &expectation.expected_out;
+        let deviation =
+            if &amount_out > expected { &amount_out - expected } else { expected - &amount_out };
+        if deviation * 10_000u32 > expected * expectation.tolerance_bps {
+            return Err(format!(
+                "expected {expected} within {} bps, got {amount_out}",
+                expectation.tolerance_bps
+            ));
+        }
+        Ok(())
+    }
+
+    /// A token known to the decoder, or to a component in the store.
+    fn token(&self, address: &Bytes) -> Result<Token, String> {
+        self.decoder
+            .token_registry()
+            .by_address(address)
+            .map(|token| token.as_ref().clone())
+            .or_else(|| self.store.token(address).cloned())
+            .ok_or_else(|| format!("unknown token {address}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::ToBigUint;
+
+    use super::*;
+    use crate::evm::protocol::uniswap_v2::state::UniswapV2State;
+
+    const POOL: &str = "0x0d4a11d5eeaac28ec3f61d100daf4d40471f1852";
+    const WETH: &str = "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2";
+    const USDT: &str = "0xdac17f958d2ee523a2206206994597c13d831ec7";
+
+    fn asset(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join(format!("tests/assets/decoder/{name}.json"))
+    }
+
+    async fn runner() -> ScenarioRunner {
+        let mut runner = ScenarioRunner::new();
+        runner.register_decoder::<UniswapV2State>("uniswap_v2");
+        let tokens = [WETH, USDT]
+            .into_iter()
+            .map(|address| {
+                let token = Token::new(address, 18, address, 100_000.to_biguint().unwrap());
+                (token.address.clone(), token)
+            })
+            .collect();
+        runner.set_tokens(tokens).await;
+        runner
+    }
+
+    fn quote(expected_out: u64) -> QuoteExpectation {
+        QuoteExpectation::new(
+            POOL,
+            Bytes::from(WETH),
+            Bytes::from(USDT),
+            BigUint::from(10u64).pow(18),
+            BigUint::from(expected_out),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_failure_names_step() {
+        let scenario = Scenario::new("failing")
+            .message_file(asset("uniswap_v2_snapshot"))
+            .expect_quote(quote(3_578_876_850).with_tolerance_bps(0));
+
+        let failure = runner()
+            .await
+            .run(&scenario)
+            .await
+            .unwrap_err();
+
+        assert_eq!(failure.step, 1);
+        assert_eq!(failure.reason, "expected 3578876850 within 0 bps, got 3578876851");
+        assert!(failure
+            .to_string()
+            .starts_with("Scenario `failing` failed at step 1 (expect quote of"));
+    }
+
+    #[tokio::test]
+    async fn test_tolerance() {
+        let scenario = Scenario::new("tolerance")
+            .message_file(asset("uniswap_v2_snapshot"))
+            .expect_quote(quote(3_578_500_000).with_tolerance_bps(2));
+
+        assert_eq!(runner().await.run(&scenario).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_load_json() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::copy(asset("uniswap_v2_snapshot"), dir.path().join("snapshot.json")).unwrap();
+        let path = dir.path().join("scenario.json");
+        fs::write(
+            &path,
+            format!(
+                r#"{{
+                    "name": "from json",
+                    "steps": [
+                        {{ "step": "message", "file": "snapshot.json" }},
+                        {{ "step": "expect_updated", "pools": ["{POOL}"] }},
+                        {{
+                            "step": "expect_quote",
+                            "pool": "{POOL}",
+                            "token_in": "{WETH}",
+                            "token_out": "{USDT}",
+                            "amount_in": "1000000000000000000",
+                            "expected_out": "3578876851"
+                        }}
+                    ]
+                }}"#
+            ),
+        )
+        .unwrap();
+
+        let scenario = Scenario::load(&path).unwrap();
+
+        assert_eq!(runner().await.run(&scenario).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_load_yaml() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::copy(asset("uniswap_v2_snapshot"), dir.path().join("snapshot.json")).unwrap();
+        fs::copy(asset("uniswap_v2_delta"), dir.path().join("delta.json")).unwrap();
+        let path = dir.path().join("scenario.yaml");
+        fs::write(
+            &path,
+            format!(
+                r#"
+name: from yaml
+steps:
+  - step: message
+    file: snapshot.json
+  - step: expect_updated
+    pools: ["{POOL}"]
+  - step: message
+    file: delta.json
+  - step: expect_quote
+    pool: "{POOL}"
+    token_in: "{WETH}"
+    token_out: "{USDT}"
+    amount_in: "1000000000000000000"
+    expected_out: "3577541567"
+"#
+            ),
+        )
+        .unwrap();
+
+        let scenario = Scenario::load(&path).unwrap();
+
+        assert_eq!(scenario.name, "from yaml");
+        assert_eq!(runner().await.run(&scenario).await, Ok(()));
+    }
+}
@@ -0,0 +1,412 @@
+//! Startup self-test.
+//!
+//! Deployments usually fail for mundane reasons - a wrong URL, missing token metadata, a skewed
+//! clock - that otherwise only surface minutes later as missing quotes. [`self_test`] runs the
+//! whole pipeline once, end to end, and reports every step separately so a single failure
+//! doesn't hide the state of the others.
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use futures::future::BoxFuture;
+use num_bigint::BigUint;
+use num_traits::Zero;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use super::{clock::ClockSource, protocol::utils::bytes_to_address};
+use crate::{
+    models::Token,
+    protocol::{errors::catch_panic, state::ProtocolSim},
+};
+
+pub const HTTP_HEALTH: &str = "http_health";
+pub const WS_SUBSCRIBE: &str = "ws_subscribe";
+pub const CONTRACT_STATE: &str = "contract_state";
+pub const DECODE_PREFIX: &str = "decode:";
+pub const REFERENCE_QUOTE: &str = "reference_quote";
+pub const CLOCK_SKEW: &str = "clock_skew";
+
+/// A component fetched from Tycho and decoded into a simulatable state.
+#[derive(Debug)]
+pub struct DecodedComponent {
+    pub id: String,
+    pub tokens: Vec<Token>,
+    pub state: Box<dyn ProtocolSim>,
+}
+
+/// The connections and decoders exercised by the self-test.
+///
+/// Errors are plain diagnostics; they end up in the report as they are.
+pub trait SelfTestTarget: Send + Sync {
+    /// Checks the HTTP endpoint is healthy and returns the schema version it serves.
+    fn http_health(&self) -> BoxFuture<'_, Result<String, String>>;
+
+    /// Connects to the websocket, subscribes to an extractor and waits for the acknowledgement.
+    fn ws_round_trip(&self) -> BoxFuture<'_, Result<(), String>>;
+
+    /// Fetches a small contract state and applies it to a simulation database. Returns the
+    /// number of accounts applied.
+    fn fetch_and_apply_contract_state(&self) -> BoxFuture<'_, Result<usize, String>>;
+
+    /// Fetches one component of the protocol system and decodes it.
+    fn decode_component<'a>(
+        &'a self,
+        protocol_system: &'a str,
+    ) -> BoxFuture<'a, Result<DecodedComponent, String>>;
+
+    /// The timestamp of the latest block.
+    fn latest_block_timestamp(&self) -> BoxFuture<'_, Result<u64, String>>;
+}
+
+#[derive(Debug, Clone)]
+pub struct SelfTestConfig {
+    /// Time limit of each individual check.
+    pub check_timeout: Duration,
+    /// Protocol systems of which at least one component must decode.
+    pub protocols: Vec<String>,
+    /// The schema version the HTTP endpoint must serve. Any version passes if `None`.
+    pub expected_schema_version: Option<String>,
+    /// Maximum tolerated difference between the local clock and the latest block timestamp.
+    pub max_clock_skew: Duration,
+}
+
+impl Default for SelfTestConfig {
+    fn default() -> Self {
+        Self {
+            check_timeout: Duration::from_secs(10),
+            protocols: Vec::new(),
+            expected_schema_version: None,
+            max_clock_skew: Duration::from_secs(60),
+        }
+    }
+}
+
+/// The outcome of a single check.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CheckResult {
+    pub name: String,
+    pub passed: bool,
+    pub diagnostics: String,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SelfTestReport {
+    pub checks: Vec<CheckResult>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.passed)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &CheckResult> {
+        self.checks.iter().filter(|c| !c.passed)
+    }
+
+    pub fn check(&self, name: &str) -> Option<&CheckResult> {
+        self.checks
+            .iter()
+            .find(|c| c.name == name)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Report is always serializable")
+    }
+
+    fn record(&mut self, name: &str, started: Instant, outcome: Result<String, String>) {
+        let (passed, diagnostics) = match outcome {
+            Ok(diagnostics) => (true, diagnostics),
+            Err(diagnostics) => (false, diagnostics),
+        };
+        if passed {
+            info!(check = name, %diagnostics, "Self-test check passed");
+        } else {
+            warn!(check = name, %diagnostics, "Self-test check failed");
+        }
+        self.checks.push(CheckResult {
+            name: name.to_string(),
+            passed,
+            diagnostics,
+            duration_ms: started.elapsed().as_millis() as u64,
+        });
+    }
+}
+
+async fn with_timeout<T>(
+    timeout: Duration,
+    fut: impl Future<Output = Result<T, String>>,
+) -> Result<T, String> {
+    tokio::time::timeout(timeout, fut)
+        .await
+        .unwrap_or_else(|_| Err(format!("timed out after {timeout:?}")))
+}
+
+/// Runs all checks against the target. Failing checks don't abort the remaining ones.
+pub async fn self_test(
+    config: &SelfTestConfig,
+    target: &dyn SelfTestTarget,
+    clock: &dyn ClockSource,
+) -> SelfTestReport {
+    let mut report = SelfTestReport::default();
+    let timeout = config.check_timeout;
+
+    let started = Instant::now();
+    let outcome = with_timeout(timeout, target.http_health())
+        .await
+        .and_then(|version| match &config.expected_schema_version {
+            Some(expected) if *expected != version => {
+                Err(format!("schema version {version}, expected {expected}"))
+            }
+            _ => Ok(format!("schema version {version}")),
+        });
+    report.record(HTTP_HEALTH, started, outcome);
+
+    let started = Instant::now();
+    let outcome = with_timeout(timeout, target.ws_round_trip())
+        .await
+        .map(|_| "subscription acknowledged".to_string());
+    report.record(WS_SUBSCRIBE, started, outcome);
+
+    let started = Instant::now();
+    let outcome = with_timeout(timeout, target.fetch_and_apply_contract_state())
+        .await
+        .map(|accounts| format!("applied {accounts} accounts"));
+    report.record(CONTRACT_STATE, started, outcome);
+
+    let mut quotable = None;
+    for protocol in &config.protocols {
+        let started = Instant::now();
+        let outcome = with_timeout(timeout, target.decode_component(protocol)).await;
+        let outcome = outcome.map(|component| {
+            let diagnostics = format!("decoded component {}", component.id);
+            quotable.get_or_insert(component);
+            diagnostics
+        });
+        report.record(&format!("{DECODE_PREFIX}{protocol}"), started, outcome);
+    }
+
+    let started = Instant::now();
+    let outcome = match quotable {
+        Some(component) => reference_quote(&component),
+        None => Err("no decoded component to quote".to_string()),
+    };
+    report.record(REFERENCE_QUOTE, started, outcome);
+
+    let started = Instant::now();
+    let outcome = with_timeout(timeout, target.latest_block_timestamp())
+        .await
+        .and_then(|block_timestamp| {
+            let now = clock.now();
+            let skew = Duration::from_secs(now.abs_diff(block_timestamp));
+            let diagnostics = format!("local time {now}, latest block {block_timestamp}");
+            if skew > config.max_clock_skew {
+                Err(format!("{diagnostics}: skew of {skew:?} exceeds {:?}", config.max_clock_skew))
+            } else {
+                Ok(diagnostics)
+            }
+        });
+    report.record(CLOCK_SKEW, started, outcome);
+
+    report
+}
+
+/// Quotes a small fraction of the component's sell limit for its first two tokens.
+fn reference_quote(component: &DecodedComponent) -> Result<String, String> {
+    let [token_in, token_out, ..] = component.tokens.as_slice() else {
+        return Err(format!("component {} has less than two tokens", component.id));
+    };
+    catch_panic(&component.id, || {
+        let (max_in, _) = component.state.get_limits(
+            bytes_to_address(&token_in.address)?,
+            bytes_to_address(&token_out.address)?,
+        )?;
+        let amount_in = (max_in / 1000u32).max(BigUint::from(1u8));
+        let res = component
+            .state
+            .get_amount_out(amount_in.clone(), token_in, token_out)?;
+        Ok((amount_in, res.amount))
+    })
+    .map_err(|err| format!("quoting component {} failed: {err}", component.id))
+    .and_then(|(amount_in, amount_out)| {
+        if amount_out.is_zero() {
+            return Err(format!(
+                "component {} quoted zero {} for {amount_in} {}",
+                component.id, token_out.symbol, token_in.symbol
+            ));
+        }
+        Ok(format!(
+            "component {}: {amount_in} {} -> {amount_out} {}",
+            component.id, token_in.symbol, token_out.symbol
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::U256;
+    use futures::FutureExt;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::evm::{clock::FixedClock, protocol::uniswap_v2::state::UniswapV2State};
+
+    const BLOCK_TIMESTAMP: u64 = 1_700_000_000;
+
+    #[derive(Default)]
+    struct MockTarget {
+        fail: Option<&'static str>,
+        hang: Option<&'static str>,
+    }
+
+    impl MockTarget {
+        fn respond<T: Send + 'static>(
+            &self,
+            check: &str,
+            ok: T,
+        ) -> BoxFuture<'_, Result<T, String>> {
+            let fail = self.fail == Some(check);
+            let hang = self.hang == Some(check);
+            async move {
+                if hang {
+                    futures::future::pending::<()>().await;
+                }
+                if fail {
+                    Err("injected failure".to_string())
+                } else {
+                    Ok(ok)
+                }
+            }
+            .boxed()
+        }
+    }
+
+    impl SelfTestTarget for MockTarget {
+        fn http_health(&self) -> BoxFuture<'_, Result<String, String>> {
+            self.respond(HTTP_HEALTH, "1".to_string())
+        }
+
+        fn ws_round_trip(&self) -> BoxFuture<'_, Result<(), String>> {
+            self.respond(WS_SUBSCRIBE, ())
+        }
+
+        fn fetch_and_apply_contract_state(&self) -> BoxFuture<'_, Result<usize, String>> {
+            self.respond(CONTRACT_STATE, 3)
+        }
+
+        fn decode_component<'a>(
+            &'a self,
+            protocol_system: &'a str,
+        ) -> BoxFuture<'a, Result<DecodedComponent, String>> {
+            let component = DecodedComponent {
+                id: format!("{protocol_system}_pool"),
+                tokens: vec![
+                    Token::new(
+                        "0x0000000000000000000000000000000000000001",
+                        18,
+                        "A",
+                        BigUint::zero(),
+                    ),
+                    Token::new(
+                        "0x0000000000000000000000000000000000000002",
+                        18,
+                        "B",
+                        BigUint::zero(),
+                    ),
+                ],
+                state: Box::new(UniswapV2State::new(
+                    U256::from(1_000_000_000u64),
+                    U256::from(2_000_000_000u64),
+                )),
+            };
+            self.respond(&format!("{DECODE_PREFIX}{protocol_system}"), component)
+        }
+
+        fn latest_block_timestamp(&self) -> BoxFuture<'_, Result<u64, String>> {
+            self.respond(CLOCK_SKEW, BLOCK_TIMESTAMP)
+        }
+    }
+
+    fn config() -> SelfTestConfig {
+        SelfTestConfig {
+            check_timeout: Duration::from_millis(50),
+            protocols: vec!["uniswap_v2".to_string(), "uniswap_v3".to_string()],
+            expected_schema_version: Some("1".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_all_checks_pass() {
+        let report =
+            self_test(&config(), &MockTarget::default(), &FixedClock(BLOCK_TIMESTAMP)).await;
+
+        assert!(report.passed(), "{}", report.to_json());
+        assert_eq!(report.checks.len(), 7);
+    }
+
+    #[rstest]
+    #[case::http(HTTP_HEALTH)]
+    #[case::ws(WS_SUBSCRIBE)]
+    #[case::contract_state(CONTRACT_STATE)]
+    #[case::decode("decode:uniswap_v3")]
+    #[case::clock(CLOCK_SKEW)]
+    #[tokio::test]
+    async fn test_injected_failure_is_isolated(#[case] check: &'static str) {
+        let target = MockTarget { fail: Some(check), ..Default::default() };
+
+        let report = self_test(&config(), &target, &FixedClock(BLOCK_TIMESTAMP)).await;
+
+        let failures: Vec<_> = report.failures().collect();
+        assert_eq!(failures.len(), 1, "{}", report.to_json());
+        assert_eq!(failures[0].name, check);
+        assert_eq!(failures[0].diagnostics, "injected failure");
+        assert_eq!(report.checks.len(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_hanging_check_times_out() {
+        let target = MockTarget { hang: Some(WS_SUBSCRIBE), ..Default::default() };
+
+        let report = self_test(&config(), &target, &FixedClock(BLOCK_TIMESTAMP)).await;
+
+        let check = report.check(WS_SUBSCRIBE).unwrap();
+        assert!(!check.passed);
+        assert!(check.diagnostics.contains("timed out"));
+        assert!(
+            report
+                .check(CONTRACT_STATE)
+                .unwrap()
+                .passed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_clock_skew_and_schema_mismatch() {
+        let config = SelfTestConfig { expected_schema_version: Some("2".to_string()), ..config() };
+
+        let report =
+            self_test(&config, &MockTarget::default(), &FixedClock(BLOCK_TIMESTAMP + 3_600)).await;
+
+        let failed: Vec<_> = report
+            .failures()
+            .map(|c| c.name.as_str())
+            .collect();
+        assert_eq!(failed, vec![HTTP_HEALTH, CLOCK_SKEW]);
+    }
+
+    #[tokio::test]
+    async fn test_report_serializes_to_json() {
+        let target = MockTarget { fail: Some("decode:uniswap_v2"), ..Default::default() };
+
+        let report = self_test(&config(), &target, &FixedClock(BLOCK_TIMESTAMP)).await;
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["checks"][3]["name"], "decode:uniswap_v2");
+        assert_eq!(json["checks"][3]["passed"], false);
+        // The reference quote falls back to the next decoded component.
+        assert_eq!(json["checks"][5]["passed"], true);
+    }
+}
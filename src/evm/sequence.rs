@@ -0,0 +1,200 @@
+//! Restoring the order of numbered block changes.
+//!
+//! In high-latency scenarios messages of a subscription may arrive out of order. If the server
+//! numbers its messages, see [`BlockAccountChanges::sequence_number`], a [`SequenceValidator`]
+//! holds back early messages until the missing ones arrived and releases them in order.
+//! Messages without a sequence number are passed through unchanged.
+use std::collections::BTreeMap;
+
+use thiserror::Error;
+use tracing::{debug, warn};
+
+use crate::evm::tycho_models::BlockAccountChanges;
+
+/// Number of early messages buffered by default.
+pub const DEFAULT_MAX_BUFFER_SIZE: usize = 16;
+
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceError {
+    /// The messages between `expected` and `received` did not arrive in time and were skipped.
+    #[error("Skipped from sequence number {expected} to {received}")]
+    GapTooLarge { expected: u64, received: u64 },
+}
+
+/// The output of a [`SequenceValidator`], in the order it should be processed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SequenceEvent {
+    Message(BlockAccountChanges),
+    Error(SequenceError),
+}
+
+#[derive(Debug)]
+pub struct SequenceValidator {
+    max_buffer_size: usize,
+    next_sequence_number: Option<u64>,
+    buffer: BTreeMap<u64, BlockAccountChanges>,
+}
+
+impl Default for SequenceValidator {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_BUFFER_SIZE)
+    }
+}
+
+impl SequenceValidator {
+    /// Creates a validator buffering up to `max_buffer_size` early messages.
+    ///
+    /// The first numbered message received sets the expected sequence.
+    pub fn new(max_buffer_size: usize) -> Self {
+        Self { max_buffer_size, next_sequence_number: None, buffer: BTreeMap::new() }
+    }
+
+    /// The sequence number of the next message to release, `None` before the first numbered
+    /// message.
+    pub fn next_sequence_number(&self) -> Option<u64> {
+        self.next_sequence_number
+    }
+
+    /// Number of messages held back until a gap is filled.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Processes a received message and returns the messages it releases, in order.
+    ///
+    /// Messages ahead of the expected sequence number are buffered. If a message is more than
+    /// `max_buffer_size` ahead, or the buffer is full, the missing messages are given up on: a
+    /// [`SequenceError::GapTooLarge`] is emitted and the sequence skips ahead to the received
+    /// message, releasing all buffered messages before it. Messages behind the expected sequence
+    /// number were already released or skipped and are dropped.
+    pub fn push(&mut self, changes: BlockAccountChanges) -> Vec<SequenceEvent> {
+        let Some(received) = changes.sequence_number else {
+            return vec![SequenceEvent::Message(changes)];
+        };
+        let expected = *self
+            .next_sequence_number
+            .get_or_insert(received);
+
+        let mut events = Vec::new();
+        if received < expected {
+            debug!(received, expected, "DroppingStaleMessage");
+            return events;
+        }
+        if received > expected {
+            let gap = received - expected;
+            if gap <= self.max_buffer_size as u64 && self.buffer.len() < self.max_buffer_size {
+                debug!(received, expected, "BufferingEarlyMessage");
+                self.buffer.insert(received, changes);
+                return events;
+            }
+            let error = SequenceError::GapTooLarge { expected, received };
+            warn!(%error, buffered = self.buffer.len(), "SequenceGapTooLarge");
+            events.push(SequenceEvent::Error(error));
+            let later = self.buffer.split_off(&received);
+            events.extend(
+                std::mem::replace(&mut self.buffer, later)
+                    .into_values()
+                    .map(SequenceEvent::Message),
+            );
+        }
+
+        events.push(SequenceEvent::Message(changes));
+        let mut next = received + 1;
+        while let Some(buffered) = self.buffer.remove(&next) {
+            events.push(SequenceEvent::Message(buffered));
+            next += 1;
+        }
+        self.next_sequence_number = Some(next);
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm::tycho_models::Block;
+
+    fn changes(sequence_number: Option<u64>) -> BlockAccountChanges {
+        BlockAccountChanges {
+            block: Block { number: sequence_number.unwrap_or_default(), ..Default::default() },
+            sequence_number,
+            ..Default::default()
+        }
+    }
+
+    /// Pushes the messages with the given sequence numbers and returns the released sequence
+    /// numbers, with errors as `None`.
+    fn push_all(validator: &mut SequenceValidator, sequence: &[u64]) -> Vec<Option<u64>> {
+        sequence
+            .iter()
+            .flat_map(|n| validator.push(changes(Some(*n))))
+            .map(|event| match event {
+                SequenceEvent::Message(changes) => changes.sequence_number,
+                SequenceEvent::Error(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_out_of_order_delivery_is_reordered() {
+        let mut validator = SequenceValidator::new(4);
+
+        let released = push_all(&mut validator, &[10, 12, 13, 11, 15, 14]);
+
+        assert_eq!(released, [10, 11, 12, 13, 14, 15].map(Some));
+        assert_eq!(validator.next_sequence_number(), Some(16));
+        assert_eq!(validator.buffered(), 0);
+    }
+
+    #[test]
+    fn test_messages_are_held_until_gap_is_filled() {
+        let mut validator = SequenceValidator::new(4);
+        push_all(&mut validator, &[1]);
+
+        assert!(validator
+            .push(changes(Some(3)))
+            .is_empty());
+        assert_eq!(validator.buffered(), 1);
+        assert_eq!(push_all(&mut validator, &[2]), [Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn test_gap_too_large_skips_ahead() {
+        let mut validator = SequenceValidator::new(2);
+        push_all(&mut validator, &[1, 3]);
+
+        let events = validator.push(changes(Some(10)));
+
+        assert_eq!(
+            events,
+            vec![
+                SequenceEvent::Error(SequenceError::GapTooLarge { expected: 2, received: 10 }),
+                SequenceEvent::Message(changes(Some(3))),
+                SequenceEvent::Message(changes(Some(10))),
+            ]
+        );
+        assert_eq!(validator.next_sequence_number(), Some(11));
+    }
+
+    #[test]
+    fn test_full_buffer_skips_ahead() {
+        let mut validator = SequenceValidator::new(2);
+
+        let released = push_all(&mut validator, &[1, 3, 4, 5, 2]);
+
+        assert_eq!(released, vec![Some(1), None, Some(3), Some(4), Some(5)]);
+        assert_eq!(validator.next_sequence_number(), Some(6));
+    }
+
+    #[test]
+    fn test_stale_and_unnumbered_messages() {
+        let mut validator = SequenceValidator::default();
+        push_all(&mut validator, &[5, 6]);
+
+        assert!(validator
+            .push(changes(Some(5)))
+            .is_empty());
+        assert_eq!(validator.push(changes(None)), vec![SequenceEvent::Message(changes(None))]);
+        assert_eq!(validator.next_sequence_number(), Some(7));
+    }
+}
@@ -1,41 +1,78 @@
-use std::{clone::Clone, collections::HashMap, default::Default, fmt::Debug};
+use std::{clone::Clone, collections::HashMap, default::Default, fmt::Debug, sync::Arc};
 
 use alloy_primitives::U256;
-use foundry_config::{Chain, Config};
+use foundry_config::Config;
 use foundry_evm::traces::{SparsedTraceArena, TraceKind};
 use revm::{
     inspector_handle_register,
     interpreter::{return_ok, InstructionResult},
     primitives::{
         alloy_primitives, bytes, Address, BlockEnv, EVMError, EVMResult, EvmState, ExecutionResult,
-        Output, ResultAndState, SpecId, TransactTo, TxEnv,
+        Log, Output, ResultAndState, SpecId, TransactTo, TxEnv,
     },
     DatabaseRef, Evm,
 };
 use revm_inspectors::tracing::{TracingInspector, TracingInspectorConfig};
-use strum_macros::Display;
+use thiserror::Error;
 use tokio::runtime::{Handle, Runtime};
 use tracing::{debug, info};
+use tycho_common::models::Chain;
 
 use super::{
     account_storage::StateUpdate,
+    revision::ChainRevisionSchedule,
     traces::{handle_traces, TraceResult},
 };
 use crate::evm::engine_db::{
-    engine_db_interface::EngineDatabaseInterface, simulation_db::OverriddenSimulationDB,
+    engine_db_interface::{EngineDatabaseError, EngineDatabaseInterface},
+    simulation_db::{OverriddenSimulationDB, SimulationDBError},
+    tycho_db::PreCachedDBError,
 };
+#[cfg(feature = "profiling")]
+use crate::profiling::{profile, ProfiledFn};
 
 /// An error representing any transaction simulation result other than successful execution
-#[derive(Debug, Display, Clone, PartialEq)]
+///
+/// Errors compare equal if they are the same variant with equal fields. `PartialEq` can't be
+/// derived because the source of a [`DatabaseError`](Self::DatabaseError) isn't comparable, so
+/// database errors are compared by their messages instead.
+#[derive(Debug, Error, Clone)]
 pub enum SimulationEngineError {
     /// Something went wrong while getting storage; might be caused by network issues.
-    /// Retrying may help.
+    /// Retrying may help. Used for database errors without structured context, like plain
+    /// transport errors.
+    #[error("Storage error: {0}")]
     StorageError(String),
     /// Gas limit has been reached. Retrying while increasing gas limit or waiting for a gas price
     /// reduction may help.
+    #[error("Out of gas: {0}")]
     OutOfGas(String, String),
     /// Simulation didn't succeed; likely not related to network or gas, so retrying won't help
+    #[error("Transaction error: {data}")]
     TransactionError { data: String, gas_used: Option<u64> },
+    /// The database failed to provide state; might be caused by network issues. Retrying may
+    /// help. Used for the structured errors of [`SimulationDB`] and [`PreCachedDB`], which are
+    /// kept as source.
+    ///
+    /// [`SimulationDB`]: crate::evm::engine_db::simulation_db::SimulationDB
+    /// [`PreCachedDB`]: crate::evm::engine_db::tycho_db::PreCachedDB
+    #[error("Database error: {0}")]
+    DatabaseError(#[source] Arc<dyn std::error::Error + Send + Sync>),
+}
+
+impl PartialEq for SimulationEngineError {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::StorageError(a), Self::StorageError(b)) => a == b,
+            (Self::OutOfGas(a, b), Self::OutOfGas(c, d)) => a == c && b == d,
+            (
+                Self::TransactionError { data: a, gas_used: b },
+                Self::TransactionError { data: c, gas_used: d },
+            ) => a == c && b == d,
+            (Self::DatabaseError(a), Self::DatabaseError(b)) => a.to_string() == b.to_string(),
+            _ => false,
+        }
+    }
 }
 
 /// A result of a successful transaction simulation
@@ -47,22 +84,28 @@ pub struct SimulationResult {
     pub state_updates: HashMap<Address, StateUpdate>,
     /// Gas used by the transaction (already reduced by the refunded gas)
     pub gas_used: u64,
+    /// Logs emitted by the transaction, in order
+    pub logs: Vec<Log>,
 }
 
+/// The EVM revision used unless a historical one is requested.
+const LATEST_SPEC_ID: SpecId = SpecId::CANCUN;
+
 /// Simulation engine
 #[derive(Debug, Clone)]
 pub struct SimulationEngine<D: EngineDatabaseInterface + Clone + Debug>
 where
-    <D as DatabaseRef>::Error: std::fmt::Debug,
+    <D as DatabaseRef>::Error: EngineDatabaseError,
     <D as EngineDatabaseInterface>::Error: std::fmt::Debug,
 {
     pub state: D,
     pub trace: bool,
+    spec_id: SpecId,
 }
 
 impl<D: EngineDatabaseInterface + Clone + Debug> SimulationEngine<D>
 where
-    <D as DatabaseRef>::Error: std::fmt::Debug,
+    <D as DatabaseRef>::Error: EngineDatabaseError,
     <D as EngineDatabaseInterface>::Error: std::fmt::Debug,
 {
     /// Create a new simulation engine
@@ -72,7 +115,22 @@ where
     /// * `state` - Database reference to be used for simulation
     /// * `trace` - Whether to print the entire execution trace
     pub fn new(state: D, trace: bool) -> Self {
-        Self { state, trace }
+        Self { state, trace, spec_id: LATEST_SPEC_ID }
+    }
+
+    /// The EVM revision simulations are executed with.
+    pub fn spec_id(&self) -> SpecId {
+        self.spec_id
+    }
+
+    /// Uses the EVM revision that was active at `block` on `chain` for subsequent simulations.
+    ///
+    /// Falls back to the latest supported revision if the chain has no known revision schedule.
+    pub fn set_revision_for_block(&mut self, chain: Chain, block: u64) -> SpecId {
+        self.spec_id = ChainRevisionSchedule::for_chain(chain)
+            .and_then(|schedule| schedule.spec_at(block))
+            .unwrap_or(LATEST_SPEC_ID);
+        self.spec_id
     }
 
     /// Simulate a transaction
@@ -82,6 +140,8 @@ where
         &self,
         params: &SimulationParameters,
     ) -> Result<SimulationResult, SimulationEngineError> {
+        #[cfg(feature = "profiling")]
+        let _profile = profile(ProfiledFn::SimulateTx);
         // We allocate a new EVM so we can work with a simple referenced DB instead of a fully
         // concurrently save shared reference and write locked object. Note that concurrently
         // calling this method is therefore not possible.
@@ -90,12 +150,17 @@ where
         // struct outlive this scope.
 
         // We protect the state from being consumed.
+        let nonce_overrides = params
+            .nonce
+            .map(|nonce| HashMap::from([(params.revm_caller(), nonce)]));
         let db_ref = OverriddenSimulationDB {
             inner_db: &self.state,
             overrides: &params
                 .overrides
                 .clone()
                 .unwrap_or_default(),
+            balance_overrides: params.balance_overrides.as_ref(),
+            nonce_overrides: nonce_overrides.as_ref(),
         };
 
         let tx_env = TxEnv {
@@ -106,6 +171,7 @@ where
             transact_to: params.revm_to(),
             value: params.value,
             data: params.revm_data(),
+            nonce: params.nonce,
             ..Default::default()
         };
 
@@ -116,7 +182,7 @@ where
         };
 
         let default_builder = Evm::builder()
-            .with_spec_id(SpecId::CANCUN)
+            .with_spec_id(self.spec_id)
             .with_ref_db(db_ref)
             .with_block_env(block_env)
             .with_tx_env(tx_env);
@@ -196,9 +262,14 @@ where
 
         tokio::task::block_in_place(|| {
             let future = async {
-                handle_traces(trace_res, &Config::default(), Some(Chain::default()), true)
-                    .await
-                    .expect("failure handling traces");
+                handle_traces(
+                    trace_res,
+                    &Config::default(),
+                    Some(foundry_config::Chain::default()),
+                    true,
+                )
+                .await
+                .expect("failure handling traces");
             };
             if let Ok(handle) = Handle::try_current() {
                 // If successful, use the existing runtime to block on the future
@@ -227,14 +298,14 @@ where
 /// # Errors
 ///
 /// * `SimulationError` - simulation wasn't successful for any reason. See variants for details.
-fn interpret_evm_result<DBError: std::fmt::Debug>(
+fn interpret_evm_result<DBError: EngineDatabaseError>(
     evm_result: EVMResult<DBError>,
 ) -> Result<SimulationResult, SimulationEngineError> {
     match evm_result {
         Ok(result_and_state) => match result_and_state.result {
-            ExecutionResult::Success { gas_used, gas_refunded, output, .. } => {
-                Ok(interpret_evm_success(gas_used, gas_refunded, output, result_and_state.state))
-            }
+            ExecutionResult::Success { gas_used, gas_refunded, output, logs, .. } => Ok(
+                interpret_evm_success(gas_used, gas_refunded, output, logs, result_and_state.state),
+            ),
             ExecutionResult::Revert { output, gas_used } => {
                 Err(SimulationEngineError::TransactionError {
                     data: format!("0x{}", hex::encode(output)),
@@ -255,7 +326,13 @@ fn interpret_evm_result<DBError: std::fmt::Debug>(
             }),
             EVMError::Database(db_error) => {
                 info!("Are we at database error? {:?}", &db_error);
-                Err(SimulationEngineError::StorageError(format!("Storage error: {:?}", db_error)))
+                let message = format!("Storage error: {:?}", db_error);
+                let source: Box<dyn std::error::Error + Send + Sync> = db_error.into();
+                if source.is::<SimulationDBError>() || source.is::<PreCachedDBError>() {
+                    Err(SimulationEngineError::DatabaseError(source.into()))
+                } else {
+                    Err(SimulationEngineError::StorageError(message))
+                }
             }
             EVMError::Custom(err) => Err(SimulationEngineError::TransactionError {
                 data: format!("Unexpected error {}", err),
@@ -278,6 +355,7 @@ fn interpret_evm_success(
     gas_used: u64,
     gas_refunded: u64,
     output: Output,
+    logs: Vec<Log>,
     state: EvmState,
 ) -> SimulationResult {
     SimulationResult {
@@ -316,12 +394,14 @@ fn interpret_evm_success(
                                 }
                             }
                         },
+                        ..Default::default()
                     },
                 );
             }
             account_updates
         },
         gas_used: gas_used - gas_refunded,
+        logs,
     }
 }
 
@@ -339,6 +419,12 @@ pub struct SimulationParameters {
     /// EVM state overrides.
     /// Will be merged with existing state. Will take effect only for current simulation.
     pub overrides: Option<HashMap<Address, HashMap<U256, U256>>>,
+    /// Native balance overrides by account, e.g. to fund the caller of a call sending value.
+    /// Will take effect only for current simulation.
+    pub balance_overrides: Option<HashMap<Address, U256>>,
+    /// Nonce the transaction is sent with, e.g. to simulate it after other transactions of the
+    /// same caller. The caller's nonce is overridden to match it. Left unchecked if `None`.
+    pub nonce: Option<u64>,
     /// Limit of gas to be used by the transaction
     pub gas_limit: Option<u64>,
     /// The block number to be used by the transaction. This is independent of the states block.
@@ -400,6 +486,7 @@ mod tests {
     use crate::{
         evm::engine_db::{
             engine_db_interface::EngineDatabaseInterface, simulation_db::SimulationDB,
+            tycho_db::PreCachedDB,
         },
         protocol::errors::SimulationError,
     };
@@ -424,6 +511,8 @@ mod tests {
                 .cloned()
                 .collect(),
             ),
+            balance_overrides: None,
+            nonce: None,
             gas_limit: Some(33),
             block_number: 0,
             timestamp: 0,
@@ -465,6 +554,8 @@ mod tests {
             data: Vec::new(),
             value: U256::from(0u64),
             overrides: None,
+            balance_overrides: None,
+            nonce: None,
             gas_limit: None,
             block_number: 0,
             timestamp: 0,
@@ -539,6 +630,7 @@ mod tests {
                         .collect(),
                 ),
                 balance: Some(U256::from_limbs([1, 0, 0, 0])),
+                ..Default::default()
             },
         )]
         .iter()
@@ -632,6 +724,30 @@ mod tests {
             _ => panic!("Wrong type of SimulationError!"),
         }
     }
+
+    #[test]
+    fn test_interpret_result_err_structured_db_error() {
+        let address = Address::repeat_byte(0x11);
+        let evm_result: EVMResult<SimulationDBError> =
+            Err(EVMError::Database(SimulationDBError::NetworkDisabled { address, slot: None }));
+
+        let err = interpret_evm_result(evm_result).unwrap_err();
+
+        assert!(err
+            .to_string()
+            .contains(&address.to_string()));
+        assert_eq!(err, err.clone());
+        match err {
+            SimulationEngineError::DatabaseError(source) => {
+                assert!(matches!(
+                    source.downcast_ref::<SimulationDBError>(),
+                    Some(SimulationDBError::NetworkDisabled { .. })
+                ));
+            }
+            _ => panic!("Wrong type of SimulationError!"),
+        }
+    }
+
     fn new_state() -> SimulationDB<RootProvider<BoxTransport>> {
         dotenv().ok();
         let eth_rpc_url = env::var("RPC_URL").expect("Missing RPC_URL in environment");
@@ -649,6 +765,17 @@ mod tests {
         SimulationDB::new(client, Some(Arc::new(runtime)), None)
     }
 
+    #[test]
+    fn test_set_revision_for_block() {
+        let mut engine = SimulationEngine::new(PreCachedDB::new().unwrap(), false);
+        assert_eq!(engine.spec_id(), SpecId::CANCUN);
+
+        let spec_id = engine.set_revision_for_block(Chain::Ethereum, 12_500_000);
+
+        assert_eq!(spec_id, SpecId::BERLIN);
+        assert_eq!(engine.spec_id(), SpecId::BERLIN);
+    }
+
     #[test]
     fn test_integration_revm_v2_swap() -> Result<(), Box<dyn Error>> {
         let state = new_state();
@@ -692,6 +819,8 @@ mod tests {
             data: encoded,
             value: U256::from(0u64),
             overrides: None,
+            balance_overrides: None,
+            nonce: None,
             gas_limit: None,
             block_number: 0,
             timestamp: 0,
@@ -820,6 +949,8 @@ mod tests {
             data: calldata,
             value: U256::from(0u64),
             overrides: Some(overrides),
+            balance_overrides: None,
+            nonce: None,
             gas_limit: None,
             block_number: 0,
             timestamp: 0,
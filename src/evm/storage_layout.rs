@@ -0,0 +1,267 @@
+//! Typed access to packed storage words and derived storage slots.
+//!
+//! Solidity packs small state variables into a single 32 byte slot, e.g. `UniswapV3Pool.slot0` or
+//! the reserves of a `UniswapV2Pair`. The packers here convert between those words and their
+//! fields, and [`StorageWriter`] collects the resulting slots into [`StateUpdate`]s that can be
+//! applied to a [`SimulationDB`](crate::evm::engine_db::simulation_db::SimulationDB).
+use std::collections::HashMap;
+
+use alloy_primitives::{keccak256, Address, U256};
+
+use crate::evm::{account_storage::StateUpdate, SlotId};
+
+/// `UniswapV2Pair` slot packing `reserve0`, `reserve1` and `blockTimestampLast`.
+pub const V2_RESERVES_SLOT: SlotId = U256::from_limbs([8, 0, 0, 0]);
+/// `UniswapV3Pool.slot0`.
+pub const V3_SLOT0_SLOT: SlotId = U256::ZERO;
+/// `UniswapV3Pool.liquidity`.
+pub const V3_LIQUIDITY_SLOT: SlotId = U256::from_limbs([4, 0, 0, 0]);
+
+/// `UniswapV3Pool.slot0`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct UniswapV3Slot0 {
+    /// `sqrtPriceX96`, a uint160.
+    pub sqrt_price: U256,
+    /// An int24.
+    pub tick: i32,
+    pub observation_index: u16,
+    pub observation_cardinality: u16,
+    pub observation_cardinality_next: u16,
+    pub fee_protocol: u8,
+    pub unlocked: bool,
+}
+
+impl UniswapV3Slot0 {
+    pub fn pack(&self) -> U256 {
+        bits(self.sqrt_price, 0, 160) |
+            (U256::from(self.tick as u32 & 0xff_ffff) << 160) |
+            (U256::from(self.observation_index) << 184) |
+            (U256::from(self.observation_cardinality) << 200) |
+            (U256::from(self.observation_cardinality_next) << 216) |
+            (U256::from(self.fee_protocol) << 232) |
+            (U256::from(self.unlocked as u8) << 240)
+    }
+
+    pub fn unpack(word: U256) -> Self {
+        // Sign extend the int24
+        let tick = ((bits(word, 160, 24).to::<u32>() << 8) as i32) >> 8;
+        Self {
+            sqrt_price: bits(word, 0, 160),
+            tick,
+            observation_index: bits(word, 184, 16).to::<u16>(),
+            observation_cardinality: bits(word, 200, 16).to::<u16>(),
+            observation_cardinality_next: bits(word, 216, 16).to::<u16>(),
+            fee_protocol: bits(word, 232, 8).to::<u8>(),
+            unlocked: !bits(word, 240, 8).is_zero(),
+        }
+    }
+}
+
+/// The `UniswapV2Pair` reserves slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct UniswapV2Reserves {
+    /// A uint112.
+    pub reserve0: U256,
+    /// A uint112.
+    pub reserve1: U256,
+    pub block_timestamp_last: u32,
+}
+
+impl UniswapV2Reserves {
+    pub fn pack(&self) -> U256 {
+        bits(self.reserve0, 0, 112) |
+            (bits(self.reserve1, 0, 112) << 112) |
+            (U256::from(self.block_timestamp_last) << 224)
+    }
+
+    pub fn unpack(word: U256) -> Self {
+        Self {
+            reserve0: bits(word, 0, 112),
+            reserve1: bits(word, 112, 112),
+            block_timestamp_last: bits(word, 224, 32).to::<u32>(),
+        }
+    }
+}
+
+/// Slot of `key` in a Solidity mapping stored at `base`, `keccak256(key . base)`.
+///
+/// Address keys are left padded, i.e. `U256::from_be_slice(address.as_slice())`.
+pub fn mapping_slot(key: U256, base: SlotId) -> SlotId {
+    let mut preimage = [0u8; 64];
+    preimage[..32].copy_from_slice(&key.to_be_bytes::<32>());
+    preimage[32..].copy_from_slice(&base.to_be_bytes::<32>());
+    U256::from_be_bytes(keccak256(preimage).0)
+}
+
+/// Slot of `mapping[keys[0]][keys[1]]...` for nested mappings stored at `base`.
+pub fn nested_mapping_slot(keys: &[U256], base: SlotId) -> SlotId {
+    keys.iter()
+        .fold(base, |slot, key| mapping_slot(*key, slot))
+}
+
+/// Slot of element `index` of a dynamic array stored at `base`, whose elements take
+/// `element_slots` slots each.
+pub fn dynamic_array_slot(base: SlotId, index: u64, element_slots: u64) -> SlotId {
+    let start = U256::from_be_bytes(keccak256(base.to_be_bytes::<32>()).0);
+    start.wrapping_add(U256::from(index) * U256::from(element_slots))
+}
+
+/// Collects storage writes into [`StateUpdate`]s.
+#[derive(Debug, Clone, Default)]
+pub struct StorageWriter {
+    updates: HashMap<Address, StateUpdate>,
+}
+
+impl StorageWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn slot(mut self, address: Address, slot: SlotId, value: U256) -> Self {
+        self.updates
+            .entry(address)
+            .or_default()
+            .storage
+            .get_or_insert_with(HashMap::new)
+            .insert(slot, value);
+        self
+    }
+
+    pub fn balance(mut self, address: Address, balance: U256) -> Self {
+        self.updates
+            .entry(address)
+            .or_default()
+            .balance = Some(balance);
+        self
+    }
+
+    pub fn mapping(self, address: Address, base: SlotId, key: U256, value: U256) -> Self {
+        self.slot(address, mapping_slot(key, base), value)
+    }
+
+    pub fn v3_slot0(self, pool: Address, slot0: &UniswapV3Slot0) -> Self {
+        self.slot(pool, V3_SLOT0_SLOT, slot0.pack())
+    }
+
+    pub fn v3_liquidity(self, pool: Address, liquidity: u128) -> Self {
+        self.slot(pool, V3_LIQUIDITY_SLOT, U256::from(liquidity))
+    }
+
+    pub fn v2_reserves(self, pair: Address, reserves: &UniswapV2Reserves) -> Self {
+        self.slot(pair, V2_RESERVES_SLOT, reserves.pack())
+    }
+
+    /// The collected updates, ready for `SimulationDB::update_state`.
+    pub fn build(self) -> HashMap<Address, StateUpdate> {
+        self.updates
+    }
+}
+
+/// The `len` bits of `word` starting at bit `offset`.
+pub(crate) fn bits(word: U256, offset: usize, len: usize) -> U256 {
+    (word >> offset) & ((U256::from(1u64) << len) - U256::from(1u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rstest::rstest;
+
+    use super::*;
+    use crate::evm::{protocol::vm::utils::get_storage_slot_index_at_key, ContractCompiler};
+
+    const MAX_TICK: i32 = 887_272;
+    const MAX_SQRT_PRICE: &str = "1461446703485210103287273052203988822378723970342";
+
+    #[rstest]
+    #[case::max_tick(MAX_TICK, u16::MAX, u8::MAX, true)]
+    #[case::min_tick(-MAX_TICK, 0, 0, false)]
+    #[case::minus_one(-1, 1, 0x44, true)]
+    #[case::zero(0, 0, 0, false)]
+    fn test_v3_slot0_round_trip(
+        #[case] tick: i32,
+        #[case] observation: u16,
+        #[case] fee_protocol: u8,
+        #[case] unlocked: bool,
+    ) {
+        let slot0 = UniswapV3Slot0 {
+            sqrt_price: U256::from_str(MAX_SQRT_PRICE).unwrap(),
+            tick,
+            observation_index: observation,
+            observation_cardinality: observation,
+            observation_cardinality_next: observation,
+            fee_protocol,
+            unlocked,
+        };
+
+        assert_eq!(UniswapV3Slot0::unpack(slot0.pack()), slot0);
+    }
+
+    #[test]
+    fn test_v3_slot0_layout() {
+        let slot0 = UniswapV3Slot0 { tick: -1, unlocked: true, ..Default::default() };
+
+        assert_eq!(slot0.pack(), (U256::from(0xff_ffffu32) << 160) | (U256::from(1u8) << 240));
+    }
+
+    #[rstest]
+    #[case::max((U256::from(1u64) << 112) - U256::from(1u64), u32::MAX)]
+    #[case::zero(U256::ZERO, 0)]
+    fn test_v2_reserves_round_trip(#[case] reserve: U256, #[case] timestamp: u32) {
+        let reserves = UniswapV2Reserves {
+            reserve0: reserve,
+            reserve1: reserve,
+            block_timestamp_last: timestamp,
+        };
+
+        assert_eq!(UniswapV2Reserves::unpack(reserves.pack()), reserves);
+    }
+
+    #[test]
+    fn test_mapping_slot_matches_solidity_layout() {
+        let owner = Address::repeat_byte(0x6f);
+        let spender = Address::repeat_byte(0xc6);
+        let base = U256::from(3u64);
+        let key = |address: Address| U256::from_be_slice(address.as_slice());
+
+        assert_eq!(
+            mapping_slot(key(owner), base),
+            get_storage_slot_index_at_key(owner, base, ContractCompiler::Solidity)
+        );
+        assert_eq!(
+            nested_mapping_slot(&[key(owner), key(spender)], base),
+            get_storage_slot_index_at_key(
+                spender,
+                get_storage_slot_index_at_key(owner, base, ContractCompiler::Solidity),
+                ContractCompiler::Solidity
+            )
+        );
+    }
+
+    #[test]
+    fn test_dynamic_array_slot() {
+        let base = U256::from(2u64);
+        let start = U256::from_be_bytes(keccak256(base.to_be_bytes::<32>()).0);
+
+        assert_eq!(dynamic_array_slot(base, 0, 1), start);
+        assert_eq!(dynamic_array_slot(base, 3, 2), start + U256::from(6u64));
+    }
+
+    #[test]
+    fn test_writer_merges_updates_per_account() {
+        let pool = Address::repeat_byte(0x11);
+        let slot0 = UniswapV3Slot0 { tick: 10, ..Default::default() };
+
+        let updates = StorageWriter::new()
+            .v3_slot0(pool, &slot0)
+            .v3_liquidity(pool, 42)
+            .balance(pool, U256::from(7u64))
+            .build();
+
+        let storage = updates[&pool].storage.as_ref().unwrap();
+        assert_eq!(UniswapV3Slot0::unpack(storage[&V3_SLOT0_SLOT]), slot0);
+        assert_eq!(storage[&V3_LIQUIDITY_SLOT], U256::from(42u64));
+        assert_eq!(updates[&pool].balance, Some(U256::from(7u64)));
+    }
+}
@@ -1,6 +1,7 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
-use futures::{Stream, StreamExt};
+use futures::{future, stream, Stream, StreamExt};
+use tokio::sync::mpsc::UnboundedSender;
 use tokio_stream::wrappers::ReceiverStream;
 use tycho_client::{
     feed::{component_tracker::ComponentFilter, synchronizer::ComponentWithState},
@@ -9,7 +10,17 @@ use tycho_client::{
 use tycho_common::{models::Chain, Bytes};
 
 use crate::{
-    evm::decoder::{StreamDecodeError, TychoStreamDecoder},
+    evm::{
+        backend_override::QuoteBackendOverride,
+        block_ordering::BlockOrderingPolicy,
+        clock::ClockSource,
+        decoder::{StreamDecodeError, TychoStreamDecoder},
+        freshness::FreshnessGuard,
+        lifecycle::{Lifecycle, LifecycleEvent},
+        monitoring::BlockGapMonitor,
+        warmup::{WarmupEvent, WarmupPriority},
+        watchdog::{PipelineWatchdog, RecoveryHandler},
+    },
     models::Token,
     protocol::{
         errors::InvalidSnapshotError,
@@ -48,15 +59,37 @@ use crate::{
 /// # Errors
 /// Returns a `StreamError` if the underlying stream builder fails to initialize.
 pub struct ProtocolStreamBuilder {
+    tycho_url: String,
     decoder: TychoStreamDecoder,
     stream_builder: TychoStreamBuilder,
+    gap_monitor: Option<Arc<BlockGapMonitor>>,
+    lifecycle: Option<Lifecycle>,
+    watchdog: Option<(Arc<PipelineWatchdog>, Arc<dyn RecoveryHandler>)>,
+    freshness_guard: Option<Arc<FreshnessGuard>>,
+}
+
+/// How often the watchdog of a stream checks for a stall.
+const WATCHDOG_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Aborts the task when dropped, so a task tied to a stream doesn't outlive it.
+struct AbortOnDrop(tokio::task::AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
 }
 
 impl ProtocolStreamBuilder {
     pub fn new(tycho_url: &str, chain: Chain) -> Self {
         Self {
+            tycho_url: tycho_url.to_string(),
             decoder: TychoStreamDecoder::new(),
             stream_builder: TychoStreamBuilder::new(tycho_url, chain.into()),
+            gap_monitor: None,
+            lifecycle: None,
+            watchdog: None,
+            freshness_guard: None,
         }
     }
 
@@ -86,6 +119,28 @@ impl ProtocolStreamBuilder {
         self
     }
 
+    /// Registers `T` as the VM implementation of the exchange `name`, used for its pools
+    /// overridden to the VM through the registry set with [`Self::backend_overrides`].
+    pub fn vm_fallback<T>(mut self, name: &str) -> Self
+    where
+        T: ProtocolSim
+            + TryFromWithBlock<ComponentWithState, Error = InvalidSnapshotError>
+            + Send
+            + 'static,
+    {
+        self.decoder
+            .register_vm_fallback::<T>(name);
+        self
+    }
+
+    /// Consults `overrides` for the backend of each pool. Pools whose override changes are
+    /// re-decoded into their new backend with the next block.
+    pub fn backend_overrides(mut self, overrides: Arc<QuoteBackendOverride>) -> Self {
+        self.decoder
+            .set_backend_overrides(overrides);
+        self
+    }
+
     /// Sets the block time for the Tycho client.
     pub fn block_time(mut self, block_time: u64) -> Self {
         self.stream_builder = self
@@ -135,18 +190,169 @@ impl ProtocolStreamBuilder {
         self
     }
 
+    /// Sets the priority of `extractor` for components reported by several extractors in the
+    /// same block. Only the reports of the extractor with the highest priority are applied to such
+    /// a component; by default native extractors take precedence over VM extractors.
+    pub fn extractor_priority(mut self, extractor: &str, priority: u32) -> Self {
+        self.decoder
+            .set_extractor_priority(extractor, priority);
+        self
+    }
+
+    /// Decodes the components of `priority` first on startup.
+    ///
+    /// The first snapshot containing any of them is yielded as two `BlockUpdate`s of the same
+    /// block: one with the prioritized components, then one with the rest.
+    /// [`WarmupEvent::PartialReady`] is sent to `events` once the first is decoded.
+    pub fn warmup_priority(
+        mut self,
+        priority: WarmupPriority,
+        events: UnboundedSender<WarmupEvent>,
+    ) -> Self {
+        self.decoder
+            .set_warmup_priority(priority, events);
+        self
+    }
+
+    /// Rejects blocks that don't follow the previous block according to `policy`, see
+    /// [`BlockOrderingPolicy::for_chain`] for the default of a chain. Rejected blocks are
+    /// yielded as a `StreamDecodeError::BlockOrdering`.
+    pub fn block_ordering(mut self, policy: BlockOrderingPolicy) -> Self {
+        self.decoder.set_block_ordering(policy);
+        self
+    }
+
+    /// Reports every decoded block to `monitor`, which warns about missed or late blocks.
+    pub fn block_gap_monitor(mut self, monitor: Arc<BlockGapMonitor>) -> Self {
+        self.gap_monitor = Some(monitor);
+        self
+    }
+
+    /// Publishes the lifecycle transitions of the stream to `lifecycle`.
+    ///
+    /// `Connected` once the stream is built, the transitions reported by each message with the
+    /// last `BlockUpdate` of it, see [`Lifecycle::observe`], and `ShutDown` when the stream
+    /// ends. Reconnects are handled inside tycho-client and aren't published.
+    pub fn lifecycle(mut self, lifecycle: Lifecycle) -> Self {
+        self.lifecycle = Some(lifecycle);
+        self
+    }
+
+    /// Reports every decoded block to `watchdog` and runs its checks while the stream is alive,
+    /// executing the due recovery actions with `handler`.
+    ///
+    /// The checks start once the stream is built and stop when the stream ends or is dropped.
+    pub fn watchdog(
+        mut self,
+        watchdog: Arc<PipelineWatchdog>,
+        handler: Arc<dyn RecoveryHandler>,
+    ) -> Self {
+        self.watchdog = Some((watchdog, handler));
+        self
+    }
+
+    /// Uses `clock` as the current time of time-dependent pools and of the decoded blocks,
+    /// instead of the host's wall clock.
+    ///
+    /// Tycho's block headers carry no timestamp, so a
+    /// [`BlockAnchoredClock`](crate::evm::clock::BlockAnchoredClock) has to be anchored by the
+    /// caller, e.g. to the latest block of an RPC node.
+    pub fn clock(mut self, clock: Arc<dyn ClockSource>) -> Self {
+        self.decoder.set_clock(clock);
+        self
+    }
+
+    /// Reports every decoded block to `guard`, which tells quoting code whether the received
+    /// data is stale.
+    pub fn freshness_guard(mut self, guard: Arc<FreshnessGuard>) -> Self {
+        self.freshness_guard = Some(guard);
+        self
+    }
+
     pub async fn build(
         self,
     ) -> Result<impl Stream<Item = Result<BlockUpdate, StreamDecodeError>>, StreamError> {
         let (_, rx) = self.stream_builder.build().await?;
         let decoder = Arc::new(self.decoder);
+        let gap_monitor = self.gap_monitor;
+        let freshness_guard = self.freshness_guard;
+        let lifecycle = self.lifecycle;
+        if let Some(lifecycle) = &lifecycle {
+            lifecycle.publish(LifecycleEvent::Connected { address: self.tycho_url });
+        }
+        let shutdown = lifecycle.clone();
+        let watchdog_task = self
+            .watchdog
+            .clone()
+            .map(|(watchdog, handler)| {
+                AbortOnDrop(
+                    tokio::spawn(async move {
+                        watchdog
+                            .run(handler.as_ref(), WATCHDOG_CHECK_INTERVAL)
+                            .await
+                    })
+                    .abort_handle(),
+                )
+            });
+        let watchdog = self
+            .watchdog
+            .map(|(watchdog, _)| watchdog);
 
-        Ok(Box::pin(ReceiverStream::new(rx).then({
-            let decoder = decoder.clone(); // Clone the decoder for the closure
-            move |msg| {
-                let decoder = decoder.clone(); // Clone again for the async block
-                async move { decoder.decode(msg).await }
-            }
-        })))
+        Ok(Box::pin(
+            ReceiverStream::new(rx)
+                .map(Some)
+                // runs once tycho-client closes the stream, after the last message was decoded
+                .chain(stream::once(async move {
+                    drop(watchdog_task);
+                    if let Some(lifecycle) = shutdown {
+                        lifecycle.publish(LifecycleEvent::ShutDown {
+                            reason: "Tycho stream ended".to_string(),
+                        });
+                    }
+                    None
+                }))
+                .filter_map(future::ready)
+                .map(move |msg| {
+                    // Most messages are decoded in one batch, a message split by the warm-up
+                    // priority yields an update per batch.
+                    let batches = decoder.batches(&msg);
+                    let msg = Arc::new(msg);
+                    let decoder = decoder.clone();
+                    let gap_monitor = gap_monitor.clone();
+                    let lifecycle = lifecycle.clone();
+                    let watchdog = watchdog.clone();
+                    let freshness_guard = freshness_guard.clone();
+                    stream::iter(batches).then(move |batch| {
+                        let (decoder, gap_monitor, lifecycle, watchdog, freshness_guard, msg) = (
+                            decoder.clone(),
+                            gap_monitor.clone(),
+                            lifecycle.clone(),
+                            watchdog.clone(),
+                            freshness_guard.clone(),
+                            msg.clone(),
+                        );
+                        async move {
+                            let last = batch.is_last();
+                            let update = decoder
+                                .decode_batch(&msg, batch)
+                                .await?;
+                            if let Some(monitor) = gap_monitor.filter(|_| last) {
+                                monitor.block_received(update.block_number);
+                            }
+                            if let Some(lifecycle) = lifecycle.filter(|_| last) {
+                                lifecycle.observe(&msg);
+                            }
+                            if let Some(watchdog) = watchdog.filter(|_| last) {
+                                watchdog.block_applied();
+                            }
+                            if let Some(guard) = freshness_guard.filter(|_| last) {
+                                guard.block_received();
+                            }
+                            Ok(update)
+                        }
+                    })
+                })
+                .flatten(),
+        ))
     }
 }
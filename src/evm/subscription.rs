@@ -0,0 +1,1779 @@
+//! Subscribing to an extractor over an established WebSocket connection.
+//!
+//! The transport is abstracted as a sink of [`Command`]s and a stream of [`WebSocketMessage`]s,
+//! so the handshake can be driven over any connection, or a mock server in tests.
+//!
+//! A [`SubscriptionClient`] adds and removes extractors while the connection is in use: its
+//! requests are queued on a channel and sent by the [`SubscriptionDriver`] that owns the
+//! connection, which routes the server's responses back to them.
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{stream, Sink, SinkExt, Stream, StreamExt};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::Instant,
+};
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+
+use super::{
+    engine_db::tycho_db::TychoClientError,
+    lifecycle::{Lifecycle, LifecycleEvent},
+    liveness::LivenessTracker,
+    tycho_models::{
+        AccountSet, BlockAccountChanges, Command, ExtractorIdentity, Response, SubscriptionEvent,
+        SubscriptionOptions, SyncStatus, WebSocketMessage,
+    },
+};
+
+/// How long to wait for the server to confirm a subscription by default.
+pub const DEFAULT_SUBSCRIPTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A subscription confirmed by the server.
+#[derive(Debug)]
+pub struct Subscription {
+    pub subscription_id: Uuid,
+    /// The messages received while waiting for the confirmation, in the order they arrived. They
+    /// are to be handled before any later message of the connection.
+    pub buffered: Vec<WebSocketMessage>,
+}
+
+/// Subscribes to `extractor` and waits for the server to confirm the subscription.
+///
+/// Sends a `Command::Subscribe` and waits until a `Response::NewSubscription` for the same
+/// extractor is received. All other messages received in the meantime, including confirmations
+/// of other extractors, are kept in [`Subscription::buffered`] so that no block is lost.
+///
+/// # Errors
+///
+/// * `TychoClientError::SubscriptionTimeout` - if no confirmation arrives within
+///   `subscription_timeout`. The connection should be closed by the caller, as a late confirmation
+///   can't be told apart from a subscription that was never requested.
+/// * `TychoClientError::ConnectionClosed` - if sending the command fails or the message stream ends
+///   before the subscription is confirmed.
+///
+/// # Returns
+///
+/// The id of the new subscription and the messages received before the confirmation.
+pub async fn subscribe<C, M>(
+    commands: &mut C,
+    messages: &mut M,
+    extractor: ExtractorIdentity,
+    options: SubscriptionOptions,
+    subscription_timeout: Duration,
+) -> Result<Subscription, TychoClientError>
+where
+    C: Sink<Command> + Unpin,
+    C::Error: std::fmt::Debug,
+    M: Stream<Item = WebSocketMessage> + Unpin,
+{
+    commands
+        .send(Command::subscribe(extractor.clone(), options))
+        .await
+        .map_err(|e| TychoClientError::ConnectionClosed(format!("{e:?}")))?;
+    let started = Instant::now();
+    debug!(%extractor, "SubscriptionRequested");
+
+    let confirmation = async {
+        let mut buffered = Vec::new();
+        while let Some(msg) = messages.next().await {
+            match msg {
+                WebSocketMessage::Response(Response::NewSubscription {
+                    extractor_id,
+                    subscription_id,
+                }) if extractor_id == extractor => {
+                    return Ok(Subscription { subscription_id, buffered })
+                }
+                other => {
+                    debug!(?other, "BufferingMessageBeforeSubscription");
+                    buffered.push(other);
+                }
+            }
+        }
+        Err(TychoClientError::ConnectionClosed(format!(
+            "Stream ended before subscription to {extractor} was confirmed"
+        )))
+    };
+
+    match tokio::time::timeout(subscription_timeout, confirmation).await {
+        Ok(res) => res,
+        Err(_) => {
+            let waited = started.elapsed();
+            warn!(%extractor, ?waited, "SubscriptionTimeout");
+            Err(TychoClientError::SubscriptionTimeout { extractor, waited })
+        }
+    }
+}
+
+/// Forwards the block changes and snapshots received from the server to `data` as
+/// [`SubscriptionEvent`]s, until the message stream ends.
+///
+/// A snapshot is only forwarded if `include_snapshot` is set, and only before the first block
+/// changes, so consumers that requested none never see a `SubscriptionEvent::Snapshot` and a
+/// snapshot always precedes the changes it is the base of. Heartbeats are not forwarded; they are
+/// recorded in `liveness` instead. Responses are dropped.
+///
+/// Transitions are published to `lifecycle` once the message causing them has been forwarded:
+/// `SnapshotApplied` after a snapshot, `CaughtUp` once the block of an extractor's latest heartbeat
+/// has been forwarded, and `Degraded` when a heartbeat reports the extractor isn't synced.
+///
+/// # Errors
+///
+/// * `TychoClientError::ConnectionClosed` - if forwarding an event to `data` fails.
+pub async fn route_messages<M, D>(
+    messages: &mut M,
+    data: &mut D,
+    include_snapshot: bool,
+    liveness: &LivenessTracker,
+    lifecycle: &Lifecycle,
+) -> Result<(), TychoClientError>
+where
+    M: Stream<Item = WebSocketMessage> + Unpin,
+    D: Sink<SubscriptionEvent> + Unpin,
+    D::Error: std::fmt::Debug,
+{
+    // last forwarded block and latest block of a synced heartbeat, per extractor
+    let mut forwarded: HashMap<String, u64> = HashMap::new();
+    let mut latest: HashMap<String, u64> = HashMap::new();
+    while let Some(msg) = messages.next().await {
+        let (extractor, block) = match msg {
+            WebSocketMessage::Heartbeat(status) => {
+                liveness.heartbeat(&status);
+                if status.status != SyncStatus::Synced {
+                    latest.remove(&status.extractor);
+                    lifecycle.publish(LifecycleEvent::Degraded {
+                        reason: format!("{} is {:?}", status.extractor, status.status),
+                    });
+                    continue;
+                }
+                latest.insert(status.extractor.clone(), status.latest_block);
+                let Some(block) = forwarded
+                    .get(&status.extractor)
+                    .copied()
+                else {
+                    continue;
+                };
+                (status.extractor, block)
+            }
+            WebSocketMessage::Snapshot(snapshot) => {
+                if !include_snapshot || !forwarded.is_empty() {
+                    debug!(
+                        extractor = snapshot.extractor,
+                        block = snapshot.block.number,
+                        "SkippingSnapshot"
+                    );
+                    continue;
+                }
+                let (extractor, block) = (snapshot.extractor.clone(), snapshot.block.number);
+                forward(data, SubscriptionEvent::Snapshot(snapshot)).await?;
+                lifecycle.publish(LifecycleEvent::SnapshotApplied { block });
+                forwarded.insert(extractor.clone(), block);
+                (extractor, block)
+            }
+            WebSocketMessage::BlockAccountChanges(changes) => {
+                let (extractor, block) = (changes.extractor().to_string(), changes.block.number);
+                forward(data, SubscriptionEvent::Changes(changes)).await?;
+                forwarded.insert(extractor.clone(), block);
+                (extractor, block)
+            }
+            WebSocketMessage::Response(response) => {
+                debug!(?response, "SkippingResponse");
+                continue;
+            }
+        };
+        if latest
+            .get(&extractor)
+            .is_some_and(|latest| block >= *latest)
+        {
+            lifecycle.publish(LifecycleEvent::CaughtUp { block });
+        }
+    }
+    Ok(())
+}
+
+async fn forward<D>(data: &mut D, event: SubscriptionEvent) -> Result<(), TychoClientError>
+where
+    D: Sink<SubscriptionEvent> + Unpin,
+    D::Error: std::fmt::Debug,
+{
+    data.send(event)
+        .await
+        .map_err(|e| TychoClientError::ConnectionClosed(format!("{e:?}")))
+}
+
+/// How often a [`SubscriptionSession`] or [`SubscriptionDriver`] tries to reconnect after losing
+/// the connection.
+///
+/// The wait before an attempt starts at `backoff` and grows by `multiplier` with every further
+/// attempt, e.g. 0.5s, 1s, 2s, 4s, ... by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// Attempts to reconnect before giving up. The count restarts once a session's subscription
+    /// is confirmed, or once a driver connected.
+    pub max_attempts: u32,
+    /// Wait before the first attempt.
+    pub backoff: Duration,
+    /// Factor the wait grows by with every attempt after the first.
+    pub multiplier: u32,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self { max_attempts: 10, backoff: Duration::from_millis(500), multiplier: 2 }
+    }
+}
+
+impl ReconnectPolicy {
+    /// The wait before the `attempt`th attempt, counting from 1. Saturates at `Duration::MAX`.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.multiplier
+            .checked_pow(attempt.saturating_sub(1))
+            .and_then(|factor| self.backoff.checked_mul(factor))
+            .unwrap_or(Duration::MAX)
+    }
+}
+
+/// A subscription kept alive across reconnects.
+#[derive(Debug, Clone)]
+pub struct SubscriptionSession {
+    extractor: ExtractorIdentity,
+    options: SubscriptionOptions,
+    subscription_timeout: Duration,
+    policy: ReconnectPolicy,
+}
+
+impl SubscriptionSession {
+    pub fn new(extractor: ExtractorIdentity) -> Self {
+        Self {
+            extractor,
+            options: SubscriptionOptions::default(),
+            subscription_timeout: DEFAULT_SUBSCRIPTION_TIMEOUT,
+            policy: ReconnectPolicy::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: SubscriptionOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_subscription_timeout(mut self, subscription_timeout: Duration) -> Self {
+        self.subscription_timeout = subscription_timeout;
+        self
+    }
+
+    pub fn with_reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn subscription_timeout(&self) -> Duration {
+        self.subscription_timeout
+    }
+
+    /// Subscribes and forwards the events of the subscription to `data`, reconnecting whenever
+    /// the connection is lost.
+    ///
+    /// `connect` opens a new connection and returns the server address with the command sink and
+    /// message stream of the connection. After subscribing, messages are forwarded with
+    /// [`route_messages`] until the server closes the connection, then the session reconnects
+    /// according to its [`ReconnectPolicy`]. Every transition is published to `lifecycle`,
+    /// `ShutDown` last.
+    ///
+    /// # Errors
+    ///
+    /// * `TychoClientError::ConnectionClosed` - if forwarding a message to `data` fails.
+    /// * The error of the last attempt, once `max_attempts` consecutive attempts failed.
+    pub async fn run<F, Fut, C, M, D>(
+        &self,
+        mut connect: F,
+        data: &mut D,
+        liveness: &LivenessTracker,
+        lifecycle: &Lifecycle,
+    ) -> Result<(), TychoClientError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<(String, C, M), TychoClientError>>,
+        C: Sink<Command> + Unpin,
+        C::Error: std::fmt::Debug,
+        M: Stream<Item = WebSocketMessage> + Unpin,
+        D: Sink<SubscriptionEvent> + Unpin,
+        D::Error: std::fmt::Debug,
+    {
+        let mut attempt = 0;
+        loop {
+            let err = match connect().await {
+                Ok((address, mut commands, mut messages)) => {
+                    lifecycle.publish(LifecycleEvent::Connected { address });
+                    match subscribe(
+                        &mut commands,
+                        &mut messages,
+                        self.extractor.clone(),
+                        self.options,
+                        self.subscription_timeout,
+                    )
+                    .await
+                    {
+                        Ok(Subscription { subscription_id, buffered }) => {
+                            attempt = 0;
+                            lifecycle.publish(LifecycleEvent::Subscribed {
+                                extractor: self.extractor.clone(),
+                                subscription_id,
+                            });
+                            let mut messages = stream::iter(buffered).chain(&mut messages);
+                            if let Err(err) = route_messages(
+                                &mut messages,
+                                data,
+                                self.options.include_snapshot,
+                                liveness,
+                                lifecycle,
+                            )
+                            .await
+                            {
+                                lifecycle
+                                    .publish(LifecycleEvent::ShutDown { reason: err.to_string() });
+                                return Err(err);
+                            }
+                            TychoClientError::ConnectionClosed(format!(
+                                "Server closed the subscription to {}",
+                                self.extractor
+                            ))
+                        }
+                        Err(err) => err,
+                    }
+                }
+                Err(err) => err,
+            };
+            if attempt >= self.policy.max_attempts {
+                lifecycle.publish(LifecycleEvent::ShutDown { reason: err.to_string() });
+                return Err(err);
+            }
+            attempt += 1;
+            lifecycle.publish(LifecycleEvent::Reconnecting { attempt, reason: err.to_string() });
+            tokio::time::sleep(self.policy.delay(attempt)).await;
+        }
+    }
+}
+
+/// Subscribes to and unsubscribes from extractors at runtime, over the connection of a
+/// [`SubscriptionDriver`].
+///
+/// Clones share the driver and the active extractors.
+#[derive(Debug, Clone)]
+pub struct SubscriptionClient {
+    commands: mpsc::UnboundedSender<Command>,
+    state: Arc<Mutex<SubscriptionState>>,
+    subscription_timeout: Duration,
+}
+
+/// The active extractors and the requests waiting for the server's response.
+#[derive(Debug, Default)]
+struct SubscriptionState {
+    active_extractors: HashMap<ExtractorIdentity, Uuid>,
+    /// The options each extractor was subscribed to with, to subscribe again after reconnecting.
+    options: HashMap<ExtractorIdentity, SubscriptionOptions>,
+    /// Extractors that were active or waiting for confirmation when the connection was lost, and
+    /// are subscribed to again on every new connection until the server confirms.
+    resubscriptions: HashMap<ExtractorIdentity, SubscriptionOptions>,
+    pending_subscriptions: HashMap<ExtractorIdentity, Vec<oneshot::Sender<Uuid>>>,
+    pending_unsubscriptions: HashMap<Uuid, oneshot::Sender<()>>,
+}
+
+impl SubscriptionClient {
+    /// Creates a client and the driver which has to run for its requests to be sent.
+    pub fn new() -> (Self, SubscriptionDriver) {
+        let (commands, requests) = mpsc::unbounded_channel();
+        let state = Arc::new(Mutex::new(SubscriptionState::default()));
+        let client = Self {
+            commands,
+            state: state.clone(),
+            subscription_timeout: DEFAULT_SUBSCRIPTION_TIMEOUT,
+        };
+        (client, SubscriptionDriver { requests, state })
+    }
+
+    pub fn with_subscription_timeout(mut self, subscription_timeout: Duration) -> Self {
+        self.subscription_timeout = subscription_timeout;
+        self
+    }
+
+    /// Subscribes to `extractor` and waits for the server to confirm the subscription.
+    ///
+    /// An extractor that is already subscribed to isn't subscribed to again, its subscription id
+    /// is returned right away.
+    ///
+    /// # Errors
+    ///
+    /// * `TychoClientError::SubscriptionTimeout` - if no confirmation arrives within the
+    ///   subscription timeout. Other callers waiting for the same extractor keep waiting; once none
+    ///   is left, a late confirmation ends the subscription again.
+    /// * `TychoClientError::ConnectionClosed` - if the driver stopped before the subscription was
+    ///   confirmed.
+    pub async fn subscribe(
+        &self,
+        extractor: ExtractorIdentity,
+        options: SubscriptionOptions,
+    ) -> Result<Uuid, TychoClientError> {
+        let (confirmation, requested) = {
+            let mut state = self.state.lock().unwrap();
+            if let Some(subscription_id) = state.active_extractors.get(&extractor) {
+                return Ok(*subscription_id);
+            }
+            let resubscribing = state
+                .resubscriptions
+                .contains_key(&extractor);
+            let (sender, confirmation) = oneshot::channel();
+            let waiting = state
+                .pending_subscriptions
+                .entry(extractor.clone())
+                .or_default();
+            waiting.push(sender);
+            let requested = resubscribing || waiting.len() > 1;
+            if !requested {
+                state
+                    .options
+                    .insert(extractor.clone(), options);
+            }
+            (confirmation, requested)
+        };
+        if !requested {
+            if let Err(err) = self.send(Command::subscribe(extractor.clone(), options)) {
+                self.state
+                    .lock()
+                    .unwrap()
+                    .pending_subscriptions
+                    .remove(&extractor);
+                return Err(err);
+            }
+            debug!(%extractor, "SubscriptionRequested");
+        }
+        let started = Instant::now();
+
+        match tokio::time::timeout(self.subscription_timeout, confirmation).await {
+            Ok(Ok(subscription_id)) => Ok(subscription_id),
+            Ok(Err(_)) => Err(TychoClientError::ConnectionClosed(format!(
+                "Connection closed before subscription to {extractor} was confirmed"
+            ))),
+            Err(_) => {
+                // Only this caller gave up, others may still be waiting for the confirmation. The
+                // receiver was dropped with the timed out future, which closes its sender.
+                let mut state = self.state.lock().unwrap();
+                if let Some(waiting) = state
+                    .pending_subscriptions
+                    .get_mut(&extractor)
+                {
+                    waiting.retain(|sender| !sender.is_closed());
+                    if waiting.is_empty() {
+                        state
+                            .pending_subscriptions
+                            .remove(&extractor);
+                    }
+                }
+                drop(state);
+                let waited = started.elapsed();
+                warn!(%extractor, ?waited, "SubscriptionTimeout");
+                Err(TychoClientError::SubscriptionTimeout { extractor, waited })
+            }
+        }
+    }
+
+    /// Ends the subscription to `extractor` and waits for the server to confirm it.
+    ///
+    /// An extractor waiting to be subscribed to again after a reconnect is dropped right away.
+    ///
+    /// # Errors
+    ///
+    /// * `TychoClientError::NotSubscribed` - if `extractor` isn't subscribed to.
+    /// * `TychoClientError::RequestTimeout` - if no confirmation arrives within the subscription
+    ///   timeout. The extractor is still considered active.
+    /// * `TychoClientError::ConnectionClosed` - if the driver stopped before the end of the
+    ///   subscription was confirmed.
+    pub async fn unsubscribe(&self, extractor: &ExtractorIdentity) -> Result<(), TychoClientError> {
+        let (subscription_id, confirmation) = {
+            let mut state = self.state.lock().unwrap();
+            let Some(subscription_id) = state
+                .active_extractors
+                .get(extractor)
+                .copied()
+            else {
+                if state
+                    .resubscriptions
+                    .remove(extractor)
+                    .is_some()
+                {
+                    state.options.remove(extractor);
+                    return Ok(());
+                }
+                return Err(TychoClientError::NotSubscribed(extractor.clone()));
+            };
+            let (sender, confirmation) = oneshot::channel();
+            state
+                .pending_unsubscriptions
+                .insert(subscription_id, sender);
+            (subscription_id, confirmation)
+        };
+        let remove_pending = || {
+            self.state
+                .lock()
+                .unwrap()
+                .pending_unsubscriptions
+                .remove(&subscription_id);
+        };
+        if let Err(err) = self.send(Command::Unsubscribe { subscription_id }) {
+            remove_pending();
+            return Err(err);
+        }
+        debug!(%extractor, %subscription_id, "UnsubscriptionRequested");
+
+        match tokio::time::timeout(self.subscription_timeout, confirmation).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => Err(TychoClientError::ConnectionClosed(format!(
+                "Connection closed before subscription to {extractor} ended"
+            ))),
+            Err(_) => {
+                remove_pending();
+                Err(TychoClientError::RequestTimeout { waited: self.subscription_timeout })
+            }
+        }
+    }
+
+    /// The subscription ids of the extractors currently subscribed to.
+    pub fn active_extractors(&self) -> HashMap<ExtractorIdentity, Uuid> {
+        self.state
+            .lock()
+            .unwrap()
+            .active_extractors
+            .clone()
+    }
+
+    fn send(&self, command: Command) -> Result<(), TychoClientError> {
+        self.commands
+            .send(command)
+            .map_err(|_| {
+                TychoClientError::ConnectionClosed("Subscription driver stopped".to_string())
+            })
+    }
+}
+
+/// What the driver does with a message received from the server.
+enum Routed {
+    /// Forward the message to the consumer.
+    Forward(WebSocketMessage),
+    /// Nothing, the message was a response to a request of the client.
+    Handled,
+    /// End the subscription, confirmed after all its callers timed out.
+    Unsubscribe(Uuid),
+}
+
+/// How a connection driven by a [`SubscriptionDriver`] ended.
+enum ConnectionEnd {
+    /// The server closed the connection.
+    Closed,
+    /// Sending a command over the connection failed.
+    CommandFailed(TychoClientError),
+}
+
+/// Sends the commands of a [`SubscriptionClient`] over a connection and routes the server's
+/// responses back to the client.
+#[derive(Debug)]
+pub struct SubscriptionDriver {
+    requests: mpsc::UnboundedReceiver<Command>,
+    state: Arc<Mutex<SubscriptionState>>,
+}
+
+impl SubscriptionDriver {
+    /// Sends the client's commands to `commands` and forwards all messages except the responses
+    /// to them to `data`, until the message stream ends. Snapshots of extractors subscribed to
+    /// without [`SubscriptionOptions::include_snapshot`] are dropped.
+    ///
+    /// The subscriptions end with the connection: once this returns, the client has no active
+    /// extractors and its requests fail with `TychoClientError::ConnectionClosed`.
+    ///
+    /// # Errors
+    ///
+    /// * `TychoClientError::ConnectionClosed` - if sending a command or forwarding a message fails.
+    pub async fn run<C, M, D>(
+        mut self,
+        commands: &mut C,
+        messages: &mut M,
+        data: &mut D,
+    ) -> Result<(), TychoClientError>
+    where
+        C: Sink<Command> + Unpin,
+        C::Error: std::fmt::Debug,
+        M: Stream<Item = WebSocketMessage> + Unpin,
+        D: Sink<WebSocketMessage> + Unpin,
+        D::Error: std::fmt::Debug,
+    {
+        let res = match self
+            .drive(commands, messages, data)
+            .await
+        {
+            Ok(ConnectionEnd::Closed) => Ok(()),
+            Ok(ConnectionEnd::CommandFailed(err)) | Err(err) => Err(err),
+        };
+        self.stop();
+        res
+    }
+
+    /// Like [`Self::run`], but reconnects according to `policy` whenever the connection is lost.
+    ///
+    /// `connect` opens a new connection and returns the server address with the command sink and
+    /// message stream of the connection. The extractors active when the connection was lost are
+    /// subscribed to again on the new connection, with the options they were first subscribed to
+    /// with, and become active again once the server confirms. So are the extractors whose
+    /// subscription wasn't confirmed yet, so their callers keep waiting instead of timing out.
+    ///
+    /// # Errors
+    ///
+    /// * `TychoClientError::ConnectionClosed` - if forwarding a message to `data` fails.
+    /// * The error of the last attempt, once `max_attempts` consecutive attempts failed.
+    pub async fn run_with_reconnect<F, Fut, C, M, D>(
+        mut self,
+        mut connect: F,
+        data: &mut D,
+        policy: ReconnectPolicy,
+    ) -> Result<(), TychoClientError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<(String, C, M), TychoClientError>>,
+        C: Sink<Command> + Unpin,
+        C::Error: std::fmt::Debug,
+        M: Stream<Item = WebSocketMessage> + Unpin,
+        D: Sink<WebSocketMessage> + Unpin,
+        D::Error: std::fmt::Debug,
+    {
+        let mut attempt = 0;
+        let res = loop {
+            let err = match connect().await {
+                Ok((address, mut commands, mut messages)) => {
+                    attempt = 0;
+                    debug!(%address, "SubscriptionDriverConnected");
+                    match self
+                        .drive(&mut commands, &mut messages, data)
+                        .await
+                    {
+                        Ok(ConnectionEnd::Closed) => TychoClientError::ConnectionClosed(format!(
+                            "Server {address} closed the connection"
+                        )),
+                        Ok(ConnectionEnd::CommandFailed(err)) => err,
+                        Err(err) => break Err(err),
+                    }
+                }
+                Err(err) => err,
+            };
+            self.connection_lost();
+            if attempt >= policy.max_attempts {
+                error!(%err, attempts = attempt, "SubscriptionDriverGaveUp");
+                break Err(err);
+            }
+            attempt += 1;
+            let delay = policy.delay(attempt);
+            warn!(%err, attempt, ?delay, "SubscriptionDriverReconnecting");
+            tokio::time::sleep(delay).await;
+        };
+        self.stop();
+        res
+    }
+
+    /// Drives a connection until it ends, subscribing to the extractors of a lost connection
+    /// first.
+    ///
+    /// Fails if forwarding a message to `data` fails.
+    async fn drive<C, M, D>(
+        &mut self,
+        commands: &mut C,
+        messages: &mut M,
+        data: &mut D,
+    ) -> Result<ConnectionEnd, TychoClientError>
+    where
+        C: Sink<Command> + Unpin,
+        C::Error: std::fmt::Debug,
+        M: Stream<Item = WebSocketMessage> + Unpin,
+        D: Sink<WebSocketMessage> + Unpin,
+        D::Error: std::fmt::Debug,
+    {
+        let resubscriptions: Vec<_> = self
+            .state
+            .lock()
+            .unwrap()
+            .resubscriptions
+            .iter()
+            .map(|(extractor, options)| Command::subscribe(extractor.clone(), *options))
+            .collect();
+        for command in resubscriptions {
+            if let Err(e) = commands.send(command).await {
+                return Ok(ConnectionEnd::CommandFailed(TychoClientError::ConnectionClosed(
+                    format!("{e:?}"),
+                )));
+            }
+        }
+        loop {
+            tokio::select! {
+                Some(command) = self.requests.recv() => {
+                    if !Self::still_requested(&self.state, &command) {
+                        continue;
+                    }
+                    if let Err(e) = commands.send(command).await {
+                        return Ok(ConnectionEnd::CommandFailed(
+                            TychoClientError::ConnectionClosed(format!("{e:?}")),
+                        ));
+                    }
+                }
+                msg = messages.next() => {
+                    let Some(msg) = msg else {
+                        return Ok(ConnectionEnd::Closed);
+                    };
+                    match Self::route_response(&self.state, msg) {
+                        Routed::Forward(msg) => data
+                            .send(msg)
+                            .await
+                            .map_err(|e| TychoClientError::ConnectionClosed(format!("{e:?}")))?,
+                        Routed::Handled => {}
+                        Routed::Unsubscribe(subscription_id) => {
+                            if let Err(e) =
+                                commands.send(Command::Unsubscribe { subscription_id }).await
+                            {
+                                return Ok(ConnectionEnd::CommandFailed(
+                                    TychoClientError::ConnectionClosed(format!("{e:?}")),
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Marks the active extractors to be subscribed to again, except those waiting to be
+    /// unsubscribed from, whose subscriptions ended with the connection. Extractors waiting for
+    /// confirmation are subscribed to again as well, as their request was lost with the connection.
+    fn connection_lost(&self) {
+        let mut state = self.state.lock().unwrap();
+        let pending: Vec<_> = state
+            .pending_subscriptions
+            .keys()
+            .cloned()
+            .collect();
+        for extractor in pending {
+            let options = state
+                .options
+                .get(&extractor)
+                .copied()
+                .unwrap_or_default();
+            state
+                .resubscriptions
+                .insert(extractor, options);
+        }
+        for (extractor, subscription_id) in std::mem::take(&mut state.active_extractors) {
+            if let Some(sender) = state
+                .pending_unsubscriptions
+                .remove(&subscription_id)
+            {
+                state.options.remove(&extractor);
+                let _ = sender.send(());
+                continue;
+            }
+            let options = state
+                .options
+                .get(&extractor)
+                .copied()
+                .unwrap_or_default();
+            state
+                .resubscriptions
+                .insert(extractor, options);
+        }
+    }
+
+    /// Whether `command`, queued by the client, still has to be sent.
+    ///
+    /// A subscription queued before the connection was lost is already requested again with the
+    /// resubscriptions of the new connection, and an unsubscription queued before it was resolved
+    /// when the connection was lost.
+    fn still_requested(state: &Mutex<SubscriptionState>, command: &Command) -> bool {
+        let state = state.lock().unwrap();
+        match command {
+            Command::Subscribe { extractor_id, .. } => !state
+                .resubscriptions
+                .contains_key(extractor_id),
+            Command::Unsubscribe { subscription_id } => state
+                .pending_unsubscriptions
+                .contains_key(subscription_id),
+        }
+    }
+
+    /// Fails all waiting and later requests of the client.
+    fn stop(self) {
+        // Close the channel first, so no request is queued once the waiting ones are dropped.
+        let Self { requests, state } = self;
+        drop(requests);
+        *state.lock().unwrap() = SubscriptionState::default();
+    }
+
+    /// Whether `extractor` was subscribed to with [`SubscriptionOptions::include_snapshot`] set.
+    fn snapshot_requested(state: &Mutex<SubscriptionState>, extractor: &str) -> bool {
+        state
+            .lock()
+            .unwrap()
+            .options
+            .iter()
+            .any(|(identity, options)| identity.name == extractor && options.include_snapshot)
+    }
+
+    /// Resolves the request `msg` responds to, or returns `msg` to be forwarded if it isn't a
+    /// response. Snapshots are only forwarded if the extractor was subscribed to with
+    /// [`SubscriptionOptions::include_snapshot`] set.
+    ///
+    /// A subscription confirmed after all its callers timed out is ended again, as nobody is
+    /// waiting for it.
+    fn route_response(state: &Mutex<SubscriptionState>, msg: WebSocketMessage) -> Routed {
+        let response = match msg {
+            WebSocketMessage::Response(response) => response,
+            WebSocketMessage::Snapshot(snapshot) => {
+                return if Self::snapshot_requested(state, &snapshot.extractor) {
+                    Routed::Forward(WebSocketMessage::Snapshot(snapshot))
+                } else {
+                    debug!(
+                        extractor = snapshot.extractor,
+                        block = snapshot.block.number,
+                        "SkippingSnapshot"
+                    );
+                    Routed::Handled
+                };
+            }
+            msg => return Routed::Forward(msg),
+        };
+        let mut state = state.lock().unwrap();
+        match response {
+            Response::NewSubscription { extractor_id, subscription_id } => {
+                let resubscribed = state
+                    .resubscriptions
+                    .remove(&extractor_id)
+                    .is_some();
+                let waiting = state
+                    .pending_subscriptions
+                    .remove(&extractor_id);
+                if waiting.is_none() && !resubscribed {
+                    let Some(active) = state
+                        .active_extractors
+                        .get(&extractor_id)
+                    else {
+                        warn!(%extractor_id, %subscription_id, "EndingLateSubscription");
+                        state.options.remove(&extractor_id);
+                        return Routed::Unsubscribe(subscription_id);
+                    };
+                    if *active != subscription_id {
+                        warn!(%extractor_id, %subscription_id, "EndingDuplicateSubscription");
+                        return Routed::Unsubscribe(subscription_id);
+                    }
+                    debug!(%extractor_id, "SkippingRepeatedConfirmation");
+                    return Routed::Handled;
+                }
+                state
+                    .active_extractors
+                    .insert(extractor_id, subscription_id);
+                for sender in waiting.unwrap_or_default() {
+                    // The caller may have timed out in the meantime.
+                    let _ = sender.send(subscription_id);
+                }
+            }
+            Response::SubscriptionEnded { subscription_id } => {
+                let ended: Vec<_> = state
+                    .active_extractors
+                    .iter()
+                    .filter(|(_, id)| **id == subscription_id)
+                    .map(|(extractor, _)| extractor.clone())
+                    .collect();
+                for extractor in ended {
+                    state
+                        .active_extractors
+                        .remove(&extractor);
+                    state.options.remove(&extractor);
+                }
+                if let Some(sender) = state
+                    .pending_unsubscriptions
+                    .remove(&subscription_id)
+                {
+                    let _ = sender.send(());
+                }
+            }
+        }
+        Routed::Handled
+    }
+}
+
+/// Decodes a text frame received from the server.
+///
+/// With an account `filter` installed, block changes are decoded with
+/// [`BlockAccountChanges::from_json_filtered`], skipping the updates of all other accounts
+/// without deserializing them. Other messages, and all messages without a filter, are decoded in
+/// full. An [`AccountFilter`](super::bloom::AccountFilter) rules out most untracked accounts
+/// with a single bloom filter probe.
+pub fn decode_message(
+    text: &str,
+    filter: Option<&dyn AccountSet>,
+) -> Result<WebSocketMessage, TychoClientError> {
+    if let Some(accounts) = filter {
+        if let Ok(changes) = BlockAccountChanges::from_json_filtered(text, accounts) {
+            return Ok(WebSocketMessage::BlockAccountChanges(changes));
+        }
+    }
+    serde_json::from_str(text)
+        .map_err(|e| TychoClientError::ParseResponse("websocket message".to_string(), Some(e)))
+}
+
+/// Appends the bearer `token` to the WebSocket URL `url` as the `token` query parameter.
+///
+/// Browsers and most WebSocket clients can't set headers on the upgrade request, so servers
+/// requiring authentication accept the token in the URL instead. The token is percent-encoded
+/// and other query parameters of `url` are kept.
+///
+/// # Errors
+///
+/// * `TychoClientError::UriParsing` - if `url` isn't a valid URL.
+pub fn ws_url_with_token(url: &str, token: &str) -> Result<String, TychoClientError> {
+    let mut url = reqwest::Url::parse(url)
+        .map_err(|err| TychoClientError::UriParsing(url.to_string(), err.to_string()))?;
+    url.query_pairs_mut()
+        .append_pair("token", token);
+    Ok(url.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashSet, VecDeque},
+        sync::{Arc, Mutex},
+    };
+
+    use alloy_primitives::{Address, U256};
+    use futures::channel::mpsc;
+    use rstest::rstest;
+    use tokio::{sync::broadcast, task::JoinHandle};
+
+    use super::*;
+    use crate::evm::{
+        bloom::AccountFilter,
+        tycho_models::{AccountUpdate, Block, Chain, ChangeType, ExtractorStatus, Snapshot},
+    };
+
+    fn extractor(name: &str) -> ExtractorIdentity {
+        ExtractorIdentity::new(Chain::Ethereum, name)
+    }
+
+    fn confirmation(name: &str, subscription_id: Uuid) -> WebSocketMessage {
+        WebSocketMessage::Response(Response::NewSubscription {
+            extractor_id: extractor(name),
+            subscription_id,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_confirmed() {
+        let (mut commands, mut server_commands) = mpsc::unbounded();
+        let (server_messages, mut messages) = mpsc::unbounded();
+        let subscription_id = Uuid::new_v4();
+        server_messages
+            .unbounded_send(confirmation("other", Uuid::new_v4()))
+            .unwrap();
+        server_messages
+            .unbounded_send(changes(1))
+            .unwrap();
+        server_messages
+            .unbounded_send(confirmation("vm:ambient", subscription_id))
+            .unwrap();
+
+        let res = subscribe(
+            &mut commands,
+            &mut messages,
+            extractor("vm:ambient"),
+            SubscriptionOptions::default(),
+            Duration::from_secs(1),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(res.subscription_id, subscription_id);
+        assert!(matches!(
+            res.buffered.as_slice(),
+            [
+                WebSocketMessage::Response(Response::NewSubscription { .. }),
+                WebSocketMessage::BlockAccountChanges(changes)
+            ] if changes.block.number == 1
+        ));
+        assert_eq!(
+            server_commands.next().await,
+            Some(Command::subscribe(extractor("vm:ambient"), SubscriptionOptions::default()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_times_out_without_reply() {
+        let (mut commands, mut server_commands) = mpsc::unbounded();
+        let (server_messages, mut messages) = mpsc::unbounded::<WebSocketMessage>();
+        // The mock server accepts the subscription but never replies.
+        let server = tokio::spawn(async move {
+            let command = server_commands.next().await;
+            (command, server_messages)
+        });
+        let timeout = Duration::from_millis(50);
+
+        let res = subscribe(
+            &mut commands,
+            &mut messages,
+            extractor("vm:ambient"),
+            SubscriptionOptions::default(),
+            timeout,
+        )
+        .await;
+
+        let Err(TychoClientError::SubscriptionTimeout { extractor: timed_out, waited }) = res
+        else {
+            panic!("Expected a subscription timeout, got {res:?}");
+        };
+        assert_eq!(timed_out, extractor("vm:ambient"));
+        assert!(waited >= timeout);
+        let (command, _server_messages) = server.await.unwrap();
+        assert!(matches!(command, Some(Command::Subscribe { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_connection_closed() {
+        let (mut commands, _server_commands) = mpsc::unbounded();
+        let (server_messages, mut messages) = mpsc::unbounded::<WebSocketMessage>();
+        drop(server_messages);
+
+        let res = subscribe(
+            &mut commands,
+            &mut messages,
+            extractor("vm:ambient"),
+            SubscriptionOptions::default(),
+            Duration::from_secs(1),
+        )
+        .await;
+
+        assert!(matches!(res, Err(TychoClientError::ConnectionClosed(_))));
+    }
+
+    /// Answers every command like the server would, until the commands end, and returns the
+    /// commands.
+    async fn answer_commands(
+        mut commands: mpsc::UnboundedReceiver<Command>,
+        messages: mpsc::UnboundedSender<WebSocketMessage>,
+    ) -> Vec<Command> {
+        let mut received = Vec::new();
+        while let Some(command) = commands.next().await {
+            let response = match &command {
+                Command::Subscribe { extractor_id, .. } => Response::NewSubscription {
+                    extractor_id: extractor_id.clone(),
+                    subscription_id: Uuid::new_v4(),
+                },
+                Command::Unsubscribe { subscription_id } => {
+                    Response::SubscriptionEnded { subscription_id: *subscription_id }
+                }
+            };
+            messages
+                .unbounded_send(WebSocketMessage::Response(response))
+                .unwrap();
+            received.push(command);
+        }
+        received
+    }
+
+    type Connection =
+        (String, mpsc::UnboundedSender<Command>, mpsc::UnboundedReceiver<WebSocketMessage>);
+
+    /// A connection answered by [`answer_commands`], with the sender to close it by.
+    fn answered_connection(
+    ) -> (Connection, JoinHandle<Vec<Command>>, mpsc::UnboundedSender<WebSocketMessage>) {
+        let (commands, server_commands) = mpsc::unbounded();
+        let (server_messages, messages) = mpsc::unbounded();
+        let server = tokio::spawn(answer_commands(server_commands, server_messages.clone()));
+        (("ws://localhost:4242".to_string(), commands, messages), server, server_messages)
+    }
+
+    #[tokio::test]
+    async fn test_client_subscribes_and_unsubscribes_at_runtime() {
+        let (mut commands, server_commands) = mpsc::unbounded();
+        let (server_messages, mut messages) = mpsc::unbounded();
+        let (mut data, mut received) = mpsc::unbounded::<WebSocketMessage>();
+        let (client, driver) = SubscriptionClient::new();
+        tokio::spawn(answer_commands(server_commands, server_messages.clone()));
+        tokio::spawn(async move {
+            driver
+                .run(&mut commands, &mut messages, &mut data)
+                .await
+        });
+
+        let ambient = client
+            .subscribe(extractor("vm:ambient"), SubscriptionOptions::default())
+            .await
+            .unwrap();
+        let uniswap = client
+            .subscribe(extractor("vm:uniswap_v2"), SubscriptionOptions::default())
+            .await
+            .unwrap();
+
+        assert_ne!(ambient, uniswap);
+        assert_eq!(
+            client
+                .subscribe(extractor("vm:ambient"), SubscriptionOptions::default())
+                .await
+                .unwrap(),
+            ambient
+        );
+        assert_eq!(
+            client.active_extractors(),
+            HashMap::from([
+                (extractor("vm:ambient"), ambient),
+                (extractor("vm:uniswap_v2"), uniswap)
+            ])
+        );
+
+        client
+            .unsubscribe(&extractor("vm:ambient"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            client.active_extractors(),
+            HashMap::from([(extractor("vm:uniswap_v2"), uniswap)])
+        );
+        let res = client
+            .unsubscribe(&extractor("vm:ambient"))
+            .await;
+        assert!(
+            matches!(res, Err(TychoClientError::NotSubscribed(e)) if e == extractor("vm:ambient"))
+        );
+        // Only messages other than the responses are forwarded.
+        server_messages
+            .unbounded_send(heartbeat(SyncStatus::Synced, 1))
+            .unwrap();
+        assert!(matches!(received.next().await, Some(WebSocketMessage::Heartbeat(_))));
+    }
+
+    #[tokio::test]
+    async fn test_client_fails_once_driver_stopped() {
+        let (mut commands, _server_commands) = mpsc::unbounded();
+        let (server_messages, mut messages) = mpsc::unbounded::<WebSocketMessage>();
+        let (mut data, _received) = mpsc::unbounded::<WebSocketMessage>();
+        let (client, driver) = SubscriptionClient::new();
+        drop(server_messages);
+
+        driver
+            .run(&mut commands, &mut messages, &mut data)
+            .await
+            .unwrap();
+        let res = client
+            .subscribe(extractor("vm:ambient"), SubscriptionOptions::default())
+            .await;
+
+        assert!(matches!(res, Err(TychoClientError::ConnectionClosed(_))));
+        assert!(client.active_extractors().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_client_subscription_timeout() {
+        let (mut commands, mut server_commands) = mpsc::unbounded();
+        let (server_messages, mut messages) = mpsc::unbounded::<WebSocketMessage>();
+        let (mut data, _received) = mpsc::unbounded::<WebSocketMessage>();
+        let (client, driver) = SubscriptionClient::new();
+        let client = client.with_subscription_timeout(Duration::from_millis(50));
+        tokio::spawn(async move {
+            driver
+                .run(&mut commands, &mut messages, &mut data)
+                .await
+        });
+
+        let res = client
+            .subscribe(extractor("vm:ambient"), SubscriptionOptions::default())
+            .await;
+
+        assert!(matches!(res, Err(TychoClientError::SubscriptionTimeout { .. })));
+        assert!(matches!(server_commands.next().await, Some(Command::Subscribe { .. })));
+        assert!(client.active_extractors().is_empty());
+
+        // a confirmation arriving after the timeout ends the subscription again
+        let subscription_id = Uuid::new_v4();
+        server_messages
+            .unbounded_send(confirmation("vm:ambient", subscription_id))
+            .unwrap();
+
+        assert!(matches!(
+            server_commands.next().await,
+            Some(Command::Unsubscribe { subscription_id: id }) if id == subscription_id
+        ));
+        assert!(client.active_extractors().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout_keeps_other_waiters() {
+        let (mut commands, mut server_commands) = mpsc::unbounded();
+        let (server_messages, mut messages) = mpsc::unbounded::<WebSocketMessage>();
+        let (mut data, _received) = mpsc::unbounded::<WebSocketMessage>();
+        let (client, driver) = SubscriptionClient::new();
+        tokio::spawn(async move {
+            driver
+                .run(&mut commands, &mut messages, &mut data)
+                .await
+        });
+        let impatient = client
+            .clone()
+            .with_subscription_timeout(Duration::from_millis(50));
+        let patient = tokio::spawn({
+            let client = client.with_subscription_timeout(Duration::from_secs(10));
+            async move {
+                client
+                    .subscribe(extractor("vm:ambient"), SubscriptionOptions::default())
+                    .await
+            }
+        });
+        assert!(matches!(server_commands.next().await, Some(Command::Subscribe { .. })));
+
+        let res = impatient
+            .subscribe(extractor("vm:ambient"), SubscriptionOptions::default())
+            .await;
+        let subscription_id = Uuid::new_v4();
+        server_messages
+            .unbounded_send(confirmation("vm:ambient", subscription_id))
+            .unwrap();
+
+        assert!(matches!(res, Err(TychoClientError::SubscriptionTimeout { .. })));
+        assert_eq!(patient.await.unwrap().unwrap(), subscription_id);
+        assert_eq!(
+            impatient.active_extractors(),
+            HashMap::from([(extractor("vm:ambient"), subscription_id)])
+        );
+    }
+
+    fn heartbeat(status: SyncStatus, latest_block: u64) -> WebSocketMessage {
+        WebSocketMessage::Heartbeat(ExtractorStatus {
+            extractor: "vm:ambient".to_string(),
+            chain: Chain::Ethereum,
+            status,
+            latest_block,
+        })
+    }
+
+    fn changes(block_number: u64) -> WebSocketMessage {
+        WebSocketMessage::BlockAccountChanges(BlockAccountChanges::new(
+            "vm:ambient".to_string(),
+            Chain::Ethereum,
+            Block { number: block_number, ..Default::default() },
+            HashMap::new(),
+            HashMap::new(),
+        ))
+    }
+
+    #[test]
+    fn test_reconnect_delay_grows() {
+        let policy = ReconnectPolicy::default();
+
+        let delays: Vec<_> = (1..=4)
+            .map(|attempt| policy.delay(attempt))
+            .collect();
+
+        assert_eq!(
+            delays,
+            [500, 1_000, 2_000, 4_000]
+                .map(Duration::from_millis)
+                .to_vec()
+        );
+        let policy = ReconnectPolicy { multiplier: u32::MAX, ..policy };
+        assert_eq!(policy.delay(10), Duration::MAX);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_driver_resubscribes_after_reconnect() {
+        let (first, _, first_messages) = answered_connection();
+        let (second, second_server, second_messages) = answered_connection();
+        let refused =
+            || TychoClientError::Connect("ws://localhost:4242".to_string(), "refused".to_string());
+        let mut script = VecDeque::from([Ok(first), Err(refused()), Ok(second)]);
+        let (mut data, _received) = mpsc::unbounded::<WebSocketMessage>();
+        let (client, driver) = SubscriptionClient::new();
+        let policy = ReconnectPolicy { max_attempts: 2, ..ReconnectPolicy::default() };
+        let driver = tokio::spawn(async move {
+            driver
+                .run_with_reconnect(
+                    || {
+                        let next: Result<Connection, _> = script
+                            .pop_front()
+                            .unwrap_or_else(|| Err(refused()));
+                        async move { next }
+                    },
+                    &mut data,
+                    policy,
+                )
+                .await
+        });
+        let options = SubscriptionOptions::new(true, None);
+        let extractors = [extractor("vm:ambient"), extractor("vm:uniswap_v2")];
+        let mut subscribed = HashMap::new();
+        for extractor in &extractors {
+            let subscription_id = client
+                .subscribe(extractor.clone(), options)
+                .await
+                .unwrap();
+            subscribed.insert(extractor.clone(), subscription_id);
+        }
+
+        first_messages.close_channel();
+        let mut resubscribed = HashMap::new();
+        for _ in 0..100 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            resubscribed = client.active_extractors();
+            if resubscribed.len() == extractors.len() {
+                break;
+            }
+        }
+
+        assert_eq!(
+            resubscribed
+                .keys()
+                .collect::<HashSet<_>>(),
+            subscribed
+                .keys()
+                .collect::<HashSet<_>>()
+        );
+        assert!(resubscribed
+            .iter()
+            .all(|(extractor, id)| subscribed[extractor] != *id));
+
+        second_messages.close_channel();
+        let res = driver.await.unwrap();
+
+        assert!(matches!(res, Err(TychoClientError::Connect(..))));
+        let commands = second_server.await.unwrap();
+        assert_eq!(commands.len(), extractors.len());
+        assert!(commands.iter().all(|command| matches!(
+            command,
+            Command::Subscribe { extractor_id, options: sent }
+                if extractors.contains(extractor_id) && *sent == options
+        )));
+        assert!(client.active_extractors().is_empty());
+        assert!(matches!(
+            client
+                .subscribe(extractor("vm:ambient"), options)
+                .await,
+            Err(TychoClientError::ConnectionClosed(_))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_driver_resubscribes_pending_after_reconnect() {
+        // the first server never answers, so the subscription is pending when it disconnects
+        let (commands, mut first_server) = mpsc::unbounded();
+        let (first_messages, messages) = mpsc::unbounded();
+        let first: Connection = ("ws://localhost:4242".to_string(), commands, messages);
+        let (second, second_server, second_messages) = answered_connection();
+        let mut script = VecDeque::from([first, second]);
+        let (mut data, _received) = mpsc::unbounded::<WebSocketMessage>();
+        let (client, driver) = SubscriptionClient::new();
+        let client = client.with_subscription_timeout(Duration::from_secs(10));
+        let policy = ReconnectPolicy { max_attempts: 1, ..ReconnectPolicy::default() };
+        tokio::spawn(async move {
+            driver
+                .run_with_reconnect(
+                    || {
+                        let next = script.pop_front().ok_or_else(|| {
+                            TychoClientError::Connect(
+                                "ws://localhost:4242".to_string(),
+                                "refused".to_string(),
+                            )
+                        });
+                        async move { next }
+                    },
+                    &mut data,
+                    policy,
+                )
+                .await
+        });
+        let subscription = tokio::spawn({
+            let client = client.clone();
+            async move {
+                client
+                    .subscribe(extractor("vm:ambient"), SubscriptionOptions::default())
+                    .await
+            }
+        });
+
+        assert!(matches!(first_server.next().await, Some(Command::Subscribe { .. })));
+        first_messages.close_channel();
+        let subscription_id = subscription.await.unwrap().unwrap();
+
+        assert_eq!(
+            client.active_extractors(),
+            HashMap::from([(extractor("vm:ambient"), subscription_id)])
+        );
+        second_messages.close_channel();
+        assert_eq!(second_server.await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_session_forwards_messages_before_confirmation() {
+        let (commands, _server_commands) = mpsc::unbounded();
+        let (server_messages, messages) = mpsc::unbounded();
+        for msg in [snapshot(10), confirmation("vm:ambient", Uuid::new_v4()), changes(11)] {
+            server_messages
+                .unbounded_send(msg)
+                .unwrap();
+        }
+        drop(server_messages);
+        let mut connection = Some(("ws://localhost:4242".to_string(), commands, messages));
+        let (mut data, received) = mpsc::unbounded::<SubscriptionEvent>();
+        let session = SubscriptionSession::new(extractor("vm:ambient"))
+            .with_options(SubscriptionOptions::new(true, None))
+            .with_reconnect_policy(ReconnectPolicy {
+                max_attempts: 0,
+                ..ReconnectPolicy::default()
+            });
+
+        let res = session
+            .run(
+                || {
+                    let next = connection.take().ok_or_else(|| {
+                        TychoClientError::Connect(
+                            "ws://localhost:4242".to_string(),
+                            "refused".to_string(),
+                        )
+                    });
+                    async move { next }
+                },
+                &mut data,
+                &LivenessTracker::new(),
+                &Lifecycle::new(),
+            )
+            .await;
+        drop(data);
+
+        assert!(matches!(res, Err(TychoClientError::ConnectionClosed(_))));
+        let blocks: Vec<_> = received
+            .map(|event| event.block().number)
+            .collect()
+            .await;
+        assert_eq!(blocks, [10, 11]);
+    }
+
+    #[tokio::test]
+    async fn test_route_messages_consumes_heartbeats() {
+        let (server_messages, mut messages) = mpsc::unbounded();
+        let (mut data, data_rx) = mpsc::unbounded();
+        let (events_tx, mut events) = tokio::sync::mpsc::unbounded_channel();
+        let liveness = LivenessTracker::new().with_events(events_tx);
+        for msg in [
+            heartbeat(SyncStatus::Synced, 1),
+            changes(1),
+            heartbeat(SyncStatus::Lagging, 1),
+            changes(2),
+            heartbeat(SyncStatus::Synced, 3),
+            changes(3),
+        ] {
+            server_messages
+                .unbounded_send(msg)
+                .unwrap();
+        }
+        drop(server_messages);
+
+        route_messages(&mut messages, &mut data, false, &liveness, &Lifecycle::new())
+            .await
+            .unwrap();
+        drop(data);
+
+        let blocks: Vec<_> = data_rx
+            .map(|event| match event {
+                SubscriptionEvent::Changes(changes) => changes.block.number,
+                other => panic!("Expected block changes, got {other:?}"),
+            })
+            .collect()
+            .await;
+        assert_eq!(blocks, vec![1, 2, 3]);
+        let mut transitions = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            transitions.push(event.current);
+        }
+        assert_eq!(transitions, vec![SyncStatus::Synced, SyncStatus::Lagging, SyncStatus::Synced]);
+        assert_eq!(liveness.status("vm:ambient"), Some(SyncStatus::Synced));
+    }
+
+    #[rstest]
+    #[case::with_snapshot(true, vec![(true, 10), (false, 11), (false, 12)])]
+    #[case::without_snapshot(false, vec![(false, 11), (false, 12)])]
+    #[tokio::test]
+    async fn test_route_messages_gates_snapshot(
+        #[case] include_snapshot: bool,
+        #[case] expected: Vec<(bool, u64)>,
+    ) {
+        let (server_messages, mut messages) = mpsc::unbounded();
+        let (mut data, data_rx) = mpsc::unbounded();
+        // a snapshot after the first changes would rewind the consumer's state
+        for msg in [snapshot(10), changes(11), snapshot(11), changes(12)] {
+            server_messages
+                .unbounded_send(msg)
+                .unwrap();
+        }
+        drop(server_messages);
+
+        route_messages(
+            &mut messages,
+            &mut data,
+            include_snapshot,
+            &LivenessTracker::new(),
+            &Lifecycle::new(),
+        )
+        .await
+        .unwrap();
+        drop(data);
+
+        let events: Vec<_> = data_rx
+            .map(|event| (matches!(event, SubscriptionEvent::Snapshot(_)), event.block().number))
+            .collect()
+            .await;
+        assert_eq!(events, expected);
+    }
+
+    #[rstest]
+    #[case::with_snapshot(true)]
+    #[case::without_snapshot(false)]
+    #[tokio::test]
+    async fn test_driver_forwards_requested_snapshots_only(#[case] include_snapshot: bool) {
+        let (mut commands, server_commands) = mpsc::unbounded();
+        let (server_messages, mut messages) = mpsc::unbounded();
+        let (mut data, mut received) = mpsc::unbounded::<WebSocketMessage>();
+        let (client, driver) = SubscriptionClient::new();
+        tokio::spawn(answer_commands(server_commands, server_messages.clone()));
+        tokio::spawn(async move {
+            driver
+                .run(&mut commands, &mut messages, &mut data)
+                .await
+        });
+        client
+            .subscribe(extractor("vm:ambient"), SubscriptionOptions::new(include_snapshot, None))
+            .await
+            .unwrap();
+
+        for msg in [snapshot(10), changes(11)] {
+            server_messages
+                .unbounded_send(msg)
+                .unwrap();
+        }
+
+        if include_snapshot {
+            assert!(matches!(
+                received.next().await,
+                Some(WebSocketMessage::Snapshot(snapshot)) if snapshot.block.number == 10
+            ));
+        }
+        assert!(matches!(
+            received.next().await,
+            Some(WebSocketMessage::BlockAccountChanges(changes)) if changes.block.number == 11
+        ));
+    }
+
+    fn snapshot(block_number: u64) -> WebSocketMessage {
+        WebSocketMessage::Snapshot(Snapshot::new(
+            "vm:ambient".to_string(),
+            Chain::Ethereum,
+            Block { number: block_number, ..Default::default() },
+            Vec::new(),
+        ))
+    }
+
+    /// What a consumer observes, in order: the blocks forwarded to it and lifecycle transitions.
+    #[derive(Debug, PartialEq)]
+    enum Observed {
+        Block(u64),
+        Event(LifecycleEvent),
+    }
+
+    type Events = Arc<Mutex<broadcast::Receiver<LifecycleEvent>>>;
+
+    fn drain_events(events: &Events, log: &Mutex<Vec<Observed>>) {
+        while let Ok(event) = events.lock().unwrap().try_recv() {
+            log.lock()
+                .unwrap()
+                .push(Observed::Event(event));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session_lifecycle() {
+        let (lifecycle, events) = Lifecycle::channel();
+        let events = Arc::new(Mutex::new(events));
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let subscription_ids = [Uuid::new_v4(), Uuid::new_v4()];
+        let address = "ws://localhost:4242".to_string();
+        let refused = || TychoClientError::Connect(address.clone(), "refused".to_string());
+        let mut server_commands = Vec::new();
+        let mut connection = |subscription_id, scripted: Vec<WebSocketMessage>| {
+            let (commands, commands_rx) = mpsc::unbounded();
+            let (server_messages, messages) = mpsc::unbounded();
+            server_commands.push(commands_rx);
+            for msg in std::iter::once(confirmation("vm:ambient", subscription_id)).chain(scripted)
+            {
+                server_messages
+                    .unbounded_send(msg)
+                    .unwrap();
+            }
+            // the server closes the connection after the scripted messages
+            Ok((address.clone(), commands, messages))
+        };
+        let mut script = VecDeque::from([
+            connection(
+                subscription_ids[0],
+                vec![snapshot(10), heartbeat(SyncStatus::Synced, 12), changes(11), changes(12)],
+            ),
+            Err(refused()),
+            connection(
+                subscription_ids[1],
+                vec![
+                    snapshot(13),
+                    heartbeat(SyncStatus::Lagging, 13),
+                    heartbeat(SyncStatus::Synced, 14),
+                    changes(14),
+                ],
+            ),
+        ]);
+        let mut data = Box::pin(futures::sink::unfold(
+            (log.clone(), events.clone()),
+            |(log, events), event: SubscriptionEvent| async move {
+                drain_events(&events, &log);
+                log.lock()
+                    .unwrap()
+                    .push(Observed::Block(event.block().number));
+                Ok::<_, ()>((log, events))
+            },
+        ));
+        let session = SubscriptionSession::new(extractor("vm:ambient"))
+            .with_options(SubscriptionOptions::new(true, None))
+            .with_reconnect_policy(ReconnectPolicy {
+                max_attempts: 2,
+                backoff: Duration::ZERO,
+                multiplier: 1,
+            });
+
+        let res = session
+            .run(
+                || {
+                    let next = script
+                        .pop_front()
+                        .unwrap_or_else(|| Err(refused()));
+                    async move { next }
+                },
+                &mut data,
+                &LivenessTracker::new(),
+                &lifecycle,
+            )
+            .await;
+        drain_events(&events, &log);
+
+        assert!(matches!(res, Err(TychoClientError::Connect(..))));
+        let connected = || Observed::Event(LifecycleEvent::Connected { address: address.clone() });
+        let subscribed = |subscription_id| {
+            Observed::Event(LifecycleEvent::Subscribed {
+                extractor: extractor("vm:ambient"),
+                subscription_id,
+            })
+        };
+        let closed = TychoClientError::ConnectionClosed(format!(
+            "Server closed the subscription to {}",
+            extractor("vm:ambient")
+        ))
+        .to_string();
+        let reconnecting = |attempt, reason: String| {
+            Observed::Event(LifecycleEvent::Reconnecting { attempt, reason })
+        };
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                connected(),
+                subscribed(subscription_ids[0]),
+                Observed::Block(10),
+                Observed::Event(LifecycleEvent::SnapshotApplied { block: 10 }),
+                Observed::Block(11),
+                Observed::Block(12),
+                Observed::Event(LifecycleEvent::CaughtUp { block: 12 }),
+                reconnecting(1, closed.clone()),
+                reconnecting(2, refused().to_string()),
+                connected(),
+                subscribed(subscription_ids[1]),
+                Observed::Block(13),
+                Observed::Event(LifecycleEvent::SnapshotApplied { block: 13 }),
+                Observed::Event(LifecycleEvent::Degraded {
+                    reason: "vm:ambient is Lagging".to_string()
+                }),
+                Observed::Block(14),
+                Observed::Event(LifecycleEvent::CaughtUp { block: 14 }),
+                reconnecting(1, closed),
+                reconnecting(2, refused().to_string()),
+                Observed::Event(LifecycleEvent::ShutDown { reason: refused().to_string() }),
+            ]
+        );
+        assert_eq!(server_commands.len(), 2);
+    }
+
+    #[rstest]
+    #[case::plain("ws://localhost:4242/v1/ws", "ws://localhost:4242/v1/ws?token=secret%2Bkey")]
+    #[case::query("wss://tycho.xyz/ws?chain=1", "wss://tycho.xyz/ws?chain=1&token=secret%2Bkey")]
+    fn test_ws_url_with_token(#[case] url: &str, #[case] expected: &str) {
+        assert_eq!(ws_url_with_token(url, "secret+key").unwrap(), expected);
+    }
+
+    #[test]
+    fn test_decode_message_with_filter() {
+        let kept = Address::repeat_byte(0x01);
+        let dropped = Address::repeat_byte(0x02);
+        let update = |address| {
+            AccountUpdate::new(
+                address,
+                Chain::Ethereum,
+                HashMap::new(),
+                Some(U256::from(1u64)),
+                None,
+                ChangeType::Update,
+            )
+        };
+        let changes = BlockAccountChanges::new(
+            "vm:ambient".to_string(),
+            Chain::Ethereum,
+            Block::default(),
+            HashMap::from([(kept, update(kept)), (dropped, update(dropped))]),
+            HashMap::new(),
+        );
+        let changes_json = serde_json::to_string(&changes).unwrap();
+        let confirmation_json =
+            serde_json::to_string(&confirmation("vm:ambient", Uuid::new_v4())).unwrap();
+        let filter = HashSet::from([kept]);
+        let mut bloom_filter = AccountFilter::default();
+        bloom_filter.insert(kept);
+
+        let filtered = decode_message(&changes_json, Some(&filter)).unwrap();
+        let bloom_filtered = decode_message(&changes_json, Some(&bloom_filter)).unwrap();
+        let unfiltered = decode_message(&changes_json, None).unwrap();
+        let response = decode_message(&confirmation_json, Some(&filter)).unwrap();
+
+        for filtered in [filtered, bloom_filtered] {
+            let WebSocketMessage::BlockAccountChanges(filtered) = filtered else {
+                panic!("Expected block changes, got {filtered:?}");
+            };
+            assert_eq!(
+                filtered
+                    .account_updates
+                    .keys()
+                    .collect::<Vec<_>>(),
+                vec![&kept]
+            );
+        }
+        assert_eq!(bloom_filter.stats().probes, 2);
+        assert!(matches!(
+            unfiltered,
+            WebSocketMessage::BlockAccountChanges(c) if c == changes
+        ));
+        assert!(matches!(response, WebSocketMessage::Response(_)));
+    }
+}
@@ -0,0 +1,366 @@
+//! Transfer gas of tokens registered without one.
+//!
+//! The tokens endpoint often has no transfer gas for new tokens, which makes routes through them
+//! look cheaper than they are. [`TokenGasBackfill`] queues such tokens when they are registered
+//! and measures their gas with a [`TokenGasMeter`], which simulates `transfer` calls between two
+//! accounts it funds with storage overrides. Measured values are written to the
+//! [`TokenRegistry`] and kept in a [`TokenGasCache`] that is persisted across runs, so each token
+//! is only measured once.
+//!
+//! Measuring runs several simulations per token and is meant to be driven off the quoting path,
+//! e.g. from a background task calling [`TokenGasBackfill::process`] on every new block.
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Debug,
+    fs,
+    path::Path,
+    sync::Arc,
+};
+
+use alloy_primitives::{Address, U256};
+use num_bigint::BigUint;
+use num_traits::Zero;
+use revm::DatabaseRef;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{debug, warn};
+use tycho_common::Bytes;
+
+use crate::{
+    evm::{
+        engine_db::{
+            engine_db_interface::{EngineDatabaseError, EngineDatabaseInterface},
+            simulation_db::BlockHeader,
+        },
+        protocol::{
+            utils::bytes_to_address,
+            vm::{
+                constants::MAX_BALANCE,
+                erc20_token::{brute_force_slots, ERC20OverwriteFactory},
+                tycho_simulation_contract::TychoSimulationContract,
+            },
+        },
+        simulation::SimulationEngine,
+    },
+    models::Token,
+    protocol::errors::SimulationError,
+    token_registry::TokenRegistry,
+};
+
+/// Gas recorded for tokens whose transfers can't be simulated. Chosen well above the cost of
+/// common tokens so routes through such tokens are rather overestimated.
+pub const DEFAULT_TRANSFER_GAS: u64 = 100_000;
+/// Amounts transferred per measurement. The median of their gas is recorded, so a token charging
+/// more for some amounts isn't measured at its extreme.
+const MEASURED_AMOUNTS: [u64; 3] = [1, 1_000_000, 1_000_000_000_000_000_000];
+const SENDER: Address = Address::new([0x5e; 20]);
+const RECIPIENT: Address = Address::new([0x5f; 20]);
+
+#[derive(Error, Debug, PartialEq)]
+pub enum TokenGasError {
+    #[error("Failed to access token gas cache: {0}")]
+    Io(String),
+    #[error("Failed to parse token gas cache: {0}")]
+    Parse(String),
+}
+
+/// The transfer gas measured for a token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenGas {
+    pub gas: u64,
+    /// Whether the transfer couldn't be simulated and `gas` is [`DEFAULT_TRANSFER_GAS`].
+    pub fallback: bool,
+}
+
+/// Measured transfer gas by token address, persisted across runs.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenGasCache {
+    tokens: HashMap<Bytes, TokenGas>,
+}
+
+impl TokenGasCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the gas saved by a previous run. A missing file yields an empty cache.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, TokenGasError> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::new()),
+            Err(err) => return Err(TokenGasError::Io(err.to_string())),
+        };
+        serde_json::from_str(&content).map_err(|err| TokenGasError::Parse(err.to_string()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), TokenGasError> {
+        let content = serde_json::to_string(self).expect("Token gas is always serializable");
+        fs::write(path, content).map_err(|err| TokenGasError::Io(err.to_string()))
+    }
+
+    pub fn get(&self, token: &Bytes) -> Option<TokenGas> {
+        self.tokens.get(token).copied()
+    }
+
+    pub fn insert(&mut self, token: Bytes, gas: TokenGas) {
+        self.tokens.insert(token, gas);
+    }
+}
+
+/// Measures the transfer gas of tokens by simulating transfers.
+pub struct TokenGasMeter<D: EngineDatabaseInterface + Clone + Debug>
+where
+    <D as DatabaseRef>::Error: EngineDatabaseError,
+    <D as EngineDatabaseInterface>::Error: Debug,
+{
+    engine: SimulationEngine<D>,
+}
+
+impl<D: EngineDatabaseInterface + Clone + Debug> TokenGasMeter<D>
+where
+    <D as DatabaseRef>::Error: EngineDatabaseError,
+    <D as EngineDatabaseInterface>::Error: Debug,
+{
+    pub fn new(engine: SimulationEngine<D>) -> Self {
+        Self { engine }
+    }
+
+    /// Measures the gas of transferring the token at `token`.
+    ///
+    /// Tokens whose transfers fail are recorded with [`DEFAULT_TRANSFER_GAS`] and flagged as a
+    /// fallback.
+    pub fn measure(&self, token: &Bytes, block: &BlockHeader) -> TokenGas {
+        match self.median_transfer_gas(token, block) {
+            Ok(gas) => TokenGas { gas, fallback: false },
+            Err(err) => {
+                warn!(?token, ?err, "Failed to measure transfer gas, using default");
+                TokenGas { gas: DEFAULT_TRANSFER_GAS, fallback: true }
+            }
+        }
+    }
+
+    fn median_transfer_gas(
+        &self,
+        token: &Bytes,
+        block: &BlockHeader,
+    ) -> Result<u64, SimulationError> {
+        let address = bytes_to_address(token)?;
+        // Tokens whose balance slot can't be found are still measured without funding the
+        // sender, in case their transfers don't check balances.
+        let overrides = brute_force_slots(&address, block, &self.engine)
+            .ok()
+            .map(|(slots, compiler)| {
+                let mut overwrites = ERC20OverwriteFactory::new(address, slots, compiler);
+                overwrites.set_balance(*MAX_BALANCE, SENDER);
+                overwrites.get_overwrites()
+            });
+        let contract = TychoSimulationContract::new(address, self.engine.clone())?;
+        let mut gas = MEASURED_AMOUNTS
+            .iter()
+            .map(|amount| {
+                Ok(contract
+                    .call(
+                        "transfer(address,uint256)",
+                        (RECIPIENT, U256::from(*amount)),
+                        block.number,
+                        Some(block.timestamp),
+                        overrides.clone(),
+                        Some(SENDER),
+                        U256::ZERO,
+                    )?
+                    .simulation_result
+                    .gas_used)
+            })
+            .collect::<Result<Vec<_>, SimulationError>>()?;
+        gas.sort_unstable();
+        Ok(gas[gas.len() / 2])
+    }
+}
+
+/// Fills in the transfer gas of tokens registered without one.
+pub struct TokenGasBackfill<D: EngineDatabaseInterface + Clone + Debug>
+where
+    <D as DatabaseRef>::Error: EngineDatabaseError,
+    <D as EngineDatabaseInterface>::Error: Debug,
+{
+    meter: TokenGasMeter<D>,
+    registry: Arc<TokenRegistry>,
+    cache: TokenGasCache,
+    queue: VecDeque<Bytes>,
+}
+
+impl<D: EngineDatabaseInterface + Clone + Debug> TokenGasBackfill<D>
+where
+    <D as DatabaseRef>::Error: EngineDatabaseError,
+    <D as EngineDatabaseInterface>::Error: Debug,
+{
+    pub fn new(
+        meter: TokenGasMeter<D>,
+        registry: Arc<TokenRegistry>,
+        cache: TokenGasCache,
+    ) -> Self {
+        Self { meter, registry, cache, queue: VecDeque::new() }
+    }
+
+    /// Registers `token`, see [`TokenRegistry::get_or_insert`].
+    ///
+    /// A token without gas gets the gas cached by a previous run, or is queued for measurement
+    /// if there is none.
+    pub fn register(&mut self, token: Token, quality: u32) -> Arc<Token> {
+        let token = self
+            .registry
+            .get_or_insert(token, quality);
+        if !token.gas.is_zero() {
+            return token;
+        }
+        match self.cache.get(&token.address) {
+            Some(cached) => {
+                self.registry
+                    .update_gas(&token.address, BigUint::from(cached.gas));
+            }
+            None if !self.queue.contains(&token.address) => {
+                debug!(address = ?token.address, "Queueing transfer gas measurement");
+                self.queue
+                    .push_back(token.address.clone());
+            }
+            None => {}
+        }
+        self.registry
+            .by_address(&token.address)
+            .unwrap_or(token)
+    }
+
+    /// Number of tokens waiting to be measured.
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Measures all queued tokens at `block`, updating the registry and the cache.
+    pub fn process(&mut self, block: &BlockHeader) -> Vec<(Bytes, TokenGas)> {
+        let mut measured = Vec::with_capacity(self.queue.len());
+        while let Some(address) = self.queue.pop_front() {
+            let gas = self.meter.measure(&address, block);
+            self.registry
+                .update_gas(&address, BigUint::from(gas.gas));
+            self.cache.insert(address.clone(), gas);
+            measured.push((address, gas));
+        }
+        measured
+    }
+
+    /// The measured gas, to be saved for the next run.
+    pub fn cache(&self) -> &TokenGasCache {
+        &self.cache
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::hex;
+    use revm::primitives::{AccountInfo, Bytecode, KECCAK_EMPTY};
+
+    use super::*;
+    use crate::{
+        evm::{
+            engine_db::{create_engine, tycho_db::PreCachedDB},
+            protocol::vm::constants::ERC20_BYTECODE,
+        },
+        routing::pool_graph::Route,
+        token_registry::DEFAULT_TOKEN_QUALITY,
+    };
+
+    const STANDARD: Address = Address::new([0x01; 20]);
+    const STORAGE_HEAVY: Address = Address::new([0x02; 20]);
+    const REVERTING: Address = Address::new([0x03; 20]);
+    /// Writes the transferred amount to three storage slots and returns `true` for any call.
+    const STORAGE_HEAVY_BYTECODE: &str = "6024358060015580600255600355600160005260206000f3";
+    /// Reverts every call.
+    const REVERTING_BYTECODE: &str = "60006000fd";
+
+    fn token(address: Address) -> Token {
+        Token::new(&address.to_string(), 18, "T", BigUint::zero())
+    }
+
+    fn meter() -> TokenGasMeter<PreCachedDB> {
+        let engine = create_engine(PreCachedDB::new().unwrap(), false).unwrap();
+        for (address, code) in [
+            (STANDARD, ERC20_BYTECODE.to_vec()),
+            (STORAGE_HEAVY, hex::decode(STORAGE_HEAVY_BYTECODE).unwrap()),
+            (REVERTING, hex::decode(REVERTING_BYTECODE).unwrap()),
+        ] {
+            engine.state.init_account(
+                address,
+                AccountInfo {
+                    balance: U256::ZERO,
+                    nonce: 0,
+                    code_hash: KECCAK_EMPTY,
+                    code: Some(Bytecode::new_raw(code.into())),
+                },
+                None,
+                true,
+            );
+        }
+        for account in [SENDER, RECIPIENT] {
+            engine
+                .state
+                .init_account(account, AccountInfo::default(), None, true);
+        }
+        TokenGasMeter::new(engine)
+    }
+
+    #[test]
+    fn test_measure() {
+        let meter = meter();
+        let block = BlockHeader::default();
+
+        let standard = meter.measure(&token(STANDARD).address, &block);
+        let storage_heavy = meter.measure(&token(STORAGE_HEAVY).address, &block);
+        let reverting = meter.measure(&token(REVERTING).address, &block);
+
+        assert!(!standard.fallback);
+        assert!(!storage_heavy.fallback);
+        // three writes to empty slots cost more than the two balance updates of a transfer
+        assert!(storage_heavy.gas > standard.gas);
+        assert_eq!(reverting, TokenGas { gas: DEFAULT_TRANSFER_GAS, fallback: true });
+    }
+
+    #[test]
+    fn test_backfill_updates_route_gas() {
+        let registry = Arc::new(TokenRegistry::new());
+        let weth = Token::new(
+            "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+            18,
+            "WETH",
+            BigUint::from(30_000u64),
+        );
+        let mut cache = TokenGasCache::new();
+        cache.insert(token(REVERTING).address, TokenGas { gas: 40_000, fallback: false });
+        let mut backfill = TokenGasBackfill::new(meter(), registry.clone(), cache);
+        let route = Route {
+            tokens: vec![weth.address.clone(), token(STANDARD).address],
+            pools: vec!["pool".to_string()],
+            rate: 1.0,
+        };
+
+        backfill.register(weth, DEFAULT_TOKEN_QUALITY);
+        backfill.register(token(STANDARD), DEFAULT_TOKEN_QUALITY);
+        let cached = backfill.register(token(REVERTING), DEFAULT_TOKEN_QUALITY);
+
+        assert_eq!(cached.gas, BigUint::from(40_000u64));
+        assert_eq!(backfill.pending(), 1);
+        assert_eq!(route.transfer_gas(&registry), Some(BigUint::from(30_000u64)));
+
+        let measured = backfill.process(&BlockHeader::default());
+
+        assert_eq!(measured.len(), 1);
+        let standard = measured[0].1;
+        assert_eq!(backfill.pending(), 0);
+        assert_eq!(
+            backfill
+                .cache()
+                .get(&token(STANDARD).address),
+            Some(standard)
+        );
+        assert_eq!(route.transfer_gas(&registry), Some(BigUint::from(30_000u64 + standard.gas)));
+    }
+}
@@ -0,0 +1,371 @@
+//! Establishing the TCP connection underneath the WebSocket transport.
+//!
+//! Without a timeout, connecting to an unreachable server blocks until the OS gives up, which can
+//! take minutes; without keepalive probes, a connection that dropped silently is only noticed once
+//! the next write fails. [`TransportOptions::connect`] opens the TCP stream with both configured,
+//! and [`TransportOptions::handshake`] bounds the TLS and WebSocket handshakes performed over it.
+//! [`TransportOptions::connect_websocket`] does both and returns a connection as expected by the
+//! `connect` functions of [`SubscriptionSession`] and [`SubscriptionDriver`].
+//!
+//! [`SubscriptionSession`]: super::subscription::SubscriptionSession
+//! [`SubscriptionDriver`]: super::subscription::SubscriptionDriver
+use std::{future::Future, time::Duration};
+
+use futures::{future, Sink, SinkExt, Stream, StreamExt};
+use socket2::{SockRef, TcpKeepalive};
+use tokio::{
+    net::{TcpStream, ToSocketAddrs},
+    time::Instant,
+};
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message};
+use tracing::{debug, warn};
+
+use super::{
+    engine_db::tycho_db::TychoClientError,
+    tycho_models::{Command, WebSocketMessage},
+};
+
+/// How long to wait for the TCP connection, and separately for the handshakes, by default.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Idle time before the first keepalive probe, and the interval between probes, by default.
+pub const DEFAULT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Socket options of the WebSocket connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransportOptions {
+    /// Bounds the TCP connect and, separately, the handshakes. Defaults to
+    /// [`DEFAULT_CONNECT_TIMEOUT`].
+    pub connect_timeout: Duration,
+    /// Interval of TCP keepalive probes, `None` to disable them. Defaults to
+    /// [`DEFAULT_KEEPALIVE_INTERVAL`].
+    pub keepalive_interval: Option<Duration>,
+    /// Disables Nagle's algorithm, so small frames such as commands are sent immediately.
+    /// Defaults to `true`.
+    pub nodelay: bool,
+}
+
+impl Default for TransportOptions {
+    fn default() -> Self {
+        Self {
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            keepalive_interval: Some(DEFAULT_KEEPALIVE_INTERVAL),
+            nodelay: true,
+        }
+    }
+}
+
+impl TransportOptions {
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    pub fn with_keepalive_interval(mut self, keepalive_interval: Option<Duration>) -> Self {
+        self.keepalive_interval = keepalive_interval;
+        self
+    }
+
+    pub fn with_nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// Opens a TCP connection to `address` and applies the socket options.
+    ///
+    /// # Errors
+    ///
+    /// * `TychoClientError::ConnectTimeout` - if no connection is established within
+    ///   `connect_timeout`.
+    /// * `TychoClientError::Connect` - if connecting fails or the options can't be set.
+    pub async fn connect<A>(&self, address: A) -> Result<TcpStream, TychoClientError>
+    where
+        A: ToSocketAddrs + std::fmt::Display,
+    {
+        let started = Instant::now();
+        let stream = match tokio::time::timeout(self.connect_timeout, TcpStream::connect(&address))
+            .await
+        {
+            Ok(Ok(stream)) => stream,
+            Ok(Err(e)) => return Err(TychoClientError::Connect(address.to_string(), e.to_string())),
+            Err(_) => {
+                let waited = started.elapsed();
+                warn!(%address, ?waited, "ConnectTimeout");
+                return Err(TychoClientError::ConnectTimeout {
+                    address: address.to_string(),
+                    waited,
+                });
+            }
+        };
+        self.apply(&stream)
+            .map_err(|e| TychoClientError::Connect(address.to_string(), e.to_string()))?;
+        debug!(%address, elapsed = ?started.elapsed(), "Connected");
+        Ok(stream)
+    }
+
+    /// Runs the handshakes performed over a connection to `address`, e.g. the TLS and WebSocket
+    /// handshakes, bounded by `connect_timeout`.
+    ///
+    /// # Errors
+    ///
+    /// * `TychoClientError::ConnectTimeout` - if `handshake` doesn't complete in time.
+    /// * Any error returned by `handshake`.
+    pub async fn handshake<F, T>(&self, address: &str, handshake: F) -> Result<T, TychoClientError>
+    where
+        F: Future<Output = Result<T, TychoClientError>>,
+    {
+        let started = Instant::now();
+        tokio::time::timeout(self.connect_timeout, handshake)
+            .await
+            .unwrap_or_else(|_| {
+                let waited = started.elapsed();
+                warn!(%address, ?waited, "HandshakeTimeout");
+                Err(TychoClientError::ConnectTimeout { address: address.to_string(), waited })
+            })
+    }
+
+    /// Opens a WebSocket connection to `url`, e.g. `wss://tycho-beta.propellerheads.xyz/v1/ws`.
+    ///
+    /// The TCP connection is established with [`Self::connect`], then the TLS and WebSocket
+    /// handshakes run over it, bounded by [`Self::handshake`]. Returns the address connected to,
+    /// the sink commands are sent to and the stream of server messages. Text frames that aren't
+    /// a [`WebSocketMessage`] are skipped; the stream ends when the connection fails or closes.
+    ///
+    /// # Errors
+    ///
+    /// * `TychoClientError::FormatRequest` - if `url` isn't a valid WebSocket URL.
+    /// * `TychoClientError::ConnectTimeout` - if the connection or the handshakes aren't completed
+    ///   within `connect_timeout`.
+    /// * `TychoClientError::Connect` - if connecting or a handshake fails.
+    pub async fn connect_websocket(
+        &self,
+        url: &str,
+    ) -> Result<
+        (
+            String,
+            impl Sink<Command, Error = TychoClientError> + Unpin,
+            impl Stream<Item = WebSocketMessage> + Unpin,
+        ),
+        TychoClientError,
+    > {
+        let request = url
+            .into_client_request()
+            .map_err(|e| TychoClientError::FormatRequest(format!("Invalid url {url}: {e}")))?;
+        let uri = request.uri();
+        let host = uri
+            .host()
+            .ok_or_else(|| TychoClientError::FormatRequest(format!("No host in url {url}")))?;
+        let port = uri
+            .port_u16()
+            .unwrap_or(if uri.scheme_str() == Some("wss") { 443 } else { 80 });
+        let address = format!("{host}:{port}");
+
+        let stream = self.connect(address.as_str()).await?;
+        let (websocket, _) = self
+            .handshake(&address, async {
+                tokio_tungstenite::client_async_tls(request, stream)
+                    .await
+                    .map_err(|e| TychoClientError::Connect(address.clone(), e.to_string()))
+            })
+            .await?;
+
+        let (write, read) = websocket.split();
+        let commands = write
+            .sink_map_err(|e| TychoClientError::ConnectionClosed(e.to_string()))
+            .with(|command: Command| {
+                future::ready(
+                    serde_json::to_string(&command)
+                        .map(Message::Text)
+                        .map_err(|e| TychoClientError::FormatRequest(e.to_string())),
+                )
+            });
+        let messages = read
+            .take_while(|frame| {
+                if let Err(e) = frame {
+                    warn!(%e, "WebSocketReadFailed");
+                }
+                future::ready(frame.is_ok())
+            })
+            .filter_map(|frame| {
+                future::ready(match frame {
+                    Ok(Message::Text(text)) => serde_json::from_str(&text)
+                        .inspect_err(|e| warn!(%e, "UnknownWebSocketMessage"))
+                        .ok(),
+                    _ => None,
+                })
+            });
+        Ok((address, commands, messages))
+    }
+
+    fn apply(&self, stream: &TcpStream) -> std::io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        let socket = SockRef::from(stream);
+        match self.keepalive_interval {
+            Some(interval) => socket.set_tcp_keepalive(
+                &TcpKeepalive::new()
+                    .with_time(interval)
+                    .with_interval(interval),
+            ),
+            None => socket.set_keepalive(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::evm::tycho_models::{Chain, ExtractorIdentity, Response, SubscriptionOptions};
+
+    /// Not routable, so a SYN sent to it is never answered.
+    const NON_ROUTABLE: &str = "10.255.255.1:443";
+
+    #[tokio::test]
+    async fn test_connect_timeout() {
+        let timeout = Duration::from_millis(200);
+        let options = TransportOptions::default().with_connect_timeout(timeout);
+        let started = Instant::now();
+
+        let res = options.connect(NON_ROUTABLE).await;
+
+        let elapsed = started.elapsed();
+        let Err(TychoClientError::ConnectTimeout { address, .. }) = res else {
+            panic!("expected a connect timeout, got {res:?}");
+        };
+        assert_eq!(address, NON_ROUTABLE);
+        assert!(elapsed >= timeout, "{elapsed:?}");
+        assert!(elapsed < timeout + Duration::from_millis(500), "{elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn test_connect_applies_options() {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let address = listener
+            .local_addr()
+            .unwrap()
+            .to_string();
+
+        let stream = TransportOptions::default()
+            .connect(address.as_str())
+            .await
+            .unwrap();
+        assert!(stream.nodelay().unwrap());
+        assert!(SockRef::from(&stream)
+            .keepalive()
+            .unwrap());
+
+        let stream = TransportOptions::default()
+            .with_keepalive_interval(None)
+            .with_nodelay(false)
+            .connect(address.as_str())
+            .await
+            .unwrap();
+        assert!(!stream.nodelay().unwrap());
+        assert!(!SockRef::from(&stream)
+            .keepalive()
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_handshake_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let address = listener
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let options = TransportOptions::default().with_connect_timeout(Duration::from_millis(100));
+        let stream = options
+            .connect(address.as_str())
+            .await
+            .unwrap();
+
+        let completed = options
+            .handshake(&address, async { Ok(stream.peer_addr().unwrap()) })
+            .await;
+        // the server never answers the handshake
+        let stalled = options
+            .handshake(&address, futures::future::pending::<Result<(), _>>())
+            .await;
+
+        assert_eq!(completed.unwrap(), listener.local_addr().unwrap());
+        assert!(matches!(stalled, Err(TychoClientError::ConnectTimeout { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_connect_websocket_timeout() {
+        let timeout = Duration::from_millis(200);
+        let options = TransportOptions::default().with_connect_timeout(timeout);
+        let started = Instant::now();
+
+        let res = options
+            .connect_websocket(&format!("ws://{NON_ROUTABLE}"))
+            .await;
+
+        let elapsed = started.elapsed();
+        assert!(matches!(res, Err(TychoClientError::ConnectTimeout { .. })));
+        assert!(elapsed < timeout + Duration::from_millis(500), "{elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn test_connect_websocket() {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let address = listener
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let subscription_id = Uuid::new_v4();
+        let extractor = ExtractorIdentity::new(Chain::Ethereum, "vm:ambient");
+        let server = tokio::spawn({
+            let extractor = extractor.clone();
+            async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut websocket = tokio_tungstenite::accept_async(stream)
+                    .await
+                    .unwrap();
+                let Some(Ok(Message::Text(command))) = websocket.next().await else {
+                    panic!("expected a command");
+                };
+                let response =
+                    Response::NewSubscription { extractor_id: extractor, subscription_id };
+                websocket
+                    .send(Message::Text("not a message".to_string()))
+                    .await
+                    .unwrap();
+                websocket
+                    .send(Message::Text(serde_json::to_string(&response).unwrap()))
+                    .await
+                    .unwrap();
+                serde_json::from_str::<Command>(&command).unwrap()
+            }
+        });
+
+        let (connected, mut commands, mut messages) = TransportOptions::default()
+            .connect_websocket(&format!("ws://{address}"))
+            .await
+            .unwrap();
+        let subscribe = Command::subscribe(extractor.clone(), SubscriptionOptions::default());
+        commands
+            .send(Command::subscribe(extractor.clone(), SubscriptionOptions::default()))
+            .await
+            .unwrap();
+
+        assert_eq!(connected, address);
+        assert_eq!(server.await.unwrap(), subscribe);
+        let Some(WebSocketMessage::Response(response)) = messages.next().await else {
+            panic!("expected the subscription response");
+        };
+        assert_eq!(
+            response,
+            Response::NewSubscription { extractor_id: extractor, subscription_id }
+        );
+        assert!(messages.next().await.is_none());
+    }
+}
@@ -1,12 +1,20 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    io::Read,
+    marker::PhantomData,
+};
 
-use alloy_primitives::{Address, B256, U256};
+use alloy_primitives::{keccak256, Address, B256, U256};
 use chrono::{NaiveDateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{
+    de::{DeserializeSeed, Error as _, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
 pub use tycho_common::{dto::ChangeType, models::Chain};
 use uuid::Uuid;
 
-use super::engine_db::simulation_db::BlockHeader;
+use super::engine_db::{simulation_db::BlockHeader, tycho_db::TychoClientError};
 use crate::{
     evm::protocol::u256_num,
     serde_helpers::{hex_bytes, hex_bytes_option},
@@ -30,12 +38,45 @@ impl std::fmt::Display for ExtractorIdentity {
     }
 }
 
+/// Options the client can attach to a subscription request.
+///
+/// Both options are opt-in: a default `SubscriptionOptions` reproduces the plain
+/// subscription behaviour, so the server never sends a [`Snapshot`] unless it was asked for.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SubscriptionOptions {
+    /// Request a full state snapshot right after the subscription is confirmed.
+    #[serde(default)]
+    pub include_snapshot: bool,
+    /// Request the deltas of the last N blocks to be replayed before live updates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backfill_blocks: Option<u64>,
+}
+
+impl SubscriptionOptions {
+    pub fn new(include_snapshot: bool, backfill_blocks: Option<u64>) -> Self {
+        Self { include_snapshot, backfill_blocks }
+    }
+}
+
 /// A command sent from the client to the server
 #[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
 #[serde(tag = "method", rename_all = "lowercase")]
 pub enum Command {
-    Subscribe { extractor_id: ExtractorIdentity },
-    Unsubscribe { subscription_id: Uuid },
+    Subscribe {
+        extractor_id: ExtractorIdentity,
+        #[serde(default)]
+        options: SubscriptionOptions,
+    },
+    Unsubscribe {
+        subscription_id: Uuid,
+    },
+}
+
+impl Command {
+    /// Creates a subscribe command with the given options.
+    pub fn subscribe(extractor_id: ExtractorIdentity, options: SubscriptionOptions) -> Self {
+        Command::Subscribe { extractor_id, options }
+    }
 }
 
 /// A response sent from the server to the client
@@ -51,7 +92,91 @@ pub enum Response {
 #[serde(untagged)]
 pub enum WebSocketMessage {
     BlockAccountChanges(BlockAccountChanges),
+    Snapshot(Snapshot),
     Response(Response),
+    Heartbeat(ExtractorStatus),
+}
+
+/// Full state of all accounts tracked by an extractor at a given block.
+///
+/// Only sent by the server if the subscription was created with
+/// [`SubscriptionOptions::include_snapshot`] set. It always precedes any
+/// [`BlockAccountChanges`] of the same subscription.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Snapshot {
+    pub extractor: String,
+    pub chain: Chain,
+    pub block: Block,
+    pub accounts: Vec<ResponseAccount>,
+}
+
+impl Snapshot {
+    pub fn new(
+        extractor: String,
+        chain: Chain,
+        block: Block,
+        accounts: Vec<ResponseAccount>,
+    ) -> Self {
+        Self { extractor, chain, block, accounts }
+    }
+}
+
+/// Sync status of an extractor. Statuses unknown to this client are kept as reported.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(from = "String", into = "String")]
+pub enum SyncStatus {
+    Synced,
+    Lagging,
+    Other(String),
+}
+
+impl From<String> for SyncStatus {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "synced" => SyncStatus::Synced,
+            "lagging" => SyncStatus::Lagging,
+            _ => SyncStatus::Other(value),
+        }
+    }
+}
+
+impl From<SyncStatus> for String {
+    fn from(value: SyncStatus) -> Self {
+        match value {
+            SyncStatus::Synced => "synced".to_string(),
+            SyncStatus::Lagging => "lagging".to_string(),
+            SyncStatus::Other(status) => status,
+        }
+    }
+}
+
+/// Status of an extractor, sent periodically and independently of any block changes.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ExtractorStatus {
+    pub extractor: String,
+    pub chain: Chain,
+    pub status: SyncStatus,
+    /// The latest block indexed by the extractor.
+    pub latest_block: u64,
+}
+
+/// An event emitted to consumers of a subscription.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubscriptionEvent {
+    /// The initial state snapshot. Only emitted if the subscription requested it, at most once
+    /// per connection and before any `Changes` event of it.
+    Snapshot(Snapshot),
+    /// State changes of a single block.
+    Changes(BlockAccountChanges),
+}
+
+impl SubscriptionEvent {
+    pub fn block(&self) -> &Block {
+        match self {
+            SubscriptionEvent::Snapshot(snapshot) => &snapshot.block,
+            SubscriptionEvent::Changes(changes) => &changes.block,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Copy, Clone, Deserialize, Serialize, Default)]
@@ -96,6 +221,13 @@ pub struct BlockAccountChanges {
     pub block: Block,
     pub account_updates: HashMap<Address, AccountUpdate>,
     pub new_pools: HashMap<Address, SwapPool>,
+    /// Position of the message in the subscription, if the server numbers its messages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence_number: Option<u64>,
+    /// Accounts whose merged update is a creation, but which existed before the first of the
+    /// merged blocks, i.e. were deleted and created again.
+    #[serde(skip)]
+    recreated: HashSet<Address>,
 }
 
 impl BlockAccountChanges {
@@ -106,7 +238,183 @@ impl BlockAccountChanges {
         account_updates: HashMap<Address, AccountUpdate>,
         new_pools: HashMap<Address, SwapPool>,
     ) -> Self {
-        Self { extractor, chain, block, account_updates, new_pools }
+        Self {
+            extractor,
+            chain,
+            block,
+            account_updates,
+            new_pools,
+            sequence_number: None,
+            recreated: HashSet::new(),
+        }
+    }
+
+    /// The extractor that emitted these changes.
+    pub fn extractor(&self) -> &str {
+        &self.extractor
+    }
+
+    /// Keeps only the updates and new pools of `accounts`.
+    pub fn retain_accounts(&mut self, accounts: &HashSet<Address>) {
+        self.account_updates
+            .retain(|address, _| accounts.contains(address));
+        self.new_pools
+            .retain(|address, _| accounts.contains(address));
+    }
+
+    /// Deserializes changes from `json`, keeping only the updates and new pools of `accounts`.
+    ///
+    /// Equivalent to deserializing all changes and calling
+    /// [`retain_accounts`](Self::retain_accounts), but the values of other accounts are skipped
+    /// without being deserialized, so they cost little more than parsing their address.
+    pub fn from_json_filtered<S: AccountSet + ?Sized>(
+        json: &str,
+        accounts: &S,
+    ) -> Result<Self, serde_json::Error> {
+        let mut deserializer = serde_json::Deserializer::from_str(json);
+        let changes = deserializer.deserialize_map(FilteredChangesVisitor(accounts))?;
+        deserializer.end()?;
+        Ok(changes)
+    }
+
+    /// Merges the changes of the following block into these changes.
+    ///
+    /// Afterwards `self` holds the block header of `newer` and the net update of every account,
+    /// see [`AccountUpdate::merge`]. Accounts whose updates cancel out are dropped, together with
+    /// their pools: a pool created and removed between the merged blocks leaves no trace. An
+    /// account that existed before them is deleted, even if it was created again in between.
+    pub fn merge(&mut self, newer: BlockAccountChanges) {
+        self.extractor = newer.extractor;
+        self.chain = newer.chain;
+        self.block = newer.block;
+        self.sequence_number = newer.sequence_number;
+        for (address, update) in newer.account_updates {
+            let merged = match self.account_updates.remove(&address) {
+                Some(older) => older.merge_tracking_recreations(update, &mut self.recreated),
+                None => Some(update),
+            };
+            match merged {
+                Some(merged) => {
+                    if merged.change == ChangeType::Deletion {
+                        self.new_pools.remove(&address);
+                    }
+                    self.account_updates
+                        .insert(address, merged);
+                }
+                None => {
+                    self.new_pools.remove(&address);
+                }
+            }
+        }
+        for (address, pool) in newer.new_pools {
+            let removed = self
+                .account_updates
+                .get(&address)
+                .is_some_and(|update| update.change == ChangeType::Deletion);
+            if !removed {
+                self.new_pools.insert(address, pool);
+            }
+        }
+    }
+}
+
+/// A set of accounts to filter decoded changes by.
+pub trait AccountSet {
+    fn contains_account(&self, address: &Address) -> bool;
+}
+
+impl AccountSet for HashSet<Address> {
+    fn contains_account(&self, address: &Address) -> bool {
+        self.contains(address)
+    }
+}
+
+/// Visits a [`BlockAccountChanges`] object, skipping the accounts outside of the filter.
+struct FilteredChangesVisitor<'a, S: ?Sized>(&'a S);
+
+impl<'de, S: AccountSet + ?Sized> Visitor<'de> for FilteredChangesVisitor<'_, S> {
+    type Value = BlockAccountChanges;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("block account changes")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut extractor = None;
+        let mut chain = None;
+        let mut block = None;
+        let mut account_updates = None;
+        let mut new_pools = None;
+        let mut sequence_number = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "extractor" => extractor = Some(map.next_value()?),
+                "chain" => chain = Some(map.next_value()?),
+                "block" => block = Some(map.next_value()?),
+                "account_updates" => {
+                    account_updates = Some(map.next_value_seed(FilteredAccounts::new(self.0))?)
+                }
+                "new_pools" => {
+                    new_pools = Some(map.next_value_seed(FilteredAccounts::new(self.0))?)
+                }
+                "sequence_number" => sequence_number = map.next_value()?,
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(BlockAccountChanges {
+            extractor: extractor.ok_or_else(|| A::Error::missing_field("extractor"))?,
+            chain: chain.ok_or_else(|| A::Error::missing_field("chain"))?,
+            block: block.ok_or_else(|| A::Error::missing_field("block"))?,
+            account_updates: account_updates
+                .ok_or_else(|| A::Error::missing_field("account_updates"))?,
+            new_pools: new_pools.ok_or_else(|| A::Error::missing_field("new_pools"))?,
+            sequence_number,
+            recreated: HashSet::new(),
+        })
+    }
+}
+
+/// Visits an object keyed by address, deserializing only the values of filtered accounts.
+struct FilteredAccounts<'a, S: ?Sized, V> {
+    accounts: &'a S,
+    value: PhantomData<V>,
+}
+
+impl<'a, S: ?Sized, V> FilteredAccounts<'a, S, V> {
+    fn new(accounts: &'a S) -> Self {
+        Self { accounts, value: PhantomData }
+    }
+}
+
+impl<'de, S: AccountSet + ?Sized, V: Deserialize<'de>> DeserializeSeed<'de>
+    for FilteredAccounts<'_, S, V>
+{
+    type Value = HashMap<Address, V>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, S: AccountSet + ?Sized, V: Deserialize<'de>> Visitor<'de> for FilteredAccounts<'_, S, V> {
+    type Value = HashMap<Address, V>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a map keyed by address")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut values = HashMap::new();
+        while let Some(address) = map.next_key::<Address>()? {
+            if self.accounts.contains_account(&address) {
+                values.insert(address, map.next_value()?);
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(values)
     }
 }
 
@@ -119,6 +427,10 @@ pub struct AccountUpdate {
     #[serde(with = "hex_bytes_option")]
     pub code: Option<Vec<u8>>,
     pub change: ChangeType,
+    /// Slots reset to zero and no longer tracked. Deletions are applied before the `slots`
+    /// writes of the same update.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deleted_slots: Vec<U256>,
 }
 
 impl AccountUpdate {
@@ -131,7 +443,111 @@ impl AccountUpdate {
         code: Option<Vec<u8>>,
         change: ChangeType,
     ) -> Self {
-        Self { address, chain, slots, balance, code, change }
+        Self { address, chain, slots, balance, code, change, deleted_slots: Vec::new() }
+    }
+
+    pub fn with_deleted_slots(mut self, deleted_slots: Vec<U256>) -> Self {
+        self.deleted_slots = deleted_slots;
+        self
+    }
+
+    /// Identifies this update as applied at `block`, to recognise it when it is delivered again.
+    ///
+    /// The fingerprint is
+    /// `keccak256(address ++ block ++ slots ++ balance ++ code ++ change ++ deleted_slots)`, with
+    /// slots as `slot ++ value` pairs sorted by slot and deleted slots sorted, so it doesn't
+    /// depend on their order.
+    pub fn fingerprint(&self, block: u64) -> [u8; 32] {
+        let mut slots: Vec<_> = self.slots.iter().collect();
+        slots.sort_unstable();
+        let mut preimage = Vec::with_capacity(20 + 8 + slots.len() * 64 + 32);
+        preimage.extend_from_slice(self.address.as_slice());
+        preimage.extend_from_slice(&block.to_be_bytes());
+        for (slot, value) in slots {
+            preimage.extend_from_slice(&slot.to_be_bytes::<32>());
+            preimage.extend_from_slice(&value.to_be_bytes::<32>());
+        }
+        if let Some(balance) = self.balance {
+            preimage.extend_from_slice(&balance.to_be_bytes::<32>());
+        }
+        if let Some(code) = &self.code {
+            preimage.extend_from_slice(keccak256(code).as_slice());
+        }
+        preimage.push(self.change as u8);
+        let mut deleted_slots = self.deleted_slots.clone();
+        deleted_slots.sort_unstable();
+        for slot in deleted_slots {
+            preimage.extend_from_slice(&slot.to_be_bytes::<32>());
+        }
+        keccak256(preimage).0
+    }
+
+    /// Combines this update with a `newer` update of the same account into a single update with
+    /// the same effect on the account as applying both in order.
+    ///
+    /// Slots, balance and code of `newer` take precedence, and a slot deleted by `newer` replaces
+    /// an earlier write to it, while a slot written by `newer` is no longer deleted. A creation
+    /// followed by updates stays a creation, and anything followed by a deletion becomes a
+    /// deletion. Updates to a deleted account are dropped, as they can't be applied.
+    /// `Unspecified` changes are ignored.
+    ///
+    /// Returns `None` if the account is created and deleted again, since the account did not
+    /// exist before its creation and the two changes cancel out. This assumes `self` holds the
+    /// creation of the account; a creation following a deletion of an existing account doesn't
+    /// cancel out, see [`BlockAccountChanges::merge`].
+    pub fn merge(self, newer: AccountUpdate) -> Option<AccountUpdate> {
+        match (self.change, newer.change) {
+            (_, ChangeType::Unspecified) => Some(self),
+            (ChangeType::Unspecified, _) => Some(newer),
+            (ChangeType::Creation, ChangeType::Deletion) => None,
+            (_, ChangeType::Deletion) | (_, ChangeType::Creation) => Some(newer),
+            (ChangeType::Deletion, ChangeType::Update) => Some(self),
+            (ChangeType::Creation, ChangeType::Update) |
+            (ChangeType::Update, ChangeType::Update) => {
+                let mut merged = self;
+                for slot in &newer.deleted_slots {
+                    merged.slots.remove(slot);
+                }
+                merged.deleted_slots.retain(|slot| {
+                    !newer.slots.contains_key(slot) && !newer.deleted_slots.contains(slot)
+                });
+                merged
+                    .deleted_slots
+                    .extend(newer.deleted_slots);
+                merged.slots.extend(newer.slots);
+                merged.balance = newer.balance.or(merged.balance);
+                merged.code = newer.code.or(merged.code);
+                Some(merged)
+            }
+        }
+    }
+
+    /// Like [`Self::merge`], for updates that are themselves merged from several blocks.
+    ///
+    /// `recreated` holds the accounts whose merged update is a creation although they existed
+    /// before the merged blocks, and is kept up to date. Deleting such an account again merges
+    /// into a deletion instead of cancelling out.
+    pub(crate) fn merge_tracking_recreations(
+        self,
+        newer: AccountUpdate,
+        recreated: &mut HashSet<Address>,
+    ) -> Option<AccountUpdate> {
+        let address = self.address;
+        // the account existed before the merged blocks unless they created it
+        let existed = recreated.remove(&address) || self.change != ChangeType::Creation;
+        let merged = if existed && newer.change == ChangeType::Deletion {
+            Some(newer)
+        } else {
+            self.merge(newer)
+        };
+        if existed &&
+            merged
+                .as_ref()
+                .is_some_and(|merged| merged.change == ChangeType::Creation)
+        {
+            recreated.insert(address);
+        }
+        merged
     }
 }
 
@@ -146,6 +562,22 @@ impl From<tycho_common::dto::AccountUpdate> for AccountUpdate {
                 .map(|balance| u256_num::bytes_to_u256(balance.into())),
             code: value.code.map(|code| code.to_vec()),
             change: value.change,
+            deleted_slots: Vec::new(),
+        }
+    }
+}
+
+/// The full state of an account, as the creation of the account.
+impl From<ResponseAccount> for AccountUpdate {
+    fn from(value: ResponseAccount) -> Self {
+        Self {
+            address: value.address,
+            chain: value.chain,
+            slots: value.slots,
+            balance: Some(value.native_balance),
+            code: Some(value.code),
+            change: ChangeType::Creation,
+            deleted_slots: Vec::new(),
         }
     }
 }
@@ -189,6 +621,76 @@ impl StateRequestResponse {
     pub fn new(accounts: Vec<ResponseAccount>) -> Self {
         Self { accounts }
     }
+
+    /// Deserializes a state response from `reader` one account at a time.
+    ///
+    /// Each account is handed to `on_account` as soon as it is parsed and never collected, so
+    /// memory usage is bounded by the largest single account rather than the whole response.
+    ///
+    /// Returns the number of accounts read.
+    pub fn stream_accounts<R, F>(reader: R, on_account: F) -> Result<usize, TychoClientError>
+    where
+        R: Read,
+        F: FnMut(ResponseAccount),
+    {
+        let mut deserializer = serde_json::Deserializer::from_reader(reader);
+        let count = deserializer
+            .deserialize_map(AccountsVisitor(on_account))
+            .and_then(|count| deserializer.end().map(|_| count))
+            .map_err(|e| TychoClientError::ParseResponse("state response".to_string(), Some(e)))?;
+        Ok(count)
+    }
+}
+
+/// Visits the top level response object, streaming the `accounts` field.
+struct AccountsVisitor<F>(F);
+
+impl<'de, F: FnMut(ResponseAccount)> Visitor<'de> for AccountsVisitor<F> {
+    type Value = usize;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a state request response")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut count = 0;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "accounts" {
+                count += map.next_value_seed(AccountsSeq(&mut self.0))?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(count)
+    }
+}
+
+/// Visits the `accounts` array, passing each element on without collecting it.
+struct AccountsSeq<'a, F>(&'a mut F);
+
+impl<'de, F: FnMut(ResponseAccount)> DeserializeSeed<'de> for AccountsSeq<'_, F> {
+    type Value = usize;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, F: FnMut(ResponseAccount)> Visitor<'de> for AccountsSeq<'_, F> {
+    type Value = usize;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a list of accounts")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut count = 0;
+        while let Some(account) = seq.next_element::<ResponseAccount>()? {
+            (self.0)(account);
+            count += 1;
+        }
+        Ok(count)
+    }
 }
 
 #[derive(PartialEq, Clone, Serialize, Deserialize, Default)]
@@ -310,7 +812,7 @@ impl Display for ContractId {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Version {
     timestamp: NaiveDateTime,
     block: Option<Block>,
@@ -353,3 +855,187 @@ impl StateRequestParameters {
         parts.join("&")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscribe_command_without_options() {
+        let extractor_id = ExtractorIdentity::new(Chain::Ethereum, "vm:ambient");
+        let json = serde_json::json!({
+            "method": "subscribe",
+            "extractor_id": serde_json::to_value(&extractor_id).unwrap(),
+        });
+
+        let command: Command = serde_json::from_value(json).expect("Failed to deserialize");
+
+        assert_eq!(command, Command::subscribe(extractor_id, SubscriptionOptions::default()));
+    }
+
+    #[test]
+    fn test_subscribe_command_options_roundtrip() {
+        let command = Command::subscribe(
+            ExtractorIdentity::new(Chain::Ethereum, "vm:ambient"),
+            SubscriptionOptions::new(true, Some(10)),
+        );
+
+        let json = serde_json::to_value(&command).expect("Failed to serialize");
+
+        assert_eq!(json["options"]["include_snapshot"], true);
+        assert_eq!(json["options"]["backfill_blocks"], 10);
+        let decoded: Command = serde_json::from_value(json).expect("Failed to deserialize");
+        assert_eq!(decoded, command);
+    }
+
+    #[test]
+    fn test_websocket_message_distinguishes_snapshot() {
+        let snapshot = Snapshot::new(
+            "vm:ambient".to_string(),
+            Chain::Ethereum,
+            Block::default(),
+            vec![ResponseAccount::default()],
+        );
+        let changes = BlockAccountChanges::default();
+
+        let snapshot_json = serde_json::to_string(&snapshot).unwrap();
+        let changes_json = serde_json::to_string(&changes).unwrap();
+
+        assert!(matches!(
+            serde_json::from_str::<WebSocketMessage>(&snapshot_json).unwrap(),
+            WebSocketMessage::Snapshot(s) if s == snapshot
+        ));
+        assert!(matches!(
+            serde_json::from_str::<WebSocketMessage>(&changes_json).unwrap(),
+            WebSocketMessage::BlockAccountChanges(c) if c == changes
+        ));
+    }
+
+    #[test]
+    fn test_websocket_message_heartbeat() {
+        let json = serde_json::json!({
+            "extractor": "vm:ambient",
+            "chain": serde_json::to_value(Chain::Ethereum).unwrap(),
+            "status": "catching_up",
+            "latest_block": 123,
+            "indexing_lag_ms": 1500
+        });
+
+        let msg: WebSocketMessage = serde_json::from_value(json).unwrap();
+
+        let WebSocketMessage::Heartbeat(status) = msg else {
+            panic!("Expected a heartbeat, got {msg:?}");
+        };
+        assert_eq!(
+            status,
+            ExtractorStatus {
+                extractor: "vm:ambient".to_string(),
+                chain: Chain::Ethereum,
+                status: SyncStatus::Other("catching_up".to_string()),
+                latest_block: 123,
+            }
+        );
+    }
+
+    fn account_changes(accounts: u64) -> BlockAccountChanges {
+        let account_updates = (0..accounts)
+            .map(|i| {
+                let address = Address::left_padding_from(&i.to_be_bytes());
+                let update = AccountUpdate::new(
+                    address,
+                    Chain::Ethereum,
+                    HashMap::from([(U256::from(i), U256::from(i + 1))]),
+                    Some(U256::from(i)),
+                    (i % 2 == 0).then(|| vec![0xfe; 32]),
+                    ChangeType::Update,
+                );
+                (address, update)
+            })
+            .collect();
+        let new_pools = (0..accounts)
+            .step_by(10)
+            .map(|i| (Address::left_padding_from(&i.to_be_bytes()), SwapPool {}))
+            .collect();
+        BlockAccountChanges::new(
+            "vm:ambient".to_string(),
+            Chain::Ethereum,
+            Block::default(),
+            account_updates,
+            new_pools,
+        )
+    }
+
+    #[test]
+    fn test_filtered_decode_matches_full_decode() {
+        let json = serde_json::to_string(&account_changes(500)).unwrap();
+        let accounts: HashSet<Address> = (0..1000u64)
+            .step_by(7)
+            .map(|i| Address::left_padding_from(&i.to_be_bytes()))
+            .collect();
+
+        let filtered = BlockAccountChanges::from_json_filtered(&json, &accounts).unwrap();
+
+        let mut expected: BlockAccountChanges = serde_json::from_str(&json).unwrap();
+        expected.retain_accounts(&accounts);
+        assert_eq!(filtered.account_updates.len(), 72);
+        assert_eq!(filtered, expected);
+    }
+
+    #[test]
+    fn test_filtered_decode_keeps_sequence_number() {
+        let mut changes = account_changes(3);
+        changes.sequence_number = Some(42);
+        let json = serde_json::to_string(&changes).unwrap();
+
+        let filtered = BlockAccountChanges::from_json_filtered(&json, &HashSet::new()).unwrap();
+        let unnumbered: BlockAccountChanges = serde_json::to_string(&account_changes(3))
+            .and_then(|json| serde_json::from_str(&json))
+            .unwrap();
+
+        assert_eq!(filtered.sequence_number, Some(42));
+        assert_eq!(unnumbered.sequence_number, None);
+    }
+
+    #[test]
+    fn test_filtered_decode_missing_field() {
+        let json = r#"{"extractor": "vm:ambient", "account_updates": {}}"#;
+
+        let res = BlockAccountChanges::from_json_filtered(json, &HashSet::new());
+
+        assert!(res
+            .unwrap_err()
+            .to_string()
+            .contains("missing field `chain`"));
+    }
+
+    #[test]
+    fn test_stream_accounts() {
+        let accounts: Vec<_> = (0..1000u64)
+            .map(|i| ResponseAccount {
+                address: Address::left_padding_from(&i.to_be_bytes()),
+                slots: HashMap::from([(U256::from(i), U256::from(i))]),
+                code: vec![0xfe; 1024],
+                ..Default::default()
+            })
+            .collect();
+        let body = serde_json::to_vec(&StateRequestResponse::new(accounts.clone())).unwrap();
+
+        let mut received = Vec::new();
+        let count = StateRequestResponse::stream_accounts(body.as_slice(), |account| {
+            received.push(account)
+        })
+        .expect("Failed to stream accounts");
+
+        assert_eq!(count, 1000);
+        assert_eq!(received, accounts);
+    }
+
+    #[test]
+    fn test_stream_accounts_invalid_body() {
+        let body = br#"{"accounts": [{"chain": 1}]}"#;
+
+        let res = StateRequestResponse::stream_accounts(body.as_slice(), |_| {});
+
+        assert!(matches!(res, Err(TychoClientError::ParseResponse(_, Some(_)))));
+    }
+}
@@ -0,0 +1,284 @@
+//! Warm-up ordering of the initial snapshot.
+//!
+//! Decoding a large snapshot takes a while, and in arbitrary order the pools that matter most
+//! may become quotable last. A [`WarmupPriority`] tells the decoder which components to process
+//! first: the first snapshot containing any of them is decoded in two batches, the prioritized
+//! components and then the rest. Once the first batch is committed the decoder emits
+//! [`WarmupEvent::PartialReady`], before the long tail is done.
+//!
+//! Priorities either come from a user provided list or from a [`QuoteFrequencyCounter`] that the
+//! quoting layer feeds with every quoted component and that is persisted across runs.
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{debug, info};
+
+/// Counts below this are dropped when the counter is loaded.
+const MIN_RETAINED_COUNT: f64 = 0.01;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum WarmupError {
+    #[error("Failed to access quote frequencies: {0}")]
+    Io(String),
+    #[error("Failed to parse quote frequencies: {0}")]
+    Parse(String),
+}
+
+/// How often each component was quoted, decayed per run so stale favourites fade out.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuoteFrequencyCounter {
+    counts: HashMap<String, f64>,
+}
+
+impl QuoteFrequencyCounter {
+    pub fn new() -> Self {
+        Self { counts: HashMap::new() }
+    }
+
+    /// Loads the counts saved by a previous run and multiplies them by `decay`, which should lie
+    /// in `(0, 1]`. A missing file yields an empty counter.
+    pub fn load(path: impl AsRef<Path>, decay: f64) -> Result<Self, WarmupError> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::new()),
+            Err(err) => return Err(WarmupError::Io(err.to_string())),
+        };
+        let mut counter: Self =
+            serde_json::from_str(&content).map_err(|err| WarmupError::Parse(err.to_string()))?;
+        counter.decay(decay);
+        Ok(counter)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), WarmupError> {
+        let content = serde_json::to_string(self).expect("Counts are always serializable");
+        fs::write(path, content).map_err(|err| WarmupError::Io(err.to_string()))
+    }
+
+    /// Records a quote of the given component.
+    pub fn record(&mut self, component_id: &str) {
+        *self
+            .counts
+            .entry(component_id.to_string())
+            .or_default() += 1.0;
+    }
+
+    pub fn count(&self, component_id: &str) -> f64 {
+        self.counts
+            .get(component_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn decay(&mut self, factor: f64) {
+        for count in self.counts.values_mut() {
+            *count *= factor;
+        }
+        self.counts
+            .retain(|_, count| *count >= MIN_RETAINED_COUNT);
+    }
+
+    /// The `n` most quoted components, most quoted first. Ties are broken by id.
+    pub fn top(&self, n: usize) -> Vec<String> {
+        let mut ids: Vec<_> = self.counts.iter().collect();
+        ids.sort_by(|(id_a, a), (id_b, b)| b.total_cmp(a).then(id_a.cmp(id_b)));
+        ids.into_iter()
+            .take(n)
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+}
+
+impl Default for QuoteFrequencyCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Components to decode first, most important first.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WarmupPriority {
+    List(Vec<String>),
+    /// The `top` most quoted components of previous runs.
+    Frequency {
+        counter: QuoteFrequencyCounter,
+        top: usize,
+    },
+}
+
+impl WarmupPriority {
+    /// Rank of every prioritized component, lower is more important.
+    pub fn ranks(&self) -> HashMap<String, usize> {
+        let ids = match self {
+            WarmupPriority::List(ids) => ids.clone(),
+            WarmupPriority::Frequency { counter, top } => counter.top(*top),
+        };
+        let mut ranks = HashMap::new();
+        for (rank, id) in ids.into_iter().enumerate() {
+            ranks.entry(id).or_insert(rank);
+        }
+        ranks
+    }
+
+    /// Sorts `items` so prioritized components come first, in rank order. The order of all
+    /// other items is kept.
+    pub fn sort<T>(&self, items: &mut [T], id: impl Fn(&T) -> &str) {
+        let ranks = self.ranks();
+        items.sort_by_key(|item| {
+            ranks
+                .get(id(item))
+                .copied()
+                .unwrap_or(usize::MAX)
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarmupEvent {
+    /// All prioritized components of the snapshot are decoded. `pools_ready` is the number of
+    /// components decoded so far.
+    PartialReady { pools_ready: usize },
+}
+
+/// Picks the snapshot the decoder splits into its prioritized components and the rest, and
+/// announces when the prioritized ones are ready.
+#[derive(Debug)]
+pub(crate) struct WarmupTracker {
+    priority: WarmupPriority,
+    events: UnboundedSender<WarmupEvent>,
+    /// Whether a snapshot containing prioritized components was seen.
+    started: AtomicBool,
+}
+
+impl WarmupTracker {
+    pub(crate) fn new(priority: WarmupPriority, events: UnboundedSender<WarmupEvent>) -> Self {
+        Self { priority, events, started: AtomicBool::new(false) }
+    }
+
+    /// The prioritized components among `snapshot_ids`. Only the first snapshot containing any of
+    /// them is split, `None` is returned for all others.
+    pub(crate) fn start<'a>(
+        &self,
+        snapshot_ids: impl IntoIterator<Item = &'a String>,
+    ) -> Option<HashSet<String>> {
+        if self.started.load(Ordering::Relaxed) {
+            return None;
+        }
+        let ranks = self.priority.ranks();
+        let ids: HashSet<_> = snapshot_ids
+            .into_iter()
+            .filter(|id| ranks.contains_key(*id))
+            .cloned()
+            .collect();
+        if ids.is_empty() ||
+            self.started
+                .swap(true, Ordering::Relaxed)
+        {
+            return None;
+        }
+        debug!(n = ids.len(), "Decoding prioritized components first");
+        Some(ids)
+    }
+
+    /// Emits [`WarmupEvent::PartialReady`]. Called once the prioritized components are
+    /// committed, with the number of pools decoded so far.
+    pub(crate) fn ready(&self, pools_ready: usize) {
+        info!(pools_ready, "Prioritized components ready");
+        // Nobody listening is fine, the event is informational.
+        let _ = self
+            .events
+            .send(WarmupEvent::PartialReady { pools_ready });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn counter(counts: &[(&str, usize)]) -> QuoteFrequencyCounter {
+        let mut counter = QuoteFrequencyCounter::new();
+        for (id, n) in counts {
+            for _ in 0..*n {
+                counter.record(id);
+            }
+        }
+        counter
+    }
+
+    #[test]
+    fn test_sort_follows_list() {
+        let priority = WarmupPriority::List(vec!["c".to_string(), "a".to_string()]);
+        let mut ids = vec!["a", "b", "c", "d"];
+
+        priority.sort(&mut ids, |id| id);
+
+        assert_eq!(ids, vec!["c", "a", "b", "d"]);
+    }
+
+    #[test]
+    fn test_sort_follows_frequency() {
+        let priority =
+            WarmupPriority::Frequency { counter: counter(&[("a", 1), ("b", 5), ("c", 3)]), top: 2 };
+        let mut ids = vec!["a", "b", "c", "d"];
+
+        priority.sort(&mut ids, |id| id);
+
+        assert_eq!(ids, vec!["b", "c", "a", "d"]);
+    }
+
+    #[test]
+    fn test_counter_round_trip_applies_decay() {
+        let dir = tempdir().unwrap();
+        let path = dir
+            .path()
+            .join("quote_frequencies.json");
+        counter(&[("a", 4), ("b", 1)])
+            .save(&path)
+            .unwrap();
+
+        let loaded = QuoteFrequencyCounter::load(&path, 0.5).unwrap();
+
+        assert_eq!(loaded.count("a"), 2.0);
+        assert_eq!(loaded.count("b"), 0.5);
+
+        loaded.save(&path).unwrap();
+        let reloaded = QuoteFrequencyCounter::load(&path, 0.01).unwrap();
+        assert_eq!(reloaded.count("a"), 0.02);
+        // Decayed below the retention threshold.
+        assert_eq!(reloaded.top(10), vec!["a".to_string()]);
+    }
+
+    #[test]
+    fn test_load_missing_file() {
+        let dir = tempdir().unwrap();
+
+        let loaded = QuoteFrequencyCounter::load(dir.path().join("missing.json"), 0.5).unwrap();
+
+        assert_eq!(loaded, QuoteFrequencyCounter::new());
+    }
+
+    #[test]
+    fn test_only_first_prioritized_snapshot_is_split() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let tracker = WarmupTracker::new(
+            WarmupPriority::List(vec!["a".to_string(), "b".to_string(), "x".to_string()]),
+            tx,
+        );
+        let ids = ["a", "b", "c"].map(String::from);
+
+        assert_eq!(tracker.start(&["c".to_string()]), None);
+        assert_eq!(tracker.start(&ids), Some(HashSet::from(["a", "b"].map(String::from))));
+        assert_eq!(tracker.start(&ids), None);
+        assert!(rx.try_recv().is_err());
+        tracker.ready(2);
+        assert_eq!(rx.try_recv().unwrap(), WarmupEvent::PartialReady { pools_ready: 2 });
+    }
+}
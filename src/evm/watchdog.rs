@@ -0,0 +1,452 @@
+//! Detection of a wedged pipeline.
+//!
+//! A websocket connection can stay alive while no blocks arrive, e.g. when the extractor on the
+//! server stalls. [`PipelineWatchdog`] tracks the time since the last applied block and escalates
+//! through increasingly drastic recovery actions the longer the stall lasts. Every action fires
+//! once per threshold crossing and at most once per [`WatchdogConfig::min_action_interval`], so a
+//! server that is genuinely down doesn't cause a reconnect storm.
+//!
+//! A watchdog created [`with_liveness`](PipelineWatchdog::with_liveness) also reports the
+//! extractors' own view: its alert level is at least `Warning` while any extractor says it isn't
+//! synced, even if blocks still arrive. One created [`with_clock`](PipelineWatchdog::with_clock)
+//! reports the skew of the host's clock with its stats.
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tracing::{debug, info, warn};
+use tycho_common::models::Chain;
+
+use crate::evm::{
+    block_time::ChainBlockTime, clock::BlockAnchoredClock, liveness::LivenessTracker,
+};
+
+/// Warnings are never raised before this long without a block, however fast the chain.
+pub const MIN_WARNING_THRESHOLD: Duration = Duration::from_secs(30);
+
+/// A recovery action, in escalation order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum WatchdogAction {
+    LogWarning,
+    EmitHealthEvent,
+    ForceReconnect,
+    Resync,
+}
+
+impl WatchdogAction {
+    /// The alert level reached once this action is due.
+    pub fn alert_level(&self) -> AlertLevel {
+        match self {
+            WatchdogAction::LogWarning => AlertLevel::Warning,
+            WatchdogAction::EmitHealthEvent => AlertLevel::Unhealthy,
+            WatchdogAction::ForceReconnect => AlertLevel::Reconnecting,
+            WatchdogAction::Resync => AlertLevel::Resyncing,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertLevel {
+    Healthy,
+    Warning,
+    Unhealthy,
+    Reconnecting,
+    Resyncing,
+}
+
+/// Executes the recovery actions. Implementations must not block.
+pub trait RecoveryHandler: Send + Sync {
+    fn handle(&self, action: WatchdogAction, stalled_for: Duration);
+}
+
+/// An action and the time without blocks after which it is due.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EscalationStep {
+    pub after: Duration,
+    pub action: WatchdogAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchdogConfig {
+    /// Escalation steps, ordered by threshold.
+    pub escalation: Vec<EscalationStep>,
+    /// Minimum time between two executions of the same action, across stalls.
+    pub min_action_interval: Duration,
+}
+
+impl WatchdogConfig {
+    /// Creates a config from escalation steps in any order.
+    pub fn new(mut escalation: Vec<EscalationStep>, min_action_interval: Duration) -> Self {
+        escalation.sort_by_key(|step| step.after);
+        Self { escalation, min_action_interval }
+    }
+
+    /// Escalates after 5, 10, 25 and 50 expected blocks, but never warns before
+    /// [`MIN_WARNING_THRESHOLD`]. On Ethereum this warns after a minute and resyncs after ten.
+    pub fn from_block_time(block_time: Duration) -> Self {
+        let base = (block_time * 5).max(MIN_WARNING_THRESHOLD);
+        Self::new(
+            vec![
+                EscalationStep { after: base, action: WatchdogAction::LogWarning },
+                EscalationStep { after: base * 2, action: WatchdogAction::EmitHealthEvent },
+                EscalationStep { after: base * 5, action: WatchdogAction::ForceReconnect },
+                EscalationStep { after: base * 10, action: WatchdogAction::Resync },
+            ],
+            base * 5,
+        )
+    }
+
+    /// The default config for a chain, based on its block time.
+    pub fn for_chain(chain: Chain) -> Self {
+        Self::from_block_time(ChainBlockTime::blocks_to_duration(chain, 1))
+    }
+}
+
+/// Current watchdog status, as exposed to stats consumers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct WatchdogStats {
+    pub alert_level: AlertLevel,
+    pub seconds_since_last_block: u64,
+    /// See [`BlockAnchoredClock::skew`], `None` without a clock or before it observed a block.
+    pub clock_skew_secs: Option<i64>,
+}
+
+#[derive(Debug)]
+struct WatchdogState {
+    last_block: Instant,
+    /// Number of escalation steps crossed during the current stall.
+    crossed: usize,
+    level: AlertLevel,
+    last_executed: HashMap<WatchdogAction, Instant>,
+}
+
+#[derive(Debug)]
+pub struct PipelineWatchdog {
+    config: WatchdogConfig,
+    state: Mutex<WatchdogState>,
+    liveness: Option<Arc<LivenessTracker>>,
+    clock: Option<Arc<BlockAnchoredClock>>,
+}
+
+impl PipelineWatchdog {
+    pub fn new(config: WatchdogConfig) -> Self {
+        Self::new_at(config, Instant::now())
+    }
+
+    fn new_at(config: WatchdogConfig, now: Instant) -> Self {
+        Self {
+            config,
+            state: Mutex::new(WatchdogState {
+                last_block: now,
+                crossed: 0,
+                level: AlertLevel::Healthy,
+                last_executed: HashMap::new(),
+            }),
+            liveness: None,
+            clock: None,
+        }
+    }
+
+    /// Raises the alert level to the one of `liveness` while an extractor isn't synced.
+    pub fn with_liveness(mut self, liveness: Arc<LivenessTracker>) -> Self {
+        self.liveness = Some(liveness);
+        self
+    }
+
+    /// Reports the skew of `clock` with the stats.
+    pub fn with_clock(mut self, clock: Arc<BlockAnchoredClock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// The higher of `level` and the liveness alert level.
+    fn with_liveness_level(&self, level: AlertLevel) -> AlertLevel {
+        self.liveness
+            .as_ref()
+            .map_or(level, |liveness| level.max(liveness.alert_level()))
+    }
+
+    /// Records that a block update was applied, which ends any stall.
+    pub fn block_applied(&self) {
+        self.block_applied_at(Instant::now());
+    }
+
+    fn block_applied_at(&self, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        if state.level != AlertLevel::Healthy {
+            info!(
+                stalled_for = ?now.saturating_duration_since(state.last_block),
+                "Pipeline recovered"
+            );
+        }
+        state.last_block = now;
+        state.crossed = 0;
+        state.level = AlertLevel::Healthy;
+    }
+
+    /// Executes the actions whose threshold was crossed since the last check and returns the
+    /// resulting alert level.
+    pub fn check(&self, handler: &dyn RecoveryHandler) -> AlertLevel {
+        self.check_at(Instant::now(), handler)
+    }
+
+    fn check_at(&self, now: Instant, handler: &dyn RecoveryHandler) -> AlertLevel {
+        let mut due = Vec::new();
+        let (level, stalled_for) = {
+            let mut state = self.state.lock().unwrap();
+            let stalled_for = now.saturating_duration_since(state.last_block);
+            while let Some(step) = self
+                .config
+                .escalation
+                .get(state.crossed)
+                .filter(|step| step.after <= stalled_for)
+            {
+                state.crossed += 1;
+                state.level = state
+                    .level
+                    .max(step.action.alert_level());
+                let rate_limited = state
+                    .last_executed
+                    .get(&step.action)
+                    .is_some_and(|last| {
+                        now.saturating_duration_since(*last) < self.config.min_action_interval
+                    });
+                if rate_limited {
+                    debug!(action = ?step.action, "Skipping rate limited watchdog action");
+                    continue;
+                }
+                state
+                    .last_executed
+                    .insert(step.action, now);
+                due.push(step.action);
+            }
+            (state.level, stalled_for)
+        };
+
+        // The handler is called without holding the lock, so it may report blocks itself.
+        for action in due {
+            warn!(?action, ?stalled_for, "No block applied, pipeline may be wedged");
+            handler.handle(action, stalled_for);
+        }
+        self.with_liveness_level(level)
+    }
+
+    pub fn alert_level(&self) -> AlertLevel {
+        self.with_liveness_level(self.state.lock().unwrap().level)
+    }
+
+    pub fn stats(&self) -> WatchdogStats {
+        let (level, last_block) = {
+            let state = self.state.lock().unwrap();
+            (state.level, state.last_block)
+        };
+        WatchdogStats {
+            alert_level: self.with_liveness_level(level),
+            seconds_since_last_block: last_block.elapsed().as_secs(),
+            clock_skew_secs: self
+                .clock
+                .as_ref()
+                .and_then(|clock| clock.skew()),
+        }
+    }
+
+    /// Checks the pipeline every `check_interval`, forever. Meant to be spawned next to the task
+    /// applying the block updates.
+    pub async fn run(&self, handler: &dyn RecoveryHandler, check_interval: Duration) {
+        let mut interval = tokio::time::interval(check_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.check(handler);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm::{
+        clock::FixedClock,
+        tycho_models::{self, ExtractorStatus, SyncStatus},
+    };
+
+    #[derive(Default)]
+    struct RecordingHandler {
+        actions: Mutex<Vec<WatchdogAction>>,
+    }
+
+    impl RecordingHandler {
+        fn count(&self, action: WatchdogAction) -> usize {
+            self.actions
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|a| **a == action)
+                .count()
+        }
+
+        fn take(&self) -> Vec<WatchdogAction> {
+            std::mem::take(&mut self.actions.lock().unwrap())
+        }
+    }
+
+    impl RecoveryHandler for RecordingHandler {
+        fn handle(&self, action: WatchdogAction, _stalled_for: Duration) {
+            self.actions
+                .lock()
+                .unwrap()
+                .push(action);
+        }
+    }
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    fn config(min_action_interval: Duration) -> WatchdogConfig {
+        WatchdogConfig::new(
+            vec![
+                EscalationStep { after: secs(40), action: WatchdogAction::Resync },
+                EscalationStep { after: secs(10), action: WatchdogAction::LogWarning },
+                EscalationStep { after: secs(30), action: WatchdogAction::ForceReconnect },
+                EscalationStep { after: secs(20), action: WatchdogAction::EmitHealthEvent },
+            ],
+            min_action_interval,
+        )
+    }
+
+    #[test]
+    fn test_escalates_through_each_level() {
+        let t0 = Instant::now();
+        let watchdog = PipelineWatchdog::new_at(config(secs(0)), t0);
+        let handler = RecordingHandler::default();
+
+        let steps = [
+            (5, AlertLevel::Healthy, vec![]),
+            (10, AlertLevel::Warning, vec![WatchdogAction::LogWarning]),
+            (15, AlertLevel::Warning, vec![]),
+            (20, AlertLevel::Unhealthy, vec![WatchdogAction::EmitHealthEvent]),
+            (30, AlertLevel::Reconnecting, vec![WatchdogAction::ForceReconnect]),
+            (35, AlertLevel::Reconnecting, vec![]),
+            (40, AlertLevel::Resyncing, vec![WatchdogAction::Resync]),
+            (100, AlertLevel::Resyncing, vec![]),
+        ];
+        for (at, level, actions) in steps {
+            assert_eq!(watchdog.check_at(t0 + secs(at), &handler), level, "at {at}s");
+            assert_eq!(handler.take(), actions, "at {at}s");
+            assert_eq!(watchdog.alert_level(), level);
+        }
+    }
+
+    #[test]
+    fn test_skipped_checks_execute_all_crossed_actions() {
+        let t0 = Instant::now();
+        let watchdog = PipelineWatchdog::new_at(config(secs(0)), t0);
+        let handler = RecordingHandler::default();
+
+        assert_eq!(watchdog.check_at(t0 + secs(35), &handler), AlertLevel::Reconnecting);
+
+        assert_eq!(
+            handler.take(),
+            vec![
+                WatchdogAction::LogWarning,
+                WatchdogAction::EmitHealthEvent,
+                WatchdogAction::ForceReconnect
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reconnects_once_per_threshold_crossing() {
+        let t0 = Instant::now();
+        let watchdog = PipelineWatchdog::new_at(config(secs(0)), t0);
+        let handler = RecordingHandler::default();
+
+        for at in 0..=35 {
+            watchdog.check_at(t0 + secs(at), &handler);
+        }
+        assert_eq!(handler.count(WatchdogAction::ForceReconnect), 1);
+
+        watchdog.block_applied_at(t0 + secs(36));
+        assert_eq!(watchdog.alert_level(), AlertLevel::Healthy);
+        for at in 36..=70 {
+            watchdog.check_at(t0 + secs(at), &handler);
+        }
+        assert_eq!(handler.count(WatchdogAction::ForceReconnect), 2);
+    }
+
+    #[test]
+    fn test_actions_are_rate_limited_across_stalls() {
+        let t0 = Instant::now();
+        let watchdog = PipelineWatchdog::new_at(config(secs(50)), t0);
+        let handler = RecordingHandler::default();
+
+        watchdog.check_at(t0 + secs(30), &handler);
+        // Recovers briefly and stalls again: the second crossing comes too soon.
+        watchdog.block_applied_at(t0 + secs(31));
+        watchdog.check_at(t0 + secs(61), &handler);
+        assert_eq!(handler.count(WatchdogAction::ForceReconnect), 1);
+        assert_eq!(watchdog.alert_level(), AlertLevel::Reconnecting);
+
+        watchdog.block_applied_at(t0 + secs(62));
+        watchdog.check_at(t0 + secs(92), &handler);
+        assert_eq!(handler.count(WatchdogAction::ForceReconnect), 2);
+    }
+
+    #[test]
+    fn test_liveness_raises_alert_level() {
+        let t0 = Instant::now();
+        let liveness = Arc::new(LivenessTracker::new());
+        let watchdog =
+            PipelineWatchdog::new_at(config(secs(0)), t0).with_liveness(liveness.clone());
+        let handler = RecordingHandler::default();
+        let heartbeat = |status| ExtractorStatus {
+            extractor: "vm:ambient".to_string(),
+            chain: tycho_models::Chain::Ethereum,
+            status,
+            latest_block: 100,
+        };
+
+        liveness.heartbeat(&heartbeat(SyncStatus::Lagging));
+        assert_eq!(watchdog.check_at(t0 + secs(5), &handler), AlertLevel::Warning);
+        assert_eq!(watchdog.stats().alert_level, AlertLevel::Warning);
+        assert!(handler.take().is_empty());
+
+        // a stall still escalates beyond the liveness level
+        assert_eq!(watchdog.check_at(t0 + secs(20), &handler), AlertLevel::Unhealthy);
+
+        watchdog.block_applied_at(t0 + secs(21));
+        assert_eq!(watchdog.alert_level(), AlertLevel::Warning);
+        liveness.heartbeat(&heartbeat(SyncStatus::Synced));
+        assert_eq!(watchdog.alert_level(), AlertLevel::Healthy);
+    }
+
+    #[test]
+    fn test_config_for_chain() {
+        let ethereum = WatchdogConfig::for_chain(Chain::Ethereum);
+        let thresholds: Vec<_> = ethereum
+            .escalation
+            .iter()
+            .map(|step| step.after)
+            .collect();
+        assert_eq!(thresholds, vec![secs(60), secs(120), secs(300), secs(600)]);
+
+        let base = WatchdogConfig::for_chain(Chain::Base);
+        assert_eq!(base.escalation[0].after, MIN_WARNING_THRESHOLD);
+    }
+
+    #[test]
+    fn test_stats_report_clock_skew() {
+        let clock = Arc::new(BlockAnchoredClock::new(FixedClock(1_700_000_300)));
+        let watchdog = PipelineWatchdog::new(config(secs(0))).with_clock(clock.clone());
+        assert_eq!(watchdog.stats().clock_skew_secs, None);
+
+        clock.observe_block(1_700_000_000);
+
+        assert_eq!(watchdog.stats().clock_skew_secs, Some(300));
+    }
+}
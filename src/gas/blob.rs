@@ -0,0 +1,181 @@
+//! EIP-4844 blob gas pricing.
+//!
+//! L2s post their batches, and with them the withdrawals they contain, as blobs on mainnet. The
+//! price of blob gas follows its own fee market: it grows exponentially with the blob gas used
+//! above the per-block target, accumulated as the block's excess blob gas. The target and the
+//! update fraction are parameters of the fork, see [`BlobFork`].
+use alloy_primitives::U256;
+
+/// Blob base fee when there is no excess blob gas, in wei.
+pub const MIN_BLOB_BASE_FEE: u64 = 1;
+/// Blob gas consumed by a single blob.
+pub const GAS_PER_BLOB: u64 = 131_072;
+
+/// The forks that changed the blob parameters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlobFork {
+    /// Introduced blobs (EIP-4844).
+    Cancun,
+    /// Doubled the blob target, activated on mainnet with Pectra (EIP-7691).
+    #[default]
+    Prague,
+}
+
+impl BlobFork {
+    /// Blob gas a block is expected to use: three blobs since Cancun, six since Prague.
+    pub fn target_blob_gas_per_block(&self) -> u64 {
+        match self {
+            BlobFork::Cancun => 393_216,
+            BlobFork::Prague => 786_432,
+        }
+    }
+
+    /// Controls how fast the blob base fee changes with the excess blob gas.
+    pub fn blob_base_fee_update_fraction(&self) -> u64 {
+        match self {
+            BlobFork::Cancun => 3_338_477,
+            BlobFork::Prague => 5_007_716,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlobGasPriceOracle {
+    excess_blob_gas: u64,
+    fork: BlobFork,
+}
+
+impl BlobGasPriceOracle {
+    /// Creates an oracle for a block with the given excess blob gas, priced with the parameters of
+    /// the current mainnet fork.
+    pub fn new(excess_blob_gas: u64) -> Self {
+        Self { excess_blob_gas, fork: BlobFork::default() }
+    }
+
+    /// Prices blob gas with the parameters of `fork`.
+    pub fn with_fork(mut self, fork: BlobFork) -> Self {
+        self.fork = fork;
+        self
+    }
+
+    /// Creates an oracle for the block of `fork` following the given parent block
+    /// (`calc_excess_blob_gas` in EIP-4844).
+    pub fn from_block_header(
+        parent_excess_blob_gas: u64,
+        parent_blob_gas_used: u64,
+        fork: BlobFork,
+    ) -> Self {
+        Self::new(
+            parent_excess_blob_gas
+                .saturating_add(parent_blob_gas_used)
+                .saturating_sub(fork.target_blob_gas_per_block()),
+        )
+        .with_fork(fork)
+    }
+
+    pub fn excess_blob_gas(&self) -> u64 {
+        self.excess_blob_gas
+    }
+
+    pub fn fork(&self) -> BlobFork {
+        self.fork
+    }
+
+    /// Price of a unit of blob gas in wei (`get_base_fee_per_blob_gas` in EIP-4844).
+    pub fn current_blob_base_fee(&self) -> U256 {
+        fake_exponential(
+            U256::from(MIN_BLOB_BASE_FEE),
+            U256::from(self.excess_blob_gas),
+            U256::from(
+                self.fork
+                    .blob_base_fee_update_fraction(),
+            ),
+        )
+    }
+
+    /// Cost in wei of posting `num_blobs` blobs at the current blob base fee.
+    pub fn blob_gas_cost(&self, num_blobs: u8) -> U256 {
+        U256::from(num_blobs) * U256::from(GAS_PER_BLOB) * self.current_blob_base_fee()
+    }
+}
+
+/// Approximates `factor * e ** (numerator / denominator)` with a Taylor expansion, as specified
+/// by EIP-4844.
+fn fake_exponential(factor: U256, numerator: U256, denominator: U256) -> U256 {
+    let mut output = U256::ZERO;
+    let mut accum = factor * denominator;
+    let mut i = U256::from(1);
+    while !accum.is_zero() {
+        output += accum;
+        accum = accum * numerator / (denominator * i);
+        i += U256::from(1);
+    }
+    output / denominator
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    // Reference values of the blob base fee, as used by the execution clients' test suites.
+    #[rstest]
+    #[case::no_excess(0, "1")]
+    #[case::below_first_increase(2_314_057, "1")]
+    #[case::first_increase(2_314_058, "2")]
+    #[case::ten_mib(10 * 1024 * 1024, "23")]
+    #[case::below_u64_max(148_099_578, "18446739238971471609")]
+    #[case::above_u64_max(148_099_579, "18446744762204311910")]
+    #[case::above_u128_range(161_087_488, "902580055246494526580")]
+    fn test_current_blob_base_fee(#[case] excess_blob_gas: u64, #[case] expected: &str) {
+        let oracle = BlobGasPriceOracle::new(excess_blob_gas).with_fork(BlobFork::Cancun);
+
+        assert_eq!(oracle.current_blob_base_fee(), U256::from_str(expected).unwrap());
+    }
+
+    #[rstest]
+    #[case::no_excess(0, "1")]
+    #[case::below_first_increase(3_471_086, "1")]
+    #[case::first_increase(3_471_087, "2")]
+    #[case::ten_mib(10 * 1024 * 1024, "8")]
+    fn test_prague_blob_base_fee(#[case] excess_blob_gas: u64, #[case] expected: &str) {
+        let oracle = BlobGasPriceOracle::new(excess_blob_gas);
+
+        assert_eq!(oracle.current_blob_base_fee(), U256::from_str(expected).unwrap());
+    }
+
+    #[rstest]
+    #[case::below_target(BlobFork::Cancun, 0, 2 * GAS_PER_BLOB, 0)]
+    #[case::at_target(BlobFork::Cancun, 0, 3 * GAS_PER_BLOB, 0)]
+    #[case::above_target(BlobFork::Cancun, 0, 6 * GAS_PER_BLOB, 3 * GAS_PER_BLOB)]
+    #[case::consumes_excess(BlobFork::Cancun, GAS_PER_BLOB, 2 * GAS_PER_BLOB, 0)]
+    #[case::accumulates(BlobFork::Cancun, 3 * GAS_PER_BLOB, 5 * GAS_PER_BLOB, 5 * GAS_PER_BLOB)]
+    #[case::prague_at_target(BlobFork::Prague, 0, 6 * GAS_PER_BLOB, 0)]
+    #[case::prague_above_target(BlobFork::Prague, 0, 9 * GAS_PER_BLOB, 3 * GAS_PER_BLOB)]
+    fn test_from_block_header(
+        #[case] fork: BlobFork,
+        #[case] parent_excess_blob_gas: u64,
+        #[case] parent_blob_gas_used: u64,
+        #[case] expected: u64,
+    ) {
+        let oracle = BlobGasPriceOracle::from_block_header(
+            parent_excess_blob_gas,
+            parent_blob_gas_used,
+            fork,
+        );
+
+        assert_eq!(oracle.excess_blob_gas(), expected);
+        assert_eq!(oracle.fork(), fork);
+    }
+
+    #[test]
+    fn test_blob_gas_cost() {
+        let oracle = BlobGasPriceOracle::new(10 * 1024 * 1024).with_fork(BlobFork::Cancun);
+
+        assert_eq!(oracle.blob_gas_cost(0), U256::ZERO);
+        assert_eq!(oracle.blob_gas_cost(6), U256::from(6 * GAS_PER_BLOB * 23));
+    }
+}
@@ -0,0 +1,2 @@
+//! Gas pricing beyond the execution gas of a simulated transaction.
+pub mod blob;
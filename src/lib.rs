@@ -11,11 +11,29 @@ extern crate core;
 // Reexports
 pub use tycho_client;
 pub use tycho_common;
-pub use tycho_common as tycho_core; // Use `tycho_common` directly instead of `tycho_core`.
 
+/// The former name of the [`tycho_common`] re-export, kept for one release.
+#[doc(hidden)]
+#[deprecated(note = "use `tycho_simulation::tycho_common` instead")]
+pub mod tycho_core {
+    pub use tycho_common::*;
+}
+
+pub mod analytics;
 #[cfg(feature = "evm")]
 pub mod evm;
+pub mod gas;
+pub mod memory_budget;
 pub mod models;
+pub mod prelude;
+#[cfg(feature = "profiling")]
+pub mod profiling;
 pub mod protocol;
+pub mod routing;
+#[doc(hidden)]
 pub mod serde_helpers;
+#[cfg(feature = "regression-tests")]
+pub mod testing;
+pub mod token_registry;
+pub mod types;
 pub mod utils;
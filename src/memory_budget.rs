@@ -0,0 +1,349 @@
+//! A shared memory budget for caches.
+//!
+//! Instead of sizing every cache on its own, caches register with a [`MemoryBudget`] and report
+//! their approximate size through [`MemoryAccounted`]. Once the total exceeds the budget, the
+//! caches are asked to shed memory in the order of their [`ShedPriority`]: data that is cheap to
+//! recompute goes first. Components registered as [`ShedPriority::Essential`], like the tracked
+//! pool states, count towards the usage but are never asked to shed.
+//!
+//! Caches linked to the budget through a [`BudgetHandle`] enforce it whenever they grow, so the
+//! usage is brought back under the budget without the owner polling [`MemoryBudget::enforce`].
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    sync::{Arc, RwLock, Weak},
+};
+
+use serde::Serialize;
+use tracing::{debug, warn};
+
+/// The order in which caches shed memory, lowest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShedPriority {
+    /// Cached quotes and routes, recomputed on the next request.
+    Quotes,
+    /// Accounts fetched lazily from a node, fetched again when needed.
+    LazyAccounts,
+    /// History kept for convenience, like recent block headers.
+    History,
+    /// State that can't be recovered without a resync. Never shed.
+    Essential,
+}
+
+/// A component whose memory usage is accounted against a [`MemoryBudget`].
+pub trait MemoryAccounted: Debug + Send + Sync {
+    /// Approximate heap and inline size in bytes.
+    fn approx_bytes(&self) -> usize;
+
+    /// Frees about `target` bytes, or everything that can be freed if that is less. Returns the
+    /// number of bytes freed.
+    fn shed(&self, target: usize) -> usize;
+}
+
+/// Memory shed by a component during enforcement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShedEvent {
+    pub component: String,
+    pub priority: ShedPriority,
+    pub freed: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ComponentUsage {
+    pub priority: ShedPriority,
+    pub bytes: usize,
+}
+
+/// Memory usage, as exposed to stats consumers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MemoryStats {
+    pub budget: usize,
+    pub total: usize,
+    pub components: BTreeMap<String, ComponentUsage>,
+}
+
+/// A cache's link to the budget it is registered with, see [`MemoryBudget::handle`].
+///
+/// The budget is held weakly, as the budget itself holds the cache. A default handle isn't linked
+/// to any budget and enforces nothing.
+#[derive(Debug, Clone, Default)]
+pub struct BudgetHandle(Weak<MemoryBudget>);
+
+impl BudgetHandle {
+    /// Enforces the budget, if it still exists.
+    ///
+    /// The budget asks components to shed, so this must not be called while holding a lock that
+    /// a component's [`MemoryAccounted`] implementation takes.
+    pub fn enforce(&self) -> Vec<ShedEvent> {
+        self.0
+            .upgrade()
+            .map(|budget| budget.enforce())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug)]
+struct RegisteredComponent {
+    name: String,
+    priority: ShedPriority,
+    component: Arc<dyn MemoryAccounted>,
+}
+
+/// Coordinates the memory usage of the registered components.
+///
+/// The budget is internally synchronized and meant to be shared behind an `Arc`.
+#[derive(Debug)]
+pub struct MemoryBudget {
+    budget: RwLock<usize>,
+    components: RwLock<Vec<RegisteredComponent>>,
+}
+
+impl MemoryBudget {
+    /// Creates a budget of `budget` bytes.
+    pub fn new(budget: usize) -> Self {
+        Self { budget: RwLock::new(budget), components: RwLock::new(Vec::new()) }
+    }
+
+    /// Registers `component` under `name`, replacing any component previously registered under
+    /// the same name.
+    pub fn register(
+        &self,
+        name: &str,
+        priority: ShedPriority,
+        component: Arc<dyn MemoryAccounted>,
+    ) {
+        let mut components = self.components.write().unwrap();
+        components.retain(|registered| registered.name != name);
+        components.push(RegisteredComponent { name: name.to_string(), priority, component });
+    }
+
+    /// A handle for components to enforce this budget when they grow.
+    pub fn handle(self: &Arc<Self>) -> BudgetHandle {
+        BudgetHandle(Arc::downgrade(self))
+    }
+
+    pub fn budget(&self) -> usize {
+        *self.budget.read().unwrap()
+    }
+
+    /// Changes the budget and immediately enforces it.
+    pub fn set_budget(&self, budget: usize) -> Vec<ShedEvent> {
+        *self.budget.write().unwrap() = budget;
+        self.enforce()
+    }
+
+    /// Total approximate usage of all registered components.
+    pub fn usage(&self) -> usize {
+        self.components
+            .read()
+            .unwrap()
+            .iter()
+            .map(|registered| registered.component.approx_bytes())
+            .sum()
+    }
+
+    /// Asks components to shed memory until the usage fits the budget.
+    ///
+    /// Components are asked in the order of their priority, and in registration order within a
+    /// priority. Each one is asked to free the full excess, so later components are only asked if
+    /// the earlier ones couldn't free enough. Returns the memory shed by each asked component.
+    pub fn enforce(&self) -> Vec<ShedEvent> {
+        let budget = self.budget();
+        let components = self.components.read().unwrap();
+        let mut usage: usize = components
+            .iter()
+            .map(|registered| registered.component.approx_bytes())
+            .sum();
+        let mut order: Vec<_> = components
+            .iter()
+            .filter(|registered| registered.priority != ShedPriority::Essential)
+            .collect();
+        // stable sort, keeps the registration order within a priority
+        order.sort_by_key(|registered| registered.priority);
+
+        let mut events = Vec::new();
+        for registered in order {
+            if usage <= budget {
+                break;
+            }
+            let freed = registered
+                .component
+                .shed(usage - budget);
+            debug!(component = registered.name, freed, "Shed memory");
+            usage = usage.saturating_sub(freed);
+            events.push(ShedEvent {
+                component: registered.name.clone(),
+                priority: registered.priority,
+                freed,
+            });
+        }
+        if usage > budget {
+            warn!(usage, budget, "Memory usage exceeds budget after shedding all caches");
+        }
+        events
+    }
+
+    pub fn stats(&self) -> MemoryStats {
+        let components: BTreeMap<_, _> = self
+            .components
+            .read()
+            .unwrap()
+            .iter()
+            .map(|registered| {
+                (
+                    registered.name.clone(),
+                    ComponentUsage {
+                        priority: registered.priority,
+                        bytes: registered.component.approx_bytes(),
+                    },
+                )
+            })
+            .collect();
+        MemoryStats {
+            budget: self.budget(),
+            total: components
+                .values()
+                .map(|usage| usage.bytes)
+                .sum(),
+            components,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// A cache of `bytes` bytes that frees in chunks of `chunk` bytes.
+    #[derive(Debug)]
+    struct MockCache {
+        bytes: Mutex<usize>,
+        chunk: usize,
+    }
+
+    impl MockCache {
+        fn new(bytes: usize, chunk: usize) -> Arc<Self> {
+            Arc::new(Self { bytes: Mutex::new(bytes), chunk })
+        }
+    }
+
+    impl MemoryAccounted for MockCache {
+        fn approx_bytes(&self) -> usize {
+            *self.bytes.lock().unwrap()
+        }
+
+        fn shed(&self, target: usize) -> usize {
+            let mut bytes = self.bytes.lock().unwrap();
+            let freed = target
+                .div_ceil(self.chunk)
+                .saturating_mul(self.chunk)
+                .min(*bytes);
+            *bytes -= freed;
+            freed
+        }
+    }
+
+    fn event(component: &str, priority: ShedPriority, freed: usize) -> ShedEvent {
+        ShedEvent { component: component.to_string(), priority, freed }
+    }
+
+    fn setup(budget: usize) -> MemoryBudget {
+        let memory = MemoryBudget::new(budget);
+        // registered out of order on purpose
+        memory.register("headers", ShedPriority::History, MockCache::new(300, 10));
+        memory.register("pools", ShedPriority::Essential, MockCache::new(1_000, 1));
+        memory.register("quotes", ShedPriority::Quotes, MockCache::new(200, 50));
+        memory.register("accounts", ShedPriority::LazyAccounts, MockCache::new(400, 100));
+        memory
+    }
+
+    #[test]
+    fn test_within_budget() {
+        let memory = setup(2_000);
+
+        assert_eq!(memory.enforce(), vec![]);
+        assert_eq!(memory.usage(), 1_900);
+    }
+
+    #[test]
+    fn test_sheds_in_priority_order() {
+        let memory = setup(1_420);
+
+        let events = memory.enforce();
+
+        assert_eq!(
+            events,
+            vec![
+                event("quotes", ShedPriority::Quotes, 200),
+                event("accounts", ShedPriority::LazyAccounts, 300),
+            ]
+        );
+        assert_eq!(memory.usage(), 1_400);
+    }
+
+    #[test]
+    fn test_never_sheds_essential() {
+        let memory = setup(0);
+
+        let events = memory.enforce();
+
+        assert_eq!(
+            events,
+            vec![
+                event("quotes", ShedPriority::Quotes, 200),
+                event("accounts", ShedPriority::LazyAccounts, 400),
+                event("headers", ShedPriority::History, 300),
+            ]
+        );
+        assert_eq!(memory.stats().components["pools"].bytes, 1_000);
+        assert_eq!(memory.stats().total, 1_000);
+    }
+
+    #[test]
+    fn test_handle_enforces_until_budget_is_dropped() {
+        let memory = Arc::new(setup(1_800));
+        let handle = memory.handle();
+
+        assert_eq!(handle.enforce(), vec![event("quotes", ShedPriority::Quotes, 100)]);
+        drop(memory);
+        assert_eq!(handle.enforce(), vec![]);
+        assert_eq!(BudgetHandle::default().enforce(), vec![]);
+    }
+
+    #[test]
+    fn test_set_budget_enforces() {
+        let memory = setup(2_000);
+
+        let events = memory.set_budget(1_800);
+
+        assert_eq!(events, vec![event("quotes", ShedPriority::Quotes, 100)]);
+        assert_eq!(
+            memory.stats(),
+            MemoryStats {
+                budget: 1_800,
+                total: 1_800,
+                components: BTreeMap::from([
+                    (
+                        "accounts".to_string(),
+                        ComponentUsage { priority: ShedPriority::LazyAccounts, bytes: 400 }
+                    ),
+                    (
+                        "headers".to_string(),
+                        ComponentUsage { priority: ShedPriority::History, bytes: 300 }
+                    ),
+                    (
+                        "pools".to_string(),
+                        ComponentUsage { priority: ShedPriority::Essential, bytes: 1_000 }
+                    ),
+                    (
+                        "quotes".to_string(),
+                        ComponentUsage { priority: ShedPriority::Quotes, bytes: 100 }
+                    ),
+                ]),
+            }
+        );
+    }
+}
@@ -33,7 +33,7 @@ pub struct Token {
     pub decimals: usize,
     /// The symbol of the token
     pub symbol: String,
-    /// The amount of gas it takes to transfer the token
+    /// The amount of gas it takes to transfer the token, zero if unknown
     pub gas: BigUint,
 }
 
@@ -70,6 +70,29 @@ impl Token {
     pub fn one(&self) -> U256 {
         U256::from(10).pow(U256::from(self.decimals))
     }
+
+    /// Converts a token received from Tycho like `TryFrom`, but records a missing transfer gas as
+    /// zero instead of failing. Such tokens are measured later, see `evm::token_gas`.
+    pub fn try_from_unmeasured(value: ResponseToken) -> Result<Self, ModelError> {
+        let gas = value
+            .gas
+            .iter()
+            .flatten()
+            .min()
+            .copied();
+        convert_response_token(value, gas.unwrap_or_default())
+    }
+}
+
+fn convert_response_token(value: ResponseToken, gas: u64) -> Result<Token, ModelError> {
+    Ok(Token {
+        address: value.address,
+        decimals: value.decimals.try_into().map_err(|e| {
+            ModelError::ConversionError(format!("Failed to convert decimals: {}", e))
+        })?,
+        symbol: value.symbol.to_string(),
+        gas: BigUint::from(gas),
+    })
 }
 
 impl PartialOrd for Token {
@@ -94,26 +117,14 @@ impl TryFrom<ResponseToken> for Token {
     type Error = ModelError;
 
     fn try_from(value: ResponseToken) -> Result<Self, Self::Error> {
-        Ok(Self {
-            address: value.address,
-            decimals: value.decimals.try_into().map_err(|e| {
-                ModelError::ConversionError(format!("Failed to convert decimals: {}", e))
-            })?,
-            symbol: value.symbol.to_string(),
-            gas: BigUint::from(
-                value
-                    .gas
-                    .into_iter()
-                    .flatten()
-                    .collect::<Vec<u64>>()
-                    .iter()
-                    .min()
-                    .copied()
-                    .ok_or_else(|| {
-                        ModelError::MissingData("Gas attribute is missing".to_string())
-                    })?,
-            ),
-        })
+        let gas = value
+            .gas
+            .iter()
+            .flatten()
+            .min()
+            .copied()
+            .ok_or_else(|| ModelError::MissingData("Gas attribute is missing".to_string()))?;
+        convert_response_token(value, gas)
     }
 }
 
@@ -179,4 +190,32 @@ mod tests {
 
         assert_eq!(usdc.one(), U256::from(1000000));
     }
+
+    fn response_token(gas: Vec<Option<u64>>) -> ResponseToken {
+        ResponseToken {
+            address: Bytes::from("0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"),
+            symbol: "USDC".to_string(),
+            decimals: 6,
+            gas,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_try_from_response_token() {
+        let token =
+            Token::try_from(response_token(vec![Some(40_000), None, Some(30_000)])).unwrap();
+
+        assert_eq!(token.gas, BigUint::from(30_000u64));
+        assert_eq!(token.decimals, 6);
+    }
+
+    #[test]
+    fn test_try_from_response_token_missing_gas() {
+        let res = Token::try_from(response_token(vec![None]));
+
+        assert!(matches!(res, Err(ModelError::MissingData(_))));
+        let token = Token::try_from_unmeasured(response_token(vec![None])).unwrap();
+        assert_eq!(token.gas, BigUint::ZERO);
+    }
 }
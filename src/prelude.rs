@@ -0,0 +1,41 @@
+//! The stable public API.
+//!
+//! `use tycho_simulation::prelude::*;` imports the types integrators build on: the protocol
+//! simulation trait and its results, the core models, the simulation engine and its databases,
+//! the Tycho clients and the errors they return. Items are only added to or removed from the
+//! prelude in a release that announces it; the deeper module paths may move between releases.
+//!
+//! Deep modules that only hold items re-exported here, and modules holding implementation
+//! details, are hidden from the documentation.
+//!
+//! The `prelude_api` test checks at compile time that every item resolves to the same item as
+//! its full path and that the signatures integrators build on are unchanged, so removing or
+//! changing anything here fails that test.
+#[cfg(feature = "evm")]
+pub use crate::evm::{
+    decoder::StreamDecodeError,
+    engine_db::{
+        create_engine,
+        engine_db_interface::{EngineDatabaseError, EngineDatabaseInterface},
+        simulation_db::{SimulationDB, SimulationDBError},
+        tycho_db::{PreCachedDB, PreCachedDBError, TychoClientError},
+        update_engine,
+    },
+    http_client::{
+        AsyncTychoHttpClientImpl, DeduplicatingHttpClient, RetryingTychoHttpClient, TychoHttpClient,
+    },
+    protocol::vm::state::EVMPoolState,
+    simulation::{SimulationEngine, SimulationEngineError, SimulationParameters, SimulationResult},
+    stream::ProtocolStreamBuilder,
+};
+pub use crate::{
+    models::{Balances, ModelError, Token},
+    protocol::{
+        errors::{InvalidSnapshotError, LimitedSwapError, SimulationError, TransitionError},
+        models::{
+            BlockUpdate, GetAmountOutResult, LimitedSwapResult, PoolId, ProtocolComponent,
+            TryFromWithBlock,
+        },
+        state::ProtocolSim,
+    },
+};
@@ -0,0 +1,171 @@
+//! Time spent in the hot functions of a simulation.
+//!
+//! With the `profiling` feature enabled, database reads, state updates and simulations record
+//! their call count and duration in a process-wide [`SimulationProfiler`]. Counters are atomics so
+//! concurrent simulations don't contend on a lock; recording a call costs two clock reads and two
+//! atomic additions.
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// The profiled functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfiledFn {
+    /// Account reads of the engine databases.
+    Basic,
+    /// Storage reads of the engine databases.
+    Storage,
+    /// State updates of the engine databases.
+    UpdateState,
+    /// Swap quotes of VM pools.
+    SimulateSwap,
+    /// Transactions executed by the simulation engine.
+    SimulateTx,
+}
+
+impl ProfiledFn {
+    const ALL: [ProfiledFn; 5] =
+        [Self::Basic, Self::Storage, Self::UpdateState, Self::SimulateSwap, Self::SimulateTx];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Basic => "basic",
+            Self::Storage => "storage",
+            Self::UpdateState => "update_state",
+            Self::SimulateSwap => "simulate_swap",
+            Self::SimulateTx => "simulate_tx",
+        }
+    }
+}
+
+/// Call count and total time of each profiled function that was called.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProfileReport {
+    pub calls: HashMap<&'static str, (u64, Duration)>,
+}
+
+#[derive(Debug)]
+struct Counter {
+    calls: AtomicU64,
+    nanos: AtomicU64,
+}
+
+impl Counter {
+    const fn new() -> Self {
+        Self { calls: AtomicU64::new(0), nanos: AtomicU64::new(0) }
+    }
+}
+
+#[derive(Debug)]
+pub struct SimulationProfiler {
+    counters: [Counter; ProfiledFn::ALL.len()],
+}
+
+static PROFILER: SimulationProfiler = SimulationProfiler::new();
+
+impl SimulationProfiler {
+    const fn new() -> Self {
+        Self { counters: [const { Counter::new() }; ProfiledFn::ALL.len()] }
+    }
+
+    /// The profiler the instrumented functions record to.
+    pub fn global() -> &'static Self {
+        &PROFILER
+    }
+
+    /// Starts timing a call of `function`, which is recorded when the returned guard is dropped.
+    pub fn start(&'static self, function: ProfiledFn) -> ProfileGuard {
+        ProfileGuard { profiler: self, function, started: Instant::now() }
+    }
+
+    pub fn record(&self, function: ProfiledFn, elapsed: Duration) {
+        let counter = &self.counters[function as usize];
+        counter
+            .calls
+            .fetch_add(1, Ordering::Relaxed);
+        counter
+            .nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn report(&self) -> ProfileReport {
+        let calls = ProfiledFn::ALL
+            .iter()
+            .filter_map(|function| {
+                let counter = &self.counters[*function as usize];
+                let calls = counter.calls.load(Ordering::Relaxed);
+                let nanos = counter.nanos.load(Ordering::Relaxed);
+                (calls > 0).then(|| (function.name(), (calls, Duration::from_nanos(nanos))))
+            })
+            .collect();
+        ProfileReport { calls }
+    }
+
+    pub fn reset(&self) {
+        for counter in &self.counters {
+            counter
+                .calls
+                .store(0, Ordering::Relaxed);
+            counter
+                .nanos
+                .store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Records the time since its creation on drop, see [`SimulationProfiler::start`].
+#[must_use]
+#[derive(Debug)]
+pub struct ProfileGuard {
+    profiler: &'static SimulationProfiler,
+    function: ProfiledFn,
+    started: Instant,
+}
+
+impl Drop for ProfileGuard {
+    fn drop(&mut self) {
+        self.profiler
+            .record(self.function, self.started.elapsed());
+    }
+}
+
+/// Starts timing a call of `function` on the global profiler.
+pub fn profile(function: ProfiledFn) -> ProfileGuard {
+    SimulationProfiler::global().start(function)
+}
+
+/// Calls and time recorded since the start of the process or the last [`reset_profiling`].
+pub fn profiling_report() -> ProfileReport {
+    SimulationProfiler::global().report()
+}
+
+pub fn reset_profiling() {
+    SimulationProfiler::global().reset()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_and_reset() {
+        let profiler = SimulationProfiler::new();
+        profiler.record(ProfiledFn::Basic, Duration::from_micros(3));
+        profiler.record(ProfiledFn::Basic, Duration::from_micros(5));
+        profiler.record(ProfiledFn::SimulateTx, Duration::from_millis(1));
+
+        let report = profiler.report();
+        profiler.reset();
+
+        assert_eq!(
+            report.calls,
+            HashMap::from([
+                ("basic", (2, Duration::from_micros(8))),
+                ("simulate_tx", (1, Duration::from_millis(1))),
+            ])
+        );
+        assert_eq!(profiler.report(), ProfileReport::default());
+    }
+}
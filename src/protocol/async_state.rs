@@ -0,0 +1,226 @@
+//! Asynchronous protocol simulation
+//!
+//! [`ProtocolSim`] is synchronous, which suits states held fully in memory. Some states have to
+//! do IO while quoting, e.g. fetching tick ranges or contracts they haven't loaded yet, and
+//! blocking on that IO inside a synchronous quote stalls the runtime driving it.
+//! [`AsyncProtocolSim`] mirrors the quoting part of `ProtocolSim` with methods returning futures.
+//! In-memory states keep implementing `ProtocolSim` and can be used wherever an
+//! `AsyncProtocolSim` is expected through [`SyncAdapter`].
+use alloy_primitives::Address;
+use futures::future::BoxFuture;
+use num_bigint::BigUint;
+
+use crate::{
+    models::Token,
+    protocol::{errors::SimulationError, models::GetAmountOutResult, state::ProtocolSim},
+};
+
+/// Asynchronous counterpart of [`ProtocolSim`]'s quoting methods.
+///
+/// See [`ProtocolSim`] for the semantics of each method.
+pub trait AsyncProtocolSim: std::fmt::Debug + Send + Sync + 'static {
+    fn fee(&self) -> f64;
+
+    fn spot_price<'a>(
+        &'a self,
+        base: &'a Token,
+        quote: &'a Token,
+    ) -> BoxFuture<'a, Result<f64, SimulationError>>;
+
+    fn get_amount_out<'a>(
+        &'a self,
+        amount_in: BigUint,
+        token_in: &'a Token,
+        token_out: &'a Token,
+    ) -> BoxFuture<'a, Result<GetAmountOutResult, SimulationError>>;
+
+    fn get_limits(
+        &self,
+        sell_token: Address,
+        buy_token: Address,
+    ) -> BoxFuture<'_, Result<(BigUint, BigUint), SimulationError>>;
+}
+
+/// Exposes a synchronous state as an [`AsyncProtocolSim`].
+///
+/// The returned futures complete on their first poll, running the synchronous method in place.
+#[derive(Debug, Clone)]
+pub struct SyncAdapter(Box<dyn ProtocolSim>);
+
+impl SyncAdapter {
+    pub fn new(state: impl ProtocolSim) -> Self {
+        Self(Box::new(state))
+    }
+
+    pub fn inner(&self) -> &dyn ProtocolSim {
+        self.0.as_ref()
+    }
+}
+
+impl From<Box<dyn ProtocolSim>> for SyncAdapter {
+    fn from(state: Box<dyn ProtocolSim>) -> Self {
+        Self(state)
+    }
+}
+
+impl AsyncProtocolSim for SyncAdapter {
+    fn fee(&self) -> f64 {
+        self.0.fee()
+    }
+
+    fn spot_price<'a>(
+        &'a self,
+        base: &'a Token,
+        quote: &'a Token,
+    ) -> BoxFuture<'a, Result<f64, SimulationError>> {
+        Box::pin(async move { self.0.spot_price(base, quote) })
+    }
+
+    fn get_amount_out<'a>(
+        &'a self,
+        amount_in: BigUint,
+        token_in: &'a Token,
+        token_out: &'a Token,
+    ) -> BoxFuture<'a, Result<GetAmountOutResult, SimulationError>> {
+        Box::pin(async move {
+            self.0
+                .get_amount_out(amount_in, token_in, token_out)
+        })
+    }
+
+    fn get_limits(
+        &self,
+        sell_token: Address,
+        buy_token: Address,
+    ) -> BoxFuture<'_, Result<(BigUint, BigUint), SimulationError>> {
+        Box::pin(async move { self.0.get_limits(sell_token, buy_token) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{any::Any, collections::HashMap, str::FromStr};
+
+    use alloy_primitives::U256;
+    use futures::executor::block_on;
+    use num_bigint::ToBigUint;
+    use tycho_common::{dto::ProtocolStateDelta, Bytes};
+
+    use super::*;
+    use crate::{
+        evm::protocol::uniswap_v2::state::UniswapV2State,
+        models::Balances,
+        protocol::{
+            conformance::{run_conformance_suite, ConformanceSpec, ZeroAmountPolicy},
+            errors::TransitionError,
+        },
+    };
+
+    /// Quotes through the adapter's futures, so the conformance suite exercises the adapter.
+    #[derive(Debug, Clone)]
+    struct Blocking(SyncAdapter);
+
+    impl ProtocolSim for Blocking {
+        fn fee(&self) -> f64 {
+            AsyncProtocolSim::fee(&self.0)
+        }
+
+        fn spot_price(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
+            block_on(AsyncProtocolSim::spot_price(&self.0, base, quote))
+        }
+
+        fn get_amount_out(
+            &self,
+            amount_in: BigUint,
+            token_in: &Token,
+            token_out: &Token,
+        ) -> Result<GetAmountOutResult, SimulationError> {
+            block_on(AsyncProtocolSim::get_amount_out(&self.0, amount_in, token_in, token_out))
+        }
+
+        fn get_limits(
+            &self,
+            sell_token: Address,
+            buy_token: Address,
+        ) -> Result<(BigUint, BigUint), SimulationError> {
+            block_on(AsyncProtocolSim::get_limits(&self.0, sell_token, buy_token))
+        }
+
+        fn delta_transition(
+            &mut self,
+            _delta: ProtocolStateDelta,
+            _tokens: &HashMap<Bytes, Token>,
+            _balances: &Balances,
+        ) -> Result<(), TransitionError<String>> {
+            unimplemented!("not quoted")
+        }
+
+        fn clone_box(&self) -> Box<dyn ProtocolSim> {
+            Box::new(self.clone())
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+
+        fn eq(&self, other: &dyn ProtocolSim) -> bool {
+            other
+                .as_any()
+                .downcast_ref::<Blocking>()
+                .is_some_and(|other| self.0.inner().eq(other.0.inner()))
+        }
+    }
+
+    fn token(address: &str, decimals: usize) -> Token {
+        Token::new(address, decimals, "T", 10_000.to_biguint().unwrap())
+    }
+
+    #[test]
+    fn test_adapter_matches_sync_state() {
+        let state = UniswapV2State::new(U256::from(1_000_000u64), U256::from(2_000_000u64));
+        let adapter = SyncAdapter::new(state.clone());
+        let (a, b) = (
+            token("0x0000000000000000000000000000000000000001", 18),
+            token("0x0000000000000000000000000000000000000002", 18),
+        );
+
+        let res = block_on(adapter.get_amount_out(BigUint::from(1_000u64), &a, &b)).unwrap();
+
+        let expected = state
+            .get_amount_out(BigUint::from(1_000u64), &a, &b)
+            .unwrap();
+        assert_eq!(res.amount, expected.amount);
+        assert_eq!(res.gas, expected.gas);
+        assert_eq!(
+            block_on(AsyncProtocolSim::spot_price(&adapter, &a, &b)).unwrap(),
+            state.spot_price(&a, &b).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_adapter_conformance() {
+        let token_x = token("0x0000000000000000000000000000000000000001", 18);
+        let token_y = token("0x0000000000000000000000000000000000000002", 6);
+        let amounts = ["1000000000000000", "1000000000000000000", "100000000000000000000"]
+            .map(|amount| BigUint::from_str(amount).unwrap())
+            .to_vec();
+        let spec = ConformanceSpec::new(token_x, token_y, amounts)
+            .with_zero_amount(ZeroAmountPolicy::Rejects);
+
+        let report = run_conformance_suite(
+            || {
+                Blocking(SyncAdapter::new(UniswapV2State::new(
+                    U256::from_str("1000000000000000000000000").unwrap(),
+                    U256::from_str("2000000000000").unwrap(),
+                )))
+            },
+            spec,
+        );
+
+        report.assert_ok();
+    }
+}
@@ -0,0 +1,524 @@
+//! Conformance checks for [`ProtocolSim`] implementations.
+//!
+//! The trait's contract has requirements the compiler can't enforce: quotes must compose through
+//! `new_state`, outputs must grow with the input, fees must be accounted for, and no input may
+//! panic. [`run_conformance_suite`] exercises an implementation against all of them and reports
+//! every violation instead of stopping at the first one, so third party implementations can run
+//! the same checks as the crate's own pools.
+//!
+//! Requires the `testing` feature.
+//!
+//! ```ignore
+//! let spec = ConformanceSpec::new(weth, usdc, vec![BigUint::from(10u64).pow(15)]);
+//! run_conformance_suite(|| MyPool::new(...), spec).assert_ok();
+//! ```
+use std::fmt;
+
+use num_bigint::BigUint;
+use num_traits::{ToPrimitive, Zero};
+
+use crate::{
+    models::Token,
+    protocol::{
+        errors::{catch_panic, SimulationError},
+        models::GetAmountOutResult,
+        state::ProtocolSim,
+    },
+};
+
+/// How an implementation handles quotes for a zero input amount.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZeroAmountPolicy {
+    /// Quotes zero output.
+    ReturnsZero,
+    /// Fails with [`SimulationError::InvalidInput`].
+    Rejects,
+    /// Either of the above.
+    Either,
+}
+
+/// Restores a state from its serialized form, see [`ConformanceSpec::with_serde_round_trip`].
+pub type SerdeRoundTrip = fn(&dyn ProtocolSim) -> Result<Box<dyn ProtocolSim>, String>;
+
+/// What to check and with which inputs.
+#[derive(Debug, Clone)]
+pub struct ConformanceSpec {
+    pub token_in: Token,
+    pub token_out: Token,
+    /// Increasing amounts that the pool can swap in full.
+    pub amounts: Vec<BigUint>,
+    pub zero_amount: ZeroAmountPolicy,
+    /// Largest amount of the randomized sweep; smaller amounts are drawn more often.
+    pub sweep_max: u128,
+    pub sweep_iterations: usize,
+    pub seed: u64,
+    /// Relative slack of comparisons between quotes that only agree up to rounding and the fees
+    /// retained by the pool.
+    pub relative_tolerance: f64,
+    pub serde_round_trip: Option<SerdeRoundTrip>,
+}
+
+impl ConformanceSpec {
+    pub fn new(token_in: Token, token_out: Token, amounts: Vec<BigUint>) -> Self {
+        let sweep_max = amounts
+            .iter()
+            .max()
+            .and_then(|amount| (amount * 100u32).to_u128())
+            .unwrap_or(u128::MAX);
+        Self {
+            token_in,
+            token_out,
+            amounts,
+            zero_amount: ZeroAmountPolicy::Either,
+            sweep_max,
+            sweep_iterations: 200,
+            seed: 0x5eed,
+            relative_tolerance: 1e-4,
+            serde_round_trip: None,
+        }
+    }
+
+    pub fn with_zero_amount(mut self, policy: ZeroAmountPolicy) -> Self {
+        self.zero_amount = policy;
+        self
+    }
+
+    pub fn with_sweep(mut self, max: u128, iterations: usize, seed: u64) -> Self {
+        self.sweep_max = max;
+        self.sweep_iterations = iterations;
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_relative_tolerance(mut self, tolerance: f64) -> Self {
+        self.relative_tolerance = tolerance;
+        self
+    }
+
+    /// Also checks that serializing and restoring a state yields an equal state.
+    pub fn with_serde_round_trip(mut self, round_trip: SerdeRoundTrip) -> Self {
+        self.serde_round_trip = Some(round_trip);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConformanceCheck {
+    /// Larger inputs never yield smaller outputs.
+    Monotonicity,
+    /// Swapping the output back on the new state never returns more than the original input.
+    RoundTrip,
+    /// Sequential quotes through `new_state` move the price against the trader and match a single
+    /// quote of the combined amount.
+    Composition,
+    /// Zero amounts are handled according to the [`ZeroAmountPolicy`].
+    ZeroAmount,
+    /// The fee is a valid ratio and no quote pays out more than the fee-adjusted spot price.
+    FeeAccounting,
+    /// Serialized states restore to equal states.
+    SerdeRoundTrip,
+    /// No amount of the randomized sweep panics.
+    PanicFreedom,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConformanceFailure {
+    pub check: ConformanceCheck,
+    pub message: String,
+}
+
+impl fmt::Display for ConformanceFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.check, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConformanceReport {
+    pub failures: Vec<ConformanceFailure>,
+}
+
+impl ConformanceReport {
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Panics listing all failures, if there are any.
+    pub fn assert_ok(&self) {
+        if !self.is_ok() {
+            let failures: Vec<_> = self
+                .failures
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+            panic!("Conformance checks failed:\n{}", failures.join("\n"));
+        }
+    }
+
+    fn fail(&mut self, check: ConformanceCheck, message: String) {
+        self.failures
+            .push(ConformanceFailure { check, message });
+    }
+}
+
+/// Runs all conformance checks on states created by `factory`. Every check starts from a fresh
+/// state.
+pub fn run_conformance_suite<P: ProtocolSim>(
+    factory: impl Fn() -> P,
+    spec: ConformanceSpec,
+) -> ConformanceReport {
+    let mut report = ConformanceReport::default();
+    check_monotonicity(&factory(), &spec, &mut report);
+    check_round_trip(&factory(), &spec, &mut report);
+    check_composition(&factory(), &spec, &mut report);
+    check_zero_amount(&factory(), &spec, &mut report);
+    check_fee_accounting(&factory(), &spec, &mut report);
+    if let Some(round_trip) = spec.serde_round_trip {
+        check_serde_round_trip(&factory(), round_trip, &mut report);
+    }
+    check_panic_freedom(&factory(), &spec, &mut report);
+    report
+}
+
+fn quote(
+    state: &dyn ProtocolSim,
+    amount: &BigUint,
+    token_in: &Token,
+    token_out: &Token,
+) -> Result<GetAmountOutResult, SimulationError> {
+    catch_panic("conformance", || state.get_amount_out(amount.clone(), token_in, token_out))
+}
+
+fn check_monotonicity(
+    state: &dyn ProtocolSim,
+    spec: &ConformanceSpec,
+    report: &mut ConformanceReport,
+) {
+    let mut previous: Option<(&BigUint, BigUint)> = None;
+    for amount in &spec.amounts {
+        let out = match quote(state, amount, &spec.token_in, &spec.token_out) {
+            Ok(res) => res.amount,
+            Err(err) => {
+                report.fail(
+                    ConformanceCheck::Monotonicity,
+                    format!("Quote for {amount} failed: {err}"),
+                );
+                return;
+            }
+        };
+        if let Some((previous_amount, previous_out)) = &previous {
+            if out < *previous_out {
+                report.fail(
+                    ConformanceCheck::Monotonicity,
+                    format!(
+                        "{amount} in yields {out}, less than {previous_out} for {previous_amount}"
+                    ),
+                );
+            }
+        }
+        previous = Some((amount, out));
+    }
+}
+
+fn check_round_trip(
+    state: &dyn ProtocolSim,
+    spec: &ConformanceSpec,
+    report: &mut ConformanceReport,
+) {
+    for amount in &spec.amounts {
+        let Ok(there) = quote(state, amount, &spec.token_in, &spec.token_out) else {
+            continue;
+        };
+        if there.amount.is_zero() {
+            continue;
+        }
+        match quote(there.new_state.as_ref(), &there.amount, &spec.token_out, &spec.token_in) {
+            Ok(back) if back.amount > *amount => report.fail(
+                ConformanceCheck::RoundTrip,
+                format!("{amount} in returns {} after swapping there and back", back.amount),
+            ),
+            Err(SimulationError::InternalPanic { message, .. }) => report.fail(
+                ConformanceCheck::RoundTrip,
+                format!("Swapping {} back panicked: {message}", there.amount),
+            ),
+            _ => {}
+        }
+    }
+}
+
+fn check_composition(
+    state: &dyn ProtocolSim,
+    spec: &ConformanceSpec,
+    report: &mut ConformanceReport,
+) {
+    for amount in &spec.amounts {
+        let (Ok(first), Ok(combined)) = (
+            quote(state, amount, &spec.token_in, &spec.token_out),
+            quote(state, &(amount * 2u32), &spec.token_in, &spec.token_out),
+        ) else {
+            continue;
+        };
+        let second = match quote(first.new_state.as_ref(), amount, &spec.token_in, &spec.token_out)
+        {
+            Ok(second) => second,
+            Err(err) => {
+                report.fail(
+                    ConformanceCheck::Composition,
+                    format!("Quoting {amount} on the state after quoting {amount} failed: {err}"),
+                );
+                continue;
+            }
+        };
+        if second.amount > first.amount {
+            report.fail(
+                ConformanceCheck::Composition,
+                format!(
+                    "Quoting {amount} again on the new state yields {}, more than {}",
+                    second.amount, first.amount
+                ),
+            );
+        }
+        let sequential = &first.amount + &second.amount;
+        if !approx_eq(&sequential, &combined.amount, spec.relative_tolerance) {
+            report.fail(
+                ConformanceCheck::Composition,
+                format!(
+                    "Two sequential quotes of {amount} yield {sequential}, a single quote of \
+                     twice the amount {}",
+                    combined.amount
+                ),
+            );
+        }
+    }
+}
+
+fn check_zero_amount(
+    state: &dyn ProtocolSim,
+    spec: &ConformanceSpec,
+    report: &mut ConformanceReport,
+) {
+    let res = quote(state, &BigUint::zero(), &spec.token_in, &spec.token_out);
+    let allowed = match (&res, spec.zero_amount) {
+        (Ok(res), ZeroAmountPolicy::ReturnsZero | ZeroAmountPolicy::Either) => res.amount.is_zero(),
+        (
+            Err(SimulationError::InvalidInput(..)),
+            ZeroAmountPolicy::Rejects | ZeroAmountPolicy::Either,
+        ) => true,
+        _ => false,
+    };
+    if !allowed {
+        let outcome = match res {
+            Ok(res) => format!("returned {}", res.amount),
+            Err(err) => format!("failed with {err}"),
+        };
+        report.fail(
+            ConformanceCheck::ZeroAmount,
+            format!("Quote for zero {outcome}, expected {:?}", spec.zero_amount),
+        );
+    }
+}
+
+fn check_fee_accounting(
+    state: &dyn ProtocolSim,
+    spec: &ConformanceSpec,
+    report: &mut ConformanceReport,
+) {
+    let fee = state.fee();
+    if !(0.0..1.0).contains(&fee) {
+        report.fail(ConformanceCheck::FeeAccounting, format!("Fee {fee} is not a ratio in [0, 1)"));
+        return;
+    }
+    let price = match state.spot_price(&spec.token_in, &spec.token_out) {
+        Ok(price) => price,
+        Err(err) => {
+            report.fail(ConformanceCheck::FeeAccounting, format!("Spot price failed: {err}"));
+            return;
+        }
+    };
+    let decimals = spec.token_out.decimals as i32 - spec.token_in.decimals as i32;
+    for amount in &spec.amounts {
+        let Ok(res) = quote(state, amount, &spec.token_in, &spec.token_out) else {
+            continue;
+        };
+        let (Some(amount_in), Some(amount_out)) = (amount.to_f64(), res.amount.to_f64()) else {
+            continue;
+        };
+        let bound = amount_in * (1.0 - fee) * price * 10f64.powi(decimals);
+        if amount_out > (bound * (1.0 + spec.relative_tolerance)).floor() + 1.0 {
+            report.fail(
+                ConformanceCheck::FeeAccounting,
+                format!(
+                    "{amount} in yields {}, more than {bound} allowed by spot price {price} and \
+                     fee {fee}",
+                    res.amount
+                ),
+            );
+        }
+    }
+}
+
+fn check_serde_round_trip(
+    state: &dyn ProtocolSim,
+    round_trip: SerdeRoundTrip,
+    report: &mut ConformanceReport,
+) {
+    match round_trip(state) {
+        Ok(restored) if !restored.eq(state) => report.fail(
+            ConformanceCheck::SerdeRoundTrip,
+            "Restored state differs from the original".to_string(),
+        ),
+        Err(err) => report.fail(ConformanceCheck::SerdeRoundTrip, err),
+        _ => {}
+    }
+}
+
+fn check_panic_freedom(
+    state: &dyn ProtocolSim,
+    spec: &ConformanceSpec,
+    report: &mut ConformanceReport,
+) {
+    let mut rng = XorShift(spec.seed.max(1));
+    let max_bits = 128 - spec.sweep_max.leading_zeros();
+    for _ in 0..spec.sweep_iterations {
+        let bits = rng.next() as u32 % max_bits.max(1) + 1;
+        let raw = ((u128::from(rng.next()) << 64) | u128::from(rng.next())) >> (128 - bits);
+        let amount = BigUint::from(raw.clamp(1, spec.sweep_max.max(1)));
+        for (token_in, token_out) in
+            [(&spec.token_in, &spec.token_out), (&spec.token_out, &spec.token_in)]
+        {
+            if let Err(SimulationError::InternalPanic { message, .. }) =
+                quote(state, &amount, token_in, token_out)
+            {
+                report.fail(
+                    ConformanceCheck::PanicFreedom,
+                    format!("Quoting {amount} {} panicked: {message}", token_in.symbol),
+                );
+            }
+        }
+    }
+}
+
+fn approx_eq(a: &BigUint, b: &BigUint, tolerance: f64) -> bool {
+    let (Some(a), Some(b)) = (a.to_f64(), b.to_f64()) else {
+        return a == b;
+    };
+    // Every quote may round down by up to one unit
+    (a - b).abs() <= a.max(b) * tolerance + 2.0
+}
+
+/// Deterministic pseudo random numbers for reproducible sweeps.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{any::Any, collections::HashMap};
+
+    use alloy_primitives::Address;
+    use num_bigint::ToBigUint;
+    use tycho_common::{dto::ProtocolStateDelta, Bytes};
+
+    use super::*;
+    use crate::{models::Balances, protocol::errors::TransitionError};
+
+    /// Pays out twice the input and ignores its state, violating most checks.
+    #[derive(Debug, Clone)]
+    struct GenerousPool;
+
+    impl ProtocolSim for GenerousPool {
+        fn fee(&self) -> f64 {
+            0.0
+        }
+
+        fn spot_price(&self, _base: &Token, _quote: &Token) -> Result<f64, SimulationError> {
+            Ok(1.0)
+        }
+
+        fn get_amount_out(
+            &self,
+            amount_in: BigUint,
+            _token_in: &Token,
+            _token_out: &Token,
+        ) -> Result<GetAmountOutResult, SimulationError> {
+            if amount_in > BigUint::from(1_000_000u64) {
+                panic!("overflow");
+            }
+            Ok(GetAmountOutResult::new(amount_in * 2u32, BigUint::zero(), Box::new(self.clone())))
+        }
+
+        fn get_limits(
+            &self,
+            _sell_token: Address,
+            _buy_token: Address,
+        ) -> Result<(BigUint, BigUint), SimulationError> {
+            Ok((BigUint::zero(), BigUint::zero()))
+        }
+
+        fn delta_transition(
+            &mut self,
+            _delta: ProtocolStateDelta,
+            _tokens: &HashMap<Bytes, Token>,
+            _balances: &Balances,
+        ) -> Result<(), TransitionError<String>> {
+            Ok(())
+        }
+
+        fn clone_box(&self) -> Box<dyn ProtocolSim> {
+            Box::new(self.clone())
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+
+        fn eq(&self, _other: &dyn ProtocolSim) -> bool {
+            false
+        }
+    }
+
+    fn token(address: &str) -> Token {
+        Token::new(address, 18, "T", 10_000.to_biguint().unwrap())
+    }
+
+    #[test]
+    fn test_reports_violations() {
+        let spec = ConformanceSpec::new(
+            token("0x0000000000000000000000000000000000000001"),
+            token("0x0000000000000000000000000000000000000002"),
+            vec![BigUint::from(100u64), BigUint::from(1_000u64)],
+        )
+        .with_zero_amount(ZeroAmountPolicy::Rejects)
+        .with_sweep(10_000_000, 50, 7)
+        .with_serde_round_trip(|state| Ok(state.clone_box()));
+
+        let report = run_conformance_suite(|| GenerousPool, spec);
+
+        let failed: Vec<_> = report
+            .failures
+            .iter()
+            .map(|failure| failure.check)
+            .collect();
+        for check in [
+            ConformanceCheck::RoundTrip,
+            ConformanceCheck::ZeroAmount,
+            ConformanceCheck::FeeAccounting,
+            ConformanceCheck::SerdeRoundTrip,
+            ConformanceCheck::PanicFreedom,
+        ] {
+            assert!(failed.contains(&check), "{check:?} not reported");
+        }
+        assert!(!failed.contains(&ConformanceCheck::Monotonicity));
+    }
+}
@@ -1,6 +1,15 @@
 //! Protocol generic errors
-use std::{fmt, io};
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    cell::{Cell, RefCell},
+    error::Error,
+    fmt, io,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Once},
+};
 
+use alloy_primitives::{Address, U256};
 use serde_json::Error as SerdeError;
 use thiserror::Error;
 
@@ -28,7 +37,7 @@ pub enum InvalidSnapshotError {
     #[error("Value error {0}")]
     ValueError(String),
     #[error("Unable to set up vm state on the engine: {0}")]
-    VMError(SimulationError),
+    VMError(#[source] SimulationError),
 }
 
 impl From<SimulationError> for InvalidSnapshotError {
@@ -47,6 +56,10 @@ impl From<SimulationError> for InvalidSnapshotError {
 ///   network problem.
 /// - `InvalidInput`: Indicates that the simulation has failed due to bad input parameters.
 /// - `FatalError`: There is a bug with this pool or protocol - do not attempt simulation again.
+/// - `InternalPanic`: The pool's implementation panicked. The pool should be quarantined.
+/// - `StateAccess`: Reading chain state failed during the simulation of a contract. Like a
+///   `RecoverableError`, retrying at a later time may succeed. The failure of the lower layer is
+///   kept as source.
 #[derive(Error, Debug)]
 pub enum SimulationError {
     #[error("Fatal error: {0}")]
@@ -55,6 +68,160 @@ pub enum SimulationError {
     InvalidInput(String, Option<GetAmountOutResult>),
     #[error("Recoverable error: {0}")]
     RecoverableError(String),
+    #[error("Panic in pool {pool_id}: {message}")]
+    InternalPanic {
+        pool_id: String,
+        message: String,
+        /// Where the pool panicked. Captured for panics inside [`catch_panic`], elsewhere only if
+        /// enabled through `RUST_BACKTRACE`.
+        backtrace: Arc<Backtrace>,
+    },
+    #[error("Failed to access state of contract {contract} at block {block}: {source}")]
+    StateAccess {
+        contract: Address,
+        block: u64,
+        /// The pool being simulated, if known.
+        pool_id: Option<String>,
+        #[source]
+        source: Box<dyn Error + Send + Sync>,
+    },
+}
+
+impl SimulationError {
+    /// Attributes a state access failure to the given pool. Other errors are returned unchanged.
+    pub fn in_pool(self, id: &str) -> Self {
+        match self {
+            SimulationError::StateAccess { contract, block, pool_id: None, source } => {
+                SimulationError::StateAccess {
+                    contract,
+                    block,
+                    pool_id: Some(id.to_string()),
+                    source,
+                }
+            }
+            err => err,
+        }
+    }
+
+    /// An [`SimulationError::InternalPanic`] of the given pool, from the payload of the panic.
+    pub(crate) fn internal_panic(pool_id: &str, payload: &(dyn Any + Send)) -> Self {
+        let backtrace = PANIC_BACKTRACE
+            .take()
+            .unwrap_or_else(Backtrace::capture);
+        SimulationError::InternalPanic {
+            pool_id: pool_id.to_string(),
+            message: panic_message(payload),
+            backtrace: Arc::new(backtrace),
+        }
+    }
+
+    /// Renders this error followed by all of its sources, see [`error_chain`].
+    pub fn error_chain(&self) -> String {
+        error_chain(self)
+    }
+}
+
+/// Renders `error` followed by all of its sources, outermost first, e.g. for logging.
+///
+/// Sources whose message the rendered chain already ends with are not repeated.
+///
+/// ```
+/// use std::io;
+///
+/// use tycho_simulation::protocol::errors::{error_chain, FileError};
+///
+/// let err = FileError::Io(io::Error::new(io::ErrorKind::NotFound, "no such file"));
+/// assert_eq!(error_chain(&err), "I/O error: no such file");
+/// ```
+pub fn error_chain(error: &(dyn Error + 'static)) -> String {
+    let mut chain = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        let message = cause.to_string();
+        if !chain.ends_with(&message) {
+            chain.push_str(": ");
+            chain.push_str(&message);
+        }
+        source = cause.source();
+    }
+    chain
+}
+
+/// A swap price limit the pool contract would reject.
+///
+/// Mirrors the contracts' revert conditions, e.g. Uniswap V3's `SPL` or Ekubo's
+/// `SqrtRatioLimitWrongDirection`. Prices are in the pool's native sqrt price format.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PriceLimitError {
+    #[error("Price limit {limit} is on the wrong side of the current price {current}")]
+    WrongDirection { limit: U256, current: U256 },
+    #[error("Price limit {0} is outside the valid price range")]
+    OutOfRange(U256),
+}
+
+/// Errors of swaps bounded by a price limit.
+#[derive(Error, Debug)]
+pub enum LimitedSwapError {
+    #[error(transparent)]
+    PriceLimit(#[from] PriceLimitError),
+    #[error(transparent)]
+    Simulation(#[from] SimulationError),
+}
+
+thread_local! {
+    /// Number of [`guard_panic`] calls running on this thread.
+    static GUARDS: Cell<usize> = const { Cell::new(0) };
+    /// Backtrace of the last panic inside [`guard_panic`] on this thread, until it is taken.
+    static PANIC_BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
+}
+
+static BACKTRACE_HOOK: Once = Once::new();
+
+/// Chains a panic hook capturing the backtrace of panics inside [`guard_panic`] in front of the
+/// current one. The backtrace is only available to the hook, not to the code catching the panic.
+fn install_backtrace_hook() {
+    BACKTRACE_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if GUARDS.get() > 0 {
+                PANIC_BACKTRACE.set(Some(Backtrace::force_capture()));
+            }
+            previous(info);
+        }));
+    });
+}
+
+/// Runs `f`, converting a panic into a `SimulationError::InternalPanic` for the given pool,
+/// with the backtrace of the panic.
+///
+/// Use this around calls into third party math or user provided implementations so that a
+/// single misbehaving pool can't take down a whole batch. Catching an unwind costs nothing
+/// unless a panic actually occurs.
+pub fn catch_panic<T>(
+    pool_id: &str,
+    f: impl FnOnce() -> Result<T, SimulationError>,
+) -> Result<T, SimulationError> {
+    guard_panic(pool_id, f)?
+}
+
+/// Like [`catch_panic`], for functions returning any type.
+pub(crate) fn guard_panic<R>(pool_id: &str, f: impl FnOnce() -> R) -> Result<R, SimulationError> {
+    install_backtrace_hook();
+    GUARDS.set(GUARDS.get() + 1);
+    let res = panic::catch_unwind(AssertUnwindSafe(f));
+    GUARDS.set(GUARDS.get() - 1);
+    res.map_err(|payload| SimulationError::internal_panic(pool_id, payload.as_ref()))
+}
+
+/// Extracts the message from a panic payload.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
 }
 
 impl<T> From<SimulationError> for TransitionError<T> {
@@ -65,7 +232,7 @@ impl<T> From<SimulationError> for TransitionError<T> {
 
 impl From<FileError> for SimulationError {
     fn from(error: FileError) -> Self {
-        SimulationError::FatalError(error.to_string())
+        SimulationError::FatalError(error_chain(&error))
     }
 }
 
@@ -80,10 +247,10 @@ pub enum FileError {
     /// Occurs when a bad file path was given, which cannot be converted to string.
     #[error("File path conversion error {0}")]
     FilePath(String),
-    #[error("I/O error {0}")]
-    Io(io::Error),
+    #[error("I/O error: {0}")]
+    Io(#[source] io::Error),
     #[error("Json parsing error {0}")]
-    Parse(SerdeError),
+    Parse(#[source] SerdeError),
 }
 
 impl From<io::Error> for FileError {
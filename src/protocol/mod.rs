@@ -1,3 +1,10 @@
+pub mod async_state;
+#[cfg(any(test, feature = "testing"))]
+pub mod conformance;
 pub mod errors;
 pub mod models;
+pub mod pair_index;
+pub mod pool_store;
+pub mod post_processing;
+pub mod provenance;
 pub mod state;
@@ -24,28 +24,34 @@
 //! It's worth emphasizing that although the term "pair" used in this
 //! module refers to a trading pair, it does not necessarily imply two
 //! tokens only. Some pairs might have more than two tokens.
-use std::{collections::HashMap, default::Default, future::Future};
+use std::{collections::HashMap, default::Default, future::Future, sync::Arc};
 
 use chrono::NaiveDateTime;
 use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
 use tycho_client::feed::Header;
 use tycho_common::{models::Chain, Bytes};
 
-use super::state::ProtocolSim;
-use crate::models::Token;
+use super::{post_processing::QuoteAdjustment, provenance::Provenance, state::ProtocolSim};
+use crate::{models::Token, types::TokenAmount};
+
+/// Identifier of a pool: the id of its [`ProtocolComponent`], as used by the keys of a
+/// [`BlockUpdate`] and by [`PoolStore`](super::pool_store::PoolStore).
+pub type PoolId = String;
 
 /// ProtocolComponent struct represents the properties of a trading pair
 ///
 /// # Fields
 ///
 /// * `address`: String, the address of the trading pair
-/// * `tokens`: `Vec<ERC20Token>`, the tokens of the trading pair
+/// * `tokens`: `Vec<Arc<Token>>`, the tokens of the trading pair. Components decoded by the
+///   `TychoStreamDecoder` share the instances of its `TokenRegistry`.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ProtocolComponent {
     #[deprecated(since = "0.73.0", note = "Use `id` instead")]
     pub address: Bytes,
     pub id: Bytes,
-    pub tokens: Vec<Token>,
+    pub tokens: Vec<Arc<Token>>,
     pub protocol_system: String,
     pub protocol_type_name: String,
     pub chain: Chain,
@@ -63,7 +69,7 @@ impl ProtocolComponent {
         protocol_system: String,
         protocol_type_name: String,
         chain: Chain,
-        tokens: Vec<Token>,
+        tokens: Vec<impl Into<Arc<Token>>>,
         contract_ids: Vec<Bytes>,
         static_attributes: HashMap<String, Bytes>,
         creation_tx: Bytes,
@@ -72,7 +78,10 @@ impl ProtocolComponent {
         ProtocolComponent {
             address: Default::default(),
             id,
-            tokens,
+            tokens: tokens
+                .into_iter()
+                .map(Into::into)
+                .collect(),
             protocol_system,
             protocol_type_name,
             chain,
@@ -85,7 +94,7 @@ impl ProtocolComponent {
 
     pub fn from_with_tokens(
         core_model: tycho_common::dto::ProtocolComponent,
-        mut tokens: Vec<Token>,
+        mut tokens: Vec<Arc<Token>>,
     ) -> Self {
         tokens.sort_unstable_by_key(|t| t.address.clone());
         let id = Bytes::from(core_model.id.as_str());
@@ -113,7 +122,7 @@ impl From<ProtocolComponent> for tycho_common::models::protocol::ProtocolCompone
             tokens: component
                 .tokens
                 .into_iter()
-                .map(|t| t.address)
+                .map(|t| t.address.clone())
                 .collect(),
             static_attributes: component.static_attributes,
             change: Default::default(),
@@ -143,17 +152,34 @@ pub trait TryFromWithBlock<T> {
 ///
 /// * `amount`: BigUint, the amount of the trading pair
 /// * `gas`: BigUint, the gas of the trading pair
+/// * `provenance`: the inputs the quote was computed from, if requested from a
+///   [`PoolStore`](super::pool_store::PoolStore) with provenance enabled
+/// * `adjustments`: how much each post-processor took off the quote, if it was quoted with a
+///   [`QuotePostProcessorChain`](super::post_processing::QuotePostProcessorChain)
 #[derive(Debug)]
+#[non_exhaustive]
 pub struct GetAmountOutResult {
     pub amount: BigUint,
     pub gas: BigUint,
     pub new_state: Box<dyn ProtocolSim>,
+    pub provenance: Option<Provenance>,
+    pub adjustments: Vec<QuoteAdjustment>,
 }
 
 impl GetAmountOutResult {
     /// Constructs a new GetAmountOutResult struct with the given amount and gas
     pub fn new(amount: BigUint, gas: BigUint, new_state: Box<dyn ProtocolSim>) -> Self {
-        GetAmountOutResult { amount, gas, new_state }
+        GetAmountOutResult { amount, gas, new_state, provenance: None, adjustments: Vec::new() }
+    }
+
+    pub fn with_provenance(mut self, provenance: Provenance) -> Self {
+        self.provenance = Some(provenance);
+        self
+    }
+
+    pub fn with_adjustments(mut self, adjustments: Vec<QuoteAdjustment>) -> Self {
+        self.adjustments = adjustments;
+        self
     }
 
     /// Aggregates the given GetAmountOutResult struct to the current one.
@@ -165,15 +191,55 @@ impl GetAmountOutResult {
     }
 }
 
+/// The result of a swap bounded by a price limit.
+///
+/// Unlike [`GetAmountOutResult`] the swap may stop before the specified amount is filled, so both
+/// sides of the swap are reported.
+#[derive(Debug)]
+pub struct LimitedSwapResult {
+    /// The amount of the sell token the swap consumes.
+    pub amount_in: TokenAmount,
+    /// The amount of the buy token the swap returns.
+    pub amount_out: TokenAmount,
+    pub gas: BigUint,
+    pub new_state: Box<dyn ProtocolSim>,
+    /// Whether the swap stopped at the price limit before the specified amount was filled.
+    pub limit_reached: bool,
+}
+
+/// The implementation simulating a pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    /// A Rust implementation of the protocol's math.
+    Native,
+    /// The protocol's contracts, executed in the VM.
+    Vm,
+}
+
+/// A component reported by several extractors in the same block, of which only one report was
+/// applied.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ComponentConflict {
+    pub component_id: String,
+    /// The extractor whose report was applied.
+    pub winner: String,
+    /// The extractor whose report was dropped.
+    pub loser: String,
+}
+
 #[derive(Debug)]
 pub struct BlockUpdate {
     pub block_number: u64,
+    pub block_hash: Bytes,
     /// The new and updated states of this block
     pub states: HashMap<String, Box<dyn ProtocolSim>>,
     /// The new pairs that were added in this block
     pub new_pairs: HashMap<String, ProtocolComponent>,
     /// The pairs that were removed in this block
     pub removed_pairs: HashMap<String, ProtocolComponent>,
+    /// Components reported by several extractors, ordered by component id
+    pub conflicts: Vec<ComponentConflict>,
 }
 
 impl BlockUpdate {
@@ -182,11 +248,28 @@ impl BlockUpdate {
         states: HashMap<String, Box<dyn ProtocolSim>>,
         new_pairs: HashMap<String, ProtocolComponent>,
     ) -> Self {
-        BlockUpdate { block_number, states, new_pairs, removed_pairs: HashMap::new() }
+        BlockUpdate {
+            block_number,
+            block_hash: Bytes::default(),
+            states,
+            new_pairs,
+            removed_pairs: HashMap::new(),
+            conflicts: Vec::new(),
+        }
+    }
+
+    pub fn set_block_hash(mut self, hash: Bytes) -> Self {
+        self.block_hash = hash;
+        self
     }
 
     pub fn set_removed_pairs(mut self, pairs: HashMap<String, ProtocolComponent>) -> Self {
         self.removed_pairs = pairs;
         self
     }
+
+    pub fn set_conflicts(mut self, conflicts: Vec<ComponentConflict>) -> Self {
+        self.conflicts = conflicts;
+        self
+    }
 }
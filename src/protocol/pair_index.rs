@@ -0,0 +1,194 @@
+//! Token pair index
+//!
+//! Maps every unordered token pair of a component to the ids of the components trading it, so
+//! that pools with more than two tokens can be found for any of their pairs, not only the first.
+//!
+//! # Memory
+//!
+//! A component with `n` tokens contributes `n * (n - 1) / 2` pairs, each stored once per lookup
+//! direction. Protocols supported by Tycho cap their pools at 8 tokens, which bounds a single
+//! component to 28 pairs.
+use std::collections::{HashMap, HashSet};
+
+use itertools::Itertools;
+use tycho_common::Bytes;
+
+use super::models::{BlockUpdate, ProtocolComponent};
+
+/// An unordered token pair. The lower address always comes first.
+pub type TokenPair = (Bytes, Bytes);
+
+fn token_pair(a: &Bytes, b: &Bytes) -> TokenPair {
+    if a <= b {
+        (a.clone(), b.clone())
+    } else {
+        (b.clone(), a.clone())
+    }
+}
+
+/// An incrementally maintained index of token pairs to component ids.
+#[derive(Debug, Clone, Default)]
+pub struct PairIndex {
+    by_pair: HashMap<TokenPair, HashSet<String>>,
+    by_component: HashMap<String, Vec<TokenPair>>,
+}
+
+impl PairIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Indexes all token pairs of a component. Re-inserting a component replaces its pairs.
+    pub fn insert(&mut self, id: &str, tokens: &[Bytes]) {
+        self.remove(id);
+        let pairs: Vec<TokenPair> = tokens
+            .iter()
+            .unique()
+            .tuple_combinations()
+            .map(|(a, b)| token_pair(a, b))
+            .collect();
+        for pair in &pairs {
+            self.by_pair
+                .entry(pair.clone())
+                .or_default()
+                .insert(id.to_string());
+        }
+        self.by_component
+            .insert(id.to_string(), pairs);
+    }
+
+    /// Indexes a protocol component.
+    pub fn insert_component(&mut self, id: &str, component: &ProtocolComponent) {
+        let tokens: Vec<Bytes> = component
+            .tokens
+            .iter()
+            .map(|t| t.address.clone())
+            .collect();
+        self.insert(id, &tokens);
+    }
+
+    /// Removes a component and all of its pairs. Returns whether the component was indexed.
+    pub fn remove(&mut self, id: &str) -> bool {
+        let Some(pairs) = self.by_component.remove(id) else {
+            return false;
+        };
+        for pair in pairs {
+            if let Some(ids) = self.by_pair.get_mut(&pair) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    self.by_pair.remove(&pair);
+                }
+            }
+        }
+        true
+    }
+
+    /// Applies the new and removed pairs of a block update.
+    pub fn apply(&mut self, update: &BlockUpdate) {
+        for id in update.removed_pairs.keys() {
+            self.remove(id);
+        }
+        for (id, component) in &update.new_pairs {
+            self.insert_component(id, component);
+        }
+    }
+
+    /// Returns the ids of all components trading the given pair, in either direction.
+    pub fn pools_for_pair(&self, a: &Bytes, b: &Bytes) -> impl Iterator<Item = &String> {
+        self.by_pair
+            .get(&token_pair(a, b))
+            .into_iter()
+            .flatten()
+    }
+
+    /// Returns all pairs indexed for the given component.
+    pub fn pairs_of_pool(&self, id: &str) -> &[TokenPair] {
+        self.by_component
+            .get(id)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// All indexed pairs with the ids of the components trading them.
+    pub fn pairs(&self) -> impl Iterator<Item = (&TokenPair, &HashSet<String>)> {
+        self.by_pair.iter()
+    }
+
+    /// Number of distinct pairs in the index.
+    pub fn n_pairs(&self) -> usize {
+        self.by_pair.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(n: u8) -> Vec<Bytes> {
+        (1..=n)
+            .map(|i| Bytes::from(vec![i]))
+            .collect()
+    }
+
+    #[test]
+    fn test_insert_multi_token_pool() {
+        let mut index = PairIndex::new();
+        let tokens = tokens(3);
+
+        index.insert("curve_3pool", &tokens);
+
+        assert_eq!(index.pairs_of_pool("curve_3pool").len(), 3);
+        for (a, b) in tokens.iter().tuple_combinations() {
+            assert_eq!(
+                index
+                    .pools_for_pair(b, a)
+                    .collect::<Vec<_>>(),
+                vec!["curve_3pool"]
+            );
+        }
+    }
+
+    #[test]
+    fn test_insert_four_token_pool() {
+        let mut index = PairIndex::new();
+
+        index.insert("balancer", &tokens(4));
+
+        assert_eq!(index.pairs_of_pool("balancer").len(), 6);
+        assert_eq!(index.n_pairs(), 6);
+    }
+
+    #[test]
+    fn test_remove_cleans_all_entries() {
+        let mut index = PairIndex::new();
+        let tokens = tokens(3);
+        index.insert("curve_3pool", &tokens);
+        index.insert("uniswap_v2", &tokens[..2]);
+
+        assert!(index.remove("curve_3pool"));
+
+        assert!(index
+            .pairs_of_pool("curve_3pool")
+            .is_empty());
+        assert_eq!(index.n_pairs(), 1);
+        assert_eq!(
+            index
+                .pools_for_pair(&tokens[0], &tokens[1])
+                .collect::<Vec<_>>(),
+            vec!["uniswap_v2"]
+        );
+        assert!(!index.remove("curve_3pool"));
+    }
+
+    #[test]
+    fn test_reinsert_replaces_pairs() {
+        let mut index = PairIndex::new();
+        let tokens = tokens(3);
+        index.insert("pool", &tokens);
+
+        index.insert("pool", &tokens[1..]);
+
+        assert_eq!(index.pairs_of_pool("pool").len(), 1);
+        assert_eq!(index.n_pairs(), 1);
+    }
+}
@@ -0,0 +1,597 @@
+//! The pools tracked from a protocol stream.
+//!
+//! Applying every [`BlockUpdate`] of a stream to a [`PoolStore`] keeps the latest state and
+//! component of each pool in one place, together with a [`PairIndex`] over their token pairs.
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    mem,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use alloy_primitives::B256;
+use num_bigint::BigUint;
+use tycho_common::Bytes;
+
+use super::{
+    errors::{catch_panic, SimulationError},
+    models::{BlockUpdate, GetAmountOutResult, ProtocolComponent},
+    pair_index::PairIndex,
+    post_processing::{PoolSummary, QuotePostProcessorChain},
+    provenance::{state_fingerprint, Provenance},
+    state::ProtocolSim,
+};
+#[cfg(feature = "evm")]
+use crate::evm::warmup::QuoteFrequencyCounter;
+use crate::{
+    memory_budget::{BudgetHandle, MemoryAccounted, MemoryBudget},
+    models::Token,
+};
+
+/// Approximate memory used by the pools of a [`PoolStore`], updated whenever pools change.
+///
+/// Dropping tracked pools would require a resync, so they are registered as
+/// [`ShedPriority::Essential`](crate::memory_budget::ShedPriority::Essential) and never shed.
+#[derive(Debug, Default)]
+pub struct PoolStoreMemory(AtomicUsize);
+
+impl MemoryAccounted for PoolStoreMemory {
+    fn approx_bytes(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn shed(&self, _target: usize) -> usize {
+        0
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PoolStore {
+    states: HashMap<String, Box<dyn ProtocolSim>>,
+    components: HashMap<String, ProtocolComponent>,
+    tokens: HashMap<Bytes, Arc<Token>>,
+    pairs: PairIndex,
+    block_number: u64,
+    block_hash: Bytes,
+    /// Whether quotes are stamped with their [`Provenance`].
+    provenance: bool,
+    post_processors: Option<Arc<QuotePostProcessorChain>>,
+    /// State fingerprints computed since the pool's state last changed.
+    fingerprints: Mutex<HashMap<String, Option<B256>>>,
+    /// Counts the quotes of every pool, to decode the most quoted pools first on the next start.
+    #[cfg(feature = "evm")]
+    quote_frequencies: Option<Arc<Mutex<QuoteFrequencyCounter>>>,
+    /// Approximate memory of the states, components and tokens, adjusted on every change.
+    bytes: usize,
+    memory: Arc<PoolStoreMemory>,
+    budget: BudgetHandle,
+}
+
+impl PoolStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stamps the quotes of [`Self::quote`] with their [`Provenance`].
+    pub fn with_provenance(mut self, enabled: bool) -> Self {
+        self.provenance = enabled;
+        self
+    }
+
+    /// Runs every quote of [`Self::quote`] and [`Self::best_quote`] through `post_processors`.
+    pub fn with_post_processors(mut self, post_processors: Arc<QuotePostProcessorChain>) -> Self {
+        self.post_processors = Some(post_processors);
+        self
+    }
+
+    /// Records every successful quote of [`Self::quote`] in `counter`.
+    ///
+    /// Saving the counter on shutdown and loading it as a
+    /// [`WarmupPriority::Frequency`](crate::evm::warmup::WarmupPriority::Frequency) on the next
+    /// start decodes the most quoted pools first.
+    #[cfg(feature = "evm")]
+    pub fn with_quote_frequencies(mut self, counter: Arc<Mutex<QuoteFrequencyCounter>>) -> Self {
+        self.quote_frequencies = Some(counter);
+        self
+    }
+
+    /// Enforces `budget` whenever pools are added or updated. [`Self::memory`] must be registered
+    /// with the budget as well for the pools to count towards it.
+    pub fn with_memory_budget(mut self, budget: &Arc<MemoryBudget>) -> Self {
+        self.budget = budget.handle();
+        self
+    }
+
+    /// The memory used by the pools, to register with a [`MemoryBudget`].
+    pub fn memory(&self) -> Arc<PoolStoreMemory> {
+        self.memory.clone()
+    }
+
+    /// Adds a pool, replacing any pool with the same id.
+    pub fn insert(&mut self, id: &str, component: ProtocolComponent, state: Box<dyn ProtocolSim>) {
+        self.index_component(id, component);
+        self.forget_fingerprint(id);
+        self.insert_state(id.to_string(), state);
+        self.account_memory();
+    }
+
+    /// Applies the removed pools, new pools and updated states of a block.
+    ///
+    /// States of pools without a known component are kept, their component may be added by a
+    /// later block.
+    pub fn apply(&mut self, update: BlockUpdate) {
+        self.block_number = update.block_number;
+        self.block_hash = update.block_hash;
+        for id in update.removed_pairs.keys() {
+            self.pairs.remove(id);
+            if let Some(component) = self.components.remove(id) {
+                self.bytes = self
+                    .bytes
+                    .saturating_sub(component_size(id, &component));
+            }
+            if let Some(state) = self.states.remove(id) {
+                self.bytes = self
+                    .bytes
+                    .saturating_sub(state_size(id, state.as_ref()));
+            }
+            self.forget_fingerprint(id);
+        }
+        for (id, component) in update.new_pairs {
+            self.index_component(&id, component);
+        }
+        for (id, state) in update.states {
+            self.forget_fingerprint(&id);
+            self.insert_state(id, state);
+        }
+        self.account_memory();
+    }
+
+    /// Publishes the approximate memory used by the pools and enforces the budget, if any.
+    fn account_memory(&self) {
+        self.memory
+            .0
+            .store(self.bytes, Ordering::Relaxed);
+        self.budget.enforce();
+    }
+
+    fn insert_state(&mut self, id: String, state: Box<dyn ProtocolSim>) {
+        self.bytes += state_size(&id, state.as_ref());
+        match self.states.entry(id) {
+            Entry::Occupied(mut entry) => {
+                let replaced = entry.insert(state);
+                self.bytes = self
+                    .bytes
+                    .saturating_sub(state_size(entry.key(), replaced.as_ref()));
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(state);
+            }
+        }
+    }
+
+    fn forget_fingerprint(&mut self, id: &str) {
+        self.fingerprints
+            .get_mut()
+            .unwrap()
+            .remove(id);
+    }
+
+    fn index_component(&mut self, id: &str, component: ProtocolComponent) {
+        self.pairs
+            .insert_component(id, &component);
+        for token in &component.tokens {
+            if let Entry::Vacant(entry) = self.tokens.entry(token.address.clone()) {
+                self.bytes += token_size(token);
+                entry.insert(token.clone());
+            }
+        }
+        self.bytes += component_size(id, &component);
+        if let Some(replaced) = self
+            .components
+            .insert(id.to_string(), component)
+        {
+            self.bytes = self
+                .bytes
+                .saturating_sub(component_size(id, &replaced));
+        }
+    }
+
+    pub fn state(&self, id: &str) -> Option<&dyn ProtocolSim> {
+        self.states.get(id).map(Box::as_ref)
+    }
+
+    pub fn component(&self, id: &str) -> Option<&ProtocolComponent> {
+        self.components.get(id)
+    }
+
+    /// A token of any tracked pool.
+    pub fn token(&self, address: &Bytes) -> Option<&Token> {
+        self.tokens
+            .get(address)
+            .map(Arc::as_ref)
+    }
+
+    pub fn pair_index(&self) -> &PairIndex {
+        &self.pairs
+    }
+
+    /// The states of all pools trading `a` against `b`, in either direction, including pools with
+    /// more than two tokens.
+    pub fn pools_for_pair<'a>(
+        &'a self,
+        a: &Bytes,
+        b: &Bytes,
+    ) -> impl Iterator<Item = (&'a str, &'a dyn ProtocolSim)> + 'a {
+        self.pairs
+            .pools_for_pair(a, b)
+            .filter_map(|id| Some((id.as_str(), self.state(id)?)))
+    }
+
+    /// Number of the last applied block.
+    pub fn block_number(&self) -> u64 {
+        self.block_number
+    }
+
+    pub fn block_hash(&self) -> &Bytes {
+        &self.block_hash
+    }
+
+    /// Fingerprint of a pool's current state, computed once per state. `None` if the pool isn't
+    /// in the store or its state has no canonical encoding.
+    pub fn state_fingerprint(&self, id: &str) -> Option<B256> {
+        let state = self.states.get(id)?;
+        let mut fingerprints = self.fingerprints.lock().unwrap();
+        *fingerprints
+            .entry(id.to_string())
+            .or_insert_with(|| state_fingerprint(state.as_ref()))
+    }
+
+    /// Quotes swapping `amount_in` of `token_in` for `token_out` in the given pool.
+    ///
+    /// The quote carries its [`Provenance`] if enabled with [`Self::with_provenance`], and is
+    /// adjusted by the post-processors set with [`Self::with_post_processors`]. A panic of the
+    /// pool's implementation is returned as [`SimulationError::InternalPanic`].
+    pub fn quote(
+        &self,
+        id: &str,
+        amount_in: BigUint,
+        token_in: &Token,
+        token_out: &Token,
+    ) -> Result<GetAmountOutResult, SimulationError> {
+        let state = self.state(id).ok_or_else(|| {
+            SimulationError::InvalidInput(format!("Pool {id} is not in the store"), None)
+        })?;
+        let mut quote = catch_panic(id, || state.get_amount_out(amount_in, token_in, token_out))?;
+        if let Some(post_processors) = self
+            .post_processors
+            .as_ref()
+            .filter(|chain| !chain.is_empty())
+        {
+            let protocol_system = self
+                .component(id)
+                .map_or("", |component| component.protocol_system.as_str());
+            let pool = catch_panic(id, || {
+                PoolSummary::from_state(id, protocol_system, state, token_in, token_out)
+            })?;
+            quote = post_processors.process(&pool, quote);
+        }
+        #[cfg(feature = "evm")]
+        if let Some(counter) = &self.quote_frequencies {
+            counter.lock().unwrap().record(id);
+        }
+        if !self.provenance {
+            return Ok(quote);
+        }
+        Ok(quote.with_provenance(Provenance::new(
+            self.block_number,
+            self.block_hash.clone(),
+            id,
+            self.state_fingerprint(id),
+            state.backend(),
+        )))
+    }
+
+    /// Returns the id of the pool paying out the most for `amount_in`, along with its quote from
+    /// [`Self::quote`], so post-processors take part in picking the pool.
+    ///
+    /// Pools failing to quote, including pools panicking, are skipped. If all of them fail, the
+    /// first error is returned.
+    pub fn best_quote(
+        &self,
+        amount_in: &BigUint,
+        token_in: &Token,
+        token_out: &Token,
+    ) -> Result<(String, GetAmountOutResult), SimulationError> {
+        let mut best: Option<(&str, GetAmountOutResult)> = None;
+        let mut first_error = None;
+        for (id, _) in self.pools_for_pair(&token_in.address, &token_out.address) {
+            match self.quote(id, amount_in.clone(), token_in, token_out) {
+                Ok(res) => {
+                    if best
+                        .as_ref()
+                        .is_none_or(|(_, best)| res.amount > best.amount)
+                    {
+                        best = Some((id, res));
+                    }
+                }
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        best.map(|(id, res)| (id.to_string(), res))
+            .ok_or_else(|| {
+                first_error.unwrap_or_else(|| {
+                    SimulationError::InvalidInput("No pools to quote".to_string(), None)
+                })
+            })
+    }
+
+    /// Number of pools with a state.
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+}
+
+/// Approximate memory of a pool's state, including its heap data.
+fn state_size(id: &str, state: &dyn ProtocolSim) -> usize {
+    id.len() + mem::size_of_val(state) + state.approx_heap_size()
+}
+
+fn component_size(id: &str, component: &ProtocolComponent) -> usize {
+    let contracts: usize = component
+        .contract_ids
+        .iter()
+        .map(|contract| mem::size_of::<Bytes>() + contract.len())
+        .sum();
+    let attributes: usize = component
+        .static_attributes
+        .iter()
+        .map(|(name, value)| mem::size_of::<(String, Bytes)>() + name.len() + value.len())
+        .sum();
+    id.len() +
+        mem::size_of::<ProtocolComponent>() +
+        component.tokens.len() * mem::size_of::<Arc<Token>>() +
+        contracts +
+        attributes
+}
+
+fn token_size(token: &Token) -> usize {
+    mem::size_of::<Token>() + token.address.len() + token.symbol.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::backtrace::BacktraceStatus;
+
+    use alloy_primitives::U256;
+    use chrono::NaiveDateTime;
+    use num_bigint::BigUint;
+    use tycho_common::models::Chain;
+
+    use super::*;
+    use crate::{
+        evm::protocol::{
+            uniswap_v2::state::UniswapV2State,
+            uniswap_v3::{enums::FeeAmount, state::UniswapV3State},
+            utils::uniswap::tick_list::TickInfo,
+        },
+        protocol::{post_processing::QuotePostProcessor, state::MockProtocolSim},
+    };
+
+    fn component(id: &str, tokens: &[&str]) -> ProtocolComponent {
+        ProtocolComponent::new(
+            Bytes::from(id),
+            "uniswap_v2".to_string(),
+            "uniswap_v2_pool".to_string(),
+            Chain::Ethereum,
+            tokens
+                .iter()
+                .map(|address| Token::new(address, 18, "T", BigUint::from(10_000u64)))
+                .collect(),
+            Vec::new(),
+            HashMap::new(),
+            Bytes::default(),
+            NaiveDateTime::default(),
+        )
+    }
+
+    #[test]
+    fn test_apply_block_updates() {
+        let (a, b, c) = ("0x01", "0x02", "0x03");
+        let mut store = PoolStore::new();
+        store.apply(BlockUpdate::new(
+            1,
+            HashMap::from([
+                ("0xaa".to_string(), Box::new(MockProtocolSim::new()) as Box<dyn ProtocolSim>),
+                ("0xbb".to_string(), Box::new(MockProtocolSim::new()) as Box<dyn ProtocolSim>),
+            ]),
+            HashMap::from([
+                ("0xaa".to_string(), component("0xaa", &[a, b])),
+                ("0xbb".to_string(), component("0xbb", &[b, c])),
+            ]),
+        ));
+
+        store.apply(
+            BlockUpdate::new(2, HashMap::new(), HashMap::new()).set_removed_pairs(HashMap::from([
+                ("0xaa".to_string(), component("0xaa", &[a, b])),
+            ])),
+        );
+
+        assert_eq!(store.len(), 1);
+        assert!(store.state("0xaa").is_none());
+        assert!(store.component("0xbb").is_some());
+        assert_eq!(
+            store
+                .pair_index()
+                .pools_for_pair(&Bytes::from(a), &Bytes::from(b))
+                .count(),
+            0
+        );
+        assert!(store.token(&Bytes::from(c)).is_some());
+    }
+
+    #[test]
+    fn test_pools_for_pair() {
+        let mut store = PoolStore::new();
+        let state = || Box::new(UniswapV2State::new(U256::from(1_000u64), U256::from(1_000u64)));
+        store.insert("0xaa", component("0xaa", &["0x01", "0x02"]), state());
+        store.insert("0xbb", component("0xbb", &["0x01", "0x02", "0x03"]), state());
+
+        let pools: Vec<_> = store
+            .pools_for_pair(&Bytes::from("0x03"), &Bytes::from("0x02"))
+            .map(|(id, _)| id)
+            .collect();
+
+        assert_eq!(pools, vec!["0xbb"]);
+        assert_eq!(
+            store
+                .pools_for_pair(&Bytes::from("0x02"), &Bytes::from("0x01"))
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_quotes_are_counted() {
+        let counter = Arc::new(Mutex::new(QuoteFrequencyCounter::new()));
+        let mut store = PoolStore::new().with_quote_frequencies(counter.clone());
+        let state = UniswapV2State::new(U256::from(1_000_000u64), U256::from(2_000_000u64));
+        store.insert("0xaa", component("0xaa", &["0x01", "0x02"]), Box::new(state));
+        let token = |address| Token::new(address, 18, "T", BigUint::from(10_000u64));
+        let (token_in, token_out) = (token("0x01"), token("0x02"));
+
+        for _ in 0..2 {
+            store
+                .quote("0xaa", BigUint::from(1_000u64), &token_in, &token_out)
+                .unwrap();
+        }
+        assert!(store
+            .quote("0xbb", BigUint::from(1_000u64), &token_in, &token_out)
+            .is_err());
+
+        let counter = counter.lock().unwrap();
+        assert_eq!(counter.count("0xaa"), 2.0);
+        assert_eq!(counter.count("0xbb"), 0.0);
+    }
+
+    #[test]
+    fn test_memory_follows_pool_changes() {
+        let state = |ticks: i32| {
+            let ticks = (1..=ticks)
+                .map(|i| TickInfo::new(i * 60, 0))
+                .collect();
+            Box::new(UniswapV3State::new(0, U256::from(1u64) << 96, FeeAmount::Medium, 0, ticks))
+        };
+        let mut store = PoolStore::new();
+        let memory = store.memory();
+
+        store.insert("0xaa", component("0xaa", &["0x01", "0x02"]), state(10));
+        let one_pool = memory.approx_bytes();
+        store.insert("0xaa", component("0xaa", &["0x01", "0x02"]), state(1_000));
+        assert_eq!(memory.approx_bytes() - one_pool, 990 * mem::size_of::<TickInfo>());
+
+        store.insert("0xbb", component("0xbb", &["0x01", "0x02"]), state(10));
+        store.apply(BlockUpdate::new(2, HashMap::new(), HashMap::new()).set_removed_pairs(
+            HashMap::from([("0xaa".to_string(), component("0xaa", &["0x01", "0x02"]))]),
+        ));
+        assert_eq!(memory.approx_bytes(), one_pool);
+    }
+
+    #[test]
+    fn test_panicking_pool_is_skipped() {
+        let (a, b) = (
+            "0x0000000000000000000000000000000000000001",
+            "0x0000000000000000000000000000000000000002",
+        );
+        let token = |address| Token::new(address, 18, "T", BigUint::from(10_000u64));
+        let (token_in, token_out) = (token(a), token(b));
+        let mut panicking = MockProtocolSim::new();
+        panicking
+            .expect_get_amount_out()
+            .returning(|_, _, _| panic!("overflow"));
+        let mut store = PoolStore::new();
+        store.insert("0xaa", component("0xaa", &[a, b]), Box::new(panicking));
+        store.insert(
+            "0xbb",
+            component("0xbb", &[a, b]),
+            Box::new(UniswapV2State::new(U256::from(1_000_000u64), U256::from(2_000_000u64))),
+        );
+        let amount_in = BigUint::from(1_000u64);
+
+        let err = store
+            .quote("0xaa", amount_in.clone(), &token_in, &token_out)
+            .unwrap_err();
+        let (best, _) = store
+            .best_quote(&amount_in, &token_in, &token_out)
+            .unwrap();
+
+        let SimulationError::InternalPanic { pool_id, message, backtrace } = &err else {
+            panic!("expected a panic, got {err}");
+        };
+        assert_eq!((pool_id.as_str(), message.as_str()), ("0xaa", "overflow"));
+        assert_eq!(backtrace.status(), BacktraceStatus::Captured);
+        assert_eq!(best, "0xbb");
+    }
+
+    /// Halves the quotes of one pool.
+    #[derive(Debug)]
+    struct HalvePool(&'static str);
+
+    impl QuotePostProcessor for HalvePool {
+        fn name(&self) -> &str {
+            "halve_pool"
+        }
+
+        fn process(&self, pool: &PoolSummary, mut quote: GetAmountOutResult) -> GetAmountOutResult {
+            if pool.id == self.0 {
+                quote.amount /= 2u32;
+            }
+            quote
+        }
+    }
+
+    #[test]
+    fn test_post_processors_adjust_quotes() {
+        let (a, b) = (
+            "0x0000000000000000000000000000000000000001",
+            "0x0000000000000000000000000000000000000002",
+        );
+        let token = |address| Token::new(address, 18, "T", BigUint::from(10_000u64));
+        let (token_in, token_out) = (token(a), token(b));
+        let state = |reserve_out: u64| {
+            Box::new(UniswapV2State::new(U256::from(1_000_000u64), U256::from(reserve_out)))
+        };
+        let mut store = PoolStore::new();
+        store.insert("0xaa", component("0xaa", &[a, b]), state(2_000_000));
+        store.insert("0xbb", component("0xbb", &[a, b]), state(1_500_000));
+        let amount_in = BigUint::from(1_000u64);
+
+        let (best, raw) = store
+            .best_quote(&amount_in, &token_in, &token_out)
+            .unwrap();
+        assert_eq!(best, "0xaa");
+        assert!(raw.adjustments.is_empty());
+
+        let store = store
+            .with_post_processors(Arc::new(QuotePostProcessorChain::new().with(HalvePool("0xaa"))));
+        let quote = store
+            .quote("0xaa", amount_in.clone(), &token_in, &token_out)
+            .unwrap();
+        assert_eq!(quote.amount, &raw.amount / 2u32);
+        assert_eq!(quote.adjustments.len(), 1);
+        assert_eq!(quote.adjustments[0].processor, "halve_pool");
+        assert_eq!(quote.adjustments[0].amount_before, raw.amount);
+
+        let (best, _) = store
+            .best_quote(&amount_in, &token_in, &token_out)
+            .unwrap();
+        assert_eq!(best, "0xbb");
+    }
+}
@@ -0,0 +1,291 @@
+//! Quote post-processing
+//!
+//! Consumers often adjust raw quotes before acting on them, e.g. with safety haircuts or
+//! penalties for pools with little liquidity. A [`QuotePostProcessorChain`] applies an ordered
+//! list of such adjustments to a quote and records how much each of them took off. Chains are
+//! applied to the quotes of a [`PoolStore`](super::pool_store::PoolStore), including its best
+//! quote search, and of routes quoted with
+//! [`quote_route_with`](crate::routing::route_quote::quote_route_with).
+//!
+//! Post-processors may only ever reduce the quoted amount. A processor increasing it trips a
+//! debug assertion and is clamped to the amount it received in release builds.
+use std::fmt::Debug;
+
+use alloy_primitives::Address;
+use num_bigint::BigUint;
+use num_traits::Zero;
+
+use super::{errors::SimulationError, models::GetAmountOutResult, state::ProtocolSim};
+use crate::models::Token;
+
+const BPS_DENOMINATOR: u32 = 10_000;
+
+/// The properties of a pool post-processors can base their adjustments on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolSummary {
+    pub id: String,
+    pub protocol_system: String,
+    /// The maximum amount of the output token the pool can provide, e.g. the second element
+    /// returned by `ProtocolSim::get_limits`.
+    pub depth: BigUint,
+}
+
+impl PoolSummary {
+    pub fn new(id: &str, protocol_system: &str, depth: BigUint) -> Self {
+        Self { id: id.to_string(), protocol_system: protocol_system.to_string(), depth }
+    }
+
+    /// Summarises `state` quoted for swapping `token_in` for `token_out`, taking the depth from
+    /// its limits.
+    pub fn from_state(
+        id: &str,
+        protocol_system: &str,
+        state: &dyn ProtocolSim,
+        token_in: &Token,
+        token_out: &Token,
+    ) -> Result<Self, SimulationError> {
+        let address = |token: &Token| {
+            Address::try_from(token.address.as_ref()).map_err(|_| {
+                SimulationError::InvalidInput(
+                    format!("Invalid token address {}", token.address),
+                    None,
+                )
+            })
+        };
+        let (_, depth) = state.get_limits(address(token_in)?, address(token_out)?)?;
+        Ok(Self::new(id, protocol_system, depth))
+    }
+}
+
+/// Adjusts a raw quote, e.g. to account for router specific costs or risks.
+pub trait QuotePostProcessor: Debug + Send + Sync {
+    /// Name used to attribute adjustments to this processor.
+    fn name(&self) -> &str;
+
+    /// Returns the adjusted quote. Must not increase `quote.amount`.
+    fn process(&self, pool: &PoolSummary, quote: GetAmountOutResult) -> GetAmountOutResult;
+}
+
+/// Reduces every quote by a fixed share.
+#[derive(Debug, Clone)]
+pub struct HaircutProcessor {
+    haircut_bps: u32,
+}
+
+impl HaircutProcessor {
+    /// Creates a processor cutting `haircut_bps` basis points off every quote. Values above
+    /// 10000 are capped at 100%.
+    pub fn new(haircut_bps: u32) -> Self {
+        Self { haircut_bps: haircut_bps.min(BPS_DENOMINATOR) }
+    }
+}
+
+impl QuotePostProcessor for HaircutProcessor {
+    fn name(&self) -> &str {
+        "haircut"
+    }
+
+    fn process(&self, _pool: &PoolSummary, mut quote: GetAmountOutResult) -> GetAmountOutResult {
+        quote.amount = &quote.amount * (BPS_DENOMINATOR - self.haircut_bps) / BPS_DENOMINATOR;
+        quote
+    }
+}
+
+/// Penalises quotes proportionally to the share of the pool's depth they consume.
+///
+/// A quote taking the pool's entire depth is reduced by `max_penalty_bps`, a quote taking half
+/// of it by half of that, and so on.
+#[derive(Debug, Clone)]
+pub struct DepthPenaltyProcessor {
+    max_penalty_bps: u32,
+}
+
+impl DepthPenaltyProcessor {
+    pub fn new(max_penalty_bps: u32) -> Self {
+        Self { max_penalty_bps: max_penalty_bps.min(BPS_DENOMINATOR) }
+    }
+}
+
+impl QuotePostProcessor for DepthPenaltyProcessor {
+    fn name(&self) -> &str {
+        "depth_penalty"
+    }
+
+    fn process(&self, pool: &PoolSummary, mut quote: GetAmountOutResult) -> GetAmountOutResult {
+        if pool.depth.is_zero() {
+            quote.amount = BigUint::zero();
+            return quote;
+        }
+        let used = (&quote.amount).min(&pool.depth);
+        let penalty = &quote.amount * used * self.max_penalty_bps /
+            (&pool.depth * BigUint::from(BPS_DENOMINATOR));
+        quote.amount -= penalty;
+        quote
+    }
+}
+
+/// The amount a single post-processor took off a quote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuoteAdjustment {
+    pub processor: String,
+    pub amount_before: BigUint,
+    pub amount_after: BigUint,
+}
+
+impl QuoteAdjustment {
+    pub fn reduction(&self) -> BigUint {
+        &self.amount_before - &self.amount_after
+    }
+}
+
+/// A post-processed quote together with the contribution of each processor, in the order they
+/// were applied.
+#[derive(Debug)]
+pub struct ProcessedQuote {
+    pub quote: GetAmountOutResult,
+    pub raw_amount: BigUint,
+    pub adjustments: Vec<QuoteAdjustment>,
+}
+
+/// An ordered chain of post-processors.
+#[derive(Debug, Default)]
+pub struct QuotePostProcessorChain {
+    processors: Vec<Box<dyn QuotePostProcessor>>,
+}
+
+impl QuotePostProcessorChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a processor. Processors run in the order they were added.
+    pub fn with(mut self, processor: impl QuotePostProcessor + 'static) -> Self {
+        self.processors
+            .push(Box::new(processor));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.processors.is_empty()
+    }
+
+    /// Runs the quote through all processors.
+    pub fn apply(&self, pool: &PoolSummary, mut quote: GetAmountOutResult) -> ProcessedQuote {
+        let raw_amount = quote.amount.clone();
+        let mut adjustments = Vec::with_capacity(self.processors.len());
+        for processor in &self.processors {
+            let amount_before = quote.amount.clone();
+            quote = processor.process(pool, quote);
+            debug_assert!(
+                quote.amount <= amount_before,
+                "post-processor {} increased the quoted amount",
+                processor.name()
+            );
+            if quote.amount > amount_before {
+                quote.amount = amount_before.clone();
+            }
+            adjustments.push(QuoteAdjustment {
+                processor: processor.name().to_string(),
+                amount_before,
+                amount_after: quote.amount.clone(),
+            });
+        }
+        ProcessedQuote { quote, raw_amount, adjustments }
+    }
+
+    /// Runs the quote through all processors and reports their adjustments with it, see
+    /// [`GetAmountOutResult::adjustments`].
+    pub fn process(&self, pool: &PoolSummary, quote: GetAmountOutResult) -> GetAmountOutResult {
+        let processed = self.apply(pool, quote);
+        processed
+            .quote
+            .with_adjustments(processed.adjustments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::state::MockProtocolSim;
+
+    fn quote(amount: u64) -> GetAmountOutResult {
+        GetAmountOutResult::new(
+            BigUint::from(amount),
+            BigUint::from(100_000u64),
+            Box::new(MockProtocolSim::new()),
+        )
+    }
+
+    fn pool(depth: u64) -> PoolSummary {
+        PoolSummary::new("pool", "uniswap_v2", BigUint::from(depth))
+    }
+
+    #[derive(Debug)]
+    struct Bonus;
+
+    impl QuotePostProcessor for Bonus {
+        fn name(&self) -> &str {
+            "bonus"
+        }
+
+        fn process(
+            &self,
+            _pool: &PoolSummary,
+            mut quote: GetAmountOutResult,
+        ) -> GetAmountOutResult {
+            quote.amount += 1u32;
+            quote
+        }
+    }
+
+    #[test]
+    fn test_chain_applies_processors_in_order() {
+        let chain = QuotePostProcessorChain::new()
+            .with(HaircutProcessor::new(100))
+            .with(DepthPenaltyProcessor::new(1_000));
+
+        let res = chain.apply(&pool(100_000), quote(10_000));
+
+        // 1% haircut: 10_000 -> 9_900, then 9_900 * 9.9% * 10% penalty: 9_900 -> 9_802
+        assert_eq!(res.raw_amount, BigUint::from(10_000u64));
+        assert_eq!(res.quote.amount, BigUint::from(9_802u64));
+        assert_eq!(
+            res.adjustments,
+            vec![
+                QuoteAdjustment {
+                    processor: "haircut".to_string(),
+                    amount_before: BigUint::from(10_000u64),
+                    amount_after: BigUint::from(9_900u64),
+                },
+                QuoteAdjustment {
+                    processor: "depth_penalty".to_string(),
+                    amount_before: BigUint::from(9_900u64),
+                    amount_after: BigUint::from(9_802u64),
+                },
+            ]
+        );
+        assert_eq!(res.adjustments[1].reduction(), BigUint::from(98u64));
+    }
+
+    #[test]
+    fn test_depth_penalty_caps_at_full_depth() {
+        let chain = QuotePostProcessorChain::new().with(DepthPenaltyProcessor::new(500));
+
+        let res = chain.apply(&pool(1_000), quote(10_000));
+
+        assert_eq!(res.quote.amount, BigUint::from(9_500u64));
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, should_panic(expected = "increased the quoted amount"))]
+    fn test_increasing_processor_is_clamped() {
+        let chain = QuotePostProcessorChain::new()
+            .with(Bonus)
+            .with(HaircutProcessor::new(100));
+
+        let res = chain.apply(&pool(100_000), quote(10_000));
+
+        assert_eq!(res.adjustments[0].reduction(), BigUint::zero());
+        assert_eq!(res.quote.amount, BigUint::from(9_900u64));
+    }
+}
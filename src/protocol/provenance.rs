@@ -0,0 +1,244 @@
+//! Provenance of quotes
+//!
+//! A [`PoolStore`] with provenance enabled stamps every quote with the block, pool, state and
+//! implementation it was computed from. [`verify_provenance`] checks a stamped quote against a
+//! store later on, e.g. one restored from the same block, to prove which inputs produced it.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use alloy_primitives::{keccak256, B256};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tycho_common::Bytes;
+
+use super::{
+    models::{Backend, GetAmountOutResult},
+    pool_store::PoolStore,
+    state::ProtocolSim,
+};
+
+/// The inputs a quote was computed from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    pub block_number: u64,
+    pub block_hash: Bytes,
+    pub pool_id: String,
+    /// Hash of the pool's state, see [`state_fingerprint`]. `None` if the state has no canonical
+    /// encoding.
+    pub state_fingerprint: Option<B256>,
+    pub backend: Backend,
+    /// Version of this crate.
+    pub crate_version: String,
+    /// Unix time in seconds at which the quote was computed.
+    pub timestamp: u64,
+}
+
+impl Provenance {
+    pub fn new(
+        block_number: u64,
+        block_hash: Bytes,
+        pool_id: &str,
+        state_fingerprint: Option<B256>,
+        backend: Backend,
+    ) -> Self {
+        Self {
+            block_number,
+            block_hash,
+            pool_id: pool_id.to_string(),
+            state_fingerprint,
+            backend,
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ProvenanceError {
+    #[error("Quote has no provenance")]
+    Missing,
+    #[error(
+        "Quote is from block {quoted} ({quoted_hash}), the store is at {store} ({store_hash})"
+    )]
+    BlockMismatch { quoted: u64, quoted_hash: Bytes, store: u64, store_hash: Bytes },
+    #[error("Pool {0} is not in the store")]
+    UnknownPool(String),
+    #[error("State of pool {pool_id} has no canonical encoding to verify it with")]
+    Unverifiable { pool_id: String },
+    #[error("State of pool {pool_id} differs from the quoted state")]
+    StateMismatch { pool_id: String },
+    #[error("Pool {pool_id} was quoted with {quoted:?}, the store simulates it with {store:?}")]
+    BackendMismatch { pool_id: String, quoted: Backend, store: Backend },
+}
+
+/// Hash of the state's [`ProtocolSim::dump`], if it has one.
+pub fn state_fingerprint(state: &dyn ProtocolSim) -> Option<B256> {
+    state.dump().map(keccak256)
+}
+
+/// Checks that `quote` was computed from the block and pool state held by `store`.
+pub fn verify_provenance(
+    quote: &GetAmountOutResult,
+    store: &PoolStore,
+) -> Result<(), ProvenanceError> {
+    let provenance = quote
+        .provenance
+        .as_ref()
+        .ok_or(ProvenanceError::Missing)?;
+    if provenance.block_number != store.block_number() ||
+        &provenance.block_hash != store.block_hash()
+    {
+        return Err(ProvenanceError::BlockMismatch {
+            quoted: provenance.block_number,
+            quoted_hash: provenance.block_hash.clone(),
+            store: store.block_number(),
+            store_hash: store.block_hash().clone(),
+        });
+    }
+    let pool_id = &provenance.pool_id;
+    let state = store
+        .state(pool_id)
+        .ok_or_else(|| ProvenanceError::UnknownPool(pool_id.clone()))?;
+    if state.backend() != provenance.backend {
+        return Err(ProvenanceError::BackendMismatch {
+            pool_id: pool_id.clone(),
+            quoted: provenance.backend,
+            store: state.backend(),
+        });
+    }
+    let Some(fingerprint) = provenance.state_fingerprint else {
+        return Err(ProvenanceError::Unverifiable { pool_id: pool_id.clone() });
+    };
+    if store.state_fingerprint(pool_id) != Some(fingerprint) {
+        return Err(ProvenanceError::StateMismatch { pool_id: pool_id.clone() });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use alloy_primitives::U256;
+    use num_bigint::BigUint;
+
+    use super::*;
+    use crate::{
+        evm::protocol::uniswap_v2::state::UniswapV2State, models::Token,
+        protocol::models::BlockUpdate,
+    };
+
+    const POOL: &str = "0xaa";
+
+    fn pool(reserve: u64) -> Box<dyn ProtocolSim> {
+        Box::new(UniswapV2State::new(U256::from(reserve), U256::from(2_000_000u64)))
+    }
+
+    fn store_at(block_number: u64, reserve: u64, provenance: bool) -> PoolStore {
+        let mut store = PoolStore::new().with_provenance(provenance);
+        store.apply(
+            BlockUpdate::new(
+                block_number,
+                HashMap::from([(POOL.to_string(), pool(reserve))]),
+                HashMap::new(),
+            )
+            .set_block_hash(Bytes::from(block_number.to_be_bytes().to_vec())),
+        );
+        store
+    }
+
+    fn quote(store: &PoolStore) -> GetAmountOutResult {
+        let token = |address| Token::new(address, 18, "T", BigUint::from(10_000u64));
+        store
+            .quote(
+                POOL,
+                BigUint::from(1_000u64),
+                &token("0x0000000000000000000000000000000000000001"),
+                &token("0x0000000000000000000000000000000000000002"),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_state_fingerprint() {
+        let fingerprint = state_fingerprint(pool(1_000_000).as_ref());
+
+        assert!(fingerprint.is_some());
+        assert_eq!(state_fingerprint(pool(1_000_000).as_ref()), fingerprint);
+        assert_ne!(state_fingerprint(pool(1_000_001).as_ref()), fingerprint);
+    }
+
+    #[test]
+    fn test_store_fingerprint_changes_with_state() {
+        let mut store = store_at(1, 1_000_000, true);
+        let before = store.state_fingerprint(POOL).unwrap();
+        assert_eq!(store.state_fingerprint(POOL), Some(before));
+
+        store.apply(BlockUpdate::new(
+            2,
+            HashMap::from([(POOL.to_string(), pool(1_000_001))]),
+            HashMap::new(),
+        ));
+
+        assert_ne!(store.state_fingerprint(POOL), Some(before));
+        assert_eq!(store.state_fingerprint(POOL), state_fingerprint(pool(1_000_001).as_ref()));
+    }
+
+    #[test]
+    fn test_quote_provenance() {
+        let provenance = quote(&store_at(1, 1_000_000, true))
+            .provenance
+            .unwrap();
+
+        assert_eq!(provenance.block_number, 1);
+        assert_eq!(provenance.pool_id, POOL);
+        assert_eq!(provenance.state_fingerprint, state_fingerprint(pool(1_000_000).as_ref()));
+        assert_eq!(provenance.backend, Backend::Native);
+        assert_eq!(provenance.crate_version, env!("CARGO_PKG_VERSION"));
+        assert!(quote(&store_at(1, 1_000_000, false))
+            .provenance
+            .is_none());
+    }
+
+    #[test]
+    fn test_verify_provenance() {
+        let quoted = quote(&store_at(1, 1_000_000, true));
+        let json = serde_json::to_string(&quoted.provenance).unwrap();
+        let persisted = GetAmountOutResult::new(quoted.amount, quoted.gas, quoted.new_state)
+            .with_provenance(serde_json::from_str(&json).unwrap());
+
+        assert_eq!(verify_provenance(&persisted, &store_at(1, 1_000_000, false)), Ok(()));
+        assert_eq!(
+            verify_provenance(&persisted, &store_at(1, 1_000_001, false)),
+            Err(ProvenanceError::StateMismatch { pool_id: POOL.to_string() })
+        );
+        assert!(matches!(
+            verify_provenance(&persisted, &store_at(2, 1_000_000, false)),
+            Err(ProvenanceError::BlockMismatch { quoted: 1, store: 2, .. })
+        ));
+        assert_eq!(
+            verify_provenance(&persisted, &PoolStore::new()),
+            Err(ProvenanceError::BlockMismatch {
+                quoted: 1,
+                quoted_hash: Bytes::from(1u64.to_be_bytes().to_vec()),
+                store: 0,
+                store_hash: Bytes::default(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_verify_provenance_without_fingerprint() {
+        let store = store_at(1, 1_000_000, false);
+        let quoted = quote(&store);
+        let unverifiable =
+            Provenance::new(1, store.block_hash().clone(), POOL, None, Backend::Native);
+
+        assert_eq!(
+            verify_provenance(&quoted.with_provenance(unverifiable), &store),
+            Err(ProvenanceError::Unverifiable { pool_id: POOL.to_string() })
+        );
+    }
+}
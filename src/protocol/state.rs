@@ -8,6 +8,8 @@
 //!  - `fee`: Returns the protocol's fee as a ratio.
 //!  - `spot_price`: Returns the current spot price between two tokens.
 //!  - `get_amount_out`: Returns the amount of output tokens given an amount of input tokens.
+//!  - `max_input`: Returns the largest input amount `get_amount_out` accepts.
+//!  - `backend`: Returns whether the state is simulated natively or in the VM.
 //!  - `delta_transition`: Applies a state delta to the simulated protocol.
 //!  - `clone_box`: Clones the simulated protocol state as a trait object.
 //!  - `as_any`: Allows downcasting of the trait object.
@@ -44,22 +46,26 @@
 //! assert_eq!(state.spot_price(&weth, &usdc).unwrap(), 1218.0683462769755f64);
 //! assert_eq!(out, 1214374202.to_biguint().unwrap());
 //! ```
-use std::{any::Any, collections::HashMap};
+use std::{any::Any, collections::HashMap, sync::Arc};
 
-use alloy_primitives::Address;
+use alloy_primitives::{Address, U256};
 #[cfg(test)]
 use mockall::mock;
 use num_bigint::BigUint;
 use tycho_common::{dto::ProtocolStateDelta, Bytes};
 
 use crate::{
+    evm::clock::ClockSource,
     models::{Balances, Token},
     protocol::{
         errors::{SimulationError, TransitionError},
-        models::GetAmountOutResult,
+        models::{Backend, GetAmountOutResult},
     },
 };
 
+/// Largest power of ten probed by the default `ProtocolSim::max_input`.
+pub const MAX_INPUT_PROBE_EXPONENT: u32 = 60;
+
 /// ProtocolSim trait
 /// This trait defines the methods that a protocol state must implement in order to be used
 /// in the trade simulation.
@@ -138,6 +144,61 @@ pub trait ProtocolSim: std::fmt::Debug + Send + Sync + 'static {
         buy_token: Address,
     ) -> Result<(BigUint, BigUint), SimulationError>;
 
+    /// Returns the largest input amount that can be swapped from `token_in` to `token_out`.
+    ///
+    /// The returned amount is always safe to pass to `get_amount_out`. Native implementations
+    /// compute it from the pool's liquidity. The default implementation probes `get_amount_out`
+    /// with amounts of `10^k` for increasing `k` and returns the largest one that succeeds, so the
+    /// actual capacity lies within a factor of 10 above the result.
+    ///
+    /// # Errors
+    ///
+    /// Fails if not even the smallest amount can be swapped.
+    fn max_input(&self, token_in: &Token, token_out: &Token) -> Result<U256, SimulationError> {
+        let mut max_input = None;
+        for exponent in 0..=MAX_INPUT_PROBE_EXPONENT {
+            let amount = BigUint::from(10u8).pow(exponent);
+            if self
+                .get_amount_out(amount.clone(), token_in, token_out)
+                .is_err()
+            {
+                break;
+            }
+            max_input = Some(U256::from_be_slice(&amount.to_bytes_be()));
+        }
+        max_input.ok_or_else(|| {
+            SimulationError::RecoverableError(format!(
+                "No amount of {} can be swapped for {}",
+                token_in.symbol, token_out.symbol
+            ))
+        })
+    }
+
+    /// The implementation simulating this state, [`Backend::Native`] unless overridden.
+    fn backend(&self) -> Backend {
+        Backend::Native
+    }
+
+    /// A canonical encoding of the whole state, for fingerprinting it in quote provenance.
+    ///
+    /// Equal states must encode to the same bytes, independent of e.g. the iteration order of
+    /// hash maps. Defaults to `None`, for states without such an encoding, whose quotes can't be
+    /// verified.
+    fn dump(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Approximate heap memory owned by the state in bytes, e.g. by its ticks. Memory budgets
+    /// account for a state with its inline size plus this. Defaults to 0, for states without heap
+    /// data.
+    fn approx_heap_size(&self) -> usize {
+        0
+    }
+
+    /// Sets the source of the current time for states whose quotes depend on it, like oracle
+    /// pools writing a snapshot on every swap. Ignored by all other states.
+    fn set_clock(&mut self, _clock: Arc<dyn ClockSource>) {}
+
     /// Decodes and applies a protocol state delta to the state
     ///
     /// Will error if the provided delta is missing any required attributes or if any of the
@@ -260,3 +321,58 @@ impl ProtocolSim for MockProtocolSim {
         self.eq(other)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens() -> (Token, Token) {
+        (
+            Token::new("0x0000000000000000000000000000000000000001", 18, "A", BigUint::from(0u8)),
+            Token::new("0x0000000000000000000000000000000000000002", 18, "B", BigUint::from(0u8)),
+        )
+    }
+
+    /// A pool that reverts for inputs above `capacity`.
+    fn pool_with_capacity(capacity: u128) -> MockProtocolSim {
+        let mut pool = MockProtocolSim::new();
+        pool.expect_get_amount_out()
+            .returning(move |amount_in, _, _| {
+                if amount_in > BigUint::from(capacity) {
+                    return Err(SimulationError::RecoverableError("revert".to_string()));
+                }
+                Ok(GetAmountOutResult::new(
+                    amount_in,
+                    BigUint::from(0u8),
+                    Box::new(MockProtocolSim::new()),
+                ))
+            });
+        pool
+    }
+
+    #[test]
+    fn test_default_max_input_probes_powers_of_ten() {
+        let (token_a, token_b) = tokens();
+        let pool = pool_with_capacity(5 * 10u128.pow(20));
+
+        let max_input = pool
+            .max_input(&token_a, &token_b)
+            .unwrap();
+
+        assert_eq!(max_input, U256::from(10u128.pow(20)));
+        assert!(pool
+            .get_amount_out(BigUint::from(10u128.pow(20)), &token_a, &token_b)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_default_max_input_nothing_swappable() {
+        let (token_a, token_b) = tokens();
+        let pool = pool_with_capacity(0);
+
+        assert!(matches!(
+            pool.max_input(&token_a, &token_b),
+            Err(SimulationError::RecoverableError(_))
+        ));
+    }
+}
@@ -0,0 +1,276 @@
+//! Concurrent quoting of asynchronous pools.
+//!
+//! Quotes of [`AsyncProtocolSim`] pools mostly wait on IO, so they are driven concurrently on the
+//! calling task rather than spread over threads. At most `concurrency` quotes are in flight at a
+//! time, which bounds the load put on the node or API serving the pools.
+use std::panic::AssertUnwindSafe;
+
+use futures::{stream, FutureExt, StreamExt};
+use num_bigint::BigUint;
+
+use crate::{
+    models::Token,
+    protocol::{
+        async_state::AsyncProtocolSim, errors::SimulationError, models::GetAmountOutResult,
+    },
+};
+
+/// Quotes swapping `amount_in` on every pool, with at most `concurrency` quotes in flight.
+///
+/// Results are returned in the order of `pools`. A `concurrency` of 0 is treated as 1. A pool
+/// panicking fails with a [`SimulationError::InternalPanic`] carrying its index in `pools` as the
+/// pool id, the other quotes are unaffected.
+pub async fn quote_batch_async(
+    pools: &[&dyn AsyncProtocolSim],
+    amount_in: &BigUint,
+    token_in: &Token,
+    token_out: &Token,
+    concurrency: usize,
+) -> Vec<Result<GetAmountOutResult, SimulationError>> {
+    stream::iter(pools.iter().enumerate())
+        .map(|(i, pool)| async move {
+            // creating the future may run the pool's code as well, so it is caught too
+            AssertUnwindSafe(async {
+                pool.get_amount_out(amount_in.clone(), token_in, token_out)
+                    .await
+            })
+            .catch_unwind()
+            .await
+            .unwrap_or_else(|payload| {
+                Err(SimulationError::internal_panic(&i.to_string(), payload.as_ref()))
+            })
+        })
+        .buffered(concurrency.max(1))
+        .collect()
+        .await
+}
+
+/// Returns the index of the pool paying out the most for `amount_in`, along with its quote.
+///
+/// Pools failing to quote are skipped. If all of them fail, the first error is returned.
+pub async fn find_best_quote_async(
+    pools: &[&dyn AsyncProtocolSim],
+    amount_in: &BigUint,
+    token_in: &Token,
+    token_out: &Token,
+    concurrency: usize,
+) -> Result<(usize, GetAmountOutResult), SimulationError> {
+    let mut best: Option<(usize, GetAmountOutResult)> = None;
+    let mut first_error = None;
+    let quotes = quote_batch_async(pools, amount_in, token_in, token_out, concurrency).await;
+    for (i, quote) in quotes.into_iter().enumerate() {
+        match quote {
+            Ok(res) => {
+                if best
+                    .as_ref()
+                    .is_none_or(|(_, best)| res.amount > best.amount)
+                {
+                    best = Some((i, res));
+                }
+            }
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    best.ok_or_else(|| {
+        first_error
+            .unwrap_or_else(|| SimulationError::InvalidInput("No pools to quote".to_string(), None))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use alloy_primitives::{Address, U256};
+    use futures::future::BoxFuture;
+    use num_bigint::ToBigUint;
+
+    use super::*;
+    use crate::{
+        evm::protocol::uniswap_v2::state::UniswapV2State,
+        protocol::{
+            async_state::SyncAdapter,
+            state::{MockProtocolSim, ProtocolSim},
+        },
+    };
+
+    const LATENCY: Duration = Duration::from_millis(100);
+
+    /// A pool paying out `amount_in * rate` after waiting for [`LATENCY`], like a pool fetching
+    /// its state on every quote.
+    #[derive(Debug)]
+    struct SlowPool {
+        rate: u32,
+    }
+
+    impl AsyncProtocolSim for SlowPool {
+        fn fee(&self) -> f64 {
+            0.0
+        }
+
+        fn spot_price<'a>(
+            &'a self,
+            _base: &'a Token,
+            _quote: &'a Token,
+        ) -> BoxFuture<'a, Result<f64, SimulationError>> {
+            Box::pin(async move { Ok(self.rate as f64) })
+        }
+
+        fn get_amount_out<'a>(
+            &'a self,
+            amount_in: BigUint,
+            _token_in: &'a Token,
+            _token_out: &'a Token,
+        ) -> BoxFuture<'a, Result<GetAmountOutResult, SimulationError>> {
+            Box::pin(async move {
+                tokio::time::sleep(LATENCY).await;
+                if self.rate == 0 {
+                    return Err(SimulationError::RecoverableError("No liquidity".to_string()));
+                }
+                let state = UniswapV2State::new(U256::from(1u64), U256::from(1u64));
+                Ok(GetAmountOutResult::new(
+                    amount_in * self.rate,
+                    BigUint::from(50_000u64),
+                    Box::new(state),
+                ))
+            })
+        }
+
+        fn get_limits(
+            &self,
+            _sell_token: Address,
+            _buy_token: Address,
+        ) -> BoxFuture<'_, Result<(BigUint, BigUint), SimulationError>> {
+            Box::pin(async move { Ok((BigUint::from(u64::MAX), BigUint::from(u64::MAX))) })
+        }
+    }
+
+    fn tokens() -> (Token, Token) {
+        (
+            Token::new(
+                "0x0000000000000000000000000000000000000001",
+                18,
+                "A",
+                10_000.to_biguint().unwrap(),
+            ),
+            Token::new(
+                "0x0000000000000000000000000000000000000002",
+                18,
+                "B",
+                10_000.to_biguint().unwrap(),
+            ),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_quotes_run_concurrently() {
+        let pools: Vec<SlowPool> = (1..=100)
+            .map(|rate| SlowPool { rate })
+            .collect();
+        let pools: Vec<&dyn AsyncProtocolSim> = pools
+            .iter()
+            .map(|pool| pool as &dyn AsyncProtocolSim)
+            .collect();
+        let (a, b) = tokens();
+        let start = Instant::now();
+
+        let quotes = quote_batch_async(&pools, &BigUint::from(10u64), &a, &b, 100).await;
+
+        // Sequential quoting would take 100 times the latency.
+        assert!(start.elapsed() < LATENCY * 5, "took {:?}", start.elapsed());
+        let amounts: Vec<_> = quotes
+            .into_iter()
+            .map(|quote| quote.unwrap().amount)
+            .collect();
+        let expected: Vec<_> = (1..=100u32)
+            .map(|rate| BigUint::from(10u64) * rate)
+            .collect();
+        assert_eq!(amounts, expected);
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limit() {
+        let pools: Vec<SlowPool> = (1..=4)
+            .map(|rate| SlowPool { rate })
+            .collect();
+        let pools: Vec<&dyn AsyncProtocolSim> = pools
+            .iter()
+            .map(|pool| pool as &dyn AsyncProtocolSim)
+            .collect();
+        let (a, b) = tokens();
+        let start = Instant::now();
+
+        quote_batch_async(&pools, &BigUint::from(10u64), &a, &b, 2).await;
+
+        assert!(start.elapsed() >= LATENCY * 2);
+    }
+
+    #[tokio::test]
+    async fn test_panicking_pool_fails_alone() {
+        let mut panicking = MockProtocolSim::new();
+        panicking
+            .expect_get_amount_out()
+            .returning(|_, _, _| panic!("overflow"));
+        let panicking = SyncAdapter::new(panicking);
+        let pool = SlowPool { rate: 2 };
+        let (a, b) = tokens();
+
+        let quotes =
+            quote_batch_async(&[&panicking, &pool], &BigUint::from(10u64), &a, &b, 2).await;
+
+        assert!(matches!(
+            &quotes[0],
+            Err(SimulationError::InternalPanic { pool_id, message, .. })
+                if pool_id == "0" && message == "overflow"
+        ));
+        assert_eq!(quotes[1].as_ref().unwrap().amount, BigUint::from(20u64));
+    }
+
+    #[tokio::test]
+    async fn test_find_best_quote_skips_failing_pools() {
+        let pools = [SlowPool { rate: 2 }, SlowPool { rate: 0 }, SlowPool { rate: 5 }];
+        let pools: Vec<&dyn AsyncProtocolSim> = pools
+            .iter()
+            .map(|pool| pool as &dyn AsyncProtocolSim)
+            .collect();
+        let (a, b) = tokens();
+
+        let (index, res) = find_best_quote_async(&pools, &BigUint::from(10u64), &a, &b, 8)
+            .await
+            .unwrap();
+
+        assert_eq!(index, 2);
+        assert_eq!(res.amount, BigUint::from(50u64));
+    }
+
+    #[tokio::test]
+    async fn test_find_best_quote_without_quotes() {
+        let failing = SlowPool { rate: 0 };
+        let (a, b) = tokens();
+
+        let none = find_best_quote_async(&[], &BigUint::from(10u64), &a, &b, 8).await;
+        let failed = find_best_quote_async(&[&failing], &BigUint::from(10u64), &a, &b, 8).await;
+
+        assert!(matches!(none, Err(SimulationError::InvalidInput(..))));
+        assert!(matches!(failed, Err(SimulationError::RecoverableError(..))));
+    }
+
+    #[tokio::test]
+    async fn test_sync_pools_through_adapter() {
+        let state = UniswapV2State::new(U256::from(1_000_000u64), U256::from(2_000_000u64));
+        let pool = SyncAdapter::new(state.clone());
+        let (a, b) = tokens();
+
+        let (index, res) = find_best_quote_async(&[&pool], &BigUint::from(1_000u64), &a, &b, 1)
+            .await
+            .unwrap();
+
+        let expected = state
+            .get_amount_out(BigUint::from(1_000u64), &a, &b)
+            .unwrap();
+        assert_eq!(index, 0);
+        assert_eq!(res.amount, expected.amount);
+    }
+}
@@ -0,0 +1,286 @@
+//! Caching of best routes within a block.
+//!
+//! Prices move with every block, so a route found on a [`PoolGraph`] is only reused within the
+//! block it was computed at. [`RouteCache`] keys routes by token pair and amount bucket, the
+//! rounded binary logarithm of the amount, so requests for similar amounts share a route.
+use std::{
+    collections::HashMap,
+    mem,
+    sync::{Arc, Mutex},
+};
+
+use alloy_primitives::U256;
+use tycho_common::Bytes;
+
+use super::pool_graph::{PoolGraph, PoolGraphError, Route};
+use crate::memory_budget::{BudgetHandle, MemoryAccounted, MemoryBudget};
+
+/// `(token_in, token_out, amount_bucket)`
+type RouteKey = (Bytes, Bytes, u32);
+
+#[derive(Debug, Clone, PartialEq)]
+struct CachedRoute {
+    route: Route,
+    computed_at_block: u64,
+}
+
+/// The bucket of `amount`: `log2(amount)` rounded to the nearest integer, 0 for 0.
+pub fn amount_bucket(amount: U256) -> u32 {
+    if amount.is_zero() {
+        return 0;
+    }
+    amount.approx_log2().round() as u32
+}
+
+/// The cache is internally synchronized and meant to be shared behind an `Arc`.
+#[derive(Debug, Default)]
+pub struct RouteCache {
+    routes: Mutex<HashMap<RouteKey, CachedRoute>>,
+    memory: BudgetHandle,
+}
+
+impl RouteCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enforces `budget` whenever routes are added. The cache must be registered with the budget
+    /// as well to be asked to shed.
+    pub fn with_memory_budget(mut self, budget: &Arc<MemoryBudget>) -> Self {
+        self.memory = budget.handle();
+        self
+    }
+
+    /// Returns the cached route for swapping `amount_in`, unless it was computed before
+    /// `current_block`.
+    pub fn get(
+        &self,
+        token_in: &Bytes,
+        token_out: &Bytes,
+        amount_in: U256,
+        current_block: u64,
+    ) -> Option<Route> {
+        self.routes
+            .lock()
+            .unwrap()
+            .get(&(token_in.clone(), token_out.clone(), amount_bucket(amount_in)))
+            .filter(|cached| current_block <= cached.computed_at_block)
+            .map(|cached| cached.route.clone())
+    }
+
+    pub fn insert(&self, amount_in: U256, route: Route, computed_at_block: u64) {
+        insert_route(&mut self.routes.lock().unwrap(), amount_in, route, computed_at_block);
+        self.memory.enforce();
+    }
+
+    /// Computes and caches the routes of all `common_pairs` for all `common_amounts` at `block`.
+    ///
+    /// Returns the number of routes cached; pairs without a route are skipped.
+    pub fn warm(
+        &self,
+        graph: &PoolGraph,
+        common_pairs: &[(Bytes, Bytes)],
+        common_amounts: &[U256],
+        block: u64,
+    ) -> Result<usize, PoolGraphError> {
+        let mut routes = Vec::new();
+        for (token_in, token_out) in common_pairs {
+            let Some(route) = graph.shortest_path(token_in, token_out)? else {
+                continue;
+            };
+            for amount in common_amounts {
+                routes.push((*amount, route.clone()));
+            }
+        }
+        let cached = routes.len();
+        {
+            let mut cache = self.routes.lock().unwrap();
+            for (amount, route) in routes {
+                insert_route(&mut cache, amount, route, block);
+            }
+        }
+        self.memory.enforce();
+        Ok(cached)
+    }
+
+    /// Drops all routes computed before `current_block`.
+    pub fn evict_stale(&self, current_block: u64) {
+        self.routes
+            .lock()
+            .unwrap()
+            .retain(|_, cached| current_block <= cached.computed_at_block);
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn insert_route(
+    routes: &mut HashMap<RouteKey, CachedRoute>,
+    amount_in: U256,
+    route: Route,
+    computed_at_block: u64,
+) {
+    let (Some(token_in), Some(token_out)) = (route.tokens.first(), route.tokens.last()) else {
+        return;
+    };
+    routes.insert(
+        (token_in.clone(), token_out.clone(), amount_bucket(amount_in)),
+        CachedRoute { route, computed_at_block },
+    );
+}
+
+fn route_bytes((token_in, token_out, _): &RouteKey, cached: &CachedRoute) -> usize {
+    mem::size_of::<(RouteKey, CachedRoute)>() +
+        token_in.len() +
+        token_out.len() +
+        cached
+            .route
+            .tokens
+            .iter()
+            .map(|token| mem::size_of::<Bytes>() + token.len())
+            .sum::<usize>() +
+        cached
+            .route
+            .pools
+            .iter()
+            .map(|pool| mem::size_of::<String>() + pool.len())
+            .sum::<usize>()
+}
+
+impl MemoryAccounted for RouteCache {
+    /// Approximate memory used by the cached routes.
+    fn approx_bytes(&self) -> usize {
+        self.routes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, cached)| route_bytes(key, cached))
+            .sum()
+    }
+
+    /// Drops arbitrary routes until `target` bytes are freed.
+    fn shed(&self, target: usize) -> usize {
+        let mut freed = 0;
+        self.routes
+            .lock()
+            .unwrap()
+            .retain(|key, cached| {
+                if freed >= target {
+                    return true;
+                }
+                freed += route_bytes(key, cached);
+                false
+            });
+        freed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    use crate::memory_budget::ShedPriority;
+
+    fn token(i: u8) -> Bytes {
+        Bytes::from(vec![i])
+    }
+
+    fn route() -> Route {
+        Route { tokens: vec![token(0), token(1)], pools: vec!["pool".to_string()], rate: 2.0 }
+    }
+
+    #[rstest]
+    #[case::zero(0, 0)]
+    #[case::one(1, 0)]
+    #[case::below_midpoint(1_400, 10)]
+    #[case::above_midpoint(1_500, 11)]
+    #[case::power_of_two(1 << 20, 20)]
+    fn test_amount_bucket(#[case] amount: u64, #[case] bucket: u32) {
+        assert_eq!(amount_bucket(U256::from(amount)), bucket);
+    }
+
+    #[test]
+    fn test_route_expires_after_its_block() {
+        let cache = RouteCache::new();
+        cache.insert(U256::from(1_000u64), route(), 100);
+
+        assert_eq!(cache.get(&token(0), &token(1), U256::from(1_000u64), 100), Some(route()));
+        assert_eq!(cache.get(&token(0), &token(1), U256::from(1_000u64), 101), None);
+    }
+
+    #[test]
+    fn test_similar_amounts_share_route() {
+        let cache = RouteCache::new();
+        cache.insert(U256::from(1_000u64), route(), 100);
+
+        assert!(cache
+            .get(&token(0), &token(1), U256::from(1_100u64), 100)
+            .is_some());
+        assert!(cache
+            .get(&token(0), &token(1), U256::from(4_000u64), 100)
+            .is_none());
+        assert!(cache
+            .get(&token(1), &token(0), U256::from(1_000u64), 100)
+            .is_none());
+    }
+
+    #[test]
+    fn test_warm_and_evict() {
+        let mut graph = PoolGraph::new();
+        graph
+            .add_edge("pool", &token(0), &token(1), 2.0)
+            .unwrap();
+        let cache = RouteCache::new();
+
+        let cached = cache
+            .warm(
+                &graph,
+                &[(token(0), token(1)), (token(1), token(0))],
+                &[U256::from(1_000u64), U256::from(1_000_000u64)],
+                100,
+            )
+            .unwrap();
+
+        assert_eq!(cached, 2);
+        assert_eq!(cache.get(&token(0), &token(1), U256::from(1_000_000u64), 100), Some(route()));
+        cache.evict_stale(101);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_shed() {
+        let cache = RouteCache::new();
+        cache.insert(U256::from(1_000u64), route(), 100);
+        cache.insert(U256::from(1_000_000u64), route(), 100);
+        let per_route = cache.approx_bytes() / 2;
+
+        assert_eq!(cache.shed(1), per_route);
+        assert_eq!(cache.approx_bytes(), per_route);
+        assert_eq!(cache.shed(usize::MAX), per_route);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_insert_enforces_budget() {
+        let probe = RouteCache::new();
+        probe.insert(U256::from(1_000u64), route(), 100);
+        let per_route = probe.approx_bytes();
+        let memory = Arc::new(MemoryBudget::new(per_route));
+        let cache = Arc::new(RouteCache::new().with_memory_budget(&memory));
+        memory.register("routes", ShedPriority::Quotes, cache.clone());
+
+        cache.insert(U256::from(1_000u64), route(), 100);
+        assert_eq!(cache.len(), 1);
+        cache.insert(U256::from(1_000_000u64), route(), 100);
+
+        assert_eq!(cache.len(), 1);
+        assert_eq!(memory.usage(), per_route);
+    }
+}
@@ -0,0 +1,400 @@
+//! Detection of arbitrage cycles over the tracked pools.
+//!
+//! Cycles starting and ending at a token are enumerated over the pair index of a [`PoolStore`]
+//! and simulated with a probe amount. A swap never pays out more than its marginal rate, the spot
+//! price net of the pool fee, so the sum of the log marginal rates along a cycle bounds its
+//! profit from above. Paths whose bound can't reach the profit threshold are pruned before any
+//! swap is simulated, which keeps the search fast on large pool sets where most pairs are priced
+//! consistently.
+use std::collections::HashMap;
+
+use num_bigint::BigUint;
+use num_traits::{ToPrimitive, Zero};
+use tracing::debug;
+use tycho_common::Bytes;
+
+use super::route_quote::{quote_route, Hop};
+use crate::protocol::{errors::SimulationError, pool_store::PoolStore};
+
+/// Slack of the log rate bound, absorbing the imprecision of `f64` spot prices.
+const LOG_RATE_TOLERANCE: f64 = 1e-9;
+const BPS: u64 = 10_000;
+
+/// A profitable cycle, simulated with the probe amount.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CycleOpportunity {
+    /// The tokens along the cycle, starting and ending with the start token.
+    pub tokens: Vec<Bytes>,
+    /// The id of the pool used for each hop.
+    pub pools: Vec<String>,
+    pub amount_in: BigUint,
+    pub amount_out: BigUint,
+    pub gas: BigUint,
+    /// Output minus input and gas cost, in the start token.
+    pub profit: BigUint,
+    /// `profit` relative to `amount_in`, in basis points.
+    pub profit_bps: u64,
+}
+
+#[derive(Debug)]
+struct Edge {
+    to: usize,
+    pool_id: String,
+    log_rate: f64,
+}
+
+/// Tokens connected by a directed edge per pool and swap direction, weighted with the log of the
+/// marginal rate.
+#[derive(Debug, Default)]
+struct CycleGraph {
+    tokens: Vec<Bytes>,
+    index: HashMap<Bytes, usize>,
+    edges: Vec<Vec<Edge>>,
+}
+
+impl CycleGraph {
+    fn build(store: &PoolStore) -> Self {
+        let mut graph = Self::default();
+        for ((a, b), pool_ids) in store.pair_index().pairs() {
+            let (Some(token_a), Some(token_b)) = (store.token(a), store.token(b)) else {
+                continue;
+            };
+            for pool_id in pool_ids {
+                let Some(state) = store.state(pool_id) else {
+                    continue;
+                };
+                let fee_factor = 1.0 - state.fee();
+                for (token_in, token_out) in [(token_a, token_b), (token_b, token_a)] {
+                    let rate = state
+                        .spot_price(token_in, token_out)
+                        .unwrap_or(f64::NAN) *
+                        fee_factor;
+                    if !rate.is_finite() || rate <= 0.0 {
+                        continue;
+                    }
+                    let from = graph.node(&token_in.address);
+                    let to = graph.node(&token_out.address);
+                    graph.edges[from].push(Edge {
+                        to,
+                        pool_id: pool_id.clone(),
+                        log_rate: rate.ln(),
+                    });
+                }
+            }
+        }
+        // the pair index iterates in arbitrary order, keep the search deterministic
+        for edges in &mut graph.edges {
+            edges.sort_by(|a, b| {
+                graph.tokens[a.to]
+                    .cmp(&graph.tokens[b.to])
+                    .then_with(|| a.pool_id.cmp(&b.pool_id))
+            });
+        }
+        graph
+    }
+
+    fn node(&mut self, token: &Bytes) -> usize {
+        if let Some(node) = self.index.get(token) {
+            return *node;
+        }
+        self.tokens.push(token.clone());
+        self.edges.push(Vec::new());
+        self.index
+            .insert(token.clone(), self.tokens.len() - 1);
+        self.tokens.len() - 1
+    }
+}
+
+/// Depth-first enumeration of the simple cycles through the start token that pass the log rate
+/// bound.
+struct CycleSearch<'a> {
+    graph: &'a CycleGraph,
+    start: usize,
+    max_length: usize,
+    threshold: f64,
+    /// Largest log rate of any edge.
+    max_log_rate: f64,
+    /// Largest log rate of an edge back to the start token.
+    best_into_start: f64,
+    visited: Vec<bool>,
+    path: Vec<&'a Edge>,
+    candidates: Vec<Vec<&'a Edge>>,
+}
+
+impl CycleSearch<'_> {
+    fn visit(&mut self, node: usize, log_sum: f64) {
+        let graph = self.graph;
+        let hops = self.path.len() + 1;
+        for edge in &graph.edges[node] {
+            let log_sum = log_sum + edge.log_rate;
+            if edge.to == self.start {
+                if hops >= 2 && log_sum + LOG_RATE_TOLERANCE >= self.threshold {
+                    let mut cycle = self.path.clone();
+                    cycle.push(edge);
+                    self.candidates.push(cycle);
+                }
+                continue;
+            }
+            if hops >= self.max_length ||
+                self.visited[edge.to] ||
+                log_sum + self.remaining_bound(self.max_length - hops) + LOG_RATE_TOLERANCE <
+                    self.threshold
+            {
+                continue;
+            }
+            self.visited[edge.to] = true;
+            self.path.push(edge);
+            self.visit(edge.to, log_sum);
+            self.path.pop();
+            self.visited[edge.to] = false;
+        }
+    }
+
+    /// Upper bound of the log rate of at most `hops_left` further hops, the last of which returns
+    /// to the start token.
+    fn remaining_bound(&self, hops_left: usize) -> f64 {
+        self.best_into_start + self.max_log_rate.max(0.0) * (hops_left - 1) as f64
+    }
+}
+
+/// Finds the cycles from `start_token` back to itself of at most `max_length` hops that turn
+/// `probe_amount` into a profit of at least `min_profit_bps`, net of gas.
+///
+/// Tokens are visited at most once per cycle, but a pool with more than two tokens may be used by
+/// several hops. Like any route, every hop is quoted against the pool's current state.
+/// `gas_price` is the price of one unit of gas in the smallest unit of the start token, e.g. the
+/// gas price of a gas oracle converted at the current rate of the start token.
+///
+/// Returns the opportunities ordered by descending profit, ties broken by tokens and pools.
+pub fn find_cycles(
+    store: &PoolStore,
+    start_token: &Bytes,
+    max_length: usize,
+    min_profit_bps: u32,
+    probe_amount: &BigUint,
+    gas_price: &BigUint,
+) -> Vec<CycleOpportunity> {
+    let graph = CycleGraph::build(store);
+    let Some(&start) = graph.index.get(start_token) else {
+        return Vec::new();
+    };
+    let edges = graph.edges.iter().flatten();
+    let max_log_rate = edges
+        .clone()
+        .map(|edge| edge.log_rate)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let best_into_start = edges
+        .filter(|edge| edge.to == start)
+        .map(|edge| edge.log_rate)
+        .fold(f64::NEG_INFINITY, f64::max);
+    if max_length < 2 || probe_amount.is_zero() || best_into_start == f64::NEG_INFINITY {
+        return Vec::new();
+    }
+
+    let mut search = CycleSearch {
+        graph: &graph,
+        start,
+        max_length,
+        threshold: (1.0 + f64::from(min_profit_bps) / BPS as f64).ln(),
+        max_log_rate,
+        best_into_start,
+        visited: vec![false; graph.tokens.len()],
+        path: Vec::new(),
+        candidates: Vec::new(),
+    };
+    search.visited[start] = true;
+    search.visit(start, 0.0);
+    debug!(candidates = search.candidates.len(), "CycleCandidates");
+
+    let mut opportunities: Vec<_> = search
+        .candidates
+        .iter()
+        .filter_map(|cycle| {
+            let tokens: Vec<_> = std::iter::once(start_token.clone())
+                .chain(
+                    cycle
+                        .iter()
+                        .map(|edge| graph.tokens[edge.to].clone()),
+                )
+                .collect();
+            let pools: Vec<_> = cycle
+                .iter()
+                .map(|edge| edge.pool_id.clone())
+                .collect();
+            let (amount_out, gas) = simulate_cycle(store, &tokens, &pools, probe_amount)
+                .inspect_err(|err| debug!(?pools, %err, "CycleSimulationFailed"))
+                .ok()?;
+            let cost = probe_amount + &gas * gas_price;
+            if amount_out <= cost {
+                return None;
+            }
+            let profit = &amount_out - cost;
+            let profit_bps = (&profit * BPS / probe_amount)
+                .to_u64()
+                .unwrap_or(u64::MAX);
+            (profit_bps >= u64::from(min_profit_bps)).then(|| CycleOpportunity {
+                tokens,
+                pools,
+                amount_in: probe_amount.clone(),
+                amount_out,
+                gas,
+                profit,
+                profit_bps,
+            })
+        })
+        .collect();
+    opportunities.sort_by(|a, b| {
+        b.profit
+            .cmp(&a.profit)
+            .then_with(|| a.tokens.cmp(&b.tokens))
+            .then_with(|| a.pools.cmp(&b.pools))
+    });
+    opportunities
+}
+
+/// Quotes `amount_in` along the cycle with [`quote_route`]. Returns the amount out and the gas
+/// used.
+fn simulate_cycle(
+    store: &PoolStore,
+    tokens: &[Bytes],
+    pools: &[String],
+    amount_in: &BigUint,
+) -> Result<(BigUint, BigUint), SimulationError> {
+    let token = |address: &Bytes| {
+        store
+            .token(address)
+            .ok_or_else(|| SimulationError::InvalidInput(format!("Unknown token {address}"), None))
+    };
+    let hops = pools
+        .iter()
+        .zip(tokens.windows(2))
+        .map(|(pool_id, pair)| {
+            let unknown_pool =
+                || SimulationError::InvalidInput(format!("Unknown pool {pool_id}"), None);
+            let pool = store
+                .state(pool_id)
+                .ok_or_else(unknown_pool)?;
+            let protocol_system = &store
+                .component(pool_id)
+                .ok_or_else(unknown_pool)?
+                .protocol_system;
+            Ok(Hop {
+                id: pool_id,
+                protocol_system,
+                pool,
+                token_in: token(&pair[0])?,
+                token_out: token(&pair[1])?,
+            })
+        })
+        .collect::<Result<Vec<_>, SimulationError>>()?;
+    let quote = quote_route(&hops, amount_in.clone())?;
+    Ok((quote.amount_out, quote.gas))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use alloy_primitives::U256;
+    use chrono::NaiveDateTime;
+    use num_bigint::ToBigUint;
+    use tycho_common::models::Chain;
+
+    use super::*;
+    use crate::{
+        evm::protocol::uniswap_v2::state::UniswapV2State, models::Token,
+        protocol::models::ProtocolComponent,
+    };
+
+    fn token(i: u64) -> Token {
+        Token::new(&format!("{i:#042x}"), 18, &format!("T{i}"), 10_000.to_biguint().unwrap())
+    }
+
+    fn e18(amount: u64) -> BigUint {
+        BigUint::from(amount) * BigUint::from(10u64).pow(18)
+    }
+
+    /// Adds a uniswap v2 pool of tokens `a` and `b`, with `reserve_a` and `reserve_b` in units of
+    /// 10^15.
+    fn add_pool(
+        store: &mut PoolStore,
+        id: &str,
+        (a, reserve_a): (u64, u64),
+        (b, reserve_b): (u64, u64),
+    ) {
+        let reserve = |units: u64| U256::from(units) * U256::from(10u64).pow(U256::from(15));
+        let (reserve0, reserve1) = if a < b {
+            (reserve(reserve_a), reserve(reserve_b))
+        } else {
+            (reserve(reserve_b), reserve(reserve_a))
+        };
+        let component = ProtocolComponent::new(
+            Bytes::from_str(id).unwrap(),
+            "uniswap_v2".to_string(),
+            "uniswap_v2_pool".to_string(),
+            Chain::Ethereum,
+            vec![token(a), token(b)],
+            Vec::new(),
+            HashMap::new(),
+            Bytes::default(),
+            NaiveDateTime::default(),
+        );
+        store.insert(id, component, Box::new(UniswapV2State::new(reserve0, reserve1)));
+    }
+
+    /// Three pools between tokens 1, 2 and 3, where token 1 is 10% cheaper in the 3/1 pool.
+    fn imbalanced_store() -> PoolStore {
+        let mut store = PoolStore::new();
+        add_pool(&mut store, "0x01", (1, 1_000_000_000), (2, 1_000_000_000));
+        add_pool(&mut store, "0x02", (2, 1_000_000_000), (3, 1_000_000_000));
+        add_pool(&mut store, "0x03", (3, 1_000_000_000), (1, 1_100_000_000));
+        store
+    }
+
+    #[test]
+    fn test_finds_imbalanced_cycle() {
+        let store = imbalanced_store();
+        let start = token(1).address;
+        let gas_price = BigUint::from(10u64).pow(12);
+
+        let opportunities = find_cycles(&store, &start, 3, 50, &e18(10), &gas_price);
+
+        assert_eq!(
+            opportunities,
+            vec![CycleOpportunity {
+                tokens: vec![start.clone(), token(2).address, token(3).address, start],
+                pools: vec!["0x01".to_string(), "0x02".to_string(), "0x03".to_string()],
+                amount_in: e18(10),
+                amount_out: BigUint::from_str("10900971632104524117").unwrap(),
+                gas: 360_000u64.into(),
+                profit: BigUint::from_str("540971632104524117").unwrap(),
+                profit_bps: 540,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_threshold_and_length_bound() {
+        let store = imbalanced_store();
+        let start = token(1).address;
+
+        assert!(find_cycles(&store, &start, 3, 1_000, &e18(10), &BigUint::zero()).is_empty());
+        assert!(find_cycles(&store, &start, 2, 0, &e18(10), &BigUint::zero()).is_empty());
+    }
+
+    #[test]
+    fn test_balanced_graph_yields_nothing() {
+        let mut store = PoolStore::new();
+        // 10k pools over 1k tokens, all priced 1:1
+        for i in 0..10_000u64 {
+            let (a, b) = (1 + i % 1_000, 1 + (i * 7 + 1) % 1_000);
+            if a != b {
+                add_pool(&mut store, &format!("{:#06x}", i + 1), (a, 1_000), (b, 1_000));
+            }
+        }
+
+        let opportunities = find_cycles(&store, &token(1).address, 4, 0, &e18(1), &BigUint::zero());
+
+        assert!(opportunities.is_empty());
+    }
+}
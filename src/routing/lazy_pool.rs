@@ -0,0 +1,289 @@
+//! Lazily loaded pool states.
+//!
+//! Building a pool graph from a registry requires a state for every pool, even though most pools
+//! are never quoted. [`LazyPool`] defers loading the state until it is first needed and caches
+//! it afterwards, so unused pools cost nothing but their id.
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use alloy_primitives::{Address, U256};
+use num_bigint::BigUint;
+use tracing::warn;
+use tycho_common::{dto::ProtocolStateDelta, Bytes};
+
+use crate::{
+    models::{Balances, Token},
+    protocol::{
+        errors::{SimulationError, TransitionError},
+        models::GetAmountOutResult,
+        state::ProtocolSim,
+    },
+};
+
+/// Loads the state of a pool on demand, e.g. by reading its storage from a `SimulationDB`.
+pub trait PoolLoader<P>: Debug + Send + Sync {
+    fn fetch_from_db(&self, id: &str) -> Result<P, SimulationError>;
+}
+
+/// A pool whose state is loaded on first use.
+///
+/// Implements `ProtocolSim` by forwarding to the loaded state. Loading happens at most once, even
+/// if several threads quote the pool concurrently or it was cloned before; failed loads are not
+/// cached and retried on the next call.
+#[derive(Debug)]
+pub struct LazyPool<P> {
+    id: String,
+    loader: Arc<dyn PoolLoader<P>>,
+    /// Shared with the clones until a delta is applied to one of them.
+    state: Arc<OnceLock<P>>,
+    loading: Arc<Mutex<()>>,
+}
+
+impl<P> Clone for LazyPool<P> {
+    fn clone(&self) -> Self {
+        Self {
+            id: self.id.clone(),
+            loader: self.loader.clone(),
+            state: self.state.clone(),
+            loading: self.loading.clone(),
+        }
+    }
+}
+
+impl<P> LazyPool<P> {
+    pub fn new(id: &str, loader: Arc<dyn PoolLoader<P>>) -> Self {
+        Self { id: id.to_string(), loader, state: Arc::default(), loading: Arc::default() }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.state.get().is_some()
+    }
+
+    /// Returns the state, loading it if this is the first access.
+    pub fn state(&self) -> Result<&P, SimulationError> {
+        if let Some(state) = self.state.get() {
+            return Ok(state);
+        }
+        let _guard = self
+            .loading
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(state) = self.state.get() {
+            return Ok(state);
+        }
+        let state = self.loader.fetch_from_db(&self.id)?;
+        Ok(self.state.get_or_init(|| state))
+    }
+}
+
+impl<P: ProtocolSim + Clone> ProtocolSim for LazyPool<P> {
+    /// The fee of the loaded state. `NaN` if the state can't be loaded.
+    fn fee(&self) -> f64 {
+        match self.state() {
+            Ok(state) => state.fee(),
+            Err(err) => {
+                warn!(pool = self.id, ?err, "Failed to load pool state");
+                f64::NAN
+            }
+        }
+    }
+
+    fn spot_price(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
+        self.state()?.spot_price(base, quote)
+    }
+
+    fn get_amount_out(
+        &self,
+        amount_in: BigUint,
+        token_in: &Token,
+        token_out: &Token,
+    ) -> Result<GetAmountOutResult, SimulationError> {
+        self.state()?
+            .get_amount_out(amount_in, token_in, token_out)
+    }
+
+    fn max_input(&self, token_in: &Token, token_out: &Token) -> Result<U256, SimulationError> {
+        self.state()?
+            .max_input(token_in, token_out)
+    }
+
+    fn get_limits(
+        &self,
+        sell_token: Address,
+        buy_token: Address,
+    ) -> Result<(BigUint, BigUint), SimulationError> {
+        self.state()?
+            .get_limits(sell_token, buy_token)
+    }
+
+    /// Applies the delta to the loaded state, which stops sharing it with the clones. Deltas for
+    /// pools that are not loaded yet are dropped, since loading reads the then current state
+    /// anyway.
+    fn delta_transition(
+        &mut self,
+        delta: ProtocolStateDelta,
+        tokens: &HashMap<Bytes, Token>,
+        balances: &Balances,
+    ) -> Result<(), TransitionError<String>> {
+        if !self.is_loaded() {
+            return Ok(());
+        }
+        match Arc::make_mut(&mut self.state).get_mut() {
+            Some(state) => state.delta_transition(delta, tokens, balances),
+            None => Ok(()),
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn ProtocolSim> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn eq(&self, other: &dyn ProtocolSim) -> bool {
+        let Some(other) = other
+            .as_any()
+            .downcast_ref::<LazyPool<P>>()
+        else {
+            return false;
+        };
+        match (self.state.get(), other.state.get()) {
+            (Some(state), Some(other_state)) => state.eq(other_state),
+            (None, None) => self.id == other.id,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "evm"))]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::evm::protocol::uniswap_v2::state::UniswapV2State;
+
+    #[derive(Debug, Default)]
+    struct CountingLoader {
+        fetches: AtomicUsize,
+        fail: bool,
+    }
+
+    impl PoolLoader<UniswapV2State> for CountingLoader {
+        fn fetch_from_db(&self, id: &str) -> Result<UniswapV2State, SimulationError> {
+            self.fetches
+                .fetch_add(1, Ordering::SeqCst);
+            if self.fail {
+                return Err(SimulationError::RecoverableError(format!("{id} not found")));
+            }
+            Ok(UniswapV2State::new(U256::from(1_000_000u64), U256::from(2_000_000u64)))
+        }
+    }
+
+    fn tokens() -> (Token, Token) {
+        (
+            Token::new("0x0000000000000000000000000000000000000001", 18, "A", BigUint::from(0u8)),
+            Token::new("0x0000000000000000000000000000000000000002", 18, "B", BigUint::from(0u8)),
+        )
+    }
+
+    #[test]
+    fn test_loads_once_regardless_of_quote_count() {
+        let loader = Arc::new(CountingLoader::default());
+        let pools: Vec<_> = ["pool_a", "pool_b"]
+            .into_iter()
+            .map(|id| LazyPool::new(id, loader.clone() as Arc<dyn PoolLoader<UniswapV2State>>))
+            .collect();
+        let (token_a, token_b) = tokens();
+
+        assert_eq!(loader.fetches.load(Ordering::SeqCst), 0);
+        for _ in 0..10 {
+            for pool in &pools {
+                pool.get_amount_out(BigUint::from(1_000u32), &token_a, &token_b)
+                    .unwrap();
+            }
+        }
+
+        assert_eq!(loader.fetches.load(Ordering::SeqCst), 2);
+        assert!(pools.iter().all(LazyPool::is_loaded));
+    }
+
+    #[test]
+    fn test_unused_pool_is_never_loaded() {
+        let loader = Arc::new(CountingLoader::default());
+
+        let pool = LazyPool::new("pool", loader.clone() as Arc<dyn PoolLoader<UniswapV2State>>);
+        let _ = pool.clone_box();
+
+        assert!(!pool.is_loaded());
+        assert_eq!(loader.fetches.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_clones_share_the_loaded_state() {
+        let loader = Arc::new(CountingLoader::default());
+        let pool = LazyPool::new("pool", loader.clone() as Arc<dyn PoolLoader<UniswapV2State>>);
+        let clone = pool.clone();
+        let (token_a, token_b) = tokens();
+
+        clone
+            .get_amount_out(BigUint::from(1_000u32), &token_a, &token_b)
+            .unwrap();
+        pool.get_amount_out(BigUint::from(1_000u32), &token_a, &token_b)
+            .unwrap();
+
+        assert!(pool.is_loaded());
+        assert_eq!(loader.fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_delta_is_not_applied_to_clones() {
+        let loader = Arc::new(CountingLoader::default());
+        let mut pool = LazyPool::new("pool", loader.clone() as Arc<dyn PoolLoader<UniswapV2State>>);
+        pool.state().unwrap();
+        let clone = pool.clone();
+        let delta = ProtocolStateDelta {
+            component_id: "pool".to_string(),
+            updated_attributes: HashMap::from([
+                ("reserve0".to_string(), Bytes::from(U256::from(1u64).to_be_bytes_vec())),
+                ("reserve1".to_string(), Bytes::from(U256::from(2u64).to_be_bytes_vec())),
+            ]),
+            deleted_attributes: Default::default(),
+        };
+
+        pool.delta_transition(delta, &HashMap::new(), &Balances::default())
+            .unwrap();
+
+        assert_eq!(pool.state().unwrap().reserve0, U256::from(1u64));
+        assert_eq!(clone.state().unwrap().reserve0, U256::from(1_000_000u64));
+        assert_eq!(loader.fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_failed_load_is_retried() {
+        let loader = Arc::new(CountingLoader { fail: true, ..Default::default() });
+        let pool = LazyPool::new("pool", loader.clone() as Arc<dyn PoolLoader<UniswapV2State>>);
+        let (token_a, token_b) = tokens();
+
+        for _ in 0..2 {
+            let res = pool.get_amount_out(BigUint::from(1_000u32), &token_a, &token_b);
+            assert!(matches!(res, Err(SimulationError::RecoverableError(_))));
+        }
+
+        assert_eq!(loader.fetches.load(Ordering::SeqCst), 2);
+        assert!(!pool.is_loaded());
+    }
+}
@@ -0,0 +1,8 @@
+//! Pool selection and routing helpers.
+pub mod async_quote;
+pub mod cache;
+pub mod cycles;
+pub mod lazy_pool;
+pub mod pool_graph;
+pub mod route_quote;
+pub mod volatility;
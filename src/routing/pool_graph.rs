@@ -0,0 +1,512 @@
+//! Token graph for route finding.
+//!
+//! Tokens are nodes and every pool contributes a directed edge per tradable direction. An edge is
+//! weighted with the negative logarithm of its exchange rate, so the path with the lowest total
+//! weight is the one with the highest product of rates.
+//!
+//! Rates above 1 yield negative weights, which Dijkstra's algorithm can't handle on its own. The
+//! weights are therefore reweighted with Bellman-Ford potentials first (Johnson's technique),
+//! which keeps shortest paths intact as long as the graph has no arbitrage cycle. The potentials
+//! are computed once and reused until the graph changes. If the graph has an arbitrage cycle, they
+//! are computed per query for the tokens reachable from the sold token instead, so the cycle only
+//! fails the queries that can reach it.
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, VecDeque},
+    sync::OnceLock,
+};
+
+use num_bigint::BigUint;
+use thiserror::Error;
+use tycho_common::Bytes;
+
+use crate::{models::Token, protocol::state::ProtocolSim, token_registry::TokenRegistry};
+
+/// Weight differences below this are considered float noise.
+const WEIGHT_TOLERANCE: f64 = 1e-12;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum PoolGraphError {
+    #[error("Invalid exchange rate {rate} for pool {pool_id}")]
+    InvalidRate { pool_id: String, rate: f64 },
+    #[error("The graph contains an arbitrage cycle through {0:?}")]
+    ArbitrageCycle(Vec<Bytes>),
+}
+
+#[derive(Debug, Clone)]
+struct Edge {
+    to: usize,
+    pool_id: String,
+    rate: f64,
+    weight: f64,
+}
+
+/// The best route between two tokens.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    /// The tokens along the route, starting with the sold and ending with the bought token.
+    pub tokens: Vec<Bytes>,
+    /// The id of the pool used for each hop.
+    pub pools: Vec<String>,
+    /// The product of the exchange rates of all hops.
+    pub rate: f64,
+}
+
+impl Route {
+    /// Gas of transferring every token of the route once, with the gas currently registered for
+    /// each token. `None` if a token isn't registered.
+    pub fn transfer_gas(&self, registry: &TokenRegistry) -> Option<BigUint> {
+        self.tokens
+            .iter()
+            .map(|address| {
+                registry
+                    .by_address(address)
+                    .map(|token| token.gas.clone())
+            })
+            .sum()
+    }
+}
+
+/// A directed graph of tokens connected by pools.
+#[derive(Debug, Clone, Default)]
+pub struct PoolGraph {
+    tokens: Vec<Bytes>,
+    index: HashMap<Bytes, usize>,
+    edges: Vec<Vec<Edge>>,
+    /// Potentials of all tokens, `None` if the graph has an arbitrage cycle.
+    cached_potentials: OnceLock<Option<Vec<f64>>>,
+}
+
+impl PoolGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn node(&mut self, token: &Bytes) -> usize {
+        if let Some(node) = self.index.get(token) {
+            return *node;
+        }
+        self.tokens.push(token.clone());
+        self.edges.push(Vec::new());
+        self.index
+            .insert(token.clone(), self.tokens.len() - 1);
+        self.tokens.len() - 1
+    }
+
+    /// Adds a directed edge for swapping `token_in` to `token_out` on pool `pool_id`.
+    ///
+    /// `rate` is the amount of `token_out` received per unit of `token_in`, fees included.
+    pub fn add_edge(
+        &mut self,
+        pool_id: &str,
+        token_in: &Bytes,
+        token_out: &Bytes,
+        rate: f64,
+    ) -> Result<(), PoolGraphError> {
+        if !rate.is_finite() || rate <= 0.0 {
+            return Err(PoolGraphError::InvalidRate { pool_id: pool_id.to_string(), rate });
+        }
+        let from = self.node(token_in);
+        let to = self.node(token_out);
+        self.edges[from].push(Edge { to, pool_id: pool_id.to_string(), rate, weight: -rate.ln() });
+        self.cached_potentials = OnceLock::new();
+        Ok(())
+    }
+
+    /// Adds edges in both directions for every token pair of a pool, using its spot price net of
+    /// the pool fee as rate.
+    ///
+    /// Either all edges of the pool are added or, if any of its rates is invalid, none.
+    pub fn add_pool(
+        &mut self,
+        pool_id: &str,
+        state: &dyn ProtocolSim,
+        tokens: &[Token],
+    ) -> Result<(), PoolGraphError> {
+        let fee_factor = 1.0 - state.fee();
+        let mut edges = Vec::new();
+        for token_in in tokens {
+            for token_out in tokens {
+                if token_in == token_out {
+                    continue;
+                }
+                let rate = state
+                    .spot_price(token_in, token_out)
+                    .unwrap_or(f64::NAN) *
+                    fee_factor;
+                if !rate.is_finite() || rate <= 0.0 {
+                    return Err(PoolGraphError::InvalidRate { pool_id: pool_id.to_string(), rate });
+                }
+                edges.push((&token_in.address, &token_out.address, rate));
+            }
+        }
+        for (token_in, token_out, rate) in edges {
+            self.add_edge(pool_id, token_in, token_out, rate)?;
+        }
+        Ok(())
+    }
+
+    /// Number of tokens in the graph.
+    pub fn n_tokens(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// All edges as `(pool_id, token_in, token_out, rate)`.
+    pub fn edges(&self) -> impl Iterator<Item = (&str, &Bytes, &Bytes, f64)> {
+        self.edges
+            .iter()
+            .enumerate()
+            .flat_map(move |(from, edges)| {
+                edges.iter().map(move |edge| {
+                    (edge.pool_id.as_str(), &self.tokens[from], &self.tokens[edge.to], edge.rate)
+                })
+            })
+    }
+
+    /// Returns the route from `from` to `to` with the highest product of exchange rates, or
+    /// `None` if `to` can't be reached.
+    ///
+    /// # Errors
+    ///
+    /// Fails if an arbitrage cycle is reachable from `from`, since the best rate is unbounded then.
+    pub fn shortest_path(&self, from: &Bytes, to: &Bytes) -> Result<Option<Route>, PoolGraphError> {
+        let (Some(&source), Some(&target)) = (self.index.get(from), self.index.get(to)) else {
+            return Ok(None);
+        };
+        let cached = self.cached_potentials.get_or_init(|| {
+            self.potentials(&vec![true; self.tokens.len()])
+                .ok()
+        });
+        let potentials = match cached {
+            Some(potentials) => Cow::Borrowed(potentials),
+            None => Cow::Owned(self.potentials(&self.reachable(source))?),
+        };
+
+        // Dijkstra on the reweighted edges `w(u, v) + h(u) - h(v)`, which are non-negative.
+        let mut distances = vec![f64::INFINITY; self.tokens.len()];
+        let mut previous: Vec<Option<(usize, usize)>> = vec![None; self.tokens.len()];
+        let mut heap = BinaryHeap::new();
+        distances[source] = 0.0;
+        heap.push(Candidate { distance: 0.0, node: source });
+        while let Some(Candidate { distance, node }) = heap.pop() {
+            if node == target {
+                break;
+            }
+            if distance > distances[node] {
+                continue;
+            }
+            for (i, edge) in self.edges[node].iter().enumerate() {
+                let reduced = (edge.weight + potentials[node] - potentials[edge.to]).max(0.0);
+                let next = distance + reduced;
+                if next < distances[edge.to] {
+                    distances[edge.to] = next;
+                    previous[edge.to] = Some((node, i));
+                    heap.push(Candidate { distance: next, node: edge.to });
+                }
+            }
+        }
+        if distances[target].is_infinite() {
+            return Ok(None);
+        }
+
+        let mut hops = Vec::new();
+        let mut node = target;
+        while let Some((prev, i)) = previous[node] {
+            hops.push(&self.edges[prev][i]);
+            node = prev;
+        }
+        hops.reverse();
+        let mut tokens = vec![from.clone()];
+        tokens.extend(
+            hops.iter()
+                .map(|edge| self.tokens[edge.to].clone()),
+        );
+        Ok(Some(Route {
+            tokens,
+            pools: hops
+                .iter()
+                .map(|edge| edge.pool_id.clone())
+                .collect(),
+            rate: hops
+                .iter()
+                .map(|edge| edge.rate)
+                .product(),
+        }))
+    }
+
+    /// The tokens reachable from `source`, as a mask over all tokens.
+    fn reachable(&self, source: usize) -> Vec<bool> {
+        let mut reachable = vec![false; self.tokens.len()];
+        reachable[source] = true;
+        let mut queue = VecDeque::from([source]);
+        while let Some(node) = queue.pop_front() {
+            for edge in &self.edges[node] {
+                if !reachable[edge.to] {
+                    reachable[edge.to] = true;
+                    queue.push_back(edge.to);
+                }
+            }
+        }
+        reachable
+    }
+
+    /// Bellman-Ford potentials from a virtual source connected to every `included` token, such
+    /// that `w(u, v) + h(u) - h(v) >= 0` for every edge between them. `included` must contain
+    /// every token reachable from an included token.
+    fn potentials(&self, included: &[bool]) -> Result<Vec<f64>, PoolGraphError> {
+        let mut potentials = vec![0.0; self.tokens.len()];
+        let edges = || {
+            self.edges
+                .iter()
+                .enumerate()
+                .filter(|(node, _)| included[*node])
+        };
+        if edges()
+            .flat_map(|(_, edges)| edges)
+            .all(|edge| edge.weight >= 0.0)
+        {
+            return Ok(potentials);
+        }
+        let mut predecessor = vec![None; self.tokens.len()];
+        let mut last_updated = None;
+        // Every token is one edge away from the virtual source, so |V| rounds suffice for
+        // convergence. An update in the round after that proves a negative cycle.
+        for _ in 0..=self.tokens.len() {
+            last_updated = None;
+            for (node, edges) in edges() {
+                for edge in edges {
+                    let candidate = potentials[node] + edge.weight;
+                    // Ignore float noise, it must not be mistaken for a cycle.
+                    if candidate < potentials[edge.to] - WEIGHT_TOLERANCE {
+                        potentials[edge.to] = candidate;
+                        predecessor[edge.to] = Some(node);
+                        last_updated = Some(edge.to);
+                    }
+                }
+            }
+            if last_updated.is_none() {
+                return Ok(potentials);
+            }
+        }
+        let mut node = last_updated.unwrap_or_default();
+        // Walking back |V| steps from a node updated in the last round ends up on the cycle.
+        for _ in 0..self.tokens.len() {
+            node = predecessor[node].unwrap_or(node);
+        }
+        Err(PoolGraphError::ArbitrageCycle(self.cycle(node, &predecessor)))
+    }
+
+    /// Follows the predecessors from `start`, a node on a cycle, around the cycle.
+    fn cycle(&self, start: usize, predecessor: &[Option<usize>]) -> Vec<Bytes> {
+        let mut cycle = vec![self.tokens[start].clone()];
+        let mut node = start;
+        while let Some(prev) = predecessor[node] {
+            if prev == start {
+                break;
+            }
+            cycle.push(self.tokens[prev].clone());
+            node = prev;
+        }
+        cycle.reverse();
+        cycle
+    }
+}
+
+/// A heap entry ordered by ascending distance.
+#[derive(Debug, PartialEq)]
+struct Candidate {
+    distance: f64,
+    node: usize,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .distance
+            .total_cmp(&self.distance)
+            .then_with(|| self.node.cmp(&other.node))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    use crate::protocol::{errors::SimulationError, state::MockProtocolSim};
+
+    fn token(i: u8) -> Bytes {
+        Bytes::from(vec![i])
+    }
+
+    /// Ten tokens with reference prices, connected by fifteen pools `(token_a, token_b, fee)`.
+    /// Rates follow the reference prices net of the pool fee, so there is no arbitrage and the
+    /// best route is the one losing the least to fees.
+    fn graph() -> PoolGraph {
+        let prices = [1.0, 2.0, 0.5, 3000.0, 1.0, 1.0, 40.0, 0.01, 7.0, 100.0];
+        let pools = [
+            (0, 1, 0.003),
+            (1, 2, 0.003),
+            (2, 9, 0.01),
+            (0, 9, 0.05),
+            (0, 3, 0.003),
+            (3, 7, 0.003),
+            (7, 9, 0.0005),
+            (3, 4, 0.0001),
+            (4, 5, 0.0001),
+            (5, 9, 0.0001),
+            (1, 6, 0.003),
+            (6, 8, 0.003),
+            (8, 9, 0.003),
+            (2, 5, 0.01),
+            (4, 8, 0.001),
+        ];
+        let mut graph = PoolGraph::new();
+        for (i, (a, b, fee)) in pools.into_iter().enumerate() {
+            let id = format!("pool_{}", i + 1);
+            let rate = prices[a] / prices[b] * (1.0 - fee);
+            graph
+                .add_edge(&id, &token(a as u8), &token(b as u8), rate)
+                .unwrap();
+            let rate = prices[b] / prices[a] * (1.0 - fee);
+            graph
+                .add_edge(&id, &token(b as u8), &token(a as u8), rate)
+                .unwrap();
+        }
+        graph
+    }
+
+    #[test]
+    fn test_shortest_path_finds_best_rate() {
+        let graph = graph();
+
+        let route = graph
+            .shortest_path(&token(0), &token(9))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(graph.n_tokens(), 10);
+        // 0 -> 3 -> 4 -> 5 -> 9 loses 0.3% + 3 * 0.01% to fees, less than any other route,
+        // e.g. 0.3% + 0.3% + 0.05% via token 7 or 5% on the direct pool.
+        assert_eq!(route.pools, vec!["pool_5", "pool_8", "pool_9", "pool_10"]);
+        assert_eq!(route.tokens, vec![token(0), token(3), token(4), token(5), token(9)]);
+        let expected = 0.01 * 0.997 * 0.9999_f64.powi(3);
+        assert!((route.rate - expected).abs() < 1e-15, "{} != {expected}", route.rate);
+    }
+
+    #[rstest]
+    #[case::unknown_token(token(0), token(42))]
+    #[case::unreachable(token(0), token(11))]
+    fn test_shortest_path_no_route(#[case] from: Bytes, #[case] to: Bytes) {
+        let mut graph = graph();
+        graph
+            .add_edge("isolated", &token(10), &token(11), 1.0)
+            .unwrap();
+
+        assert_eq!(graph.shortest_path(&from, &to), Ok(None));
+    }
+
+    #[test]
+    fn test_shortest_path_arbitrage_cycle() {
+        let mut graph = PoolGraph::new();
+        graph
+            .add_edge("a", &token(0), &token(1), 2.0)
+            .unwrap();
+        graph
+            .add_edge("b", &token(1), &token(2), 2.0)
+            .unwrap();
+        graph
+            .add_edge("c", &token(2), &token(0), 0.3)
+            .unwrap();
+
+        let res = graph.shortest_path(&token(0), &token(2));
+
+        let Err(PoolGraphError::ArbitrageCycle(cycle)) = res else {
+            panic!("expected an arbitrage cycle, got {res:?}");
+        };
+        assert_eq!(cycle.len(), 3);
+    }
+
+    #[test]
+    fn test_arbitrage_cycle_only_fails_queries_reaching_it() {
+        let mut graph = graph();
+        graph
+            .add_edge("a", &token(10), &token(11), 2.0)
+            .unwrap();
+        graph
+            .add_edge("b", &token(11), &token(10), 2.0)
+            .unwrap();
+
+        let route = graph
+            .shortest_path(&token(0), &token(9))
+            .unwrap()
+            .unwrap();
+        assert_eq!(route.pools, vec!["pool_5", "pool_8", "pool_9", "pool_10"]);
+        assert!(matches!(
+            graph.shortest_path(&token(10), &token(11)),
+            Err(PoolGraphError::ArbitrageCycle(_))
+        ));
+    }
+
+    #[test]
+    fn test_potentials_are_recomputed_after_adding_edges() {
+        let mut graph = graph();
+        assert!(graph
+            .shortest_path(&token(0), &token(9))
+            .is_ok());
+
+        graph
+            .add_edge("arbitrage", &token(9), &token(0), 1_000.0)
+            .unwrap();
+
+        assert!(matches!(
+            graph.shortest_path(&token(0), &token(9)),
+            Err(PoolGraphError::ArbitrageCycle(_))
+        ));
+    }
+
+    #[test]
+    fn test_add_pool_is_atomic() {
+        let tokens: Vec<_> = (1..=3u8)
+            .map(|i| Token::new(&format!("0x{i:040x}"), 18, "T", BigUint::from(10_000u64)))
+            .collect();
+        let mut state = MockProtocolSim::new();
+        state.expect_fee().return_const(0.0);
+        let invalid = tokens[2].clone();
+        state
+            .expect_spot_price()
+            .returning(move |_, quote| {
+                if quote == &invalid {
+                    Err(SimulationError::RecoverableError("No liquidity".to_string()))
+                } else {
+                    Ok(1.0)
+                }
+            });
+        let mut graph = PoolGraph::new();
+
+        let res = graph.add_pool("pool", &state, &tokens);
+
+        assert!(matches!(res, Err(PoolGraphError::InvalidRate { .. })));
+        assert_eq!(graph.n_tokens(), 0);
+        assert_eq!(graph.edges().count(), 0);
+    }
+
+    #[rstest]
+    #[case::zero(0.0)]
+    #[case::negative(-1.0)]
+    #[case::nan(f64::NAN)]
+    fn test_add_edge_invalid_rate(#[case] rate: f64) {
+        let res = PoolGraph::new().add_edge("pool", &token(0), &token(1), rate);
+
+        assert!(matches!(res, Err(PoolGraphError::InvalidRate { .. })));
+    }
+}
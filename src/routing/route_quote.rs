@@ -0,0 +1,251 @@
+//! Quotes along a route of several pools.
+//!
+//! Each hop receives exactly the amount the previous hop paid out. Pools round every amount
+//! against the user, so a route can never be quoted higher than its hops' marginal prices allow;
+//! a warning is logged for every hop that does, to catch pool math rounding in the output's
+//! favour.
+use num_bigint::BigUint;
+use num_traits::{ToPrimitive, Zero};
+use tracing::warn;
+
+use super::volatility::RiskScore;
+use crate::{
+    models::Token,
+    protocol::{
+        errors::{catch_panic, SimulationError},
+        post_processing::{PoolSummary, QuoteAdjustment, QuotePostProcessorChain},
+        state::ProtocolSim,
+    },
+};
+
+/// Relative slack of the marginal price bound, absorbing the imprecision of `f64` spot prices.
+const SPOT_PRICE_TOLERANCE: f64 = 1e-9;
+
+/// A swap through a single pool.
+#[derive(Debug, Clone, Copy)]
+pub struct Hop<'a> {
+    pub id: &'a str,
+    pub protocol_system: &'a str,
+    pub pool: &'a dyn ProtocolSim,
+    pub token_in: &'a Token,
+    pub token_out: &'a Token,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteQuote {
+    /// Amount received from the last hop.
+    pub amount_out: BigUint,
+    /// Amount received from each hop, in route order.
+    pub hop_amounts: Vec<BigUint>,
+    /// How much each post-processor took off each hop's quote, in route order.
+    pub hop_adjustments: Vec<Vec<QuoteAdjustment>>,
+    pub gas: BigUint,
+    /// Risk of the route reverting, if a
+    /// [`VolatilityTracker`](super::volatility::VolatilityTracker) attached it.
+    pub revert_risk: Option<RiskScore>,
+}
+
+/// Quotes swapping `amount_in` through all `hops` in order.
+pub fn quote_route(hops: &[Hop], amount_in: BigUint) -> Result<RouteQuote, SimulationError> {
+    quote_route_with(hops, amount_in, &QuotePostProcessorChain::new())
+}
+
+/// Like [`quote_route`], but runs the quote of every hop through `post_processors`. Each hop
+/// receives the adjusted amount of the previous hop.
+pub fn quote_route_with(
+    hops: &[Hop],
+    amount_in: BigUint,
+    post_processors: &QuotePostProcessorChain,
+) -> Result<RouteQuote, SimulationError> {
+    if hops.is_empty() {
+        return Err(SimulationError::InvalidInput("Route has no hops".to_string(), None));
+    }
+    let mut amount = amount_in;
+    let mut hop_amounts = Vec::with_capacity(hops.len());
+    let mut hop_adjustments = Vec::with_capacity(hops.len());
+    let mut gas = BigUint::zero();
+    for (i, hop) in hops.iter().enumerate() {
+        if i > 0 && hops[i - 1].token_out != hop.token_in {
+            return Err(SimulationError::InvalidInput(
+                format!("Hop {i} doesn't start with the token hop {} ends with", i - 1),
+                None,
+            ));
+        }
+        let mut res = catch_panic(hop.id, || {
+            hop.pool
+                .get_amount_out(amount.clone(), hop.token_in, hop.token_out)
+        })?;
+        if exceeds_marginal_price(hop, &amount, &res.amount) {
+            warn!(
+                hop = i,
+                amount_in = %amount,
+                amount_out = %res.amount,
+                "Hop pays out more than its spot price allows"
+            );
+        }
+        if !post_processors.is_empty() {
+            let pool = PoolSummary::from_state(
+                hop.id,
+                hop.protocol_system,
+                hop.pool,
+                hop.token_in,
+                hop.token_out,
+            )?;
+            res = post_processors.process(&pool, res);
+        }
+        amount = res.amount;
+        hop_amounts.push(amount.clone());
+        hop_adjustments.push(res.adjustments);
+        gas += res.gas;
+    }
+    Ok(RouteQuote { amount_out: amount, hop_amounts, hop_adjustments, gas, revert_risk: None })
+}
+
+/// Whether `amount_out` is more than `amount_in` is worth at the hop's spot price, which no
+/// correctly rounded swap can pay out. Hops without a spot price are never flagged.
+fn exceeds_marginal_price(hop: &Hop, amount_in: &BigUint, amount_out: &BigUint) -> bool {
+    let Ok(price) = hop
+        .pool
+        .spot_price(hop.token_in, hop.token_out)
+    else {
+        return false;
+    };
+    let (Some(amount_in), Some(amount_out)) = (amount_in.to_f64(), amount_out.to_f64()) else {
+        return false;
+    };
+    let decimals = hop.token_out.decimals as i32 - hop.token_in.decimals as i32;
+    let bound = amount_in * price * 10f64.powi(decimals) * (1.0 + SPOT_PRICE_TOLERANCE);
+    amount_out > bound.floor()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use alloy_primitives::U256;
+    use num_bigint::ToBigUint;
+
+    use super::*;
+    use crate::{
+        evm::protocol::uniswap_v2::state::UniswapV2State,
+        protocol::{
+            models::GetAmountOutResult, post_processing::HaircutProcessor, state::MockProtocolSim,
+        },
+    };
+
+    fn token(address: &str) -> Token {
+        Token::new(address, 18, "T", 10_000.to_biguint().unwrap())
+    }
+
+    fn hop<'a>(pool: &'a dyn ProtocolSim, token_in: &'a Token, token_out: &'a Token) -> Hop<'a> {
+        Hop { id: "pool", protocol_system: "uniswap_v2", pool, token_in, token_out }
+    }
+
+    #[test]
+    fn test_quote_route_chains_hops() {
+        let (a, b, c) = (
+            token("0x0000000000000000000000000000000000000001"),
+            token("0x0000000000000000000000000000000000000002"),
+            token("0x0000000000000000000000000000000000000003"),
+        );
+        let ab = UniswapV2State::new(U256::from(1_000_000u64), U256::from(2_000_000u64));
+        let bc = UniswapV2State::new(U256::from(3_000_000u64), U256::from(1_000_000u64));
+        let amount_in = BigUint::from(10_001u64);
+
+        let res = quote_route(&[hop(&ab, &a, &b), hop(&bc, &b, &c)], amount_in.clone()).unwrap();
+
+        let first = ab
+            .get_amount_out(amount_in, &a, &b)
+            .unwrap()
+            .amount;
+        let second = bc
+            .get_amount_out(first.clone(), &b, &c)
+            .unwrap()
+            .amount;
+        assert_eq!(res.hop_amounts, vec![first, second.clone()]);
+        assert_eq!(res.amount_out, second);
+        assert_eq!(res.gas, BigUint::from_str("240000").unwrap());
+    }
+
+    #[test]
+    fn test_quote_route_applies_post_processors() {
+        let (a, b, c) = (
+            token("0x0000000000000000000000000000000000000001"),
+            token("0x0000000000000000000000000000000000000002"),
+            token("0x0000000000000000000000000000000000000003"),
+        );
+        let ab = UniswapV2State::new(U256::from(1_000_000u64), U256::from(2_000_000u64));
+        let bc = UniswapV2State::new(U256::from(3_000_000u64), U256::from(1_000_000u64));
+        let hops = [hop(&ab, &a, &b), hop(&bc, &b, &c)];
+        let post_processors = QuotePostProcessorChain::new().with(HaircutProcessor::new(100));
+        let amount_in = BigUint::from(10_001u64);
+
+        let raw = quote_route(&hops, amount_in.clone()).unwrap();
+        let res = quote_route_with(&hops, amount_in.clone(), &post_processors).unwrap();
+
+        let first = &raw.hop_amounts[0] * 99u32 / 100u32;
+        let second = bc
+            .get_amount_out(first.clone(), &b, &c)
+            .unwrap()
+            .amount *
+            99u32 /
+            100u32;
+        assert_eq!(res.hop_amounts, vec![first.clone(), second.clone()]);
+        assert_eq!(res.amount_out, second);
+        assert!(res.amount_out < raw.amount_out);
+        assert_eq!(res.hop_adjustments[0][0].processor, "haircut");
+        assert_eq!(res.hop_adjustments[1][0].amount_after, res.amount_out);
+        assert_eq!(raw.hop_adjustments, vec![Vec::new(), Vec::new()]);
+    }
+
+    #[test]
+    fn test_quote_route_keeps_amount_above_spot_price() {
+        let (a, b) = (
+            token("0x0000000000000000000000000000000000000001"),
+            token("0x0000000000000000000000000000000000000002"),
+        );
+        let mut pool = MockProtocolSim::new();
+        pool.expect_spot_price()
+            .returning(|_, _| Ok(1.0));
+        pool.expect_get_amount_out()
+            .returning(|amount_in, _, _| {
+                Ok(GetAmountOutResult::new(
+                    amount_in + 1u32,
+                    BigUint::zero(),
+                    Box::new(MockProtocolSim::new()),
+                ))
+            });
+
+        let res = quote_route(&[hop(&pool, &a, &b)], BigUint::from(1_000u64)).unwrap();
+
+        assert_eq!(res.amount_out, BigUint::from(1_001u64));
+    }
+
+    #[test]
+    fn test_quote_route_rejects_disconnected_hops() {
+        let (a, b, c) = (
+            token("0x0000000000000000000000000000000000000001"),
+            token("0x0000000000000000000000000000000000000002"),
+            token("0x0000000000000000000000000000000000000003"),
+        );
+        let pool = UniswapV2State::new(U256::from(1_000_000u64), U256::from(2_000_000u64));
+
+        let res = quote_route(&[hop(&pool, &a, &b), hop(&pool, &c, &a)], BigUint::from(1_000u64));
+
+        assert!(matches!(res, Err(SimulationError::InvalidInput(..))));
+    }
+
+    #[test]
+    fn test_exceeds_marginal_price() {
+        let (a, b) = (
+            token("0x0000000000000000000000000000000000000001"),
+            token("0x0000000000000000000000000000000000000002"),
+        );
+        // Spot price of 2, so 1000 in is worth at most 2000 out.
+        let pool = UniswapV2State::new(U256::from(1_000_000u64), U256::from(2_000_000u64));
+        let hop = hop(&pool, &a, &b);
+
+        assert!(!exceeds_marginal_price(&hop, &BigUint::from(1_000u64), &BigUint::from(2_000u64)));
+        assert!(exceeds_marginal_price(&hop, &BigUint::from(1_000u64), &BigUint::from(2_001u64)));
+    }
+}
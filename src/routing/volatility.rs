@@ -0,0 +1,400 @@
+//! Revert risk of routes from recent pool volatility.
+//!
+//! A swap reverts when a pool's state moves between quoting and inclusion far enough that the
+//! output drops below the minimum accepted by the slippage tolerance. [`VolatilityTracker`]
+//! records how much each pool's spot price and liquidity changed per block over a sliding window
+//! and estimates the probability of such a drop within the next blocks.
+//!
+//! The log output of a hop is modelled as a random walk whose per-block variance is the mean
+//! squared log change of the pool's spot price plus that of its liquidity. Counting liquidity
+//! changes as output changes overestimates the risk of small swaps, which suits a score meant to
+//! decide whether to widen slippage. Blocks in which a pool wasn't updated count as blocks
+//! without change. Hops are assumed to move independently, so their variances add up.
+use std::collections::{HashMap, VecDeque};
+
+use alloy_primitives::Address;
+use num_traits::ToPrimitive;
+
+use super::{pool_graph::Route, route_quote::RouteQuote};
+use crate::{
+    models::Token,
+    protocol::{models::BlockUpdate, state::ProtocolSim},
+};
+
+/// Blocks of history a pool needs before its volatility is estimated, by default.
+pub const DEFAULT_MIN_HISTORY_BLOCKS: u64 = 10;
+/// Blocks between quoting and inclusion assumed by default.
+pub const DEFAULT_HORIZON_BLOCKS: u64 = 2;
+
+/// Probability of a revert.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RevertProbability {
+    /// The pool isn't tracked or its history is too short to tell.
+    Unknown,
+    /// Probability in `[0, 1]`.
+    Known(f64),
+}
+
+/// Revert risk of a route, per hop in route order and for the whole route.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiskScore {
+    pub hops: Vec<RevertProbability>,
+    /// Unknown if any hop is.
+    pub combined: RevertProbability,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Observation {
+    spot_price: f64,
+    liquidity: Option<f64>,
+}
+
+/// Squared log changes of a block.
+#[derive(Debug, Clone, Copy)]
+struct Change {
+    block: u64,
+    squared: f64,
+}
+
+#[derive(Debug, Clone)]
+struct PoolHistory {
+    base: Token,
+    quote: Token,
+    first_block: Option<u64>,
+    last: Option<Observation>,
+    changes: VecDeque<Change>,
+}
+
+#[derive(Debug, Clone)]
+pub struct VolatilityTracker {
+    window_blocks: u64,
+    min_history_blocks: u64,
+    horizon_blocks: u64,
+    current_block: u64,
+    pools: HashMap<String, PoolHistory>,
+}
+
+impl VolatilityTracker {
+    /// A tracker estimating volatility from the last `window_blocks` blocks.
+    pub fn new(window_blocks: u64) -> Self {
+        Self {
+            window_blocks: window_blocks.max(1),
+            min_history_blocks: DEFAULT_MIN_HISTORY_BLOCKS.min(window_blocks.max(1)),
+            horizon_blocks: DEFAULT_HORIZON_BLOCKS,
+            current_block: 0,
+            pools: HashMap::new(),
+        }
+    }
+
+    /// Blocks of history a pool needs before its risk is known. Capped at the window size.
+    pub fn with_min_history(mut self, blocks: u64) -> Self {
+        self.min_history_blocks = blocks.clamp(1, self.window_blocks);
+        self
+    }
+
+    /// Blocks between quoting and inclusion the risk is estimated for.
+    pub fn with_horizon(mut self, blocks: u64) -> Self {
+        self.horizon_blocks = blocks.max(1);
+        self
+    }
+
+    /// Tracks the spot price of `base` in `quote` of pool `pool_id`.
+    ///
+    /// Pools added by a [`BlockUpdate`] are tracked with their first two tokens automatically.
+    pub fn track(&mut self, pool_id: &str, base: Token, quote: Token) {
+        self.pools
+            .entry(pool_id.to_string())
+            .or_insert_with(|| PoolHistory {
+                base,
+                quote,
+                first_block: None,
+                last: None,
+                changes: VecDeque::new(),
+            });
+    }
+
+    /// Records the states of all tracked pools updated in `update`.
+    pub fn observe(&mut self, update: &BlockUpdate) {
+        for (id, component) in &update.new_pairs {
+            if let [base, quote, ..] = component.tokens.as_slice() {
+                self.track(id, Token::clone(base), Token::clone(quote));
+            }
+        }
+        self.current_block = self
+            .current_block
+            .max(update.block_number);
+        let oldest = self
+            .current_block
+            .saturating_sub(self.window_blocks);
+        for (id, history) in self.pools.iter_mut() {
+            if let Some(state) = update.states.get(id) {
+                history.record(update.block_number, state.as_ref());
+            }
+            while history
+                .changes
+                .front()
+                .is_some_and(|change| change.block <= oldest)
+            {
+                history.changes.pop_front();
+            }
+        }
+    }
+
+    /// Per-block variance of the log output of pool `pool_id`, `None` if unknown.
+    pub fn variance(&self, pool_id: &str) -> Option<f64> {
+        let history = self.pools.get(pool_id)?;
+        let covered = self
+            .current_block
+            .saturating_sub(history.first_block?)
+            .min(self.window_blocks);
+        if covered < self.min_history_blocks {
+            return None;
+        }
+        let squared: f64 = history
+            .changes
+            .iter()
+            .map(|change| change.squared)
+            .sum();
+        Some(squared / covered as f64)
+    }
+
+    /// Estimates the probability that the output of `route` falls below the minimum implied by
+    /// `slippage_tolerance_bps` within the horizon.
+    pub fn revert_risk(&self, route: &Route, slippage_tolerance_bps: u32) -> RiskScore {
+        let variances: Vec<Option<f64>> = route
+            .pools
+            .iter()
+            .map(|pool_id| self.variance(pool_id))
+            .collect();
+        let hops = variances
+            .iter()
+            .map(|variance| match variance {
+                Some(variance) => RevertProbability::Known(
+                    self.probability_below(*variance, slippage_tolerance_bps),
+                ),
+                None => RevertProbability::Unknown,
+            })
+            .collect();
+        let combined = match variances
+            .iter()
+            .copied()
+            .sum::<Option<f64>>()
+        {
+            Some(variance) if !route.pools.is_empty() => {
+                RevertProbability::Known(self.probability_below(variance, slippage_tolerance_bps))
+            }
+            _ => RevertProbability::Unknown,
+        };
+        RiskScore { hops, combined }
+    }
+
+    /// Attaches the revert risk of `route` to its quote.
+    pub fn attach_risk(&self, quote: &mut RouteQuote, route: &Route, slippage_tolerance_bps: u32) {
+        quote.revert_risk = Some(self.revert_risk(route, slippage_tolerance_bps));
+    }
+
+    /// Probability that a random walk with per-block `variance` drops below the tolerance over
+    /// the horizon.
+    fn probability_below(&self, variance: f64, slippage_tolerance_bps: u32) -> f64 {
+        if slippage_tolerance_bps >= 10_000 {
+            return 0.0;
+        }
+        let threshold = -(1.0 - slippage_tolerance_bps as f64 / 10_000.0).ln();
+        let deviation = (variance * self.horizon_blocks as f64).sqrt();
+        if deviation == 0.0 {
+            return 0.0;
+        }
+        normal_cdf(-threshold / deviation)
+    }
+}
+
+impl PoolHistory {
+    fn record(&mut self, block: u64, state: &dyn ProtocolSim) {
+        let Ok(spot_price) = state.spot_price(&self.base, &self.quote) else {
+            return;
+        };
+        if !spot_price.is_finite() || spot_price <= 0.0 {
+            return;
+        }
+        let liquidity = token_address(&self.base)
+            .zip(token_address(&self.quote))
+            .and_then(|(base, quote)| state.get_limits(base, quote).ok())
+            .and_then(|(max_in, _)| max_in.to_f64())
+            .filter(|liquidity| *liquidity > 0.0);
+        let observation = Observation { spot_price, liquidity };
+        self.first_block.get_or_insert(block);
+        if let Some(last) = self.last.replace(observation) {
+            let price_change = (spot_price / last.spot_price).ln();
+            let liquidity_change = match (liquidity, last.liquidity) {
+                (Some(liquidity), Some(last)) => (liquidity / last).ln(),
+                _ => 0.0,
+            };
+            self.changes.push_back(Change {
+                block,
+                squared: price_change.powi(2) + liquidity_change.powi(2),
+            });
+        }
+    }
+}
+
+fn token_address(token: &Token) -> Option<Address> {
+    Address::try_from(token.address.as_ref()).ok()
+}
+
+/// Standard normal CDF, via the Abramowitz and Stegun approximation of `erf` (error < 1.5e-7).
+fn normal_cdf(x: f64) -> f64 {
+    let z = x.abs() / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.3275911 * z);
+    let poly = t *
+        (0.254829592 +
+            t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let erf = 1.0 - poly * (-z * z).exp();
+    if x >= 0.0 {
+        0.5 * (1.0 + erf)
+    } else {
+        0.5 * (1.0 - erf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::U256;
+    use num_bigint::BigUint;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::evm::protocol::uniswap_v2::state::UniswapV2State;
+
+    fn tokens() -> (Token, Token) {
+        (
+            Token::new(
+                "0x0000000000000000000000000000000000000001",
+                18,
+                "A",
+                BigUint::from(10_000u64),
+            ),
+            Token::new(
+                "0x0000000000000000000000000000000000000002",
+                18,
+                "B",
+                BigUint::from(10_000u64),
+            ),
+        )
+    }
+
+    fn route(pools: &[&str]) -> Route {
+        Route {
+            tokens: vec![],
+            pools: pools
+                .iter()
+                .map(|p| p.to_string())
+                .collect(),
+            rate: 1.0,
+        }
+    }
+
+    /// Feeds `blocks` blocks in which the reserves of `volatile` swing by 1% and those of
+    /// `quiet` by 0.01%, back and forth.
+    fn tracker(blocks: u64) -> VolatilityTracker {
+        let (a, b) = tokens();
+        let mut tracker = VolatilityTracker::new(50);
+        tracker.track("volatile", a.clone(), b.clone());
+        tracker.track("quiet", a, b);
+        for block in 1..=blocks {
+            let sign = if block % 2 == 0 { 1 } else { -1 };
+            let pool = |bps: i64| -> Box<dyn ProtocolSim> {
+                let reserve1 = (1_000_000_000i64 * (10_000 + sign * bps) / 10_000) as u64;
+                Box::new(UniswapV2State::new(U256::from(1_000_000_000u64), U256::from(reserve1)))
+            };
+            let states = HashMap::from([
+                ("volatile".to_string(), pool(100)),
+                ("quiet".to_string(), pool(1)),
+            ]);
+            tracker.observe(&BlockUpdate::new(block, states, HashMap::new()));
+        }
+        tracker
+    }
+
+    fn known(probability: RevertProbability) -> f64 {
+        match probability {
+            RevertProbability::Known(p) => p,
+            RevertProbability::Unknown => panic!("Expected a known probability"),
+        }
+    }
+
+    #[test]
+    fn test_volatile_pool_is_riskier() {
+        let tracker = tracker(30);
+
+        let volatile = tracker.revert_risk(&route(&["volatile"]), 50);
+        let quiet = tracker.revert_risk(&route(&["quiet"]), 50);
+
+        assert!(known(volatile.combined) > 0.2, "{volatile:?}");
+        assert!(known(quiet.combined) < 1e-6, "{quiet:?}");
+    }
+
+    #[test]
+    fn test_wider_tolerance_lowers_risk() {
+        let tracker = tracker(30);
+
+        let tight = known(
+            tracker
+                .revert_risk(&route(&["volatile"]), 10)
+                .combined,
+        );
+        let wide = known(
+            tracker
+                .revert_risk(&route(&["volatile"]), 500)
+                .combined,
+        );
+
+        assert!(tight > wide, "{tight} <= {wide}");
+    }
+
+    #[test]
+    fn test_combined_risk_exceeds_hops() {
+        let tracker = tracker(30);
+
+        let risk = tracker.revert_risk(&route(&["volatile", "volatile"]), 50);
+
+        assert_eq!(risk.hops.len(), 2);
+        assert!(known(risk.combined) > known(risk.hops[0]));
+    }
+
+    #[rstest]
+    #[case::short_history(&["volatile"], 3)]
+    #[case::untracked_pool(&["unknown"], 30)]
+    #[case::one_unknown_hop(&["volatile", "unknown"], 30)]
+    fn test_unknown_risk(#[case] pools: &[&str], #[case] blocks: u64) {
+        let tracker = tracker(blocks);
+
+        let risk = tracker.revert_risk(&route(pools), 50);
+
+        assert_eq!(risk.combined, RevertProbability::Unknown);
+        assert_eq!(risk.hops.last(), Some(&RevertProbability::Unknown));
+    }
+
+    #[test]
+    fn test_attach_risk() {
+        let tracker = tracker(30);
+        let mut quote = RouteQuote {
+            amount_out: BigUint::from(1u64),
+            hop_amounts: vec![BigUint::from(1u64)],
+            hop_adjustments: vec![Vec::new()],
+            gas: BigUint::from(1u64),
+            revert_risk: None,
+        };
+
+        tracker.attach_risk(&mut quote, &route(&["quiet"]), 50);
+
+        assert_eq!(quote.revert_risk, Some(tracker.revert_risk(&route(&["quiet"]), 50)));
+    }
+
+    #[rstest]
+    #[case(0.0, 0.5)]
+    #[case(-1.0, 0.158655)]
+    #[case(1.959964, 0.975)]
+    fn test_normal_cdf(#[case] x: f64, #[case] exp: f64) {
+        assert!((normal_cdf(x) - exp).abs() < 1e-6);
+    }
+}
@@ -68,6 +68,30 @@ pub mod hex_bytes_option {
     }
 }
 
+/// serde functions for numbers stored as decimal strings, e.g. ones exceeding the integer range of
+/// JSON
+pub mod decimal {
+    use std::{fmt::Display, str::FromStr};
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer, T: Display>(x: &T, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(x)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: FromStr,
+        T::Err: Display,
+    {
+        let value = String::deserialize(d)?;
+        value
+            .parse()
+            .map_err(|e| serde::de::Error::custom(format!("invalid number {value}: {e}")))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use serde::{Deserialize, Serialize};
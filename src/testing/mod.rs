@@ -0,0 +1,2 @@
+//! Tooling for testing simulations against recorded outputs.
+pub mod regression;
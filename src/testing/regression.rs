@@ -0,0 +1,324 @@
+//! Regression testing of pool simulations.
+//!
+//! Refactoring protocol math should not change any quote. A fixtures file records the output of
+//! swaps on fixed pool states; [`SimulationRegressionRunner`] replays them and reports every
+//! output that changed. After an intended change the expected outputs are recorded again with
+//! [`SimulationRegressionRunner::update_expected`].
+//!
+//! Numbers are stored as decimal strings, as most of them exceed the integer range of JSON.
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use alloy_primitives::U256;
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    evm::protocol::{
+        u256_num::{biguint_to_u256, u256_to_biguint, u256_to_f64},
+        uniswap_v2::state::UniswapV2State,
+        uniswap_v3::{enums::FeeAmount, state::UniswapV3State},
+        utils::uniswap::tick_list::TickInfo,
+    },
+    models::Token,
+    protocol::state::ProtocolSim,
+    serde_helpers::decimal,
+};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum RegressionError {
+    #[error("Failed to access regression fixtures: {0}")]
+    Io(String),
+    #[error("Failed to parse regression fixtures: {0}")]
+    Parse(String),
+}
+
+/// The state of a pool to simulate on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "protocol", rename_all = "snake_case")]
+pub enum PoolStateFixture {
+    UniswapV2 {
+        #[serde(with = "decimal")]
+        reserve0: U256,
+        #[serde(with = "decimal")]
+        reserve1: U256,
+    },
+    UniswapV3 {
+        #[serde(with = "decimal")]
+        liquidity: u128,
+        #[serde(with = "decimal")]
+        sqrt_price: U256,
+        /// Fee in hundredths of a basis point, e.g. 3000 for 0.3%.
+        fee: i32,
+        tick: i32,
+        ticks: Vec<TickFixture>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TickFixture {
+    pub index: i32,
+    #[serde(with = "decimal")]
+    pub net_liquidity: i128,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenFixture {
+    pub address: String,
+    pub decimals: usize,
+}
+
+/// A swap of `amount_in` on `pool`, along with its recorded output.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegressionFixture {
+    pub id: String,
+    pub pool: PoolStateFixture,
+    pub token_in: TokenFixture,
+    pub token_out: TokenFixture,
+    #[serde(with = "decimal")]
+    pub amount_in: U256,
+    #[serde(with = "decimal")]
+    pub expected_output: U256,
+}
+
+impl RegressionFixture {
+    /// Simulates the swap and returns its output.
+    pub fn simulate(&self) -> Result<U256, String> {
+        let state: Box<dyn ProtocolSim> = match &self.pool {
+            PoolStateFixture::UniswapV2 { reserve0, reserve1 } => {
+                Box::new(UniswapV2State::new(*reserve0, *reserve1))
+            }
+            PoolStateFixture::UniswapV3 { liquidity, sqrt_price, fee, tick, ticks } => {
+                let fee = FeeAmount::try_from(*fee)
+                    .map_err(|_| format!("Unsupported Uniswap V3 fee {fee}"))?;
+                let ticks = ticks
+                    .iter()
+                    .map(|t| TickInfo::new(t.index, t.net_liquidity))
+                    .collect();
+                Box::new(UniswapV3State::new(*liquidity, *sqrt_price, fee, *tick, ticks))
+            }
+        };
+        let token_in = self.token_in.to_token("IN");
+        let token_out = self.token_out.to_token("OUT");
+        let res = state
+            .get_amount_out(u256_to_biguint(self.amount_in), &token_in, &token_out)
+            .map_err(|e| e.to_string())?;
+        Ok(biguint_to_u256(&res.amount))
+    }
+}
+
+impl TokenFixture {
+    fn to_token(&self, symbol: &str) -> Token {
+        Token::new(&self.address, self.decimals, symbol, BigUint::from(10_000u64))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RegressionReport {
+    /// Number of fixtures simulated.
+    pub checked: usize,
+    /// `(fixture_id, expected, actual, diff_pct)` of every output that changed.
+    pub mismatches: Vec<(String, U256, U256, f64)>,
+    /// `(fixture_id, error)` of every simulation that failed.
+    pub errors: Vec<(String, String)>,
+}
+
+impl RegressionReport {
+    pub fn passed(&self) -> bool {
+        self.mismatches.is_empty() && self.errors.is_empty()
+    }
+}
+
+impl fmt::Display for RegressionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} fixtures checked, {} mismatches, {} errors",
+            self.checked,
+            self.mismatches.len(),
+            self.errors.len()
+        )?;
+        for (id, expected, actual, diff_pct) in &self.mismatches {
+            writeln!(f, "  {id}: expected {expected}, got {actual} ({diff_pct:+.6}%)")?;
+        }
+        for (id, error) in &self.errors {
+            writeln!(f, "  {id}: {error}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Relative difference of `actual` to `expected` in percent.
+///
+/// Infinite if `expected` is zero and `actual` isn't.
+pub fn diff_pct(expected: U256, actual: U256) -> f64 {
+    if expected == actual {
+        return 0.0;
+    }
+    if expected.is_zero() {
+        return f64::INFINITY;
+    }
+    let expected_f = u256_to_f64(expected);
+    (u256_to_f64(actual) - expected_f) / expected_f * 100.0
+}
+
+/// Replays the fixtures of a file and compares their outputs to the recorded ones.
+#[derive(Debug, Clone)]
+pub struct SimulationRegressionRunner {
+    path: PathBuf,
+    fixtures: Vec<RegressionFixture>,
+}
+
+impl SimulationRegressionRunner {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RegressionError> {
+        let path = path.as_ref().to_path_buf();
+        let content =
+            fs::read_to_string(&path).map_err(|err| RegressionError::Io(err.to_string()))?;
+        let fixtures = serde_json::from_str(&content)
+            .map_err(|err| RegressionError::Parse(err.to_string()))?;
+        Ok(Self { path, fixtures })
+    }
+
+    pub fn fixtures(&self) -> &[RegressionFixture] {
+        &self.fixtures
+    }
+
+    /// Simulates every fixture and reports those whose output changed or failed.
+    pub fn run(&self) -> RegressionReport {
+        let mut report = RegressionReport::default();
+        for fixture in &self.fixtures {
+            report.checked += 1;
+            match fixture.simulate() {
+                Ok(actual) if actual != fixture.expected_output => report.mismatches.push((
+                    fixture.id.clone(),
+                    fixture.expected_output,
+                    actual,
+                    diff_pct(fixture.expected_output, actual),
+                )),
+                Ok(_) => {}
+                Err(error) => report
+                    .errors
+                    .push((fixture.id.clone(), error)),
+            }
+        }
+        report
+    }
+
+    /// Records the current outputs as expected and overwrites the fixtures file.
+    ///
+    /// Fixtures whose simulation fails keep their expected output. Returns the report of the
+    /// outputs as they were before the update.
+    pub fn update_expected(&mut self) -> Result<RegressionReport, RegressionError> {
+        let report = self.run();
+        for fixture in &mut self.fixtures {
+            if let Ok(actual) = fixture.simulate() {
+                fixture.expected_output = actual;
+            }
+        }
+        let content =
+            serde_json::to_string_pretty(&self.fixtures).expect("Fixtures are always serializable");
+        fs::write(&self.path, content + "\n")
+            .map_err(|err| RegressionError::Io(err.to_string()))?;
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    const FIXTURES: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/assets/regression/fixtures.json");
+
+    fn v2_fixture(expected_output: u64) -> RegressionFixture {
+        RegressionFixture {
+            id: "uniswap_v2".to_string(),
+            pool: PoolStateFixture::UniswapV2 {
+                reserve0: U256::from_str("33372357002392258830279").unwrap(),
+                reserve1: U256::from_str("43356945776493").unwrap(),
+            },
+            token_in: TokenFixture {
+                address: "0x0000000000000000000000000000000000000000".to_string(),
+                decimals: 18,
+            },
+            token_out: TokenFixture {
+                address: "0x0000000000000000000000000000000000000001".to_string(),
+                decimals: 6,
+            },
+            amount_in: U256::from_str("10000000000000000000").unwrap(),
+            expected_output: U256::from(expected_output),
+        }
+    }
+
+    #[rstest]
+    #[case::equal(100, 100, 0.0)]
+    #[case::higher(100, 101, 1.0)]
+    #[case::lower(200, 150, -25.0)]
+    #[case::from_zero(0, 1, f64::INFINITY)]
+    fn test_diff_pct(#[case] expected: u64, #[case] actual: u64, #[case] exp: f64) {
+        assert_eq!(diff_pct(U256::from(expected), U256::from(actual)), exp);
+    }
+
+    #[test]
+    fn test_sample_fixtures_load() {
+        let runner = SimulationRegressionRunner::load(FIXTURES).unwrap();
+
+        assert!(runner
+            .fixtures()
+            .iter()
+            .any(|f| matches!(f.pool, PoolStateFixture::UniswapV2 { .. })));
+        assert!(runner
+            .fixtures()
+            .iter()
+            .any(|f| matches!(f.pool, PoolStateFixture::UniswapV3 { .. })));
+    }
+
+    #[test]
+    fn test_update_expected() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let fixtures = vec![v2_fixture(12_949_029_867), v2_fixture(13_000_000_000)];
+        fs::write(file.path(), serde_json::to_string(&fixtures).unwrap()).unwrap();
+        let mut runner = SimulationRegressionRunner::load(file.path()).unwrap();
+
+        let report = runner.update_expected().unwrap();
+
+        assert_eq!(report.checked, 2);
+        assert_eq!(report.mismatches.len(), 1);
+        let (_, expected, actual, diff) = report.mismatches[0].clone();
+        assert_eq!(
+            (expected, actual),
+            (U256::from(13_000_000_000u64), U256::from(12_949_029_867u64))
+        );
+        assert!(diff < 0.0);
+        let reloaded = SimulationRegressionRunner::load(file.path()).unwrap();
+        assert!(reloaded.run().passed());
+    }
+
+    #[test]
+    fn test_simulation_error_is_reported() {
+        let mut fixture = v2_fixture(0);
+        fixture.id = "invalid_fee".to_string();
+        fixture.pool = PoolStateFixture::UniswapV3 {
+            liquidity: 1,
+            sqrt_price: U256::from(1u64) << 96,
+            fee: 1234,
+            tick: 0,
+            ticks: vec![],
+        };
+        let runner = SimulationRegressionRunner { path: PathBuf::new(), fixtures: vec![fixture] };
+
+        let report = runner.run();
+
+        assert_eq!(
+            report.errors,
+            vec![("invalid_fee".to_string(), "Unsupported Uniswap V3 fee 1234".to_string())]
+        );
+    }
+}
@@ -0,0 +1,221 @@
+//! Shared token instances
+//!
+//! Every pool referencing a token used to carry its own copy of it. The [`TokenRegistry`] interns
+//! tokens by address instead: all consumers hold an `Arc` to the same instance, and per token
+//! metadata like the quality score is kept in one place, so updating it is immediately visible to
+//! everyone sharing the registry.
+//!
+//! Interning doesn't change equality: `Token` compares and hashes by address only.
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use num_bigint::BigUint;
+use num_traits::Zero;
+use tycho_common::Bytes;
+
+use crate::models::Token;
+
+/// Quality assigned to tokens that were configured explicitly rather than received from Tycho.
+pub const DEFAULT_TOKEN_QUALITY: u32 = 100;
+
+#[derive(Debug)]
+struct RegisteredToken {
+    token: Arc<Token>,
+    quality: u32,
+}
+
+/// An interning registry of tokens, keyed by address.
+///
+/// The registry is internally synchronized and meant to be shared behind an `Arc`.
+#[derive(Debug, Default)]
+pub struct TokenRegistry {
+    tokens: RwLock<HashMap<Bytes, RegisteredToken>>,
+}
+
+impl TokenRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the shared instance of `token`, registering it with `quality` if its address is
+    /// unknown.
+    ///
+    /// If the address is already registered the existing instance is returned and both `token`
+    /// and `quality` are ignored, use [`TokenRegistry::update_quality`] to change the quality of
+    /// a known token.
+    pub fn get_or_insert(&self, token: Token, quality: u32) -> Arc<Token> {
+        if let Some(existing) = self.by_address(&token.address) {
+            return existing;
+        }
+        let mut tokens = self.tokens.write().unwrap();
+        tokens
+            .entry(token.address.clone())
+            .or_insert_with(|| RegisteredToken { token: Arc::new(token), quality })
+            .token
+            .clone()
+    }
+
+    /// Returns the shared instance of the token at `address`, if registered.
+    pub fn by_address(&self, address: &Bytes) -> Option<Arc<Token>> {
+        self.tokens
+            .read()
+            .unwrap()
+            .get(address)
+            .map(|entry| entry.token.clone())
+    }
+
+    /// Returns the quality of the token at `address`, if registered.
+    pub fn quality(&self, address: &Bytes) -> Option<u32> {
+        self.tokens
+            .read()
+            .unwrap()
+            .get(address)
+            .map(|entry| entry.quality)
+    }
+
+    /// Sets the quality of the token at `address`.
+    ///
+    /// Returns `false` if the token is not registered.
+    pub fn update_quality(&self, address: &Bytes, quality: u32) -> bool {
+        match self
+            .tokens
+            .write()
+            .unwrap()
+            .get_mut(address)
+        {
+            Some(entry) => {
+                entry.quality = quality;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Sets the transfer gas of the token at `address`.
+    ///
+    /// The registry hands out a new instance carrying the gas from now on, instances returned
+    /// earlier keep the previous value. Returns `false` if the token is not registered.
+    pub fn update_gas(&self, address: &Bytes, gas: BigUint) -> bool {
+        match self
+            .tokens
+            .write()
+            .unwrap()
+            .get_mut(address)
+        {
+            Some(entry) => {
+                entry.token = Arc::new(Token { gas, ..entry.token.as_ref().clone() });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Registered tokens whose transfer gas is unknown.
+    pub fn missing_gas(&self) -> Vec<Arc<Token>> {
+        self.tokens
+            .read()
+            .unwrap()
+            .values()
+            .filter(|entry| entry.token.gas.is_zero())
+            .map(|entry| entry.token.clone())
+            .collect()
+    }
+
+    /// Whether the token at `address` is registered with a quality of at least `min_quality`.
+    pub fn meets_quality(&self, address: &Bytes, min_quality: u32) -> bool {
+        self.quality(address)
+            .is_some_and(|quality| quality >= min_quality)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigUint;
+
+    use super::*;
+
+    fn token(i: u64) -> Token {
+        Token {
+            address: Bytes::from(i.to_be_bytes().to_vec()).lpad(20, 0),
+            decimals: 18,
+            symbol: format!("TOKEN{i}"),
+            gas: BigUint::from(30_000u64),
+        }
+    }
+
+    #[test]
+    fn test_pools_share_token_instances() {
+        let registry = TokenRegistry::new();
+        let n_tokens = 100u64;
+
+        let pools: Vec<[Arc<Token>; 2]> = (0..10_000u64)
+            .map(|i| {
+                let a = i % n_tokens;
+                let b = (a + 1 + i / n_tokens % (n_tokens - 1)) % n_tokens;
+                [
+                    registry.get_or_insert(token(a), DEFAULT_TOKEN_QUALITY),
+                    registry.get_or_insert(token(b), DEFAULT_TOKEN_QUALITY),
+                ]
+            })
+            .collect();
+
+        assert_eq!(registry.len(), n_tokens as usize);
+        let token0 = registry
+            .by_address(&token(0).address)
+            .unwrap();
+        let pools_with_token0 = pools
+            .iter()
+            .filter(|pool| {
+                pool.iter()
+                    .any(|t| Arc::ptr_eq(t, &token0))
+            })
+            .count();
+        assert_eq!(pools_with_token0, 200);
+        // one reference per pool, plus the registry's and `token0` itself
+        assert_eq!(Arc::strong_count(&token0), pools_with_token0 + 2);
+        for pool in pools.iter().step_by(997) {
+            for t in pool {
+                assert!(Arc::ptr_eq(t, &registry.by_address(&t.address).unwrap()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_or_insert_keeps_first_instance() {
+        let registry = TokenRegistry::new();
+        let first = registry.get_or_insert(token(1), 80);
+
+        let mut renamed = token(1);
+        renamed.symbol = "OTHER".to_string();
+        let second = registry.get_or_insert(renamed, 10);
+
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(second.symbol, "TOKEN1");
+        assert_eq!(registry.quality(&token(1).address), Some(80));
+    }
+
+    #[test]
+    fn test_quality_update_affects_filter() {
+        let registry = TokenRegistry::new();
+        let address = registry
+            .get_or_insert(token(1), 80)
+            .address
+            .clone();
+
+        assert!(registry.meets_quality(&address, 51));
+        assert!(registry.update_quality(&address, 20));
+        assert!(!registry.meets_quality(&address, 51));
+        assert!(!registry.update_quality(&token(2).address, 20));
+        assert!(!registry.meets_quality(&token(2).address, 0));
+    }
+}
@@ -0,0 +1,229 @@
+//! Common value types.
+use std::{fmt, str::FromStr};
+
+use alloy_primitives::{U256, U512};
+use num_bigint::BigUint;
+use thiserror::Error;
+
+use crate::models::Token;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TokenAmountError {
+    #[error("Can't combine amounts with {0} and {1} decimals")]
+    DecimalsMismatch(u8, u8),
+    #[error("Token amount overflow")]
+    Overflow,
+}
+
+/// A raw token amount together with the decimals of its token.
+///
+/// Keeps raw (smallest unit) and human readable amounts from being mixed up: the raw value is
+/// only interpreted through its decimals, and arithmetic refuses to combine amounts of tokens
+/// with different decimals.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct TokenAmount {
+    pub raw: U256,
+    pub decimals: u8,
+}
+
+impl TokenAmount {
+    pub fn new(raw: U256, decimals: u8) -> Self {
+        Self { raw, decimals }
+    }
+
+    /// An amount of `token`.
+    pub fn of(raw: U256, token: &Token) -> Self {
+        Self::new(raw, token.decimals as u8)
+    }
+
+    /// Converts a human readable amount, e.g. `1.5` USDC, to its raw representation.
+    ///
+    /// Digits beyond the token's precision are truncated. Negative and NaN values map to zero,
+    /// values that don't fit into 256 bits saturate at `U256::MAX`.
+    pub fn from_human(value: f64, decimals: u8) -> Self {
+        if value.is_nan() || value <= 0.0 {
+            return Self::new(U256::ZERO, decimals);
+        }
+        if value.is_infinite() {
+            return Self::new(U256::MAX, decimals);
+        }
+        // `Display` of f64 is the shortest representation that round trips and never uses an
+        // exponent, so this converts the decimal value the caller wrote, not its binary
+        // approximation.
+        let repr = value.to_string();
+        let (integer, fraction) = repr
+            .split_once('.')
+            .unwrap_or((&repr, ""));
+        let fraction: String = fraction
+            .chars()
+            .chain(std::iter::repeat('0'))
+            .take(decimals as usize)
+            .collect();
+        let raw = U256::from_str(&format!("{integer}{fraction}")).unwrap_or(U256::MAX);
+        Self::new(raw, decimals)
+    }
+
+    /// Converts the amount to a human readable float, rounded to the nearest representable value.
+    pub fn to_human(&self) -> f64 {
+        self.to_decimal_string()
+            .parse()
+            .unwrap_or(f64::NAN)
+    }
+
+    /// Adds two amounts of tokens with the same decimals.
+    pub fn add(&self, other: &Self) -> Result<Self, TokenAmountError> {
+        if self.decimals != other.decimals {
+            return Err(TokenAmountError::DecimalsMismatch(self.decimals, other.decimals));
+        }
+        let raw = self
+            .raw
+            .checked_add(other.raw)
+            .ok_or(TokenAmountError::Overflow)?;
+        Ok(Self::new(raw, self.decimals))
+    }
+
+    /// Scales the amount by `numerator / denominator`, rounding down.
+    ///
+    /// The intermediate product is computed with 512 bits, a result that doesn't fit into 256
+    /// bits saturates at `U256::MAX`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `denominator` is zero.
+    pub fn mul_fraction(&self, numerator: U256, denominator: U256) -> Self {
+        let scaled = U512::from(self.raw) * U512::from(numerator) / U512::from(denominator);
+        let limbs = scaled.as_limbs();
+        let raw = if limbs[4..].iter().all(|limb| *limb == 0) {
+            U256::from_limbs_slice(&limbs[..4])
+        } else {
+            U256::MAX
+        };
+        Self::new(raw, self.decimals)
+    }
+
+    /// The exact decimal value with all `decimals` fractional digits, e.g. `1.500000`.
+    fn to_decimal_string(&self) -> String {
+        let digits = self.raw.to_string();
+        let decimals = self.decimals as usize;
+        if decimals == 0 {
+            return digits;
+        }
+        let padded = format!("{digits:0>width$}", width = decimals + 1);
+        let (integer, fraction) = padded.split_at(padded.len() - decimals);
+        format!("{integer}.{fraction}")
+    }
+}
+
+/// Formats the exact human readable value without trailing zeros, e.g. `1.5`.
+impl fmt::Display for TokenAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let repr = self.to_decimal_string();
+        if self.decimals == 0 {
+            return f.write_str(&repr);
+        }
+        f.write_str(
+            repr.trim_end_matches('0')
+                .trim_end_matches('.'),
+        )
+    }
+}
+
+impl From<&TokenAmount> for BigUint {
+    fn from(value: &TokenAmount) -> Self {
+        BigUint::from_bytes_be(&value.raw.to_be_bytes::<32>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case::zero(0.0, 18, "0")]
+    #[case::one_usdc(1.0, 6, "1000000")]
+    #[case::fraction(1.1, 18, "1100000000000000000")]
+    #[case::truncated(0.1234567, 6, "123456")]
+    #[case::below_precision(0.0000001, 6, "0")]
+    #[case::no_decimals(42.9, 0, "42")]
+    #[case::negative(-1.0, 18, "0")]
+    #[case::nan(f64::NAN, 18, "0")]
+    #[case::overflow(
+        1e60,
+        18,
+        "115792089237316195423570985008687907853269984665640564039457584007913129639935"
+    )]
+    #[case::infinite(
+        f64::INFINITY,
+        18,
+        "115792089237316195423570985008687907853269984665640564039457584007913129639935"
+    )]
+    fn test_from_human(#[case] value: f64, #[case] decimals: u8, #[case] raw: &str) {
+        assert_eq!(TokenAmount::from_human(value, decimals).raw, U256::from_str(raw).unwrap());
+    }
+
+    #[rstest]
+    #[case::zero("0", 18, 0.0)]
+    #[case::one_wei("1", 18, 1e-18)]
+    #[case::one_usdc("1000000", 6, 1.0)]
+    #[case::no_decimals("42", 0, 42.0)]
+    #[case::max(
+        "115792089237316195423570985008687907853269984665640564039457584007913129639935",
+        0,
+        1.157920892373162e77
+    )]
+    #[case::max_decimals("1", 255, 1e-255)]
+    fn test_to_human(#[case] raw: &str, #[case] decimals: u8, #[case] expected: f64) {
+        assert_eq!(TokenAmount::new(U256::from_str(raw).unwrap(), decimals).to_human(), expected);
+    }
+
+    #[rstest]
+    #[case(1.5, 6)]
+    #[case(1234.000001, 6)]
+    #[case(0.1, 18)]
+    #[case(1e-18, 18)]
+    #[case(123456789.0, 0)]
+    fn test_human_roundtrip(#[case] value: f64, #[case] decimals: u8) {
+        assert_eq!(TokenAmount::from_human(value, decimals).to_human(), value);
+    }
+
+    #[rstest]
+    #[case::whole("1500000", 6, "1.5")]
+    #[case::integer("2000000", 6, "2")]
+    #[case::zero("0", 18, "0")]
+    #[case::one_wei("1", 18, "0.000000000000000001")]
+    #[case::no_decimals("42", 0, "42")]
+    #[case::trailing_zeros_without_decimals("4200", 0, "4200")]
+    fn test_display(#[case] raw: &str, #[case] decimals: u8, #[case] expected: &str) {
+        assert_eq!(TokenAmount::new(U256::from_str(raw).unwrap(), decimals).to_string(), expected);
+    }
+
+    #[test]
+    fn test_add() {
+        let a = TokenAmount::new(U256::from(1u64), 6);
+
+        assert_eq!(a.add(&a), Ok(TokenAmount::new(U256::from(2u64), 6)));
+        assert_eq!(
+            a.add(&TokenAmount::new(U256::from(1u64), 18)),
+            Err(TokenAmountError::DecimalsMismatch(6, 18))
+        );
+        assert_eq!(TokenAmount::new(U256::MAX, 6).add(&a), Err(TokenAmountError::Overflow));
+    }
+
+    #[rstest]
+    #[case::half(U256::from(1_000u64), U256::from(1u64), U256::from(2u64), U256::from(500u64))]
+    #[case::rounds_down(U256::from(10u64), U256::from(1u64), U256::from(3u64), U256::from(3u64))]
+    #[case::wide_intermediate(U256::MAX, U256::MAX, U256::MAX, U256::MAX)]
+    #[case::saturates(U256::MAX, U256::from(2u64), U256::from(1u64), U256::MAX)]
+    fn test_mul_fraction(
+        #[case] raw: U256,
+        #[case] numerator: U256,
+        #[case] denominator: U256,
+        #[case] expected: U256,
+    ) {
+        let res = TokenAmount::new(raw, 18).mul_fraction(numerator, denominator);
+
+        assert_eq!(res, TokenAmount::new(expected, 18));
+    }
+}
@@ -81,7 +81,7 @@ pub async fn load_all_tokens(
             let token_clone = token.clone();
             (
                 token.address.clone(),
-                token.try_into().unwrap_or_else(|_| {
+                Token::try_from_unmeasured(token).unwrap_or_else(|_| {
                     panic!("Couldn't convert {:?} into ERC20 token.", token_clone)
                 }),
             )
@@ -0,0 +1,89 @@
+//! Shedding caches under memory pressure must leave the tracked pools intact.
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+use alloy_primitives::U256;
+use chrono::NaiveDateTime;
+use num_bigint::BigUint;
+use tycho_common::{models::Chain, Bytes};
+use tycho_simulation::{
+    evm::protocol::uniswap_v2::state::UniswapV2State,
+    memory_budget::{MemoryAccounted, MemoryBudget, ShedPriority},
+    models::Token,
+    protocol::{models::ProtocolComponent, pool_store::PoolStore},
+    routing::{cache::RouteCache, pool_graph::Route},
+};
+
+const POOL: &str = "0x0d4a11d5eeaac28ec3f61d100daf4d40471f1852";
+
+fn token(address: &str, decimals: usize) -> Token {
+    Token::new(address, decimals, "T", BigUint::from(10_000u64))
+}
+
+fn component(tokens: Vec<Token>) -> ProtocolComponent {
+    ProtocolComponent::new(
+        Bytes::from_str(POOL).unwrap(),
+        "uniswap_v2".to_string(),
+        "uniswap_v2_pool".to_string(),
+        Chain::Ethereum,
+        tokens,
+        Vec::new(),
+        HashMap::new(),
+        Bytes::default(),
+        NaiveDateTime::default(),
+    )
+}
+
+#[test]
+fn test_tracked_pools_remain_quotable_after_shedding() {
+    let (token_in, token_out) = (
+        token("0x0000000000000000000000000000000000000000", 18),
+        token("0x0000000000000000000000000000000000000001", 6),
+    );
+    let memory = Arc::new(MemoryBudget::new(usize::MAX));
+    let mut pools = PoolStore::new().with_memory_budget(&memory);
+    let routes = Arc::new(RouteCache::new().with_memory_budget(&memory));
+    memory.register("pools", ShedPriority::Essential, pools.memory());
+    memory.register("routes", ShedPriority::Quotes, routes.clone());
+
+    pools.insert(
+        POOL,
+        component(vec![token_in.clone(), token_out.clone()]),
+        Box::new(UniswapV2State::new(
+            U256::from_str("33372357002392258830279").unwrap(),
+            U256::from_str("43356945776493").unwrap(),
+        )),
+    );
+    let pool_bytes = pools.memory().approx_bytes();
+    assert!(pool_bytes > 0);
+    for i in 0..100u64 {
+        let route = Route {
+            tokens: vec![Bytes::from(vec![0u8; 20]), Bytes::from(vec![1u8; 20])],
+            pools: vec![POOL.to_string()],
+            rate: 1.0,
+        };
+        routes.insert(U256::from(1u64) << i, route, 1);
+    }
+    assert_eq!(memory.usage(), pool_bytes + routes.approx_bytes());
+
+    // lowering the budget below the pools alone sheds every route, but no pool
+    let events = memory.set_budget(pool_bytes - 1);
+
+    assert_eq!(events.len(), 1);
+    assert!(routes.is_empty());
+    assert_eq!(memory.usage(), pool_bytes);
+    let res = pools
+        .quote(POOL, BigUint::from(10_000_000_000_000_000_000u128), &token_in, &token_out)
+        .unwrap();
+    assert_eq!(res.amount, BigUint::from(12_949_029_867u64));
+
+    // growing caches enforce the budget themselves
+    let route = Route {
+        tokens: vec![Bytes::from(vec![0u8; 20]), Bytes::from(vec![1u8; 20])],
+        pools: vec![POOL.to_string()],
+        rate: 1.0,
+    };
+    routes.insert(U256::from(1u64), route, 2);
+
+    assert!(routes.is_empty());
+    assert_eq!(pools.len(), 1);
+}
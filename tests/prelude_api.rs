@@ -0,0 +1,125 @@
+//! The prelude is the stable API: this test fails to compile if an item is removed from it or
+//! renamed, if it resolves to a different item, or if a signature integrators build on changes.
+//!
+//! A failure here is a breaking change, which may only ship in a release that announces it.
+#![allow(dead_code)]
+
+use num_bigint::BigUint;
+use tycho_simulation::prelude::*;
+
+/// Fails to compile unless the prelude type and the type at its full path are the same.
+macro_rules! same_type {
+    ($($prelude:ty => $path:ty),* $(,)?) => {
+        $(const _: fn($prelude) -> $path = |item| item;)*
+    };
+}
+
+same_type!(
+    Balances => tycho_simulation::models::Balances,
+    ModelError => tycho_simulation::models::ModelError,
+    Token => tycho_simulation::models::Token,
+    InvalidSnapshotError => tycho_simulation::protocol::errors::InvalidSnapshotError,
+    LimitedSwapError => tycho_simulation::protocol::errors::LimitedSwapError,
+    SimulationError => tycho_simulation::protocol::errors::SimulationError,
+    TransitionError => tycho_simulation::protocol::errors::TransitionError,
+    BlockUpdate => tycho_simulation::protocol::models::BlockUpdate,
+    GetAmountOutResult => tycho_simulation::protocol::models::GetAmountOutResult,
+    LimitedSwapResult => tycho_simulation::protocol::models::LimitedSwapResult,
+    PoolId => tycho_simulation::protocol::models::PoolId,
+    ProtocolComponent => tycho_simulation::protocol::models::ProtocolComponent,
+    Box<dyn ProtocolSim> => Box<dyn tycho_simulation::protocol::state::ProtocolSim>,
+);
+
+/// Compiles only if the prelude trait is the trait at its full path.
+fn try_from_with_block<S: TryFromWithBlock<BlockUpdate>>() {
+    fn full_path<S: tycho_simulation::protocol::models::TryFromWithBlock<BlockUpdate>>() {}
+    full_path::<S>()
+}
+
+const _: fn(&str, usize, &str, BigUint) -> Token = Token::new;
+
+/// The prelude alone is enough to simulate against a pool.
+fn quote(
+    state: &dyn ProtocolSim,
+    amount_in: BigUint,
+    token_in: &Token,
+    token_out: &Token,
+) -> Result<GetAmountOutResult, SimulationError> {
+    state.get_amount_out(amount_in, token_in, token_out)
+}
+
+fn pools(update: &BlockUpdate) -> impl Iterator<Item = &ProtocolComponent> {
+    update.new_pairs.values()
+}
+
+#[cfg(feature = "evm")]
+mod evm {
+    use std::{collections::HashMap, future::Future};
+
+    use alloy::{providers::RootProvider, transports::BoxTransport};
+    use alloy_primitives::Address;
+    use tycho_simulation::{
+        evm::{
+            engine_db::simulation_db::BlockHeader,
+            tycho_models::{AccountUpdate, ResponseAccount},
+        },
+        tycho_common::models::Chain,
+    };
+
+    use super::*;
+
+    same_type!(
+        StreamDecodeError => tycho_simulation::evm::decoder::StreamDecodeError,
+        SimulationDB<RootProvider<BoxTransport>> =>
+            tycho_simulation::evm::engine_db::simulation_db::SimulationDB<
+                RootProvider<BoxTransport>,
+            >,
+        SimulationDBError => tycho_simulation::evm::engine_db::simulation_db::SimulationDBError,
+        PreCachedDB => tycho_simulation::evm::engine_db::tycho_db::PreCachedDB,
+        PreCachedDBError => tycho_simulation::evm::engine_db::tycho_db::PreCachedDBError,
+        TychoClientError => tycho_simulation::evm::engine_db::tycho_db::TychoClientError,
+        AsyncTychoHttpClientImpl => tycho_simulation::evm::http_client::AsyncTychoHttpClientImpl,
+        DeduplicatingHttpClient<AsyncTychoHttpClientImpl> =>
+            tycho_simulation::evm::http_client::DeduplicatingHttpClient<AsyncTychoHttpClientImpl>,
+        RetryingTychoHttpClient<AsyncTychoHttpClientImpl> =>
+            tycho_simulation::evm::http_client::RetryingTychoHttpClient<AsyncTychoHttpClientImpl>,
+        Box<dyn TychoHttpClient> => Box<dyn tycho_simulation::evm::http_client::TychoHttpClient>,
+        EVMPoolState<PreCachedDB> =>
+            tycho_simulation::evm::protocol::vm::state::EVMPoolState<PreCachedDB>,
+        SimulationEngine<PreCachedDB> =>
+            tycho_simulation::evm::simulation::SimulationEngine<PreCachedDB>,
+        SimulationEngineError => tycho_simulation::evm::simulation::SimulationEngineError,
+        SimulationParameters => tycho_simulation::evm::simulation::SimulationParameters,
+        SimulationResult => tycho_simulation::evm::simulation::SimulationResult,
+        ProtocolStreamBuilder => tycho_simulation::evm::stream::ProtocolStreamBuilder,
+    );
+
+    /// Compiles only if the prelude trait is the trait at its full path.
+    fn engine_database<D: EngineDatabaseInterface>() {
+        fn full_path<
+            D: tycho_simulation::evm::engine_db::engine_db_interface::EngineDatabaseInterface,
+        >() {
+        }
+        full_path::<D>()
+    }
+
+    /// Compiles only if the prelude trait is the trait at its full path.
+    fn engine_database_error<E: EngineDatabaseError>() {
+        fn full_path<
+            E: tycho_simulation::evm::engine_db::engine_db_interface::EngineDatabaseError,
+        >() {
+        }
+        full_path::<E>()
+    }
+
+    const _: fn(PreCachedDB, bool) -> Result<SimulationEngine<PreCachedDB>, SimulationError> =
+        create_engine::<PreCachedDB>;
+    const _: fn() -> Result<PreCachedDB, PreCachedDBError> = PreCachedDB::new;
+    const _: fn(&str) -> Result<AsyncTychoHttpClientImpl, TychoClientError> =
+        AsyncTychoHttpClientImpl::new;
+    const _: fn(&str, Chain) -> ProtocolStreamBuilder = ProtocolStreamBuilder::new;
+
+    fn update(db: PreCachedDB, block: BlockHeader) -> impl Future<Output = Vec<AccountUpdate>> {
+        update_engine(db, block, None::<HashMap<Address, ResponseAccount>>, HashMap::new())
+    }
+}
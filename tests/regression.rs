@@ -0,0 +1,47 @@
+//! Replays the recorded swaps of `tests/assets/regression/fixtures.json` and fails if any output
+//! changed.
+//!
+//! Run with `cargo test --features regression-tests --test regression`. After an intended change
+//! of outputs, record them with `cargo test --features regression-tests --test regression --
+//! --update-expected`.
+use std::process::ExitCode;
+
+use tycho_simulation::testing::regression::SimulationRegressionRunner;
+
+const FIXTURES: &str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/tests/assets/regression/fixtures.json");
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    // Test runners like nextest list the tests of a binary before running them.
+    if args.iter().any(|arg| arg == "--list") {
+        if !args
+            .iter()
+            .any(|arg| arg == "--ignored")
+        {
+            println!("regression: test");
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    let mut runner = SimulationRegressionRunner::load(FIXTURES).expect("Failed to load fixtures");
+    if args
+        .iter()
+        .any(|arg| arg == "--update-expected")
+    {
+        let report = runner
+            .update_expected()
+            .expect("Failed to update fixtures");
+        print!("{report}");
+        println!("Updated {FIXTURES}");
+        return ExitCode::SUCCESS;
+    }
+
+    let report = runner.run();
+    print!("{report}");
+    if report.passed() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
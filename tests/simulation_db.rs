@@ -0,0 +1,98 @@
+//! Applying the revert of a state update must restore the state from before the update.
+use std::{collections::HashMap, sync::Arc};
+
+use alloy::{
+    providers::{ProviderBuilder, RootProvider},
+    transports::BoxTransport,
+};
+use alloy_primitives::{Address, Bytes, B256, U256};
+use revm::{
+    primitives::{AccountInfo, Bytecode},
+    DatabaseRef,
+};
+use tycho_simulation::evm::{
+    account_storage::StateUpdate,
+    engine_db::{
+        engine_db_interface::EngineDatabaseInterface,
+        simulation_db::{BlockHeader, SimulationDB},
+    },
+};
+
+const ACCOUNT: Address = Address::repeat_byte(0x11);
+
+/// A database that never reaches a node, serving only the accounts initialized in it.
+fn offline_db() -> SimulationDB<RootProvider<BoxTransport>> {
+    let runtime = Arc::new(tokio::runtime::Runtime::new().unwrap());
+    let client = runtime.block_on(async {
+        ProviderBuilder::new()
+            .on_builtin("http://127.0.0.1:1")
+            .await
+            .unwrap()
+    });
+    SimulationDB::new(Arc::new(client), Some(runtime), None)
+}
+
+fn header(number: u64) -> BlockHeader {
+    BlockHeader { number, hash: B256::repeat_byte(number as u8), timestamp: number * 12 }
+}
+
+/// The account info and the given slots of [`ACCOUNT`].
+fn account_state(db: &SimulationDB<RootProvider<BoxTransport>>) -> (AccountInfo, Vec<U256>) {
+    let info = db.basic_ref(ACCOUNT).unwrap().unwrap();
+    let slots = (0..5)
+        .map(|slot| {
+            db.storage_ref(ACCOUNT, U256::from(slot))
+                .unwrap()
+        })
+        .collect();
+    (info, slots)
+}
+
+#[test]
+fn test_revert_restores_state() {
+    let mut db = offline_db();
+    let code = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x00]));
+    let info = AccountInfo::new(U256::from(1_000), 0, code.hash_slow(), code);
+    let storage = HashMap::from([(U256::from(1), U256::from(10)), (U256::from(2), U256::from(20))]);
+    // mocked, so the slots that aren't set read as zero instead of being fetched
+    db.init_account(ACCOUNT, info, Some(storage), true);
+    let original = account_state(&db);
+    let new_code = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x01, 0x00]));
+    let update = StateUpdate {
+        storage: Some(HashMap::from([
+            (U256::from(1), U256::from(11)),
+            (U256::from(3), U256::from(30)),
+        ])),
+        balance: Some(U256::from(2_000)),
+        deleted_slots: vec![U256::from(2), U256::from(4)],
+        code: Some((Some(new_code.clone()), new_code.hash_slow())),
+    };
+
+    let reverts = db
+        .update_state(&HashMap::from([(ACCOUNT, update)]), header(1))
+        .unwrap();
+
+    let (info, slots) = account_state(&db);
+    assert_eq!(info.balance, U256::from(2_000));
+    assert_eq!(info.code, Some(new_code.clone()));
+    assert_eq!(info.code_hash, new_code.hash_slow());
+    assert_eq!(slots, [0, 11, 0, 30, 0].map(U256::from));
+
+    // without a block ordering policy, the revert is applied at the same block like any update
+    db.update_state(&reverts, header(1))
+        .unwrap();
+
+    assert_eq!(account_state(&db), original);
+}
+
+#[test]
+fn test_update_of_unknown_account_is_skipped() {
+    let mut db = offline_db();
+    let update = StateUpdate { balance: Some(U256::from(1)), ..Default::default() };
+
+    let reverts = db
+        .update_state(&HashMap::from([(ACCOUNT, update)]), header(1))
+        .unwrap();
+
+    assert_eq!(reverts[&ACCOUNT], StateUpdate::default());
+}
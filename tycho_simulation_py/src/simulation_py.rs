@@ -2,10 +2,11 @@ use std::{collections::HashMap, str::FromStr};
 
 use alloy::{providers::RootProvider, transports::BoxTransport};
 use num_bigint::BigUint;
-use pyo3::{prelude::*, types::PyType};
+use pyo3::{exceptions::PyRuntimeError, prelude::*, types::PyType};
 use revm::primitives::{Address, U256 as rU256};
 use tycho_simulation::evm::{
     account_storage,
+    block_ordering::BlockOrderingError,
     engine_db::{engine_db_interface::EngineDatabaseInterface, simulation_db, tycho_db},
     simulation,
 };
@@ -67,14 +68,14 @@ impl SimulationEngineInner {
         &mut self,
         updates: &HashMap<Address, account_storage::StateUpdate>,
         block: simulation_db::BlockHeader,
-    ) -> HashMap<Address, account_storage::StateUpdate> {
+    ) -> Result<HashMap<Address, account_storage::StateUpdate>, BlockOrderingError> {
         match self {
             SimulationEngineInner::SimulationDB(engine) => engine
                 .state
                 .update_state(updates, block),
-            SimulationEngineInner::TychoDB(engine) => engine
+            SimulationEngineInner::TychoDB(engine) => Ok(engine
                 .state
-                .update_state(updates, block),
+                .update_state(updates, block)),
         }
     }
 
@@ -218,7 +219,8 @@ impl SimulationEngine {
 
         let reverse_updates = self_
             .0
-            .update_state(&rust_updates, block);
+            .update_state(&rust_updates, block)
+            .map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
 
         let mut py_reverse_updates: HashMap<String, StateUpdate> = HashMap::new();
         for (key, value) in reverse_updates {
@@ -111,6 +111,7 @@ impl From<SimulationParameters> for simulation::SimulationParameters {
             data: params.data,
             value: U256::from_be_slice(params.value.to_bytes_be().as_slice()),
             overrides,
+            balance_overrides: None,
             gas_limit: params.gas_limit,
             block_number: params.block_number.unwrap_or(0),
             timestamp: params.timestamp.unwrap_or(0),
@@ -126,6 +127,12 @@ impl From<SimulationParameters> for simulation::SimulationParameters {
 ///     New values of storage slots
 /// balance: Optional[int]
 ///     New native token balance
+/// deleted_slots: list[int]
+///     Storage slots reset to zero
+/// code: Optional[bytearray]
+///     New contract code, if it changed and is loaded
+/// code_hash: Optional[bytearray]
+///     Hash of the new contract code, if it changed
 #[pyclass]
 #[derive(Clone, Debug)]
 pub struct StateUpdate {
@@ -133,14 +140,28 @@ pub struct StateUpdate {
     pub storage: Option<HashMap<BigUint, BigUint>>,
     #[pyo3(get)]
     pub balance: Option<BigUint>,
+    #[pyo3(get)]
+    pub deleted_slots: Vec<BigUint>,
+    #[pyo3(get)]
+    pub code: Option<Vec<u8>>,
+    #[pyo3(get)]
+    pub code_hash: Option<Vec<u8>>,
 }
 
 #[pymethods]
 impl StateUpdate {
     #[new]
-    #[pyo3(signature = (storage = None, balance = None))]
-    fn new(storage: Option<HashMap<BigUint, BigUint>>, balance: Option<BigUint>) -> Self {
-        Self { storage, balance }
+    #[pyo3(signature = (
+        storage = None, balance = None, deleted_slots = None, code = None, code_hash = None
+    ))]
+    fn new(
+        storage: Option<HashMap<BigUint, BigUint>>,
+        balance: Option<BigUint>,
+        deleted_slots: Option<Vec<BigUint>>,
+        code: Option<Vec<u8>>,
+        code_hash: Option<Vec<u8>>,
+    ) -> Self {
+        Self { storage, balance, deleted_slots: deleted_slots.unwrap_or_default(), code, code_hash }
     }
 }
 
@@ -161,7 +182,23 @@ impl From<account_storage::StateUpdate> for StateUpdate {
             py_balances = Some(BigUint::from_bytes_le(rust_balances.as_le_slice()))
         }
 
-        StateUpdate { storage: Some(py_storage), balance: py_balances }
+        let deleted_slots = state_update
+            .deleted_slots
+            .iter()
+            .map(|slot| BigUint::from_bytes_le(slot.as_le_slice()))
+            .collect();
+        let (code, code_hash) = match state_update.code {
+            Some((code, hash)) => (code.map(|c| c.original_bytes().to_vec()), Some(hash.to_vec())),
+            None => (None, None),
+        };
+
+        StateUpdate {
+            storage: Some(py_storage),
+            balance: py_balances,
+            deleted_slots,
+            code,
+            code_hash,
+        }
     }
 }
 
@@ -182,7 +219,30 @@ impl From<StateUpdate> for account_storage::StateUpdate {
             rust_balance = Some(U256::from_str(&py_balance.to_string()).unwrap());
         }
 
-        account_storage::StateUpdate { storage: Some(rust_storage), balance: rust_balance }
+        let deleted_slots = py_state_update
+            .deleted_slots
+            .iter()
+            .map(|slot| U256::from_str(&slot.to_string()).unwrap())
+            .collect();
+        let code_hash = py_state_update
+            .code_hash
+            .map(|hash| B256::from_slice(&hash));
+        let code = match (py_state_update.code, code_hash) {
+            (Some(code), hash) => {
+                let code = Bytecode::new_raw(revm::primitives::Bytes::from(code));
+                let hash = hash.unwrap_or_else(|| code.hash_slow());
+                Some((Some(code), hash))
+            }
+            (None, Some(hash)) => Some((None, hash)),
+            (None, None) => None,
+        };
+
+        account_storage::StateUpdate {
+            storage: Some(rust_storage),
+            balance: rust_balance,
+            deleted_slots,
+            code,
+        }
     }
 }
 
@@ -281,6 +341,7 @@ impl From<AccountUpdate> for tycho_models::AccountUpdate {
             balance: rust_balance,
             code: py_update.code,
             change: tycho_models::ChangeType::from_str(py_update.change.as_str()).unwrap(),
+            deleted_slots: Vec::new(),
         }
     }
 }
@@ -456,6 +517,10 @@ impl From<simulation::SimulationEngineError> for SimulationErrorDetails {
             simulation::SimulationEngineError::OutOfGas(reason, _) => {
                 SimulationErrorDetails { data: reason, gas_used: None }
             }
+            err @ simulation::SimulationEngineError::DatabaseError(_) => SimulationErrorDetails {
+                data: tycho_simulation::protocol::errors::error_chain(&err),
+                gas_used: None,
+            },
         }
     }
 }